@@ -0,0 +1,112 @@
+use reqwest::{Client, RequestBuilder};
+use serde_json::{Map, Value};
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum ClickHouseClientError {
+    #[error("http error: {0}")]
+    Http(#[from] reqwest::Error),
+
+    #[error("clickhouse returned status {0}: {1}")]
+    Server(u16, String),
+
+    #[error("invalid json in response: {0}")]
+    Json(#[from] serde_json::Error),
+}
+
+/// A minimal client for ClickHouse's HTTP interface. Rows are exchanged in the
+/// `JSONEachRow` format so that tables with arbitrary schemas can be written
+/// without generating typed row structs.
+pub struct ClickHouseClient {
+    http_client: Client,
+    url: String,
+    database: String,
+    username: String,
+    password: Option<String>,
+}
+
+impl ClickHouseClient {
+    pub fn new(
+        url: String,
+        database: String,
+        username: String,
+        password: Option<String>,
+    ) -> ClickHouseClient {
+        ClickHouseClient {
+            http_client: Client::new(),
+            url,
+            database,
+            username,
+            password,
+        }
+    }
+
+    pub fn database(&self) -> &str {
+        &self.database
+    }
+
+    fn request(&self) -> RequestBuilder {
+        let request = self
+            .http_client
+            .post(&self.url)
+            .query(&[("database", &self.database)])
+            .header("X-ClickHouse-User", &self.username);
+        match &self.password {
+            Some(password) => request.header("X-ClickHouse-Key", password),
+            None => request,
+        }
+    }
+
+    async fn send(&self, request: RequestBuilder) -> Result<String, ClickHouseClientError> {
+        let response = request.send().await?;
+        let status = response.status();
+        let body = response.text().await?;
+        if !status.is_success() {
+            return Err(ClickHouseClientError::Server(status.as_u16(), body));
+        }
+        Ok(body)
+    }
+
+    /// Executes a statement which doesn't return rows
+    pub async fn execute(&self, query: &str) -> Result<(), ClickHouseClientError> {
+        self.send(self.request().body(query.to_string())).await?;
+        Ok(())
+    }
+
+    /// Runs a query and returns each result row as a JSON object
+    pub async fn query(
+        &self,
+        query: &str,
+    ) -> Result<Vec<Map<String, Value>>, ClickHouseClientError> {
+        let body = self
+            .send(self.request().body(format!("{query} format JSONEachRow")))
+            .await?;
+        let mut rows = vec![];
+        for line in body.lines().filter(|l| !l.is_empty()) {
+            rows.push(serde_json::from_str(line)?);
+        }
+        Ok(rows)
+    }
+
+    /// Inserts all `rows` into `table` in a single request
+    pub async fn insert_rows(
+        &self,
+        table: &str,
+        rows: &[Map<String, Value>],
+    ) -> Result<(), ClickHouseClientError> {
+        if rows.is_empty() {
+            return Ok(());
+        }
+        let mut body = String::new();
+        for row in rows {
+            body.push_str(&serde_json::to_string(row)?);
+            body.push('\n');
+        }
+        let request = self
+            .request()
+            .query(&[("query", format!("insert into {table} format JSONEachRow"))])
+            .body(body);
+        self.send(request).await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,443 @@
+use std::{collections::HashSet, path::Path};
+
+use duckdb::{
+    appender_params_from_iter, params_from_iter,
+    types::{TimeUnit, Value},
+    Connection,
+};
+use pg_escape::quote_identifier;
+use tokio_postgres::types::{Kind, PgLsn, Type};
+
+use crate::{
+    conversions::{
+        days_since_epoch, geometry::is_geometry, micros_since_midnight, table_row::TableRow,
+        text::TextFormatConverter, ArrayCell, Cell,
+    },
+    table::{ColumnChanges, ColumnSchema, LookupKey, TableId, TableName},
+};
+
+/// Schema in which the sink keeps its own bookkeeping tables
+const PG_REPLICATE_SCHEMA: &str = "pg_replicate";
+
+/// Numeric precisions above this can't be represented by DuckDB's DECIMAL type
+const DUCKDB_MAX_DECIMAL_PRECISION: i32 = 38;
+
+/// Size of the varlena header which Postgres adds to every type modifier
+const VARHDRSZ: i32 = 4;
+
+/// A client for a DuckDB database which mirrors Postgres tables
+pub struct DuckDbClient {
+    conn: Connection,
+}
+
+impl DuckDbClient {
+    /// Opens a DuckDB database backed by a file at `path`, creating it if missing
+    pub fn open_file<P: AsRef<Path>>(path: P) -> Result<DuckDbClient, duckdb::Error> {
+        let conn = Connection::open(path)?;
+        Ok(DuckDbClient { conn })
+    }
+
+    /// Opens a transient in-memory DuckDB database
+    pub fn open_in_memory() -> Result<DuckDbClient, duckdb::Error> {
+        let conn = Connection::open_in_memory()?;
+        Ok(DuckDbClient { conn })
+    }
+
+    /// Creates the schema and tables used to store the resumption state
+    pub fn create_state_tables_if_missing(&self) -> Result<(), duckdb::Error> {
+        self.create_schema_if_missing(PG_REPLICATE_SCHEMA)?;
+        self.conn.execute_batch(&format!(
+            "create table if not exists {PG_REPLICATE_SCHEMA}.copied_tables (table_id uinteger primary key);
+            create table if not exists {PG_REPLICATE_SCHEMA}.last_lsn (id integer primary key, lsn ubigint not null);"
+        ))?;
+        Ok(())
+    }
+
+    pub fn create_schema_if_missing(&self, schema_name: &str) -> Result<(), duckdb::Error> {
+        let query = format!(
+            "create schema if not exists {};",
+            quote_identifier(schema_name)
+        );
+        self.conn.execute(&query, [])?;
+        Ok(())
+    }
+
+    /// Creates a table for `table_name` if it doesn't exist yet. When the lookup key
+    /// is an index, its columns become the primary key of the DuckDB table so that
+    /// CDC events can be applied as upserts.
+    pub fn create_table_if_missing(
+        &self,
+        table_name: &TableName,
+        column_schemas: &[ColumnSchema],
+        lookup_key: &LookupKey,
+    ) -> Result<(), duckdb::Error> {
+        self.create_schema_if_missing(&table_name.schema)?;
+
+        let mut columns = column_schemas
+            .iter()
+            .map(|column_schema| {
+                let nullable = if column_schema.nullable {
+                    ""
+                } else {
+                    " not null"
+                };
+                format!(
+                    "{} {}{nullable}",
+                    quote_identifier(&column_schema.name),
+                    Self::postgres_to_duckdb_type(&column_schema.typ, column_schema.modifier)
+                )
+            })
+            .collect::<Vec<_>>();
+
+        if let LookupKey::Key {
+            name: _,
+            columns: key_columns,
+        } = lookup_key
+        {
+            let key_columns = key_columns
+                .iter()
+                .map(|c| quote_identifier(c))
+                .collect::<Vec<_>>()
+                .join(", ");
+            columns.push(format!("primary key ({key_columns})"));
+        }
+
+        let query = format!(
+            "create table if not exists {} ({});",
+            table_name.as_quoted_identifier(),
+            columns.join(", ")
+        );
+        self.conn.execute(&query, [])?;
+        Ok(())
+    }
+
+    /// Maps a Postgres type to the closest DuckDB type. Types without a DuckDB
+    /// equivalent are stored as VARCHAR, PostGIS geometries as WKB blobs which
+    /// DuckDB's spatial extension can read with `ST_GeomFromWKB`.
+    pub fn postgres_to_duckdb_type(typ: &Type, modifier: i32) -> String {
+        if let Kind::Array(element_type) = typ.kind() {
+            return format!("{}[]", Self::postgres_to_duckdb_type(element_type, -1));
+        }
+        if is_geometry(typ) {
+            return "blob".to_string();
+        }
+
+        match *typ {
+            Type::BOOL => "boolean".to_string(),
+            Type::CHAR | Type::BPCHAR | Type::VARCHAR | Type::NAME | Type::TEXT => {
+                "varchar".to_string()
+            }
+            Type::INT2 => "smallint".to_string(),
+            Type::INT4 => "integer".to_string(),
+            Type::INT8 => "bigint".to_string(),
+            Type::FLOAT4 => "float".to_string(),
+            Type::FLOAT8 => "double".to_string(),
+            Type::NUMERIC => Self::numeric_to_duckdb_type(modifier),
+            Type::BYTEA => "blob".to_string(),
+            Type::DATE => "date".to_string(),
+            Type::TIME => "time".to_string(),
+            Type::TIMESTAMP => "timestamp".to_string(),
+            Type::TIMESTAMPTZ => "timestamptz".to_string(),
+            Type::UUID => "uuid".to_string(),
+            Type::OID => "uinteger".to_string(),
+            _ => "varchar".to_string(),
+        }
+    }
+
+    /// An unconstrained numeric (modifier -1) or one wider than DuckDB supports
+    /// is stored as VARCHAR to avoid losing precision, as are NaN and infinities.
+    fn numeric_to_duckdb_type(modifier: i32) -> String {
+        if modifier < VARHDRSZ {
+            return "varchar".to_string();
+        }
+        let modifier = modifier - VARHDRSZ;
+        let precision = (modifier >> 16) & 0xffff;
+        let scale = modifier & 0xffff;
+        if precision > DUCKDB_MAX_DECIMAL_PRECISION {
+            "varchar".to_string()
+        } else {
+            format!("decimal({precision}, {scale})")
+        }
+    }
+
+    /// Alters a table after its schema changed. Added columns are nullable as the
+    /// rows inserted before don't have their values.
+    pub fn alter_table(
+        &self,
+        table_name: &TableName,
+        changes: &ColumnChanges,
+    ) -> Result<(), duckdb::Error> {
+        let table_name = table_name.as_quoted_identifier();
+        for column_schema in &changes.dropped {
+            let query = format!(
+                "alter table {table_name} drop column if exists {};",
+                quote_identifier(&column_schema.name)
+            );
+            self.conn.execute(&query, [])?;
+        }
+        for column_schema in &changes.added {
+            let query = format!(
+                "alter table {table_name} add column if not exists {} {};",
+                quote_identifier(&column_schema.name),
+                Self::postgres_to_duckdb_type(&column_schema.typ, column_schema.modifier)
+            );
+            self.conn.execute(&query, [])?;
+        }
+        for (previous, column_schema) in &changes.altered {
+            let name = quote_identifier(&column_schema.name);
+            let typ = Self::postgres_to_duckdb_type(&column_schema.typ, column_schema.modifier);
+            if typ != Self::postgres_to_duckdb_type(&previous.typ, previous.modifier) {
+                let query =
+                    format!("alter table {table_name} alter column {name} set data type {typ};");
+                self.conn.execute(&query, [])?;
+            }
+            if previous.nullable != column_schema.nullable {
+                let nullability = if column_schema.nullable {
+                    "drop not null"
+                } else {
+                    "set not null"
+                };
+                let query = format!("alter table {table_name} alter column {name} {nullability};");
+                self.conn.execute(&query, [])?;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn truncate_table(&self, table_name: &TableName) -> Result<(), duckdb::Error> {
+        let query = format!("delete from {};", table_name.as_quoted_identifier());
+        self.conn.execute(&query, [])?;
+        Ok(())
+    }
+
+    /// Bulk loads rows into a table using DuckDB's appender
+    pub fn insert_rows(
+        &self,
+        table_name: &TableName,
+        rows: Vec<TableRow>,
+    ) -> Result<(), duckdb::Error> {
+        let mut appender = self
+            .conn
+            .appender_to_db(&table_name.name, &table_name.schema)?;
+        for row in rows {
+            appender.append_row(appender_params_from_iter(Self::row_values(row)))?;
+        }
+        appender.flush()?;
+        Ok(())
+    }
+
+    /// Inserts a row, replacing an existing row with the same primary key
+    pub fn upsert_row(
+        &self,
+        table_name: &TableName,
+        column_schemas: &[ColumnSchema],
+        row: TableRow,
+    ) -> Result<(), duckdb::Error> {
+        let columns = column_schemas
+            .iter()
+            .map(|c| quote_identifier(&c.name))
+            .collect::<Vec<_>>()
+            .join(", ");
+        let placeholders = vec!["?"; column_schemas.len()].join(", ");
+        let query = format!(
+            "insert or replace into {} ({columns}) values ({placeholders});",
+            table_name.as_quoted_identifier()
+        );
+        self.conn
+            .execute(&query, params_from_iter(Self::row_values(row)))?;
+        Ok(())
+    }
+
+    /// Inserts a row into a table without a primary key
+    pub fn insert_row(
+        &self,
+        table_name: &TableName,
+        column_schemas: &[ColumnSchema],
+        row: TableRow,
+    ) -> Result<(), duckdb::Error> {
+        let columns = column_schemas
+            .iter()
+            .map(|c| quote_identifier(&c.name))
+            .collect::<Vec<_>>()
+            .join(", ");
+        let placeholders = vec!["?"; column_schemas.len()].join(", ");
+        let query = format!(
+            "insert into {} ({columns}) values ({placeholders});",
+            table_name.as_quoted_identifier()
+        );
+        self.conn
+            .execute(&query, params_from_iter(Self::row_values(row)))?;
+        Ok(())
+    }
+
+    /// Deletes the rows matching `row` on the lookup key columns. For a
+    /// [`LookupKey::FullRow`] every column has to match.
+    pub fn delete_row(
+        &self,
+        table_name: &TableName,
+        column_schemas: &[ColumnSchema],
+        lookup_key: &LookupKey,
+        row: TableRow,
+    ) -> Result<(), duckdb::Error> {
+        let (columns, values): (Vec<String>, Vec<Value>) =
+            match lookup_key.extract(column_schemas, &row) {
+                Some(key_values) => (
+                    key_values.columns,
+                    key_values
+                        .values
+                        .into_iter()
+                        .map(Self::cell_to_value)
+                        .collect(),
+                ),
+                None => (
+                    column_schemas.iter().map(|c| c.name.clone()).collect(),
+                    Self::row_values(row),
+                ),
+            };
+        let predicates: Vec<String> = columns
+            .iter()
+            .map(|c| format!("{} is not distinct from ?", quote_identifier(c)))
+            .collect();
+
+        let query = format!(
+            "delete from {} where {};",
+            table_name.as_quoted_identifier(),
+            predicates.join(" and ")
+        );
+        self.conn.execute(&query, params_from_iter(values))?;
+        Ok(())
+    }
+
+    pub fn get_copied_table_ids(&self) -> Result<HashSet<TableId>, duckdb::Error> {
+        let mut statement = self.conn.prepare(&format!(
+            "select table_id from {PG_REPLICATE_SCHEMA}.copied_tables;"
+        ))?;
+        let table_ids = statement
+            .query_map([], |row| row.get::<_, u32>(0))?
+            .collect::<Result<HashSet<_>, _>>()?;
+        Ok(table_ids)
+    }
+
+    pub fn insert_into_copied_tables(&self, table_id: TableId) -> Result<(), duckdb::Error> {
+        self.conn.execute(
+            &format!("insert or ignore into {PG_REPLICATE_SCHEMA}.copied_tables values (?);"),
+            [table_id],
+        )?;
+        Ok(())
+    }
+
+    pub fn delete_from_copied_tables(&self, table_id: TableId) -> Result<(), duckdb::Error> {
+        self.conn.execute(
+            &format!("delete from {PG_REPLICATE_SCHEMA}.copied_tables where table_id = ?;"),
+            [table_id],
+        )?;
+        Ok(())
+    }
+
+    pub fn get_last_lsn(&self) -> Result<PgLsn, duckdb::Error> {
+        let mut statement = self.conn.prepare(&format!(
+            "select lsn from {PG_REPLICATE_SCHEMA}.last_lsn where id = 1;"
+        ))?;
+        let mut rows = statement.query([])?;
+        let lsn = match rows.next()? {
+            Some(row) => row.get::<_, u64>(0)?,
+            None => 0,
+        };
+        Ok(lsn.into())
+    }
+
+    pub fn set_last_lsn(&self, lsn: PgLsn) -> Result<(), duckdb::Error> {
+        let lsn: u64 = lsn.into();
+        self.conn.execute(
+            &format!("insert or replace into {PG_REPLICATE_SCHEMA}.last_lsn values (1, ?);"),
+            [lsn],
+        )?;
+        Ok(())
+    }
+
+    pub fn begin_transaction(&self) -> Result<(), duckdb::Error> {
+        self.conn.execute_batch("begin transaction;")
+    }
+
+    pub fn commit_transaction(&self) -> Result<(), duckdb::Error> {
+        self.conn.execute_batch("commit;")
+    }
+
+    pub fn rollback_transaction(&self) -> Result<(), duckdb::Error> {
+        self.conn.execute_batch("rollback;")
+    }
+
+    fn row_values(row: TableRow) -> Vec<Value> {
+        row.values.into_iter().map(Self::cell_to_value).collect()
+    }
+
+    fn cell_to_value(cell: Cell) -> Value {
+        match cell {
+            Cell::Null => Value::Null,
+            Cell::Bool(b) => Value::Boolean(b),
+            Cell::String(s) => Value::Text(s),
+            Cell::I16(i) => Value::SmallInt(i),
+            Cell::I32(i) => Value::Int(i),
+            Cell::U32(u) => Value::UInt(u),
+            Cell::I64(i) => Value::BigInt(i),
+            Cell::F32(f) => Value::Float(f),
+            Cell::F64(f) => Value::Double(f),
+            Cell::Numeric(n) => Value::Text(n.to_string()),
+            Cell::Date(d) => Value::Date32(days_since_epoch(d)),
+            Cell::Time(t) => Value::Time64(TimeUnit::Microsecond, micros_since_midnight(t)),
+            Cell::TimeStamp(ts) => {
+                Value::Timestamp(TimeUnit::Microsecond, ts.and_utc().timestamp_micros())
+            }
+            Cell::TimeStampTz(ts) => Value::Timestamp(TimeUnit::Microsecond, ts.timestamp_micros()),
+            Cell::Interval(i) => Value::Text(i.to_string()),
+            Cell::Uuid(u) => Value::Text(u.to_string()),
+            Cell::Json(j) => Value::Text(j.to_string()),
+            Cell::Bytes(b) => Value::Blob(b),
+            Cell::Array(a) => Self::array_to_value(a),
+            cell @ (Cell::Range(_) | Cell::Multirange(_)) => {
+                Value::Text(TextFormatConverter::to_text(&cell).unwrap_or_default())
+            }
+            Cell::Geometry(g) => Value::Blob(g.to_wkb()),
+            Cell::Bits(b) => Value::Text(b.to_string()),
+        }
+    }
+
+    fn array_to_value(array: ArrayCell) -> Value {
+        fn list<T>(values: Vec<Option<T>>, f: impl Fn(T) -> Value) -> Value {
+            Value::List(
+                values
+                    .into_iter()
+                    .map(|v| v.map(&f).unwrap_or(Value::Null))
+                    .collect(),
+            )
+        }
+
+        match array {
+            ArrayCell::Null => Value::Null,
+            ArrayCell::Bool(v) => list(v, Value::Boolean),
+            ArrayCell::String(v) => list(v, Value::Text),
+            ArrayCell::I16(v) => list(v, Value::SmallInt),
+            ArrayCell::I32(v) => list(v, Value::Int),
+            ArrayCell::U32(v) => list(v, Value::UInt),
+            ArrayCell::I64(v) => list(v, Value::BigInt),
+            ArrayCell::F32(v) => list(v, Value::Float),
+            ArrayCell::F64(v) => list(v, Value::Double),
+            ArrayCell::Numeric(v) => list(v, |n| Value::Text(n.to_string())),
+            ArrayCell::Date(v) => list(v, |d| Value::Date32(days_since_epoch(d))),
+            ArrayCell::Time(v) => list(v, |t| {
+                Value::Time64(TimeUnit::Microsecond, micros_since_midnight(t))
+            }),
+            ArrayCell::TimeStamp(v) => list(v, |ts| {
+                Value::Timestamp(TimeUnit::Microsecond, ts.and_utc().timestamp_micros())
+            }),
+            ArrayCell::TimeStampTz(v) => list(v, |ts| {
+                Value::Timestamp(TimeUnit::Microsecond, ts.timestamp_micros())
+            }),
+            ArrayCell::Interval(v) => list(v, |i| Value::Text(i.to_string())),
+            ArrayCell::Uuid(v) => list(v, |u| Value::Text(u.to_string())),
+            ArrayCell::Json(v) => list(v, |j| Value::Text(j.to_string())),
+            ArrayCell::Bytes(v) => list(v, Value::Blob),
+            ArrayCell::Nested(v) => Value::List(v.into_iter().map(Self::array_to_value).collect()),
+        }
+    }
+}
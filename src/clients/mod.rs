@@ -1 +1,5 @@
+#[cfg(feature = "clickhouse")]
+pub mod clickhouse;
+#[cfg(feature = "duckdb")]
+pub mod duckdb;
 pub mod postgres;
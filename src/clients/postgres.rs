@@ -1,25 +1,309 @@
-use std::collections::{HashMap, HashSet};
+use std::{
+    collections::{hash_map::RandomState, HashMap, HashSet},
+    error::Error as _,
+    hash::{BuildHasher, Hasher},
+    io,
+    iter::Peekable,
+    ops::Range,
+    str::CharIndices,
+    time::{Duration, Instant},
+};
 
+use bytes::Bytes;
+use chrono::{DateTime, Utc};
 use pg_escape::{quote_identifier, quote_literal};
-use postgres_replication::LogicalReplicationStream;
+use postgres_replication::{protocol::LogicalReplicationMessage, LogicalReplicationStream};
 use thiserror::Error;
 use tokio_postgres::{
     config::ReplicationMode,
+    error::SqlState,
+    tls::{MakeTlsConnect, NoTlsStream},
     types::{Kind, PgLsn, Type},
-    Client as PostgresClient, Config, CopyOutStream, NoTls, SimpleQueryMessage, SimpleQueryRow,
+    Client as PostgresClient, Config, Connection, CopyBothDuplex, CopyOutStream, NoTls,
+    SimpleQueryMessage, SimpleQueryRow, Socket,
 };
-use tracing::{info, warn};
+use tracing::{info, instrument, warn};
 
-use crate::table::{ColumnSchema, LookupKey, TableId, TableName, TableSchema};
+use crate::{
+    conversions::hex::from_bytea_hex,
+    table::{ColumnSchema, ForeignKey, LookupKey, TableId, TableName, TableSchema},
+};
 
 pub struct SlotInfo {
     pub confirmed_flush_lsn: PgLsn,
+    /// Whether the slot decodes prepared transactions at `PREPARE TRANSACTION`
+    pub two_phase: bool,
+    /// Whether the slot is synchronized to standbys, so that it survives a failover.
+    /// Always false before Postgres 17.
+    pub failover: bool,
+}
+
+/// The position of a slot and the pid of the walsender process consuming it, if any
+pub struct SlotActivity {
+    pub confirmed_flush_lsn: PgLsn,
+    pub active_pid: Option<i32>,
+}
+
+/// The server's identity as reported by `IDENTIFY_SYSTEM`. A failover to a standby
+/// keeps the system id but switches to a new timeline.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SystemIdentity {
+    pub system_id: String,
+    pub timeline: u32,
+    /// The server's current WAL flush position
+    pub xlog_pos: PgLsn,
+}
+
+/// The operations a publication publishes
+pub struct PublishedOperations {
+    pub insert: bool,
+    pub update: bool,
+    pub delete: bool,
+    pub truncate: bool,
+}
+
+/// An operation whose changes a publication publishes
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum PublishedOperation {
+    Insert,
+    Update,
+    Delete,
+    Truncate,
+}
+
+impl PublishedOperation {
+    const ALL: [PublishedOperation; 4] = [
+        PublishedOperation::Insert,
+        PublishedOperation::Update,
+        PublishedOperation::Delete,
+        PublishedOperation::Truncate,
+    ];
+
+    /// The operation's name in a publication's `publish` option
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            PublishedOperation::Insert => "insert",
+            PublishedOperation::Update => "update",
+            PublishedOperation::Delete => "delete",
+            PublishedOperation::Truncate => "truncate",
+        }
+    }
+}
+
+/// The options of a publication, see [`ReplicationClient::create_publication`] and
+/// [`ReplicationClient::get_publication_options`]. The default are Postgres'
+/// defaults, publishing all operations without publishing via the partition root.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PublicationOptions {
+    pub publish: HashSet<PublishedOperation>,
+    /// Whether changes to partitions are published as changes to their root table
+    pub via_partition_root: bool,
+}
+
+impl Default for PublicationOptions {
+    fn default() -> PublicationOptions {
+        PublicationOptions {
+            publish: PublishedOperation::ALL.into_iter().collect(),
+            via_partition_root: false,
+        }
+    }
+}
+
+impl PublicationOptions {
+    /// The options as a `with (...)` clause, with the operations in a fixed order
+    fn with_clause(&self) -> String {
+        let publish: Vec<&str> = PublishedOperation::ALL
+            .iter()
+            .filter(|operation| self.publish.contains(operation))
+            .map(PublishedOperation::as_str)
+            .collect();
+        format!(
+            "with (publish = {}, publish_via_partition_root = {})",
+            quote_literal(&publish.join(", ")),
+            self.via_partition_root
+        )
+    }
+}
+
+/// A table's size according to the statistics in `pg_class`, e.g. to copy large
+/// tables first. The row and page counts are as of the last vacuum or analyze.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TableSizeEstimate {
+    /// Estimated number of rows, -1 if the table was never vacuumed or analyzed
+    pub reltuples: f64,
+    /// Number of pages of the table itself
+    pub relpages: i32,
+    /// Size of the table including indexes and TOAST data, see
+    /// `pg_total_relation_size`
+    pub total_bytes: u64,
+}
+
+/// A publication's properties from `pg_publication`
+pub struct PublicationInfo {
+    pub name: String,
+    pub owner: String,
+    /// Whether the publication includes all tables, including ones created later
+    pub all_tables: bool,
+    /// Whether changes to partitions are published as changes to their root table
+    pub via_root: bool,
+    pub operations: PublishedOperations,
+}
+
+/// A table of a publication with the publication's row filter for it, see
+/// [`ReplicationClient::get_publication_tables`]
+#[derive(Debug, Clone, PartialEq)]
+pub struct PublicationTable {
+    pub table_name: TableName,
+    /// The filter's `WHERE` expression as deparsed by `pg_get_expr`, e.g.
+    /// `(status = 'active'::text)`, `None` if all rows are published
+    pub row_filter: Option<String>,
+}
+
+/// Whether a server is set up to be replicated from, see
+/// [`ReplicationClient::check_source`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SourceCheck {
+    /// Whether `wal_level` is `logical`, which logical replication requires
+    pub wal_level_ok: bool,
+    pub publication_exists: bool,
+    /// How many more replication slots can be created before reaching
+    /// `max_replication_slots`
+    pub slots_available: u32,
+    /// Whether `track_commit_timestamp` is on, which
+    /// [`ReplicationClient::get_commit_timestamp_origin`] needs
+    pub track_commit_timestamp: bool,
+}
+
+/// When and from which replication origin a transaction was committed, see
+/// [`ReplicationClient::get_commit_timestamp_origin`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CommitTimestampOrigin {
+    pub timestamp: DateTime<Utc>,
+    /// The id of the replication origin the transaction was replayed from, e.g. by a
+    /// subscription, `None` if it was committed locally
+    pub origin_id: Option<u32>,
+    /// The origin's name, `None` if it was committed locally or the origin has
+    /// since been dropped
+    pub origin: Option<String>,
+}
+
+/// A table's replica identity, which determines the old values Postgres sends for
+/// updates and deletes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReplicaIdentity {
+    /// The primary key, if the table has one
+    Default,
+    Nothing,
+    Full,
+    /// A unique index chosen with `REPLICA IDENTITY USING INDEX`
+    Index,
+}
+
+/// Why a table's updates and deletes fail on the publisher
+#[derive(Debug, Error, Clone, Copy, PartialEq, Eq)]
+pub enum ReplicaIdentityProblem {
+    #[error("replica identity is default but the table has no primary key")]
+    NoPrimaryKey,
+
+    #[error("replica identity is nothing")]
+    Nothing,
+
+    #[error("replica identity index doesn't exist")]
+    NoIdentityIndex,
+}
+
+/// The replica identity of a table in a publication, see
+/// [`ReplicationClient::check_replica_identities`]
+#[derive(Debug)]
+pub struct TableReplicaIdentity {
+    pub table_name: TableName,
+    pub replica_identity: ReplicaIdentity,
+    /// Set if the publication publishes updates or deletes, which the table's
+    /// replica identity doesn't support
+    pub problem: Option<ReplicaIdentityProblem>,
+}
+
+/// Time based replication lag as reported by the walsender in `pg_stat_replication`.
+/// Each lag is `None` until the consumer has sent a status update for the respective
+/// position and again after the consumer has caught up and stayed idle.
+pub struct ReplicationLag {
+    pub write_lag: Option<Duration>,
+    pub flush_lag: Option<Duration>,
+    pub replay_lag: Option<Duration>,
+}
+
+/// How often [`ReplicationClient::wait_for_slot_inactive`] checks the slot
+const SLOT_INACTIVE_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// How to retry connecting while Postgres isn't reachable or still starting up.
+/// Attempt `n` waits `base_delay * 2^(n - 1)` before retrying, reduced by a random
+/// fraction of up to `jitter` (between 0 and 1) so that many clients starting at
+/// the same time don't retry in lockstep.
+#[derive(Debug, Clone)]
+pub struct ConnectRetryPolicy {
+    max_attempts: u32,
+    base_delay: Duration,
+    jitter: f64,
+}
+
+impl ConnectRetryPolicy {
+    pub fn new(max_attempts: u32, base_delay: Duration, jitter: f64) -> ConnectRetryPolicy {
+        ConnectRetryPolicy {
+            max_attempts: max_attempts.max(1),
+            base_delay,
+            jitter: jitter.clamp(0.0, 1.0),
+        }
+    }
+
+    fn delay(&self, attempt: u32) -> Duration {
+        let delay = self.base_delay.saturating_mul(1 << (attempt - 1).min(16));
+        let random = RandomState::new().build_hasher().finish() as f64 / u64::MAX as f64;
+        delay.mul_f64(1.0 - self.jitter * random)
+    }
+
+    /// Whether an error connecting is likely to go away by itself, i.e. the
+    /// connection failed or Postgres is still starting up, unlike invalid
+    /// configurations or failed authentication
+    fn is_transient(e: &tokio_postgres::Error) -> bool {
+        match e.as_db_error() {
+            Some(db_error) => *db_error.code() == SqlState::CANNOT_CONNECT_NOW,
+            None => e.is_closed() || e.source().is_some_and(|source| source.is::<io::Error>()),
+        }
+    }
+}
+
+/// The logical decoding output plugin of a replication slot
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum OutputPlugin {
+    /// Postgres' built in plugin, streamed with
+    /// [`ReplicationClient::get_logical_replication_stream`]
+    #[default]
+    PgOutput,
+    /// The wal2json extension, for servers which don't offer pgoutput. Streamed with
+    /// [`ReplicationClient::get_wal2json_stream`].
+    Wal2Json,
+}
+
+impl OutputPlugin {
+    fn name(&self) -> &'static str {
+        match self {
+            OutputPlugin::PgOutput => "pgoutput",
+            OutputPlugin::Wal2Json => "wal2json",
+        }
+    }
 }
 
 /// A client for Postgres logical replication
 pub struct ReplicationClient {
     postgres_client: PostgresClient,
     in_txn: bool,
+    include_generated_columns: bool,
+    include_comments: bool,
+    exclude_columns: HashMap<TableName, HashSet<String>>,
+    allowed_schemas: Option<HashSet<String>>,
+    denied_schemas: HashSet<String>,
+    output_plugin: OutputPlugin,
+    failover_slots: bool,
 }
 
 #[derive(Debug, Error)]
@@ -39,6 +323,17 @@ pub enum ReplicationClientError {
     #[error("replica identity '{0}' not supported")]
     ReplicaIdentityNotSupported(String),
 
+    #[error(
+        "table {0} is unlogged, its changes aren't written to the WAL and can't be replicated"
+    )]
+    UnloggedTable(TableName),
+
+    #[error("table {0} is temporary, its changes can't be replicated")]
+    TemporaryTable(TableName),
+
+    #[error("leaf partition {0} is a foreign table, its changes can't be replicated")]
+    ForeignTablePartition(TableName),
+
     #[error("type modifier column is not a valid u32")]
     TypeModifierColumnNotI32,
 
@@ -48,11 +343,70 @@ pub enum ReplicationClientError {
     #[error("table {0} doesn't exist")]
     MissingTable(TableName),
 
+    #[error("table with id {0} doesn't exist")]
+    MissingTableId(TableId),
+
+    #[error("table {1} no longer has id {0}, it was dropped and recreated or renamed")]
+    TableChanged(TableId, TableName),
+
     #[error("not a valid PgLsn")]
     InvalidPgLsn,
 
+    #[error("not a valid pid")]
+    InvalidPid,
+
+    #[error("{0} is not a valid lag")]
+    InvalidLag(String),
+
+    #[error("{0} is not a valid row count")]
+    InvalidRowCount(String),
+
+    #[error("{0} is not a valid table size")]
+    InvalidTableSize(String),
+
+    #[error("{0} is not a valid timeline")]
+    InvalidTimeline(String),
+
+    #[error("{0} is not a valid slot count")]
+    InvalidSlotCount(String),
+
+    #[error("{0} is not a valid commit timestamp")]
+    InvalidCommitTimestamp(String),
+
+    #[error("{0} is not a valid server version")]
+    InvalidServerVersion(String),
+
+    #[error(
+        "track_commit_timestamp is off, so Postgres doesn't record when and from which origin transactions commit; set it to on and restart Postgres to use commit timestamps"
+    )]
+    CommitTimestampNotTracked,
+
+    #[error("{0} is not a valid column list")]
+    InvalidColumnList(String),
+
+    #[error("invalid logical decoding message: {0}")]
+    InvalidMessage(String),
+
+    #[error("column {0} of table {1} is part of its lookup key and can't be excluded")]
+    ExcludedKeyColumn(String, TableName),
+
     #[error("failed to create slot")]
     FailedToCreateSlot,
+
+    #[error("failover slots need Postgres 17 or later, the server runs version {0}")]
+    FailoverSlotsUnsupported(u32),
+
+    #[error("replication slot {0} doesn't exist")]
+    MissingSlot(String),
+
+    #[error("replication slot {0} is still active after waiting for {1:?}")]
+    SlotStillActive(String, Duration),
+
+    #[error("replication slot is active for pid {pid}")]
+    SlotActive { pid: i32 },
+
+    #[error("replication={0} is not supported, only replication=database is")]
+    UnsupportedReplicationParam(String),
 }
 
 impl ReplicationClient {
@@ -64,8 +418,62 @@ impl ReplicationClient {
         username: &str,
         password: Option<String>,
     ) -> Result<ReplicationClient, ReplicationClientError> {
-        info!("connecting to postgres");
+        let config = Self::no_tls_config(host, port, database, username, password);
+        Self::connect(config, NoTls).await
+    }
+
+    /// Same as [`ReplicationClient::connect_no_tls`] but returns the connection, which
+    /// performs the actual communication with Postgres, instead of spawning a task
+    /// driving it. The client makes no progress until the connection is polled, e.g.
+    /// by a task the caller spawns and supervises. The connection resolves once the
+    /// client is dropped or with an error when the connection fails.
+    pub async fn connect_no_tls_with_connection(
+        host: &str,
+        port: u16,
+        database: &str,
+        username: &str,
+        password: Option<String>,
+    ) -> Result<(ReplicationClient, Connection<Socket, NoTlsStream>), ReplicationClientError> {
+        let config = Self::no_tls_config(host, port, database, username, password);
+        let (postgres_client, connection) = Self::connect_unspawned(config, NoTls).await?;
+        Ok((Self::new(postgres_client), connection))
+    }
+
+    /// Same as [`ReplicationClient::connect_no_tls`] but retries transient connection
+    /// errors, like connection refused while Postgres is starting, per `retry_policy`
+    pub async fn connect_no_tls_with_retry(
+        host: &str,
+        port: u16,
+        database: &str,
+        username: &str,
+        password: Option<String>,
+        retry_policy: &ConnectRetryPolicy,
+    ) -> Result<ReplicationClient, ReplicationClientError> {
+        let config = Self::no_tls_config(host, port, database, username, password);
+        let mut attempt = 1;
+        loop {
+            match Self::connect(config.clone(), NoTls).await {
+                Err(ReplicationClientError::TokioPostgresError(e))
+                    if attempt < retry_policy.max_attempts
+                        && ConnectRetryPolicy::is_transient(&e) =>
+                {
+                    let delay = retry_policy.delay(attempt);
+                    warn!("failed to connect to postgres (attempt {attempt}), retrying in {delay:?}: {e}");
+                    tokio::time::sleep(delay).await;
+                    attempt += 1;
+                }
+                result => return result,
+            }
+        }
+    }
 
+    fn no_tls_config(
+        host: &str,
+        port: u16,
+        database: &str,
+        username: &str,
+        password: Option<String>,
+    ) -> Config {
         let mut config = Config::new();
         config
             .host(host)
@@ -78,7 +486,144 @@ impl ReplicationClient {
             config.password(password);
         }
 
-        let (postgres_client, connection) = config.connect(NoTls).await?;
+        config
+    }
+
+    /// Connect to a postgres database in logical replication mode using a libpq
+    /// connection string, either a `postgres://` URI or `key=value` pairs. The
+    /// `replication` parameter is optional but if present must be `database`.
+    /// The `sslmode` in the connection string decides whether `tls` is used.
+    pub async fn connect_from_uri<T>(
+        uri: &str,
+        tls: T,
+    ) -> Result<ReplicationClient, ReplicationClientError>
+    where
+        T: MakeTlsConnect<Socket>,
+        T::Stream: Send + 'static,
+    {
+        let (uri, replication) = Self::take_replication_param(uri);
+        if let Some(replication) = replication {
+            if replication != "database" {
+                return Err(ReplicationClientError::UnsupportedReplicationParam(
+                    replication,
+                ));
+            }
+        }
+
+        let mut config: Config = uri.parse()?;
+        config.replication_mode(ReplicationMode::Logical);
+
+        Self::connect(config, tls).await
+    }
+
+    /// Removes the `replication` parameter from a connection string because
+    /// tokio_postgres doesn't parse it, returning it separately. The other
+    /// parameters are left as they were written, including their quoting.
+    pub fn take_replication_param(conn_str: &str) -> (String, Option<String>) {
+        let mut replication = None;
+
+        if conn_str.starts_with("postgres://") || conn_str.starts_with("postgresql://") {
+            let Some((base, query)) = conn_str.split_once('?') else {
+                return (conn_str.to_string(), None);
+            };
+            let params: Vec<&str> = query
+                .split('&')
+                .filter(|param| match param.split_once('=') {
+                    Some(("replication", value)) => {
+                        replication = Some(value.to_string());
+                        false
+                    }
+                    _ => true,
+                })
+                .collect();
+            let uri = if params.is_empty() {
+                base.to_string()
+            } else {
+                format!("{base}?{}", params.join("&"))
+            };
+            return (uri, replication);
+        }
+
+        let mut remaining = String::with_capacity(conn_str.len());
+        let mut copied_up_to = 0;
+        for (key, value, range) in Self::key_value_pairs(conn_str) {
+            if key == "replication" {
+                remaining.push_str(&conn_str[copied_up_to..range.start]);
+                copied_up_to = range.end;
+                // Like libpq, the last value wins
+                replication = Some(value);
+            }
+        }
+        remaining.push_str(&conn_str[copied_up_to..]);
+        (remaining.trim().to_string(), replication)
+    }
+
+    /// Parses the `key=value` pairs of a libpq connection string, returning each
+    /// key, its unquoted and unescaped value and the byte range of the whole pair.
+    /// Like libpq, it allows whitespace around `=`, single quoted values and
+    /// backslash escapes. Parsing stops at the first malformed pair, which is left
+    /// for tokio_postgres to report.
+    fn key_value_pairs(conn_str: &str) -> Vec<(String, String, Range<usize>)> {
+        fn skip_whitespace(chars: &mut Peekable<CharIndices<'_>>) {
+            while chars.next_if(|(_, c)| c.is_whitespace()).is_some() {}
+        }
+
+        let mut pairs = vec![];
+        let mut chars = conn_str.char_indices().peekable();
+
+        loop {
+            skip_whitespace(&mut chars);
+            let Some(&(start, _)) = chars.peek() else {
+                break;
+            };
+            let mut key = String::new();
+            while let Some((_, c)) = chars.next_if(|(_, c)| *c != '=' && !c.is_whitespace()) {
+                key.push(c);
+            }
+            skip_whitespace(&mut chars);
+            if chars.next_if(|(_, c)| *c == '=').is_none() {
+                break;
+            }
+            skip_whitespace(&mut chars);
+
+            let mut value = String::new();
+            let quoted = chars.next_if(|(_, c)| *c == '\'').is_some();
+            let mut end = conn_str.len();
+            let mut closed = !quoted;
+            while let Some(&(i, c)) = chars.peek() {
+                if !quoted && c.is_whitespace() {
+                    end = i;
+                    break;
+                }
+                chars.next();
+                match c {
+                    '\\' => {
+                        if let Some((_, escaped)) = chars.next() {
+                            value.push(escaped);
+                        }
+                    }
+                    '\'' if quoted => {
+                        end = i + 1;
+                        closed = true;
+                        break;
+                    }
+                    c => value.push(c),
+                }
+            }
+            if !closed {
+                break;
+            }
+            pairs.push((key, value, start..end));
+        }
+        pairs
+    }
+
+    async fn connect<T>(config: Config, tls: T) -> Result<ReplicationClient, ReplicationClientError>
+    where
+        T: MakeTlsConnect<Socket>,
+        T::Stream: Send + 'static,
+    {
+        let (postgres_client, connection) = Self::connect_unspawned(config, tls).await?;
 
         tokio::spawn(async move {
             info!("waiting for connection to terminate");
@@ -87,12 +632,117 @@ impl ReplicationClient {
             }
         });
 
+        Ok(Self::new(postgres_client))
+    }
+
+    #[instrument(skip_all, fields(dbname = config.get_dbname()))]
+    async fn connect_unspawned<T>(
+        config: Config,
+        tls: T,
+    ) -> Result<(PostgresClient, Connection<Socket, T::Stream>), ReplicationClientError>
+    where
+        T: MakeTlsConnect<Socket>,
+    {
+        info!("connecting to postgres");
+
+        let connected = config.connect(tls).await?;
+
         info!("successfully connected to postgres");
 
-        Ok(ReplicationClient {
+        Ok(connected)
+    }
+
+    fn new(postgres_client: PostgresClient) -> ReplicationClient {
+        ReplicationClient {
             postgres_client,
             in_txn: false,
-        })
+            include_generated_columns: false,
+            include_comments: false,
+            exclude_columns: HashMap::new(),
+            allowed_schemas: None,
+            denied_schemas: HashSet::new(),
+            output_plugin: OutputPlugin::default(),
+            failover_slots: false,
+        }
+    }
+
+    /// Sets the output plugin of the slots created by this client, pgoutput by default
+    pub fn set_output_plugin(&mut self, output_plugin: OutputPlugin) {
+        self.output_plugin = output_plugin;
+    }
+
+    /// Makes the slots created by this client failover slots, created with the
+    /// `FAILOVER` option of Postgres 17 so that they can be synchronized to standbys
+    /// and streaming continues from a promoted standby without copying the tables
+    /// again. Creating a slot fails with
+    /// [`ReplicationClientError::FailoverSlotsUnsupported`] on older servers.
+    /// Temporary slots, which Postgres doesn't allow to fail over, are created
+    /// without the option.
+    ///
+    /// The option alone doesn't synchronize the slot. The standby must stream from
+    /// the primary through a physical slot set as its `primary_slot_name`, with
+    /// `hot_standby_feedback = on`, `sync_replication_slots = on` and a `dbname` in
+    /// its `primary_conninfo`. The primary should list that physical slot in
+    /// `synchronized_standby_slots`, so that changes aren't sent to this client
+    /// before the standby received them. Whether a standby's slot is synchronized
+    /// and usable shows in the `synced` column of its `pg_replication_slots`.
+    pub fn set_failover_slots(&mut self, failover: bool) {
+        self.failover_slots = failover;
+    }
+
+    /// Makes [`ReplicationClient::get_column_schemas`] include stored generated columns,
+    /// flagged with [`ColumnSchema::generated`]. Their values are included in table
+    /// copies but Postgres doesn't send them in cdc events, so a sink which needs them
+    /// must recompute them from the other columns.
+    pub fn set_include_generated_columns(&mut self, include: bool) {
+        self.include_generated_columns = include;
+    }
+
+    /// Makes [`ReplicationClient::get_column_schemas`] and the table schemas include
+    /// the comments set by `COMMENT ON COLUMN` and `COMMENT ON TABLE`, e.g. for sinks
+    /// which document the tables they create. Off by default to save the lookups.
+    pub fn set_include_comments(&mut self, include: bool) {
+        self.include_comments = include;
+    }
+
+    /// Leaves the columns `column_names` of a table out of its schema, so that they
+    /// are neither copied nor decoded in cdc events. Unlike a publication's column
+    /// list this only affects this client, Postgres still sends the columns' values
+    /// in cdc events. Loading the table's schema fails if the columns include a lookup
+    /// key column, which includes every column for tables without a key.
+    pub fn set_exclude_columns(
+        &mut self,
+        table_name: TableName,
+        column_names: impl IntoIterator<Item = impl Into<String>>,
+    ) {
+        let column_names = column_names.into_iter().map(Into::into).collect();
+        self.exclude_columns.insert(table_name, column_names);
+    }
+
+    /// Restricts [`ReplicationClient::get_publication_table_names`] and
+    /// [`ReplicationClient::get_publication_tables`] to the tables in `schemas`, e.g.
+    /// to replicate some tenants' schemas of a publication including all tables.
+    /// Applies in addition to the publication, which still decides what Postgres
+    /// sends.
+    pub fn set_allowed_schemas(&mut self, schemas: impl IntoIterator<Item = impl Into<String>>) {
+        self.allowed_schemas = Some(schemas.into_iter().map(Into::into).collect());
+    }
+
+    /// Leaves the tables in `schemas` out of
+    /// [`ReplicationClient::get_publication_table_names`] and
+    /// [`ReplicationClient::get_publication_tables`], also if their schemas are
+    /// allowed by [`ReplicationClient::set_allowed_schemas`]
+    pub fn set_denied_schemas(&mut self, schemas: impl IntoIterator<Item = impl Into<String>>) {
+        self.denied_schemas = schemas.into_iter().map(Into::into).collect();
+    }
+
+    /// Whether tables in `schema` pass the allowed and denied schemas
+    pub fn is_schema_allowed(&self, schema: &str) -> bool {
+        let allowed = self
+            .allowed_schemas
+            .as_ref()
+            .map_or(true, |allowed_schemas| allowed_schemas.contains(schema));
+        allowed && !self.denied_schemas.contains(schema)
     }
 
     /// Starts a read-only trasaction with repeatable read isolation level
@@ -113,6 +763,40 @@ impl ReplicationClient {
         Ok(())
     }
 
+    /// Sets a savepoint `name` in the current transaction, if there is one
+    pub async fn savepoint(&self, name: &str) -> Result<(), ReplicationClientError> {
+        if self.in_txn {
+            self.postgres_client
+                .simple_query(&format!("savepoint {};", quote_identifier(name)))
+                .await?;
+        }
+        Ok(())
+    }
+
+    /// Rolls the current transaction back to the savepoint `name`, which leaves a
+    /// transaction aborted by an error usable again
+    pub async fn rollback_to_savepoint(&self, name: &str) -> Result<(), ReplicationClientError> {
+        if self.in_txn {
+            self.postgres_client
+                .simple_query(&format!(
+                    "rollback to savepoint {};",
+                    quote_identifier(name)
+                ))
+                .await?;
+        }
+        Ok(())
+    }
+
+    /// Releases the savepoint `name`, keeping what was done since it was set
+    pub async fn release_savepoint(&self, name: &str) -> Result<(), ReplicationClientError> {
+        if self.in_txn {
+            self.postgres_client
+                .simple_query(&format!("release savepoint {};", quote_identifier(name)))
+                .await?;
+        }
+        Ok(())
+    }
+
     async fn rollback_txn(&mut self) -> Result<(), ReplicationClientError> {
         if self.in_txn {
             self.postgres_client.simple_query("rollback;").await?;
@@ -122,6 +806,7 @@ impl ReplicationClient {
     }
 
     /// Returns a [CopyOutStream] for a table
+    #[instrument(skip_all, fields(table = %table_name))]
     pub async fn get_table_copy_stream(
         &self,
         table_name: &TableName,
@@ -133,8 +818,62 @@ impl ReplicationClient {
             .collect::<Vec<_>>()
             .join(", ");
 
+        // Generated columns can't be listed in a COPY but can be selected
+        let copy_query = if column_schemas.iter().any(|col| col.generated) {
+            format!(
+                r#"COPY (SELECT {column_list} FROM {}) TO STDOUT WITH (FORMAT text);"#,
+                table_name.as_quoted_identifier(),
+            )
+        } else {
+            format!(
+                r#"COPY {} ({column_list}) TO STDOUT WITH (FORMAT text);"#,
+                table_name.as_quoted_identifier(),
+            )
+        };
+
+        let stream = self.postgres_client.copy_out_simple(&copy_query).await?;
+
+        Ok(stream)
+    }
+
+    /// Returns a [CopyOutStream] of at most `limit` rows of a table ordered by
+    /// `key_columns`, starting after the row whose key columns have the values `after`
+    /// in text format or, without `after`, from the first row. Copying a table in such chunks is
+    /// slower than a single [`ReplicationClient::get_table_copy_stream`] but can be
+    /// resumed after the last row copied.
+    #[instrument(skip_all, fields(table = %table_name))]
+    pub async fn get_table_keyset_copy_stream(
+        &self,
+        table_name: &TableName,
+        column_schemas: &[ColumnSchema],
+        key_columns: &[String],
+        after: Option<&[String]>,
+        limit: u64,
+    ) -> Result<CopyOutStream, ReplicationClientError> {
+        let column_list = column_schemas
+            .iter()
+            .map(|col| quote_identifier(&col.name))
+            .collect::<Vec<_>>()
+            .join(", ");
+        let key_list = key_columns
+            .iter()
+            .map(|name| quote_identifier(name))
+            .collect::<Vec<_>>()
+            .join(", ");
+        let key_pred = match after {
+            Some(after) => {
+                let values = after
+                    .iter()
+                    .map(|value| quote_literal(value))
+                    .collect::<Vec<_>>()
+                    .join(", ");
+                format!(" WHERE ({key_list}) > ({values})")
+            }
+            None => String::new(),
+        };
+
         let copy_query = format!(
-            r#"COPY {} ({column_list}) TO STDOUT WITH (FORMAT text);"#,
+            r#"COPY (SELECT {column_list} FROM {}{key_pred} ORDER BY {key_list} LIMIT {limit}) TO STDOUT WITH (FORMAT text);"#,
             table_name.as_quoted_identifier(),
         );
 
@@ -173,26 +912,48 @@ impl ReplicationClient {
             ("".into(), "")
         };
 
+        let generated_pred = if self.include_generated_columns {
+            ""
+        } else {
+            "and a.attgenerated = ''"
+        };
+
+        let comment_col = if self.include_comments {
+            "col_description(a.attrelid, a.attnum)"
+        } else {
+            "null"
+        };
+
         let column_info_query = format!(
             "{}
             select a.attname,
                 a.atttypid,
                 a.atttypmod,
                 a.attnotnull,
-                coalesce(i.indisprimary, false) as primary
+                coalesce(i.indisprimary, false) as primary,
+                c.collname,
+                a.attgenerated <> '' as generated,
+                {} as comment,
+                pg_get_expr(d.adbin, d.adrelid) as default_expression
             from pg_attribute a
             left join pg_index i
                 on a.attrelid = i.indrelid
                 and a.attnum = any(i.indkey)
                 and i.indisprimary = true
+            left join pg_collation c
+                on a.attcollation = c.oid
+            left join pg_attrdef d
+                on a.attrelid = d.adrelid
+                and a.attnum = d.adnum
+                and a.attgenerated = ''
             where a.attnum > 0::int2
             and not a.attisdropped
-            and a.attgenerated = ''
+            {}
             and a.attrelid = {}
             {}
             order by a.attnum
             ",
-            pub_cte, table_id, pub_pred
+            pub_cte, comment_col, generated_pred, table_id, pub_pred
         );
 
         let mut column_schemas = vec![];
@@ -220,13 +981,10 @@ impl ReplicationClient {
                     .parse()
                     .map_err(|_| ReplicationClientError::OidColumnNotU32)?;
 
-                //TODO: For now we assume all types are simple, fix it later
-                let typ = Type::from_oid(type_oid).unwrap_or(Type::new(
-                    format!("unnamed(oid: {type_oid})"),
-                    type_oid,
-                    Kind::Simple,
-                    "pg_catalog".to_string(),
-                ));
+                let typ = match Type::from_oid(type_oid) {
+                    Some(typ) => typ,
+                    None => self.get_type(type_oid).await?,
+                };
 
                 let modifier = row
                     .try_get("atttypmod")?
@@ -245,11 +1003,31 @@ impl ReplicationClient {
                         ))?
                         == "f";
 
+                // collname is null for columns of non-collatable types
+                let collation = row.try_get("collname")?.map(|c| c.to_string());
+
+                let generated = row.try_get("generated")? == Some("t");
+                if generated {
+                    warn!(
+                        "column {name} of table {table_id} is generated, cdc events won't carry its value"
+                    );
+                }
+
+                let comment = row.try_get("comment")?.map(|c| c.to_string());
+
+                // pg_attrdef holds the expressions of generated columns too, which
+                // aren't defaults and are excluded in the query
+                let default_expression = row.try_get("default_expression")?.map(|d| d.to_string());
+
                 column_schemas.push(ColumnSchema {
                     name,
                     typ,
                     modifier,
                     nullable,
+                    collation,
+                    generated,
+                    comment,
+                    default_expression,
                 })
             }
         }
@@ -404,42 +1182,282 @@ impl ReplicationClient {
         Ok(table_schemas)
     }
 
-    async fn get_table_schema(
+    /// Returns the schema of the table with id `table_id`, e.g. to refresh a cached
+    /// schema after the table was altered
+    pub async fn get_table_schema_by_id(
         &self,
-        table_name: TableName,
+        table_id: TableId,
         publication: Option<&str>,
     ) -> Result<TableSchema, ReplicationClientError> {
-        let table_id = self
-            .get_table_id(&table_name)
+        let table_name = self
+            .get_table_name(table_id)
             .await?
-            .ok_or(ReplicationClientError::MissingTable(table_name.clone()))?;
-
-        let column_schemas = self.get_column_schemas(table_id, publication).await?;
-        let lookup_key = self.get_lookup_key(table_id, &column_schemas).await?;
-
-        let table_schema = TableSchema {
-            table_name,
-            table_id,
-            column_schemas,
-            lookup_key,
-        };
-        Ok(table_schema)
+            .ok_or(ReplicationClientError::MissingTableId(table_id))?;
+        self.get_table_schema(table_name, publication).await
     }
 
-    /// Returns the table id (called relation id in Postgres) of a table
-    /// Also checks whether the replica identity is default or full and
-    /// returns an error if not.
-    pub async fn get_table_id(
+    /// Checks that `table_id` still belongs to `table_name`, e.g. in the transaction
+    /// of a slot's snapshot before backfilling a table whose schema was loaded
+    /// earlier. If the table was dropped and recreated in between, the copy would
+    /// read the new table or fail to find the relation midway.
+    pub async fn verify_table_id(
         &self,
-        table: &TableName,
-    ) -> Result<Option<TableId>, ReplicationClientError> {
-        let quoted_schema = quote_literal(&table.schema);
-        let quoted_name = quote_literal(&table.name);
+        table_id: TableId,
+        table_name: &TableName,
+    ) -> Result<(), ReplicationClientError> {
+        match self.get_table_name(table_id).await? {
+            Some(actual) if actual == *table_name => Ok(()),
+            _ => Err(ReplicationClientError::TableChanged(
+                table_id,
+                table_name.clone(),
+            )),
+        }
+    }
 
-        let table_info_query = format!(
-            "select c.oid,
-                c.relreplident
-            from pg_class c
+    async fn get_table_name(
+        &self,
+        table_id: TableId,
+    ) -> Result<Option<TableName>, ReplicationClientError> {
+        let query = format!(
+            "select n.nspname, c.relname
+            from pg_class c
+            join pg_namespace n on c.relnamespace = n.oid
+            where c.oid = {table_id};"
+        );
+
+        for msg in self.postgres_client.simple_query(&query).await? {
+            if let SimpleQueryMessage::Row(row) = msg {
+                let schema = row
+                    .get(0)
+                    .ok_or(ReplicationClientError::MissingColumn(
+                        "nspname".to_string(),
+                        "pg_namespace".to_string(),
+                    ))?
+                    .to_string();
+
+                let name = row
+                    .get(1)
+                    .ok_or(ReplicationClientError::MissingColumn(
+                        "relname".to_string(),
+                        "pg_class".to_string(),
+                    ))?
+                    .to_string();
+
+                return Ok(Some(TableName { schema, name }));
+            }
+        }
+
+        Ok(None)
+    }
+
+    async fn get_table_schema(
+        &self,
+        table_name: TableName,
+        publication: Option<&str>,
+    ) -> Result<TableSchema, ReplicationClientError> {
+        let table_id = self
+            .get_table_id(&table_name)
+            .await?
+            .ok_or(ReplicationClientError::MissingTable(table_name.clone()))?;
+
+        let mut column_schemas = self.get_column_schemas(table_id, publication).await?;
+        let lookup_key = self.get_lookup_key(table_id, &column_schemas).await?;
+
+        let mut excluded_columns = vec![];
+        if let Some(exclude_columns) = self.exclude_columns.get(&table_name) {
+            for column_name in exclude_columns {
+                let is_key_column = match &lookup_key {
+                    LookupKey::Key { name: _, columns } => columns.contains(column_name),
+                    LookupKey::FullRow => true,
+                };
+                if is_key_column {
+                    return Err(ReplicationClientError::ExcludedKeyColumn(
+                        column_name.clone(),
+                        table_name,
+                    ));
+                }
+            }
+            // Generated columns aren't sent in cdc events, so they have no position
+            excluded_columns = column_schemas
+                .iter()
+                .filter(|column_schema| !column_schema.generated)
+                .enumerate()
+                .filter(|(_, column_schema)| exclude_columns.contains(&column_schema.name))
+                .map(|(i, _)| i)
+                .collect();
+            column_schemas.retain(|column_schema| !exclude_columns.contains(&column_schema.name));
+        }
+
+        let comment = if self.include_comments {
+            self.get_table_comment(table_id).await?
+        } else {
+            None
+        };
+
+        let table_schema = TableSchema {
+            table_name,
+            table_id,
+            column_schemas,
+            lookup_key,
+            excluded_columns,
+            comment,
+        };
+        Ok(table_schema)
+    }
+
+    /// Looks up a type which isn't built into Postgres by its oid, e.g. one created by
+    /// an extension like PostGIS' `geometry`. Range and multirange types get their
+    /// subtype from `pg_range`, all other types are assumed to be simple.
+    async fn get_type(&self, type_oid: u32) -> Result<Type, ReplicationClientError> {
+        let unnamed = |type_oid: u32| {
+            Type::new(
+                format!("unnamed(oid: {type_oid})"),
+                type_oid,
+                Kind::Simple,
+                "pg_catalog".to_string(),
+            )
+        };
+
+        let type_query = format!(
+            "select t.typname, n.nspname, t.typtype
+            from pg_type t
+            join pg_namespace n on t.typnamespace = n.oid
+            where t.oid = {type_oid};"
+        );
+        let mut found = None;
+        for msg in self.postgres_client.simple_query(&type_query).await? {
+            if let SimpleQueryMessage::Row(row) = msg {
+                let name = row.try_get("typname")?.unwrap_or_default().to_string();
+                let schema = row.try_get("nspname")?.unwrap_or_default().to_string();
+                let typtype = row.try_get("typtype")?.unwrap_or_default().to_string();
+                found = Some((name, schema, typtype));
+            }
+        }
+        let Some((name, schema, typtype)) = found else {
+            return Ok(unnamed(type_oid));
+        };
+
+        // multiranges only exist since Postgres 14, so rngmultitypid is only
+        // queried for types which are multiranges
+        let range_pred = match typtype.as_str() {
+            "r" => "rngtypid",
+            "m" => "rngmultitypid",
+            _ => return Ok(Type::new(name, type_oid, Kind::Simple, schema)),
+        };
+        let range_query =
+            format!("select rngsubtype from pg_range where {range_pred} = {type_oid};");
+        let mut subtype_oid = None;
+        for msg in self.postgres_client.simple_query(&range_query).await? {
+            if let SimpleQueryMessage::Row(row) = msg {
+                subtype_oid = row
+                    .try_get("rngsubtype")?
+                    .map(|oid| oid.parse::<u32>())
+                    .transpose()
+                    .map_err(|_| ReplicationClientError::OidColumnNotU32)?;
+            }
+        }
+        let Some(subtype_oid) = subtype_oid else {
+            return Ok(unnamed(type_oid));
+        };
+
+        let subtype = Type::from_oid(subtype_oid).unwrap_or_else(|| unnamed(subtype_oid));
+        let kind = if typtype == "r" {
+            Kind::Range(subtype)
+        } else {
+            Kind::Multirange(subtype)
+        };
+        Ok(Type::new(name, type_oid, kind, schema))
+    }
+
+    async fn get_table_comment(
+        &self,
+        table_id: TableId,
+    ) -> Result<Option<String>, ReplicationClientError> {
+        let query = format!("select obj_description({table_id}, 'pg_class');");
+
+        for msg in self.postgres_client.simple_query(&query).await? {
+            if let SimpleQueryMessage::Row(row) = msg {
+                return Ok(row.get(0).map(|c| c.to_string()));
+            }
+        }
+
+        Ok(None)
+    }
+
+    /// Returns the foreign keys of a table, ordered by constraint name
+    pub async fn get_foreign_keys(
+        &self,
+        table_id: TableId,
+    ) -> Result<Vec<ForeignKey>, ReplicationClientError> {
+        let query = format!(
+            r#"select c.conname,
+                c.confrelid,
+                n.nspname,
+                r.relname,
+                (select json_agg(a.attname order by k.ord)
+                    from unnest(c.conkey) with ordinality k(attnum, ord)
+                    join pg_attribute a on a.attrelid = c.conrelid and a.attnum = k.attnum
+                ) as columns,
+                (select json_agg(a.attname order by k.ord)
+                    from unnest(c.confkey) with ordinality k(attnum, ord)
+                    join pg_attribute a on a.attrelid = c.confrelid and a.attnum = k.attnum
+                ) as referenced_columns
+            from pg_constraint c
+            join pg_class r on r.oid = c.confrelid
+            join pg_namespace n on n.oid = r.relnamespace
+            where c.contype = 'f' and c.conrelid = {table_id}
+            order by c.conname;"#
+        );
+
+        let mut foreign_keys = vec![];
+        for msg in self.postgres_client.simple_query(&query).await? {
+            if let SimpleQueryMessage::Row(row) = msg {
+                let get = |column: &str| {
+                    row.get(column).ok_or(ReplicationClientError::MissingColumn(
+                        column.to_string(),
+                        "pg_constraint".to_string(),
+                    ))
+                };
+                let get_names = |column: &str| {
+                    serde_json::from_str::<Vec<String>>(get(column)?)
+                        .map_err(|_| ReplicationClientError::InvalidColumnList(column.to_string()))
+                };
+
+                foreign_keys.push(ForeignKey {
+                    name: get("conname")?.to_string(),
+                    columns: get_names("columns")?,
+                    referenced_table_id: get("confrelid")?
+                        .parse()
+                        .map_err(|_| ReplicationClientError::OidColumnNotU32)?,
+                    referenced_table_name: TableName {
+                        schema: get("nspname")?.to_string(),
+                        name: get("relname")?.to_string(),
+                    },
+                    referenced_columns: get_names("referenced_columns")?,
+                });
+            }
+        }
+
+        Ok(foreign_keys)
+    }
+
+    /// Returns the table id (called relation id in Postgres) of a table
+    /// Also checks whether the replica identity is default or full and
+    /// returns an error if not. Unlogged and temporary tables are never
+    /// replicated because their changes aren't decoded from the WAL, so
+    /// they are errors too.
+    pub async fn get_table_id(
+        &self,
+        table: &TableName,
+    ) -> Result<Option<TableId>, ReplicationClientError> {
+        let quoted_schema = quote_literal(&table.schema);
+        let quoted_name = quote_literal(&table.name);
+
+        let table_info_query = format!(
+            "select c.oid,
+                c.relreplident,
+                c.relpersistence
+            from pg_class c
             join pg_namespace n
                 on (c.relnamespace = n.oid)
             where n.nspname = {}
@@ -457,6 +1475,19 @@ impl ReplicationClient {
                             "pg_class".to_string(),
                         ))?;
 
+                let persistence =
+                    row.try_get("relpersistence")?
+                        .ok_or(ReplicationClientError::MissingColumn(
+                            "relpersistence".to_string(),
+                            "pg_class".to_string(),
+                        ))?;
+
+                match persistence {
+                    "u" => return Err(ReplicationClientError::UnloggedTable(table.clone())),
+                    "t" => return Err(ReplicationClientError::TemporaryTable(table.clone())),
+                    _ => {}
+                }
+
                 if !(replica_identity == "d" || replica_identity == "f") {
                     return Err(ReplicationClientError::ReplicaIdentityNotSupported(
                         replica_identity.to_string(),
@@ -478,11 +1509,145 @@ impl ReplicationClient {
         Ok(None)
     }
 
-    /// Returns the slot info of an existing slot. The slot info currently only has the
-    /// confirmed_flush_lsn column of the pg_replication_slots table.
-    async fn get_slot(&self, slot_name: &str) -> Result<Option<SlotInfo>, ReplicationClientError> {
+    /// Counts the rows of a table, optionally only those matching `predicate`, a SQL
+    /// boolean expression which is inserted into the query as is
+    pub async fn count_rows(
+        &self,
+        table_name: &TableName,
+        predicate: Option<&str>,
+    ) -> Result<u64, ReplicationClientError> {
+        let mut query = format!(
+            "select count(*) as count from {}",
+            table_name.as_quoted_identifier()
+        );
+        if let Some(predicate) = predicate {
+            query.push_str(&format!(" where {predicate}"));
+        }
+        query.push(';');
+
+        for msg in self.postgres_client.simple_query(&query).await? {
+            if let SimpleQueryMessage::Row(row) = msg {
+                let count = row
+                    .get("count")
+                    .ok_or(ReplicationClientError::MissingColumn(
+                        "count".to_string(),
+                        table_name.to_string(),
+                    ))?;
+                return count
+                    .parse()
+                    .map_err(|_| ReplicationClientError::InvalidRowCount(count.to_string()));
+            }
+        }
+
+        Err(ReplicationClientError::MissingColumn(
+            "count".to_string(),
+            table_name.to_string(),
+        ))
+    }
+
+    /// Returns the estimated size of a table, `None` if it doesn't exist
+    pub async fn estimated_table_size(
+        &self,
+        table_id: TableId,
+    ) -> Result<Option<TableSizeEstimate>, ReplicationClientError> {
+        let query = format!(
+            "select reltuples, relpages, pg_total_relation_size(oid) as total_bytes
+            from pg_class
+            where oid = {table_id};"
+        );
+
+        for msg in self.postgres_client.simple_query(&query).await? {
+            if let SimpleQueryMessage::Row(row) = msg {
+                let get = |column: &str| {
+                    row.get(column).ok_or(ReplicationClientError::MissingColumn(
+                        column.to_string(),
+                        "pg_class".to_string(),
+                    ))
+                };
+                let invalid =
+                    |value: &str| ReplicationClientError::InvalidTableSize(value.to_string());
+
+                let reltuples = get("reltuples")?;
+                let relpages = get("relpages")?;
+                let total_bytes = get("total_bytes")?;
+                return Ok(Some(TableSizeEstimate {
+                    reltuples: reltuples.parse().map_err(|_| invalid(reltuples))?,
+                    relpages: relpages.parse().map_err(|_| invalid(relpages))?,
+                    total_bytes: total_bytes.parse().map_err(|_| invalid(total_bytes))?,
+                }));
+            }
+        }
+
+        Ok(None)
+    }
+
+    /// Returns the leaf partitions of a partitioned table, i.e. the tables that hold its
+    /// rows. A table which isn't partitioned is its own only leaf. Fails if a leaf is a
+    /// foreign table, whose rows live on another server and whose changes Postgres
+    /// doesn't publish, so that a copy doesn't silently miss them.
+    pub async fn get_leaf_partitions(
+        &self,
+        table_id: TableId,
+    ) -> Result<Vec<TableName>, ReplicationClientError> {
+        let query = format!(
+            "select n.nspname, c.relname, c.relkind
+            from pg_partition_tree({table_id}) t
+            join pg_class c on c.oid = t.relid
+            join pg_namespace n on n.oid = c.relnamespace
+            where t.isleaf
+            order by t.level, c.relname;"
+        );
+
+        let mut leaf_partitions = vec![];
+        for msg in self.postgres_client.simple_query(&query).await? {
+            if let SimpleQueryMessage::Row(row) = msg {
+                let schema = row
+                    .get(0)
+                    .ok_or(ReplicationClientError::MissingColumn(
+                        "nspname".to_string(),
+                        "pg_namespace".to_string(),
+                    ))?
+                    .to_string();
+
+                let name = row
+                    .get(1)
+                    .ok_or(ReplicationClientError::MissingColumn(
+                        "relname".to_string(),
+                        "pg_class".to_string(),
+                    ))?
+                    .to_string();
+
+                let relkind = row.get(2).ok_or(ReplicationClientError::MissingColumn(
+                    "relkind".to_string(),
+                    "pg_class".to_string(),
+                ))?;
+
+                let table_name = TableName { schema, name };
+                if relkind == "f" {
+                    return Err(ReplicationClientError::ForeignTablePartition(table_name));
+                }
+                leaf_partitions.push(table_name)
+            }
+        }
+
+        Ok(leaf_partitions)
+    }
+
+    /// Returns the slot info of an existing slot from the confirmed_flush_lsn,
+    /// two_phase and failover columns of the pg_replication_slots table
+    pub async fn get_slot(
+        &self,
+        slot_name: &str,
+    ) -> Result<Option<SlotInfo>, ReplicationClientError> {
+        // The failover column was added in Postgres 17
+        let failover = if self.server_version_num().await? >= 170000 {
+            "failover"
+        } else {
+            "false"
+        };
         let query = format!(
-            r#"select confirmed_flush_lsn from pg_replication_slots where slot_name = {};"#,
+            r#"select confirmed_flush_lsn, two_phase, {failover} as failover
+            from pg_replication_slots where slot_name = {};"#,
             quote_literal(slot_name)
         );
 
@@ -501,6 +1666,187 @@ impl ReplicationClient {
 
                 return Ok(Some(SlotInfo {
                     confirmed_flush_lsn,
+                    two_phase: row.get("two_phase") == Some("t"),
+                    failover: row.get("failover") == Some("t"),
+                }));
+            }
+        }
+
+        Ok(None)
+    }
+
+    /// Returns the server's version as a number, e.g. 170002 for Postgres 17.2
+    async fn server_version_num(&self) -> Result<u32, ReplicationClientError> {
+        let query = "select current_setting('server_version_num') as version;";
+        for msg in self.postgres_client.simple_query(query).await? {
+            if let SimpleQueryMessage::Row(row) = msg {
+                let version = row
+                    .get("version")
+                    .ok_or(ReplicationClientError::MissingColumn(
+                        "version".to_string(),
+                        "current_setting".to_string(),
+                    ))?;
+                return version.parse().map_err(|_| {
+                    ReplicationClientError::InvalidServerVersion(version.to_string())
+                });
+            }
+        }
+        Err(ReplicationClientError::MissingColumn(
+            "version".to_string(),
+            "current_setting".to_string(),
+        ))
+    }
+
+    /// Returns the confirmed flush lsn of a slot and the pid of the process consuming it
+    pub async fn get_slot_activity(
+        &self,
+        slot_name: &str,
+    ) -> Result<Option<SlotActivity>, ReplicationClientError> {
+        let query = format!(
+            r#"select confirmed_flush_lsn, active_pid from pg_replication_slots where slot_name = {};"#,
+            quote_literal(slot_name)
+        );
+
+        for res in self.postgres_client.simple_query(&query).await? {
+            if let SimpleQueryMessage::Row(row) = res {
+                let confirmed_flush_lsn = row
+                    .get("confirmed_flush_lsn")
+                    .ok_or(ReplicationClientError::MissingColumn(
+                        "confirmed_flush_lsn".to_string(),
+                        "pg_replication_slots".to_string(),
+                    ))?
+                    .parse()
+                    .map_err(|_| ReplicationClientError::InvalidPgLsn)?;
+
+                let active_pid = row
+                    .get("active_pid")
+                    .map(|pid| pid.parse())
+                    .transpose()
+                    .map_err(|_| ReplicationClientError::InvalidPid)?;
+
+                return Ok(Some(SlotActivity {
+                    confirmed_flush_lsn,
+                    active_pid,
+                }));
+            }
+        }
+
+        Ok(None)
+    }
+
+    /// Returns the server's system id, timeline and WAL position, e.g. to notice a
+    /// failover by comparing the timeline with the one seen before
+    pub async fn identify_system(&self) -> Result<SystemIdentity, ReplicationClientError> {
+        for msg in self.postgres_client.simple_query("IDENTIFY_SYSTEM").await? {
+            if let SimpleQueryMessage::Row(row) = msg {
+                let get = |column: &str| {
+                    row.get(column).ok_or(ReplicationClientError::MissingColumn(
+                        column.to_string(),
+                        "identify_system".to_string(),
+                    ))
+                };
+
+                let system_id = get("systemid")?.to_string();
+
+                let timeline = get("timeline")?;
+                let timeline = timeline
+                    .parse()
+                    .map_err(|_| ReplicationClientError::InvalidTimeline(timeline.to_string()))?;
+
+                let xlog_pos = get("xlogpos")?
+                    .parse()
+                    .map_err(|_| ReplicationClientError::InvalidPgLsn)?;
+
+                return Ok(SystemIdentity {
+                    system_id,
+                    timeline,
+                    xlog_pos,
+                });
+            }
+        }
+
+        Err(ReplicationClientError::MissingColumn(
+            "systemid".to_string(),
+            "identify_system".to_string(),
+        ))
+    }
+
+    /// Returns the server's current WAL write position, see `pg_current_wal_lsn()`.
+    /// Fails on a standby.
+    pub async fn current_wal_lsn(&self) -> Result<PgLsn, ReplicationClientError> {
+        self.get_wal_lsn("pg_current_wal_lsn").await
+    }
+
+    /// Returns the position up to which the server's WAL is flushed to disk, see
+    /// `pg_current_wal_flush_lsn()`. Fails on a standby.
+    pub async fn current_wal_flush_lsn(&self) -> Result<PgLsn, ReplicationClientError> {
+        self.get_wal_lsn("pg_current_wal_flush_lsn").await
+    }
+
+    /// Returns whether a consumer which applied everything up to `applied_lsn` is
+    /// within `max_lag_bytes` of the server's current flush lsn. As Postgres writes WAL
+    /// which isn't replicated, e.g. for vacuum or other databases, a consumer which has
+    /// received everything can still be somewhat behind the flush lsn.
+    pub async fn is_caught_up(
+        &self,
+        applied_lsn: PgLsn,
+        max_lag_bytes: u64,
+    ) -> Result<bool, ReplicationClientError> {
+        let flush_lsn = self.current_wal_flush_lsn().await?;
+        let lag = u64::from(flush_lsn).saturating_sub(applied_lsn.into());
+        Ok(lag <= max_lag_bytes)
+    }
+
+    async fn get_wal_lsn(&self, function: &str) -> Result<PgLsn, ReplicationClientError> {
+        let query = format!("select {function}() as lsn;");
+        for res in self.postgres_client.simple_query(&query).await? {
+            if let SimpleQueryMessage::Row(row) = res {
+                return row
+                    .get("lsn")
+                    .ok_or(ReplicationClientError::MissingColumn(
+                        "lsn".to_string(),
+                        function.to_string(),
+                    ))?
+                    .parse()
+                    .map_err(|_| ReplicationClientError::InvalidPgLsn);
+            }
+        }
+        Err(ReplicationClientError::MissingColumn(
+            "lsn".to_string(),
+            function.to_string(),
+        ))
+    }
+
+    /// Returns the lag of the walsender streaming from a slot, found by joining the
+    /// slot's active_pid with `pg_stat_replication`. Returns `None` if no process is
+    /// streaming from the slot.
+    pub async fn get_replication_lag(
+        &self,
+        slot_name: &str,
+    ) -> Result<Option<ReplicationLag>, ReplicationClientError> {
+        let query = format!(
+            r#"select extract(epoch from r.write_lag) as write_lag,
+                extract(epoch from r.flush_lag) as flush_lag,
+                extract(epoch from r.replay_lag) as replay_lag
+            from pg_replication_slots s
+            join pg_stat_replication r on r.pid = s.active_pid
+            where s.slot_name = {};"#,
+            quote_literal(slot_name)
+        );
+
+        for res in self.postgres_client.simple_query(&query).await? {
+            if let SimpleQueryMessage::Row(row) = res {
+                let get_lag = |column: &str| {
+                    row.get(column)
+                        .map(|secs| secs.parse().map(Duration::from_secs_f64))
+                        .transpose()
+                        .map_err(|_| ReplicationClientError::InvalidLag(column.to_string()))
+                };
+
+                return Ok(Some(ReplicationLag {
+                    write_lag: get_lag("write_lag")?,
+                    flush_lag: get_lag("flush_lag")?,
+                    replay_lag: get_lag("replay_lag")?,
                 }));
             }
         }
@@ -508,15 +1854,164 @@ impl ReplicationClient {
         Ok(None)
     }
 
+    /// Returns the commit lsn of the first transaction in a publication committed at or
+    /// after `timestamp`, to start streaming from a wall-clock time. A stream started at
+    /// the returned lsn begins with that transaction. Returns `None` if no such
+    /// transaction was committed yet.
+    ///
+    /// Only the transactions the slot still retains, those after its confirmed_flush_lsn,
+    /// are searched, so this can't go back further than the slot's position. The result
+    /// is precise to a transaction and relies on the clock of the Postgres server. It
+    /// needs no `track_commit_timestamp`, as the timestamps come from the commit records,
+    /// but the retained changes are decoded to find them, which can take a while if the
+    /// slot lags far behind. The slot must not be in use by a stream.
+    pub async fn find_lsn_at_time(
+        &self,
+        slot_name: &str,
+        publication: &str,
+        timestamp: DateTime<Utc>,
+    ) -> Result<Option<PgLsn>, ReplicationClientError> {
+        const POSTGRES_EPOCH_MICROS: i64 = 946_684_800_000_000;
+        let target = timestamp.timestamp_micros() - POSTGRES_EPOCH_MICROS;
+
+        // Only begin messages, which start with a 'B', hold the commit timestamp
+        let query = format!(
+            r#"select data from pg_logical_slot_peek_binary_changes(
+                {}, null, null, 'proto_version', '1', 'publication_names', {}
+            ) where get_byte(data, 0) = 66 order by lsn;"#,
+            quote_literal(slot_name),
+            quote_literal(publication)
+        );
+
+        for msg in self.postgres_client.simple_query(&query).await? {
+            if let SimpleQueryMessage::Row(row) = msg {
+                let data = row
+                    .get("data")
+                    .ok_or(ReplicationClientError::MissingColumn(
+                        "data".to_string(),
+                        "pg_logical_slot_peek_binary_changes".to_string(),
+                    ))?;
+                let data = from_bytea_hex(data)
+                    .map_err(|e| ReplicationClientError::InvalidMessage(e.to_string()))?;
+                let message = LogicalReplicationMessage::parse(&Bytes::from(data), false)
+                    .map_err(|e| ReplicationClientError::InvalidMessage(e.to_string()))?;
+                if let LogicalReplicationMessage::Begin(begin_body) = message {
+                    if begin_body.timestamp() >= target {
+                        return Ok(Some(begin_body.final_lsn().into()));
+                    }
+                }
+            }
+        }
+
+        Ok(None)
+    }
+
+    /// Returns whether a slot still retains the WAL needed to resume streaming from `lsn`.
+    /// This is not the case if the slot's restart_lsn has advanced past `lsn` or if the
+    /// WAL it requires has been removed (wal_status is lost). A consumer resuming from a
+    /// stored lsn should check this first and re-backfill if it returns false, as
+    /// starting replication would otherwise fail or silently skip changes.
+    pub async fn is_lsn_retained(
+        &self,
+        slot_name: &str,
+        lsn: PgLsn,
+    ) -> Result<bool, ReplicationClientError> {
+        let query = format!(
+            r#"select restart_lsn, wal_status from pg_replication_slots where slot_name = {};"#,
+            quote_literal(slot_name)
+        );
+
+        for res in self.postgres_client.simple_query(&query).await? {
+            if let SimpleQueryMessage::Row(row) = res {
+                if row.get("wal_status") == Some("lost") {
+                    return Ok(false);
+                }
+
+                // restart_lsn is null if the slot has never reserved WAL or has lost it
+                let Some(restart_lsn) = row.get("restart_lsn") else {
+                    return Ok(false);
+                };
+                let restart_lsn: PgLsn = restart_lsn
+                    .parse()
+                    .map_err(|_| ReplicationClientError::InvalidPgLsn)?;
+
+                return Ok(restart_lsn <= lsn);
+            }
+        }
+
+        Err(ReplicationClientError::MissingSlot(slot_name.to_string()))
+    }
+
+    /// Waits until no process consumes a slot, which allows handing a slot over from one
+    /// consumer to another, e.g. during a blue/green deployment. The new consumer calls
+    /// this after the old one has been asked to stop and then starts streaming from the
+    /// returned confirmed flush lsn, so no position is lost. Returns an error if the slot
+    /// is still active after `timeout`.
+    ///
+    /// Nothing prevents a third process from acquiring the slot after this returns, in
+    /// which case starting replication fails with a "slot is active" error.
+    pub async fn wait_for_slot_inactive(
+        &self,
+        slot_name: &str,
+        timeout: Duration,
+    ) -> Result<SlotActivity, ReplicationClientError> {
+        let start = Instant::now();
+        loop {
+            let slot_activity = self
+                .get_slot_activity(slot_name)
+                .await?
+                .ok_or(ReplicationClientError::MissingSlot(slot_name.to_string()))?;
+
+            if slot_activity.active_pid.is_none() {
+                return Ok(slot_activity);
+            }
+
+            if start.elapsed() >= timeout {
+                return Err(ReplicationClientError::SlotStillActive(
+                    slot_name.to_string(),
+                    timeout,
+                ));
+            }
+
+            tokio::time::sleep(SLOT_INACTIVE_POLL_INTERVAL).await;
+        }
+    }
+
     /// Creates a logical replication slot. This will only succeed if the postgres connection
     /// is in logical replication mode. Otherwise it will fail with the following error:
     /// `syntax error at or near "CREATE_REPLICATION_SLOT"``
     ///
-    /// Returns the consistent_point column as slot info.
-    async fn create_slot(&self, slot_name: &str) -> Result<SlotInfo, ReplicationClientError> {
+    /// `snapshot_action` is `USE_SNAPSHOT` or `EXPORT_SNAPSHOT`. Returns the
+    /// consistent_point column as slot info and the snapshot_name column, which is
+    /// only set for an exported snapshot.
+    #[instrument(skip(self))]
+    async fn create_slot(
+        &self,
+        slot_name: &str,
+        temporary: bool,
+        snapshot_action: &str,
+    ) -> Result<(SlotInfo, Option<String>), ReplicationClientError> {
+        // Temporary slots are dropped with the connection, so they can't fail over
+        let failover = self.failover_slots && !temporary;
+        let temporary = if temporary { " TEMPORARY" } else { "" };
+        // Failover is only an option of the parenthesized syntax of Postgres 15+
+        let options = if failover {
+            let version = self.server_version_num().await?;
+            if version < 170000 {
+                return Err(ReplicationClientError::FailoverSlotsUnsupported(version));
+            }
+            let snapshot = match snapshot_action {
+                "EXPORT_SNAPSHOT" => "export",
+                _ => "use",
+            };
+            format!("(SNAPSHOT '{snapshot}', FAILOVER)")
+        } else {
+            snapshot_action.to_string()
+        };
         let query = format!(
-            r#"CREATE_REPLICATION_SLOT {} LOGICAL pgoutput USE_SNAPSHOT"#,
-            quote_identifier(slot_name)
+            r#"CREATE_REPLICATION_SLOT {}{temporary} LOGICAL {} {options}"#,
+            quote_identifier(slot_name),
+            self.output_plugin.name(),
         );
         let results = self.postgres_client.simple_query(&query).await?;
 
@@ -530,9 +2025,13 @@ impl ReplicationClient {
                     ))?
                     .parse()
                     .map_err(|_| ReplicationClientError::InvalidPgLsn)?;
-                return Ok(SlotInfo {
+                let snapshot_name = row.get("snapshot_name").map(|name| name.to_string());
+                let slot_info = SlotInfo {
                     confirmed_flush_lsn: consistent_point,
-                });
+                    two_phase: false,
+                    failover,
+                };
+                return Ok((slot_info, snapshot_name));
             }
         }
         Err(ReplicationClientError::FailedToCreateSlot)
@@ -547,13 +2046,74 @@ impl ReplicationClient {
         if let Some(slot_info) = self.get_slot(slot_name).await? {
             Ok(slot_info)
         } else {
-            self.rollback_txn().await?;
-            self.begin_readonly_transaction().await?;
-            Ok(self.create_slot(slot_name).await?)
+            self.create_slot_using_snapshot(slot_name, false).await
         }
     }
 
-    /// Returns all table names in a publication
+    /// Creates a new slot and starts a read-only transaction on this connection which
+    /// sees the database as of the slot's consistent point. Fails if the slot exists.
+    /// A `temporary` slot is dropped when this connection closes.
+    pub async fn create_slot_using_snapshot(
+        &mut self,
+        slot_name: &str,
+        temporary: bool,
+    ) -> Result<SlotInfo, ReplicationClientError> {
+        self.rollback_txn().await?;
+        self.begin_readonly_transaction().await?;
+        let (slot_info, _) = self
+            .create_slot(slot_name, temporary, "USE_SNAPSHOT")
+            .await?;
+        Ok(slot_info)
+    }
+
+    /// Creates a new slot and exports a snapshot of the database as of the slot's
+    /// consistent point, returning the snapshot's name. Other connections can import
+    /// the snapshot with [`ReplicationClient::begin_readonly_transaction_with_snapshot`]
+    /// until this connection runs another command. Fails if the slot exists. A
+    /// `temporary` slot is dropped when this connection closes.
+    pub async fn create_slot_exporting_snapshot(
+        &mut self,
+        slot_name: &str,
+        temporary: bool,
+    ) -> Result<(SlotInfo, String), ReplicationClientError> {
+        self.rollback_txn().await?;
+        let (slot_info, snapshot_name) = self
+            .create_slot(slot_name, temporary, "EXPORT_SNAPSHOT")
+            .await?;
+        let snapshot_name = snapshot_name.ok_or(ReplicationClientError::MissingColumn(
+            "snapshot_name".to_string(),
+            "create_replication_slot".to_string(),
+        ))?;
+        Ok((slot_info, snapshot_name))
+    }
+
+    /// Drops the slot `slot_name`, which must not be in use
+    pub async fn drop_slot(&self, slot_name: &str) -> Result<(), ReplicationClientError> {
+        let query = format!("DROP_REPLICATION_SLOT {}", quote_identifier(slot_name));
+        self.postgres_client.simple_query(&query).await?;
+        Ok(())
+    }
+
+    /// Starts a read-only transaction with repeatable read isolation level which sees
+    /// the database as of a snapshot exported by another connection
+    pub async fn begin_readonly_transaction_with_snapshot(
+        &mut self,
+        snapshot_name: &str,
+    ) -> Result<(), ReplicationClientError> {
+        self.rollback_txn().await?;
+        self.begin_readonly_transaction().await?;
+        self.postgres_client
+            .simple_query(&format!(
+                "set transaction snapshot {};",
+                quote_literal(snapshot_name)
+            ))
+            .await?;
+        Ok(())
+    }
+
+    /// Returns all table names in a publication, except those outside the schemas
+    /// allowed by [`ReplicationClient::set_allowed_schemas`] and
+    /// [`ReplicationClient::set_denied_schemas`]
     pub async fn get_publication_table_names(
         &self,
         publication: &str,
@@ -586,6 +2146,10 @@ impl ReplicationClient {
                     ))?
                     .to_string();
 
+                if !self.is_schema_allowed(&schema) {
+                    continue;
+                }
+
                 table_names.push(TableName { schema, name })
             }
         }
@@ -593,6 +2157,114 @@ impl ReplicationClient {
         Ok(table_names)
     }
 
+    /// Returns the tables in a publication like
+    /// [`ReplicationClient::get_publication_table_names`] together with their row
+    /// filters, ordered by name, e.g. to show which rows of each table are
+    /// replicated. Only tables added by name can have a row filter, and row filters
+    /// were added in Postgres 15, so older servers have none.
+    pub async fn get_publication_tables(
+        &self,
+        publication: &str,
+    ) -> Result<Vec<PublicationTable>, ReplicationClientError> {
+        let version_query = "select current_setting('server_version_num')::int >= 150000
+            as supported;";
+        let mut supported = false;
+        for msg in self.postgres_client.simple_query(version_query).await? {
+            if let SimpleQueryMessage::Row(row) = msg {
+                supported = row.get("supported") == Some("t");
+            }
+        }
+
+        let row_filter = if supported {
+            "pg_get_expr(r.prqual, r.prrelid)"
+        } else {
+            "null"
+        };
+        let query = format!(
+            "select t.schemaname, t.tablename, {row_filter} as row_filter
+            from pg_publication_tables t
+            join pg_publication p on p.pubname = t.pubname
+            join pg_namespace n on n.nspname = t.schemaname
+            join pg_class c on c.relnamespace = n.oid and c.relname = t.tablename
+            left join pg_publication_rel r on r.prpubid = p.oid and r.prrelid = c.oid
+            where t.pubname = {}
+            order by t.schemaname, t.tablename;",
+            quote_literal(publication)
+        );
+
+        let mut tables = vec![];
+        for msg in self.postgres_client.simple_query(&query).await? {
+            if let SimpleQueryMessage::Row(row) = msg {
+                let get = |column: &str| {
+                    row.get(column).ok_or(ReplicationClientError::MissingColumn(
+                        column.to_string(),
+                        "pg_publication_tables".to_string(),
+                    ))
+                };
+
+                let schema = get("schemaname")?.to_string();
+                if !self.is_schema_allowed(&schema) {
+                    continue;
+                }
+
+                tables.push(PublicationTable {
+                    table_name: TableName {
+                        schema,
+                        name: get("tablename")?.to_string(),
+                    },
+                    row_filter: row.get("row_filter").map(|f| f.to_string()),
+                });
+            }
+        }
+
+        Ok(tables)
+    }
+
+    /// Returns the schemas a publication includes as a whole, added with `FOR TABLES
+    /// IN SCHEMA`, ordered by name. Unlike tables added by name, tables created in
+    /// these schemas later join the publication automatically, so their changes
+    /// show up in the cdc stream without altering the publication. Schema
+    /// publications were added in Postgres 15, older servers have none.
+    pub async fn get_publication_schemas(
+        &self,
+        publication: &str,
+    ) -> Result<Vec<String>, ReplicationClientError> {
+        let version_query = "select current_setting('server_version_num')::int >= 150000
+            as supported;";
+        for msg in self.postgres_client.simple_query(version_query).await? {
+            if let SimpleQueryMessage::Row(row) = msg {
+                if row.get("supported") != Some("t") {
+                    return Ok(vec![]);
+                }
+            }
+        }
+
+        let query = format!(
+            "select n.nspname
+            from pg_publication_namespace pn
+            join pg_publication p on p.oid = pn.pnpubid
+            join pg_namespace n on n.oid = pn.pnnspid
+            where p.pubname = {}
+            order by n.nspname;",
+            quote_literal(publication)
+        );
+
+        let mut schemas = vec![];
+        for msg in self.postgres_client.simple_query(&query).await? {
+            if let SimpleQueryMessage::Row(row) = msg {
+                let schema = row
+                    .get("nspname")
+                    .ok_or(ReplicationClientError::MissingColumn(
+                        "nspname".to_string(),
+                        "pg_namespace".to_string(),
+                    ))?;
+                schemas.push(schema.to_string());
+            }
+        }
+
+        Ok(schemas)
+    }
+
     pub async fn publication_exists(
         &self,
         publication: &str,
@@ -613,12 +2285,362 @@ impl ReplicationClient {
         Ok(false)
     }
 
+    /// Creates a publication of `tables` with `options`. Without tables the
+    /// publication is empty, and tables can be added with `alter publication`.
+    pub async fn create_publication(
+        &self,
+        publication: &str,
+        tables: &[TableName],
+        options: &PublicationOptions,
+    ) -> Result<(), ReplicationClientError> {
+        let for_tables = if tables.is_empty() {
+            String::new()
+        } else {
+            let tables: Vec<String> = tables.iter().map(TableName::as_quoted_identifier).collect();
+            format!(" for table {}", tables.join(", "))
+        };
+        let query = format!(
+            "create publication {}{for_tables} {};",
+            quote_identifier(publication),
+            options.with_clause()
+        );
+        self.postgres_client.simple_query(&query).await?;
+        Ok(())
+    }
+
+    /// Returns the options of an existing publication, `None` if it doesn't exist
+    pub async fn get_publication_options(
+        &self,
+        publication: &str,
+    ) -> Result<Option<PublicationOptions>, ReplicationClientError> {
+        let query = format!(
+            "select pubinsert, pubupdate, pubdelete, pubtruncate, pubviaroot
+            from pg_publication where pubname = {};",
+            quote_literal(publication)
+        );
+
+        for msg in self.postgres_client.simple_query(&query).await? {
+            if let SimpleQueryMessage::Row(row) = msg {
+                let get_bool = |column: &str| {
+                    row.get(column).map(|value| value == "t").ok_or(
+                        ReplicationClientError::MissingColumn(
+                            column.to_string(),
+                            "pg_publication".to_string(),
+                        ),
+                    )
+                };
+                let mut publish = HashSet::new();
+                for (operation, column) in PublishedOperation::ALL.into_iter().zip([
+                    "pubinsert",
+                    "pubupdate",
+                    "pubdelete",
+                    "pubtruncate",
+                ]) {
+                    if get_bool(column)? {
+                        publish.insert(operation);
+                    }
+                }
+                return Ok(Some(PublicationOptions {
+                    publish,
+                    via_partition_root: get_bool("pubviaroot")?,
+                }));
+            }
+        }
+
+        Ok(None)
+    }
+
+    /// Checks the server's settings needed to replicate `publication`, e.g. to
+    /// validate a source's config before a pipeline is created for it. Nothing is
+    /// created or changed. That the server can be connected to at all is checked by
+    /// connecting the client.
+    pub async fn check_source(
+        &self,
+        publication: &str,
+    ) -> Result<SourceCheck, ReplicationClientError> {
+        let query = "select current_setting('wal_level') as wal_level,
+            current_setting('track_commit_timestamp') as track_commit_timestamp,
+            current_setting('max_replication_slots')::int
+                - (select count(*) from pg_replication_slots) as slots_available;";
+
+        let mut settings = None;
+        for msg in self.postgres_client.simple_query(query).await? {
+            if let SimpleQueryMessage::Row(row) = msg {
+                let get = |column: &str| {
+                    row.get(column).ok_or(ReplicationClientError::MissingColumn(
+                        column.to_string(),
+                        "pg_settings".to_string(),
+                    ))
+                };
+                let slots_available = get("slots_available")?;
+                let slots_available: i64 = slots_available.parse().map_err(|_| {
+                    ReplicationClientError::InvalidSlotCount(slots_available.to_string())
+                })?;
+                settings = Some((
+                    get("wal_level")? == "logical",
+                    get("track_commit_timestamp")? == "on",
+                    slots_available.max(0),
+                ));
+            }
+        }
+        let (wal_level_ok, track_commit_timestamp, slots_available) =
+            settings.ok_or(ReplicationClientError::MissingColumn(
+                "wal_level".to_string(),
+                "pg_settings".to_string(),
+            ))?;
+
+        Ok(SourceCheck {
+            wal_level_ok,
+            publication_exists: self.publication_exists(publication).await?,
+            slots_available: slots_available as u32,
+            track_commit_timestamp,
+        })
+    }
+
+    /// Whether `track_commit_timestamp` is on, i.e. Postgres records when and from
+    /// which replication origin transactions commit. Changing it needs a restart, and
+    /// only transactions committed while it's on are recorded.
+    pub async fn is_commit_timestamp_tracked(&self) -> Result<bool, ReplicationClientError> {
+        let query = "select current_setting('track_commit_timestamp') as track_commit_timestamp;";
+        for msg in self.postgres_client.simple_query(query).await? {
+            if let SimpleQueryMessage::Row(row) = msg {
+                return Ok(row.get("track_commit_timestamp") == Some("on"));
+            }
+        }
+        Err(ReplicationClientError::MissingColumn(
+            "track_commit_timestamp".to_string(),
+            "pg_settings".to_string(),
+        ))
+    }
+
+    /// Returns when transaction `xid` was committed and the replication origin it
+    /// was replayed from, e.g. to audit where a change came from. Returns `None` if
+    /// the transaction didn't commit, committed before `track_commit_timestamp` was
+    /// turned on or is too old for its timestamp to still be kept. Fails with
+    /// [`ReplicationClientError::CommitTimestampNotTracked`] if
+    /// `track_commit_timestamp` is off.
+    pub async fn get_commit_timestamp_origin(
+        &self,
+        xid: u32,
+    ) -> Result<Option<CommitTimestampOrigin>, ReplicationClientError> {
+        if !self.is_commit_timestamp_tracked().await? {
+            return Err(ReplicationClientError::CommitTimestampNotTracked);
+        }
+
+        let query = format!(
+            "select (extract(epoch from c.timestamp) * 1000000)::int8 as timestamp,
+                c.roident, o.roname
+            from pg_xact_commit_timestamp_origin('{xid}'::xid) c
+            left join pg_replication_origin o on o.roident = c.roident;"
+        );
+        for msg in self.postgres_client.simple_query(&query).await? {
+            if let SimpleQueryMessage::Row(row) = msg {
+                let Some(micros) = row.get("timestamp") else {
+                    return Ok(None);
+                };
+                let timestamp = micros
+                    .parse()
+                    .ok()
+                    .and_then(DateTime::from_timestamp_micros)
+                    .ok_or_else(|| {
+                        ReplicationClientError::InvalidCommitTimestamp(micros.to_string())
+                    })?;
+                let origin_id = match row.get("roident") {
+                    Some("0") | None => None,
+                    Some(roident) => Some(
+                        roident
+                            .parse()
+                            .map_err(|_| ReplicationClientError::OidColumnNotU32)?,
+                    ),
+                };
+                return Ok(Some(CommitTimestampOrigin {
+                    timestamp,
+                    origin_id,
+                    origin: row.get("roname").map(str::to_string),
+                }));
+            }
+        }
+
+        Ok(None)
+    }
+
+    /// Returns all publications in the database, ordered by name
+    pub async fn list_publications(&self) -> Result<Vec<PublicationInfo>, ReplicationClientError> {
+        let query = "select pubname, pg_get_userbyid(pubowner) as owner, puballtables,
+            pubinsert, pubupdate, pubdelete, pubtruncate, pubviaroot
+            from pg_publication order by pubname;";
+
+        let mut publications = vec![];
+        for msg in self.postgres_client.simple_query(query).await? {
+            if let SimpleQueryMessage::Row(row) = msg {
+                let get = |column: &str| {
+                    row.get(column).ok_or(ReplicationClientError::MissingColumn(
+                        column.to_string(),
+                        "pg_publication".to_string(),
+                    ))
+                };
+                let get_bool = |column: &str| get(column).map(|value| value == "t");
+
+                publications.push(PublicationInfo {
+                    name: get("pubname")?.to_string(),
+                    owner: get("owner")?.to_string(),
+                    all_tables: get_bool("puballtables")?,
+                    via_root: get_bool("pubviaroot")?,
+                    operations: PublishedOperations {
+                        insert: get_bool("pubinsert")?,
+                        update: get_bool("pubupdate")?,
+                        delete: get_bool("pubdelete")?,
+                        truncate: get_bool("pubtruncate")?,
+                    },
+                });
+            }
+        }
+
+        Ok(publications)
+    }
+
+    /// Checks that every table in a publication which publishes updates or deletes
+    /// has a replica identity, as Postgres otherwise fails the updates and deletes
+    /// themselves, not just their replication. Returns the tables ordered by name.
+    pub async fn check_replica_identities(
+        &self,
+        publication: &str,
+    ) -> Result<Vec<TableReplicaIdentity>, ReplicationClientError> {
+        if !self.publication_exists(publication).await? {
+            return Err(ReplicationClientError::MissingPublication(
+                publication.to_string(),
+            ));
+        }
+
+        let query = format!(
+            r#"select n.nspname, c.relname, c.relreplident,
+                p.pubupdate or p.pubdelete as publishes_changes,
+                exists(
+                    select 1 from pg_index i where i.indrelid = c.oid and i.indisprimary
+                ) as has_primary_key,
+                exists(
+                    select 1 from pg_index i where i.indrelid = c.oid and i.indisreplident
+                ) as has_identity_index
+            from pg_publication p
+            join pg_publication_tables pt on pt.pubname = p.pubname
+            join pg_namespace n on n.nspname = pt.schemaname
+            join pg_class c on c.relnamespace = n.oid and c.relname = pt.tablename
+            where p.pubname = {}
+            order by n.nspname, c.relname;"#,
+            quote_literal(publication)
+        );
+
+        let mut tables = vec![];
+        for msg in self.postgres_client.simple_query(&query).await? {
+            if let SimpleQueryMessage::Row(row) = msg {
+                let get = |column: &str| {
+                    row.get(column).ok_or(ReplicationClientError::MissingColumn(
+                        column.to_string(),
+                        "pg_publication_tables".to_string(),
+                    ))
+                };
+                let get_bool = |column: &str| get(column).map(|value| value == "t");
+
+                let replica_identity = match get("relreplident")? {
+                    "d" => ReplicaIdentity::Default,
+                    "n" => ReplicaIdentity::Nothing,
+                    "f" => ReplicaIdentity::Full,
+                    "i" => ReplicaIdentity::Index,
+                    replica_identity => {
+                        return Err(ReplicationClientError::ReplicaIdentityNotSupported(
+                            replica_identity.to_string(),
+                        ))
+                    }
+                };
+                let problem = match replica_identity {
+                    _ if !get_bool("publishes_changes")? => None,
+                    ReplicaIdentity::Default if !get_bool("has_primary_key")? => {
+                        Some(ReplicaIdentityProblem::NoPrimaryKey)
+                    }
+                    ReplicaIdentity::Nothing => Some(ReplicaIdentityProblem::Nothing),
+                    ReplicaIdentity::Index if !get_bool("has_identity_index")? => {
+                        Some(ReplicaIdentityProblem::NoIdentityIndex)
+                    }
+                    _ => None,
+                };
+
+                tables.push(TableReplicaIdentity {
+                    table_name: TableName {
+                        schema: get("nspname")?.to_string(),
+                        name: get("relname")?.to_string(),
+                    },
+                    replica_identity,
+                    problem,
+                });
+            }
+        }
+
+        Ok(tables)
+    }
+
+    /// Returns the current values of `column_names` in text format from the row identified
+    /// by `key`, which holds pairs of key column names and their values in text format.
+    /// Returns `None` if no such row exists.
+    pub async fn get_column_values(
+        &self,
+        table_name: &TableName,
+        column_names: &[String],
+        key: &[(String, String)],
+    ) -> Result<Option<Vec<Option<String>>>, ReplicationClientError> {
+        let column_list = column_names
+            .iter()
+            .map(|name| quote_identifier(name))
+            .collect::<Vec<_>>()
+            .join(", ");
+        let key_pred = key
+            .iter()
+            .map(|(name, value)| format!("{} = {}", quote_identifier(name), quote_literal(value)))
+            .collect::<Vec<_>>()
+            .join(" and ");
+
+        let query = format!(
+            "select {column_list} from {} where {key_pred};",
+            table_name.as_quoted_identifier()
+        );
+
+        for msg in self.postgres_client.simple_query(&query).await? {
+            if let SimpleQueryMessage::Row(row) = msg {
+                let mut values = Vec::with_capacity(column_names.len());
+                for i in 0..column_names.len() {
+                    values.push(row.try_get(i)?.map(|value| value.to_string()));
+                }
+                return Ok(Some(values));
+            }
+        }
+
+        Ok(None)
+    }
+
+    #[instrument(skip(self, start_lsn), fields(start_lsn = %start_lsn))]
     pub async fn get_logical_replication_stream(
         &self,
         publication: &str,
         slot_name: &str,
         start_lsn: PgLsn,
     ) -> Result<LogicalReplicationStream, ReplicationClientError> {
+        let copy_stream = self
+            .get_raw_logical_replication_stream(publication, slot_name, start_lsn)
+            .await?;
+
+        let stream = LogicalReplicationStream::new(copy_stream, Some(2));
+
+        Ok(stream)
+    }
+
+    /// Starts the same stream as [`ReplicationClient::get_logical_replication_stream`]
+    /// but returns its messages undecoded, as Postgres sends them
+    #[instrument(skip(self, start_lsn), fields(start_lsn = %start_lsn))]
+    pub async fn get_raw_logical_replication_stream(
+        &self,
+        publication: &str,
+        slot_name: &str,
+        start_lsn: PgLsn,
+    ) -> Result<CopyBothDuplex<Bytes>, ReplicationClientError> {
         let options = format!(
             r#"("proto_version" '2', "publication_names" {}, "streaming" 'on')"#,
             quote_literal(publication),
@@ -633,11 +2655,90 @@ impl ReplicationClient {
 
         let copy_stream = self
             .postgres_client
-            .copy_both_simple::<bytes::Bytes>(&query)
-            .await?;
+            .copy_both_simple::<Bytes>(&query)
+            .await
+            .map_err(|e| Self::start_replication_error(e, slot_name))?;
 
-        let stream = LogicalReplicationStream::new(copy_stream, Some(2));
+        Ok(copy_stream)
+    }
 
-        Ok(stream)
+    /// Starts streaming the changes to `table_names` from a slot created with the
+    /// [`OutputPlugin::Wal2Json`] plugin. The stream's messages hold one JSON change
+    /// record each, in wal2json's format version 2.
+    #[instrument(skip(self, start_lsn, table_names), fields(start_lsn = %start_lsn))]
+    pub async fn get_wal2json_stream(
+        &self,
+        slot_name: &str,
+        start_lsn: PgLsn,
+        table_names: &[TableName],
+    ) -> Result<CopyBothDuplex<Bytes>, ReplicationClientError> {
+        // wal2json requires special characters in table names to be escaped
+        let escape = |name: &str| {
+            let mut escaped = String::with_capacity(name.len());
+            for c in name.chars() {
+                if matches!(c, ' ' | '\'' | ',' | '.' | '*' | '\\') {
+                    escaped.push('\\');
+                }
+                escaped.push(c);
+            }
+            escaped
+        };
+        let add_tables = table_names
+            .iter()
+            .map(|table_name| {
+                format!(
+                    "{}.{}",
+                    escape(&table_name.schema),
+                    escape(&table_name.name)
+                )
+            })
+            .collect::<Vec<_>>()
+            .join(",");
+        let options = format!(
+            r#"("format-version" '2', "include-xids" '1', "include-lsn" '1', "include-timestamp" '1', "include-transaction" '1', "add-tables" {})"#,
+            quote_literal(&add_tables),
+        );
+
+        let query = format!(
+            r#"START_REPLICATION SLOT {} LOGICAL {} {}"#,
+            quote_identifier(slot_name),
+            start_lsn,
+            options
+        );
+
+        let copy_stream = self
+            .postgres_client
+            .copy_both_simple::<Bytes>(&query)
+            .await
+            .map_err(|e| Self::start_replication_error(e, slot_name))?;
+
+        Ok(copy_stream)
+    }
+
+    /// Tells apart the errors of `START_REPLICATION` which callers can act on: the slot
+    /// being in use by another process, or missing, e.g. after a failover to a standby
+    /// the slot wasn't synchronized to
+    fn start_replication_error(
+        e: tokio_postgres::Error,
+        slot_name: &str,
+    ) -> ReplicationClientError {
+        if let Some(pid) = Self::slot_active_pid(&e) {
+            return ReplicationClientError::SlotActive { pid };
+        }
+        if e.code() == Some(&SqlState::UNDEFINED_OBJECT) {
+            return ReplicationClientError::MissingSlot(slot_name.to_string());
+        }
+        e.into()
+    }
+
+    /// Returns the pid from a "replication slot is active for PID" error, which
+    /// Postgres returns when another process is still streaming from the slot
+    fn slot_active_pid(e: &tokio_postgres::Error) -> Option<i32> {
+        let db_error = e.as_db_error()?;
+        if *db_error.code() != SqlState::OBJECT_IN_USE {
+            return None;
+        }
+        let (_, pid) = db_error.message().rsplit_once("PID ")?;
+        pid.parse().ok()
     }
 }
@@ -0,0 +1,396 @@
+use std::sync::Arc;
+
+use arrow::{
+    array::{
+        ArrayRef, BinaryBuilder, BooleanBuilder, Date32Builder, Float32Builder, Float64Builder,
+        Int16Builder, Int32Builder, Int64Builder, IntervalMonthDayNanoBuilder, ListBuilder,
+        StringBuilder, Time64MicrosecondBuilder, TimestampMicrosecondBuilder, UInt32Builder,
+    },
+    datatypes::{
+        DataType, Field, IntervalMonthDayNano, IntervalMonthDayNanoType, IntervalUnit, Schema,
+        SchemaRef, TimeUnit,
+    },
+    error::ArrowError,
+    record_batch::RecordBatch,
+};
+use thiserror::Error;
+use tokio_postgres::types::{Kind, Type};
+
+use crate::table::{ColumnSchema, TableSchema};
+
+use super::{
+    days_since_epoch,
+    geometry::is_geometry,
+    interval::PgInterval,
+    micros_since_midnight,
+    range::{multirange_to_text, range_to_text},
+    table_row::TableRow,
+    ArrayCell, Cell,
+};
+
+/// Name of the column holding the [`RowOp`] of each row in a record batch
+pub const OP_COLUMN_NAME: &str = "_pg_replicate_op";
+
+#[derive(Debug, Error)]
+pub enum ArrowConversionError {
+    #[error("unexpected value {1} in column {0}")]
+    UnexpectedCell(String, String),
+
+    #[error("row has {1} values but the table has {0} columns")]
+    ColumnCountMismatch(usize, usize),
+
+    #[error("arrow error: {0}")]
+    Arrow(#[from] ArrowError),
+}
+
+/// The change a row in a record batch represents
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RowOp {
+    Insert,
+    Update,
+    Delete,
+}
+
+impl RowOp {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            RowOp::Insert => "insert",
+            RowOp::Update => "update",
+            RowOp::Delete => "delete",
+        }
+    }
+}
+
+/// Maps a Postgres type to the Arrow type its [`Cell`]s are converted to.
+/// Numerics, money, uuids, json, bit strings and unknown types are represented as
+/// strings, PostGIS geometries as WKB.
+pub fn postgres_to_arrow_type(typ: &Type) -> DataType {
+    if is_geometry(typ) {
+        return DataType::Binary;
+    }
+    match *typ {
+        Type::BOOL => DataType::Boolean,
+        Type::INT2 => DataType::Int16,
+        Type::INT4 => DataType::Int32,
+        Type::INT8 => DataType::Int64,
+        Type::OID => DataType::UInt32,
+        Type::FLOAT4 => DataType::Float32,
+        Type::FLOAT8 => DataType::Float64,
+        Type::BYTEA => DataType::Binary,
+        Type::DATE => DataType::Date32,
+        Type::TIME => DataType::Time64(TimeUnit::Microsecond),
+        Type::TIMESTAMP => DataType::Timestamp(TimeUnit::Microsecond, None),
+        Type::TIMESTAMPTZ => DataType::Timestamp(TimeUnit::Microsecond, Some("UTC".into())),
+        Type::INTERVAL => DataType::Interval(IntervalUnit::MonthDayNano),
+        Type::BOOL_ARRAY => list_of(DataType::Boolean),
+        Type::INT2_ARRAY => list_of(DataType::Int16),
+        Type::INT4_ARRAY => list_of(DataType::Int32),
+        Type::INT8_ARRAY => list_of(DataType::Int64),
+        Type::OID_ARRAY => list_of(DataType::UInt32),
+        Type::FLOAT4_ARRAY => list_of(DataType::Float32),
+        Type::FLOAT8_ARRAY => list_of(DataType::Float64),
+        Type::BYTEA_ARRAY => list_of(DataType::Binary),
+        Type::DATE_ARRAY => list_of(DataType::Date32),
+        Type::TIME_ARRAY => list_of(DataType::Time64(TimeUnit::Microsecond)),
+        Type::TIMESTAMP_ARRAY => list_of(DataType::Timestamp(TimeUnit::Microsecond, None)),
+        Type::TIMESTAMPTZ_ARRAY => list_of(DataType::Timestamp(
+            TimeUnit::Microsecond,
+            Some("UTC".into()),
+        )),
+        Type::INTERVAL_ARRAY => list_of(DataType::Interval(IntervalUnit::MonthDayNano)),
+        Type::CHAR_ARRAY
+        | Type::BPCHAR_ARRAY
+        | Type::VARCHAR_ARRAY
+        | Type::NAME_ARRAY
+        | Type::TEXT_ARRAY
+        | Type::NUMERIC_ARRAY
+        | Type::MONEY_ARRAY
+        | Type::UUID_ARRAY
+        | Type::JSON_ARRAY
+        | Type::JSONB_ARRAY => list_of(DataType::Utf8),
+        _ => DataType::Utf8,
+    }
+}
+
+fn list_of(data_type: DataType) -> DataType {
+    DataType::List(Arc::new(Field::new("item", data_type, true)))
+}
+
+/// Builds the Arrow schema of a table's record batches: one field per column
+/// followed by the [`OP_COLUMN_NAME`] field. All column fields are nullable
+/// because deleted rows only carry the values of the replica identity columns.
+pub fn to_arrow_schema(column_schemas: &[ColumnSchema]) -> Schema {
+    let mut fields: Vec<Field> = column_schemas
+        .iter()
+        .map(|c| Field::new(&c.name, postgres_to_arrow_type(&c.typ), true))
+        .collect();
+    fields.push(Field::new(OP_COLUMN_NAME, DataType::Utf8, false));
+    Schema::new(fields)
+}
+
+/// Accumulates a table's rows column by column until they are turned into a [`RecordBatch`]
+pub struct RecordBatchBuilder {
+    schema: SchemaRef,
+    column_schemas: Vec<ColumnSchema>,
+    columns: Vec<Vec<Cell>>,
+    ops: Vec<RowOp>,
+}
+
+impl RecordBatchBuilder {
+    pub fn new(table_schema: &TableSchema) -> RecordBatchBuilder {
+        let column_schemas = table_schema.column_schemas.clone();
+        RecordBatchBuilder {
+            schema: Arc::new(to_arrow_schema(&column_schemas)),
+            columns: vec![vec![]; column_schemas.len()],
+            column_schemas,
+            ops: vec![],
+        }
+    }
+
+    pub fn schema(&self) -> SchemaRef {
+        self.schema.clone()
+    }
+
+    pub fn len(&self) -> usize {
+        self.ops.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ops.is_empty()
+    }
+
+    pub fn push(&mut self, op: RowOp, row: TableRow) -> Result<(), ArrowConversionError> {
+        if row.values.len() != self.columns.len() {
+            return Err(ArrowConversionError::ColumnCountMismatch(
+                self.columns.len(),
+                row.values.len(),
+            ));
+        }
+        for (column, value) in self.columns.iter_mut().zip(row.values) {
+            column.push(value);
+        }
+        self.ops.push(op);
+        Ok(())
+    }
+
+    /// Converts the accumulated rows into a record batch and resets the builder
+    pub fn finish(&mut self) -> Result<RecordBatch, ArrowConversionError> {
+        // The rows are taken first so that the builder is empty even if the
+        // conversion fails
+        let columns = std::mem::replace(&mut self.columns, vec![vec![]; self.column_schemas.len()]);
+        let ops = std::mem::take(&mut self.ops);
+
+        let mut arrays = Vec::with_capacity(columns.len() + 1);
+        for (column_schema, cells) in self.column_schemas.iter().zip(&columns) {
+            arrays.push(cells_to_array(column_schema, cells)?);
+        }
+
+        let mut op_builder = StringBuilder::with_capacity(ops.len(), ops.len() * 6);
+        for op in ops {
+            op_builder.append_value(op.as_str());
+        }
+        arrays.push(Arc::new(op_builder.finish()));
+
+        Ok(RecordBatch::try_new(self.schema.clone(), arrays)?)
+    }
+}
+
+fn month_day_nano(interval: &PgInterval) -> IntervalMonthDayNano {
+    IntervalMonthDayNanoType::make_value(
+        interval.months,
+        interval.days,
+        interval.microseconds * 1_000,
+    )
+}
+
+macro_rules! build_array {
+    ($column_schema:expr, $cells:expr, $builder:expr, $variant:path, |$v:ident| $value:expr) => {{
+        let mut builder = $builder;
+        for cell in $cells.iter() {
+            match cell {
+                Cell::Null => builder.append_null(),
+                $variant($v) => builder.append_value($value),
+                cell => {
+                    return Err(ArrowConversionError::UnexpectedCell(
+                        $column_schema.name.clone(),
+                        format!("{cell:?}"),
+                    ))
+                }
+            }
+        }
+        Arc::new(builder.finish()) as ArrayRef
+    }};
+}
+
+macro_rules! build_list_array {
+    ($column_schema:expr, $cells:expr, $builder:expr, $variant:path, |$v:ident| $value:expr) => {{
+        let mut builder = ListBuilder::new($builder);
+        for cell in $cells.iter() {
+            match cell {
+                Cell::Null | Cell::Array(ArrayCell::Null) => builder.append_null(),
+                Cell::Array($variant(values)) => {
+                    for value in values {
+                        match value {
+                            Some($v) => builder.values().append_value($value),
+                            None => builder.values().append_null(),
+                        }
+                    }
+                    builder.append(true);
+                }
+                cell => {
+                    return Err(ArrowConversionError::UnexpectedCell(
+                        $column_schema.name.clone(),
+                        format!("{cell:?}"),
+                    ))
+                }
+            }
+        }
+        Arc::new(builder.finish()) as ArrayRef
+    }};
+}
+
+fn cells_to_array(
+    column_schema: &ColumnSchema,
+    cells: &[Cell],
+) -> Result<ArrayRef, ArrowConversionError> {
+    let c = column_schema;
+    let array = match c.typ {
+        Type::BOOL => build_array!(c, cells, BooleanBuilder::new(), Cell::Bool, |v| *v),
+        Type::INT2 => build_array!(c, cells, Int16Builder::new(), Cell::I16, |v| *v),
+        Type::INT4 => build_array!(c, cells, Int32Builder::new(), Cell::I32, |v| *v),
+        Type::INT8 => build_array!(c, cells, Int64Builder::new(), Cell::I64, |v| *v),
+        Type::OID => build_array!(c, cells, UInt32Builder::new(), Cell::U32, |v| *v),
+        Type::FLOAT4 => build_array!(c, cells, Float32Builder::new(), Cell::F32, |v| *v),
+        Type::FLOAT8 => build_array!(c, cells, Float64Builder::new(), Cell::F64, |v| *v),
+        Type::BYTEA => build_array!(c, cells, BinaryBuilder::new(), Cell::Bytes, |v| v),
+        Type::DATE => build_array!(c, cells, Date32Builder::new(), Cell::Date, |v| {
+            days_since_epoch(*v)
+        }),
+        Type::TIME => build_array!(c, cells, Time64MicrosecondBuilder::new(), Cell::Time, |v| {
+            micros_since_midnight(*v)
+        }),
+        Type::TIMESTAMP => build_array!(
+            c,
+            cells,
+            TimestampMicrosecondBuilder::new(),
+            Cell::TimeStamp,
+            |v| v.and_utc().timestamp_micros()
+        ),
+        Type::TIMESTAMPTZ => build_array!(
+            c,
+            cells,
+            TimestampMicrosecondBuilder::new().with_timezone("UTC"),
+            Cell::TimeStampTz,
+            |v| v.timestamp_micros()
+        ),
+        Type::INTERVAL => build_array!(
+            c,
+            cells,
+            IntervalMonthDayNanoBuilder::new(),
+            Cell::Interval,
+            |v| month_day_nano(v)
+        ),
+        Type::NUMERIC | Type::MONEY => {
+            build_array!(c, cells, StringBuilder::new(), Cell::Numeric, |v| {
+                v.to_string()
+            })
+        }
+        Type::UUID => build_array!(c, cells, StringBuilder::new(), Cell::Uuid, |v| {
+            v.to_string()
+        }),
+        Type::BIT | Type::VARBIT => {
+            build_array!(c, cells, StringBuilder::new(), Cell::Bits, |v| v
+                .to_string())
+        }
+        Type::BOOL_ARRAY => {
+            build_list_array!(c, cells, BooleanBuilder::new(), ArrayCell::Bool, |v| *v)
+        }
+        Type::INT2_ARRAY => {
+            build_list_array!(c, cells, Int16Builder::new(), ArrayCell::I16, |v| *v)
+        }
+        Type::INT4_ARRAY => {
+            build_list_array!(c, cells, Int32Builder::new(), ArrayCell::I32, |v| *v)
+        }
+        Type::INT8_ARRAY => {
+            build_list_array!(c, cells, Int64Builder::new(), ArrayCell::I64, |v| *v)
+        }
+        Type::OID_ARRAY => {
+            build_list_array!(c, cells, UInt32Builder::new(), ArrayCell::U32, |v| *v)
+        }
+        Type::FLOAT4_ARRAY => {
+            build_list_array!(c, cells, Float32Builder::new(), ArrayCell::F32, |v| *v)
+        }
+        Type::FLOAT8_ARRAY => {
+            build_list_array!(c, cells, Float64Builder::new(), ArrayCell::F64, |v| *v)
+        }
+        Type::BYTEA_ARRAY => {
+            build_list_array!(c, cells, BinaryBuilder::new(), ArrayCell::Bytes, |v| v)
+        }
+        Type::DATE_ARRAY => {
+            build_list_array!(c, cells, Date32Builder::new(), ArrayCell::Date, |v| {
+                days_since_epoch(*v)
+            })
+        }
+        Type::TIME_ARRAY => build_list_array!(
+            c,
+            cells,
+            Time64MicrosecondBuilder::new(),
+            ArrayCell::Time,
+            |v| micros_since_midnight(*v)
+        ),
+        Type::TIMESTAMP_ARRAY => build_list_array!(
+            c,
+            cells,
+            TimestampMicrosecondBuilder::new(),
+            ArrayCell::TimeStamp,
+            |v| v.and_utc().timestamp_micros()
+        ),
+        Type::TIMESTAMPTZ_ARRAY => build_list_array!(
+            c,
+            cells,
+            TimestampMicrosecondBuilder::new().with_timezone("UTC"),
+            ArrayCell::TimeStampTz,
+            |v| v.timestamp_micros()
+        ),
+        Type::INTERVAL_ARRAY => build_list_array!(
+            c,
+            cells,
+            IntervalMonthDayNanoBuilder::new(),
+            ArrayCell::Interval,
+            |v| month_day_nano(v)
+        ),
+        Type::NUMERIC_ARRAY | Type::MONEY_ARRAY => {
+            build_list_array!(c, cells, StringBuilder::new(), ArrayCell::Numeric, |v| v
+                .to_string())
+        }
+        Type::UUID_ARRAY => {
+            build_list_array!(c, cells, StringBuilder::new(), ArrayCell::Uuid, |v| v
+                .to_string())
+        }
+        Type::CHAR_ARRAY
+        | Type::BPCHAR_ARRAY
+        | Type::VARCHAR_ARRAY
+        | Type::NAME_ARRAY
+        | Type::TEXT_ARRAY
+        | Type::JSON_ARRAY
+        | Type::JSONB_ARRAY => {
+            build_list_array!(c, cells, StringBuilder::new(), ArrayCell::String, |v| v)
+        }
+        _ if is_geometry(&c.typ) => {
+            build_array!(c, cells, BinaryBuilder::new(), Cell::Geometry, |v| v
+                .to_wkb())
+        }
+        _ if matches!(c.typ.kind(), Kind::Range(_)) => {
+            build_array!(c, cells, StringBuilder::new(), Cell::Range, |v| {
+                range_to_text(v)
+            })
+        }
+        _ if matches!(c.typ.kind(), Kind::Multirange(_)) => {
+            build_array!(c, cells, StringBuilder::new(), Cell::Multirange, |v| {
+                multirange_to_text(v)
+            })
+        }
+        _ => build_array!(c, cells, StringBuilder::new(), Cell::String, |v| v),
+    };
+    Ok(array)
+}
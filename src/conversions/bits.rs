@@ -0,0 +1,100 @@
+use std::fmt::{self, Display};
+
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum BitsParseError {
+    #[error("invalid bit {0}, only 0 and 1 are allowed")]
+    InvalidBit(char),
+}
+
+/// The length declared by the type modifier of a `bit(n)` or `bit varying(n)`
+/// column, `None` for a `bit varying` without a length. Values of `bit(n)` always
+/// have `n` bits, values of `bit varying(n)` at most `n`.
+pub fn declared_length(modifier: i32) -> Option<usize> {
+    (modifier >= 0).then_some(modifier as usize)
+}
+
+/// A value of a `bit` or `bit varying` column. The bits are packed into bytes with
+/// the first bit as the most significant bit of the first byte, like Postgres stores
+/// them, and the unused bits of the last byte are zero.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PgBits {
+    bytes: Vec<u8>,
+    len: usize,
+}
+
+impl PgBits {
+    /// Parses a bit string in Postgres' text format, e.g. `10110`
+    pub fn from_text(str: &str) -> Result<PgBits, BitsParseError> {
+        let mut bits = PgBits {
+            bytes: Vec::with_capacity(str.len().div_ceil(8)),
+            len: 0,
+        };
+        for c in str.chars() {
+            let bit = match c {
+                '0' => false,
+                '1' => true,
+                c => return Err(BitsParseError::InvalidBit(c)),
+            };
+            bits.push(bit);
+        }
+        Ok(bits)
+    }
+
+    fn push(&mut self, bit: bool) {
+        if self.len % 8 == 0 {
+            self.bytes.push(0);
+        }
+        if bit {
+            self.bytes[self.len / 8] |= 0x80 >> (self.len % 8);
+        }
+        self.len += 1;
+    }
+
+    /// The number of bits
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// The bit at `index`, `None` if it's out of bounds
+    pub fn get(&self, index: usize) -> Option<bool> {
+        if index >= self.len {
+            return None;
+        }
+        Some(self.bytes[index / 8] & (0x80 >> (index % 8)) != 0)
+    }
+
+    /// The packed bits, padded with zeros to whole bytes
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+
+    /// The bits as an unsigned integer with the last bit as the least significant
+    /// one, e.g. for sinks storing bit strings as integers. `None` if there are more
+    /// than 64 bits.
+    pub fn to_u64(&self) -> Option<u64> {
+        if self.len > 64 {
+            return None;
+        }
+        let value = (0..self.len).fold(0, |value, i| {
+            (value << 1) | self.get(i).expect("index in bounds") as u64
+        });
+        Some(value)
+    }
+}
+
+/// Formats the bits in Postgres' text format
+impl Display for PgBits {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for i in 0..self.len {
+            let bit = self.get(i).expect("index in bounds");
+            f.write_str(if bit { "1" } else { "0" })?;
+        }
+        Ok(())
+    }
+}
@@ -1,19 +1,26 @@
 use core::str;
-use std::{collections::HashMap, str::Utf8Error};
+use std::{
+    collections::HashMap,
+    str::Utf8Error,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
 
+use bytes::{BufMut, Bytes, BytesMut};
 use postgres_replication::protocol::{
-    BeginBody, CommitBody, DeleteBody, InsertBody, LogicalReplicationMessage, RelationBody,
-    ReplicationMessage, StreamAbortBody, StreamCommitBody, StreamStartBody, StreamStopBody,
-    TupleData, TypeBody, UpdateBody,
+    BeginBody, CommitBody, LogicalReplicationMessage, PrimaryKeepAliveBody, RelationBody,
+    ReplicaIdentity, ReplicationMessage, StreamAbortBody, StreamCommitBody, StreamStartBody,
+    StreamStopBody, Tuple, TupleData, TypeBody,
 };
 use thiserror::Error;
+use tokio_postgres::types::PgLsn;
 
 use crate::{
     pipeline::batching::BatchBoundary,
-    table::{ColumnSchema, TableId, TableSchema},
+    table::{ColumnSchema, TableId, TableName, TableSchema},
 };
 
 use super::{
+    size,
     table_row::TableRow,
     text::{FromTextError, TextFormatConverter},
     Cell,
@@ -36,6 +43,9 @@ pub enum CdcEventConversionError {
     #[error("missing tuple in delete body")]
     MissingTupleInDeleteBody,
 
+    #[error("missing tuple data for column {0}")]
+    MissingTupleData(String),
+
     #[error("schema missing for table id {0}")]
     MissingSchema(TableId),
 
@@ -44,171 +54,946 @@ pub enum CdcEventConversionError {
 
     #[error("invalid string value")]
     InvalidStr(#[from] Utf8Error),
+
+    #[error("failed to decode row of table {} at lsn {}: {}", .0.table_id, .0.lsn, .0.error)]
+    UndecodableRow(Box<UndecodableRow>),
+
+    #[error("invalid wal2json change: {0}")]
+    InvalidWal2Json(String),
+
+    #[error("change to unknown table {0}")]
+    UnknownTable(TableName),
+
+    #[error("invalid logical replication message: {0}")]
+    InvalidMessage(#[from] std::io::Error),
+
+    #[error("null value in column {0} declared NOT NULL")]
+    NullInNotNullColumn(String),
+}
+
+/// A row change whose values couldn't be decoded, e.g. because the table's
+/// schema changed in an incompatible way
+#[derive(Debug)]
+pub struct UndecodableRow {
+    pub lsn: PgLsn,
+    pub table_id: TableId,
+    /// The row's values as sent by Postgres, `None` for nulls and unchanged TOAST values
+    pub values: Vec<Option<Bytes>>,
+    pub error: CdcEventConversionError,
+}
+
+impl UndecodableRow {
+    /// Wraps an error from decoding the values in `tuple_data`
+    fn error(
+        lsn: u64,
+        table_id: TableId,
+        tuple_data: &[TupleData],
+        error: CdcEventConversionError,
+    ) -> CdcEventConversionError {
+        let values = tuple_data
+            .iter()
+            .map(|data| match data {
+                TupleData::Text(bytes) => Some(bytes.clone()),
+                TupleData::Null | TupleData::UnchangedToast => None,
+            })
+            .collect();
+        CdcEventConversionError::UndecodableRow(Box::new(UndecodableRow {
+            lsn: lsn.into(),
+            table_id,
+            values,
+            error,
+        }))
+    }
+}
+
+/// A copy of the tuples of a row change, to decode the change again after refreshing
+/// a stale table schema
+pub(crate) struct RowChange {
+    pub(crate) table_id: TableId,
+    xid: Option<u32>,
+    tuples: RowChangeTuples,
+}
+
+enum RowChangeTuples {
+    Insert(Vec<TupleData>),
+    Update {
+        key: Option<Vec<TupleData>>,
+        old: Option<Vec<TupleData>>,
+        new: Vec<TupleData>,
+    },
+    Delete {
+        key: Option<Vec<TupleData>>,
+        old: Option<Vec<TupleData>>,
+    },
+}
+
+impl RowChange {
+    /// Returns `None` for messages which aren't row changes
+    pub(crate) fn from_message(
+        message: &ReplicationMessage<LogicalReplicationMessage>,
+    ) -> Option<RowChange> {
+        let ReplicationMessage::XLogData(xlog_data) = message else {
+            return None;
+        };
+        let copy = |tuple_data: &[TupleData]| -> Vec<TupleData> {
+            tuple_data
+                .iter()
+                .map(|data| match data {
+                    TupleData::Null => TupleData::Null,
+                    TupleData::UnchangedToast => TupleData::UnchangedToast,
+                    TupleData::Text(bytes) => TupleData::Text(bytes.clone()),
+                })
+                .collect()
+        };
+        let (table_id, xid, tuples) = match xlog_data.data() {
+            LogicalReplicationMessage::Insert(body) => (
+                body.rel_id(),
+                body.xid(),
+                RowChangeTuples::Insert(copy(body.tuple().tuple_data())),
+            ),
+            LogicalReplicationMessage::Update(body) => (
+                body.rel_id(),
+                body.xid(),
+                RowChangeTuples::Update {
+                    key: body.key_tuple().map(|tuple| copy(tuple.tuple_data())),
+                    old: body.old_tuple().map(|tuple| copy(tuple.tuple_data())),
+                    new: copy(body.new_tuple().tuple_data()),
+                },
+            ),
+            LogicalReplicationMessage::Delete(body) => (
+                body.rel_id(),
+                body.xid(),
+                RowChangeTuples::Delete {
+                    key: body.key_tuple().map(|tuple| copy(tuple.tuple_data())),
+                    old: body.old_tuple().map(|tuple| copy(tuple.tuple_data())),
+                },
+            ),
+            _ => return None,
+        };
+        Some(RowChange {
+            table_id,
+            xid,
+            tuples,
+        })
+    }
+
+    /// Decodes the change like [`CdcEventConverter::try_from_with_identities`] does
+    pub(crate) fn try_into_event(
+        self,
+        table_schema: &TableSchema,
+        identity: Option<&RelationIdentity>,
+        enforce_not_null: bool,
+    ) -> Result<CdcEvent, CdcEventConversionError> {
+        let table_id = self.table_id;
+        let (event, key_only) = match &self.tuples {
+            RowChangeTuples::Insert(tuple) => {
+                let event = CdcEventConverter::try_from_insert_tuple(
+                    table_id,
+                    table_schema,
+                    tuple,
+                    self.xid,
+                );
+                (event, false)
+            }
+            RowChangeTuples::Update { key, old, new } => {
+                let event = CdcEventConverter::try_from_update_tuples(
+                    table_id,
+                    table_schema,
+                    identity,
+                    key.as_deref(),
+                    old.as_deref(),
+                    new,
+                    self.xid,
+                );
+                (
+                    event,
+                    CdcEventConverter::is_key_only(identity, key.is_some()),
+                )
+            }
+            RowChangeTuples::Delete { key, old } => {
+                let event = CdcEventConverter::try_from_delete_tuples(
+                    table_id,
+                    table_schema,
+                    identity,
+                    key.as_deref(),
+                    old.as_deref(),
+                    self.xid,
+                );
+                (
+                    event,
+                    CdcEventConverter::is_key_only(identity, key.is_some()),
+                )
+            }
+        };
+        let event = event?;
+        if enforce_not_null {
+            CdcEventConverter::check_not_null(table_schema, &event, identity, key_only)?;
+        }
+        Ok(event)
+    }
+}
+
+/// What the old tuples of a table's updates and deletes hold, by the table's
+/// replica identity
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RelationIdentity {
+    /// `REPLICA IDENTITY FULL`, old tuples hold the whole row
+    Full,
+    /// `REPLICA IDENTITY DEFAULT` or `USING INDEX`, old tuples hold the values of
+    /// these columns and nulls for the others
+    Key(Vec<String>),
+    /// `REPLICA IDENTITY NOTHING`, or a default identity of a table without a
+    /// primary key
+    Nothing,
+}
+
+impl RelationIdentity {
+    pub fn from_relation(body: &RelationBody) -> Result<RelationIdentity, CdcEventConversionError> {
+        if matches!(body.replica_identity(), ReplicaIdentity::Full) {
+            return Ok(RelationIdentity::Full);
+        }
+        let mut key_columns = vec![];
+        for column in body.columns() {
+            // Flag 1 marks the columns of the replica identity's key
+            if column.flags() & 1 == 1 {
+                key_columns.push(column.name()?.to_string());
+            }
+        }
+        if key_columns.is_empty() {
+            return Ok(RelationIdentity::Nothing);
+        }
+        Ok(RelationIdentity::Key(key_columns))
+    }
+}
+
+/// The replica identity of each table as announced by the latest Relation message
+/// received for it. Postgres sends a Relation message before the first change to a
+/// table and again after the table was altered, e.g. with `ALTER TABLE ... REPLICA
+/// IDENTITY`, so following them decodes old tuples by the identity they were sent
+/// with rather than by the [`LookupKey`](crate::table::LookupKey) found when the
+/// table schemas were loaded.
+#[derive(Debug, Clone, Default)]
+pub struct ReplicaIdentities {
+    identities: HashMap<TableId, RelationIdentity>,
+}
+
+impl ReplicaIdentities {
+    pub fn update(&mut self, body: &RelationBody) -> Result<(), CdcEventConversionError> {
+        let identity = RelationIdentity::from_relation(body)?;
+        self.identities.insert(body.rel_id(), identity);
+        Ok(())
+    }
+
+    pub fn get(&self, table_id: TableId) -> Option<&RelationIdentity> {
+        self.identities.get(&table_id)
+    }
 }
 
 pub struct CdcEventConverter;
 
 impl CdcEventConverter {
+    /// Returns the tuple data of the columns in the table schema, leaving out
+    /// excluded columns
+    pub(crate) fn included_tuple_data<'a>(
+        table_schema: &TableSchema,
+        tuple_data: &'a [TupleData],
+    ) -> Vec<&'a TupleData> {
+        tuple_data
+            .iter()
+            .enumerate()
+            .filter(|(i, _)| !table_schema.excluded_columns.contains(i))
+            .map(|(_, data)| data)
+            .collect()
+    }
+
     fn try_from_tuple_data_slice(
-        column_schemas: &[ColumnSchema],
+        table_schema: &TableSchema,
         tuple_data: &[TupleData],
     ) -> Result<TableRow, CdcEventConversionError> {
-        let mut values = Vec::with_capacity(column_schemas.len());
+        let column_schemas = &table_schema.column_schemas;
+        let mut row = TableRow::new(Vec::with_capacity(column_schemas.len()));
 
+        // Generated columns aren't sent, so the tuple only has the other columns
+        let mut tuple_data = Self::included_tuple_data(table_schema, tuple_data).into_iter();
         for (i, column_schema) in column_schemas.iter().enumerate() {
-            let cell = match &tuple_data[i] {
-                TupleData::Null => Cell::Null,
-                TupleData::UnchangedToast => TextFormatConverter::default_value(&column_schema.typ),
-                TupleData::Text(bytes) => {
-                    let str = str::from_utf8(&bytes[..])?;
-                    TextFormatConverter::try_from_str(&column_schema.typ, str)?
-                }
-            };
-            values.push(cell);
+            if column_schema.generated {
+                row.values.push(Cell::Null);
+                continue;
+            }
+            let tuple_data = tuple_data
+                .next()
+                .ok_or(CdcEventConversionError::MissingTupleData(
+                    column_schema.name.clone(),
+                ))?;
+            if matches!(tuple_data, TupleData::UnchangedToast) {
+                row.unchanged_toast.push(i);
+            }
+            row.values
+                .push(Self::try_from_tuple_data(column_schema, tuple_data)?);
+        }
+
+        Ok(row)
+    }
+
+    fn try_from_tuple_data(
+        column_schema: &ColumnSchema,
+        tuple_data: &TupleData,
+    ) -> Result<Cell, CdcEventConversionError> {
+        let cell = match tuple_data {
+            TupleData::Null => Cell::Null,
+            TupleData::UnchangedToast => TextFormatConverter::default_value(&column_schema.typ),
+            TupleData::Text(bytes) => {
+                let str = str::from_utf8(&bytes[..])?;
+                TextFormatConverter::try_from_str(&column_schema.typ, str)?
+            }
+        };
+        Ok(cell)
+    }
+
+    /// Decodes the new tuple of an update. Columns holding the unchanged TOAST marker
+    /// get their values from `full_old_tuple`, the old tuple of a table with a full
+    /// replica identity, and else default values and are listed in the row's
+    /// [`TableRow::unchanged_toast`].
+    fn try_from_new_tuple_data(
+        table_schema: &TableSchema,
+        tuple_data: &[TupleData],
+        full_old_tuple: Option<&[TupleData]>,
+    ) -> Result<TableRow, CdcEventConversionError> {
+        let Some(old_tuple_data) = full_old_tuple.filter(|old| {
+            old.len() == tuple_data.len()
+                && tuple_data
+                    .iter()
+                    .any(|data| matches!(data, TupleData::UnchangedToast))
+        }) else {
+            return Self::try_from_tuple_data_slice(table_schema, tuple_data);
+        };
+        let tuple_data: Vec<TupleData> = tuple_data
+            .iter()
+            .zip(old_tuple_data)
+            .map(|(data, old_data)| match (data, old_data) {
+                (TupleData::UnchangedToast, TupleData::Text(bytes))
+                | (TupleData::Text(bytes), _) => TupleData::Text(bytes.clone()),
+                (TupleData::UnchangedToast, _) => TupleData::UnchangedToast,
+                (TupleData::Null, _) => TupleData::Null,
+            })
+            .collect();
+        Self::try_from_tuple_data_slice(table_schema, &tuple_data)
+    }
+
+    /// Whether an old tuple holds just the replica identity's key columns, by the
+    /// table's latest identity or, if no Relation message was received for the table
+    /// yet, by whether Postgres tagged it as a key tuple
+    fn is_key_only(identity: Option<&RelationIdentity>, is_key_tuple: bool) -> bool {
+        match identity {
+            Some(RelationIdentity::Full) => false,
+            Some(RelationIdentity::Key(_) | RelationIdentity::Nothing) => true,
+            None => is_key_tuple,
+        }
+    }
+
+    /// Positions of the key columns of key tuples, those of the replica identity if
+    /// known and else those of the [`LookupKey`](crate::table::LookupKey)
+    fn key_indexes(
+        table_schema: &TableSchema,
+        identity: Option<&RelationIdentity>,
+    ) -> Option<Vec<usize>> {
+        let column_schemas = &table_schema.column_schemas;
+        match identity {
+            Some(RelationIdentity::Key(key_columns)) => key_columns
+                .iter()
+                .map(|key_column| column_schemas.iter().position(|c| &c.name == key_column))
+                .collect(),
+            _ => table_schema.lookup_key.column_indexes(column_schemas),
+        }
+    }
+
+    fn try_from_old_tuple_data(
+        table_schema: &TableSchema,
+        identity: Option<&RelationIdentity>,
+        key_only: bool,
+        tuple_data: &[TupleData],
+    ) -> Result<TableRow, CdcEventConversionError> {
+        if key_only {
+            Self::try_from_key_tuple_data(table_schema, identity, tuple_data)
+        } else {
+            Self::try_from_tuple_data_slice(table_schema, tuple_data)
         }
+    }
+
+    /// Decodes the key tuple of an update or delete. Postgres sends it with all of
+    /// the table's columns, the ones outside the replica identity being null, but a
+    /// tuple with exactly the key columns in key order is accepted as well. The
+    /// returned row has nulls for the other columns then.
+    fn try_from_key_tuple_data(
+        table_schema: &TableSchema,
+        identity: Option<&RelationIdentity>,
+        tuple_data: &[TupleData],
+    ) -> Result<TableRow, CdcEventConversionError> {
+        let column_schemas = &table_schema.column_schemas;
+        let full_width = column_schemas.iter().filter(|c| !c.generated).count()
+            + table_schema.excluded_columns.len();
+        let key_indexes = match Self::key_indexes(table_schema, identity) {
+            Some(key_indexes)
+                if tuple_data.len() == key_indexes.len() && tuple_data.len() != full_width =>
+            {
+                key_indexes
+            }
+            _ => return Self::try_from_tuple_data_slice(table_schema, tuple_data),
+        };
 
-        Ok(TableRow { values })
+        let mut values = vec![Cell::Null; column_schemas.len()];
+        for (&i, tuple_data) in key_indexes.iter().zip(tuple_data) {
+            values[i] = Self::try_from_tuple_data(&column_schemas[i], tuple_data)?;
+        }
+        Ok(TableRow::new(values))
     }
 
-    fn try_from_insert_body(
+    fn try_from_insert_tuple(
         table_id: TableId,
-        column_schemas: &[ColumnSchema],
-        insert_body: InsertBody,
+        table_schema: &TableSchema,
+        tuple: &[TupleData],
+        xid: Option<u32>,
     ) -> Result<CdcEvent, CdcEventConversionError> {
-        let row =
-            Self::try_from_tuple_data_slice(column_schemas, insert_body.tuple().tuple_data())?;
+        let row = Self::try_from_tuple_data_slice(table_schema, tuple)?;
 
-        Ok(CdcEvent::Insert((table_id, row, insert_body.xid())))
+        Ok(CdcEvent::Insert((table_id, row, xid)))
     }
 
-    //TODO: handle when identity columns are changed
-    fn try_from_update_body(
+    fn try_from_update_tuples(
         table_id: TableId,
-        column_schemas: &[ColumnSchema],
-        update_body: UpdateBody,
+        table_schema: &TableSchema,
+        identity: Option<&RelationIdentity>,
+        key_tuple: Option<&[TupleData]>,
+        old_tuple: Option<&[TupleData]>,
+        new_tuple: &[TupleData],
+        xid: Option<u32>,
     ) -> Result<CdcEvent, CdcEventConversionError> {
-        let old_row = update_body
-            .old_tuple()
-            .map(|tuple| Self::try_from_tuple_data_slice(column_schemas, tuple.tuple_data()))
+        // Postgres only sends an old tuple if the key changed or the identity is full
+        let old_tuple = match (key_tuple, old_tuple) {
+            (Some(key_tuple), _) => Some((key_tuple, true)),
+            (None, Some(old_tuple)) => Some((old_tuple, false)),
+            (None, None) => None,
+        }
+        .map(|(tuple, is_key)| (tuple, Self::is_key_only(identity, is_key)));
+        let old_row = old_tuple
+            .map(|(tuple, key_only)| {
+                Self::try_from_old_tuple_data(table_schema, identity, key_only, tuple)
+            })
             .transpose()?;
-        let new_row =
-            Self::try_from_tuple_data_slice(column_schemas, update_body.new_tuple().tuple_data())?;
+        let full_old_tuple = old_tuple
+            .filter(|(_, key_only)| !key_only)
+            .map(|(tuple, _)| tuple);
+        let new_row = Self::try_from_new_tuple_data(table_schema, new_tuple, full_old_tuple)?;
 
-        Ok(CdcEvent::Update((
-            table_id,
-            old_row,
-            new_row,
-            update_body.xid(),
-        )))
+        Ok(CdcEvent::Update((table_id, old_row, new_row, xid)))
     }
 
-    fn try_from_delete_body(
+    fn try_from_delete_tuples(
         table_id: TableId,
-        column_schemas: &[ColumnSchema],
-        delete_body: DeleteBody,
+        table_schema: &TableSchema,
+        identity: Option<&RelationIdentity>,
+        key_tuple: Option<&[TupleData]>,
+        old_tuple: Option<&[TupleData]>,
+        xid: Option<u32>,
     ) -> Result<CdcEvent, CdcEventConversionError> {
-        let tuple = delete_body
-            .key_tuple()
-            .or(delete_body.old_tuple())
-            .ok_or(CdcEventConversionError::MissingTupleInDeleteBody)?;
+        let (tuple, is_key) = match (key_tuple, old_tuple) {
+            (Some(key_tuple), _) => (key_tuple, true),
+            (None, Some(old_tuple)) => (old_tuple, false),
+            (None, None) => return Err(CdcEventConversionError::MissingTupleInDeleteBody),
+        };
+        let key_only = Self::is_key_only(identity, is_key);
+        let row = Self::try_from_old_tuple_data(table_schema, identity, key_only, tuple)?;
+
+        Ok(CdcEvent::Delete((table_id, row, xid)))
+    }
+
+    /// Checks that the rows of a row change have no nulls in columns declared NOT NULL.
+    /// Of an old row decoded from a key tuple only the key columns are checked, as
+    /// Postgres sends the other columns as nulls.
+    fn check_not_null(
+        table_schema: &TableSchema,
+        event: &CdcEvent,
+        identity: Option<&RelationIdentity>,
+        key_only: bool,
+    ) -> Result<(), CdcEventConversionError> {
+        let (old_row, new_row) = match event {
+            CdcEvent::Insert((_, row, _)) => (None, Some(row)),
+            CdcEvent::Delete((_, row, _)) => (Some(row), None),
+            CdcEvent::Update((_, old_row, new_row, _)) => (old_row.as_ref(), Some(new_row)),
+            _ => return Ok(()),
+        };
+        if let Some(old_row) = old_row {
+            if !key_only {
+                Self::check_row_not_null(table_schema, old_row, None)?;
+            } else if let Some(key_indexes) = Self::key_indexes(table_schema, identity) {
+                Self::check_row_not_null(table_schema, old_row, Some(&key_indexes))?;
+            }
+        }
+        if let Some(new_row) = new_row {
+            Self::check_row_not_null(table_schema, new_row, None)?;
+        }
+        Ok(())
+    }
+
+    /// Checks the columns at `indexes`, or all columns if `None`
+    fn check_row_not_null(
+        table_schema: &TableSchema,
+        row: &TableRow,
+        indexes: Option<&[usize]>,
+    ) -> Result<(), CdcEventConversionError> {
+        let column_schemas = &table_schema.column_schemas;
+        for (i, (column_schema, value)) in column_schemas.iter().zip(&row.values).enumerate() {
+            let checked = indexes.map_or(true, |indexes| indexes.contains(&i));
+            if checked
+                && !column_schema.nullable
+                && !column_schema.generated
+                && matches!(value, Cell::Null)
+            {
+                return Err(CdcEventConversionError::NullInNotNullColumn(
+                    column_schema.name.clone(),
+                ));
+            }
+        }
+        Ok(())
+    }
 
-        let row = Self::try_from_tuple_data_slice(column_schemas, tuple.tuple_data())?;
+    /// Converts a primary keepalive into a [`CdcEvent::Heartbeat`]
+    pub fn heartbeat(keep_alive: &PrimaryKeepAliveBody) -> CdcEvent {
+        CdcEvent::Heartbeat {
+            lsn: keep_alive.wal_end().into(),
+            server_time: Self::system_time(keep_alive.timestamp()),
+        }
+    }
 
-        Ok(CdcEvent::Delete((table_id, row, delete_body.xid())))
+    /// Converts a timestamp of the replication protocol, in microseconds since
+    /// Postgres' epoch of 2000-01-01, into a system time
+    pub(crate) fn system_time(postgres_micros: i64) -> SystemTime {
+        const POSTGRES_EPOCH_SECS: u64 = 946_684_800;
+        let micros = postgres_micros.max(0) as u64;
+        UNIX_EPOCH + Duration::from_secs(POSTGRES_EPOCH_SECS) + Duration::from_micros(micros)
     }
 
     pub fn try_from(
         value: ReplicationMessage<LogicalReplicationMessage>,
         table_schemas: &HashMap<TableId, TableSchema>,
     ) -> Result<CdcEvent, CdcEventConversionError> {
+        Self::try_from_checked(value, table_schemas, false)
+    }
+
+    /// Like [`CdcEventConverter::try_from`], additionally failing row changes with
+    /// nulls in columns declared NOT NULL if `enforce_not_null` is set. Such rows are
+    /// returned as [`UndecodableRow`]s, so that a pipeline with a dead letter queue
+    /// routes them there instead of a sink failing on them.
+    pub fn try_from_checked(
+        value: ReplicationMessage<LogicalReplicationMessage>,
+        table_schemas: &HashMap<TableId, TableSchema>,
+        enforce_not_null: bool,
+    ) -> Result<CdcEvent, CdcEventConversionError> {
+        let mut identities = ReplicaIdentities::default();
+        Self::try_from_with_identities(value, table_schemas, &mut identities, enforce_not_null)
+    }
+
+    /// Like [`CdcEventConverter::try_from_checked`], decoding the old tuples of
+    /// updates and deletes by the replica identities in `identities`, which are
+    /// updated from the Relation messages converted. A stream converting all of a
+    /// slot's messages with the same `identities` follows changes of a table's
+    /// replica identity, e.g. from `FULL` back to `DEFAULT`.
+    pub fn try_from_with_identities(
+        value: ReplicationMessage<LogicalReplicationMessage>,
+        table_schemas: &HashMap<TableId, TableSchema>,
+        identities: &mut ReplicaIdentities,
+        enforce_not_null: bool,
+    ) -> Result<CdcEvent, CdcEventConversionError> {
+        let check = |table_schema: &TableSchema,
+                     identity: Option<&RelationIdentity>,
+                     event: CdcEvent,
+                     key_only: bool|
+         -> Result<CdcEvent, CdcEventConversionError> {
+            if enforce_not_null {
+                Self::check_not_null(table_schema, &event, identity, key_only)?;
+            }
+            Ok(event)
+        };
         match value {
-            ReplicationMessage::XLogData(xlog_data) => match xlog_data.into_data() {
-                LogicalReplicationMessage::Begin(begin_body) => Ok(CdcEvent::Begin(begin_body)),
-                LogicalReplicationMessage::Commit(commit_body) => Ok(CdcEvent::Commit(commit_body)),
-                LogicalReplicationMessage::Origin(_) => {
-                    Err(CdcEventConversionError::MessageNotSupported)
-                }
-                LogicalReplicationMessage::Relation(relation_body) => {
-                    Ok(CdcEvent::Relation(relation_body))
-                }
-                LogicalReplicationMessage::Type(type_body) => Ok(CdcEvent::Type(type_body)),
-                LogicalReplicationMessage::Insert(insert_body) => {
-                    let table_id = insert_body.rel_id();
-                    let column_schemas = &table_schemas
-                        .get(&table_id)
-                        .ok_or(CdcEventConversionError::MissingSchema(table_id))?
-                        .column_schemas;
-                    Ok(Self::try_from_insert_body(
-                        table_id,
-                        column_schemas,
-                        insert_body,
-                    )?)
-                }
-                LogicalReplicationMessage::Update(update_body) => {
-                    let table_id = update_body.rel_id();
-                    let column_schemas = &table_schemas
-                        .get(&table_id)
-                        .ok_or(CdcEventConversionError::MissingSchema(table_id))?
-                        .column_schemas;
-                    Ok(Self::try_from_update_body(
-                        table_id,
-                        column_schemas,
-                        update_body,
-                    )?)
-                }
-                LogicalReplicationMessage::Delete(delete_body) => {
-                    let table_id = delete_body.rel_id();
-                    let column_schemas = &table_schemas
-                        .get(&table_id)
-                        .ok_or(CdcEventConversionError::MissingSchema(table_id))?
-                        .column_schemas;
-                    Ok(Self::try_from_delete_body(
-                        table_id,
-                        column_schemas,
-                        delete_body,
-                    )?)
+            ReplicationMessage::XLogData(xlog_data) => {
+                let lsn = xlog_data.wal_start();
+                match xlog_data.into_data() {
+                    LogicalReplicationMessage::Begin(begin_body) => Ok(CdcEvent::Begin(begin_body)),
+                    LogicalReplicationMessage::Commit(commit_body) => {
+                        Ok(CdcEvent::Commit(commit_body))
+                    }
+                    LogicalReplicationMessage::Origin(_) => {
+                        Err(CdcEventConversionError::MessageNotSupported)
+                    }
+                    LogicalReplicationMessage::Relation(relation_body) => {
+                        identities.update(&relation_body)?;
+                        Ok(CdcEvent::Relation(relation_body))
+                    }
+                    LogicalReplicationMessage::Type(type_body) => Ok(CdcEvent::Type(type_body)),
+                    LogicalReplicationMessage::Insert(insert_body) => {
+                        let table_id = insert_body.rel_id();
+                        let table_schema = table_schemas
+                            .get(&table_id)
+                            .ok_or(CdcEventConversionError::MissingSchema(table_id))?;
+                        let tuple = insert_body.tuple().tuple_data();
+                        Self::try_from_insert_tuple(
+                            table_id,
+                            table_schema,
+                            tuple,
+                            insert_body.xid(),
+                        )
+                        .and_then(|event| check(table_schema, None, event, false))
+                        .map_err(|e| {
+                            UndecodableRow::error(
+                                lsn,
+                                table_id,
+                                insert_body.tuple().tuple_data(),
+                                e,
+                            )
+                        })
+                    }
+                    LogicalReplicationMessage::Update(update_body) => {
+                        let table_id = update_body.rel_id();
+                        let table_schema = table_schemas
+                            .get(&table_id)
+                            .ok_or(CdcEventConversionError::MissingSchema(table_id))?;
+                        let identity = identities.get(table_id);
+                        let key_only =
+                            Self::is_key_only(identity, update_body.key_tuple().is_some());
+                        Self::try_from_update_tuples(
+                            table_id,
+                            table_schema,
+                            identity,
+                            update_body.key_tuple().map(Tuple::tuple_data),
+                            update_body.old_tuple().map(Tuple::tuple_data),
+                            update_body.new_tuple().tuple_data(),
+                            update_body.xid(),
+                        )
+                        .and_then(|event| check(table_schema, identity, event, key_only))
+                        .map_err(|e| {
+                            UndecodableRow::error(
+                                lsn,
+                                table_id,
+                                update_body.new_tuple().tuple_data(),
+                                e,
+                            )
+                        })
+                    }
+                    LogicalReplicationMessage::Delete(delete_body) => {
+                        let table_id = delete_body.rel_id();
+                        let table_schema = table_schemas
+                            .get(&table_id)
+                            .ok_or(CdcEventConversionError::MissingSchema(table_id))?;
+                        let identity = identities.get(table_id);
+                        let key_only =
+                            Self::is_key_only(identity, delete_body.key_tuple().is_some());
+                        Self::try_from_delete_tuples(
+                            table_id,
+                            table_schema,
+                            identity,
+                            delete_body.key_tuple().map(Tuple::tuple_data),
+                            delete_body.old_tuple().map(Tuple::tuple_data),
+                            delete_body.xid(),
+                        )
+                        .and_then(|event| check(table_schema, identity, event, key_only))
+                        .map_err(|e| {
+                            match delete_body.key_tuple().or(delete_body.old_tuple()) {
+                                Some(tuple) => {
+                                    UndecodableRow::error(lsn, table_id, tuple.tuple_data(), e)
+                                }
+                                None => e,
+                            }
+                        })
+                    }
+                    LogicalReplicationMessage::Truncate(_) => {
+                        Err(CdcEventConversionError::MessageNotSupported)
+                    }
+                    LogicalReplicationMessage::StreamStart(stream_start_body) => {
+                        Ok(CdcEvent::StreamStart(stream_start_body))
+                    }
+                    LogicalReplicationMessage::StreamStop(stream_stop_body) => {
+                        Ok(CdcEvent::StreamStop(stream_stop_body))
+                    }
+                    LogicalReplicationMessage::StreamCommit(stream_commit_body) => {
+                        Ok(CdcEvent::StreamCommit(stream_commit_body))
+                    }
+                    LogicalReplicationMessage::StreamAbort(stream_abort_body) => {
+                        Ok(CdcEvent::StreamAbort(stream_abort_body))
+                    }
+                    _ => Err(CdcEventConversionError::UnknownReplicationMessage),
                 }
-                LogicalReplicationMessage::Truncate(_) => {
-                    Err(CdcEventConversionError::MessageNotSupported)
-                }
-                LogicalReplicationMessage::StreamStart(stream_start_body) => {
-                    Ok(CdcEvent::StreamStart(stream_start_body))
-                }
-                LogicalReplicationMessage::StreamStop(stream_stop_body) => {
-                    Ok(CdcEvent::StreamStop(stream_stop_body))
-                }
-                LogicalReplicationMessage::StreamCommit(stream_commit_body) => {
-                    Ok(CdcEvent::StreamCommit(stream_commit_body))
-                }
-                LogicalReplicationMessage::StreamAbort(stream_abort_body) => {
-                    Ok(CdcEvent::StreamAbort(stream_abort_body))
-                }
-                _ => Err(CdcEventConversionError::UnknownReplicationMessage),
-            },
+            }
             ReplicationMessage::PrimaryKeepAlive(keep_alive) => Ok(CdcEvent::KeepAliveRequested {
                 reply: keep_alive.reply() == 1,
+                wal_end: keep_alive.wal_end().into(),
             }),
             _ => Err(CdcEventConversionError::UnknownReplicationMessage),
         }
     }
 }
 
+/// Whether an update changed a column, see [`CdcEvent::changed_columns`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ColumnChange {
+    Changed,
+    Unchanged,
+    /// The column's old value wasn't sent, as it isn't part of the replica identity
+    Unknown,
+}
+
 #[derive(Debug)]
 pub enum CdcEvent {
     Begin(BeginBody),
     Commit(CommitBody),
     Insert((TableId, TableRow, Option<u32>)),
+    /// The old row is the row's before-image if the table has `REPLICA IDENTITY FULL`.
+    /// With any other replica identity it is only set if the key changed and holds
+    /// just the old key columns, the other columns being null, see
+    /// [`PostgresSource::require_before_images`](crate::pipeline::sources::postgres::PostgresSource::require_before_images).
     Update((TableId, Option<TableRow>, TableRow, Option<u32>)),
     Delete((TableId, TableRow, Option<u32>)),
     Relation(RelationBody),
     Type(TypeBody),
-    KeepAliveRequested { reply: bool },
+    /// The schema of a table whose columns changed, emitted after the
+    /// [`CdcEvent::Relation`] announcing the change if enabled with
+    /// [`PostgresSource::emit_schema_changes`](crate::pipeline::sources::postgres::PostgresSource::emit_schema_changes),
+    /// or before a change decoded with a schema refreshed by
+    /// [`PostgresSource::refresh_schemas_on_decode_error`](crate::pipeline::sources::postgres::PostgresSource::refresh_schemas_on_decode_error).
+    /// The pipeline writes it to the sink with
+    /// [`BatchSink::write_table_schemas`](crate::pipeline::sinks::BatchSink::write_table_schemas)
+    /// instead of passing it on with the other events.
+    Schema(TableSchema),
+    /// A table of the stream was dropped on the source, emitted instead of failing
+    /// the stream if enabled with
+    /// [`PostgresSource::skip_dropped_tables`](crate::pipeline::sources::postgres::PostgresSource::skip_dropped_tables).
+    /// The stream has forgotten the table's schema and its remaining changes fail
+    /// to decode with [`CdcEventConversionError::MissingSchema`].
+    TableDropped {
+        table_id: TableId,
+    },
+    /// A keepalive from Postgres. `wal_end` is the position up to which the server
+    /// has sent all changes.
+    KeepAliveRequested {
+        reply: bool,
+        wal_end: PgLsn,
+    },
+    /// Emitted before each keepalive if enabled, e.g. with
+    /// [`PostgresSource::emit_heartbeats`](crate::pipeline::sources::postgres::PostgresSource::emit_heartbeats),
+    /// so that consumers see the stream is alive and how far it got while no changes
+    /// flow. `lsn` is the server's WAL end and `server_time` the time it sent the
+    /// keepalive at.
+    Heartbeat {
+        lsn: PgLsn,
+        server_time: SystemTime,
+    },
     StreamStart(StreamStartBody),
     StreamStop(StreamStopBody),
     StreamCommit(StreamCommitBody),
     StreamAbort(StreamAbortBody),
 }
 
+impl CdcEvent {
+    /// Copies the event, e.g. to write it to several sinks. The message bodies from
+    /// Postgres can't be cloned, so they are encoded back into the pgoutput messages
+    /// they were parsed from and parsed again.
+    pub fn try_clone(&self) -> Result<CdcEvent, CdcEventConversionError> {
+        let event = match self {
+            CdcEvent::Insert((table_id, row, xid)) => {
+                CdcEvent::Insert((*table_id, row.clone(), *xid))
+            }
+            CdcEvent::Update((table_id, old_row, new_row, xid)) => {
+                CdcEvent::Update((*table_id, old_row.clone(), new_row.clone(), *xid))
+            }
+            CdcEvent::Delete((table_id, row, xid)) => {
+                CdcEvent::Delete((*table_id, row.clone(), *xid))
+            }
+            CdcEvent::KeepAliveRequested { reply, wal_end } => CdcEvent::KeepAliveRequested {
+                reply: *reply,
+                wal_end: *wal_end,
+            },
+            CdcEvent::Heartbeat { lsn, server_time } => CdcEvent::Heartbeat {
+                lsn: *lsn,
+                server_time: *server_time,
+            },
+            CdcEvent::Schema(table_schema) => CdcEvent::Schema(table_schema.clone()),
+            CdcEvent::TableDropped { table_id } => CdcEvent::TableDropped {
+                table_id: *table_id,
+            },
+            event => {
+                let mut buf = BytesMut::new();
+                let in_stream = event.encode_message(&mut buf)?;
+                CdcEvent::decode_message(&buf.freeze(), in_stream)?
+            }
+        };
+        Ok(event)
+    }
+
+    /// Compares the old and new row of an update column by column, returning the
+    /// names of `table_schema`'s columns with whether they changed, or `None` for
+    /// other events. `identity` is the table's replica identity the update was sent
+    /// with, e.g. tracked from [`CdcEvent::Relation`] events with
+    /// [`ReplicaIdentities`]. Under `REPLICA IDENTITY FULL` all columns are compared,
+    /// including unchanged TOAST values, which are taken from the old row. Otherwise
+    /// Postgres only sends the old key if it changed, so only key columns are compared
+    /// and the others are [`ColumnChange::Unknown`], as are columns of `table_schema`
+    /// which the rows don't have, e.g. if the table changed since.
+    pub fn changed_columns(
+        &self,
+        table_schema: &TableSchema,
+        identity: &RelationIdentity,
+    ) -> Option<Vec<(String, ColumnChange)>> {
+        let CdcEvent::Update((_, old_row, new_row, _)) = self else {
+            return None;
+        };
+        let key_columns: Option<&[String]> = match identity {
+            RelationIdentity::Full => None,
+            RelationIdentity::Key(key_columns) => Some(key_columns),
+            RelationIdentity::Nothing => Some(&[]),
+        };
+        let changes = table_schema
+            .column_schemas
+            .iter()
+            .enumerate()
+            .map(|(i, column_schema)| {
+                let known = match key_columns {
+                    Some(key_columns) => key_columns.contains(&column_schema.name),
+                    None => true,
+                };
+                let change = match (old_row, new_row.values.get(i)) {
+                    // The column was added after the update was decoded
+                    (_, None) => ColumnChange::Unknown,
+                    _ if !known => ColumnChange::Unknown,
+                    (Some(old_row), Some(new_value)) => match old_row.values.get(i) {
+                        Some(old_value)
+                            if TextFormatConverter::to_text(old_value)
+                                == TextFormatConverter::to_text(new_value) =>
+                        {
+                            ColumnChange::Unchanged
+                        }
+                        Some(_) => ColumnChange::Changed,
+                        None => ColumnChange::Unknown,
+                    },
+                    // Without a full identity the old key is only sent if it changed
+                    (None, Some(_)) if key_columns.is_some() => ColumnChange::Unchanged,
+                    (None, Some(_)) => ColumnChange::Unknown,
+                };
+                (column_schema.name.clone(), change)
+            })
+            .collect();
+        Some(changes)
+    }
+
+    /// Encodes an event parsed from a pgoutput message other than a row change back
+    /// into the message, returning whether the message is part of a streamed
+    /// transaction, which [`CdcEvent::decode_message`] needs to parse it again
+    pub(crate) fn encode_message(
+        &self,
+        buf: &mut BytesMut,
+    ) -> Result<bool, CdcEventConversionError> {
+        let mut in_stream = false;
+        match self {
+            CdcEvent::Begin(body) => {
+                buf.put_u8(b'B');
+                buf.put_u64(body.final_lsn());
+                buf.put_i64(body.timestamp());
+                buf.put_u32(body.xid());
+            }
+            CdcEvent::Commit(body) => {
+                buf.put_u8(b'C');
+                buf.put_i8(body.flags());
+                buf.put_u64(body.commit_lsn());
+                buf.put_u64(body.end_lsn());
+                buf.put_i64(body.timestamp());
+            }
+            CdcEvent::Relation(body) => {
+                buf.put_u8(b'R');
+                if let Some(xid) = body.xid() {
+                    in_stream = true;
+                    buf.put_u32(xid);
+                }
+                buf.put_u32(body.rel_id());
+                put_cstr(buf, body.namespace()?);
+                put_cstr(buf, body.name()?);
+                buf.put_u8(match body.replica_identity() {
+                    ReplicaIdentity::Default => b'd',
+                    ReplicaIdentity::Nothing => b'n',
+                    ReplicaIdentity::Full => b'f',
+                    ReplicaIdentity::Index => b'i',
+                });
+                buf.put_i16(body.columns().len() as i16);
+                for column in body.columns() {
+                    buf.put_i8(column.flags());
+                    put_cstr(buf, column.name()?);
+                    buf.put_i32(column.type_id());
+                    buf.put_i32(column.type_modifier());
+                }
+            }
+            CdcEvent::Type(body) => {
+                buf.put_u8(b'Y');
+                if let Some(xid) = body.xid() {
+                    in_stream = true;
+                    buf.put_u32(xid);
+                }
+                buf.put_u32(body.id());
+                put_cstr(buf, body.namespace()?);
+                put_cstr(buf, body.name()?);
+            }
+            CdcEvent::StreamStart(body) => {
+                buf.put_u8(b'S');
+                buf.put_u32(body.xid());
+                buf.put_u8(body.first_segment());
+            }
+            CdcEvent::StreamStop(_) => buf.put_u8(b'E'),
+            CdcEvent::StreamCommit(body) => {
+                buf.put_u8(b'c');
+                buf.put_u32(body.xid());
+                buf.put_i8(body.flags());
+                buf.put_u64(body.commit_lsn());
+                buf.put_u64(body.end_lsn());
+                buf.put_i64(body.timestamp());
+            }
+            CdcEvent::StreamAbort(body) => {
+                buf.put_u8(b'A');
+                buf.put_u32(body.xid());
+                buf.put_u32(body.subxid());
+            }
+            CdcEvent::Insert(_)
+            | CdcEvent::Update(_)
+            | CdcEvent::Delete(_)
+            | CdcEvent::KeepAliveRequested { .. }
+            | CdcEvent::Heartbeat { .. }
+            | CdcEvent::Schema(_)
+            | CdcEvent::TableDropped { .. } => {
+                return Err(CdcEventConversionError::MessageNotSupported)
+            }
+        }
+        Ok(in_stream)
+    }
+
+    /// Parses a message encoded with [`CdcEvent::encode_message`]
+    pub(crate) fn decode_message(
+        message: &Bytes,
+        in_stream: bool,
+    ) -> Result<CdcEvent, CdcEventConversionError> {
+        match LogicalReplicationMessage::parse(message, in_stream)? {
+            LogicalReplicationMessage::Begin(body) => Ok(CdcEvent::Begin(body)),
+            LogicalReplicationMessage::Commit(body) => Ok(CdcEvent::Commit(body)),
+            LogicalReplicationMessage::Relation(body) => Ok(CdcEvent::Relation(body)),
+            LogicalReplicationMessage::Type(body) => Ok(CdcEvent::Type(body)),
+            LogicalReplicationMessage::StreamStart(body) => Ok(CdcEvent::StreamStart(body)),
+            LogicalReplicationMessage::StreamStop(body) => Ok(CdcEvent::StreamStop(body)),
+            LogicalReplicationMessage::StreamCommit(body) => Ok(CdcEvent::StreamCommit(body)),
+            LogicalReplicationMessage::StreamAbort(body) => Ok(CdcEvent::StreamAbort(body)),
+            _ => Err(CdcEventConversionError::UnknownReplicationMessage),
+        }
+    }
+}
+
+fn put_cstr(buf: &mut BytesMut, str: &str) {
+    buf.put_slice(str.as_bytes());
+    buf.put_u8(0);
+}
+
 impl BatchBoundary for CdcEvent {
     fn is_last_in_batch(&self) -> bool {
         matches!(
@@ -217,7 +1002,11 @@ impl BatchBoundary for CdcEvent {
                 | CdcEvent::StreamCommit(_)
                 | CdcEvent::StreamStop(_)
                 | CdcEvent::StreamAbort(_)
-                | CdcEvent::KeepAliveRequested { reply: _ }
+                | CdcEvent::KeepAliveRequested { .. }
         )
     }
+
+    fn estimated_size(&self) -> usize {
+        size::estimated_size(self)
+    }
 }
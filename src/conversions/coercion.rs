@@ -0,0 +1,171 @@
+use std::collections::HashMap;
+
+use thiserror::Error;
+use tokio_postgres::types::Type;
+
+use crate::table::ColumnSchema;
+
+use super::{table_row::TableRow, text::TextFormatConverter, ArrayCell, Cell};
+
+/// Largest integer magnitude a float8 represents exactly
+const MAX_EXACT_F64_INT: u64 = 1 << 53;
+
+#[derive(Debug, Error)]
+pub enum CoercionError {
+    #[error("coercion from {0} to {1} is not supported")]
+    Unsupported(Type, Type),
+
+    #[error("value {0} doesn't fit into {1}")]
+    OutOfRange(String, Type),
+
+    #[error("invalid json: {0}")]
+    InvalidJson(#[from] serde_json::Error),
+}
+
+/// A table of type coercions for sinks with narrower type systems than Postgres,
+/// e.g. a sink without int2 can coerce int2 to int4. Columns are coerced based on
+/// their Postgres type. Coercions which would lose data, like an int8 value which
+/// doesn't fit an int4, fail with [`CoercionError::OutOfRange`].
+///
+/// Supported coercions are between the integer types, from integers and float4
+/// to float8, from timestamptz to a timestamp in UTC and from any type to text, in
+/// Postgres' text format. Json values, which are passed through as the text Postgres sent, can
+/// be parsed with [`CoercionTable::parse_json`].
+#[derive(Debug, Clone, Default)]
+pub struct CoercionTable {
+    coercions: HashMap<Type, Type>,
+}
+
+impl CoercionTable {
+    pub fn new() -> CoercionTable {
+        CoercionTable::default()
+    }
+
+    /// Coerces values of type `from` into type `to`
+    pub fn add(&mut self, from: Type, to: Type) {
+        self.coercions.insert(from, to);
+    }
+
+    /// Parses json and jsonb values, including array elements, into [`Cell::Json`]
+    /// for sinks which need structured access. This gives up the original text, e.g.
+    /// the order of keys in json values.
+    pub fn parse_json(&mut self) {
+        for typ in [Type::JSON, Type::JSONB, Type::JSON_ARRAY, Type::JSONB_ARRAY] {
+            self.add(typ.clone(), typ);
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.coercions.is_empty()
+    }
+
+    /// Returns the type values of type `typ` are coerced into
+    pub fn target_type<'a>(&'a self, typ: &'a Type) -> &'a Type {
+        self.coercions.get(typ).unwrap_or(typ)
+    }
+
+    /// Checks that values of type `typ` can be coerced into their target type, by
+    /// coercing the type's default value. Types without a coercion always pass.
+    pub fn check_type(&self, typ: &Type) -> Result<(), CoercionError> {
+        match self.coercions.get(typ) {
+            Some(to) => {
+                CoercionTable::coerce_cell(typ, to, TextFormatConverter::default_value(typ))?;
+                Ok(())
+            }
+            None => Ok(()),
+        }
+    }
+
+    /// Changes the types of the columns to the types their values are coerced into
+    pub fn coerce_column_schemas(&self, column_schemas: &mut [ColumnSchema]) {
+        for column_schema in column_schemas {
+            column_schema.typ = self.target_type(&column_schema.typ).clone();
+        }
+    }
+
+    /// Coerces the values of a row whose columns have the types in `column_types`
+    pub fn coerce_row(
+        &self,
+        column_types: &[Type],
+        mut row: TableRow,
+    ) -> Result<TableRow, CoercionError> {
+        for (typ, value) in column_types.iter().zip(row.values.iter_mut()) {
+            if let Some(to) = self.coercions.get(typ) {
+                let cell = std::mem::replace(value, Cell::Null);
+                *value = Self::coerce_cell(typ, to, cell)?;
+            }
+        }
+        Ok(row)
+    }
+
+    pub fn coerce_cell(from: &Type, to: &Type, cell: Cell) -> Result<Cell, CoercionError> {
+        let out_of_range = |value: String| CoercionError::OutOfRange(value, to.clone());
+        let cell = match (to, cell) {
+            (_, Cell::Null) => Cell::Null,
+            (&Type::INT2, Cell::I32(v)) => {
+                Cell::I16(v.try_into().map_err(|_| out_of_range(v.to_string()))?)
+            }
+            (&Type::INT2, Cell::I64(v)) => {
+                Cell::I16(v.try_into().map_err(|_| out_of_range(v.to_string()))?)
+            }
+            (&Type::INT4, Cell::I16(v)) => Cell::I32(v.into()),
+            (&Type::INT4, Cell::I64(v)) => {
+                Cell::I32(v.try_into().map_err(|_| out_of_range(v.to_string()))?)
+            }
+            (&Type::INT4, Cell::U32(v)) => {
+                Cell::I32(v.try_into().map_err(|_| out_of_range(v.to_string()))?)
+            }
+            (&Type::INT8, Cell::I16(v)) => Cell::I64(v.into()),
+            (&Type::INT8, Cell::I32(v)) => Cell::I64(v.into()),
+            (&Type::INT8, Cell::U32(v)) => Cell::I64(v.into()),
+            (&Type::FLOAT8, Cell::I16(v)) => Cell::F64(v.into()),
+            (&Type::FLOAT8, Cell::I32(v)) => Cell::F64(v.into()),
+            (&Type::FLOAT8, Cell::U32(v)) => Cell::F64(v.into()),
+            (&Type::FLOAT8, Cell::F32(v)) => Cell::F64(v.into()),
+            (&Type::FLOAT8, Cell::I64(v)) => {
+                if v.unsigned_abs() > MAX_EXACT_F64_INT {
+                    return Err(out_of_range(v.to_string()));
+                }
+                Cell::F64(v as f64)
+            }
+            (&Type::TIMESTAMP, Cell::TimeStampTz(v)) => Cell::TimeStamp(v.naive_utc()),
+            (&Type::JSON | &Type::JSONB, Cell::String(v)) => Cell::Json(serde_json::from_str(&v)?),
+            (&Type::JSON_ARRAY | &Type::JSONB_ARRAY, Cell::Array(array)) => {
+                match Self::parse_json_array(array)? {
+                    Some(array) => Cell::Array(array),
+                    None => return Err(CoercionError::Unsupported(from.clone(), to.clone())),
+                }
+            }
+            (&Type::TEXT, cell) => {
+                TextFormatConverter::to_text(&cell).map_or(Cell::Null, Cell::String)
+            }
+            _ => return Err(CoercionError::Unsupported(from.clone(), to.clone())),
+        };
+        Ok(cell)
+    }
+
+    /// Parses the elements of a json array, returning `None` if they aren't strings
+    fn parse_json_array(array: ArrayCell) -> Result<Option<ArrayCell>, CoercionError> {
+        let array = match array {
+            ArrayCell::Null => ArrayCell::Null,
+            ArrayCell::String(values) => ArrayCell::Json(
+                values
+                    .into_iter()
+                    .map(|v| v.map(|v| serde_json::from_str(&v)).transpose())
+                    .collect::<Result<_, _>>()?,
+            ),
+            ArrayCell::Nested(sub_arrays) => {
+                let mut parsed = Vec::with_capacity(sub_arrays.len());
+                for sub_array in sub_arrays {
+                    match Self::parse_json_array(sub_array)? {
+                        Some(sub_array) => parsed.push(sub_array),
+                        None => return Ok(None),
+                    }
+                }
+                ArrayCell::Nested(parsed)
+            }
+            _ => return Ok(None),
+        };
+        Ok(Some(array))
+    }
+}
@@ -0,0 +1,127 @@
+use thiserror::Error;
+use tokio_postgres::types::Type;
+
+use super::hex::{self, ByteaHexParseError};
+
+/// Names of the PostGIS types decoded as [`PgGeometry`]. PostGIS is an extension, so
+/// the oids of its types differ between databases and the types are recognized by
+/// name instead.
+pub const GEOMETRY_TYPE_NAMES: [&str; 2] = ["geometry", "geography"];
+
+/// Set in the geometry type of an EWKB value if an SRID follows it
+const EWKB_SRID_FLAG: u32 = 0x2000_0000;
+
+#[derive(Debug, Error)]
+pub enum GeometryParseError {
+    #[error("invalid hex: {0}")]
+    InvalidHex(#[from] ByteaHexParseError),
+
+    #[error("geometry has {0} bytes, too few for its header")]
+    TooShort(usize),
+
+    #[error("invalid byte order {0}")]
+    InvalidByteOrder(u8),
+}
+
+/// Whether `typ` is a PostGIS geometry or geography type
+pub fn is_geometry(typ: &Type) -> bool {
+    GEOMETRY_TYPE_NAMES.contains(&typ.name())
+}
+
+/// A PostGIS geometry or geography value in EWKB, PostGIS' extension of the
+/// well-known binary format which can carry the value's SRID
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PgGeometry {
+    ewkb: Vec<u8>,
+}
+
+impl PgGeometry {
+    /// An empty geometry collection
+    pub fn empty() -> PgGeometry {
+        PgGeometry {
+            ewkb: vec![1, 7, 0, 0, 0, 0, 0, 0, 0],
+        }
+    }
+
+    pub fn from_ewkb(ewkb: Vec<u8>) -> Result<PgGeometry, GeometryParseError> {
+        let geometry = PgGeometry { ewkb };
+        geometry.header()?;
+        Ok(geometry)
+    }
+
+    /// Parses a geometry in PostGIS' text format, the EWKB as hex digits
+    pub fn from_hex(str: &str) -> Result<PgGeometry, GeometryParseError> {
+        PgGeometry::from_ewkb(hex::from_hex(str)?)
+    }
+
+    /// Formats the geometry in PostGIS' text format, the EWKB as upper case hex
+    /// digits
+    pub fn to_hex(&self) -> String {
+        self.ewkb.iter().map(|b| format!("{b:02X}")).collect()
+    }
+
+    pub fn ewkb(&self) -> &[u8] {
+        &self.ewkb
+    }
+
+    pub fn into_ewkb(self) -> Vec<u8> {
+        self.ewkb
+    }
+
+    /// The spatial reference system of the geometry, `None` if it has none
+    pub fn srid(&self) -> Option<u32> {
+        let (little_endian, geometry_type) = self.header().ok()?;
+        if geometry_type & EWKB_SRID_FLAG == 0 {
+            return None;
+        }
+        let srid = [self.ewkb[5], self.ewkb[6], self.ewkb[7], self.ewkb[8]];
+        Some(read_u32(srid, little_endian))
+    }
+
+    /// The geometry without its SRID, as read by sinks which expect plain WKB.
+    /// Z and M dimensions keep PostGIS' flags, which common WKB readers accept.
+    pub fn to_wkb(&self) -> Vec<u8> {
+        let Ok((little_endian, geometry_type)) = self.header() else {
+            return self.ewkb.clone();
+        };
+        if geometry_type & EWKB_SRID_FLAG == 0 {
+            return self.ewkb.clone();
+        }
+        let geometry_type = geometry_type & !EWKB_SRID_FLAG;
+        let mut wkb = Vec::with_capacity(self.ewkb.len() - 4);
+        wkb.push(self.ewkb[0]);
+        if little_endian {
+            wkb.extend_from_slice(&geometry_type.to_le_bytes());
+        } else {
+            wkb.extend_from_slice(&geometry_type.to_be_bytes());
+        }
+        wkb.extend_from_slice(&self.ewkb[9..]);
+        wkb
+    }
+
+    /// The byte order and geometry type of the value
+    fn header(&self) -> Result<(bool, u32), GeometryParseError> {
+        if self.ewkb.len() < 5 {
+            return Err(GeometryParseError::TooShort(self.ewkb.len()));
+        }
+        let little_endian = match self.ewkb[0] {
+            0 => false,
+            1 => true,
+            byte_order => return Err(GeometryParseError::InvalidByteOrder(byte_order)),
+        };
+        let geometry_type = [self.ewkb[1], self.ewkb[2], self.ewkb[3], self.ewkb[4]];
+        let geometry_type = read_u32(geometry_type, little_endian);
+        if geometry_type & EWKB_SRID_FLAG != 0 && self.ewkb.len() < 9 {
+            return Err(GeometryParseError::TooShort(self.ewkb.len()));
+        }
+        Ok((little_endian, geometry_type))
+    }
+}
+
+fn read_u32(bytes: [u8; 4], little_endian: bool) -> u32 {
+    if little_endian {
+        u32::from_le_bytes(bytes)
+    } else {
+        u32::from_be_bytes(bytes)
+    }
+}
@@ -18,9 +18,12 @@ pub fn from_bytea_hex(s: &str) -> Result<Vec<u8>, ByteaHexParseError> {
     if s.len() < 2 || &s[..2] != "\\x" {
         return Err(ByteaHexParseError::InvalidPrefix);
     }
+    from_hex(&s[2..])
+}
 
-    let mut result = Vec::with_capacity((s.len() - 2) / 2);
-    let s = &s[2..];
+/// Parses hex digits without a prefix, e.g. `0aff`
+pub fn from_hex(s: &str) -> Result<Vec<u8>, ByteaHexParseError> {
+    let mut result = Vec::with_capacity(s.len() / 2);
 
     if s.len() % 2 != 0 {
         return Err(ByteaHexParseError::OddNumerOfDigits);
@@ -33,3 +36,13 @@ pub fn from_bytea_hex(s: &str) -> Result<Vec<u8>, ByteaHexParseError> {
 
     Ok(result)
 }
+
+/// Formats bytes in bytea's hex format, e.g. `\x0aff`
+pub fn to_bytea_hex(bytes: &[u8]) -> String {
+    let mut result = String::with_capacity(2 + bytes.len() * 2);
+    result.push_str("\\x");
+    for byte in bytes {
+        result.push_str(&format!("{byte:02x}"));
+    }
+    result
+}
@@ -0,0 +1,213 @@
+use std::{fmt::Display, str::FromStr};
+
+use thiserror::Error;
+
+const MICROS_PER_SECOND: i64 = 1_000_000;
+const MICROS_PER_MINUTE: i64 = 60 * MICROS_PER_SECOND;
+const MICROS_PER_HOUR: i64 = 60 * MICROS_PER_MINUTE;
+
+#[derive(Debug, Error)]
+pub enum ParseIntervalError {
+    #[error("invalid input value: {0}")]
+    InvalidInput(String),
+}
+
+/// A Postgres interval as the three components Postgres stores it in. They are
+/// independent because their lengths vary: a month can have 28 to 31 days and a
+/// day 23 to 25 hours across daylight saving changes, so e.g. `1 mon -1 days` is
+/// neither normalized nor equal to `29 days`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct PgInterval {
+    pub months: i32,
+    pub days: i32,
+    pub microseconds: i64,
+}
+
+impl FromStr for PgInterval {
+    type Err = ParseIntervalError;
+
+    /// Parses an interval in the `postgres` IntervalStyle, Postgres' default, e.g.
+    /// `1 year 2 mons -3 days +04:05:06.789`, or the `iso_8601` IntervalStyle, e.g.
+    /// `P1Y2M-3DT4H5M6.789S`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || ParseIntervalError::InvalidInput(s.to_string());
+        let interval = match s.strip_prefix('P') {
+            Some(iso) => Self::parse_iso_8601(iso),
+            None => Self::parse_postgres(s),
+        };
+        interval.ok_or_else(invalid)
+    }
+}
+
+impl PgInterval {
+    fn parse_postgres(s: &str) -> Option<PgInterval> {
+        let mut interval = PgInterval::default();
+        let mut tokens = s.split_whitespace().peekable();
+        tokens.peek()?;
+        while let Some(token) = tokens.next() {
+            if token.contains(':') {
+                interval.microseconds = interval
+                    .microseconds
+                    .checked_add(Self::parse_time(token)?)?;
+                continue;
+            }
+            let value: i32 = token.parse().ok()?;
+            match tokens.next()? {
+                "year" | "years" => {
+                    interval.months = interval.months.checked_add(value.checked_mul(12)?)?
+                }
+                "mon" | "mons" => interval.months = interval.months.checked_add(value)?,
+                "day" | "days" => interval.days = interval.days.checked_add(value)?,
+                _ => return None,
+            }
+        }
+        Some(interval)
+    }
+
+    /// Parses a signed time of day like `-04:05:06.789` into microseconds. The hours
+    /// aren't limited to a day.
+    fn parse_time(s: &str) -> Option<i64> {
+        let (negative, s) = match s.as_bytes().first()? {
+            b'-' => (true, &s[1..]),
+            b'+' => (false, &s[1..]),
+            _ => (false, s),
+        };
+        let mut parts = s.split(':');
+        let hours: i64 = Self::parse_unsigned(parts.next()?)?;
+        let minutes: i64 = Self::parse_unsigned(parts.next()?)?;
+        let seconds = match parts.next() {
+            Some(seconds) => Self::parse_seconds(seconds)?,
+            None => 0,
+        };
+        if parts.next().is_some() {
+            return None;
+        }
+        let micros = hours
+            .checked_mul(MICROS_PER_HOUR)?
+            .checked_add(minutes * MICROS_PER_MINUTE)?
+            .checked_add(seconds)?;
+        Some(if negative { -micros } else { micros })
+    }
+
+    /// Parses unsigned seconds with up to six fractional digits into microseconds
+    fn parse_seconds(s: &str) -> Option<i64> {
+        let (whole, fraction) = s.split_once('.').unwrap_or((s, ""));
+        if fraction.len() > 6 || !fraction.bytes().all(|b| b.is_ascii_digit()) {
+            return None;
+        }
+        let whole: i64 = Self::parse_unsigned(whole)?;
+        let fraction: i64 = format!("{fraction:0<6}").parse().ok()?;
+        whole.checked_mul(MICROS_PER_SECOND)?.checked_add(fraction)
+    }
+
+    fn parse_unsigned(s: &str) -> Option<i64> {
+        if s.is_empty() || !s.bytes().all(|b| b.is_ascii_digit()) {
+            return None;
+        }
+        s.parse().ok()
+    }
+
+    /// Parses the part of an ISO 8601 duration after its `P`. Every component can
+    /// be signed and only seconds can have a fraction, which is how Postgres
+    /// formats intervals in the `iso_8601` IntervalStyle.
+    fn parse_iso_8601(s: &str) -> Option<PgInterval> {
+        let mut interval = PgInterval::default();
+        let (date, time) = s.split_once('T').unwrap_or((s, ""));
+        if date.is_empty() && time.is_empty() {
+            return None;
+        }
+
+        for (value, designator) in Self::iso_8601_components(date)? {
+            let value: i32 = value.parse().ok()?;
+            match designator {
+                'Y' => interval.months = interval.months.checked_add(value.checked_mul(12)?)?,
+                'M' => interval.months = interval.months.checked_add(value)?,
+                'W' => interval.days = interval.days.checked_add(value.checked_mul(7)?)?,
+                'D' => interval.days = interval.days.checked_add(value)?,
+                _ => return None,
+            }
+        }
+
+        for (value, designator) in Self::iso_8601_components(time)? {
+            let micros = match designator {
+                'H' => value.parse::<i64>().ok()?.checked_mul(MICROS_PER_HOUR)?,
+                'M' => value.parse::<i64>().ok()?.checked_mul(MICROS_PER_MINUTE)?,
+                'S' => match value.strip_prefix('-') {
+                    Some(seconds) => -Self::parse_seconds(seconds)?,
+                    None => Self::parse_seconds(value.strip_prefix('+').unwrap_or(value))?,
+                },
+                _ => return None,
+            };
+            interval.microseconds = interval.microseconds.checked_add(micros)?;
+        }
+
+        Some(interval)
+    }
+
+    /// Splits e.g. `1Y-2M` into `[("1", 'Y'), ("-2", 'M')]`
+    fn iso_8601_components(s: &str) -> Option<Vec<(&str, char)>> {
+        let mut components = vec![];
+        let mut start = 0;
+        for (i, c) in s.char_indices() {
+            if c.is_ascii_alphabetic() {
+                if i == start {
+                    return None;
+                }
+                components.push((&s[start..i], c));
+                start = i + 1;
+            }
+        }
+        if start != s.len() {
+            return None;
+        }
+        Some(components)
+    }
+}
+
+/// Formats the interval in the `postgres` IntervalStyle, the way Postgres does
+impl Display for PgInterval {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut fields = vec![];
+        // A positive field following a negative one gets an explicit sign
+        let mut is_before = false;
+        let years = self.months / 12;
+        let months = self.months % 12;
+        for (value, unit, units) in [
+            (years, "year", "years"),
+            (months, "mon", "mons"),
+            (self.days, "day", "days"),
+        ] {
+            if value == 0 {
+                continue;
+            }
+            let sign = if is_before && value > 0 { "+" } else { "" };
+            let unit = if value == 1 { unit } else { units };
+            fields.push(format!("{sign}{value} {unit}"));
+            is_before = value < 0;
+        }
+
+        if self.microseconds != 0 || fields.is_empty() {
+            let sign = if self.microseconds < 0 {
+                "-"
+            } else if is_before {
+                "+"
+            } else {
+                ""
+            };
+            let micros = self.microseconds.unsigned_abs();
+            let hours = micros / MICROS_PER_HOUR as u64;
+            let minutes = micros % MICROS_PER_HOUR as u64 / MICROS_PER_MINUTE as u64;
+            let seconds = micros % MICROS_PER_MINUTE as u64 / MICROS_PER_SECOND as u64;
+            let fraction = micros % MICROS_PER_SECOND as u64;
+            let mut time = format!("{sign}{hours:02}:{minutes:02}:{seconds:02}");
+            if fraction != 0 {
+                let fraction = format!("{fraction:06}");
+                time.push('.');
+                time.push_str(fraction.trim_end_matches('0'));
+            }
+            fields.push(time);
+        }
+
+        write!(f, "{}", fields.join(" "))
+    }
+}
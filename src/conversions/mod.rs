@@ -1,15 +1,29 @@
 use std::fmt::Debug;
 
-use chrono::{DateTime, NaiveDate, NaiveDateTime, NaiveTime, Utc};
+use bits::PgBits;
+use chrono::{DateTime, NaiveDate, NaiveDateTime, NaiveTime, Timelike, Utc};
+use geometry::PgGeometry;
+use interval::PgInterval;
 use numeric::PgNumeric;
+use range::PgRange;
 use uuid::Uuid;
 
+#[cfg(feature = "arrow")]
+pub mod arrow;
+pub mod bits;
 pub mod bool;
 pub mod cdc_event;
+pub mod coercion;
+pub mod geometry;
 pub mod hex;
+pub mod interval;
+pub mod money;
 pub mod numeric;
+pub mod range;
+pub mod size;
 pub mod table_row;
 pub mod text;
+pub mod wal2json;
 
 #[derive(Debug, Clone)]
 pub enum Cell {
@@ -27,10 +41,15 @@ pub enum Cell {
     Time(NaiveTime),
     TimeStamp(NaiveDateTime),
     TimeStampTz(DateTime<Utc>),
+    Interval(PgInterval),
     Uuid(Uuid),
     Json(serde_json::Value),
     Bytes(Vec<u8>),
     Array(ArrayCell),
+    Range(PgRange),
+    Multirange(Vec<PgRange>),
+    Geometry(PgGeometry),
+    Bits(PgBits),
 }
 
 #[derive(Debug, Clone)]
@@ -49,7 +68,21 @@ pub enum ArrayCell {
     Time(Vec<Option<NaiveTime>>),
     TimeStamp(Vec<Option<NaiveDateTime>>),
     TimeStampTz(Vec<Option<DateTime<Utc>>>),
+    Interval(Vec<Option<PgInterval>>),
     Uuid(Vec<Option<Uuid>>),
     Json(Vec<Option<serde_json::Value>>),
     Bytes(Vec<Option<Vec<u8>>>),
+    /// A multi-dimensional array as its sub-arrays, which all have the same element type
+    Nested(Vec<ArrayCell>),
+}
+
+/// Days between the Unix epoch and `date`, as dates are stored by Arrow, DuckDB and
+/// Debezium
+pub(crate) fn days_since_epoch(date: NaiveDate) -> i32 {
+    (date - DateTime::UNIX_EPOCH.date_naive()).num_days() as i32
+}
+
+/// Microseconds between midnight and `time`
+pub(crate) fn micros_since_midnight(time: NaiveTime) -> i64 {
+    time.num_seconds_from_midnight() as i64 * 1_000_000 + time.nanosecond() as i64 / 1_000
 }
@@ -0,0 +1,34 @@
+use bigdecimal::BigDecimal;
+use thiserror::Error;
+
+use super::numeric::PgNumeric;
+
+#[derive(Debug, Error)]
+pub enum ParseMoneyError {
+    #[error("invalid input value: {0}")]
+    InvalidInput(String),
+}
+
+/// Parses a money value as Postgres formats it, e.g. `$1,234.56`, `-$0.99` or
+/// `($5.00)`, into a decimal of the currency's major unit with its minor units as
+/// the fraction, i.e. `1234.56`.
+///
+/// The format depends on the `lc_monetary` setting of the session which formats
+/// the value. Currency symbols, group separators and a sign or parentheses in any
+/// position are handled, but the decimal point is assumed to be a `.`, as in the
+/// `C` and `en_US` locales. Values formatted in locales which use a `,` as the
+/// decimal point, like `de_DE`, are parsed wrongly, so set `lc_monetary` to `C`
+/// for the replication user, e.g. with `alter role ... set lc_monetary = 'C'`.
+pub fn parse_money(s: &str) -> Result<PgNumeric, ParseMoneyError> {
+    let invalid = || ParseMoneyError::InvalidInput(s.to_string());
+    let negative = s.contains('-') || (s.contains('(') && s.contains(')'));
+    let digits: String = s
+        .chars()
+        .filter(|c| c.is_ascii_digit() || *c == '.')
+        .collect();
+    if !digits.bytes().any(|b| b.is_ascii_digit()) {
+        return Err(invalid());
+    }
+    let value: BigDecimal = digits.parse().map_err(|_| invalid())?;
+    Ok(PgNumeric::Value(if negative { -value } else { value }))
+}
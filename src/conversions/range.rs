@@ -0,0 +1,215 @@
+use std::{iter::Peekable, str::Chars};
+
+use thiserror::Error;
+use tokio_postgres::types::{Kind, Type};
+
+use super::{
+    text::{FromTextError, TextFormatConverter},
+    Cell,
+};
+
+/// A value of a range type like `int4range` or `tsrange`, whose bounds are cells of
+/// the range's subtype
+#[derive(Debug, Clone)]
+pub struct PgRange {
+    /// `None` if the range is unbounded below
+    pub lower: Option<Box<Cell>>,
+    /// `None` if the range is unbounded above
+    pub upper: Option<Box<Cell>>,
+    pub lower_inc: bool,
+    pub upper_inc: bool,
+    /// Whether this is the empty range, which has no bounds
+    pub empty: bool,
+}
+
+impl PgRange {
+    pub fn empty() -> PgRange {
+        PgRange {
+            lower: None,
+            upper: None,
+            lower_inc: false,
+            upper_inc: false,
+            empty: true,
+        }
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum RangeParseError {
+    #[error("unexpected end of input")]
+    UnexpectedEnd,
+
+    #[error("unexpected character {0}")]
+    UnexpectedCharacter(char),
+
+    #[error("trailing characters after the range")]
+    TrailingCharacters,
+}
+
+/// The subtype of the ranges of a range or multirange type, e.g. `int4` for
+/// `int4range` and `int4multirange`
+pub fn range_subtype(typ: &Type) -> Option<&Type> {
+    let inner = match typ.kind() {
+        Kind::Range(inner) | Kind::Multirange(inner) => inner,
+        _ => return None,
+    };
+    // A multirange's kind might name its range type rather than the subtype
+    match inner.kind() {
+        Kind::Range(subtype) => Some(subtype),
+        _ => Some(inner),
+    }
+}
+
+/// Parses a range in Postgres' text format, e.g. `[1,5)`, `(,"2024-01-01")` or
+/// `empty`. A missing bound is unbounded, and bounds can be quoted with `"` and `\`
+/// escaped by a backslash or `"` by doubling it.
+pub fn parse_range(str: &str, subtype: &Type) -> Result<PgRange, FromTextError> {
+    let mut chars = str.trim().chars().peekable();
+    let range = parse_range_from(&mut chars, subtype)?;
+    if chars.next().is_some() {
+        return Err(RangeParseError::TrailingCharacters.into());
+    }
+    Ok(range)
+}
+
+/// Parses a multirange in Postgres' text format, e.g. `{[1,3),[5,7)}` or `{}`
+pub fn parse_multirange(str: &str, subtype: &Type) -> Result<Vec<PgRange>, FromTextError> {
+    let mut chars = str.trim().chars().peekable();
+    expect(&mut chars, '{')?;
+    let mut ranges = vec![];
+    skip_whitespace(&mut chars);
+    if chars.peek() == Some(&'}') {
+        chars.next();
+    } else {
+        loop {
+            ranges.push(parse_range_from(&mut chars, subtype)?);
+            skip_whitespace(&mut chars);
+            match chars.next() {
+                Some(',') => skip_whitespace(&mut chars),
+                Some('}') => break,
+                Some(c) => return Err(RangeParseError::UnexpectedCharacter(c).into()),
+                None => return Err(RangeParseError::UnexpectedEnd.into()),
+            }
+        }
+    }
+    if chars.next().is_some() {
+        return Err(RangeParseError::TrailingCharacters.into());
+    }
+    Ok(ranges)
+}
+
+fn parse_range_from(chars: &mut Peekable<Chars>, subtype: &Type) -> Result<PgRange, FromTextError> {
+    skip_whitespace(chars);
+    let lower_inc = match chars.next() {
+        Some('[') => true,
+        Some('(') => false,
+        Some(c) if c.eq_ignore_ascii_case(&'e') => {
+            for expected in "mpty".chars() {
+                match chars.next() {
+                    Some(c) if c.eq_ignore_ascii_case(&expected) => {}
+                    Some(c) => return Err(RangeParseError::UnexpectedCharacter(c).into()),
+                    None => return Err(RangeParseError::UnexpectedEnd.into()),
+                }
+            }
+            return Ok(PgRange::empty());
+        }
+        Some(c) => return Err(RangeParseError::UnexpectedCharacter(c).into()),
+        None => return Err(RangeParseError::UnexpectedEnd.into()),
+    };
+
+    let lower = parse_bound(chars, subtype)?;
+    expect(chars, ',')?;
+    let upper = parse_bound(chars, subtype)?;
+    let upper_inc = match chars.next() {
+        Some(']') => true,
+        Some(')') => false,
+        Some(c) => return Err(RangeParseError::UnexpectedCharacter(c).into()),
+        None => return Err(RangeParseError::UnexpectedEnd.into()),
+    };
+
+    Ok(PgRange {
+        lower,
+        upper,
+        lower_inc,
+        upper_inc,
+        empty: false,
+    })
+}
+
+/// Parses a bound up to the `,` or closing bracket following it, `None` if it's empty
+fn parse_bound(
+    chars: &mut Peekable<Chars>,
+    subtype: &Type,
+) -> Result<Option<Box<Cell>>, FromTextError> {
+    let mut bound = String::new();
+    let mut quoted = false;
+    let mut in_quotes = false;
+    loop {
+        match chars.peek() {
+            None => return Err(RangeParseError::UnexpectedEnd.into()),
+            Some(',' | ']' | ')') if !in_quotes => break,
+            Some(_) => {}
+        }
+        match chars.next() {
+            Some('"') if in_quotes && chars.peek() == Some(&'"') => {
+                chars.next();
+                bound.push('"');
+            }
+            Some('"') => {
+                quoted = true;
+                in_quotes = !in_quotes;
+            }
+            Some('\\') => match chars.next() {
+                Some(c) => bound.push(c),
+                None => return Err(RangeParseError::UnexpectedEnd.into()),
+            },
+            Some(c) => bound.push(c),
+            None => return Err(RangeParseError::UnexpectedEnd.into()),
+        }
+    }
+
+    if bound.is_empty() && !quoted {
+        return Ok(None);
+    }
+    let value = TextFormatConverter::try_from_str(subtype, &bound)?;
+    Ok(Some(Box::new(value)))
+}
+
+/// Formats a range in Postgres' text format with quoted bounds
+pub fn range_to_text(range: &PgRange) -> String {
+    if range.empty {
+        return "empty".to_string();
+    }
+    let bound =
+        |bound: &Option<Box<Cell>>| match bound.as_deref().and_then(TextFormatConverter::to_text) {
+            Some(text) => format!("\"{}\"", text.replace('\\', "\\\\").replace('"', "\\\"")),
+            None => String::new(),
+        };
+    format!(
+        "{}{},{}{}",
+        if range.lower_inc { '[' } else { '(' },
+        bound(&range.lower),
+        bound(&range.upper),
+        if range.upper_inc { ']' } else { ')' },
+    )
+}
+
+/// Formats a multirange in Postgres' text format, e.g. `{"[1,3)","[5,7)"}`
+pub fn multirange_to_text(ranges: &[PgRange]) -> String {
+    let ranges: Vec<String> = ranges.iter().map(range_to_text).collect();
+    format!("{{{}}}", ranges.join(","))
+}
+
+fn expect(chars: &mut Peekable<Chars>, expected: char) -> Result<(), RangeParseError> {
+    match chars.next() {
+        Some(c) if c == expected => Ok(()),
+        Some(c) => Err(RangeParseError::UnexpectedCharacter(c)),
+        None => Err(RangeParseError::UnexpectedEnd),
+    }
+}
+
+fn skip_whitespace(chars: &mut Peekable<Chars>) {
+    while chars.peek().is_some_and(|c| c.is_whitespace()) {
+        chars.next();
+    }
+}
@@ -0,0 +1,72 @@
+use std::mem::size_of;
+
+use crate::conversions::{
+    cdc_event::CdcEvent, range::PgRange, table_row::TableRow, ArrayCell, Cell,
+};
+
+/// Estimates the memory an event takes up, counting the values of its rows
+pub(crate) fn estimated_size(event: &CdcEvent) -> usize {
+    size_of::<CdcEvent>()
+        + match event {
+            CdcEvent::Insert((_, row, _)) | CdcEvent::Delete((_, row, _)) => {
+                estimated_row_size(row)
+            }
+            CdcEvent::Update((_, old_row, new_row, _)) => {
+                old_row.as_ref().map_or(0, estimated_row_size) + estimated_row_size(new_row)
+            }
+            _ => 0,
+        }
+}
+
+/// Estimates the memory the values of a row take up
+pub(crate) fn estimated_row_size(row: &TableRow) -> usize {
+    row.values.iter().map(cell_size).sum()
+}
+
+fn cell_size(cell: &Cell) -> usize {
+    size_of::<Cell>()
+        + match cell {
+            Cell::String(v) => v.len(),
+            Cell::Bytes(v) => v.len(),
+            Cell::Json(v) => v.to_string().len(),
+            Cell::Array(array) => array_size(array),
+            Cell::Range(range) => range_size(range),
+            Cell::Multirange(ranges) => ranges.iter().map(range_size).sum(),
+            Cell::Geometry(v) => v.ewkb().len(),
+            Cell::Bits(v) => v.as_bytes().len(),
+            _ => 0,
+        }
+}
+
+fn range_size(range: &PgRange) -> usize {
+    let bound_size = |bound: &Option<Box<Cell>>| bound.as_deref().map_or(0, cell_size);
+    size_of::<PgRange>() + bound_size(&range.lower) + bound_size(&range.upper)
+}
+
+fn array_size(array: &ArrayCell) -> usize {
+    fn values_size<T>(values: &[Option<T>]) -> usize {
+        values.len() * size_of::<Option<T>>()
+    }
+
+    match array {
+        ArrayCell::Null => 0,
+        ArrayCell::Bool(v) => values_size(v),
+        ArrayCell::String(v) => values_size(v) + v.iter().flatten().map(String::len).sum::<usize>(),
+        ArrayCell::I16(v) => values_size(v),
+        ArrayCell::I32(v) => values_size(v),
+        ArrayCell::U32(v) => values_size(v),
+        ArrayCell::I64(v) => values_size(v),
+        ArrayCell::F32(v) => values_size(v),
+        ArrayCell::F64(v) => values_size(v),
+        ArrayCell::Numeric(v) => values_size(v),
+        ArrayCell::Date(v) => values_size(v),
+        ArrayCell::Time(v) => values_size(v),
+        ArrayCell::TimeStamp(v) => values_size(v),
+        ArrayCell::TimeStampTz(v) => values_size(v),
+        ArrayCell::Interval(v) => values_size(v),
+        ArrayCell::Uuid(v) => values_size(v),
+        ArrayCell::Json(v) => values_size(v),
+        ArrayCell::Bytes(v) => values_size(v) + v.iter().flatten().map(Vec::len).sum::<usize>(),
+        ArrayCell::Nested(v) => v.iter().map(array_size).sum(),
+    }
+}
@@ -1,23 +1,45 @@
 use core::str;
-use std::str::Utf8Error;
+use std::{mem::size_of, str::Utf8Error};
 
 use thiserror::Error;
 use tokio_postgres::types::Type;
 use tracing::error;
 
-use crate::{conversions::text::TextFormatConverter, pipeline::batching::BatchBoundary};
+use crate::{
+    conversions::{size::estimated_row_size, text::TextFormatConverter},
+    pipeline::batching::BatchBoundary,
+    table::ColumnSchema,
+};
 
 use super::{text::FromTextError, Cell};
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct TableRow {
     pub values: Vec<Cell>,
+    /// Indexes of the columns of an update's new row whose TOASTed value didn't
+    /// change and wasn't sent by Postgres. Their values are placeholders, see
+    /// [`TextFormatConverter::default_value`], which must not overwrite the
+    /// stored values.
+    pub unchanged_toast: Vec<usize>,
+}
+
+impl TableRow {
+    pub fn new(values: Vec<Cell>) -> TableRow {
+        TableRow {
+            values,
+            unchanged_toast: vec![],
+        }
+    }
 }
 
 impl BatchBoundary for TableRow {
     fn is_last_in_batch(&self) -> bool {
         true
     }
+
+    fn estimated_size(&self) -> usize {
+        size_of::<TableRow>() + estimated_row_size(self)
+    }
 }
 
 #[derive(Debug, Error)]
@@ -34,17 +56,30 @@ pub enum TableRowConversionError {
     #[error("unterminated row")]
     UnterminatedRow,
 
+    #[error("unterminated quoted value")]
+    UnterminatedQuote,
+
     #[error("invalid value: {0}")]
     InvalidValue(#[from] FromTextError),
 }
 
+/// The format of the rows of a `COPY ... TO STDOUT`, with Postgres' default
+/// delimiter, quote and null string of each format
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum CopyFormat {
+    #[default]
+    Text,
+    /// `FORMAT csv`, with a header row if `header` is set
+    Csv { header: bool },
+}
+
 pub struct TableRowConverter;
 
 impl TableRowConverter {
     // parses text produced by this code in Postgres: https://github.com/postgres/postgres/blob/263a3f5f7f508167dbeafc2aefd5835b41d77481/src/backend/commands/copyto.c#L988-L1134
     pub fn try_from(
         row: &[u8],
-        column_schemas: &[crate::table::ColumnSchema],
+        column_schemas: &[ColumnSchema],
     ) -> Result<TableRow, TableRowConversionError> {
         let mut values = Vec::with_capacity(column_schemas.len());
 
@@ -53,6 +88,9 @@ impl TableRowConverter {
         let mut chars = row_str.chars();
         let mut val_str = String::with_capacity(10);
         let mut in_escape = false;
+        // Whether the value is the null marker `\N`, rather than a value which
+        // reads `\N` after unescaping, i.e. the escaped string `\\N`
+        let mut null_marker = false;
         let mut row_terminated = false;
         let mut done = false;
 
@@ -62,6 +100,7 @@ impl TableRowConverter {
                     Some(c) => match c {
                         c if in_escape => {
                             if c == 'N' {
+                                null_marker = val_str.is_empty();
                                 val_str.push('\\');
                                 val_str.push(c);
                             } else if c == 'b' {
@@ -108,26 +147,107 @@ impl TableRowConverter {
                     return Err(TableRowConversionError::NumColsMismatch);
                 };
 
-                let value = if val_str == "\\N" {
+                let value = if null_marker && val_str == "\\N" {
                     Cell::Null
                 } else {
-                    match TextFormatConverter::try_from_str(&column_schema.typ, &val_str) {
-                        Ok(value) => value,
-                        Err(e) => {
-                            error!(
-                                "error parsing column `{}` of type `{}` from text `{val_str}`",
-                                column_schema.name, column_schema.typ
-                            );
-                            return Err(e.into());
-                        }
-                    }
+                    Self::parse_value(column_schema, &val_str)?
                 };
 
                 values.push(value);
                 val_str.clear();
+                null_marker = false;
+            }
+        }
+
+        Ok(TableRow::new(values))
+    }
+
+    /// Parses a row in `COPY`'s csv format: values are separated by commas and can be
+    /// quoted with double quotes, which are doubled within quoted values. An unquoted
+    /// empty value is null while a quoted one is an empty string. Rows end with a
+    /// newline, but quoted values can span lines.
+    pub fn try_from_csv(
+        row: &[u8],
+        column_schemas: &[ColumnSchema],
+    ) -> Result<TableRow, TableRowConversionError> {
+        let mut values = Vec::with_capacity(column_schemas.len());
+
+        let row_str = str::from_utf8(row)?;
+        let row_str = row_str
+            .strip_suffix('\n')
+            .ok_or(TableRowConversionError::UnterminatedRow)?;
+        let row_str = row_str.strip_suffix('\r').unwrap_or(row_str);
+
+        let mut column_schemas_iter = column_schemas.iter();
+        let mut chars = row_str.chars().peekable();
+        let mut val_str = String::with_capacity(10);
+        loop {
+            let mut quoted = false;
+            let mut in_quotes = false;
+            let mut row_done = true;
+            while let Some(c) = chars.next() {
+                match c {
+                    '"' if in_quotes => {
+                        if chars.peek() == Some(&'"') {
+                            chars.next();
+                            val_str.push('"');
+                        } else {
+                            in_quotes = false;
+                        }
+                    }
+                    '"' => {
+                        quoted = true;
+                        in_quotes = true;
+                    }
+                    ',' if !in_quotes => {
+                        row_done = false;
+                        break;
+                    }
+                    c => val_str.push(c),
+                }
             }
+            if in_quotes {
+                return Err(TableRowConversionError::UnterminatedQuote);
+            }
+
+            let Some(column_schema) = column_schemas_iter.next() else {
+                return Err(TableRowConversionError::NumColsMismatch);
+            };
+
+            let value = if val_str.is_empty() && !quoted {
+                Cell::Null
+            } else {
+                Self::parse_value(column_schema, &val_str)?
+            };
+
+            values.push(value);
+            val_str.clear();
+
+            if row_done {
+                break;
+            }
+        }
+
+        if column_schemas_iter.next().is_some() {
+            return Err(TableRowConversionError::NumColsMismatch);
         }
 
-        Ok(TableRow { values })
+        Ok(TableRow::new(values))
+    }
+
+    fn parse_value(
+        column_schema: &ColumnSchema,
+        val_str: &str,
+    ) -> Result<Cell, TableRowConversionError> {
+        match TextFormatConverter::try_from_str(&column_schema.typ, val_str) {
+            Ok(value) => Ok(value),
+            Err(e) => {
+                error!(
+                    "error parsing column `{}` of type `{}` from text `{val_str}`",
+                    column_schema.name, column_schema.typ
+                );
+                Err(e.into())
+            }
+        }
     }
 }
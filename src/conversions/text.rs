@@ -1,15 +1,29 @@
 use core::str;
-use std::num::{ParseFloatError, ParseIntError};
+use std::{
+    iter::Peekable,
+    num::{ParseFloatError, ParseIntError},
+    str::Chars,
+};
 
 use bigdecimal::ParseBigDecimalError;
 use chrono::{DateTime, FixedOffset, NaiveDate, NaiveDateTime, NaiveTime, Utc};
 use thiserror::Error;
-use tokio_postgres::types::Type;
+use tokio_postgres::types::{Kind, Type};
 use uuid::Uuid;
 
-use crate::conversions::{bool::parse_bool, hex};
+use crate::conversions::{bool::parse_bool, hex, money::parse_money};
 
-use super::{bool::ParseBoolError, hex::ByteaHexParseError, numeric::PgNumeric, ArrayCell, Cell};
+use super::{
+    bits::{BitsParseError, PgBits},
+    bool::ParseBoolError,
+    geometry::{self, GeometryParseError, PgGeometry},
+    hex::ByteaHexParseError,
+    interval::{ParseIntervalError, PgInterval},
+    money::ParseMoneyError,
+    numeric::PgNumeric,
+    range::{self, PgRange, RangeParseError},
+    ArrayCell, Cell,
+};
 
 #[derive(Debug, Error)]
 pub enum FromTextError {
@@ -25,6 +39,12 @@ pub enum FromTextError {
     #[error("invalid numeric: {0}")]
     InvalidNumeric(#[from] ParseBigDecimalError),
 
+    #[error("invalid money: {0}")]
+    InvalidMoney(#[from] ParseMoneyError),
+
+    #[error("invalid interval: {0}")]
+    InvalidInterval(#[from] ParseIntervalError),
+
     #[error("invalid bytea: {0}")]
     InvalidBytea(#[from] ByteaHexParseError),
 
@@ -40,6 +60,15 @@ pub enum FromTextError {
     #[error("invalid array: {0}")]
     InvalidArray(#[from] ArrayParseError),
 
+    #[error("invalid range: {0}")]
+    InvalidRange(#[from] RangeParseError),
+
+    #[error("invalid geometry: {0}")]
+    InvalidGeometry(#[from] GeometryParseError),
+
+    #[error("invalid bit string: {0}")]
+    InvalidBits(#[from] BitsParseError),
+
     #[error("row get error: {0:?}")]
     RowGetError(#[from] Box<dyn std::error::Error + Sync + Send>),
 }
@@ -53,10 +82,27 @@ pub enum ArrayParseError {
 
     #[error("missing braces")]
     MissingBraces,
+
+    #[error("unexpected end of input")]
+    UnexpectedEnd,
+
+    #[error("unexpected character {0}")]
+    UnexpectedCharacter(char),
+
+    #[error("trailing characters after the array")]
+    TrailingCharacters,
 }
 
 impl TextFormatConverter {
     pub fn default_value(typ: &Type) -> Cell {
+        match typ.kind() {
+            Kind::Range(_) => return Cell::Range(PgRange::empty()),
+            Kind::Multirange(_) => return Cell::Multirange(vec![]),
+            _ => {}
+        }
+        if geometry::is_geometry(typ) {
+            return Cell::Geometry(PgGeometry::empty());
+        }
         match *typ {
             Type::BOOL => Cell::Bool(bool::default()),
             Type::BOOL_ARRAY => Cell::Array(ArrayCell::Bool(Vec::default())),
@@ -78,10 +124,13 @@ impl TextFormatConverter {
             Type::FLOAT4_ARRAY => Cell::Array(ArrayCell::F32(Vec::default())),
             Type::FLOAT8 => Cell::F64(f64::default()),
             Type::FLOAT8_ARRAY => Cell::Array(ArrayCell::F64(Vec::default())),
-            Type::NUMERIC => Cell::Numeric(PgNumeric::default()),
-            Type::NUMERIC_ARRAY => Cell::Array(ArrayCell::Numeric(Vec::default())),
+            Type::NUMERIC | Type::MONEY => Cell::Numeric(PgNumeric::default()),
+            Type::NUMERIC_ARRAY | Type::MONEY_ARRAY => {
+                Cell::Array(ArrayCell::Numeric(Vec::default()))
+            }
             Type::BYTEA => Cell::Bytes(Vec::default()),
             Type::BYTEA_ARRAY => Cell::Array(ArrayCell::Bytes(Vec::default())),
+            Type::BIT | Type::VARBIT => Cell::Bits(PgBits::default()),
             Type::DATE => Cell::Date(NaiveDate::MIN),
             Type::DATE_ARRAY => Cell::Array(ArrayCell::Date(Vec::default())),
             Type::TIME => Cell::Time(NaiveTime::MIN),
@@ -93,10 +142,12 @@ impl TextFormatConverter {
                 Cell::TimeStampTz(val)
             }
             Type::TIMESTAMPTZ_ARRAY => Cell::Array(ArrayCell::TimeStampTz(Vec::default())),
+            Type::INTERVAL => Cell::Interval(PgInterval::default()),
+            Type::INTERVAL_ARRAY => Cell::Array(ArrayCell::Interval(Vec::default())),
             Type::UUID => Cell::Uuid(Uuid::default()),
             Type::UUID_ARRAY => Cell::Array(ArrayCell::Uuid(Vec::default())),
-            Type::JSON | Type::JSONB => Cell::Json(serde_json::Value::default()),
-            Type::JSON_ARRAY | Type::JSONB_ARRAY => Cell::Array(ArrayCell::Json(Vec::default())),
+            Type::JSON | Type::JSONB => Cell::String("null".to_string()),
+            Type::JSON_ARRAY | Type::JSONB_ARRAY => Cell::Array(ArrayCell::String(Vec::default())),
             Type::OID => Cell::U32(u32::default()),
             Type::OID_ARRAY => Cell::Array(ArrayCell::U32(Vec::default())),
             #[cfg(feature = "unknown_types_to_bytes")]
@@ -108,7 +159,80 @@ impl TextFormatConverter {
         }
     }
 
+    /// Whether values of type `typ` are decoded into a [`Cell`] of their own, i.e.
+    /// aren't rejected or, with the `unknown_types_to_bytes` feature, passed through
+    /// as the text Postgres sent. Ranges and multiranges are supported if their
+    /// subtype is, and PostGIS types are recognized by name.
+    pub fn is_supported(typ: &Type) -> bool {
+        if let Some(subtype) = range::range_subtype(typ) {
+            return TextFormatConverter::is_supported(subtype);
+        }
+        if geometry::is_geometry(typ) {
+            return true;
+        }
+        matches!(
+            *typ,
+            Type::BOOL
+                | Type::BOOL_ARRAY
+                | Type::CHAR
+                | Type::BPCHAR
+                | Type::VARCHAR
+                | Type::NAME
+                | Type::TEXT
+                | Type::CHAR_ARRAY
+                | Type::BPCHAR_ARRAY
+                | Type::VARCHAR_ARRAY
+                | Type::NAME_ARRAY
+                | Type::TEXT_ARRAY
+                | Type::INT2
+                | Type::INT2_ARRAY
+                | Type::INT4
+                | Type::INT4_ARRAY
+                | Type::INT8
+                | Type::INT8_ARRAY
+                | Type::FLOAT4
+                | Type::FLOAT4_ARRAY
+                | Type::FLOAT8
+                | Type::FLOAT8_ARRAY
+                | Type::NUMERIC
+                | Type::NUMERIC_ARRAY
+                | Type::MONEY
+                | Type::MONEY_ARRAY
+                | Type::BYTEA
+                | Type::BYTEA_ARRAY
+                | Type::BIT
+                | Type::VARBIT
+                | Type::DATE
+                | Type::DATE_ARRAY
+                | Type::TIME
+                | Type::TIME_ARRAY
+                | Type::TIMESTAMP
+                | Type::TIMESTAMP_ARRAY
+                | Type::TIMESTAMPTZ
+                | Type::TIMESTAMPTZ_ARRAY
+                | Type::INTERVAL
+                | Type::INTERVAL_ARRAY
+                | Type::UUID
+                | Type::UUID_ARRAY
+                | Type::JSON
+                | Type::JSONB
+                | Type::JSON_ARRAY
+                | Type::JSONB_ARRAY
+                | Type::OID
+                | Type::OID_ARRAY
+        )
+    }
+
     pub fn try_from_str(typ: &Type, str: &str) -> Result<Cell, FromTextError> {
+        if let Some(subtype) = range::range_subtype(typ) {
+            return match typ.kind() {
+                Kind::Multirange(_) => Ok(Cell::Multirange(range::parse_multirange(str, subtype)?)),
+                _ => Ok(Cell::Range(range::parse_range(str, subtype)?)),
+            };
+        }
+        if geometry::is_geometry(typ) {
+            return Ok(Cell::Geometry(PgGeometry::from_hex(str)?));
+        }
         match *typ {
             Type::BOOL => Ok(Cell::Bool(parse_bool(str)?)),
             Type::BOOL_ARRAY => TextFormatConverter::parse_array(
@@ -154,12 +278,19 @@ impl TextFormatConverter {
                 |str| Ok(Some(str.parse()?)),
                 ArrayCell::Numeric,
             ),
+            Type::MONEY => Ok(Cell::Numeric(parse_money(str)?)),
+            Type::MONEY_ARRAY => TextFormatConverter::parse_array(
+                str,
+                |str| Ok(Some(parse_money(str)?)),
+                ArrayCell::Numeric,
+            ),
             Type::BYTEA => Ok(Cell::Bytes(hex::from_bytea_hex(str)?)),
             Type::BYTEA_ARRAY => TextFormatConverter::parse_array(
                 str,
                 |str| Ok(Some(hex::from_bytea_hex(str)?)),
                 ArrayCell::Bytes,
             ),
+            Type::BIT | Type::VARBIT => Ok(Cell::Bits(PgBits::from_text(str)?)),
             Type::DATE => {
                 let val = NaiveDate::parse_from_str(str, "%Y-%m-%d")?;
                 Ok(Cell::Date(val))
@@ -232,6 +363,12 @@ impl TextFormatConverter {
                     ),
                 }
             }
+            Type::INTERVAL => Ok(Cell::Interval(str.parse()?)),
+            Type::INTERVAL_ARRAY => TextFormatConverter::parse_array(
+                str,
+                |str| Ok(Some(str.parse()?)),
+                ArrayCell::Interval,
+            ),
             Type::UUID => {
                 let val = Uuid::parse_str(str)?;
                 Ok(Cell::Uuid(val))
@@ -241,14 +378,12 @@ impl TextFormatConverter {
                 |str| Ok(Some(Uuid::parse_str(str)?)),
                 ArrayCell::Uuid,
             ),
-            Type::JSON | Type::JSONB => {
-                let val = serde_json::from_str(str)?;
-                Ok(Cell::Json(val))
-            }
+            // Passed through verbatim, see CoercionTable::parse_json to parse them
+            Type::JSON | Type::JSONB => Ok(Cell::String(str.to_string())),
             Type::JSON_ARRAY | Type::JSONB_ARRAY => TextFormatConverter::parse_array(
                 str,
-                |str| Ok(Some(serde_json::from_str(str)?)),
-                ArrayCell::Json,
+                |str| Ok(Some(str.to_string())),
+                ArrayCell::String,
             ),
             Type::OID => {
                 let val: u32 = str.parse()?;
@@ -266,59 +401,214 @@ impl TextFormatConverter {
         }
     }
 
+    /// Formats a value in Postgres' text format, the inverse of
+    /// [`TextFormatConverter::try_from_str`]. Returns `None` for nulls.
+    pub fn to_text(cell: &Cell) -> Option<String> {
+        let text = match cell {
+            Cell::Null | Cell::Array(ArrayCell::Null) => return None,
+            Cell::Bool(v) => if *v { "t" } else { "f" }.to_string(),
+            Cell::String(v) => v.clone(),
+            Cell::I16(v) => v.to_string(),
+            Cell::I32(v) => v.to_string(),
+            Cell::U32(v) => v.to_string(),
+            Cell::I64(v) => v.to_string(),
+            Cell::F32(v) => Self::float_to_text(f64::from(*v), v.to_string()),
+            Cell::F64(v) => Self::float_to_text(*v, v.to_string()),
+            Cell::Numeric(v) => v.to_string(),
+            Cell::Date(v) => v.to_string(),
+            Cell::Time(v) => v.to_string(),
+            Cell::TimeStamp(v) => v.to_string(),
+            Cell::TimeStampTz(v) => v.format("%Y-%m-%d %H:%M:%S%.f%:z").to_string(),
+            Cell::Interval(v) => v.to_string(),
+            Cell::Uuid(v) => v.to_string(),
+            Cell::Json(v) => v.to_string(),
+            Cell::Bytes(v) => hex::to_bytea_hex(v),
+            Cell::Array(array) => Self::array_to_text(array),
+            Cell::Range(range) => range::range_to_text(range),
+            Cell::Multirange(ranges) => range::multirange_to_text(ranges),
+            Cell::Geometry(v) => v.to_hex(),
+            Cell::Bits(v) => v.to_string(),
+        };
+        Some(text)
+    }
+
+    fn float_to_text(v: f64, text: String) -> String {
+        if v.is_nan() {
+            "NaN".to_string()
+        } else if v.is_infinite() {
+            if v > 0.0 { "Infinity" } else { "-Infinity" }.to_string()
+        } else {
+            text
+        }
+    }
+
+    /// Formats an array with every non-null element quoted
+    fn array_to_text(array: &ArrayCell) -> String {
+        fn elements<T>(values: &[Option<T>], to_cell: impl Fn(T) -> Cell) -> Vec<String>
+        where
+            T: Clone,
+        {
+            values
+                .iter()
+                .map(|value| match value {
+                    Some(value) => {
+                        let text = TextFormatConverter::to_text(&to_cell(value.clone()))
+                            .unwrap_or_default();
+                        let escaped = text.replace('\\', "\\\\").replace('"', "\\\"");
+                        format!("\"{escaped}\"")
+                    }
+                    None => "NULL".to_string(),
+                })
+                .collect()
+        }
+
+        let elements = match array {
+            ArrayCell::Null => return "NULL".to_string(),
+            ArrayCell::Bool(v) => elements(v, Cell::Bool),
+            ArrayCell::String(v) => elements(v, Cell::String),
+            ArrayCell::I16(v) => elements(v, Cell::I16),
+            ArrayCell::I32(v) => elements(v, Cell::I32),
+            ArrayCell::U32(v) => elements(v, Cell::U32),
+            ArrayCell::I64(v) => elements(v, Cell::I64),
+            ArrayCell::F32(v) => elements(v, Cell::F32),
+            ArrayCell::F64(v) => elements(v, Cell::F64),
+            ArrayCell::Numeric(v) => elements(v, Cell::Numeric),
+            ArrayCell::Date(v) => elements(v, Cell::Date),
+            ArrayCell::Time(v) => elements(v, Cell::Time),
+            ArrayCell::TimeStamp(v) => elements(v, Cell::TimeStamp),
+            ArrayCell::TimeStampTz(v) => elements(v, Cell::TimeStampTz),
+            ArrayCell::Interval(v) => elements(v, Cell::Interval),
+            ArrayCell::Uuid(v) => elements(v, Cell::Uuid),
+            ArrayCell::Json(v) => elements(v, Cell::Json),
+            ArrayCell::Bytes(v) => elements(v, Cell::Bytes),
+            ArrayCell::Nested(v) => v.iter().map(Self::array_to_text).collect(),
+        };
+        format!("{{{}}}", elements.join(","))
+    }
+
+    /// Parses an array in Postgres' text format, e.g. `{1,NULL,3}` or `{{"a b",c},{d,e}}`
+    /// for a multi-dimensional array, which becomes an [`ArrayCell::Nested`]. Quoted
+    /// elements can contain any character with `"` and `\` escaped by a backslash. An
+    /// unquoted `NULL` in any case is a null while a quoted `"NULL"` is a string.
     fn parse_array<P, M, T>(str: &str, mut parse: P, m: M) -> Result<Cell, FromTextError>
     where
         P: FnMut(&str) -> Result<Option<T>, FromTextError>,
-        M: FnOnce(Vec<Option<T>>) -> ArrayCell,
+        M: Fn(Vec<Option<T>>) -> ArrayCell,
     {
         if str.len() < 2 {
             return Err(ArrayParseError::InputTooShort.into());
         }
 
+        // Arrays with lower bounds other than 1 are prefixed with their dimensions,
+        // e.g. `[0:1]={1,2}`, which are dropped along with the bounds
+        let str = match str.strip_prefix('[') {
+            Some(_) => match str.split_once('=') {
+                Some((_, array)) => array,
+                None => return Err(ArrayParseError::MissingBraces.into()),
+            },
+            None => str,
+        };
+
         if !str.starts_with('{') || !str.ends_with('}') {
             return Err(ArrayParseError::MissingBraces.into());
         }
 
-        let mut res = vec![];
-        let str = &str[1..(str.len() - 1)];
-        let mut val_str = String::with_capacity(10);
-        let mut in_quotes = false;
-        let mut in_escape = false;
-        let mut chars = str.chars();
-        let mut done = str.is_empty();
+        let mut chars = str.chars().peekable();
+        let array = Self::parse_array_level(&mut chars, &mut parse, &m)?;
+        if chars.any(|c| !c.is_whitespace()) {
+            return Err(ArrayParseError::TrailingCharacters.into());
+        }
+
+        Ok(Cell::Array(array))
+    }
 
-        while !done {
+    /// Parses one dimension of an array, starting at its opening brace
+    fn parse_array_level<P, M, T>(
+        chars: &mut Peekable<Chars>,
+        parse: &mut P,
+        m: &M,
+    ) -> Result<ArrayCell, FromTextError>
+    where
+        P: FnMut(&str) -> Result<Option<T>, FromTextError>,
+        M: Fn(Vec<Option<T>>) -> ArrayCell,
+    {
+        if chars.next() != Some('{') {
+            return Err(ArrayParseError::MissingBraces.into());
+        }
+        Self::skip_whitespace(chars);
+
+        if chars.peek() == Some(&'{') {
+            let mut sub_arrays = vec![];
             loop {
+                sub_arrays.push(Self::parse_array_level(chars, parse, m)?);
+                Self::skip_whitespace(chars);
                 match chars.next() {
-                    Some(c) => match c {
-                        c if in_escape => {
-                            val_str.push(c);
-                            in_escape = false;
+                    Some(',') => Self::skip_whitespace(chars),
+                    Some('}') => return Ok(ArrayCell::Nested(sub_arrays)),
+                    _ => return Err(ArrayParseError::UnexpectedEnd.into()),
+                }
+            }
+        }
+
+        let mut values = vec![];
+        if chars.peek() == Some(&'}') {
+            chars.next();
+            return Ok(m(values));
+        }
+
+        let mut val_str = String::with_capacity(10);
+        loop {
+            val_str.clear();
+            Self::skip_whitespace(chars);
+            let quoted = chars.peek() == Some(&'"');
+            if quoted {
+                chars.next();
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\\') => {
+                            val_str.push(chars.next().ok_or(ArrayParseError::UnexpectedEnd)?)
                         }
-                        '"' => in_quotes = !in_quotes,
-                        '\\' => in_escape = true,
-                        ',' if !in_quotes => {
-                            break;
+                        Some(c) => val_str.push(c),
+                        None => return Err(ArrayParseError::UnexpectedEnd.into()),
+                    }
+                }
+                Self::skip_whitespace(chars);
+            } else {
+                while let Some(&c) = chars.peek() {
+                    match c {
+                        ',' | '}' => break,
+                        '{' | '"' => return Err(ArrayParseError::UnexpectedCharacter(c).into()),
+                        '\\' => {
+                            chars.next();
+                            val_str.push(chars.next().ok_or(ArrayParseError::UnexpectedEnd)?);
                         }
                         c => {
+                            chars.next();
                             val_str.push(c);
                         }
-                    },
-                    None => {
-                        done = true;
-                        break;
                     }
                 }
+                val_str.truncate(val_str.trim_end().len());
             }
-            let val = if val_str.to_lowercase() == "null" {
+
+            let val = if !quoted && val_str.eq_ignore_ascii_case("null") {
                 None
             } else {
                 parse(&val_str)?
             };
-            res.push(val);
-            val_str.clear();
+            values.push(val);
+
+            match chars.next() {
+                Some(',') => {}
+                Some('}') => return Ok(m(values)),
+                Some(c) => return Err(ArrayParseError::UnexpectedCharacter(c).into()),
+                None => return Err(ArrayParseError::UnexpectedEnd.into()),
+            }
         }
+    }
 
-        Ok(Cell::Array(m(res)))
+    fn skip_whitespace(chars: &mut Peekable<Chars>) {
+        while chars.next_if(|c| c.is_whitespace()).is_some() {}
     }
 }
@@ -0,0 +1,237 @@
+use std::collections::HashMap;
+
+use bytes::{BufMut, BytesMut};
+use chrono::DateTime;
+use postgres_replication::protocol::LogicalReplicationMessage;
+use serde::Deserialize;
+use tokio_postgres::types::PgLsn;
+
+use crate::table::{ColumnSchema, TableId, TableName, TableSchema};
+
+use super::{
+    cdc_event::{CdcEvent, CdcEventConversionError},
+    table_row::TableRow,
+    text::TextFormatConverter,
+    Cell,
+};
+
+/// A change record of wal2json's format version 2. Which fields are set depends on
+/// the action: `B` (begin) and `C` (commit) have the lsns, the xid and the commit
+/// timestamp, `I` (insert), `U` (update) and `D` (delete) the table and its columns.
+#[derive(Debug, Deserialize)]
+struct Wal2JsonChange {
+    action: String,
+    xid: Option<u32>,
+    lsn: Option<String>,
+    nextlsn: Option<String>,
+    timestamp: Option<String>,
+    schema: Option<String>,
+    table: Option<String>,
+    #[serde(default)]
+    columns: Vec<Wal2JsonColumn>,
+    /// The replica identity columns of an update's or a delete's old row
+    #[serde(default)]
+    identity: Vec<Wal2JsonColumn>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Wal2JsonColumn {
+    name: String,
+    value: serde_json::Value,
+}
+
+/// Converts wal2json change records into the [`CdcEvent`]s pgoutput messages are
+/// converted into, so that sinks handle changes the same with either plugin
+pub struct Wal2JsonConverter;
+
+impl Wal2JsonConverter {
+    /// Converts a change record. wal2json identifies tables by name, which `table_ids`
+    /// maps to the ids the schemas in `table_schemas` are keyed by.
+    pub fn try_from(
+        data: &[u8],
+        table_ids: &HashMap<TableName, TableId>,
+        table_schemas: &HashMap<TableId, TableSchema>,
+    ) -> Result<CdcEvent, CdcEventConversionError> {
+        let change: Wal2JsonChange = serde_json::from_slice(data)
+            .map_err(|e| CdcEventConversionError::InvalidWal2Json(e.to_string()))?;
+
+        match change.action.as_str() {
+            "B" => {
+                let final_lsn = Self::lsn(change.lsn.as_deref())?;
+                let timestamp = Self::timestamp(change.timestamp.as_deref())?;
+                Self::begin(final_lsn, timestamp, change.xid.unwrap_or(0))
+            }
+            "C" => {
+                let commit_lsn = Self::lsn(change.lsn.as_deref())?;
+                let end_lsn = match change.nextlsn.as_deref() {
+                    Some(nextlsn) => Self::lsn(Some(nextlsn))?,
+                    None => commit_lsn,
+                };
+                let timestamp = Self::timestamp(change.timestamp.as_deref())?;
+                Self::commit(commit_lsn, end_lsn, timestamp)
+            }
+            "I" | "U" | "D" => {
+                let table_name = TableName {
+                    schema: change.schema.clone().unwrap_or_default(),
+                    name: change.table.clone().unwrap_or_default(),
+                };
+                let table_id = *table_ids
+                    .get(&table_name)
+                    .ok_or(CdcEventConversionError::UnknownTable(table_name))?;
+                let table_schema = table_schemas
+                    .get(&table_id)
+                    .ok_or(CdcEventConversionError::MissingSchema(table_id))?;
+                Self::try_from_change(table_id, table_schema, change)
+            }
+            "T" | "M" => Err(CdcEventConversionError::MessageNotSupported),
+            _ => Err(CdcEventConversionError::UnknownReplicationMessage),
+        }
+    }
+
+    fn try_from_change(
+        table_id: TableId,
+        table_schema: &TableSchema,
+        change: Wal2JsonChange,
+    ) -> Result<CdcEvent, CdcEventConversionError> {
+        let column_schemas = &table_schema.column_schemas;
+        match change.action.as_str() {
+            "I" => {
+                let row = Self::try_from_columns(column_schemas, &change.columns, |column| {
+                    Err(CdcEventConversionError::MissingTupleData(
+                        column.name.clone(),
+                    ))
+                })?;
+                Ok(CdcEvent::Insert((table_id, row, None)))
+            }
+            "U" => {
+                let old_row = if change.identity.is_empty() {
+                    None
+                } else {
+                    Some(Self::try_from_columns(
+                        column_schemas,
+                        &change.identity,
+                        |_| Ok(Cell::Null),
+                    )?)
+                };
+                // Unchanged TOAST values are left out of updates
+                let new_row = Self::try_from_columns(column_schemas, &change.columns, |column| {
+                    Ok(TextFormatConverter::default_value(&column.typ))
+                })?;
+                Ok(CdcEvent::Update((table_id, old_row, new_row, None)))
+            }
+            _ => {
+                if change.identity.is_empty() {
+                    return Err(CdcEventConversionError::MissingTupleInDeleteBody);
+                }
+                let row =
+                    Self::try_from_columns(column_schemas, &change.identity, |_| Ok(Cell::Null))?;
+                Ok(CdcEvent::Delete((table_id, row, None)))
+            }
+        }
+    }
+
+    /// Builds a row from the columns of a change, calling `missing` for columns of the
+    /// schema the change doesn't have. Generated columns are never sent, they are null.
+    fn try_from_columns(
+        column_schemas: &[ColumnSchema],
+        columns: &[Wal2JsonColumn],
+        missing: impl Fn(&ColumnSchema) -> Result<Cell, CdcEventConversionError>,
+    ) -> Result<TableRow, CdcEventConversionError> {
+        let mut values = Vec::with_capacity(column_schemas.len());
+        for column_schema in column_schemas {
+            if column_schema.generated {
+                values.push(Cell::Null);
+                continue;
+            }
+            let value = match columns
+                .iter()
+                .find(|column| column.name == column_schema.name)
+            {
+                Some(column) => Self::try_from_value(column_schema, &column.value)?,
+                None => missing(column_schema)?,
+            };
+            values.push(value);
+        }
+        Ok(TableRow::new(values))
+    }
+
+    /// wal2json writes numbers and booleans as JSON numbers and booleans and all
+    /// other values in Postgres' text format as JSON strings
+    fn try_from_value(
+        column_schema: &ColumnSchema,
+        value: &serde_json::Value,
+    ) -> Result<Cell, CdcEventConversionError> {
+        let text = match value {
+            serde_json::Value::Null => return Ok(Cell::Null),
+            serde_json::Value::Bool(true) => "t".to_string(),
+            serde_json::Value::Bool(false) => "f".to_string(),
+            serde_json::Value::String(s) => s.clone(),
+            value => value.to_string(),
+        };
+        TextFormatConverter::try_from_str(&column_schema.typ, &text)
+            .map_err(CdcEventConversionError::FromBytes)
+    }
+
+    fn lsn(lsn: Option<&str>) -> Result<PgLsn, CdcEventConversionError> {
+        let lsn = lsn.ok_or(CdcEventConversionError::InvalidWal2Json(
+            "missing lsn, is include-lsn set?".to_string(),
+        ))?;
+        lsn.parse()
+            .map_err(|_| CdcEventConversionError::InvalidWal2Json(format!("invalid lsn {lsn}")))
+    }
+
+    /// Parses a commit timestamp, which wal2json writes in Postgres' text format, e.g.
+    /// `2024-05-01 12:00:00.123456+00`, into microseconds since Postgres' epoch of
+    /// 2000-01-01 like pgoutput sends it
+    fn timestamp(timestamp: Option<&str>) -> Result<i64, CdcEventConversionError> {
+        const POSTGRES_EPOCH_MICROS: i64 = 946_684_800_000_000;
+        let timestamp = timestamp.ok_or(CdcEventConversionError::InvalidWal2Json(
+            "missing timestamp, is include-timestamp set?".to_string(),
+        ))?;
+        let parsed =
+            DateTime::parse_from_str(timestamp, "%Y-%m-%d %H:%M:%S%.f%#z").map_err(|_| {
+                CdcEventConversionError::InvalidWal2Json(format!("invalid timestamp {timestamp}"))
+            })?;
+        Ok(parsed.timestamp_micros() - POSTGRES_EPOCH_MICROS)
+    }
+
+    /// The begin and commit bodies can't be built directly, so they are parsed from
+    /// the pgoutput messages they would have been sent in
+    fn begin(
+        final_lsn: PgLsn,
+        timestamp: i64,
+        xid: u32,
+    ) -> Result<CdcEvent, CdcEventConversionError> {
+        let mut buf = BytesMut::with_capacity(21);
+        buf.put_u8(b'B');
+        buf.put_u64(final_lsn.into());
+        buf.put_i64(timestamp);
+        buf.put_u32(xid);
+        match Self::parse(buf)? {
+            LogicalReplicationMessage::Begin(begin_body) => Ok(CdcEvent::Begin(begin_body)),
+            _ => Err(CdcEventConversionError::UnknownReplicationMessage),
+        }
+    }
+
+    fn commit(
+        commit_lsn: PgLsn,
+        end_lsn: PgLsn,
+        timestamp: i64,
+    ) -> Result<CdcEvent, CdcEventConversionError> {
+        let mut buf = BytesMut::with_capacity(26);
+        buf.put_u8(b'C');
+        buf.put_u8(0);
+        buf.put_u64(commit_lsn.into());
+        buf.put_u64(end_lsn.into());
+        buf.put_i64(timestamp);
+        match Self::parse(buf)? {
+            LogicalReplicationMessage::Commit(commit_body) => Ok(CdcEvent::Commit(commit_body)),
+            _ => Err(CdcEventConversionError::UnknownReplicationMessage),
+        }
+    }
+
+    fn parse(buf: BytesMut) -> Result<LogicalReplicationMessage, CdcEventConversionError> {
+        LogicalReplicationMessage::parse(&buf.freeze(), false)
+            .map_err(|e| CdcEventConversionError::InvalidWal2Json(e.to_string()))
+    }
+}
@@ -0,0 +1,206 @@
+use std::collections::{HashMap, VecDeque};
+
+use arrow::record_batch::RecordBatch;
+use futures::{ready, Stream};
+use pin_project_lite::pin_project;
+use thiserror::Error;
+use tokio_postgres::types::PgLsn;
+
+use core::pin::Pin;
+use core::task::{Context, Poll};
+
+use crate::{
+    conversions::{
+        arrow::{ArrowConversionError, RecordBatchBuilder, RowOp},
+        cdc_event::CdcEvent,
+        table_row::TableRow,
+    },
+    pipeline::{sources::postgres::CdcStreamError, streamed::StreamedTransactions},
+    table::{LookupKey, TableId, TableSchema},
+};
+
+#[derive(Debug, Error)]
+pub enum ArrowBatchStreamError {
+    #[error("cdc stream error: {0}")]
+    CdcStream(#[from] CdcStreamError),
+
+    #[error("arrow conversion error: {0}")]
+    ArrowConversion(#[from] ArrowConversionError),
+
+    #[error("missing schema for table {0}")]
+    MissingSchema(TableId),
+}
+
+/// An item of an [`ArrowBatchStream`]
+#[derive(Debug)]
+pub enum ArrowBatchEvent {
+    /// Rows of a table
+    Batch(TableId, RecordBatch),
+    /// A keepalive from Postgres, passed through so that the consumer can reply
+    /// with a status update, see [`CdcEvent::KeepAliveRequested`]
+    KeepAliveRequested { reply: bool, wal_end: PgLsn },
+}
+
+pin_project! {
+    /// Adapter stream which accumulates the rows of a cdc stream per table and
+    /// yields them as Arrow record batches. A table's batch is emitted when it
+    /// reaches max_batch_size rows and the batches of all tables are emitted on
+    /// every commit. Each batch carries the [`RowOp`] of its rows in an op column.
+    /// An update which changed the row's key is preceded by a delete of the old key.
+    /// The rows of streamed transactions are held back until they commit, see
+    /// [`StreamedTransactions`].
+    #[must_use = "streams do nothing unless polled"]
+    pub struct ArrowBatchStream<S: Stream<Item = Result<CdcEvent, CdcStreamError>>> {
+        #[pin]
+        stream: S,
+        table_schemas: HashMap<TableId, TableSchema>,
+        builders: HashMap<TableId, RecordBatchBuilder>,
+        streamed: StreamedTransactions,
+        ready_events: VecDeque<ArrowBatchEvent>,
+        max_batch_size: usize,
+        inner_stream_ended: bool,
+    }
+}
+
+impl<S: Stream<Item = Result<CdcEvent, CdcStreamError>>> ArrowBatchStream<S> {
+    pub fn new(
+        stream: S,
+        table_schemas: HashMap<TableId, TableSchema>,
+        max_batch_size: usize,
+    ) -> Self {
+        ArrowBatchStream {
+            stream,
+            table_schemas,
+            builders: HashMap::new(),
+            streamed: StreamedTransactions::new(),
+            ready_events: VecDeque::new(),
+            max_batch_size,
+            inner_stream_ended: false,
+        }
+    }
+
+    pub fn get_inner_mut(&mut self) -> &mut S {
+        &mut self.stream
+    }
+}
+
+fn push_row(
+    table_schemas: &HashMap<TableId, TableSchema>,
+    builders: &mut HashMap<TableId, RecordBatchBuilder>,
+    ready_events: &mut VecDeque<ArrowBatchEvent>,
+    max_batch_size: usize,
+    table_id: TableId,
+    op: RowOp,
+    row: TableRow,
+) -> Result<(), ArrowBatchStreamError> {
+    let table_schema = table_schemas
+        .get(&table_id)
+        .ok_or(ArrowBatchStreamError::MissingSchema(table_id))?;
+    let builder = builders
+        .entry(table_id)
+        .or_insert_with(|| RecordBatchBuilder::new(table_schema));
+    builder.push(op, row)?;
+    if builder.len() >= max_batch_size {
+        ready_events.push_back(ArrowBatchEvent::Batch(table_id, builder.finish()?));
+    }
+    Ok(())
+}
+
+fn flush_all(
+    builders: &mut HashMap<TableId, RecordBatchBuilder>,
+    ready_events: &mut VecDeque<ArrowBatchEvent>,
+) -> Result<(), ArrowBatchStreamError> {
+    for (table_id, builder) in builders.iter_mut() {
+        if !builder.is_empty() {
+            ready_events.push_back(ArrowBatchEvent::Batch(*table_id, builder.finish()?));
+        }
+    }
+    Ok(())
+}
+
+fn handle_event(
+    table_schemas: &HashMap<TableId, TableSchema>,
+    builders: &mut HashMap<TableId, RecordBatchBuilder>,
+    ready_events: &mut VecDeque<ArrowBatchEvent>,
+    max_batch_size: usize,
+    event: CdcEvent,
+) -> Result<(), ArrowBatchStreamError> {
+    let mut push = |table_id, op, row| {
+        push_row(
+            table_schemas,
+            builders,
+            ready_events,
+            max_batch_size,
+            table_id,
+            op,
+            row,
+        )
+    };
+    match event {
+        CdcEvent::Insert((table_id, row, _)) => push(table_id, RowOp::Insert, row)?,
+        CdcEvent::Update((table_id, old_row, new_row, _)) => {
+            let table_schema = table_schemas
+                .get(&table_id)
+                .ok_or(ArrowBatchStreamError::MissingSchema(table_id))?;
+            match (&table_schema.lookup_key, old_row) {
+                // An old row is only sent with a key when the key columns changed
+                (LookupKey::Key { .. }, Some(old_row)) => {
+                    push(table_id, RowOp::Delete, old_row)?;
+                    push(table_id, RowOp::Update, new_row)?;
+                }
+                (LookupKey::FullRow, Some(old_row)) => {
+                    push(table_id, RowOp::Delete, old_row)?;
+                    push(table_id, RowOp::Insert, new_row)?;
+                }
+                (_, None) => push(table_id, RowOp::Update, new_row)?,
+            }
+        }
+        CdcEvent::Delete((table_id, row, _)) => push(table_id, RowOp::Delete, row)?,
+        CdcEvent::Commit(_) | CdcEvent::StreamCommit(_) => flush_all(builders, ready_events)?,
+        CdcEvent::KeepAliveRequested { reply, wal_end } => {
+            ready_events.push_back(ArrowBatchEvent::KeepAliveRequested { reply, wal_end });
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+impl<S: Stream<Item = Result<CdcEvent, CdcStreamError>>> Stream for ArrowBatchStream<S> {
+    type Item = Result<ArrowBatchEvent, ArrowBatchStreamError>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let mut this = self.project();
+        loop {
+            if let Some(event) = this.ready_events.pop_front() {
+                return Poll::Ready(Some(Ok(event)));
+            }
+            if *this.inner_stream_ended {
+                return Poll::Ready(None);
+            }
+
+            let events = match ready!(this.stream.as_mut().poll_next(cx)) {
+                Some(Ok(event)) => this.streamed.hold(vec![event]),
+                Some(Err(e)) => return Poll::Ready(Some(Err(e.into()))),
+                None => {
+                    *this.inner_stream_ended = true;
+                    if let Err(e) = flush_all(this.builders, this.ready_events) {
+                        return Poll::Ready(Some(Err(e)));
+                    }
+                    continue;
+                }
+            };
+
+            for event in events {
+                if let Err(e) = handle_event(
+                    this.table_schemas,
+                    this.builders,
+                    this.ready_events,
+                    *this.max_batch_size,
+                    event,
+                ) {
+                    return Poll::Ready(Some(Err(e)));
+                }
+            }
+        }
+    }
+}
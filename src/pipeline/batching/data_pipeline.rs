@@ -1,43 +1,536 @@
-use std::{collections::HashSet, time::Instant};
+use std::{
+    collections::{HashMap, HashSet},
+    mem,
+    sync::Arc,
+    time::{Duration, Instant},
+};
 
-use futures::StreamExt;
-use tokio::pin;
+use futures::{channel::mpsc, SinkExt, StreamExt};
+use tokio::{
+    pin, select,
+    time::{sleep, sleep_until, timeout},
+};
 use tokio_postgres::types::PgLsn;
-use tracing::{debug, info};
+use tracing::{debug, info, instrument, warn};
 
 use crate::{
-    conversions::cdc_event::{CdcEvent, CdcEventConversionError},
+    conversions::{
+        cdc_event::{CdcEvent, CdcEventConversionError, UndecodableRow},
+        coercion::CoercionTable,
+        table_row::TableRow,
+        text::TextFormatConverter,
+    },
     pipeline::{
         batching::stream::BatchTimeoutStream,
+        circuit_breaker::SinkCircuitBreaker,
+        dead_letter::{DeadLetterBreaker, DeadLetterQueue},
+        pause::PauseHandle,
+        rate_limit::RateLimiter,
         sinks::BatchSink,
-        sources::{postgres::CdcStreamError, CommonSourceError, Source},
-        PipelineAction, PipelineError,
+        sources::{
+            backfill::{SnapshotBackfill, SnapshotBackfillError},
+            postgres::{CdcStreamError, PostgresSource, PostgresSourceError, TableCopyStreamError},
+            CommonSourceError, Source,
+        },
+        stats::{ApplyLagStats, BackfillStats, TableChangeStats},
+        stop::StopHandle,
+        transforms::{Transform, TransformError},
+        PipelineAction, PipelineError, PipelineResumptionState,
     },
-    table::TableId,
+    table::{TableId, TableName, TableSchema},
 };
+use tokio_postgres::types::Type;
 
 use super::BatchConfig;
 
+/// How often a paused pipeline checks whether it has been resumed
+const PAUSE_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// How often a paused pipeline sends status updates without a status update interval,
+/// well within Postgres' default `wal_sender_timeout` of a minute
+const PAUSED_STATUS_UPDATE_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Why [`BatchDataPipeline::consume_until`] returned
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConsumeOutcome {
+    /// All changes up to the WAL flush position at the start were passed to the sink
+    CaughtUp,
+    /// The deadline passed before the pipeline caught up
+    DeadlineReached,
+    /// The cdc stream ended before the pipeline caught up
+    StreamEnded,
+    /// The pipeline was stopped through its [`StopHandle`]
+    Stopped,
+}
+
+/// A table whose copy failed and was skipped, see
+/// [`BatchDataPipeline::set_skip_failed_tables`]
+#[derive(Debug, Clone)]
+pub struct TableCopyFailure {
+    pub table_id: TableId,
+    pub table_name: TableName,
+    pub error: String,
+}
+
+/// A column whose type the pipeline can't replicate faithfully, see
+/// [`BatchDataPipeline::unsupported_columns`]
+#[derive(Debug, Clone)]
+pub struct UnsupportedColumn {
+    pub table_name: TableName,
+    pub column: String,
+    /// The oid of the column's type
+    pub oid: u32,
+    pub reason: String,
+}
+
+/// When [`BatchDataPipeline::copy_cdc_events`] stops streaming
+#[derive(Debug, Clone, Copy)]
+struct ConsumeUntil {
+    deadline: Instant,
+    target_lsn: PgLsn,
+}
+
 pub struct BatchDataPipeline<Src: Source, Snk: BatchSink> {
     source: Src,
     sink: Snk,
     action: PipelineAction,
     batch_config: BatchConfig,
+    transform: Option<Box<dyn Transform>>,
+    coercions: CoercionTable,
+    /// Column types of the rows to coerce, i.e. before coercion
+    column_types: HashMap<TableId, Vec<Type>>,
+    dead_letter_queue: Option<(Box<dyn DeadLetterQueue>, DeadLetterBreaker)>,
+    status_update_interval: Option<Duration>,
+    idle_flush_timeout: Option<Duration>,
+    backfill_stats: Option<Arc<BackfillStats>>,
+    apply_lag_stats: Option<Arc<ApplyLagStats>>,
+    table_change_stats: Option<Arc<TableChangeStats>>,
+    keyset_batch_size: Option<u64>,
+    sink_breaker: Option<Arc<SinkCircuitBreaker>>,
+    rate_limiter: Option<Arc<RateLimiter>>,
+    pause_handle: PauseHandle,
+    stop_handle: StopHandle,
+    skip_failed_tables: bool,
+    table_copy_failures: Vec<TableCopyFailure>,
+    /// Tables whose changes aren't streamed as they weren't copied
+    skipped_tables: HashSet<TableId>,
+    drop_orphaned_slot: bool,
+    /// Backfill of tables added to the publication while streaming, with the
+    /// publication to load their schemas from
+    added_table_backfill: Option<(SnapshotBackfill, Option<String>)>,
 }
 
 impl<Src: Source, Snk: BatchSink> BatchDataPipeline<Src, Snk> {
     pub fn new(source: Src, sink: Snk, action: PipelineAction, batch_config: BatchConfig) -> Self {
+        let coercions = sink.coercions();
         BatchDataPipeline {
             source,
             sink,
             action,
             batch_config,
+            transform: None,
+            coercions,
+            column_types: HashMap::new(),
+            dead_letter_queue: None,
+            status_update_interval: None,
+            idle_flush_timeout: None,
+            backfill_stats: None,
+            apply_lag_stats: None,
+            table_change_stats: None,
+            keyset_batch_size: None,
+            sink_breaker: None,
+            rate_limiter: None,
+            pause_handle: PauseHandle::new(),
+            stop_handle: StopHandle::new(),
+            skip_failed_tables: false,
+            table_copy_failures: vec![],
+            skipped_tables: HashSet::new(),
+            drop_orphaned_slot: false,
+            added_table_backfill: None,
+        }
+    }
+
+    /// Sets a transform applied to all rows before they are written to the sink
+    pub fn set_transform(&mut self, transform: impl Transform + 'static) {
+        self.transform = Some(Box::new(transform));
+    }
+
+    /// Makes the pipeline send a status update to Postgres at least every `interval`
+    /// while cdc events arrive. Independent of this, a status update is sent whenever
+    /// the sink returns a new lsn and whenever Postgres requests one. Frequent updates
+    /// let Postgres free WAL sooner at the cost of more traffic.
+    pub fn set_status_update_interval(&mut self, interval: Duration) {
+        self.status_update_interval = Some(interval);
+    }
+
+    /// Makes the pipeline confirm the position from Postgres' keepalives once the sink
+    /// hasn't committed anything for `timeout`. Without this the confirmed lsn only
+    /// advances when the sink commits a transaction, so on a quiet system changes to
+    /// tables outside the publication make Postgres retain WAL indefinitely. The sink's
+    /// own lsn isn't advanced, so after a restart the stream starts from the confirmed
+    /// position rather than the sink's.
+    pub fn set_idle_flush_timeout(&mut self, timeout: Duration) {
+        self.idle_flush_timeout = Some(timeout);
+    }
+
+    /// Makes the pipeline record the time table copies spend reading, decoding and
+    /// writing rows in `stats`, which can be read while the pipeline runs
+    pub fn set_backfill_stats(&mut self, stats: Arc<BackfillStats>) {
+        self.backfill_stats = Some(stats);
+    }
+
+    /// Makes the pipeline record how long after their commit on the source
+    /// transactions are written by the sink in `stats`, which can be read while the
+    /// pipeline runs
+    pub fn set_apply_lag_stats(&mut self, stats: Arc<ApplyLagStats>) {
+        self.apply_lag_stats = Some(stats);
+    }
+
+    /// Makes the pipeline count the inserts, updates and deletes of each table it
+    /// streams in `stats`, which can be read while the pipeline runs
+    pub fn set_table_change_stats(&mut self, stats: Arc<TableChangeStats>) {
+        self.table_change_stats = Some(stats);
+    }
+
+    /// Makes the pipeline copy tables with a
+    /// [`LookupKey::Key`](crate::table::LookupKey::Key) in chunks of `batch_size` rows
+    /// ordered by the key, each selecting the rows after the last row of the previous
+    /// chunk, instead of with a single `COPY`. The sink stores the
+    /// key of the last row written with each chunk, see
+    /// [`BatchSink::write_table_rows_with_cursor`], so a copy interrupted by a restart
+    /// resumes after it rather than from the first row. This is slower than a `COPY`
+    /// but doesn't lose the progress of large tables to a dropped connection.
+    ///
+    /// A resumed copy reads the rest of the table outside of the slot's snapshot, so
+    /// it can see rows changed after the slot was created, whose changes are then
+    /// streamed once the copy completes. The sink gets these as the table already
+    /// has them, e.g. inserts of rows it copied, and must apply cdc events
+    /// idempotently for such a table to end up consistent.
+    pub fn set_keyset_backfill(&mut self, batch_size: u64) {
+        self.keyset_batch_size = Some(batch_size);
+    }
+
+    /// Makes the pipeline retry failed writes of rows and cdc events as long as
+    /// `breaker` allows, failing with [`PipelineError::SinkCircuitOpen`] once it trips.
+    /// Rows and events are copied before each write so that they can be written again.
+    /// Writes are only retried if the sink can take the same rows or events again,
+    /// see [`BatchSink::supports_retries`], other sinks fail on the first error.
+    pub fn set_sink_circuit_breaker(&mut self, breaker: Arc<SinkCircuitBreaker>) {
+        self.sink_breaker = Some(breaker);
+    }
+
+    /// Makes the pipeline wait for `limiter`'s tokens before passing each batch of
+    /// cdc events to the sink. Table copies aren't limited.
+    pub fn set_rate_limiter(&mut self, limiter: Arc<RateLimiter>) {
+        self.rate_limiter = Some(limiter);
+    }
+
+    /// Makes a table whose copy fails on the source's side, e.g. because it was
+    /// dropped or can't be read, be skipped instead of failing the pipeline. The
+    /// other tables are still copied and changes are only streamed for the tables
+    /// copied. Skipped tables aren't marked as copied in the sink, so a pipeline
+    /// which copies tables copies them again when it's restarted. A pipeline which
+    /// only streams skips the changes of all tables the sink hasn't marked as
+    /// copied, see [`PipelineResumptionState::copied_tables`], so that the tables
+    /// skipped by an earlier run stay skipped. Sink errors still fail the pipeline.
+    pub fn set_skip_failed_tables(&mut self, skip: bool) {
+        self.skip_failed_tables = skip;
+    }
+
+    /// The tables skipped since the pipeline was created as their copy failed
+    pub fn table_copy_failures(&self) -> &[TableCopyFailure] {
+        &self.table_copy_failures
+    }
+
+    /// Lists the columns of the source's tables whose type isn't supported, without
+    /// copying or streaming anything, so that they can be fixed or excluded before
+    /// the pipeline starts. A column is listed if its values can't be decoded, or
+    /// with the `unknown_types_to_bytes` feature would be passed through as text, or
+    /// if the sink's coercion of its type isn't supported. Columns are listed in
+    /// table name and column order.
+    pub fn unsupported_columns(&self) -> Vec<UnsupportedColumn> {
+        let mut table_schemas: Vec<&TableSchema> =
+            self.source.get_table_schemas().values().collect();
+        table_schemas.sort_by(|a, b| {
+            let a = (&a.table_name.schema, &a.table_name.name);
+            a.cmp(&(&b.table_name.schema, &b.table_name.name))
+        });
+        let mut unsupported = vec![];
+        for table_schema in table_schemas {
+            for column_schema in &table_schema.column_schemas {
+                let typ = &column_schema.typ;
+                let reason = if !TextFormatConverter::is_supported(typ) {
+                    if cfg!(feature = "unknown_types_to_bytes") {
+                        format!(
+                            "type {} is not supported, values are passed as text",
+                            typ.name()
+                        )
+                    } else {
+                        format!("type {} is not supported", typ.name())
+                    }
+                } else if let Err(e) = self.coercions.check_type(typ) {
+                    format!("sink can't store type {}: {e}", typ.name())
+                } else {
+                    continue;
+                };
+                unsupported.push(UnsupportedColumn {
+                    table_name: table_schema.table_name.clone(),
+                    column: column_schema.name.clone(),
+                    oid: typ.oid(),
+                    reason,
+                });
+            }
+        }
+        unsupported
+    }
+
+    /// Makes the pipeline drop the slot it streamed from before if the source's slot
+    /// name changed since, e.g. after a config edit, as the old slot would retain WAL
+    /// forever. Without this the old slot is only warned about. Needs a sink which
+    /// keeps the slot name, see [`BatchSink::write_slot_name`].
+    pub fn set_drop_orphaned_slot(&mut self, drop: bool) {
+        self.drop_orphaned_slot = drop;
+    }
+
+    /// Returns a handle to pause and resume the cdc stream while the pipeline runs
+    pub fn pause_handle(&self) -> PauseHandle {
+        self.pause_handle.clone()
+    }
+
+    /// Returns a handle to stop the running pipeline from another task, e.g. on
+    /// shutdown. Clones of the handle stop the same pipeline, see [`StopHandle`].
+    pub fn stop_handle(&self) -> StopHandle {
+        self.stop_handle.clone()
+    }
+
+    /// Makes cdc events which fail to decode go to `dead_letter_queue` instead of
+    /// failing the pipeline. The pipeline still fails if more than `max_dead_letters`
+    /// events are dead lettered within `window`.
+    pub fn set_dead_letter_queue(
+        &mut self,
+        dead_letter_queue: impl DeadLetterQueue + 'static,
+        max_dead_letters: usize,
+        window: Duration,
+    ) {
+        self.dead_letter_queue = Some((
+            Box::new(dead_letter_queue),
+            DeadLetterBreaker::new(max_dead_letters, window),
+        ));
+    }
+
+    async fn write_dead_letter(
+        &mut self,
+        row: &UndecodableRow,
+    ) -> Result<(), PipelineError<Src::Error, Snk::Error>> {
+        let Some((dead_letter_queue, breaker)) = self.dead_letter_queue.as_mut() else {
+            return Ok(());
+        };
+        warn!(
+            "writing undecodable event to the dead letter queue: {}",
+            row.error
+        );
+        dead_letter_queue.write_dead_letter(row).await?;
+        if !breaker.record() {
+            return Err(PipelineError::DeadLetterRateExceeded(
+                breaker.max_dead_letters(),
+                breaker.window(),
+            ));
+        }
+        Ok(())
+    }
+
+    /// Applies the transform and the sink's coercions to a row
+    fn prepare_row(
+        &self,
+        table_id: TableId,
+        row: TableRow,
+    ) -> Result<TableRow, PipelineError<Src::Error, Snk::Error>> {
+        let row = match &self.transform {
+            Some(transform) => transform.transform_row(table_id, row)?,
+            None => row,
+        };
+        match self.column_types.get(&table_id) {
+            Some(column_types) => Ok(self.coercions.coerce_row(column_types, row)?),
+            None => Ok(row),
+        }
+    }
+
+    fn prepare_cdc_event(
+        &self,
+        event: CdcEvent,
+    ) -> Result<CdcEvent, PipelineError<Src::Error, Snk::Error>> {
+        let event = match event {
+            CdcEvent::Insert((table_id, row, xid)) => {
+                CdcEvent::Insert((table_id, self.prepare_row(table_id, row)?, xid))
+            }
+            CdcEvent::Update((table_id, old_row, new_row, xid)) => CdcEvent::Update((
+                table_id,
+                old_row
+                    .map(|row| self.prepare_row(table_id, row))
+                    .transpose()?,
+                self.prepare_row(table_id, new_row)?,
+                xid,
+            )),
+            CdcEvent::Delete((table_id, row, xid)) => {
+                CdcEvent::Delete((table_id, self.prepare_row(table_id, row)?, xid))
+            }
+            event => event,
+        };
+        Ok(event)
+    }
+
+    /// Whether `event` changes a table which was skipped as its copy failed
+    fn is_of_skipped_table(&self, event: &CdcEvent) -> bool {
+        let table_id = match event {
+            CdcEvent::Insert((table_id, _, _))
+            | CdcEvent::Update((table_id, _, _, _))
+            | CdcEvent::Delete((table_id, _, _)) => table_id,
+            _ => return false,
+        };
+        self.skipped_tables.contains(table_id)
+    }
+
+    /// Skips the changes of the tables not in `copied_tables` if failed tables are
+    /// skipped, for pipelines which stream without copying tables first
+    fn skip_uncopied_tables(&mut self, copied_tables: &HashSet<TableId>) {
+        if !self.skip_failed_tables {
+            return;
+        }
+        for table_schema in self.source.get_table_schemas().values() {
+            if !copied_tables.contains(&table_schema.table_id)
+                && self.skipped_tables.insert(table_schema.table_id)
+            {
+                warn!(
+                    "skipping changes of table {} as it wasn't copied",
+                    table_schema.table_name
+                );
+            }
+        }
+    }
+
+    fn retries_writes(&self) -> bool {
+        self.sink_breaker.is_some() && self.sink.supports_retries()
+    }
+
+    /// Records a failed sink write with the circuit breaker and waits before the write
+    /// is retried, or returns the error to fail the pipeline with
+    async fn sink_write_failed(
+        &self,
+        error: Snk::Error,
+    ) -> Result<(), PipelineError<Src::Error, Snk::Error>> {
+        let Some(breaker) = self.sink_breaker.as_ref().filter(|_| self.retries_writes()) else {
+            return Err(PipelineError::Sink(error));
+        };
+        if !breaker.record_failure(&error) {
+            return Err(PipelineError::SinkCircuitOpen(
+                breaker.max_failures(),
+                breaker.window(),
+                error,
+            ));
+        }
+        warn!(
+            "sink write failed, retrying in {:?}: {error}",
+            breaker.retry_delay()
+        );
+        sleep(breaker.retry_delay()).await;
+        Ok(())
+    }
+
+    /// Writes table rows to the sink, along with the key of the last row of a keyset
+    /// copy, retrying failed writes if there is a circuit breaker
+    async fn write_table_rows(
+        &mut self,
+        mut rows: Vec<TableRow>,
+        table_id: TableId,
+        cursor: Option<Vec<String>>,
+    ) -> Result<(), PipelineError<Src::Error, Snk::Error>> {
+        loop {
+            let attempt = if self.retries_writes() {
+                rows.clone()
+            } else {
+                mem::take(&mut rows)
+            };
+            let result = match &cursor {
+                Some(cursor) => {
+                    self.sink
+                        .write_table_rows_with_cursor(attempt, table_id, cursor.clone())
+                        .await
+                }
+                None => self.sink.write_table_rows(attempt, table_id).await,
+            };
+            match result {
+                Ok(()) => break,
+                Err(e) => self.sink_write_failed(e).await?,
+            }
         }
+        if let Some(breaker) = &self.sink_breaker {
+            breaker.record_success();
+        }
+        Ok(())
+    }
+
+    /// Writes cdc events to the sink, retrying failed writes if there is a circuit
+    /// breaker
+    async fn write_cdc_events(
+        &mut self,
+        mut events: Vec<CdcEvent>,
+    ) -> Result<PgLsn, PipelineError<Src::Error, Snk::Error>> {
+        loop {
+            let attempt = if self.retries_writes() {
+                events
+                    .iter()
+                    .map(CdcEvent::try_clone)
+                    .collect::<Result<Vec<_>, _>>()
+                    .map_err(|e| CommonSourceError::CdcStream(e.into()))?
+            } else {
+                mem::take(&mut events)
+            };
+            match self.sink.write_cdc_events(attempt).await {
+                Ok(lsn) => {
+                    if let Some(breaker) = &self.sink_breaker {
+                        breaker.record_success();
+                    }
+                    return Ok(lsn);
+                }
+                Err(e) => self.sink_write_failed(e).await?,
+            }
+        }
+    }
+
+    /// Applies the transform and coercions to a table's schema, remembering the
+    /// column types the table's rows are coerced from
+    fn prepare_table_schema(
+        &mut self,
+        table_id: TableId,
+        table_schema: TableSchema,
+    ) -> Result<TableSchema, TransformError> {
+        let mut table_schema = match self.transform.as_mut() {
+            Some(transform) => transform.transform_schema(table_schema)?,
+            None => table_schema,
+        };
+        if !self.coercions.is_empty() {
+            let column_types = table_schema
+                .column_schemas
+                .iter()
+                .map(|c| c.typ.clone())
+                .collect();
+            self.column_types.insert(table_id, column_types);
+            self.coercions
+                .coerce_column_schemas(&mut table_schema.column_schemas);
+        }
+        Ok(table_schema)
     }
 
     async fn copy_table_schemas(&mut self) -> Result<(), PipelineError<Src::Error, Snk::Error>> {
-        let table_schemas = self.source.get_table_schemas();
-        let table_schemas = table_schemas.clone();
+        let table_schemas: HashMap<TableId, TableSchema> = self
+            .source
+            .get_table_schemas()
+            .clone()
+            .into_iter()
+            .map(|(table_id, table_schema)| {
+                Ok((table_id, self.prepare_table_schema(table_id, table_schema)?))
+            })
+            .collect::<Result<_, TransformError>>()?;
 
         if !table_schemas.is_empty() {
             self.sink
@@ -52,75 +545,461 @@ impl<Src: Source, Snk: BatchSink> BatchDataPipeline<Src, Snk> {
     async fn copy_tables(
         &mut self,
         copied_tables: &HashSet<TableId>,
+        backfill_cursors: &HashMap<TableId, Vec<String>>,
     ) -> Result<(), PipelineError<Src::Error, Snk::Error>> {
         let start = Instant::now();
-        let table_schemas = self.source.get_table_schemas();
 
-        let mut keys: Vec<u32> = table_schemas.keys().copied().collect();
+        let mut keys: Vec<u32> = self.source.get_table_schemas().keys().copied().collect();
         keys.sort();
 
         for key in keys {
-            let table_schema = table_schemas.get(&key).expect("failed to get table key");
+            if self.stop_handle.is_stopped() {
+                info!("pipeline stopped during table copies");
+                return Ok(());
+            }
+            let table_schema = self
+                .source
+                .get_table_schemas()
+                .get(&key)
+                .expect("failed to get table key")
+                .clone();
             if copied_tables.contains(&table_schema.table_id) {
                 info!("table {} already copied.", table_schema.table_name);
                 continue;
             }
 
+            if !self.skip_failed_tables {
+                self.copy_table(&table_schema, backfill_cursors).await?;
+                continue;
+            }
+
+            self.source
+                .begin_table_copy()
+                .await
+                .map_err(PipelineError::Source)?;
+            match self.copy_table(&table_schema, backfill_cursors).await {
+                Ok(()) => {
+                    self.source
+                        .end_table_copy(false)
+                        .await
+                        .map_err(PipelineError::Source)?;
+                    self.skipped_tables.remove(&key);
+                }
+                Err(e @ (PipelineError::Source(_) | PipelineError::CommonSource(_))) => {
+                    warn!(
+                        "skipping table {} as its copy failed: {e}",
+                        table_schema.table_name
+                    );
+                    self.source
+                        .end_table_copy(true)
+                        .await
+                        .map_err(PipelineError::Source)?;
+                    self.skipped_tables.insert(key);
+                    self.table_copy_failures.push(TableCopyFailure {
+                        table_id: key,
+                        table_name: table_schema.table_name,
+                        error: e.to_string(),
+                    });
+                }
+                Err(e) => return Err(e),
+            }
+        }
+        self.source
+            .commit_transaction()
+            .await
+            .map_err(PipelineError::Source)?;
+
+        let end = Instant::now();
+        let seconds = (end - start).as_secs();
+        debug!("took {seconds} seconds to copy tables");
+        if let Some(stats) = &self.backfill_stats {
+            info!("table copy stats: {stats}");
+        }
+        if !self.table_copy_failures.is_empty() {
+            warn!(
+                "skipped {} tables whose copy failed",
+                self.table_copy_failures.len()
+            );
+        }
+
+        Ok(())
+    }
+
+    /// Copies a table to the sink and marks it as copied
+    async fn copy_table(
+        &mut self,
+        table_schema: &TableSchema,
+        backfill_cursors: &HashMap<TableId, Vec<String>>,
+    ) -> Result<(), PipelineError<Src::Error, Snk::Error>> {
+        let table_id = table_schema.table_id;
+        let key_indexes = table_schema
+            .lookup_key
+            .column_indexes(&table_schema.column_schemas);
+        if let (Some(batch_size), Some(key_indexes)) = (self.keyset_batch_size, key_indexes) {
+            let cursor = backfill_cursors.get(&table_id).cloned();
+            self.copy_table_by_key(table_schema, &key_indexes, cursor, batch_size)
+                .await?;
+            return self
+                .sink
+                .table_copied(table_id)
+                .await
+                .map_err(PipelineError::Sink);
+        }
+
+        self.sink
+            .truncate_table(table_id)
+            .await
+            .map_err(PipelineError::Sink)?;
+
+        let mut table_rows = self
+            .source
+            .get_table_copy_stream(&table_schema.table_name, &table_schema.column_schemas)
+            .await
+            .map_err(PipelineError::Source)?;
+        let stats = self.backfill_stats.clone();
+        if let Some(stats) = &stats {
+            table_rows.set_stats(stats.clone());
+        }
+
+        let batch_timeout_stream = BatchTimeoutStream::new(table_rows, self.batch_config.clone());
+
+        pin!(batch_timeout_stream);
+
+        loop {
+            // The stream decodes rows while it is polled, so the decode time
+            // recorded meanwhile is subtracted from the time spent waiting
+            let read_start = Instant::now();
+            let decode_time_before = stats.as_ref().map(|stats| stats.decode_time());
+            let Some(batch) = batch_timeout_stream.next().await else {
+                break;
+            };
+            if let (Some(stats), Some(decode_time_before)) = (&stats, decode_time_before) {
+                let decode_time = stats.decode_time() - decode_time_before;
+                stats.add_read_time(read_start.elapsed().saturating_sub(decode_time));
+            }
+
+            info!("got {} table copy events in a batch", batch.len());
+            let prepare_start = Instant::now();
+            //TODO: Avoid a vec copy
+            let mut rows = Vec::with_capacity(batch.len());
+            for row in batch {
+                let row = row.map_err(CommonSourceError::TableCopyStream)?;
+                rows.push(self.prepare_row(table_id, row)?);
+            }
+            if let Some(stats) = &stats {
+                stats.add_decode_time(prepare_start.elapsed());
+            }
+
+            let write_start = Instant::now();
+            self.write_table_rows(rows, table_id, None).await?;
+            if let Some(stats) = &stats {
+                stats.add_write_time(write_start.elapsed());
+            }
+        }
+
+        self.sink
+            .table_copied(table_id)
+            .await
+            .map_err(PipelineError::Sink)
+    }
+
+    /// Copies a table in chunks ordered by the key columns at `key_indexes`, starting
+    /// after `cursor` if a previous copy was interrupted
+    async fn copy_table_by_key(
+        &mut self,
+        table_schema: &TableSchema,
+        key_indexes: &[usize],
+        mut cursor: Option<Vec<String>>,
+        batch_size: u64,
+    ) -> Result<(), PipelineError<Src::Error, Snk::Error>> {
+        let table_id = table_schema.table_id;
+        if cursor.is_some() {
+            info!(
+                "resuming copy of table {} after the last row copied",
+                table_schema.table_name
+            );
+        } else {
             self.sink
-                .truncate_table(table_schema.table_id)
+                .truncate_table(table_id)
                 .await
                 .map_err(PipelineError::Sink)?;
+        }
 
-            let table_rows = self
+        let key_columns: Vec<String> = key_indexes
+            .iter()
+            .map(|&i| table_schema.column_schemas[i].name.clone())
+            .collect();
+        let stats = self.backfill_stats.clone();
+        loop {
+            let read_start = Instant::now();
+            let mut table_rows = self
                 .source
-                .get_table_copy_stream(&table_schema.table_name, &table_schema.column_schemas)
+                .get_table_keyset_copy_stream(
+                    &table_schema.table_name,
+                    &table_schema.column_schemas,
+                    &key_columns,
+                    cursor.as_deref(),
+                    batch_size,
+                )
                 .await
                 .map_err(PipelineError::Source)?;
+            let decode_time_before = stats.as_ref().map(|stats| {
+                table_rows.set_stats(stats.clone());
+                stats.decode_time()
+            });
+            let batch: Vec<_> = table_rows.collect().await;
+            if let (Some(stats), Some(decode_time_before)) = (&stats, decode_time_before) {
+                let decode_time = stats.decode_time() - decode_time_before;
+                stats.add_read_time(read_start.elapsed().saturating_sub(decode_time));
+            }
+            if batch.is_empty() {
+                break;
+            }
+            let chunk_len = batch.len() as u64;
 
-            let batch_timeout_stream =
-                BatchTimeoutStream::new(table_rows, self.batch_config.clone());
+            let prepare_start = Instant::now();
+            let rows = batch
+                .into_iter()
+                .collect::<Result<Vec<_>, _>>()
+                .map_err(CommonSourceError::TableCopyStream)?;
+            // Key columns can't be null, so every value has a text format
+            let last_row = &rows[rows.len() - 1];
+            let last_key: Vec<String> = key_indexes
+                .iter()
+                .map(|&i| TextFormatConverter::to_text(&last_row.values[i]).unwrap_or_default())
+                .collect();
+            let rows = rows
+                .into_iter()
+                .map(|row| self.prepare_row(table_id, row))
+                .collect::<Result<Vec<_>, _>>()?;
+            if let Some(stats) = &stats {
+                stats.add_decode_time(prepare_start.elapsed());
+            }
 
-            pin!(batch_timeout_stream);
+            let write_start = Instant::now();
+            self.write_table_rows(rows, table_id, Some(last_key.clone()))
+                .await?;
+            if let Some(stats) = &stats {
+                stats.add_write_time(write_start.elapsed());
+            }
 
-            while let Some(batch) = batch_timeout_stream.next().await {
-                info!("got {} table copy events in a batch", batch.len());
-                //TODO: Avoid a vec copy
-                let mut rows = Vec::with_capacity(batch.len());
-                for row in batch {
-                    rows.push(row.map_err(CommonSourceError::TableCopyStream)?);
+            if chunk_len < batch_size {
+                break;
+            }
+            cursor = Some(last_key);
+        }
+        Ok(())
+    }
+
+    /// Copies the tables `table_ids`, whose changes the cdc stream couldn't decode as
+    /// they were added to the publication after the stream started, to the sink with
+    /// the [`BatchDataPipeline::set_added_table_backfill`] backfill. Returns their
+    /// schemas along with the lsn from which on the stream has to apply their changes.
+    async fn backfill_added_tables(
+        &mut self,
+        table_ids: &HashSet<TableId>,
+    ) -> Result<(Vec<TableSchema>, PgLsn), PipelineError<Src::Error, Snk::Error>> {
+        let Some((mut backfill, publication)) = self.added_table_backfill.take() else {
+            return Ok((vec![], PgLsn::from(0)));
+        };
+        let result = self
+            .backfill_added_tables_with(&mut backfill, publication.as_deref(), table_ids)
+            .await;
+        self.added_table_backfill = Some((backfill, publication));
+        result
+    }
+
+    async fn backfill_added_tables_with(
+        &mut self,
+        backfill: &mut SnapshotBackfill,
+        publication: Option<&str>,
+        table_ids: &HashSet<TableId>,
+    ) -> Result<(Vec<TableSchema>, PgLsn), PipelineError<Src::Error, Snk::Error>> {
+        let mut table_ids: Vec<TableId> = table_ids.iter().copied().collect();
+        table_ids.sort();
+        let mut table_schemas = Vec::with_capacity(table_ids.len());
+        for table_id in table_ids {
+            let table_schema = backfill
+                .get_table_schema(table_id, publication)
+                .await
+                .map_err(|e| PipelineError::AddedTableBackfill(e.into()))?;
+            info!(
+                "backfilling table {} added to the publication",
+                table_schema.table_name
+            );
+            table_schemas.push(table_schema);
+        }
+
+        let prepared_schemas = table_schemas
+            .iter()
+            .map(|table_schema| {
+                let table_id = table_schema.table_id;
+                Ok((
+                    table_id,
+                    self.prepare_table_schema(table_id, table_schema.clone())?,
+                ))
+            })
+            .collect::<Result<_, TransformError>>()?;
+        // The sink keeps the schemas of the tables it already streams
+        self.sink
+            .write_table_schemas(prepared_schemas)
+            .await
+            .map_err(PipelineError::Sink)?;
+        for table_schema in &table_schemas {
+            self.sink
+                .truncate_table(table_schema.table_id)
+                .await
+                .map_err(PipelineError::Sink)?;
+        }
+
+        let slot_name = format!(
+            "{}_added_tables",
+            self.source.slot_name().unwrap_or("pg_replicate")
+        );
+        // The copies send their rows here as the sink can't be borrowed by them
+        let (sender, mut receiver) = mpsc::channel::<(TableId, Vec<TableRow>)>(1);
+        let max_batch_size = self.batch_config.max_batch_size;
+        let copies = backfill.backfill_added_tables(
+            &slot_name,
+            &table_schemas,
+            move |table_schema, stream| {
+                let mut sender = sender.clone();
+                async move {
+                    let chunks = stream.chunks(max_batch_size);
+                    pin!(chunks);
+                    while let Some(chunk) = chunks.next().await {
+                        let rows = chunk.into_iter().collect::<Result<Vec<_>, _>>()?;
+                        // The receiver is only gone if a write failed, which fails the
+                        // backfill anyway
+                        if sender.send((table_schema.table_id, rows)).await.is_err() {
+                            break;
+                        }
+                    }
+                    Ok::<(), TableCopyStreamError>(())
                 }
-                self.sink
-                    .write_table_rows(rows, table_schema.table_id)
-                    .await
-                    .map_err(PipelineError::Sink)?;
+            },
+        );
+        let writes = async {
+            while let Some((table_id, rows)) = receiver.next().await {
+                let rows = rows
+                    .into_iter()
+                    .map(|row| self.prepare_row(table_id, row))
+                    .collect::<Result<Vec<_>, _>>()?;
+                self.write_table_rows(rows, table_id, None).await?;
             }
+            Ok::<(), PipelineError<Src::Error, Snk::Error>>(())
+        };
+        let (copied, written) = futures::join!(copies, writes);
+        written?;
+        let start_lsn = copied.map_err(PipelineError::AddedTableBackfill)?;
 
+        for table_schema in &table_schemas {
             self.sink
                 .table_copied(table_schema.table_id)
                 .await
                 .map_err(PipelineError::Sink)?;
         }
+        Ok((table_schemas, start_lsn))
+    }
+
+    /// Fails if the source's slot differs from the slot the sink recorded the pipeline
+    /// streamed from before while the sink holds tables or changes from the previous
+    /// slot, as a new slot starts at its own consistent point and the changes between
+    /// the sink's position and it would be lost. The slot the source created for the
+    /// new name is dropped again, and the previous slot is kept so that the pipeline
+    /// can be pointed back at it. Runs before anything is copied or dropped.
+    async fn check_slot_name(
+        &mut self,
+        resumption_state: &PipelineResumptionState,
+    ) -> Result<(), PipelineError<Src::Error, Snk::Error>> {
+        let (Some(slot_name), Some(previous)) = (
+            self.source.slot_name(),
+            resumption_state.slot_name.as_deref(),
+        ) else {
+            return Ok(());
+        };
+        if previous == slot_name {
+            return Ok(());
+        }
+        // The sink's last lsn is 0 until it has committed a transaction
+        let has_previous_data = u64::from(resumption_state.last_lsn) > 1
+            || !resumption_state.copied_tables.is_empty()
+            || !resumption_state.backfill_cursors.is_empty();
+        if !has_previous_data {
+            return Ok(());
+        }
+        let error = PipelineError::SlotNameChanged(previous.to_string(), slot_name.to_string());
         self.source
-            .commit_transaction()
+            .drop_created_slot()
             .await
             .map_err(PipelineError::Source)?;
+        Err(error)
+    }
 
-        let end = Instant::now();
-        let seconds = (end - start).as_secs();
-        debug!("took {seconds} seconds to copy tables");
-
-        Ok(())
+    /// Records the source's slot in the sink, warning about or dropping the slot the
+    /// pipeline streamed from before if it differs, see
+    /// [`BatchDataPipeline::check_slot_name`]
+    async fn record_slot_name(
+        &mut self,
+        previous: Option<&str>,
+    ) -> Result<(), PipelineError<Src::Error, Snk::Error>> {
+        let Some(slot_name) = self.source.slot_name().map(str::to_string) else {
+            return Ok(());
+        };
+        if previous == Some(slot_name.as_str()) {
+            return Ok(());
+        }
+        if let Some(previous) = previous {
+            if self.drop_orphaned_slot {
+                warn!(
+                    "slot name changed from {previous} to {slot_name}, dropping the previous slot"
+                );
+                // Streaming ends the snapshot's transaction anyway, and the slot is
+                // dropped outside of it
+                self.source
+                    .commit_transaction()
+                    .await
+                    .map_err(PipelineError::Source)?;
+                self.source
+                    .drop_orphaned_slot(previous)
+                    .await
+                    .map_err(PipelineError::Source)?;
+            } else {
+                warn!(
+                    "slot name changed from {previous} to {slot_name}, slot {previous} retains WAL until it's dropped"
+                );
+            }
+        }
+        self.sink
+            .write_slot_name(&slot_name)
+            .await
+            .map_err(PipelineError::Sink)
     }
 
+    /// Streams cdc events to the sink from `last_lsn` on, until the stream ends or,
+    /// with `until`, until the pipeline caught up or the deadline passed
+    #[instrument(skip(self), fields(last_lsn = %last_lsn))]
     async fn copy_cdc_events(
         &mut self,
         last_lsn: PgLsn,
-    ) -> Result<(), PipelineError<Src::Error, Snk::Error>> {
+        until: Option<ConsumeUntil>,
+    ) -> Result<ConsumeOutcome, PipelineError<Src::Error, Snk::Error>> {
         self.source
             .commit_transaction()
             .await
             .map_err(PipelineError::Source)?;
 
+        if until.is_some_and(|until| until.target_lsn <= last_lsn) {
+            info!("already caught up at lsn {last_lsn}");
+            return Ok(ConsumeOutcome::CaughtUp);
+        }
+
+        let mut last_sent_lsn = last_lsn;
+        let mut last_sink_lsn = last_lsn;
+        let mut last_status_update = Instant::now();
+        let mut last_sink_commit = Instant::now();
+        // The position up to which all changes have been passed to the sink
+        let mut passed_lsn = last_lsn;
         let mut last_lsn: u64 = last_lsn.into();
         last_lsn += 1;
         let cdc_events = self
@@ -135,47 +1014,210 @@ impl<Src: Source, Snk: BatchSink> BatchDataPipeline<Src, Snk> {
 
         pin!(batch_timeout_stream);
 
-        while let Some(batch) = batch_timeout_stream.next().await {
+        let outcome = loop {
+            if self.pause_handle.is_paused() {
+                info!("cdc stream paused at lsn {last_sent_lsn}");
+                let interval = self
+                    .status_update_interval
+                    .unwrap_or(PAUSED_STATUS_UPDATE_INTERVAL);
+                while self.pause_handle.is_paused() && !self.stop_handle.is_stopped() {
+                    if until.is_some_and(|until| Instant::now() >= until.deadline) {
+                        break;
+                    }
+                    select! {
+                        _ = sleep(PAUSE_POLL_INTERVAL) => {}
+                        _ = self.stop_handle.stopped() => {}
+                    }
+                    if last_status_update.elapsed() >= interval {
+                        let inner = batch_timeout_stream.as_mut().get_pinned_inner_mut();
+                        inner
+                            .as_mut()
+                            .send_status_update(last_sent_lsn)
+                            .await
+                            .map_err(CommonSourceError::StatusUpdate)?;
+                        last_status_update = Instant::now();
+                    }
+                }
+                info!("cdc stream resumed");
+            }
+
+            if self.stop_handle.is_stopped() {
+                break ConsumeOutcome::Stopped;
+            }
+
+            let next = async {
+                match until {
+                    Some(until) => {
+                        let remaining = until.deadline.saturating_duration_since(Instant::now());
+                        timeout(remaining, batch_timeout_stream.next()).await
+                    }
+                    None => Ok(batch_timeout_stream.next().await),
+                }
+            };
+            // Events of a batch still being filled are dropped when stopping, they
+            // are streamed again by the next run as they aren't confirmed
+            let next = select! {
+                next = next => match next {
+                    Ok(next) => next,
+                    Err(_) => break ConsumeOutcome::DeadlineReached,
+                },
+                _ = self.stop_handle.stopped() => break ConsumeOutcome::Stopped,
+            };
+            let Some(batch) = next else {
+                break ConsumeOutcome::StreamEnded;
+            };
             info!("got {} cdc events in a batch", batch.len());
             let mut send_status_update = false;
+            let mut keep_alive_lsn = None;
             let mut events = Vec::with_capacity(batch.len());
+            // Tables added to the publication since the stream started
+            let mut added_tables = HashSet::new();
             for event in batch {
                 if let Err(CdcStreamError::CdcEventConversion(
-                    CdcEventConversionError::MissingSchema(_),
+                    CdcEventConversionError::MissingSchema(table_id),
                 )) = event
                 {
+                    if self.added_table_backfill.is_some() {
+                        added_tables.insert(table_id);
+                    }
                     continue;
                 }
+                if let Err(CdcStreamError::CdcEventConversion(
+                    CdcEventConversionError::UndecodableRow(row),
+                )) = &event
+                {
+                    if self.dead_letter_queue.is_some() {
+                        self.write_dead_letter(row).await?;
+                        continue;
+                    }
+                }
                 let event = event.map_err(CommonSourceError::CdcStream)?;
-                if let CdcEvent::KeepAliveRequested { reply } = event {
+                if self.is_of_skipped_table(&event) {
+                    continue;
+                }
+                // Changes before a schema change are written with the old schema
+                if let CdcEvent::Schema(table_schema) = event {
+                    if !events.is_empty() {
+                        self.write_cdc_events(mem::take(&mut events)).await?;
+                    }
+                    let table_id = table_schema.table_id;
+                    let table_schema = self.prepare_table_schema(table_id, table_schema)?;
+                    info!(
+                        "writing changed schema of table {}",
+                        table_schema.table_name
+                    );
+                    self.sink
+                        .write_table_schemas(HashMap::from([(table_id, table_schema)]))
+                        .await
+                        .map_err(PipelineError::Sink)?;
+                    continue;
+                }
+                if let CdcEvent::KeepAliveRequested { reply, wal_end } = event {
                     send_status_update = reply;
+                    keep_alive_lsn = Some(wal_end);
                 };
-                events.push(event);
+                if let Some(stats) = &self.apply_lag_stats {
+                    stats.observe(&event);
+                }
+                if let Some(stats) = &self.table_change_stats {
+                    stats.observe(&event);
+                }
+                events.push(self.prepare_cdc_event(event)?);
             }
-            let last_lsn = self
-                .sink
-                .write_cdc_events(events)
-                .await
-                .map_err(PipelineError::Sink)?;
-            if send_status_update {
+            if let Some(limiter) = &self.rate_limiter {
+                let throttled_until = Instant::now() + limiter.reserve(limiter.cost(&events));
+                // Postgres drops the connection if it hears nothing back for
+                // `wal_sender_timeout`, which a long throttle could exceed
+                let interval = self
+                    .status_update_interval
+                    .unwrap_or(PAUSED_STATUS_UPDATE_INTERVAL);
+                while Instant::now() < throttled_until && !self.stop_handle.is_stopped() {
+                    let next_status_update = last_status_update + interval;
+                    select! {
+                        _ = sleep_until(throttled_until.min(next_status_update).into()) => {}
+                        _ = self.stop_handle.stopped() => {}
+                    }
+                    if last_status_update.elapsed() >= interval {
+                        let inner = batch_timeout_stream.as_mut().get_pinned_inner_mut();
+                        inner
+                            .as_mut()
+                            .send_status_update(last_sent_lsn)
+                            .await
+                            .map_err(CommonSourceError::StatusUpdate)?;
+                        last_status_update = Instant::now();
+                    }
+                }
+                if self.stop_handle.is_stopped() {
+                    break ConsumeOutcome::Stopped;
+                }
+            }
+            let sink_lsn = self.write_cdc_events(events).await?;
+            if let Some(stats) = &self.apply_lag_stats {
+                stats.written();
+            }
+            // The changes received so far were committed before the backfill's slot
+            // is created, so they are part of its copies, and the stream isn't read
+            // until the tables are added to it
+            if !added_tables.is_empty() {
+                let (table_schemas, start_lsn) = self.backfill_added_tables(&added_tables).await?;
+                let inner = batch_timeout_stream.as_mut().get_pinned_inner_mut();
+                for table_schema in table_schemas {
+                    inner.as_mut().add_table(table_schema, start_lsn);
+                }
+            }
+            if sink_lsn > last_sink_lsn {
+                last_sink_lsn = sink_lsn;
+                last_sink_commit = Instant::now();
+            }
+            passed_lsn = passed_lsn
+                .max(sink_lsn)
+                .max(keep_alive_lsn.unwrap_or(passed_lsn));
+            // All changes up to a keepalive's position have been passed to the sink
+            // by now, so once idle it can be confirmed even if the sink hasn't
+            // committed anything
+            let idle = self
+                .idle_flush_timeout
+                .is_some_and(|timeout| last_sink_commit.elapsed() >= timeout);
+            let last_lsn = match keep_alive_lsn {
+                Some(keep_alive_lsn) if idle => sink_lsn.max(keep_alive_lsn),
+                _ => sink_lsn,
+            }
+            .max(last_sent_lsn);
+            let interval_elapsed = self
+                .status_update_interval
+                .is_some_and(|interval| last_status_update.elapsed() >= interval);
+            if send_status_update || last_lsn > last_sent_lsn || interval_elapsed {
                 info!("sending status update with lsn: {last_lsn}");
-                let inner = unsafe {
-                    batch_timeout_stream
-                        .as_mut()
-                        .get_unchecked_mut()
-                        .get_inner_mut()
-                };
+                let inner = batch_timeout_stream.as_mut().get_pinned_inner_mut();
                 inner
                     .as_mut()
                     .send_status_update(last_lsn)
                     .await
                     .map_err(CommonSourceError::StatusUpdate)?;
+                last_sent_lsn = last_lsn;
+                last_status_update = Instant::now();
             }
+            if until.is_some_and(|until| passed_lsn >= until.target_lsn) {
+                break ConsumeOutcome::CaughtUp;
+            }
+        };
+
+        let stopped = outcome == ConsumeOutcome::Stopped;
+        if (until.is_some() || stopped) && last_sink_lsn > last_sent_lsn {
+            info!("sending final status update with lsn: {last_sink_lsn}");
+            let inner = batch_timeout_stream.as_mut().get_pinned_inner_mut();
+            inner
+                .as_mut()
+                .send_status_update(last_sink_lsn)
+                .await
+                .map_err(CommonSourceError::StatusUpdate)?;
         }
 
-        Ok(())
+        Ok(outcome)
     }
 
+    /// Runs the pipeline according to its action until the cdc stream ends, an error
+    /// occurs or it's stopped through its [`StopHandle`]
     pub async fn start(&mut self) -> Result<(), PipelineError<Src::Error, Snk::Error>> {
         let resumption_state = self
             .sink
@@ -186,19 +1228,136 @@ impl<Src: Source, Snk: BatchSink> BatchDataPipeline<Src, Snk> {
         match self.action {
             PipelineAction::TableCopiesOnly => {
                 self.copy_table_schemas().await?;
-                self.copy_tables(&resumption_state.copied_tables).await?;
+                self.copy_tables(
+                    &resumption_state.copied_tables,
+                    &resumption_state.backfill_cursors,
+                )
+                .await?;
             }
             PipelineAction::CdcOnly => {
                 self.copy_table_schemas().await?;
-                self.copy_cdc_events(resumption_state.last_lsn).await?;
+                self.check_slot_name(&resumption_state).await?;
+                self.skip_uncopied_tables(&resumption_state.copied_tables);
+                self.record_slot_name(resumption_state.slot_name.as_deref())
+                    .await?;
+                self.copy_cdc_events(resumption_state.last_lsn, None)
+                    .await?;
             }
             PipelineAction::Both => {
                 self.copy_table_schemas().await?;
-                self.copy_tables(&resumption_state.copied_tables).await?;
-                self.copy_cdc_events(resumption_state.last_lsn).await?;
+                self.check_slot_name(&resumption_state).await?;
+                self.copy_tables(
+                    &resumption_state.copied_tables,
+                    &resumption_state.backfill_cursors,
+                )
+                .await?;
+                if !self.stop_handle.is_stopped() {
+                    self.record_slot_name(resumption_state.slot_name.as_deref())
+                        .await?;
+                    self.copy_cdc_events(resumption_state.last_lsn, None)
+                        .await?;
+                }
             }
         }
 
         Ok(())
     }
 }
+
+impl<Snk: BatchSink> BatchDataPipeline<PostgresSource, Snk> {
+    /// Makes the pipeline copy tables added to the source's publication while it
+    /// streams, which the source's schemas don't include. Once the cdc stream
+    /// delivers changes of such a table, the pipeline stops reading the stream and
+    /// copies the table as of the consistent point of a temporary slot created with
+    /// `backfill`, whose connections must be separate from the source's. It then
+    /// resumes the stream, applying only the table's changes committed after the
+    /// consistent point, see [`CdcStream::add_table`](crate::pipeline::sources::postgres::CdcStream::add_table).
+    /// No status updates are sent during the copy, so it should finish within
+    /// Postgres' `wal_sender_timeout`. Changes of a streamed transaction which were
+    /// received before the copy and commit after it are lost.
+    pub fn set_added_table_backfill(&mut self, backfill: SnapshotBackfill) {
+        let publication = self.source.publication().cloned();
+        self.added_table_backfill = Some((backfill, publication));
+    }
+
+    /// Re-syncs the sink from scratch, which is the only way to recover once the
+    /// source's slot is lost (`wal_status = 'lost'`). Drops the slot and creates it
+    /// again, truncates all tables in the sink, which also marks them to be copied
+    /// again should the reset be interrupted, and copies them from the new slot's
+    /// snapshot. Unless the pipeline only copies tables, changes are then streamed
+    /// from the new slot like [`BatchDataPipeline::start`] does. The slot is recorded
+    /// in the sink, so this also re-syncs a sink after the slot name changed, see
+    /// [`PipelineError::SlotNameChanged`].
+    pub async fn reset(&mut self) -> Result<(), PipelineError<PostgresSourceError, Snk::Error>> {
+        let previous_slot_name = self
+            .sink
+            .get_resumption_state()
+            .await
+            .map_err(PipelineError::Sink)?
+            .slot_name;
+        let slot_info = self
+            .source
+            .recreate_slot()
+            .await
+            .map_err(PipelineError::Source)?;
+
+        self.copy_table_schemas().await?;
+        let mut table_ids: Vec<TableId> = self.source.get_table_schemas().keys().copied().collect();
+        table_ids.sort();
+        for table_id in table_ids {
+            self.sink
+                .truncate_table(table_id)
+                .await
+                .map_err(PipelineError::Sink)?;
+        }
+        self.copy_tables(&HashSet::new(), &HashMap::new()).await?;
+        self.record_slot_name(previous_slot_name.as_deref()).await?;
+
+        if !matches!(self.action, PipelineAction::TableCopiesOnly) {
+            self.copy_cdc_events(slot_info.confirmed_flush_lsn, None)
+                .await?;
+        }
+
+        Ok(())
+    }
+
+    /// Streams changes to the sink like a [`PipelineAction::CdcOnly`] pipeline's
+    /// [`BatchDataPipeline::start`], but only until all changes up to the server's WAL
+    /// flush position at the time of the call have been passed to the sink, or until
+    /// `deadline` passes, whichever comes first. The position the sink committed is
+    /// then confirmed to Postgres before returning, e.g. for scheduled runners which
+    /// catch up periodically instead of holding a connection open. The stream takes
+    /// over the source's connection, so each run needs a new source and pipeline.
+    pub async fn consume_until(
+        &mut self,
+        deadline: Instant,
+    ) -> Result<ConsumeOutcome, PipelineError<PostgresSourceError, Snk::Error>> {
+        let resumption_state = self
+            .sink
+            .get_resumption_state()
+            .await
+            .map_err(PipelineError::Sink)?;
+        let target_lsn = self
+            .source
+            .current_wal_flush_lsn()
+            .await
+            .map_err(PipelineError::Source)?;
+
+        self.copy_table_schemas().await?;
+        self.check_slot_name(&resumption_state).await?;
+        self.skip_uncopied_tables(&resumption_state.copied_tables);
+        self.record_slot_name(resumption_state.slot_name.as_deref())
+            .await?;
+        let outcome = self
+            .copy_cdc_events(
+                resumption_state.last_lsn,
+                Some(ConsumeUntil {
+                    deadline,
+                    target_lsn,
+                }),
+            )
+            .await?;
+        info!("consumed changes up to {target_lsn}: {outcome:?}");
+        Ok(outcome)
+    }
+}
@@ -1,11 +1,19 @@
 use std::time::Duration;
 
+#[cfg(feature = "arrow")]
+pub mod arrow;
 pub mod data_pipeline;
 pub mod stream;
 
 /// A trait to indicate which items in a stream can be the last in a batch.
 pub trait BatchBoundary: Sized {
     fn is_last_in_batch(&self) -> bool;
+
+    /// Estimates the memory the item takes up, counted against
+    /// [`BatchConfig::set_max_batch_bytes`]
+    fn estimated_size(&self) -> usize {
+        0
+    }
 }
 
 // For an item wrapped in a result we fall back to the item
@@ -18,12 +26,20 @@ impl<T: BatchBoundary, E> BatchBoundary for Result<T, E> {
             Err(_) => true,
         }
     }
+
+    fn estimated_size(&self) -> usize {
+        match self {
+            Ok(v) => v.estimated_size(),
+            Err(_) => 0,
+        }
+    }
 }
 
 #[derive(Debug, Clone)]
 pub struct BatchConfig {
     max_batch_size: usize,
     max_batch_fill_time: Duration,
+    max_batch_bytes: Option<usize>,
 }
 
 impl BatchConfig {
@@ -31,6 +47,17 @@ impl BatchConfig {
         BatchConfig {
             max_batch_size,
             max_batch_fill_time,
+            max_batch_bytes: None,
         }
     }
+
+    /// Also ends a batch once its items' estimated size reaches `max_batch_bytes`,
+    /// e.g. to bound the memory a table copy with wide rows takes up. Batches are
+    /// only read from the source as they are written, so the source's stream is
+    /// held back while a batch is written. Like with the batch size, a batch only
+    /// ends on an item which can be the last in a batch, so it can exceed the limit
+    /// by the rest of a transaction.
+    pub fn set_max_batch_bytes(&mut self, max_batch_bytes: usize) {
+        self.max_batch_bytes = Some(max_batch_bytes);
+    }
 }
@@ -10,7 +10,7 @@ use super::{BatchBoundary, BatchConfig};
 // Implementation adapted from https://github.com/tokio-rs/tokio/blob/master/tokio-stream/src/stream_ext/chunks_timeout.rs
 pin_project! {
     /// Adapter stream which batches the items of the underlying stream when it
-    /// reaches max_size or when a timeout expires. The underlying streams items
+    /// reaches max_size or max_bytes or when a timeout expires. The underlying streams items
     /// must implement [`BatchBoundary`]. A batch is guaranteed to end on an
     /// item which returns true from [`BatchBoundary::is_last_in_batch`]
     #[must_use = "streams do nothing unless polled"]
@@ -21,6 +21,8 @@ pin_project! {
         #[pin]
         deadline: Option<Sleep>,
         items: Vec<S::Item>,
+        // Estimated size of the items
+        items_size: usize,
         batch_config: BatchConfig,
         reset_timer: bool,
         inner_stream_ended: bool,
@@ -33,6 +35,7 @@ impl<B: BatchBoundary, S: Stream<Item = B>> BatchTimeoutStream<B, S> {
             stream,
             deadline: None,
             items: Vec::with_capacity(batch_config.max_batch_size),
+            items_size: 0,
             batch_config,
             reset_timer: true,
             inner_stream_ended: false,
@@ -42,6 +45,15 @@ impl<B: BatchBoundary, S: Stream<Item = B>> BatchTimeoutStream<B, S> {
     pub fn get_inner_mut(&mut self) -> &mut S {
         &mut self.stream
     }
+
+    /// Returns the underlying stream of a pinned batch stream, which can't be
+    /// borrowed with [`BatchTimeoutStream::get_inner_mut`] as its timer isn't `Unpin`
+    pub fn get_pinned_inner_mut(self: Pin<&mut Self>) -> &mut S
+    where
+        S: Unpin,
+    {
+        self.project().stream.get_mut()
+    }
 }
 
 impl<B: BatchBoundary, S: Stream<Item = B>> Stream for BatchTimeoutStream<B, S> {
@@ -65,9 +77,16 @@ impl<B: BatchBoundary, S: Stream<Item = B>> Stream for BatchTimeoutStream<B, S>
                 Poll::Pending => break,
                 Poll::Ready(Some(item)) => {
                     let is_last_in_batch = item.is_last_in_batch();
+                    *this.items_size += item.estimated_size();
                     this.items.push(item);
-                    if this.items.len() >= this.batch_config.max_batch_size && is_last_in_batch {
+                    let full = this.items.len() >= this.batch_config.max_batch_size
+                        || this
+                            .batch_config
+                            .max_batch_bytes
+                            .is_some_and(|max_bytes| *this.items_size >= max_bytes);
+                    if full && is_last_in_batch {
                         *this.reset_timer = true;
+                        *this.items_size = 0;
                         return Poll::Ready(Some(std::mem::take(this.items)));
                     }
                 }
@@ -76,6 +95,7 @@ impl<B: BatchBoundary, S: Stream<Item = B>> Stream for BatchTimeoutStream<B, S>
                         None
                     } else {
                         *this.reset_timer = true;
+                        *this.items_size = 0;
                         Some(std::mem::take(this.items))
                     };
 
@@ -94,6 +114,7 @@ impl<B: BatchBoundary, S: Stream<Item = B>> Stream for BatchTimeoutStream<B, S>
             let last_item = this.items.last().expect("missing last item");
             if last_item.is_last_in_batch() {
                 *this.reset_timer = true;
+                *this.items_size = 0;
                 return Poll::Ready(Some(std::mem::take(this.items)));
             }
         }
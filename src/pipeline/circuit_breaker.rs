@@ -0,0 +1,123 @@
+use std::{
+    collections::VecDeque,
+    fmt::Display,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum SinkCircuitBreakerError {
+    #[error("{0} failures {1:?} apart don't fit into the window of {2:?}, so the breaker would never trip")]
+    WindowTooShort(usize, Duration, Duration),
+}
+
+/// Whether a pipeline's sink writes are going through
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PipelineState {
+    Running,
+    /// The sink failed too often in a row and the pipeline stopped
+    Errored,
+}
+
+struct BreakerState {
+    /// Times of the failures since the last successful write
+    failures: VecDeque<Instant>,
+    state: PipelineState,
+    last_error: Option<String>,
+}
+
+/// Makes a pipeline retry failed sink writes after `retry_delay` instead of failing
+/// on the first error, and stop once the sink has failed `max_failures` consecutive
+/// times within `window`. A successful write resets the count. The breaker can be
+/// shared with other tasks to read the pipeline's state and the sink's last error,
+/// e.g. for a health check, see
+/// [`BatchDataPipeline::set_sink_circuit_breaker`](crate::pipeline::batching::data_pipeline::BatchDataPipeline::set_sink_circuit_breaker).
+/// The cdc stream isn't read while a write is retried, so `retry_delay` times
+/// `max_failures` should stay below Postgres' `wal_sender_timeout`. Only writes of
+/// sinks which support it are retried, see
+/// [`BatchSink::supports_retries`](crate::pipeline::sinks::BatchSink::supports_retries).
+pub struct SinkCircuitBreaker {
+    max_failures: usize,
+    window: Duration,
+    retry_delay: Duration,
+    state: Mutex<BreakerState>,
+}
+
+impl SinkCircuitBreaker {
+    /// Fails if `max_failures` retries `retry_delay` apart take longer than `window`,
+    /// as the oldest failures would leave the window before the breaker trips and
+    /// the pipeline would retry forever
+    pub fn new(
+        max_failures: usize,
+        window: Duration,
+        retry_delay: Duration,
+    ) -> Result<SinkCircuitBreaker, SinkCircuitBreakerError> {
+        let max_failures_u32 = u32::try_from(max_failures).unwrap_or(u32::MAX);
+        if retry_delay.saturating_mul(max_failures_u32) > window {
+            return Err(SinkCircuitBreakerError::WindowTooShort(
+                max_failures,
+                retry_delay,
+                window,
+            ));
+        }
+        Ok(SinkCircuitBreaker {
+            max_failures,
+            window,
+            retry_delay,
+            state: Mutex::new(BreakerState {
+                failures: VecDeque::new(),
+                state: PipelineState::Running,
+                last_error: None,
+            }),
+        })
+    }
+
+    pub fn state(&self) -> PipelineState {
+        self.state.lock().unwrap().state
+    }
+
+    /// The last error returned by the sink, also after later writes succeeded
+    pub fn last_error(&self) -> Option<String> {
+        self.state.lock().unwrap().last_error.clone()
+    }
+
+    pub fn max_failures(&self) -> usize {
+        self.max_failures
+    }
+
+    pub fn window(&self) -> Duration {
+        self.window
+    }
+
+    pub(crate) fn retry_delay(&self) -> Duration {
+        self.retry_delay
+    }
+
+    /// Records a failed write, returning false once the breaker trips and the
+    /// pipeline has to stop
+    pub(crate) fn record_failure(&self, error: &impl Display) -> bool {
+        let now = Instant::now();
+        let mut state = self.state.lock().unwrap();
+        while let Some(oldest) = state.failures.front() {
+            if now.duration_since(*oldest) <= self.window {
+                break;
+            }
+            state.failures.pop_front();
+        }
+        state.failures.push_back(now);
+        state.last_error = Some(error.to_string());
+        if state.failures.len() >= self.max_failures {
+            state.state = PipelineState::Errored;
+            return false;
+        }
+        true
+    }
+
+    pub(crate) fn record_success(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.failures.clear();
+        state.state = PipelineState::Running;
+    }
+}
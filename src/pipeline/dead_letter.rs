@@ -0,0 +1,110 @@
+use std::{
+    collections::VecDeque,
+    fs::{File, OpenOptions},
+    io::Write,
+    path::Path,
+    time::{Duration, Instant},
+};
+
+use async_trait::async_trait;
+use serde_json::json;
+use thiserror::Error;
+
+use crate::conversions::cdc_event::UndecodableRow;
+
+#[derive(Debug, Error)]
+pub enum DeadLetterQueueError {
+    #[error("io error: {0}")]
+    Io(#[from] std::io::Error),
+
+    #[error("{0}")]
+    Other(String),
+}
+
+/// A destination for cdc events which failed to decode, so that the pipeline
+/// can skip them instead of failing
+#[async_trait]
+pub trait DeadLetterQueue: Send {
+    async fn write_dead_letter(&mut self, row: &UndecodableRow)
+        -> Result<(), DeadLetterQueueError>;
+}
+
+/// A [`DeadLetterQueue`] which appends each undecodable row as a json line to a
+/// file. Values are written as hex because they might not be valid utf-8.
+pub struct FileDeadLetterQueue {
+    file: File,
+}
+
+impl FileDeadLetterQueue {
+    pub fn new(path: impl AsRef<Path>) -> Result<FileDeadLetterQueue, DeadLetterQueueError> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(FileDeadLetterQueue { file })
+    }
+}
+
+#[async_trait]
+impl DeadLetterQueue for FileDeadLetterQueue {
+    async fn write_dead_letter(
+        &mut self,
+        row: &UndecodableRow,
+    ) -> Result<(), DeadLetterQueueError> {
+        let values: Vec<Option<String>> = row
+            .values
+            .iter()
+            .map(|value| {
+                value
+                    .as_ref()
+                    .map(|bytes| bytes.iter().map(|b| format!("{b:02x}")).collect())
+            })
+            .collect();
+        let line = json!({
+            "lsn": row.lsn.to_string(),
+            "table_id": row.table_id,
+            "error": row.error.to_string(),
+            "values": values,
+        });
+        writeln!(self.file, "{line}")?;
+        self.file.flush()?;
+        Ok(())
+    }
+}
+
+/// Stops the pipeline when more than `max_dead_letters` events are dead
+/// lettered within `window`, as that points to a systemic problem rather than
+/// a few bad values
+pub(crate) struct DeadLetterBreaker {
+    max_dead_letters: usize,
+    window: Duration,
+    recent: VecDeque<Instant>,
+}
+
+impl DeadLetterBreaker {
+    pub(crate) fn new(max_dead_letters: usize, window: Duration) -> DeadLetterBreaker {
+        DeadLetterBreaker {
+            max_dead_letters,
+            window,
+            recent: VecDeque::new(),
+        }
+    }
+
+    /// Records a dead letter, returning false if the rate has been exceeded
+    pub(crate) fn record(&mut self) -> bool {
+        let now = Instant::now();
+        while let Some(oldest) = self.recent.front() {
+            if now.duration_since(*oldest) <= self.window {
+                break;
+            }
+            self.recent.pop_front();
+        }
+        self.recent.push_back(now);
+        self.recent.len() <= self.max_dead_letters
+    }
+
+    pub(crate) fn max_dead_letters(&self) -> usize {
+        self.max_dead_letters
+    }
+
+    pub(crate) fn window(&self) -> Duration {
+        self.window
+    }
+}
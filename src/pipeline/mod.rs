@@ -1,15 +1,29 @@
-use std::collections::HashSet;
+use std::{
+    collections::{HashMap, HashSet},
+    time::Duration,
+};
 
+use dead_letter::DeadLetterQueueError;
 use sinks::SinkError;
-use sources::SourceError;
+use sources::{backfill::SnapshotBackfillError, postgres::TableCopyStreamError, SourceError};
 use thiserror::Error;
 use tokio_postgres::types::PgLsn;
+use transforms::TransformError;
 
-use crate::table::TableId;
+use crate::{conversions::coercion::CoercionError, table::TableId};
 
 pub mod batching;
+pub mod circuit_breaker;
+pub mod dead_letter;
+pub mod pause;
+pub mod rate_limit;
 pub mod sinks;
 pub mod sources;
+pub mod spill;
+pub mod stats;
+pub mod stop;
+pub mod streamed;
+pub mod transforms;
 
 #[derive(Debug)]
 pub enum PipelineAction {
@@ -21,6 +35,13 @@ pub enum PipelineAction {
 pub struct PipelineResumptionState {
     pub copied_tables: HashSet<TableId>,
     pub last_lsn: PgLsn,
+    /// Key values of the last rows written of tables whose keyset copy was interrupted,
+    /// see [`BatchSink::write_table_rows_with_cursor`](sinks::BatchSink::write_table_rows_with_cursor)
+    pub backfill_cursors: HashMap<TableId, Vec<String>>,
+    /// The slot the pipeline last streamed from, see
+    /// [`BatchSink::write_slot_name`](sinks::BatchSink::write_slot_name). `None` if
+    /// the sink doesn't keep it.
+    pub slot_name: Option<String>,
 }
 
 #[derive(Debug, Error)]
@@ -33,4 +54,25 @@ pub enum PipelineError<SrcErr: SourceError, SnkErr: SinkError> {
 
     #[error("source error: {0}")]
     CommonSource(#[from] sources::CommonSourceError),
+
+    #[error("coercion error: {0}")]
+    Coercion(#[from] CoercionError),
+
+    #[error("transform error: {0}")]
+    Transform(#[from] TransformError),
+
+    #[error("dead letter queue error: {0}")]
+    DeadLetterQueue(#[from] DeadLetterQueueError),
+
+    #[error("more than {0} undecodable events within {1:?}")]
+    DeadLetterRateExceeded(usize, Duration),
+
+    #[error("sink failed {0} consecutive times within {1:?}, last error: {2}")]
+    SinkCircuitOpen(usize, Duration, #[source] SnkErr),
+
+    #[error("slot name changed from {0} to {1}, but the sink holds changes from slot {0} which slot {1} can't continue from; configure slot {0} again or re-sync the sink with `reset`")]
+    SlotNameChanged(String, String),
+
+    #[error("backfill of added tables failed: {0}")]
+    AddedTableBackfill(#[source] SnapshotBackfillError<TableCopyStreamError>),
 }
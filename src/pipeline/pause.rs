@@ -0,0 +1,33 @@
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+};
+
+/// Pauses and resumes a pipeline's cdc stream from another task, e.g. during a
+/// maintenance window of the sink. While paused the pipeline stops reading changes,
+/// leaving them in the socket's and the server's buffers, but keeps the replication
+/// connection and the slot's position and keeps sending status updates so that
+/// Postgres doesn't time out the connection. Clones control the same pipeline.
+#[derive(Debug, Clone, Default)]
+pub struct PauseHandle {
+    paused: Arc<AtomicBool>,
+}
+
+impl PauseHandle {
+    pub fn new() -> PauseHandle {
+        PauseHandle::default()
+    }
+
+    /// Stops the pipeline from reading changes once the batch being processed is done
+    pub fn pause(&self) {
+        self.paused.store(true, Ordering::Relaxed);
+    }
+
+    pub fn resume(&self) {
+        self.paused.store(false, Ordering::Relaxed);
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused.load(Ordering::Relaxed)
+    }
+}
@@ -0,0 +1,136 @@
+use std::{
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use thiserror::Error;
+use tokio::time::sleep;
+
+use crate::conversions::{cdc_event::CdcEvent, size::estimated_size};
+
+#[derive(Debug, Error)]
+pub enum RateLimiterError {
+    #[error("rate limit must be positive")]
+    ZeroRate,
+}
+
+/// What a [`RateLimiter`] limits
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RateLimit {
+    /// Inserted, updated and deleted rows per second
+    RowsPerSecond(u64),
+    /// Bytes per second, estimated from the values of the rows like the memory limit
+    /// of a [`SpillBuffer`](crate::pipeline::spill::SpillBuffer)
+    BytesPerSecond(u64),
+}
+
+impl RateLimit {
+    fn per_second(&self) -> u64 {
+        match self {
+            RateLimit::RowsPerSecond(rate) | RateLimit::BytesPerSecond(rate) => *rate,
+        }
+    }
+}
+
+struct Bucket {
+    /// Negative while writes which took more than the available tokens are paid off
+    tokens: f64,
+    last_refill: Instant,
+}
+
+/// A token bucket limiting the rate at which a pipeline passes cdc events to the
+/// sink, e.g. to stay within a sink's ingestion quota instead of running into
+/// throttling errors. The bucket holds up to a second's worth of tokens, so bursts
+/// after idle periods are capped at the rate. A batch which needs more tokens than
+/// are available is written right away and the pipeline waits for the missing
+/// tokens before the next one, without reading from the cdc stream, so Postgres
+/// retains the WAL instead. While waiting the pipeline keeps sending status updates
+/// so that Postgres doesn't time out the connection. The limiter can
+/// be shared with other tasks to read how long the pipeline has been throttled, see
+/// [`BatchDataPipeline::set_rate_limiter`](crate::pipeline::batching::data_pipeline::BatchDataPipeline::set_rate_limiter).
+pub struct RateLimiter {
+    limit: RateLimit,
+    bucket: Mutex<Bucket>,
+    throttled_nanos: AtomicU64,
+    throttles: AtomicU64,
+}
+
+impl RateLimiter {
+    pub fn new(limit: RateLimit) -> Result<RateLimiter, RateLimiterError> {
+        if limit.per_second() == 0 {
+            return Err(RateLimiterError::ZeroRate);
+        }
+        Ok(RateLimiter {
+            limit,
+            bucket: Mutex::new(Bucket {
+                tokens: limit.per_second() as f64,
+                last_refill: Instant::now(),
+            }),
+            throttled_nanos: AtomicU64::new(0),
+            throttles: AtomicU64::new(0),
+        })
+    }
+
+    pub fn limit(&self) -> RateLimit {
+        self.limit
+    }
+
+    /// Total time spent waiting for tokens
+    pub fn throttled_time(&self) -> Duration {
+        Duration::from_nanos(self.throttled_nanos.load(Ordering::Relaxed))
+    }
+
+    /// Number of times the pipeline had to wait for tokens
+    pub fn throttles(&self) -> u64 {
+        self.throttles.load(Ordering::Relaxed)
+    }
+
+    /// Waits until the bucket isn't in debt, then takes `cost` tokens from it
+    pub async fn acquire(&self, cost: u64) {
+        sleep(self.reserve(cost)).await;
+    }
+
+    /// Takes `cost` tokens from the bucket, returning how long to wait until the
+    /// bucket isn't in debt anymore before writing
+    pub fn reserve(&self, cost: u64) -> Duration {
+        let wait = {
+            let mut bucket = self.bucket.lock().unwrap();
+            let rate = self.limit.per_second() as f64;
+            let now = Instant::now();
+            let refill = now.duration_since(bucket.last_refill).as_secs_f64() * rate;
+            bucket.tokens = (bucket.tokens + refill).min(rate);
+            bucket.last_refill = now;
+            let wait = if bucket.tokens < 0.0 {
+                Duration::from_secs_f64(-bucket.tokens / rate)
+            } else {
+                Duration::ZERO
+            };
+            bucket.tokens -= cost as f64;
+            wait
+        };
+
+        if !wait.is_zero() {
+            self.throttles.fetch_add(1, Ordering::Relaxed);
+            self.throttled_nanos
+                .fetch_add(wait.as_nanos() as u64, Ordering::Relaxed);
+        }
+        wait
+    }
+
+    /// The number of tokens writing `events` takes
+    pub(crate) fn cost(&self, events: &[CdcEvent]) -> u64 {
+        let rows = events.iter().filter(|event| {
+            matches!(
+                event,
+                CdcEvent::Insert(_) | CdcEvent::Update(_) | CdcEvent::Delete(_)
+            )
+        });
+        match self.limit {
+            RateLimit::RowsPerSecond(_) => rows.count() as u64,
+            RateLimit::BytesPerSecond(_) => rows.map(|event| estimated_size(event) as u64).sum(),
+        }
+    }
+}
@@ -0,0 +1,578 @@
+use std::collections::{HashMap, HashSet};
+
+use async_trait::async_trait;
+use serde_json::{Map, Value};
+use thiserror::Error;
+use tokio_postgres::types::{Kind, PgLsn, Type};
+
+use crate::{
+    clients::clickhouse::{ClickHouseClient, ClickHouseClientError},
+    conversions::{
+        cdc_event::CdcEvent, table_row::TableRow, text::TextFormatConverter, ArrayCell, Cell,
+    },
+    pipeline::{streamed::StreamedTransactions, PipelineResumptionState},
+    table::{ColumnSchema, LookupKey, TableId, TableName, TableSchema},
+};
+
+use super::{BatchSink, SinkError};
+
+/// Column holding the commit LSN of the transaction which produced a row version
+const VERSION_COLUMN: &str = "_pg_replicate_version";
+
+/// Column set to 1 for rows which are tombstones of a deleted row
+const IS_DELETED_COLUMN: &str = "_pg_replicate_is_deleted";
+
+const COPIED_TABLES_TABLE: &str = "_pg_replicate_copied_tables";
+const LAST_LSN_TABLE: &str = "_pg_replicate_last_lsn";
+
+/// Numeric precisions above this can't be represented by ClickHouse's Decimal type
+const CLICKHOUSE_MAX_DECIMAL_PRECISION: i32 = 76;
+
+/// Size of the varlena header which Postgres adds to every type modifier
+const VARHDRSZ: i32 = 4;
+
+/// A row to be inserted along with the id of the table it belongs to
+pub type TableJsonRow = (TableId, Map<String, Value>);
+
+#[derive(Debug, Error)]
+pub enum ClickHouseSinkError {
+    #[error("clickhouse client error: {0}")]
+    Client(#[from] ClickHouseClientError),
+
+    #[error("missing table schema for table id {0}")]
+    MissingTableSchema(TableId),
+
+    #[error("invalid value for column {0} in table {1}")]
+    InvalidStateValue(String, String),
+}
+
+impl SinkError for ClickHouseSinkError {}
+
+/// A sink which lands tables in ClickHouse as `ReplacingMergeTree` tables.
+///
+/// Every table is ordered by its [`LookupKey`] columns (all columns for
+/// [`LookupKey::FullRow`]) and gets two extra columns: a version column set to
+/// the commit LSN of the transaction which wrote the row, and an is-deleted
+/// column which marks delete tombstones. Backfilled rows get version 0.
+///
+/// Rows are never updated in place. Every insert, update and delete appends a
+/// new row version and ClickHouse collapses the versions of a key during
+/// background merges, keeping the one with the highest LSN and dropping it
+/// entirely if that version is a tombstone. Because the version comes from the
+/// source's WAL position rather than arrival order, a late or replayed event
+/// with an older LSN can never overwrite a newer version. Until a merge has
+/// happened, queries must use `FINAL` (or an `argMax` on the version column) to
+/// see the deduplicated state. Multiple changes to one key within a single
+/// transaction share a version and are resolved by insertion order, which is
+/// preserved because each batch is inserted in a single request per table.
+pub struct ClickHouseSink {
+    client: ClickHouseClient,
+    table_schemas: HashMap<TableId, TableSchema>,
+    versions: RowVersions,
+}
+
+impl ClickHouseSink {
+    pub async fn new(client: ClickHouseClient) -> Result<ClickHouseSink, ClickHouseSinkError> {
+        client
+            .execute(&format!(
+                "create table if not exists {COPIED_TABLES_TABLE} (table_id UInt32) engine = ReplacingMergeTree order by table_id"
+            ))
+            .await?;
+        client
+            .execute(&format!(
+                "create table if not exists {LAST_LSN_TABLE} (id UInt8, lsn UInt64) engine = ReplacingMergeTree(lsn) order by id"
+            ))
+            .await?;
+        Ok(ClickHouseSink {
+            client,
+            table_schemas: HashMap::new(),
+            versions: RowVersions::new(),
+        })
+    }
+
+    fn table_name(table_name: &TableName) -> String {
+        quote_identifier(&format!("{}_{}", table_name.schema, table_name.name))
+    }
+
+    fn get_table_schema(&self, table_id: TableId) -> Result<&TableSchema, ClickHouseSinkError> {
+        self.table_schemas
+            .get(&table_id)
+            .ok_or(ClickHouseSinkError::MissingTableSchema(table_id))
+    }
+
+    async fn create_table_if_missing(
+        &self,
+        table_schema: &TableSchema,
+    ) -> Result<(), ClickHouseSinkError> {
+        let mut columns = table_schema
+            .column_schemas
+            .iter()
+            .map(|c| format!("{} {}", quote_identifier(&c.name), Self::column_type(c)))
+            .collect::<Vec<_>>();
+        columns.push(format!("{VERSION_COLUMN} UInt64"));
+        columns.push(format!("{IS_DELETED_COLUMN} UInt8"));
+
+        let (order_by, settings) = match &table_schema.lookup_key {
+            LookupKey::Key { name: _, columns } => (
+                columns
+                    .iter()
+                    .map(|c| quote_identifier(c))
+                    .collect::<Vec<_>>()
+                    .join(", "),
+                "",
+            ),
+            LookupKey::FullRow => (
+                table_schema
+                    .column_schemas
+                    .iter()
+                    .map(|c| quote_identifier(&c.name))
+                    .collect::<Vec<_>>()
+                    .join(", "),
+                " settings allow_nullable_key = 1",
+            ),
+        };
+
+        let query = format!(
+            "create table if not exists {} ({}) engine = ReplacingMergeTree({VERSION_COLUMN}, {IS_DELETED_COLUMN}) order by ({order_by}){settings}",
+            Self::table_name(&table_schema.table_name),
+            columns.join(", "),
+        );
+        self.client.execute(&query).await?;
+        Ok(())
+    }
+
+    /// Alters a table after its schema changed. Rows inserted before a column was
+    /// added get the default value of its type.
+    async fn alter_table(
+        &self,
+        table_schema: &TableSchema,
+        previous: &TableSchema,
+    ) -> Result<(), ClickHouseSinkError> {
+        let changes = table_schema.column_changes(previous);
+        let mut alterations = vec![];
+        for column_schema in changes.dropped {
+            alterations.push(format!(
+                "drop column if exists {}",
+                quote_identifier(&column_schema.name)
+            ));
+        }
+        for column_schema in changes.added {
+            alterations.push(format!(
+                "add column if not exists {} {}",
+                quote_identifier(&column_schema.name),
+                Self::column_type(column_schema)
+            ));
+        }
+        for (_, column_schema) in changes.altered {
+            alterations.push(format!(
+                "modify column {} {}",
+                quote_identifier(&column_schema.name),
+                Self::column_type(column_schema)
+            ));
+        }
+        if alterations.is_empty() {
+            return Ok(());
+        }
+        let query = format!(
+            "alter table {} {}",
+            Self::table_name(&table_schema.table_name),
+            alterations.join(", ")
+        );
+        self.client.execute(&query).await?;
+        Ok(())
+    }
+
+    fn column_type(column_schema: &ColumnSchema) -> String {
+        let typ = Self::postgres_to_clickhouse_type(&column_schema.typ, column_schema.modifier);
+        // ClickHouse doesn't allow arrays to be wrapped in Nullable
+        if column_schema.nullable && !matches!(column_schema.typ.kind(), Kind::Array(_)) {
+            format!("Nullable({typ})")
+        } else {
+            typ
+        }
+    }
+
+    /// Maps a Postgres type to the closest ClickHouse type. Types without a
+    /// ClickHouse equivalent are stored as String.
+    pub fn postgres_to_clickhouse_type(typ: &Type, modifier: i32) -> String {
+        if let Kind::Array(element_type) = typ.kind() {
+            return format!(
+                "Array(Nullable({}))",
+                Self::postgres_to_clickhouse_type(element_type, -1)
+            );
+        }
+
+        match *typ {
+            Type::BOOL => "Bool".to_string(),
+            Type::INT2 => "Int16".to_string(),
+            Type::INT4 => "Int32".to_string(),
+            Type::INT8 => "Int64".to_string(),
+            Type::FLOAT4 => "Float32".to_string(),
+            Type::FLOAT8 => "Float64".to_string(),
+            Type::NUMERIC => Self::numeric_to_clickhouse_type(modifier),
+            Type::DATE => "Date32".to_string(),
+            Type::TIMESTAMP => "DateTime64(6)".to_string(),
+            Type::TIMESTAMPTZ => "DateTime64(6, 'UTC')".to_string(),
+            Type::UUID => "UUID".to_string(),
+            Type::OID => "UInt32".to_string(),
+            _ => "String".to_string(),
+        }
+    }
+
+    fn numeric_to_clickhouse_type(modifier: i32) -> String {
+        if modifier < VARHDRSZ {
+            return "String".to_string();
+        }
+        let modifier = modifier - VARHDRSZ;
+        let precision = (modifier >> 16) & 0xffff;
+        let scale = modifier & 0xffff;
+        if precision > CLICKHOUSE_MAX_DECIMAL_PRECISION {
+            "String".to_string()
+        } else {
+            format!("Decimal({precision}, {scale})")
+        }
+    }
+
+    fn row_to_json(
+        column_schemas: &[ColumnSchema],
+        row: TableRow,
+        version: u64,
+        is_deleted: bool,
+    ) -> Map<String, Value> {
+        let mut map = Map::with_capacity(column_schemas.len() + 2);
+        for (column_schema, cell) in column_schemas.iter().zip(row.values) {
+            map.insert(column_schema.name.clone(), Self::cell_to_json(cell));
+        }
+        map.insert(VERSION_COLUMN.to_string(), Value::from(version));
+        map.insert(IS_DELETED_COLUMN.to_string(), Value::from(is_deleted as u8));
+        map
+    }
+
+    fn cell_to_json(cell: Cell) -> Value {
+        match cell {
+            Cell::Null => Value::Null,
+            Cell::Bool(b) => Value::Bool(b),
+            Cell::String(s) => Value::String(s),
+            Cell::I16(i) => Value::from(i),
+            Cell::I32(i) => Value::from(i),
+            Cell::U32(u) => Value::from(u),
+            Cell::I64(i) => Value::from(i),
+            Cell::F32(f) => Value::from(f),
+            Cell::F64(f) => Value::from(f),
+            Cell::Numeric(n) => Value::String(n.to_string()),
+            Cell::Date(d) => Value::String(d.format("%Y-%m-%d").to_string()),
+            Cell::Time(t) => Value::String(t.format("%H:%M:%S%.f").to_string()),
+            Cell::TimeStamp(ts) => Value::String(ts.format("%Y-%m-%d %H:%M:%S%.6f").to_string()),
+            Cell::TimeStampTz(ts) => Value::String(ts.format("%Y-%m-%d %H:%M:%S%.6f").to_string()),
+            Cell::Interval(i) => Value::String(i.to_string()),
+            Cell::Uuid(u) => Value::String(u.to_string()),
+            Cell::Json(j) => Value::String(j.to_string()),
+            Cell::Bytes(b) => Value::String(Self::bytes_to_hex(&b)),
+            Cell::Array(a) => Self::array_to_json(a),
+            cell @ (Cell::Range(_) | Cell::Multirange(_)) => {
+                Value::String(TextFormatConverter::to_text(&cell).unwrap_or_default())
+            }
+            Cell::Geometry(g) => Value::String(g.to_hex()),
+            Cell::Bits(b) => Value::String(b.to_string()),
+        }
+    }
+
+    fn array_to_json(array: ArrayCell) -> Value {
+        fn list<T>(values: Vec<Option<T>>, f: impl Fn(T) -> Cell) -> Value {
+            Value::Array(
+                values
+                    .into_iter()
+                    .map(|v| ClickHouseSink::cell_to_json(v.map(&f).unwrap_or(Cell::Null)))
+                    .collect(),
+            )
+        }
+
+        match array {
+            ArrayCell::Null => Value::Array(vec![]),
+            ArrayCell::Bool(v) => list(v, Cell::Bool),
+            ArrayCell::String(v) => list(v, Cell::String),
+            ArrayCell::I16(v) => list(v, Cell::I16),
+            ArrayCell::I32(v) => list(v, Cell::I32),
+            ArrayCell::U32(v) => list(v, Cell::U32),
+            ArrayCell::I64(v) => list(v, Cell::I64),
+            ArrayCell::F32(v) => list(v, Cell::F32),
+            ArrayCell::F64(v) => list(v, Cell::F64),
+            ArrayCell::Numeric(v) => list(v, Cell::Numeric),
+            ArrayCell::Date(v) => list(v, Cell::Date),
+            ArrayCell::Time(v) => list(v, Cell::Time),
+            ArrayCell::TimeStamp(v) => list(v, Cell::TimeStamp),
+            ArrayCell::TimeStampTz(v) => list(v, Cell::TimeStampTz),
+            ArrayCell::Interval(v) => list(v, Cell::Interval),
+            ArrayCell::Uuid(v) => list(v, Cell::Uuid),
+            ArrayCell::Json(v) => list(v, Cell::Json),
+            ArrayCell::Bytes(v) => list(v, Cell::Bytes),
+            ArrayCell::Nested(v) => Value::Array(v.into_iter().map(Self::array_to_json).collect()),
+        }
+    }
+
+    fn bytes_to_hex(bytes: &[u8]) -> String {
+        let mut hex = String::with_capacity(2 + bytes.len() * 2);
+        hex.push_str("\\x");
+        for byte in bytes {
+            hex.push_str(&format!("{byte:02x}"));
+        }
+        hex
+    }
+
+    async fn insert_rows(&self, rows: Vec<TableJsonRow>) -> Result<(), ClickHouseSinkError> {
+        let mut rows_by_table: HashMap<TableId, Vec<Map<String, Value>>> = HashMap::new();
+        for (table_id, row) in rows {
+            rows_by_table.entry(table_id).or_default().push(row);
+        }
+        for (table_id, rows) in rows_by_table {
+            let table_schema = self.get_table_schema(table_id)?;
+            self.client
+                .insert_rows(&Self::table_name(&table_schema.table_name), &rows)
+                .await?;
+        }
+        Ok(())
+    }
+
+    async fn set_last_lsn(&self, lsn: PgLsn) -> Result<(), ClickHouseSinkError> {
+        let lsn: u64 = lsn.into();
+        let mut row = Map::new();
+        row.insert("id".to_string(), Value::from(1));
+        row.insert("lsn".to_string(), Value::from(lsn));
+        self.client.insert_rows(LAST_LSN_TABLE, &[row]).await?;
+        Ok(())
+    }
+
+    async fn get_last_lsn(&self) -> Result<PgLsn, ClickHouseSinkError> {
+        let rows = self
+            .client
+            .query(&format!("select max(lsn) as lsn from {LAST_LSN_TABLE}"))
+            .await?;
+        let lsn = match rows.first().and_then(|row| row.get("lsn")) {
+            // UInt64 values are quoted by default in JSON output
+            Some(Value::String(lsn)) => lsn.parse().map_err(|_| {
+                ClickHouseSinkError::InvalidStateValue("lsn".to_string(), LAST_LSN_TABLE.into())
+            })?,
+            Some(Value::Number(lsn)) => lsn.as_u64().ok_or_else(|| {
+                ClickHouseSinkError::InvalidStateValue("lsn".to_string(), LAST_LSN_TABLE.into())
+            })?,
+            _ => 0,
+        };
+        Ok(lsn.into())
+    }
+    async fn apply_cdc_events(
+        &mut self,
+        events: Vec<CdcEvent>,
+    ) -> Result<PgLsn, ClickHouseSinkError> {
+        let (rows, last_lsn) = self.versions.push_events(&self.table_schemas, events)?;
+        self.insert_rows(rows).await?;
+
+        match last_lsn {
+            Some(last_lsn) => {
+                self.set_last_lsn(last_lsn).await?;
+                Ok(last_lsn)
+            }
+            None => self.get_last_lsn().await,
+        }
+    }
+}
+
+/// Turns cdc events into the row versions which [`ClickHouseSink`] appends. An
+/// update whose key changed also appends a tombstone of the old key. The changes of
+/// streamed transactions are held back until they commit, as their version, the
+/// commit LSN, isn't known before, and dropped if they or their subtransaction
+/// abort.
+#[derive(Debug, Default)]
+pub struct RowVersions {
+    /// Commit LSN of the transaction currently being applied
+    current_version: u64,
+    /// Version before the last [`RowVersions::push_events`]
+    previous_version: u64,
+    streamed: StreamedTransactions,
+}
+
+impl RowVersions {
+    pub fn new() -> RowVersions {
+        RowVersions::default()
+    }
+
+    /// Returns the row versions of the changes in `events` which can be applied,
+    /// along with the end LSN of the last commit among them
+    pub fn push_events(
+        &mut self,
+        table_schemas: &HashMap<TableId, TableSchema>,
+        events: Vec<CdcEvent>,
+    ) -> Result<(Vec<TableJsonRow>, Option<PgLsn>), ClickHouseSinkError> {
+        self.previous_version = self.current_version;
+        let mut rows = vec![];
+        let mut streamed_rows = vec![];
+        let mut last_lsn = None;
+
+        for event in self.streamed.hold(events) {
+            let (table_id, old_row, row, is_deleted, xid) = match event {
+                CdcEvent::Begin(begin_body) => {
+                    self.current_version = begin_body.final_lsn();
+                    continue;
+                }
+                CdcEvent::Commit(commit_body) => {
+                    last_lsn = Some(commit_body.end_lsn());
+                    continue;
+                }
+                // The held changes of the transaction were released right before
+                CdcEvent::StreamCommit(stream_commit_body) => {
+                    let version = Value::from(stream_commit_body.commit_lsn());
+                    for (table_id, mut row) in streamed_rows.drain(..) {
+                        row.insert(VERSION_COLUMN.to_string(), version.clone());
+                        rows.push((table_id, row));
+                    }
+                    last_lsn = Some(stream_commit_body.end_lsn());
+                    continue;
+                }
+                CdcEvent::Insert((table_id, row, xid)) => (table_id, None, row, false, xid),
+                CdcEvent::Update((table_id, old_row, row, xid)) => {
+                    (table_id, old_row, row, false, xid)
+                }
+                CdcEvent::Delete((table_id, row, xid)) => (table_id, None, row, true, xid),
+                CdcEvent::Relation(_)
+                | CdcEvent::Type(_)
+                | CdcEvent::Schema(_)
+                | CdcEvent::TableDropped { .. }
+                | CdcEvent::KeepAliveRequested { .. }
+                | CdcEvent::Heartbeat { .. }
+                | CdcEvent::StreamStart(_)
+                | CdcEvent::StreamStop(_)
+                | CdcEvent::StreamAbort(_) => continue,
+            };
+
+            let table_schema = table_schemas
+                .get(&table_id)
+                .ok_or(ClickHouseSinkError::MissingTableSchema(table_id))?;
+            // An old row is only sent when the key changed, or with every update for
+            // tables keyed on the full row, and its key has to be deleted as the new
+            // row version doesn't replace it
+            let versions = old_row
+                .map(|old_row| (old_row, true))
+                .into_iter()
+                .chain([(row, is_deleted)]);
+            for (row, is_deleted) in versions {
+                let row = ClickHouseSink::row_to_json(
+                    &table_schema.column_schemas,
+                    row,
+                    self.current_version,
+                    is_deleted,
+                );
+                // The xid is only sent for changes of streamed transactions, whose
+                // commit LSN isn't known until the stream commit message arrives
+                match xid {
+                    Some(_) => streamed_rows.push((table_id, row)),
+                    None => rows.push((table_id, row)),
+                }
+            }
+        }
+
+        Ok((rows, last_lsn.map(PgLsn::from)))
+    }
+
+    /// Undoes the last [`RowVersions::push_events`], e.g. after its rows failed to be
+    /// inserted, so that the events can be pushed again
+    pub fn rollback(&mut self) {
+        self.current_version = self.previous_version;
+        self.streamed.rollback();
+    }
+}
+
+fn quote_identifier(identifier: &str) -> String {
+    format!("`{}`", identifier.replace('\\', "\\\\").replace('`', "\\`"))
+}
+
+#[async_trait]
+impl BatchSink for ClickHouseSink {
+    type Error = ClickHouseSinkError;
+
+    async fn get_resumption_state(&mut self) -> Result<PipelineResumptionState, Self::Error> {
+        let rows = self
+            .client
+            .query(&format!("select table_id from {COPIED_TABLES_TABLE} final"))
+            .await?;
+        let mut copied_tables = HashSet::new();
+        for row in rows {
+            let table_id = row
+                .get("table_id")
+                .and_then(|v| v.as_u64())
+                .ok_or_else(|| {
+                    ClickHouseSinkError::InvalidStateValue(
+                        "table_id".to_string(),
+                        COPIED_TABLES_TABLE.to_string(),
+                    )
+                })?;
+            copied_tables.insert(table_id as TableId);
+        }
+        let last_lsn = self.get_last_lsn().await?;
+        Ok(PipelineResumptionState {
+            copied_tables,
+            last_lsn,
+            backfill_cursors: HashMap::new(),
+            slot_name: None,
+        })
+    }
+
+    async fn write_table_schemas(
+        &mut self,
+        table_schemas: HashMap<TableId, TableSchema>,
+    ) -> Result<(), Self::Error> {
+        for (table_id, table_schema) in &table_schemas {
+            self.create_table_if_missing(table_schema).await?;
+            if let Some(previous) = self.table_schemas.get(table_id) {
+                self.alter_table(table_schema, previous).await?;
+            }
+        }
+        self.table_schemas.extend(table_schemas);
+        Ok(())
+    }
+
+    async fn write_table_rows(
+        &mut self,
+        rows: Vec<TableRow>,
+        table_id: TableId,
+    ) -> Result<(), Self::Error> {
+        let table_schema = self.get_table_schema(table_id)?;
+        let rows = rows
+            .into_iter()
+            .map(|row| Self::row_to_json(&table_schema.column_schemas, row, 0, false))
+            .collect::<Vec<_>>();
+        self.client
+            .insert_rows(&Self::table_name(&table_schema.table_name), &rows)
+            .await?;
+        Ok(())
+    }
+
+    async fn write_cdc_events(&mut self, events: Vec<CdcEvent>) -> Result<PgLsn, Self::Error> {
+        let result = self.apply_cdc_events(events).await;
+        if result.is_err() {
+            self.versions.rollback();
+        }
+        result
+    }
+
+    async fn table_copied(&mut self, table_id: TableId) -> Result<(), Self::Error> {
+        let mut row = Map::new();
+        row.insert("table_id".to_string(), Value::from(table_id));
+        self.client.insert_rows(COPIED_TABLES_TABLE, &[row]).await?;
+        Ok(())
+    }
+
+    async fn truncate_table(&mut self, table_id: TableId) -> Result<(), Self::Error> {
+        let table_schema = self.get_table_schema(table_id)?;
+        self.client
+            .execute(&format!(
+                "truncate table if exists {}",
+                Self::table_name(&table_schema.table_name)
+            ))
+            .await?;
+        self.client
+            .execute(&format!(
+                "delete from {COPIED_TABLES_TABLE} where table_id = {table_id}"
+            ))
+            .await?;
+        Ok(())
+    }
+}
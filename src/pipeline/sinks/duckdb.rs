@@ -0,0 +1,243 @@
+use std::{collections::HashMap, path::Path};
+
+use async_trait::async_trait;
+use thiserror::Error;
+use tokio_postgres::types::PgLsn;
+
+use crate::{
+    clients::duckdb::DuckDbClient,
+    conversions::{cdc_event::CdcEvent, table_row::TableRow},
+    pipeline::{streamed::StreamedTransactions, PipelineResumptionState},
+    table::{LookupKey, TableId, TableSchema},
+};
+
+use super::{BatchSink, SinkError};
+
+#[derive(Debug, Error)]
+pub enum DuckDbSinkError {
+    #[error("duckdb error: {0}")]
+    DuckDb(#[from] duckdb::Error),
+
+    #[error("missing table schema for table id {0}")]
+    MissingTableSchema(TableId),
+
+    #[error("missing old row in update for table id {0} without a lookup key")]
+    MissingOldRow(TableId),
+}
+
+impl SinkError for DuckDbSinkError {}
+
+/// A sink which mirrors tables into a DuckDB database. Table copies are bulk
+/// loaded with DuckDB's appender and CDC events are applied as upserts and
+/// deletes keyed on each table's [`LookupKey`]. The changes of streamed
+/// transactions are held until they commit, see [`StreamedTransactions`].
+pub struct DuckDbSink {
+    client: DuckDbClient,
+    table_schemas: HashMap<TableId, TableSchema>,
+    streamed: StreamedTransactions,
+}
+
+impl DuckDbSink {
+    pub fn file<P: AsRef<Path>>(path: P) -> Result<DuckDbSink, DuckDbSinkError> {
+        let client = DuckDbClient::open_file(path)?;
+        Self::new(client)
+    }
+
+    pub fn in_memory() -> Result<DuckDbSink, DuckDbSinkError> {
+        let client = DuckDbClient::open_in_memory()?;
+        Self::new(client)
+    }
+
+    fn new(client: DuckDbClient) -> Result<DuckDbSink, DuckDbSinkError> {
+        client.create_state_tables_if_missing()?;
+        Ok(DuckDbSink {
+            client,
+            table_schemas: HashMap::new(),
+            streamed: StreamedTransactions::new(),
+        })
+    }
+
+    fn get_table_schema(&self, table_id: TableId) -> Result<&TableSchema, DuckDbSinkError> {
+        self.table_schemas
+            .get(&table_id)
+            .ok_or(DuckDbSinkError::MissingTableSchema(table_id))
+    }
+
+    fn apply_insert(&self, table_id: TableId, row: TableRow) -> Result<(), DuckDbSinkError> {
+        let schema = self.get_table_schema(table_id)?;
+        match schema.lookup_key {
+            LookupKey::Key { .. } => {
+                self.client
+                    .upsert_row(&schema.table_name, &schema.column_schemas, row)?
+            }
+            LookupKey::FullRow => {
+                self.client
+                    .insert_row(&schema.table_name, &schema.column_schemas, row)?
+            }
+        }
+        Ok(())
+    }
+
+    fn apply_update(
+        &self,
+        table_id: TableId,
+        old_row: Option<TableRow>,
+        new_row: TableRow,
+    ) -> Result<(), DuckDbSinkError> {
+        let schema = self.get_table_schema(table_id)?;
+        match schema.lookup_key {
+            LookupKey::Key { .. } => {
+                // An old row is only sent when the key columns changed
+                if let Some(old_row) = old_row {
+                    self.client.delete_row(
+                        &schema.table_name,
+                        &schema.column_schemas,
+                        &schema.lookup_key,
+                        old_row,
+                    )?;
+                }
+                self.client
+                    .upsert_row(&schema.table_name, &schema.column_schemas, new_row)?;
+            }
+            LookupKey::FullRow => {
+                let old_row = old_row.ok_or(DuckDbSinkError::MissingOldRow(table_id))?;
+                self.client.delete_row(
+                    &schema.table_name,
+                    &schema.column_schemas,
+                    &schema.lookup_key,
+                    old_row,
+                )?;
+                self.client
+                    .insert_row(&schema.table_name, &schema.column_schemas, new_row)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn apply_delete(&self, table_id: TableId, row: TableRow) -> Result<(), DuckDbSinkError> {
+        let schema = self.get_table_schema(table_id)?;
+        self.client.delete_row(
+            &schema.table_name,
+            &schema.column_schemas,
+            &schema.lookup_key,
+            row,
+        )?;
+        Ok(())
+    }
+
+    fn apply_cdc_events(&self, events: Vec<CdcEvent>) -> Result<PgLsn, DuckDbSinkError> {
+        let mut last_lsn = self.client.get_last_lsn()?;
+        for event in events {
+            match event {
+                CdcEvent::Insert((table_id, row, _)) => self.apply_insert(table_id, row)?,
+                CdcEvent::Update((table_id, old_row, new_row, _)) => {
+                    self.apply_update(table_id, old_row, new_row)?
+                }
+                CdcEvent::Delete((table_id, row, _)) => self.apply_delete(table_id, row)?,
+                CdcEvent::Commit(commit_body) => {
+                    last_lsn = commit_body.end_lsn().into();
+                }
+                CdcEvent::StreamCommit(stream_commit_body) => {
+                    last_lsn = stream_commit_body.end_lsn().into();
+                }
+                // The changes of aborted streamed transactions have been dropped
+                // by `StreamedTransactions` already
+                CdcEvent::Begin(_)
+                | CdcEvent::Relation(_)
+                | CdcEvent::Type(_)
+                | CdcEvent::Schema(_)
+                | CdcEvent::TableDropped { .. }
+                | CdcEvent::KeepAliveRequested { .. }
+                | CdcEvent::Heartbeat { .. }
+                | CdcEvent::StreamStart(_)
+                | CdcEvent::StreamStop(_)
+                | CdcEvent::StreamAbort(_) => {}
+            }
+        }
+        self.client.set_last_lsn(last_lsn)?;
+        Ok(last_lsn)
+    }
+
+    fn apply_cdc_events_in_transaction(
+        &self,
+        events: Vec<CdcEvent>,
+    ) -> Result<PgLsn, DuckDbSinkError> {
+        self.client.begin_transaction()?;
+        match self.apply_cdc_events(events) {
+            Ok(last_lsn) => {
+                self.client.commit_transaction()?;
+                Ok(last_lsn)
+            }
+            Err(e) => {
+                self.client.rollback_transaction()?;
+                Err(e)
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl BatchSink for DuckDbSink {
+    type Error = DuckDbSinkError;
+
+    async fn get_resumption_state(&mut self) -> Result<PipelineResumptionState, Self::Error> {
+        let copied_tables = self.client.get_copied_table_ids()?;
+        let last_lsn = self.client.get_last_lsn()?;
+        Ok(PipelineResumptionState {
+            copied_tables,
+            last_lsn,
+            backfill_cursors: HashMap::new(),
+            slot_name: None,
+        })
+    }
+
+    async fn write_table_schemas(
+        &mut self,
+        table_schemas: HashMap<TableId, TableSchema>,
+    ) -> Result<(), Self::Error> {
+        for (table_id, schema) in &table_schemas {
+            self.client.create_table_if_missing(
+                &schema.table_name,
+                &schema.column_schemas,
+                &schema.lookup_key,
+            )?;
+            if let Some(previous) = self.table_schemas.get(table_id) {
+                self.client
+                    .alter_table(&schema.table_name, &schema.column_changes(previous))?;
+            }
+        }
+        self.table_schemas.extend(table_schemas);
+        Ok(())
+    }
+
+    async fn write_table_rows(
+        &mut self,
+        rows: Vec<TableRow>,
+        table_id: TableId,
+    ) -> Result<(), Self::Error> {
+        let schema = self.get_table_schema(table_id)?;
+        self.client.insert_rows(&schema.table_name, rows)?;
+        Ok(())
+    }
+
+    async fn write_cdc_events(&mut self, events: Vec<CdcEvent>) -> Result<PgLsn, Self::Error> {
+        let events = self.streamed.hold(events);
+        let result = self.apply_cdc_events_in_transaction(events);
+        if result.is_err() {
+            self.streamed.rollback();
+        }
+        result
+    }
+
+    async fn table_copied(&mut self, table_id: TableId) -> Result<(), Self::Error> {
+        self.client.insert_into_copied_tables(table_id)?;
+        Ok(())
+    }
+
+    async fn truncate_table(&mut self, table_id: TableId) -> Result<(), Self::Error> {
+        let schema = self.get_table_schema(table_id)?;
+        self.client.truncate_table(&schema.table_name)?;
+        self.client.delete_from_copied_tables(table_id)?;
+        Ok(())
+    }
+}
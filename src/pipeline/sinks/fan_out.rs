@@ -0,0 +1,510 @@
+use std::{
+    collections::{HashMap, HashSet},
+    mem,
+    path::PathBuf,
+};
+
+use async_trait::async_trait;
+use futures::future::join_all;
+use thiserror::Error;
+use tokio_postgres::types::PgLsn;
+use tracing::error;
+
+use crate::{
+    conversions::{
+        cdc_event::{CdcEvent, CdcEventConversionError},
+        coercion::CoercionTable,
+        table_row::TableRow,
+    },
+    pipeline::{
+        spill::{SpillBuffer, SpillBufferError},
+        PipelineResumptionState,
+    },
+    table::{TableId, TableSchema},
+};
+
+use super::{BatchSink, SinkError};
+
+pub type BoxedSinkError = Box<dyn std::error::Error + Send + Sync>;
+
+#[derive(Debug, Error)]
+pub enum FanOutSinkError {
+    #[error("{}", display_failures(.0))]
+    Sinks(Vec<(usize, BoxedSinkError)>),
+
+    #[error("fan-out sink has no sinks")]
+    NoSinks,
+
+    #[error("primary sink {0} doesn't exist")]
+    MissingPrimary(usize),
+
+    #[error("failed to copy cdc event: {0}")]
+    CopyEvent(#[from] CdcEventConversionError),
+
+    #[error("failed to buffer transaction: {0}")]
+    Spill(#[from] SpillBufferError),
+}
+
+impl SinkError for FanOutSinkError {}
+
+fn display_failures(failures: &[(usize, BoxedSinkError)]) -> String {
+    let failures: Vec<String> = failures
+        .iter()
+        .map(|(index, e)| format!("sink {index} failed: {e}"))
+        .collect();
+    failures.join(", ")
+}
+
+/// Which sinks have to write a batch before the pipeline may advance past it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LsnPolicy {
+    /// Every sink has to succeed. The lsn confirmed to Postgres is the lowest lsn
+    /// any sink wrote up to, so no sink misses changes after a restart, but sinks
+    /// which were ahead may see some changes again.
+    AllSinks,
+    /// Only the sink at this index has to succeed. Failures of the other sinks are
+    /// logged and don't stop the pipeline, the changes they failed to write are lost
+    /// for them.
+    Primary(usize),
+}
+
+/// Object safe version of [`BatchSink`], so that sinks with different error types
+/// can be stored together
+#[async_trait]
+trait DynSink: Send {
+    async fn get_resumption_state(&mut self) -> Result<PipelineResumptionState, BoxedSinkError>;
+    async fn write_table_schemas(
+        &mut self,
+        table_schemas: HashMap<TableId, TableSchema>,
+    ) -> Result<(), BoxedSinkError>;
+    async fn write_table_rows(
+        &mut self,
+        rows: Vec<TableRow>,
+        table_id: TableId,
+    ) -> Result<(), BoxedSinkError>;
+    async fn write_table_rows_with_cursor(
+        &mut self,
+        rows: Vec<TableRow>,
+        table_id: TableId,
+        cursor: Vec<String>,
+    ) -> Result<(), BoxedSinkError>;
+    async fn write_cdc_events(&mut self, events: Vec<CdcEvent>) -> Result<PgLsn, BoxedSinkError>;
+    async fn table_copied(&mut self, table_id: TableId) -> Result<(), BoxedSinkError>;
+    async fn truncate_table(&mut self, table_id: TableId) -> Result<(), BoxedSinkError>;
+    async fn write_slot_name(&mut self, slot_name: &str) -> Result<(), BoxedSinkError>;
+    fn coercions(&self) -> CoercionTable;
+}
+
+#[async_trait]
+impl<S: BatchSink + Send> DynSink for S {
+    async fn get_resumption_state(&mut self) -> Result<PipelineResumptionState, BoxedSinkError> {
+        Ok(BatchSink::get_resumption_state(self).await?)
+    }
+
+    async fn write_table_schemas(
+        &mut self,
+        table_schemas: HashMap<TableId, TableSchema>,
+    ) -> Result<(), BoxedSinkError> {
+        Ok(BatchSink::write_table_schemas(self, table_schemas).await?)
+    }
+
+    async fn write_table_rows(
+        &mut self,
+        rows: Vec<TableRow>,
+        table_id: TableId,
+    ) -> Result<(), BoxedSinkError> {
+        Ok(BatchSink::write_table_rows(self, rows, table_id).await?)
+    }
+
+    async fn write_table_rows_with_cursor(
+        &mut self,
+        rows: Vec<TableRow>,
+        table_id: TableId,
+        cursor: Vec<String>,
+    ) -> Result<(), BoxedSinkError> {
+        Ok(BatchSink::write_table_rows_with_cursor(self, rows, table_id, cursor).await?)
+    }
+
+    async fn write_cdc_events(&mut self, events: Vec<CdcEvent>) -> Result<PgLsn, BoxedSinkError> {
+        Ok(BatchSink::write_cdc_events(self, events).await?)
+    }
+
+    async fn table_copied(&mut self, table_id: TableId) -> Result<(), BoxedSinkError> {
+        Ok(BatchSink::table_copied(self, table_id).await?)
+    }
+
+    async fn truncate_table(&mut self, table_id: TableId) -> Result<(), BoxedSinkError> {
+        Ok(BatchSink::truncate_table(self, table_id).await?)
+    }
+
+    async fn write_slot_name(&mut self, slot_name: &str) -> Result<(), BoxedSinkError> {
+        Ok(BatchSink::write_slot_name(self, slot_name).await?)
+    }
+
+    fn coercions(&self) -> CoercionTable {
+        BatchSink::coercions(self)
+    }
+}
+
+/// Holds back cdc events until the transactions they belong to are complete
+struct TransactionBarrier {
+    held_events: SpillBuffer,
+    memory_limit: usize,
+    directory: PathBuf,
+    in_transaction: bool,
+    /// Xids of streamed transactions which haven't been committed or aborted yet
+    open_streams: HashSet<u32>,
+}
+
+impl TransactionBarrier {
+    fn new(memory_limit: usize, directory: PathBuf) -> TransactionBarrier {
+        TransactionBarrier {
+            held_events: SpillBuffer::new(memory_limit, directory.clone()),
+            memory_limit,
+            directory,
+            in_transaction: false,
+            open_streams: HashSet::new(),
+        }
+    }
+
+    /// Adds events, returning the transactions they complete. Events outside of
+    /// transactions, like keepalives, are returned on their own.
+    async fn push(&mut self, events: Vec<CdcEvent>) -> Result<Vec<SpillBuffer>, SpillBufferError> {
+        let mut transactions = vec![];
+        for event in events {
+            match &event {
+                CdcEvent::Begin(_) => self.in_transaction = true,
+                CdcEvent::Commit(_) => self.in_transaction = false,
+                CdcEvent::StreamStart(body) => {
+                    self.open_streams.insert(body.xid());
+                }
+                CdcEvent::StreamCommit(body) => {
+                    self.open_streams.remove(&body.xid());
+                }
+                // Aborts of subtransactions leave the transaction open
+                CdcEvent::StreamAbort(body) if body.xid() == body.subxid() => {
+                    self.open_streams.remove(&body.xid());
+                }
+                _ => {}
+            }
+            self.held_events.push(event).await?;
+            if !self.in_transaction && self.open_streams.is_empty() {
+                let held_events = SpillBuffer::new(self.memory_limit, self.directory.clone());
+                transactions.push(mem::replace(&mut self.held_events, held_events));
+            }
+        }
+        Ok(transactions)
+    }
+}
+
+/// A sink which writes everything to several sinks, e.g. to replicate into a
+/// warehouse and a search index from a single slot. Sinks are written to
+/// concurrently, and every sink is written to even if others fail, see
+/// [`LsnPolicy`] for which failures stop the pipeline.
+///
+/// The pipeline applies a single set of coercions, which are those of the primary
+/// sink, or of the first sink with [`LsnPolicy::AllSinks`].
+pub struct FanOutSink {
+    sinks: Vec<Box<dyn DynSink>>,
+    policy: LsnPolicy,
+    barrier: Option<TransactionBarrier>,
+    spill_memory_limit: usize,
+    spill_directory: PathBuf,
+    last_lsn: PgLsn,
+}
+
+impl FanOutSink {
+    pub fn new(policy: LsnPolicy) -> FanOutSink {
+        FanOutSink {
+            sinks: vec![],
+            policy,
+            barrier: None,
+            spill_memory_limit: usize::MAX,
+            spill_directory: std::env::temp_dir(),
+            last_lsn: PgLsn::from(0),
+        }
+    }
+
+    /// Makes the sinks apply changes one transaction at a time: a transaction is
+    /// only passed to the sinks once it is complete, including streamed ones, and
+    /// the next one only once all sinks have written it. Sinks which apply tables
+    /// independently then never have one table ahead of another by more than the
+    /// transaction being written, so reads across tables see a consistent state.
+    /// This trades throughput for consistency, as sinks get many small writes and
+    /// large transactions are held until they commit, in memory unless
+    /// [`FanOutSink::spill_transactions`] is used.
+    pub fn set_global_lsn_barrier(&mut self, barrier: bool) {
+        self.barrier = barrier.then(|| {
+            TransactionBarrier::new(self.spill_memory_limit, self.spill_directory.clone())
+        });
+    }
+
+    /// Makes the global lsn barrier write transactions taking up more than
+    /// `memory_limit` bytes to a file in `directory` while they are held. Once
+    /// complete, such a transaction is read back and passed to the sinks in batches
+    /// of about `memory_limit` bytes, so sinks may see a transaction split across
+    /// several writes, but always before the next transaction.
+    pub fn spill_transactions(&mut self, memory_limit: usize, directory: impl Into<PathBuf>) {
+        self.spill_memory_limit = memory_limit;
+        self.spill_directory = directory.into();
+        if self.barrier.is_some() {
+            self.set_global_lsn_barrier(true);
+        }
+    }
+
+    /// Adds a sink, returning its index
+    pub fn add_sink<S: BatchSink + Send + 'static>(&mut self, sink: S) -> usize {
+        self.sinks.push(Box::new(sink));
+        self.sinks.len() - 1
+    }
+
+    pub fn policy(&self) -> LsnPolicy {
+        self.policy
+    }
+
+    fn check_sinks(&self) -> Result<(), FanOutSinkError> {
+        if self.sinks.is_empty() {
+            return Err(FanOutSinkError::NoSinks);
+        }
+        if let LsnPolicy::Primary(primary) = self.policy {
+            if primary >= self.sinks.len() {
+                return Err(FanOutSinkError::MissingPrimary(primary));
+            }
+        }
+        Ok(())
+    }
+
+    /// Sorts the results of the sinks by the policy, returning the results of the
+    /// sinks which succeeded keyed by their index
+    fn collect_results<T>(
+        &self,
+        results: Vec<Result<T, BoxedSinkError>>,
+    ) -> Result<Vec<(usize, T)>, FanOutSinkError> {
+        let mut succeeded = vec![];
+        let mut failures = vec![];
+        for (index, result) in results.into_iter().enumerate() {
+            match result {
+                Ok(value) => succeeded.push((index, value)),
+                Err(e) => failures.push((index, e)),
+            }
+        }
+        if failures.is_empty() {
+            return Ok(succeeded);
+        }
+        match self.policy {
+            LsnPolicy::Primary(primary) if failures.iter().all(|(index, _)| *index != primary) => {
+                for (index, e) in failures {
+                    error!("non-primary sink {index} failed: {e}");
+                }
+                Ok(succeeded)
+            }
+            _ => Err(FanOutSinkError::Sinks(failures)),
+        }
+    }
+
+    /// Picks the lsn or resumption state from the primary sink's result, or merges
+    /// the results of all sinks
+    fn select<T>(
+        &self,
+        results: Vec<(usize, T)>,
+        merge: impl Fn(T, T) -> T,
+    ) -> Result<T, FanOutSinkError> {
+        match self.policy {
+            LsnPolicy::AllSinks => results
+                .into_iter()
+                .map(|(_, value)| value)
+                .reduce(merge)
+                .ok_or(FanOutSinkError::NoSinks),
+            LsnPolicy::Primary(primary) => results
+                .into_iter()
+                .find(|(index, _)| *index == primary)
+                .map(|(_, value)| value)
+                .ok_or(FanOutSinkError::MissingPrimary(primary)),
+        }
+    }
+
+    async fn dispatch_cdc_events(
+        &mut self,
+        events: Vec<CdcEvent>,
+    ) -> Result<PgLsn, FanOutSinkError> {
+        let copies = self.copies(events, |events| {
+            events
+                .iter()
+                .map(|event| event.try_clone().map_err(FanOutSinkError::from))
+                .collect()
+        })?;
+        let results = join_all(
+            self.sinks
+                .iter_mut()
+                .zip(copies)
+                .map(|(sink, events)| sink.write_cdc_events(events)),
+        )
+        .await;
+        let results = self.collect_results(results)?;
+        let lsn = self.select(results, |a, b| a.min(b))?;
+        self.last_lsn = self.last_lsn.max(lsn);
+        Ok(lsn)
+    }
+
+    /// Copies a value once per sink, moving the original into the last sink's copy
+    fn copies<T>(
+        &self,
+        value: T,
+        copy: impl Fn(&T) -> Result<T, FanOutSinkError>,
+    ) -> Result<Vec<T>, FanOutSinkError> {
+        let mut copies = Vec::with_capacity(self.sinks.len());
+        for _ in 1..self.sinks.len() {
+            copies.push(copy(&value)?);
+        }
+        copies.push(value);
+        Ok(copies)
+    }
+}
+
+#[async_trait]
+impl BatchSink for FanOutSink {
+    type Error = FanOutSinkError;
+
+    /// With [`LsnPolicy::AllSinks`] resumes from the lowest lsn of all sinks and
+    /// copies tables again which not all sinks have copied
+    async fn get_resumption_state(&mut self) -> Result<PipelineResumptionState, Self::Error> {
+        self.check_sinks()?;
+        let results = join_all(
+            self.sinks
+                .iter_mut()
+                .map(|sink| sink.get_resumption_state()),
+        )
+        .await;
+        let results = self.collect_results(results)?;
+        self.select(results, |a, b| PipelineResumptionState {
+            copied_tables: a
+                .copied_tables
+                .intersection(&b.copied_tables)
+                .copied()
+                .collect::<HashSet<_>>(),
+            last_lsn: a.last_lsn.min(b.last_lsn),
+            // A copy can only resume where all sinks have written up to the same row
+            backfill_cursors: a
+                .backfill_cursors
+                .into_iter()
+                .filter(|(table_id, cursor)| b.backfill_cursors.get(table_id) == Some(cursor))
+                .collect(),
+            slot_name: a.slot_name.or(b.slot_name),
+        })
+    }
+
+    async fn write_table_schemas(
+        &mut self,
+        table_schemas: HashMap<TableId, TableSchema>,
+    ) -> Result<(), Self::Error> {
+        self.check_sinks()?;
+        let copies = self.copies(table_schemas, |table_schemas| Ok(table_schemas.clone()))?;
+        let results = join_all(
+            self.sinks
+                .iter_mut()
+                .zip(copies)
+                .map(|(sink, table_schemas)| sink.write_table_schemas(table_schemas)),
+        )
+        .await;
+        self.collect_results(results)?;
+        Ok(())
+    }
+
+    async fn write_table_rows(
+        &mut self,
+        rows: Vec<TableRow>,
+        table_id: TableId,
+    ) -> Result<(), Self::Error> {
+        self.check_sinks()?;
+        let copies = self.copies(rows, |rows| Ok(rows.clone()))?;
+        let results = join_all(
+            self.sinks
+                .iter_mut()
+                .zip(copies)
+                .map(|(sink, rows)| sink.write_table_rows(rows, table_id)),
+        )
+        .await;
+        self.collect_results(results)?;
+        Ok(())
+    }
+
+    async fn write_table_rows_with_cursor(
+        &mut self,
+        rows: Vec<TableRow>,
+        table_id: TableId,
+        cursor: Vec<String>,
+    ) -> Result<(), Self::Error> {
+        self.check_sinks()?;
+        let copies = self.copies(rows, |rows| Ok(rows.clone()))?;
+        let results =
+            join_all(self.sinks.iter_mut().zip(copies).map(|(sink, rows)| {
+                sink.write_table_rows_with_cursor(rows, table_id, cursor.clone())
+            }))
+            .await;
+        self.collect_results(results)?;
+        Ok(())
+    }
+
+    /// With a global lsn barrier, returns the highest lsn written so far if the
+    /// events complete no transaction
+    async fn write_cdc_events(&mut self, events: Vec<CdcEvent>) -> Result<PgLsn, Self::Error> {
+        self.check_sinks()?;
+        let Some(barrier) = self.barrier.as_mut() else {
+            return self.dispatch_cdc_events(events).await;
+        };
+        for transaction in barrier.push(events).await? {
+            let mut chunks = transaction.into_chunks().await?;
+            while let Some(events) = chunks.next_chunk().await? {
+                self.dispatch_cdc_events(events).await?;
+            }
+        }
+        Ok(self.last_lsn)
+    }
+
+    async fn table_copied(&mut self, table_id: TableId) -> Result<(), Self::Error> {
+        self.check_sinks()?;
+        let results = join_all(
+            self.sinks
+                .iter_mut()
+                .map(|sink| sink.table_copied(table_id)),
+        )
+        .await;
+        self.collect_results(results)?;
+        Ok(())
+    }
+
+    async fn truncate_table(&mut self, table_id: TableId) -> Result<(), Self::Error> {
+        self.check_sinks()?;
+        let results = join_all(
+            self.sinks
+                .iter_mut()
+                .map(|sink| sink.truncate_table(table_id)),
+        )
+        .await;
+        self.collect_results(results)?;
+        Ok(())
+    }
+
+    async fn write_slot_name(&mut self, slot_name: &str) -> Result<(), Self::Error> {
+        self.check_sinks()?;
+        let results = join_all(
+            self.sinks
+                .iter_mut()
+                .map(|sink| sink.write_slot_name(slot_name)),
+        )
+        .await;
+        self.collect_results(results)?;
+        Ok(())
+    }
+
+    fn coercions(&self) -> CoercionTable {
+        let index = match self.policy {
+            LsnPolicy::AllSinks => 0,
+            LsnPolicy::Primary(primary) => primary,
+        };
+        self.sinks
+            .get(index)
+            .map(|sink| sink.coercions())
+            .unwrap_or_default()
+    }
+}
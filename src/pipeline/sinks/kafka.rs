@@ -0,0 +1,585 @@
+use std::{
+    collections::{HashMap, HashSet},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use async_trait::async_trait;
+use futures::future::try_join_all;
+use rdkafka::{
+    admin::{AdminClient, AdminOptions, NewTopic, TopicReplication},
+    client::DefaultClientContext,
+    consumer::{Consumer, StreamConsumer},
+    error::{KafkaError, RDKafkaErrorCode},
+    producer::{FutureProducer, FutureRecord},
+    ClientConfig, Message, Offset, TopicPartitionList,
+};
+use serde_json::{json, Map, Value};
+use thiserror::Error;
+use tokio::time::timeout;
+use tokio_postgres::types::PgLsn;
+
+use crate::{
+    conversions::{
+        cdc_event::CdcEvent, days_since_epoch, micros_since_midnight, table_row::TableRow,
+        text::TextFormatConverter, ArrayCell, Cell,
+    },
+    pipeline::{streamed::StreamedTransactions, PipelineResumptionState},
+    table::{LookupKey, TableId, TableSchema},
+};
+
+use super::{BatchSink, SinkError};
+
+/// Seconds between the Unix epoch and the Postgres epoch (2000-01-01)
+const POSTGRES_EPOCH_OFFSET_SECS: i64 = 946_684_800;
+
+const SEND_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// How long reading the state topic may wait for the next record
+const STATE_READ_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Keys of the records of the state topic
+const LAST_LSN_KEY: &str = "last_lsn";
+const SLOT_NAME_KEY: &str = "slot_name";
+const COPIED_TABLE_KEY_PREFIX: &str = "copied_table.";
+const BACKFILL_CURSOR_KEY_PREFIX: &str = "backfill_cursor.";
+
+/// A message to publish: its topic, key and payload, `None` for a tombstone
+type KafkaMessage = (String, Option<String>, Option<String>);
+
+#[derive(Debug, Error)]
+pub enum KafkaSinkError {
+    #[error("kafka error: {0}")]
+    Kafka(#[from] KafkaError),
+
+    #[error("missing table schema for table id {0}")]
+    MissingTableSchema(TableId),
+
+    #[error("json error: {0}")]
+    Json(#[from] serde_json::Error),
+
+    #[error("failed to create topic {0}: {1}")]
+    CreateTopic(String, RDKafkaErrorCode),
+
+    #[error("timed out reading the state topic {0}")]
+    StateReadTimeout(String),
+
+    #[error("invalid record {0} in the state topic")]
+    InvalidState(String),
+}
+
+impl SinkError for KafkaSinkError {}
+
+/// A sink which publishes rows to Kafka as Debezium-compatible change event
+/// envelopes serialized as JSON (without an embedded schema, i.e. as produced by
+/// Debezium with `value.converter.schemas.enable=false`).
+///
+/// Each table is published to the topic `<topic_prefix>.<schema>.<table>`. Messages
+/// are keyed with the row's [`LookupKey`] columns so all changes to a row land in
+/// the same partition and stay ordered. Tables without a key use a null key.
+/// Backfilled rows are emitted with `op: "r"` and deletes of rows with a key are
+/// followed by a tombstone message to allow log compaction. The changes of
+/// streamed transactions are published once they commit, see
+/// [`StreamedTransactions`].
+///
+/// Temporal values follow Debezium's `adaptive_time_microseconds` mode, numerics
+/// are emitted as strings and binary values as hex strings.
+///
+/// The resumption state is kept in the compacted topic
+/// `<topic_prefix>.pg_replicate_state`, which is created if it doesn't exist.
+/// Its records are published after the messages they cover, so this sink isn't
+/// transactional: after a crash the messages since the last recorded LSN are
+/// published again and consumers must tolerate duplicate events.
+pub struct KafkaSink {
+    producer: FutureProducer,
+    brokers: String,
+    topic_prefix: String,
+    database: String,
+    table_schemas: HashMap<TableId, TableSchema>,
+    /// Commit LSN, xid and commit timestamp (ms) of the transaction being published
+    current_transaction: (u64, u32, i64),
+    /// End LSN of the last published transaction
+    last_lsn: u64,
+    streamed: StreamedTransactions,
+}
+
+impl KafkaSink {
+    pub fn new(
+        brokers: &str,
+        topic_prefix: String,
+        database: String,
+    ) -> Result<KafkaSink, KafkaSinkError> {
+        let producer = ClientConfig::new()
+            .set("bootstrap.servers", brokers)
+            .set("enable.idempotence", "true")
+            .create()?;
+        Ok(KafkaSink {
+            producer,
+            brokers: brokers.to_string(),
+            topic_prefix,
+            database,
+            table_schemas: HashMap::new(),
+            current_transaction: (0, 0, 0),
+            last_lsn: 0,
+            streamed: StreamedTransactions::new(),
+        })
+    }
+
+    fn get_table_schema(&self, table_id: TableId) -> Result<&TableSchema, KafkaSinkError> {
+        self.table_schemas
+            .get(&table_id)
+            .ok_or(KafkaSinkError::MissingTableSchema(table_id))
+    }
+
+    fn topic(&self, table_schema: &TableSchema) -> String {
+        format!(
+            "{}.{}.{}",
+            self.topic_prefix, table_schema.table_name.schema, table_schema.table_name.name
+        )
+    }
+
+    /// The row as a JSON object of column names and values, as published in the
+    /// `before` and `after` fields of the envelopes
+    pub fn row_to_json(table_schema: &TableSchema, row: TableRow) -> Map<String, Value> {
+        table_schema
+            .column_schemas
+            .iter()
+            .zip(row.values)
+            .map(|(column_schema, cell)| (column_schema.name.clone(), Self::cell_to_json(cell)))
+            .collect()
+    }
+
+    /// Builds the message key from the lookup key columns of `row`, `None` for
+    /// tables without a lookup key
+    pub fn key(table_schema: &TableSchema, row: &Map<String, Value>) -> Option<String> {
+        match &table_schema.lookup_key {
+            LookupKey::Key { name: _, columns } => {
+                let key = columns
+                    .iter()
+                    .map(|c| (c.clone(), row.get(c).cloned().unwrap_or(Value::Null)))
+                    .collect::<Map<_, _>>();
+                Some(Value::Object(key).to_string())
+            }
+            LookupKey::FullRow => None,
+        }
+    }
+
+    fn envelope(
+        &self,
+        table_schema: &TableSchema,
+        op: &str,
+        before: Option<Map<String, Value>>,
+        after: Option<Map<String, Value>>,
+        snapshot: bool,
+    ) -> Value {
+        let (lsn, xid, commit_ts_ms) = self.current_transaction;
+        let now_ms = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_millis() as i64)
+            .unwrap_or_default();
+        let (ts_ms, lsn, xid) = if snapshot {
+            (now_ms, Value::Null, Value::Null)
+        } else {
+            (commit_ts_ms, Value::from(lsn), Value::from(xid))
+        };
+
+        json!({
+            "before": before,
+            "after": after,
+            "source": {
+                "version": env!("CARGO_PKG_VERSION"),
+                "connector": "postgresql",
+                "name": self.topic_prefix,
+                "ts_ms": ts_ms,
+                "snapshot": snapshot.to_string(),
+                "db": self.database,
+                "schema": table_schema.table_name.schema,
+                "table": table_schema.table_name.name,
+                "txId": xid,
+                "lsn": lsn,
+            },
+            "op": op,
+            "ts_ms": now_ms,
+        })
+    }
+
+    /// Adds the messages publishing a change to `messages`: the change's envelope
+    /// and, for a delete of a row with a key, a tombstone
+    fn push_change_messages(
+        &self,
+        messages: &mut Vec<KafkaMessage>,
+        table_id: TableId,
+        op: &str,
+        before: Option<TableRow>,
+        after: Option<TableRow>,
+    ) -> Result<(), KafkaSinkError> {
+        let table_schema = self.get_table_schema(table_id)?;
+        let topic = self.topic(table_schema);
+        let before = before.map(|row| Self::row_to_json(table_schema, row));
+        let after = after.map(|row| Self::row_to_json(table_schema, row));
+        let key = after
+            .as_ref()
+            .or(before.as_ref())
+            .and_then(|row| Self::key(table_schema, row));
+        let is_delete = op == "d";
+        let envelope = self.envelope(table_schema, op, before, after, false);
+        messages.push((
+            topic.clone(),
+            key.clone(),
+            Some(serde_json::to_string(&envelope)?),
+        ));
+        // Compaction needs a key, so rows without one get no tombstone
+        if let (true, Some(key)) = (is_delete, key) {
+            messages.push((topic, Some(key), None));
+        }
+        Ok(())
+    }
+
+    async fn publish_cdc_events(&mut self, events: Vec<CdcEvent>) -> Result<PgLsn, KafkaSinkError> {
+        let mut messages = vec![];
+        let mut last_lsn = self.last_lsn;
+        // Changes of streamed transactions, published with their StreamCommit
+        let mut streamed_changes = vec![];
+
+        for event in events {
+            let (table_id, op, before, after, xid) = match event {
+                CdcEvent::Begin(begin_body) => {
+                    self.current_transaction = (
+                        begin_body.final_lsn(),
+                        begin_body.xid(),
+                        Self::postgres_ts_to_unix_ms(begin_body.timestamp()),
+                    );
+                    continue;
+                }
+                CdcEvent::Commit(commit_body) => {
+                    last_lsn = commit_body.end_lsn();
+                    continue;
+                }
+                CdcEvent::StreamCommit(stream_commit_body) => {
+                    self.current_transaction = (
+                        stream_commit_body.commit_lsn(),
+                        stream_commit_body.xid(),
+                        Self::postgres_ts_to_unix_ms(stream_commit_body.timestamp()),
+                    );
+                    for (table_id, op, before, after) in streamed_changes.drain(..) {
+                        self.push_change_messages(&mut messages, table_id, op, before, after)?;
+                    }
+                    last_lsn = stream_commit_body.end_lsn();
+                    continue;
+                }
+                CdcEvent::Insert((table_id, row, xid)) => (table_id, "c", None, Some(row), xid),
+                CdcEvent::Update((table_id, old_row, new_row, xid)) => {
+                    (table_id, "u", old_row, Some(new_row), xid)
+                }
+                CdcEvent::Delete((table_id, row, xid)) => (table_id, "d", Some(row), None, xid),
+                // The changes of aborted streamed transactions have been dropped by
+                // `StreamedTransactions` already
+                CdcEvent::Relation(_)
+                | CdcEvent::Type(_)
+                | CdcEvent::Schema(_)
+                | CdcEvent::TableDropped { .. }
+                | CdcEvent::KeepAliveRequested { .. }
+                | CdcEvent::Heartbeat { .. }
+                | CdcEvent::StreamStart(_)
+                | CdcEvent::StreamStop(_)
+                | CdcEvent::StreamAbort(_) => continue,
+            };
+            if xid.is_some() {
+                streamed_changes.push((table_id, op, before, after));
+            } else {
+                self.push_change_messages(&mut messages, table_id, op, before, after)?;
+            }
+        }
+
+        self.send_all(messages).await?;
+        if last_lsn != self.last_lsn {
+            self.write_state(vec![(LAST_LSN_KEY.to_string(), Some(last_lsn.to_string()))])
+                .await?;
+        }
+        self.last_lsn = last_lsn;
+        Ok(PgLsn::from(last_lsn))
+    }
+
+    fn state_topic(&self) -> String {
+        format!("{}.pg_replicate_state", self.topic_prefix)
+    }
+
+    fn client_config(&self) -> ClientConfig {
+        let mut config = ClientConfig::new();
+        config.set("bootstrap.servers", &self.brokers);
+        config
+    }
+
+    /// Creates the compacted state topic if it doesn't exist yet
+    async fn create_state_topic(&self) -> Result<(), KafkaSinkError> {
+        let admin: AdminClient<DefaultClientContext> = self.client_config().create()?;
+        let topic = self.state_topic();
+        let new_topic =
+            NewTopic::new(&topic, 1, TopicReplication::Fixed(-1)).set("cleanup.policy", "compact");
+        for result in admin
+            .create_topics([&new_topic], &AdminOptions::new())
+            .await?
+        {
+            match result {
+                Ok(_) | Err((_, RDKafkaErrorCode::TopicAlreadyExists)) => {}
+                Err((topic, code)) => return Err(KafkaSinkError::CreateTopic(topic, code)),
+            }
+        }
+        Ok(())
+    }
+
+    /// Reads the state topic from its beginning. The last record of each key holds
+    /// the current value and a tombstone removes the key.
+    async fn read_state(&self) -> Result<PipelineResumptionState, KafkaSinkError> {
+        let topic = self.state_topic();
+        let consumer: StreamConsumer = self
+            .client_config()
+            .set("group.id", format!("{}.pg_replicate", self.topic_prefix))
+            .set("enable.auto.commit", "false")
+            .set("enable.partition.eof", "true")
+            .create()?;
+        let mut partitions = TopicPartitionList::new();
+        partitions.add_partition_offset(&topic, 0, Offset::Beginning)?;
+        consumer.assign(&partitions)?;
+
+        let mut state = PipelineResumptionState {
+            copied_tables: HashSet::new(),
+            last_lsn: PgLsn::from(0),
+            backfill_cursors: HashMap::new(),
+            slot_name: None,
+        };
+        loop {
+            let message = match timeout(STATE_READ_TIMEOUT, consumer.recv()).await {
+                Ok(Ok(message)) => message,
+                Ok(Err(KafkaError::PartitionEOF(_))) => break,
+                Ok(Err(e)) => return Err(e.into()),
+                Err(_) => return Err(KafkaSinkError::StateReadTimeout(topic)),
+            };
+            let key = String::from_utf8_lossy(message.key().unwrap_or_default()).into_owned();
+            let value = message
+                .payload()
+                .map(|payload| {
+                    std::str::from_utf8(payload)
+                        .map_err(|_| KafkaSinkError::InvalidState(key.clone()))
+                })
+                .transpose()?;
+            Self::apply_state_record(&mut state, &key, value)?;
+        }
+        Ok(state)
+    }
+
+    fn apply_state_record(
+        state: &mut PipelineResumptionState,
+        key: &str,
+        value: Option<&str>,
+    ) -> Result<(), KafkaSinkError> {
+        let invalid = || KafkaSinkError::InvalidState(key.to_string());
+        if key == LAST_LSN_KEY {
+            let lsn: u64 = value.and_then(|v| v.parse().ok()).ok_or_else(invalid)?;
+            state.last_lsn = PgLsn::from(lsn);
+        } else if key == SLOT_NAME_KEY {
+            state.slot_name = value.map(str::to_string);
+        } else if let Some(table_id) = key.strip_prefix(COPIED_TABLE_KEY_PREFIX) {
+            let table_id: TableId = table_id.parse().map_err(|_| invalid())?;
+            if value.is_some() {
+                state.copied_tables.insert(table_id);
+            } else {
+                state.copied_tables.remove(&table_id);
+            }
+        } else if let Some(table_id) = key.strip_prefix(BACKFILL_CURSOR_KEY_PREFIX) {
+            let table_id: TableId = table_id.parse().map_err(|_| invalid())?;
+            match value {
+                Some(cursor) => {
+                    state
+                        .backfill_cursors
+                        .insert(table_id, serde_json::from_str(cursor)?);
+                }
+                None => {
+                    state.backfill_cursors.remove(&table_id);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Publishes records of the state topic, as keys and values or `None` to
+    /// remove the key
+    async fn write_state(
+        &self,
+        records: Vec<(String, Option<String>)>,
+    ) -> Result<(), KafkaSinkError> {
+        let topic = self.state_topic();
+        let messages = records
+            .into_iter()
+            .map(|(key, value)| (topic.clone(), Some(key), value))
+            .collect();
+        self.send_all(messages).await
+    }
+
+    async fn send_all(&self, messages: Vec<KafkaMessage>) -> Result<(), KafkaSinkError> {
+        let sends = messages.iter().map(|(topic, key, payload)| {
+            let mut record = FutureRecord::<str, str>::to(topic);
+            if let Some(key) = key {
+                record = record.key(key);
+            }
+            if let Some(payload) = payload {
+                record = record.payload(payload);
+            }
+            self.producer.send(record, SEND_TIMEOUT)
+        });
+        try_join_all(sends).await.map_err(|(e, _)| e)?;
+        Ok(())
+    }
+
+    fn cell_to_json(cell: Cell) -> Value {
+        match cell {
+            Cell::Null => Value::Null,
+            Cell::Bool(b) => Value::Bool(b),
+            Cell::String(s) => Value::String(s),
+            Cell::I16(i) => Value::from(i),
+            Cell::I32(i) => Value::from(i),
+            Cell::U32(u) => Value::from(u),
+            Cell::I64(i) => Value::from(i),
+            Cell::F32(f) => Value::from(f),
+            Cell::F64(f) => Value::from(f),
+            Cell::Numeric(n) => Value::String(n.to_string()),
+            Cell::Date(d) => Value::from(days_since_epoch(d)),
+            Cell::Time(t) => Value::from(micros_since_midnight(t)),
+            Cell::TimeStamp(ts) => Value::from(ts.and_utc().timestamp_micros()),
+            Cell::TimeStampTz(ts) => Value::String(ts.to_rfc3339()),
+            Cell::Interval(i) => Value::String(i.to_string()),
+            Cell::Uuid(u) => Value::String(u.to_string()),
+            Cell::Json(j) => Value::String(j.to_string()),
+            Cell::Bytes(b) => Value::String(b.iter().map(|b| format!("{b:02x}")).collect()),
+            Cell::Array(a) => Self::array_to_json(a),
+            cell @ (Cell::Range(_) | Cell::Multirange(_)) => {
+                Value::String(TextFormatConverter::to_text(&cell).unwrap_or_default())
+            }
+            Cell::Geometry(g) => Value::String(g.to_hex()),
+            Cell::Bits(b) => Value::String(b.to_string()),
+        }
+    }
+
+    fn array_to_json(array: ArrayCell) -> Value {
+        fn list<T>(values: Vec<Option<T>>, f: impl Fn(T) -> Cell) -> Value {
+            Value::Array(
+                values
+                    .into_iter()
+                    .map(|v| KafkaSink::cell_to_json(v.map(&f).unwrap_or(Cell::Null)))
+                    .collect(),
+            )
+        }
+
+        match array {
+            ArrayCell::Null => Value::Null,
+            ArrayCell::Bool(v) => list(v, Cell::Bool),
+            ArrayCell::String(v) => list(v, Cell::String),
+            ArrayCell::I16(v) => list(v, Cell::I16),
+            ArrayCell::I32(v) => list(v, Cell::I32),
+            ArrayCell::U32(v) => list(v, Cell::U32),
+            ArrayCell::I64(v) => list(v, Cell::I64),
+            ArrayCell::F32(v) => list(v, Cell::F32),
+            ArrayCell::F64(v) => list(v, Cell::F64),
+            ArrayCell::Numeric(v) => list(v, Cell::Numeric),
+            ArrayCell::Date(v) => list(v, Cell::Date),
+            ArrayCell::Time(v) => list(v, Cell::Time),
+            ArrayCell::TimeStamp(v) => list(v, Cell::TimeStamp),
+            ArrayCell::TimeStampTz(v) => list(v, Cell::TimeStampTz),
+            ArrayCell::Interval(v) => list(v, Cell::Interval),
+            ArrayCell::Uuid(v) => list(v, Cell::Uuid),
+            ArrayCell::Json(v) => list(v, Cell::Json),
+            ArrayCell::Bytes(v) => list(v, Cell::Bytes),
+            ArrayCell::Nested(v) => Value::Array(v.into_iter().map(Self::array_to_json).collect()),
+        }
+    }
+
+    fn postgres_ts_to_unix_ms(ts: i64) -> i64 {
+        ts / 1_000 + POSTGRES_EPOCH_OFFSET_SECS * 1_000
+    }
+}
+
+#[async_trait]
+impl BatchSink for KafkaSink {
+    type Error = KafkaSinkError;
+
+    async fn get_resumption_state(&mut self) -> Result<PipelineResumptionState, Self::Error> {
+        self.create_state_topic().await?;
+        let state = self.read_state().await?;
+        self.last_lsn = state.last_lsn.into();
+        Ok(state)
+    }
+
+    async fn write_table_schemas(
+        &mut self,
+        table_schemas: HashMap<TableId, TableSchema>,
+    ) -> Result<(), Self::Error> {
+        self.table_schemas.extend(table_schemas);
+        Ok(())
+    }
+
+    async fn write_table_rows(
+        &mut self,
+        rows: Vec<TableRow>,
+        table_id: TableId,
+    ) -> Result<(), Self::Error> {
+        let table_schema = self.get_table_schema(table_id)?;
+        let topic = self.topic(table_schema);
+        let mut messages = Vec::with_capacity(rows.len());
+        for row in rows {
+            let after = Self::row_to_json(table_schema, row);
+            let key = Self::key(table_schema, &after);
+            let envelope = self.envelope(table_schema, "r", None, Some(after), true);
+            messages.push((topic.clone(), key, Some(serde_json::to_string(&envelope)?)));
+        }
+        self.send_all(messages).await
+    }
+
+    async fn write_table_rows_with_cursor(
+        &mut self,
+        rows: Vec<TableRow>,
+        table_id: TableId,
+        cursor: Vec<String>,
+    ) -> Result<(), Self::Error> {
+        self.write_table_rows(rows, table_id).await?;
+        self.write_state(vec![(
+            format!("{BACKFILL_CURSOR_KEY_PREFIX}{table_id}"),
+            Some(serde_json::to_string(&cursor)?),
+        )])
+        .await
+    }
+
+    async fn write_cdc_events(&mut self, events: Vec<CdcEvent>) -> Result<PgLsn, Self::Error> {
+        let events = self.streamed.hold(events);
+        let result = self.publish_cdc_events(events).await;
+        if result.is_err() {
+            self.streamed.rollback();
+        }
+        result
+    }
+
+    async fn table_copied(&mut self, table_id: TableId) -> Result<(), Self::Error> {
+        self.write_state(vec![
+            (
+                format!("{COPIED_TABLE_KEY_PREFIX}{table_id}"),
+                Some("true".to_string()),
+            ),
+            (format!("{BACKFILL_CURSOR_KEY_PREFIX}{table_id}"), None),
+        ])
+        .await
+    }
+
+    async fn truncate_table(&mut self, table_id: TableId) -> Result<(), Self::Error> {
+        self.write_state(vec![
+            (format!("{COPIED_TABLE_KEY_PREFIX}{table_id}"), None),
+            (format!("{BACKFILL_CURSOR_KEY_PREFIX}{table_id}"), None),
+        ])
+        .await
+    }
+
+    async fn write_slot_name(&mut self, slot_name: &str) -> Result<(), Self::Error> {
+        self.write_state(vec![(
+            SLOT_NAME_KEY.to_string(),
+            Some(slot_name.to_string()),
+        )])
+        .await
+    }
+}
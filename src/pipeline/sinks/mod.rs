@@ -5,13 +5,22 @@ use thiserror::Error;
 use tokio_postgres::types::PgLsn;
 
 use crate::{
-    conversions::{cdc_event::CdcEvent, table_row::TableRow},
+    conversions::{cdc_event::CdcEvent, coercion::CoercionTable, table_row::TableRow},
     table::{TableId, TableSchema},
 };
 
 use super::PipelineResumptionState;
 
+#[cfg(feature = "clickhouse")]
+pub mod clickhouse;
+#[cfg(feature = "duckdb")]
+pub mod duckdb;
+pub mod fan_out;
+#[cfg(feature = "kafka")]
+pub mod kafka;
+pub mod postgres;
 pub mod stdout;
+pub mod table_parallel;
 
 pub trait SinkError: std::error::Error + Send + Sync + 'static {}
 
@@ -24,6 +33,11 @@ impl SinkError for InfallibleSinkError {}
 pub trait BatchSink {
     type Error: SinkError;
     async fn get_resumption_state(&mut self) -> Result<PipelineResumptionState, Self::Error>;
+    /// Writes the schemas of all tables when the pipeline starts, and later the
+    /// schemas of tables which were added or whose columns changed, see
+    /// [`CdcEvent::Schema`]. Sinks keep the schemas of the tables not in
+    /// `table_schemas`, create the tables they don't have yet and alter the ones
+    /// whose schema they were given before.
     async fn write_table_schemas(
         &mut self,
         table_schemas: HashMap<TableId, TableSchema>,
@@ -33,7 +47,48 @@ pub trait BatchSink {
         rows: Vec<TableRow>,
         table_id: TableId,
     ) -> Result<(), Self::Error>;
+    /// Writes rows of a table copied in lookup key order, see
+    /// [`BatchDataPipeline::set_keyset_backfill`](crate::pipeline::batching::data_pipeline::BatchDataPipeline::set_keyset_backfill),
+    /// along with the values of the last row's key columns in text format. Sinks which
+    /// keep these should store them atomically with the rows and return them in
+    /// [`PipelineResumptionState::backfill_cursors`] until the table is truncated or
+    /// marked as copied, so that an interrupted copy resumes after the last row
+    /// written. By default the rows are just written, and an interrupted copy starts
+    /// over.
+    async fn write_table_rows_with_cursor(
+        &mut self,
+        rows: Vec<TableRow>,
+        table_id: TableId,
+        _cursor: Vec<String>,
+    ) -> Result<(), Self::Error> {
+        self.write_table_rows(rows, table_id).await
+    }
     async fn write_cdc_events(&mut self, events: Vec<CdcEvent>) -> Result<PgLsn, Self::Error>;
     async fn table_copied(&mut self, table_id: TableId) -> Result<(), Self::Error>;
+    /// Removes all rows of a table and forgets that it was copied, so that it is
+    /// copied again if the pipeline restarts before [`BatchSink::table_copied`]
     async fn truncate_table(&mut self, table_id: TableId) -> Result<(), Self::Error>;
+
+    /// Records the name of the slot the pipeline streams from. Sinks which keep it
+    /// should return it in [`PipelineResumptionState::slot_name`], so that a pipeline
+    /// whose configured slot name changed can find the slot it used before, which
+    /// would otherwise retain WAL forever. Other sinks ignore it.
+    async fn write_slot_name(&mut self, _slot_name: &str) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
+    /// Coercions applied to the schemas and rows written to this sink, for sinks
+    /// which don't support all Postgres types
+    fn coercions(&self) -> CoercionTable {
+        CoercionTable::new()
+    }
+
+    /// Whether a failed write of rows or cdc events may be repeated with the same
+    /// rows or events, i.e. the sink writes them atomically or idempotently, so that
+    /// a retry doesn't duplicate what the failed write applied before it failed. Only
+    /// writes of sinks which do are retried by a pipeline with a
+    /// [`SinkCircuitBreaker`](crate::pipeline::circuit_breaker::SinkCircuitBreaker).
+    fn supports_retries(&self) -> bool {
+        false
+    }
 }
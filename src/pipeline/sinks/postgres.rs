@@ -0,0 +1,766 @@
+use std::{
+    collections::{hash_map::RandomState, HashMap, HashSet},
+    hash::{BuildHasher, Hasher},
+    time::Duration,
+};
+
+use async_trait::async_trait;
+use pg_escape::quote_identifier;
+use thiserror::Error;
+use tokio_postgres::{
+    error::SqlState,
+    types::{PgLsn, ToSql, Type},
+    Client, GenericClient,
+};
+use tracing::{instrument, warn, Span};
+
+use crate::{
+    conversions::{cdc_event::CdcEvent, table_row::TableRow, text::TextFormatConverter},
+    pipeline::{streamed::StreamedTransactions, PipelineResumptionState},
+    table::{LookupKey, TableId, TableName, TableSchema},
+};
+
+use super::{BatchSink, SinkError};
+
+/// Postgres allows at most this many parameters in a single statement
+const MAX_PARAMS: usize = u16::MAX as usize;
+
+/// How often a batch of cdc events is applied before a deadlock is returned, unless
+/// set with [`PostgresSink::set_deadlock_retries`]
+const DEFAULT_MAX_DEADLOCK_ATTEMPTS: u32 = 3;
+
+/// How long to wait before applying a deadlocked batch again for the first time
+const DEFAULT_DEADLOCK_RETRY_DELAY: Duration = Duration::from_millis(50);
+
+#[derive(Debug, Error)]
+pub enum PostgresSinkError {
+    #[error("tokio_postgres error: {0}")]
+    TokioPostgres(#[from] tokio_postgres::Error),
+
+    #[error("missing table schema for table id {0}")]
+    MissingTableSchema(TableId),
+
+    #[error("missing old row in update for table id {0} without a lookup key")]
+    MissingOldRow(TableId),
+
+    #[error("lookup key column {0} is missing from table {1}")]
+    MissingKeyColumn(String, TableName),
+
+    #[error("inserts into table id {0} with a lookup key must be upserts")]
+    KeyedInsert(TableId),
+}
+
+impl SinkError for PostgresSinkError {}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum BatchKind {
+    Insert,
+    Upsert,
+    Delete,
+}
+
+/// A run of consecutive changes of the same kind to one table
+struct Batch {
+    table_id: TableId,
+    kind: BatchKind,
+    rows: Vec<TableRow>,
+}
+
+/// A sink which mirrors tables into another Postgres database. Table copies
+/// are written as multi-row inserts. CDC events are applied in batches: a run
+/// of inserts and updates to a table becomes one `INSERT ... ON CONFLICT DO
+/// UPDATE` on its [`LookupKey`] and a run of deletes one `DELETE ... WHERE
+/// (key) IN (...)`. Values are sent as text parameters cast to the column types.
+/// Unchanged TOAST values of updates are left as they are. The changes of streamed
+/// transactions are held until they commit, see [`StreamedTransactions`], and a
+/// batch which deadlocks with other writers is applied again after a short delay, see
+/// [`PostgresSink::set_deadlock_retries`].
+/// The resumption state, including the cursors of keyset copies, is kept in tables of
+/// the `replicate` schema.
+pub struct PostgresSink {
+    client: Client,
+    table_schemas: HashMap<TableId, TableSchema>,
+    streamed: StreamedTransactions,
+    max_deadlock_attempts: u32,
+    deadlock_retry_delay: Duration,
+}
+
+impl PostgresSink {
+    pub async fn new(client: Client) -> Result<PostgresSink, PostgresSinkError> {
+        client
+            .batch_execute(
+                "create schema if not exists replicate;
+                create table if not exists replicate.copied_tables (table_id bigint primary key);
+                create table if not exists replicate.last_lsn (id int primary key, lsn pg_lsn not null);
+                create table if not exists replicate.backfill_cursors (table_id bigint primary key, key text[] not null);
+                create table if not exists replicate.slot_name (id int primary key, slot_name text not null);",
+            )
+            .await?;
+        Ok(PostgresSink {
+            client,
+            table_schemas: HashMap::new(),
+            streamed: StreamedTransactions::new(),
+            max_deadlock_attempts: DEFAULT_MAX_DEADLOCK_ATTEMPTS,
+            deadlock_retry_delay: DEFAULT_DEADLOCK_RETRY_DELAY,
+        })
+    }
+
+    /// Makes the sink apply a batch of cdc events which deadlocked with other
+    /// writers up to `max_attempts` times in total before returning the error.
+    /// Attempt `n` waits `base_delay * 2^(n - 1)` before retrying, reduced by a random
+    /// fraction of up to a half so that the deadlocked writers don't retry in
+    /// lockstep. By default a batch is applied up to 3 times, starting with 50ms.
+    pub fn set_deadlock_retries(&mut self, max_attempts: u32, base_delay: Duration) {
+        self.max_deadlock_attempts = max_attempts.max(1);
+        self.deadlock_retry_delay = base_delay;
+    }
+
+    fn deadlock_retry_delay(&self, attempt: u32) -> Duration {
+        let delay = self
+            .deadlock_retry_delay
+            .saturating_mul(1 << (attempt - 1).min(16));
+        let random = RandomState::new().build_hasher().finish() as f64 / u64::MAX as f64;
+        delay.mul_f64(1.0 - random / 2.0)
+    }
+
+    fn get_table_schema(&self, table_id: TableId) -> Result<&TableSchema, PostgresSinkError> {
+        self.table_schemas
+            .get(&table_id)
+            .ok_or(PostgresSinkError::MissingTableSchema(table_id))
+    }
+
+    /// Positions of the lookup key columns, or `None` for a [`LookupKey::FullRow`]
+    fn key_column_indexes(schema: &TableSchema) -> Result<Option<Vec<usize>>, PostgresSinkError> {
+        let LookupKey::Key { columns, .. } = &schema.lookup_key else {
+            return Ok(None);
+        };
+        columns
+            .iter()
+            .map(|key_column| {
+                schema
+                    .column_schemas
+                    .iter()
+                    .position(|c| &c.name == key_column)
+                    .ok_or_else(|| {
+                        PostgresSinkError::MissingKeyColumn(
+                            key_column.clone(),
+                            schema.table_name.clone(),
+                        )
+                    })
+            })
+            .collect::<Result<Vec<_>, _>>()
+            .map(Some)
+    }
+
+    fn create_table_query(schema: &TableSchema) -> Result<String, PostgresSinkError> {
+        let mut columns: Vec<String> = schema
+            .column_schemas
+            .iter()
+            .map(|c| {
+                let not_null = if c.nullable { "" } else { " not null" };
+                format!(
+                    "{} {}{not_null}",
+                    quote_identifier(&c.name),
+                    quote_identifier(c.typ.name())
+                )
+            })
+            .collect();
+        if let Some(key_indexes) = Self::key_column_indexes(schema)? {
+            let key_columns = Self::column_list(schema, &key_indexes);
+            // Primary key columns can't be null, and a key with nullable columns is
+            // only unique if nulls are equal, which needs Postgres 15
+            if Self::has_nullable_key(schema, &key_indexes) {
+                columns.push(format!("unique nulls not distinct ({key_columns})"));
+            } else {
+                columns.push(format!("primary key ({key_columns})"));
+            }
+        }
+        Ok(format!(
+            "create table if not exists {} ({})",
+            schema.table_name.as_quoted_identifier(),
+            columns.join(", ")
+        ))
+    }
+
+    /// Statements altering the table of `previous` to match `schema`. Added columns
+    /// are nullable as the rows copied before don't have their values.
+    fn alter_table_queries(schema: &TableSchema, previous: &TableSchema) -> Vec<String> {
+        let table_name = schema.table_name.as_quoted_identifier();
+        let changes = schema.column_changes(previous);
+        let mut queries = vec![];
+        for column in changes.dropped {
+            queries.push(format!(
+                "alter table {table_name} drop column if exists {}",
+                quote_identifier(&column.name)
+            ));
+        }
+        for column in changes.added {
+            queries.push(format!(
+                "alter table {table_name} add column if not exists {} {}",
+                quote_identifier(&column.name),
+                quote_identifier(column.typ.name())
+            ));
+        }
+        for (previous, column) in changes.altered {
+            let name = quote_identifier(&column.name);
+            if previous.typ != column.typ {
+                let typ = quote_identifier(column.typ.name());
+                queries.push(format!(
+                    "alter table {table_name} alter column {name} type {typ} using {name}::{typ}"
+                ));
+            }
+            if previous.nullable != column.nullable {
+                let nullability = if column.nullable {
+                    "drop not null"
+                } else {
+                    "set not null"
+                };
+                queries.push(format!(
+                    "alter table {table_name} alter column {name} {nullability}"
+                ));
+            }
+        }
+        queries
+    }
+
+    fn column_list(schema: &TableSchema, indexes: &[usize]) -> String {
+        indexes
+            .iter()
+            .map(|&i| quote_identifier(&schema.column_schemas[i].name).to_string())
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+
+    fn has_nullable_key(schema: &TableSchema, key_indexes: &[usize]) -> bool {
+        key_indexes
+            .iter()
+            .any(|&i| schema.column_schemas[i].nullable)
+    }
+
+    /// A predicate matching the rows with the key of any of `rows`, with the key
+    /// values appended to `params`. Keys without nullable columns are matched as a
+    /// whole, others column by column so that null values match.
+    fn key_match(
+        schema: &TableSchema,
+        key_indexes: &[usize],
+        rows: &[&TableRow],
+        params: &mut Vec<Option<String>>,
+    ) -> String {
+        if !Self::has_nullable_key(schema, key_indexes) {
+            let values = Self::values_list(schema, key_indexes, rows, params);
+            return format!("({}) in ({values})", Self::column_list(schema, key_indexes));
+        }
+        rows.iter()
+            .map(|row| {
+                let predicates: Vec<String> = key_indexes
+                    .iter()
+                    .map(|&i| {
+                        let column = &schema.column_schemas[i];
+                        params.push(TextFormatConverter::to_text(&row.values[i]));
+                        column.match_predicate(&Self::placeholder(params.len(), &column.typ))
+                    })
+                    .collect();
+                format!("({})", predicates.join(" and "))
+            })
+            .collect::<Vec<_>>()
+            .join(" or ")
+    }
+
+    /// A parameter placeholder casting the text parameter to the column's type
+    fn placeholder(param: usize, typ: &Type) -> String {
+        format!("${param}::text::{}", quote_identifier(typ.name()))
+    }
+
+    /// A parenthesized tuple of placeholders for the values at `indexes` of each
+    /// row, with the rows' values appended to `params`
+    fn values_list(
+        schema: &TableSchema,
+        indexes: &[usize],
+        rows: &[&TableRow],
+        params: &mut Vec<Option<String>>,
+    ) -> String {
+        rows.iter()
+            .map(|row| {
+                let placeholders: Vec<String> = indexes
+                    .iter()
+                    .map(|&i| {
+                        params.push(TextFormatConverter::to_text(&row.values[i]));
+                        Self::placeholder(params.len(), &schema.column_schemas[i].typ)
+                    })
+                    .collect();
+                format!("({})", placeholders.join(", "))
+            })
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+
+    async fn execute<C: GenericClient>(
+        client: &C,
+        query: &str,
+        params: &[Option<String>],
+    ) -> Result<(), PostgresSinkError> {
+        let params: Vec<&(dyn ToSql + Sync)> =
+            params.iter().map(|p| p as &(dyn ToSql + Sync)).collect();
+        client.execute(query, &params).await?;
+        Ok(())
+    }
+
+    /// Inserts rows, updating the rows with the same key if `key_indexes` is set.
+    /// The `unchanged_toast` columns are left out of the update.
+    async fn insert_rows<C: GenericClient>(
+        client: &C,
+        schema: &TableSchema,
+        rows: &[&TableRow],
+        key_indexes: Option<&[usize]>,
+        unchanged_toast: &[usize],
+    ) -> Result<(), PostgresSinkError> {
+        let all_indexes: Vec<usize> = (0..schema.column_schemas.len()).collect();
+        let rows_per_statement = MAX_PARAMS / all_indexes.len().max(1);
+        for chunk in rows.chunks(rows_per_statement) {
+            let mut params = vec![];
+            let values = Self::values_list(schema, &all_indexes, chunk, &mut params);
+            let mut query = format!(
+                "insert into {} ({}) values {values}",
+                schema.table_name.as_quoted_identifier(),
+                Self::column_list(schema, &all_indexes),
+            );
+            if let Some(key_indexes) = key_indexes {
+                let updates: Vec<String> = all_indexes
+                    .iter()
+                    .filter(|i| !key_indexes.contains(i) && !unchanged_toast.contains(i))
+                    .map(|&i| {
+                        let name = quote_identifier(&schema.column_schemas[i].name);
+                        format!("{name} = excluded.{name}")
+                    })
+                    .collect();
+                let conflict_action = if updates.is_empty() {
+                    "do nothing".to_string()
+                } else {
+                    format!("do update set {}", updates.join(", "))
+                };
+                query.push_str(&format!(
+                    " on conflict ({}) {conflict_action}",
+                    Self::column_list(schema, key_indexes)
+                ));
+            }
+            Self::execute(client, &query, &params).await?;
+        }
+        Ok(())
+    }
+
+    /// Upserts rows keyed on the lookup key. Only the last row for each key is
+    /// kept as a statement can't affect the same row twice, with the unchanged
+    /// TOAST values it lacks taken from the earlier rows. Rows are upserted in
+    /// groups with the same unchanged TOAST columns, which aren't updated.
+    async fn upsert_rows<C: GenericClient>(
+        client: &C,
+        schema: &TableSchema,
+        rows: &[TableRow],
+        key_indexes: &[usize],
+    ) -> Result<(), PostgresSinkError> {
+        let mut last_for_key: HashMap<Vec<Option<String>>, TableRow> = HashMap::new();
+        for row in rows {
+            let key: Vec<Option<String>> = key_indexes
+                .iter()
+                .map(|&i| TextFormatConverter::to_text(&row.values[i]))
+                .collect();
+            let mut row = row.clone();
+            if let Some(earlier) = last_for_key.get(&key) {
+                row.unchanged_toast.retain(|&i| {
+                    if earlier.unchanged_toast.contains(&i) {
+                        return true;
+                    }
+                    row.values[i] = earlier.values[i].clone();
+                    false
+                });
+            }
+            last_for_key.insert(key, row);
+        }
+        let mut by_unchanged_toast: HashMap<&[usize], Vec<&TableRow>> = HashMap::new();
+        for row in last_for_key.values() {
+            by_unchanged_toast
+                .entry(&row.unchanged_toast)
+                .or_default()
+                .push(row);
+        }
+        for (unchanged_toast, rows) in by_unchanged_toast {
+            Self::insert_rows(client, schema, &rows, Some(key_indexes), unchanged_toast).await?;
+        }
+        Ok(())
+    }
+
+    async fn delete_rows<C: GenericClient>(
+        client: &C,
+        schema: &TableSchema,
+        rows: &[TableRow],
+        key_indexes: &[usize],
+    ) -> Result<(), PostgresSinkError> {
+        let rows: Vec<&TableRow> = rows.iter().collect();
+        let rows_per_statement = MAX_PARAMS / key_indexes.len().max(1);
+        for chunk in rows.chunks(rows_per_statement) {
+            let mut params = vec![];
+            let key_match = Self::key_match(schema, key_indexes, chunk, &mut params);
+            let query = format!(
+                "delete from {} where {key_match}",
+                schema.table_name.as_quoted_identifier(),
+            );
+            Self::execute(client, &query, &params).await?;
+        }
+        Ok(())
+    }
+
+    /// Deletes a single row matching all column values of each row, for tables
+    /// without a lookup key which may contain duplicate rows
+    async fn delete_full_rows<C: GenericClient>(
+        client: &C,
+        schema: &TableSchema,
+        rows: &[TableRow],
+    ) -> Result<(), PostgresSinkError> {
+        let table_name = schema.table_name.as_quoted_identifier();
+        for row in rows {
+            let mut params = vec![];
+            let conditions: Vec<String> = schema
+                .column_schemas
+                .iter()
+                .zip(&row.values)
+                .map(|(column, value)| {
+                    params.push(TextFormatConverter::to_text(value));
+                    let name = quote_identifier(&column.name);
+                    // json has no equality operator
+                    if column.typ == Type::JSON {
+                        format!("{name}::text is not distinct from ${}", params.len())
+                    } else {
+                        let placeholder = Self::placeholder(params.len(), &column.typ);
+                        format!("{name} is not distinct from {placeholder}")
+                    }
+                })
+                .collect();
+            let query = format!(
+                "delete from {table_name} where ctid = (select ctid from {table_name} where {} limit 1)",
+                conditions.join(" and ")
+            );
+            Self::execute(client, &query, &params).await?;
+        }
+        Ok(())
+    }
+
+    #[instrument(skip_all, fields(table_id = batch.table_id, kind = ?batch.kind, rows = batch.rows.len()))]
+    async fn apply_batch<C: GenericClient>(
+        client: &C,
+        schema: &TableSchema,
+        batch: &Batch,
+    ) -> Result<(), PostgresSinkError> {
+        let key_indexes = Self::key_column_indexes(schema)?;
+        match (batch.kind, key_indexes) {
+            (BatchKind::Upsert, Some(key_indexes)) => {
+                Self::upsert_rows(client, schema, &batch.rows, &key_indexes).await
+            }
+            (BatchKind::Insert | BatchKind::Upsert, None) => {
+                let rows: Vec<&TableRow> = batch.rows.iter().collect();
+                Self::insert_rows(client, schema, &rows, None, &[]).await
+            }
+            (BatchKind::Insert, Some(_)) => Err(PostgresSinkError::KeyedInsert(batch.table_id)),
+            (BatchKind::Delete, Some(key_indexes)) => {
+                Self::delete_rows(client, schema, &batch.rows, &key_indexes).await
+            }
+            (BatchKind::Delete, None) => Self::delete_full_rows(client, schema, &batch.rows).await,
+        }
+    }
+
+    /// Splits events into runs of changes of the same kind to the same table
+    fn batch_events(
+        &self,
+        events: Vec<CdcEvent>,
+    ) -> Result<(Vec<Batch>, Option<PgLsn>), PostgresSinkError> {
+        let mut batches: Vec<Batch> = vec![];
+        let mut last_lsn = None;
+        let mut push = |table_id: TableId, kind: BatchKind, row: TableRow| match batches.last_mut()
+        {
+            Some(batch) if batch.table_id == table_id && batch.kind == kind => batch.rows.push(row),
+            _ => batches.push(Batch {
+                table_id,
+                kind,
+                rows: vec![row],
+            }),
+        };
+        for event in events {
+            match event {
+                CdcEvent::Insert((table_id, row, _)) => {
+                    let schema = self.get_table_schema(table_id)?;
+                    let kind = match schema.lookup_key {
+                        LookupKey::Key { .. } => BatchKind::Upsert,
+                        LookupKey::FullRow => BatchKind::Insert,
+                    };
+                    push(table_id, kind, row);
+                }
+                CdcEvent::Update((table_id, old_row, new_row, _)) => {
+                    let schema = self.get_table_schema(table_id)?;
+                    match schema.lookup_key {
+                        LookupKey::Key { .. } => {
+                            // An old row is only sent when the key columns changed
+                            if let Some(old_row) = old_row {
+                                push(table_id, BatchKind::Delete, old_row);
+                            }
+                            push(table_id, BatchKind::Upsert, new_row);
+                        }
+                        LookupKey::FullRow => {
+                            let old_row =
+                                old_row.ok_or(PostgresSinkError::MissingOldRow(table_id))?;
+                            push(table_id, BatchKind::Delete, old_row);
+                            push(table_id, BatchKind::Insert, new_row);
+                        }
+                    }
+                }
+                CdcEvent::Delete((table_id, row, _)) => push(table_id, BatchKind::Delete, row),
+                CdcEvent::Commit(commit_body) => {
+                    last_lsn = Some(commit_body.end_lsn().into());
+                }
+                CdcEvent::StreamCommit(stream_commit_body) => {
+                    last_lsn = Some(stream_commit_body.end_lsn().into());
+                }
+                // The changes of aborted streamed transactions have been dropped
+                // by `StreamedTransactions` already
+                CdcEvent::Begin(_)
+                | CdcEvent::Relation(_)
+                | CdcEvent::Type(_)
+                | CdcEvent::Schema(_)
+                | CdcEvent::TableDropped { .. }
+                | CdcEvent::KeepAliveRequested { .. }
+                | CdcEvent::Heartbeat { .. }
+                | CdcEvent::StreamStart(_)
+                | CdcEvent::StreamStop(_)
+                | CdcEvent::StreamAbort(_) => {}
+            }
+        }
+        Ok((batches, last_lsn))
+    }
+
+    /// Applies the changes of `events` and stores the lsn of their last commit in
+    /// one transaction, which is run again after a delay if it deadlocks
+    async fn apply_events(&mut self, events: Vec<CdcEvent>) -> Result<PgLsn, PostgresSinkError> {
+        let (batches, commit_lsn) = self.batch_events(events)?;
+        if let Some(lsn) = commit_lsn {
+            Span::current().record("lsn", tracing::field::display(lsn));
+        }
+        let mut attempt = 1;
+        loop {
+            match self.apply_batches(&batches, commit_lsn).await {
+                Err(PostgresSinkError::TokioPostgres(e))
+                    if e.code() == Some(&SqlState::T_R_DEADLOCK_DETECTED)
+                        && attempt < self.max_deadlock_attempts =>
+                {
+                    let delay = self.deadlock_retry_delay(attempt);
+                    warn!("applying cdc events deadlocked (attempt {attempt}), retrying in {delay:?}: {e}");
+                    tokio::time::sleep(delay).await;
+                    attempt += 1;
+                }
+                result => return result,
+            }
+        }
+    }
+
+    async fn apply_batches(
+        &mut self,
+        batches: &[Batch],
+        commit_lsn: Option<PgLsn>,
+    ) -> Result<PgLsn, PostgresSinkError> {
+        let transaction = self.client.transaction().await?;
+        for batch in batches {
+            let schema = self
+                .table_schemas
+                .get(&batch.table_id)
+                .ok_or(PostgresSinkError::MissingTableSchema(batch.table_id))?;
+            Self::apply_batch(&transaction, schema, batch).await?;
+        }
+        let last_lsn = match commit_lsn {
+            Some(lsn) => lsn,
+            None => Self::get_last_lsn(&transaction).await?,
+        };
+        transaction
+            .execute(
+                "insert into replicate.last_lsn (id, lsn) values (1, $1)
+                on conflict (id) do update set lsn = excluded.lsn",
+                &[&last_lsn],
+            )
+            .await?;
+        transaction.commit().await?;
+        Ok(last_lsn)
+    }
+
+    async fn get_last_lsn<C: GenericClient>(client: &C) -> Result<PgLsn, PostgresSinkError> {
+        let row = client
+            .query_opt("select lsn from replicate.last_lsn where id = 1", &[])
+            .await?;
+        Ok(match row {
+            Some(row) => row.try_get(0)?,
+            None => PgLsn::from(0),
+        })
+    }
+}
+
+#[async_trait]
+impl BatchSink for PostgresSink {
+    type Error = PostgresSinkError;
+
+    async fn get_resumption_state(&mut self) -> Result<PipelineResumptionState, Self::Error> {
+        let copied_tables = self
+            .client
+            .query("select table_id from replicate.copied_tables", &[])
+            .await?
+            .iter()
+            .map(|row| row.try_get::<_, i64>(0).map(|id| id as TableId))
+            .collect::<Result<HashSet<_>, _>>()?;
+        let last_lsn = Self::get_last_lsn(&self.client).await?;
+        let backfill_cursors = self
+            .client
+            .query("select table_id, key from replicate.backfill_cursors", &[])
+            .await?
+            .iter()
+            .map(|row| Ok((row.try_get::<_, i64>(0)? as TableId, row.try_get(1)?)))
+            .collect::<Result<HashMap<_, _>, tokio_postgres::Error>>()?;
+        let slot_name = self
+            .client
+            .query_opt(
+                "select slot_name from replicate.slot_name where id = 1",
+                &[],
+            )
+            .await?
+            .map(|row| row.try_get(0))
+            .transpose()?;
+        Ok(PipelineResumptionState {
+            copied_tables,
+            last_lsn,
+            backfill_cursors,
+            slot_name,
+        })
+    }
+
+    async fn write_table_schemas(
+        &mut self,
+        table_schemas: HashMap<TableId, TableSchema>,
+    ) -> Result<(), Self::Error> {
+        for (table_id, schema) in &table_schemas {
+            let create_schema = format!(
+                "create schema if not exists {}",
+                quote_identifier(&schema.table_name.schema)
+            );
+            self.client.execute(&create_schema, &[]).await?;
+            self.client
+                .execute(&Self::create_table_query(schema)?, &[])
+                .await?;
+            if let Some(previous) = self.table_schemas.get(table_id) {
+                for query in Self::alter_table_queries(schema, previous) {
+                    self.client.execute(&query, &[]).await?;
+                }
+            }
+        }
+        self.table_schemas.extend(table_schemas);
+        Ok(())
+    }
+
+    async fn write_table_rows(
+        &mut self,
+        rows: Vec<TableRow>,
+        table_id: TableId,
+    ) -> Result<(), Self::Error> {
+        let schema = self
+            .table_schemas
+            .get(&table_id)
+            .ok_or(PostgresSinkError::MissingTableSchema(table_id))?;
+        let rows: Vec<&TableRow> = rows.iter().collect();
+        // The rows may take several statements, which are made atomic so that a
+        // failed write can be retried
+        let transaction = self.client.transaction().await?;
+        Self::insert_rows(&transaction, schema, &rows, None, &[]).await?;
+        transaction.commit().await?;
+        Ok(())
+    }
+
+    async fn write_table_rows_with_cursor(
+        &mut self,
+        rows: Vec<TableRow>,
+        table_id: TableId,
+        cursor: Vec<String>,
+    ) -> Result<(), Self::Error> {
+        let schema = self
+            .table_schemas
+            .get(&table_id)
+            .ok_or(PostgresSinkError::MissingTableSchema(table_id))?;
+        let rows: Vec<&TableRow> = rows.iter().collect();
+        let transaction = self.client.transaction().await?;
+        Self::insert_rows(&transaction, schema, &rows, None, &[]).await?;
+        transaction
+            .execute(
+                "insert into replicate.backfill_cursors (table_id, key) values ($1, $2)
+                on conflict (table_id) do update set key = excluded.key",
+                &[&(table_id as i64), &cursor],
+            )
+            .await?;
+        transaction.commit().await?;
+        Ok(())
+    }
+
+    #[instrument(skip_all, fields(events = events.len(), lsn))]
+    async fn write_cdc_events(&mut self, events: Vec<CdcEvent>) -> Result<PgLsn, Self::Error> {
+        let events = self.streamed.hold(events);
+        let result = self.apply_events(events).await;
+        if result.is_err() {
+            self.streamed.rollback();
+        }
+        result
+    }
+
+    async fn table_copied(&mut self, table_id: TableId) -> Result<(), Self::Error> {
+        self.client
+            .execute(
+                "insert into replicate.copied_tables (table_id) values ($1) on conflict do nothing",
+                &[&(table_id as i64)],
+            )
+            .await?;
+        self.client
+            .execute(
+                "delete from replicate.backfill_cursors where table_id = $1",
+                &[&(table_id as i64)],
+            )
+            .await?;
+        Ok(())
+    }
+
+    async fn truncate_table(&mut self, table_id: TableId) -> Result<(), Self::Error> {
+        let schema = self.get_table_schema(table_id)?;
+        let query = format!(
+            "truncate table {}",
+            schema.table_name.as_quoted_identifier()
+        );
+        self.client.execute(&query, &[]).await?;
+        self.client
+            .execute(
+                "delete from replicate.copied_tables where table_id = $1",
+                &[&(table_id as i64)],
+            )
+            .await?;
+        self.client
+            .execute(
+                "delete from replicate.backfill_cursors where table_id = $1",
+                &[&(table_id as i64)],
+            )
+            .await?;
+        Ok(())
+    }
+
+    async fn write_slot_name(&mut self, slot_name: &str) -> Result<(), Self::Error> {
+        self.client
+            .execute(
+                "insert into replicate.slot_name (id, slot_name) values (1, $1)
+                on conflict (id) do update set slot_name = excluded.slot_name",
+                &[&slot_name],
+            )
+            .await?;
+        Ok(())
+    }
+
+    /// Rows and cdc events are each written in a transaction
+    fn supports_retries(&self) -> bool {
+        true
+    }
+}
@@ -21,6 +21,8 @@ impl BatchSink for StdoutSink {
         Ok(PipelineResumptionState {
             copied_tables: HashSet::new(),
             last_lsn: PgLsn::from(0),
+            backfill_cursors: HashMap::new(),
+            slot_name: None,
         })
     }
 
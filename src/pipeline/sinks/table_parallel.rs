@@ -0,0 +1,298 @@
+use std::collections::HashMap;
+
+use async_trait::async_trait;
+use futures::future::join_all;
+use thiserror::Error;
+use tokio_postgres::types::PgLsn;
+
+use crate::{
+    conversions::{
+        cdc_event::{CdcEvent, CdcEventConversionError},
+        coercion::CoercionTable,
+        table_row::TableRow,
+    },
+    pipeline::PipelineResumptionState,
+    table::{TableId, TableSchema},
+};
+
+use super::{BatchSink, SinkError};
+
+#[derive(Debug, Error)]
+pub enum TableParallelSinkError<E: SinkError> {
+    #[error("worker {0} failed: {1}")]
+    Worker(usize, #[source] E),
+
+    #[error("table-parallel sink has no workers")]
+    NoWorkers,
+
+    #[error("failed to copy cdc event: {0}")]
+    CopyEvent(#[from] CdcEventConversionError),
+}
+
+impl<E: SinkError> SinkError for TableParallelSinkError<E> {}
+
+/// A sink which applies the changes of different tables concurrently, spread over
+/// several workers, e.g. connections to a sink which supports concurrent writes to
+/// different tables. Every table belongs to one worker, which gets all of the table's
+/// rows and changes in lsn order, so the parallelism is the number of workers.
+///
+/// Every worker gets the begin, commit and other events not belonging to a table of
+/// all transactions, and the lowest lsn any worker wrote up to is confirmed. Workers
+/// must keep their own resumption state, as they commit independently: the sink
+/// resumes from the lowest lsn of all workers and knows a table to be copied if its
+/// worker does.
+///
+/// Changes are therefore applied at least once: after a restart, workers which had
+/// written further than the slowest one get the changes between the two lsns again.
+/// Workers must tolerate these duplicates, e.g. by skipping transactions whose
+/// commit lsn isn't past their own resumption lsn, or by applying changes
+/// idempotently like upserts by key.
+pub struct TableParallelSink<S: BatchSink> {
+    workers: Vec<S>,
+    consistent: bool,
+}
+
+impl<S: BatchSink + Send> TableParallelSink<S> {
+    pub fn new(workers: Vec<S>) -> TableParallelSink<S> {
+        TableParallelSink {
+            workers,
+            consistent: false,
+        }
+    }
+
+    /// Makes the workers apply changes one transaction at a time: the changes of a
+    /// transaction to different tables are still written concurrently, but the next
+    /// transaction is only passed to the workers once all of them wrote the previous
+    /// one. Without this a worker can be ahead of others by up to a batch, so reads
+    /// across tables can see part of a transaction.
+    pub fn set_consistent(&mut self, consistent: bool) {
+        self.consistent = consistent;
+    }
+
+    pub fn parallelism(&self) -> usize {
+        self.workers.len()
+    }
+
+    /// The index of the worker applying a table's changes
+    pub fn worker_of(&self, table_id: TableId) -> usize {
+        table_id as usize % self.workers.len().max(1)
+    }
+
+    fn check_workers(&self) -> Result<(), TableParallelSinkError<S::Error>> {
+        if self.workers.is_empty() {
+            return Err(TableParallelSinkError::NoWorkers);
+        }
+        Ok(())
+    }
+
+    /// Splits events by worker, with events not belonging to a table copied to all
+    fn split(
+        &self,
+        events: Vec<CdcEvent>,
+    ) -> Result<Vec<Vec<CdcEvent>>, TableParallelSinkError<S::Error>> {
+        let mut split: Vec<Vec<CdcEvent>> = self.workers.iter().map(|_| vec![]).collect();
+        for event in events {
+            let table_id = match &event {
+                CdcEvent::Insert((table_id, _, _))
+                | CdcEvent::Update((table_id, _, _, _))
+                | CdcEvent::Delete((table_id, _, _)) => Some(*table_id),
+                _ => None,
+            };
+            match table_id {
+                Some(table_id) => split[self.worker_of(table_id)].push(event),
+                None => {
+                    let (last, others) = split.split_last_mut().expect("no workers");
+                    for events in others {
+                        events.push(event.try_clone()?);
+                    }
+                    last.push(event);
+                }
+            }
+        }
+        Ok(split)
+    }
+
+    async fn dispatch_cdc_events(
+        &mut self,
+        events: Vec<CdcEvent>,
+    ) -> Result<PgLsn, TableParallelSinkError<S::Error>> {
+        let split = self.split(events)?;
+        let results = join_all(
+            self.workers
+                .iter_mut()
+                .zip(split)
+                .map(|(worker, events)| worker.write_cdc_events(events)),
+        )
+        .await;
+        let mut lsn = None;
+        for (index, result) in results.into_iter().enumerate() {
+            let worker_lsn = result.map_err(|e| TableParallelSinkError::Worker(index, e))?;
+            lsn = Some(lsn.map_or(worker_lsn, |lsn: PgLsn| lsn.min(worker_lsn)));
+        }
+        lsn.ok_or(TableParallelSinkError::NoWorkers)
+    }
+}
+
+/// Splits events after each event which completes a transaction
+fn transactions(events: Vec<CdcEvent>) -> Vec<Vec<CdcEvent>> {
+    let mut transactions = vec![];
+    let mut transaction = vec![];
+    for event in events {
+        let completes = match &event {
+            CdcEvent::Commit(_) | CdcEvent::StreamCommit(_) => true,
+            // Aborts of subtransactions leave the transaction open
+            CdcEvent::StreamAbort(body) => body.xid() == body.subxid(),
+            _ => false,
+        };
+        transaction.push(event);
+        if completes {
+            transactions.push(std::mem::take(&mut transaction));
+        }
+    }
+    if !transaction.is_empty() {
+        transactions.push(transaction);
+    }
+    transactions
+}
+
+#[async_trait]
+impl<S: BatchSink + Send> BatchSink for TableParallelSink<S> {
+    type Error = TableParallelSinkError<S::Error>;
+
+    async fn get_resumption_state(&mut self) -> Result<PipelineResumptionState, Self::Error> {
+        self.check_workers()?;
+        let results = join_all(
+            self.workers
+                .iter_mut()
+                .map(|worker| worker.get_resumption_state()),
+        )
+        .await;
+        let parallelism = self.workers.len();
+        let mut merged: Option<PipelineResumptionState> = None;
+        for (index, result) in results.into_iter().enumerate() {
+            let state = result.map_err(|e| TableParallelSinkError::Worker(index, e))?;
+            let owned = |table_id: &TableId| *table_id as usize % parallelism == index;
+            let copied_tables = state.copied_tables.into_iter().filter(owned);
+            let backfill_cursors = state
+                .backfill_cursors
+                .into_iter()
+                .filter(|(table_id, _)| owned(table_id));
+            match merged.as_mut() {
+                Some(merged) => {
+                    merged.copied_tables.extend(copied_tables);
+                    merged.backfill_cursors.extend(backfill_cursors);
+                    merged.last_lsn = merged.last_lsn.min(state.last_lsn);
+                    merged.slot_name = merged.slot_name.take().or(state.slot_name);
+                }
+                None => {
+                    merged = Some(PipelineResumptionState {
+                        copied_tables: copied_tables.collect(),
+                        last_lsn: state.last_lsn,
+                        backfill_cursors: backfill_cursors.collect(),
+                        slot_name: state.slot_name,
+                    })
+                }
+            }
+        }
+        merged.ok_or(TableParallelSinkError::NoWorkers)
+    }
+
+    async fn write_table_schemas(
+        &mut self,
+        table_schemas: HashMap<TableId, TableSchema>,
+    ) -> Result<(), Self::Error> {
+        self.check_workers()?;
+        let results = join_all(
+            self.workers
+                .iter_mut()
+                .map(|worker| worker.write_table_schemas(table_schemas.clone())),
+        )
+        .await;
+        for (index, result) in results.into_iter().enumerate() {
+            result.map_err(|e| TableParallelSinkError::Worker(index, e))?;
+        }
+        Ok(())
+    }
+
+    async fn write_table_rows(
+        &mut self,
+        rows: Vec<TableRow>,
+        table_id: TableId,
+    ) -> Result<(), Self::Error> {
+        self.check_workers()?;
+        let index = self.worker_of(table_id);
+        self.workers[index]
+            .write_table_rows(rows, table_id)
+            .await
+            .map_err(|e| TableParallelSinkError::Worker(index, e))
+    }
+
+    async fn write_table_rows_with_cursor(
+        &mut self,
+        rows: Vec<TableRow>,
+        table_id: TableId,
+        cursor: Vec<String>,
+    ) -> Result<(), Self::Error> {
+        self.check_workers()?;
+        let index = self.worker_of(table_id);
+        self.workers[index]
+            .write_table_rows_with_cursor(rows, table_id, cursor)
+            .await
+            .map_err(|e| TableParallelSinkError::Worker(index, e))
+    }
+
+    async fn write_cdc_events(&mut self, events: Vec<CdcEvent>) -> Result<PgLsn, Self::Error> {
+        self.check_workers()?;
+        if !self.consistent {
+            return self.dispatch_cdc_events(events).await;
+        }
+        let mut lsn = None;
+        for transaction in transactions(events) {
+            lsn = Some(self.dispatch_cdc_events(transaction).await?);
+        }
+        match lsn {
+            Some(lsn) => Ok(lsn),
+            None => self.dispatch_cdc_events(vec![]).await,
+        }
+    }
+
+    async fn table_copied(&mut self, table_id: TableId) -> Result<(), Self::Error> {
+        self.check_workers()?;
+        let index = self.worker_of(table_id);
+        self.workers[index]
+            .table_copied(table_id)
+            .await
+            .map_err(|e| TableParallelSinkError::Worker(index, e))
+    }
+
+    async fn truncate_table(&mut self, table_id: TableId) -> Result<(), Self::Error> {
+        self.check_workers()?;
+        let index = self.worker_of(table_id);
+        self.workers[index]
+            .truncate_table(table_id)
+            .await
+            .map_err(|e| TableParallelSinkError::Worker(index, e))
+    }
+
+    async fn write_slot_name(&mut self, slot_name: &str) -> Result<(), Self::Error> {
+        self.check_workers()?;
+        let results = join_all(
+            self.workers
+                .iter_mut()
+                .map(|worker| worker.write_slot_name(slot_name)),
+        )
+        .await;
+        for (index, result) in results.into_iter().enumerate() {
+            result.map_err(|e| TableParallelSinkError::Worker(index, e))?;
+        }
+        Ok(())
+    }
+
+    /// The coercions of the first worker, as all workers are the same kind of sink
+    fn coercions(&self) -> CoercionTable {
+        self.workers
+            .first()
+            .map(BatchSink::coercions)
+            .unwrap_or_default()
+    }
+}
@@ -0,0 +1,358 @@
+use std::{
+    collections::HashMap,
+    future::Future,
+    sync::{
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+        Arc,
+    },
+};
+
+use futures::future::try_join_all;
+use thiserror::Error;
+use tokio_postgres::types::PgLsn;
+use tracing::{info, instrument, warn};
+
+use crate::{
+    clients::postgres::{ReplicationClient, ReplicationClientError},
+    table::{TableId, TableName, TableSchema},
+};
+
+use super::postgres::{CdcStream, TableCopyStream};
+
+#[derive(Debug, Error)]
+pub enum SnapshotBackfillError<E: std::error::Error + 'static> {
+    #[error("replication client error: {0}")]
+    ReplicationClient(#[from] ReplicationClientError),
+
+    #[error("table copy error: {0}")]
+    TableCopy(#[source] E),
+}
+
+/// A table whose row count differs from the number of rows copied by a backfill
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RowCountMismatch {
+    pub table_name: TableName,
+    pub source_rows: u64,
+    pub copied_rows: u64,
+}
+
+/// Copies several tables as of the same point in time, the consistent point of a
+/// newly created slot, so that a cdc stream started from that point continues the
+/// copies without gaps or overlaps. Tables are copied one after the other on the slot's
+/// connection, or concurrently across the connections added with
+/// [`SnapshotBackfill::add_copy_client`], which import the slot's exported snapshot.
+pub struct SnapshotBackfill {
+    slot_client: ReplicationClient,
+    copy_clients: Vec<ReplicationClient>,
+    copy_leaf_partitions: bool,
+    rows_copied: HashMap<TableId, Arc<AtomicU64>>,
+}
+
+impl SnapshotBackfill {
+    /// `slot_client` must be a connection in logical replication mode
+    pub fn new(slot_client: ReplicationClient) -> SnapshotBackfill {
+        SnapshotBackfill {
+            slot_client,
+            copy_clients: vec![],
+            copy_leaf_partitions: false,
+            rows_copied: HashMap::new(),
+        }
+    }
+
+    /// Adds a connection to copy tables on, concurrently with the other copy clients
+    pub fn add_copy_client(&mut self, copy_client: ReplicationClient) {
+        self.copy_clients.push(copy_client);
+    }
+
+    /// Copies partitioned tables partition by partition, so that the copy clients can
+    /// copy the partitions of one table concurrently. `copy_table` is then called once
+    /// per leaf partition, with the schema of the partitioned table the rows belong to.
+    pub fn set_copy_leaf_partitions(&mut self, copy_leaf_partitions: bool) {
+        self.copy_leaf_partitions = copy_leaf_partitions;
+    }
+
+    /// Returns the schema of the table with id `table_id`, e.g. of a table added to
+    /// the publication of a running stream, queried on the slot's connection
+    pub async fn get_table_schema(
+        &self,
+        table_id: TableId,
+        publication: Option<&str>,
+    ) -> Result<TableSchema, ReplicationClientError> {
+        self.slot_client
+            .get_table_schema_by_id(table_id, publication)
+            .await
+    }
+
+    /// Returns the slot's connection, e.g. to start the cdc stream after the backfill
+    pub fn into_slot_client(self) -> ReplicationClient {
+        self.slot_client
+    }
+
+    /// Creates the slot `slot_name` and calls `copy_table` with a copy stream of every
+    /// table in `table_schemas`, returning the slot's consistent point once all copies
+    /// are done. The slot must not exist yet, as an existing slot's position can't be
+    /// matched with a snapshot. A copy fails the backfill, leaving the slot in place.
+    pub async fn run<F, Fut, E>(
+        &mut self,
+        slot_name: &str,
+        table_schemas: &[TableSchema],
+        copy_table: F,
+    ) -> Result<PgLsn, SnapshotBackfillError<E>>
+    where
+        F: Fn(TableSchema, TableCopyStream) -> Fut,
+        Fut: Future<Output = Result<(), E>>,
+        E: std::error::Error + 'static,
+    {
+        self.backfill(slot_name, false, table_schemas, copy_table)
+            .await
+    }
+
+    /// Copies tables added to the publication of an already running cdc stream. The
+    /// copies are made as of the consistent point of the temporary slot `slot_name`,
+    /// which is dropped again once they are done. Returns the consistent point, from
+    /// which on the running stream has to apply the tables' changes, see
+    /// [`CdcStream::add_table`]. The tables must have been added to the publication
+    /// before, so that the stream has all changes committed after the consistent point.
+    /// The stream must not be read from the creation of the slot until the tables are
+    /// added to it, as it can't decode the tables' changes before, see
+    /// [`BatchDataPipeline::set_added_table_backfill`](crate::pipeline::batching::data_pipeline::BatchDataPipeline::set_added_table_backfill).
+    /// Tables created in a schema the publication includes as a whole are added
+    /// automatically, see [`ReplicationClient::get_publication_schemas`].
+    pub async fn backfill_added_tables<F, Fut, E>(
+        &mut self,
+        slot_name: &str,
+        table_schemas: &[TableSchema],
+        copy_table: F,
+    ) -> Result<PgLsn, SnapshotBackfillError<E>>
+    where
+        F: Fn(TableSchema, TableCopyStream) -> Fut,
+        Fut: Future<Output = Result<(), E>>,
+        E: std::error::Error + 'static,
+    {
+        let consistent_point = self
+            .backfill(slot_name, true, table_schemas, copy_table)
+            .await?;
+        self.slot_client.drop_slot(slot_name).await?;
+        Ok(consistent_point)
+    }
+
+    #[instrument(skip(self, table_schemas, copy_table))]
+    async fn backfill<F, Fut, E>(
+        &mut self,
+        slot_name: &str,
+        temporary: bool,
+        table_schemas: &[TableSchema],
+        copy_table: F,
+    ) -> Result<PgLsn, SnapshotBackfillError<E>>
+    where
+        F: Fn(TableSchema, TableCopyStream) -> Fut,
+        Fut: Future<Output = Result<(), E>>,
+        E: std::error::Error + 'static,
+    {
+        self.rows_copied = table_schemas
+            .iter()
+            .map(|table_schema| (table_schema.table_id, Arc::new(AtomicU64::new(0))))
+            .collect();
+
+        if self.copy_clients.is_empty() {
+            let slot_info = self
+                .slot_client
+                .create_slot_using_snapshot(slot_name, temporary)
+                .await?;
+            info!(
+                "created slot {slot_name} at {}, copying {} tables",
+                slot_info.confirmed_flush_lsn,
+                table_schemas.len()
+            );
+            let copies = self.copies(&self.slot_client, table_schemas).await?;
+            for (table_schema, source_table_name) in copies {
+                Self::copy(
+                    &self.slot_client,
+                    table_schema,
+                    &source_table_name,
+                    &self.rows_copied[&table_schema.table_id],
+                    &copy_table,
+                )
+                .await?;
+            }
+            self.slot_client.commit_txn().await?;
+            return Ok(slot_info.confirmed_flush_lsn);
+        }
+
+        let (slot_info, snapshot_name) = self
+            .slot_client
+            .create_slot_exporting_snapshot(slot_name, temporary)
+            .await?;
+        info!(
+            "created slot {slot_name} at {} with snapshot {snapshot_name}, copying {} tables on {} connections",
+            slot_info.confirmed_flush_lsn,
+            table_schemas.len(),
+            self.copy_clients.len()
+        );
+        // The exported snapshot is only valid until the slot's connection is used again
+        for copy_client in self.copy_clients.iter_mut() {
+            copy_client
+                .begin_readonly_transaction_with_snapshot(&snapshot_name)
+                .await?;
+        }
+
+        let copies = self.copies(&self.copy_clients[0], table_schemas).await?;
+
+        // Every copy client takes the next table not yet taken until none are left
+        let next_table = AtomicUsize::new(0);
+        let copy_table = &copy_table;
+        let copies = &copies;
+        let rows_copied = &self.rows_copied;
+        try_join_all(self.copy_clients.iter_mut().map(|copy_client| {
+            let next_table = &next_table;
+            async move {
+                while let Some((table_schema, source_table_name)) =
+                    copies.get(next_table.fetch_add(1, Ordering::Relaxed))
+                {
+                    Self::copy(
+                        copy_client,
+                        table_schema,
+                        source_table_name,
+                        &rows_copied[&table_schema.table_id],
+                        copy_table,
+                    )
+                    .await?;
+                }
+                copy_client.commit_txn().await?;
+                Ok::<(), SnapshotBackfillError<E>>(())
+            }
+        }))
+        .await?;
+
+        Ok(slot_info.confirmed_flush_lsn)
+    }
+
+    /// Backfills like [`SnapshotBackfill::run`] and then starts a cdc stream of
+    /// `publication` from the slot's consistent point. The stream thus holds exactly
+    /// the changes committed after the snapshot the tables were copied from, with no
+    /// gap or overlap with the copies. The stream runs on the slot's connection, so this
+    /// backfill must be kept alive while the stream is consumed.
+    pub async fn snapshot_and_stream<F, Fut, E>(
+        &mut self,
+        slot_name: &str,
+        publication: &str,
+        table_schemas: &[TableSchema],
+        copy_table: F,
+    ) -> Result<(PgLsn, CdcStream), SnapshotBackfillError<E>>
+    where
+        F: Fn(TableSchema, TableCopyStream) -> Fut,
+        Fut: Future<Output = Result<(), E>>,
+        E: std::error::Error + 'static,
+    {
+        let consistent_point = self.run(slot_name, table_schemas, copy_table).await?;
+        info!("backfill done, starting cdc stream at {consistent_point}");
+        let stream = self
+            .slot_client
+            .get_logical_replication_stream(publication, slot_name, consistent_point)
+            .await?;
+        let table_schemas = table_schemas
+            .iter()
+            .map(|table_schema| (table_schema.table_id, table_schema.clone()))
+            .collect();
+        Ok((consistent_point, CdcStream::new(stream, table_schemas)))
+    }
+
+    /// Compares the number of rows copied of every table in the last backfill with the
+    /// table's current row count and returns the tables for which they differ by more
+    /// than `tolerance`. Changes committed after the consistent point are in the cdc
+    /// stream instead of the copies, so `tolerance` should allow for the rows inserted or
+    /// deleted since. The rows are counted on the first copy client or, without copy
+    /// clients, on the slot's connection, which then must not be streaming.
+    pub async fn verify_row_counts(
+        &self,
+        table_schemas: &[TableSchema],
+        tolerance: u64,
+    ) -> Result<Vec<RowCountMismatch>, ReplicationClientError> {
+        let client = self.copy_clients.first().unwrap_or(&self.slot_client);
+        let mut mismatches = vec![];
+        for table_schema in table_schemas {
+            let copied_rows = self
+                .rows_copied
+                .get(&table_schema.table_id)
+                .map_or(0, |rows_copied| rows_copied.load(Ordering::Relaxed));
+            let source_rows = client.count_rows(&table_schema.table_name, None).await?;
+            if source_rows.abs_diff(copied_rows) > tolerance {
+                warn!(
+                    "table {} has {source_rows} rows but {copied_rows} were copied",
+                    table_schema.table_name
+                );
+                mismatches.push(RowCountMismatch {
+                    table_name: table_schema.table_name.clone(),
+                    source_rows,
+                    copied_rows,
+                });
+            }
+        }
+        info!(
+            "verified row counts of {} tables, {} mismatches",
+            table_schemas.len(),
+            mismatches.len()
+        );
+        Ok(mismatches)
+    }
+
+    /// Pairs every table with the table to copy its rows from, which is either the
+    /// table itself or, if leaf partitions are copied, each of its leaf partitions.
+    /// Fails if a table's id doesn't resolve to its name in the snapshot anymore, see
+    /// [`ReplicationClient::verify_table_id`].
+    async fn copies<'a>(
+        &self,
+        client: &ReplicationClient,
+        table_schemas: &'a [TableSchema],
+    ) -> Result<Vec<(&'a TableSchema, TableName)>, ReplicationClientError> {
+        let mut copies = vec![];
+        for table_schema in table_schemas {
+            client
+                .verify_table_id(table_schema.table_id, &table_schema.table_name)
+                .await?;
+            let leaf_partitions = if self.copy_leaf_partitions {
+                client.get_leaf_partitions(table_schema.table_id).await?
+            } else {
+                vec![]
+            };
+            if leaf_partitions.is_empty() {
+                copies.push((table_schema, table_schema.table_name.clone()));
+            } else {
+                copies.extend(
+                    leaf_partitions
+                        .into_iter()
+                        .map(|leaf_partition| (table_schema, leaf_partition)),
+                );
+            }
+        }
+        Ok(copies)
+    }
+
+    #[instrument(skip_all, fields(
+        table_id = table_schema.table_id,
+        table = %table_schema.table_name,
+        source_table = %source_table_name,
+    ))]
+    async fn copy<F, Fut, E>(
+        client: &ReplicationClient,
+        table_schema: &TableSchema,
+        source_table_name: &TableName,
+        rows_copied: &Arc<AtomicU64>,
+        copy_table: &F,
+    ) -> Result<(), SnapshotBackfillError<E>>
+    where
+        F: Fn(TableSchema, TableCopyStream) -> Fut,
+        Fut: Future<Output = Result<(), E>>,
+        E: std::error::Error + 'static,
+    {
+        info!("copying table {}", source_table_name);
+        let stream = client
+            .get_table_copy_stream(source_table_name, &table_schema.column_schemas)
+            .await?;
+        let mut stream = TableCopyStream::new(stream, table_schema.column_schemas.clone());
+        stream.set_row_counter(rows_copied.clone());
+        copy_table(table_schema.clone(), stream)
+            .await
+            .map_err(SnapshotBackfillError::TableCopy)
+    }
+}
@@ -0,0 +1,223 @@
+use std::{
+    fs::File,
+    io::{self, BufReader, BufWriter, Read, Write},
+    path::Path,
+    pin::Pin,
+    task::{Context, Poll},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use bytes::{BufMut, Bytes, BytesMut};
+use futures::{ready, SinkExt, Stream};
+use pin_project_lite::pin_project;
+use thiserror::Error;
+use tokio_postgres::{types::PgLsn, CopyBothDuplex};
+
+use super::postgres::StatusUpdateError;
+
+/// Written at the start of every capture file
+const CAPTURE_MAGIC: &[u8; 8] = b"PGRCAP01";
+
+#[derive(Debug, Error)]
+pub enum CaptureError {
+    #[error("io error: {0}")]
+    Io(#[from] io::Error),
+
+    #[error("tokio_postgres error: {0}")]
+    TokioPostgres(#[from] tokio_postgres::Error),
+
+    #[error("not a capture file")]
+    InvalidHeader,
+
+    #[error("capture file ends within a message")]
+    Truncated,
+}
+
+/// A CopyData payload received from Postgres on a replication connection, i.e. an
+/// XLogData or primary keepalive message, along with its lsn
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CapturedMessage {
+    /// The WAL start of an XLogData message or the WAL end of a keepalive
+    pub lsn: PgLsn,
+    pub payload: Bytes,
+}
+
+impl CapturedMessage {
+    pub fn new(payload: Bytes) -> CapturedMessage {
+        // Both message kinds have their lsn right after the tag byte
+        let lsn = match payload.get(1..9) {
+            Some(lsn) => u64::from_be_bytes(lsn.try_into().expect("slice of 8 bytes")),
+            None => 0,
+        };
+        CapturedMessage {
+            lsn: lsn.into(),
+            payload,
+        }
+    }
+}
+
+/// Writes captured messages, each as its lsn and the payload's length followed by the
+/// payload, see [`CaptureReader`]
+pub struct CaptureWriter {
+    writer: Box<dyn Write + Send>,
+}
+
+impl CaptureWriter {
+    pub fn new(mut writer: impl Write + Send + 'static) -> Result<CaptureWriter, CaptureError> {
+        writer.write_all(CAPTURE_MAGIC)?;
+        Ok(CaptureWriter {
+            writer: Box::new(writer),
+        })
+    }
+
+    /// Creates the file at `path`, truncating it if it exists
+    pub fn create(path: impl AsRef<Path>) -> Result<CaptureWriter, CaptureError> {
+        CaptureWriter::new(BufWriter::new(File::create(path)?))
+    }
+
+    pub fn write(&mut self, message: &CapturedMessage) -> Result<(), CaptureError> {
+        let lsn: u64 = message.lsn.into();
+        self.writer.write_all(&lsn.to_be_bytes())?;
+        self.writer
+            .write_all(&(message.payload.len() as u32).to_be_bytes())?;
+        self.writer.write_all(&message.payload)?;
+        Ok(())
+    }
+
+    pub fn flush(&mut self) -> Result<(), CaptureError> {
+        Ok(self.writer.flush()?)
+    }
+}
+
+/// Reads the messages written by a [`CaptureWriter`], e.g. to replay them through a
+/// `MemoryCdcStream` of the `memory_source` feature when a stream failed to decode
+pub struct CaptureReader<R: Read> {
+    reader: R,
+}
+
+impl CaptureReader<BufReader<File>> {
+    pub fn open(path: impl AsRef<Path>) -> Result<CaptureReader<BufReader<File>>, CaptureError> {
+        CaptureReader::new(BufReader::new(File::open(path)?))
+    }
+}
+
+impl<R: Read> CaptureReader<R> {
+    pub fn new(mut reader: R) -> Result<CaptureReader<R>, CaptureError> {
+        let mut magic = [0; CAPTURE_MAGIC.len()];
+        reader.read_exact(&mut magic).map_err(|e| match e.kind() {
+            io::ErrorKind::UnexpectedEof => CaptureError::InvalidHeader,
+            _ => e.into(),
+        })?;
+        if &magic != CAPTURE_MAGIC {
+            return Err(CaptureError::InvalidHeader);
+        }
+        Ok(CaptureReader { reader })
+    }
+
+    /// Reads the next message, `None` at the end of the capture
+    pub fn read(&mut self) -> Result<Option<CapturedMessage>, CaptureError> {
+        let mut header = [0; 12];
+        let mut read = 0;
+        while read < header.len() {
+            match self.reader.read(&mut header[read..])? {
+                0 if read == 0 => return Ok(None),
+                0 => return Err(CaptureError::Truncated),
+                n => read += n,
+            }
+        }
+        let lsn = u64::from_be_bytes(header[..8].try_into().expect("slice of 8 bytes"));
+        let len = u32::from_be_bytes(header[8..].try_into().expect("slice of 4 bytes"));
+        let mut payload = vec![0; len as usize];
+        self.reader
+            .read_exact(&mut payload)
+            .map_err(|e| match e.kind() {
+                io::ErrorKind::UnexpectedEof => CaptureError::Truncated,
+                _ => e.into(),
+            })?;
+        Ok(Some(CapturedMessage {
+            lsn: lsn.into(),
+            payload: payload.into(),
+        }))
+    }
+}
+
+impl<R: Read> Iterator for CaptureReader<R> {
+    type Item = Result<CapturedMessage, CaptureError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.read().transpose()
+    }
+}
+
+pin_project! {
+    /// A stream of the raw messages of a pgoutput slot which writes every message it
+    /// receives to a [`CaptureWriter`] before yielding it, see
+    /// [`PostgresSource::get_capture_stream`](super::postgres::PostgresSource::get_capture_stream).
+    /// The messages aren't decoded, so that a stream which fails to decode can be
+    /// captured in full and replayed offline.
+    #[must_use = "streams do nothing unless polled"]
+    pub struct CaptureStream {
+        #[pin]
+        stream: CopyBothDuplex<Bytes>,
+        writer: CaptureWriter,
+        postgres_epoch: SystemTime,
+    }
+}
+
+impl CaptureStream {
+    pub fn new(stream: CopyBothDuplex<Bytes>, writer: CaptureWriter) -> CaptureStream {
+        const TIME_SEC_CONVERSION: u64 = 946_684_800;
+        let postgres_epoch = UNIX_EPOCH + Duration::from_secs(TIME_SEC_CONVERSION);
+
+        CaptureStream {
+            stream,
+            writer,
+            postgres_epoch,
+        }
+    }
+
+    pub async fn send_status_update(
+        self: Pin<&mut Self>,
+        lsn: PgLsn,
+    ) -> Result<(), StatusUpdateError> {
+        let mut this = self.project();
+        let ts = this.postgres_epoch.elapsed()?.as_micros() as i64;
+        let lsn: u64 = lsn.into();
+
+        let mut buf = BytesMut::with_capacity(34);
+        buf.put_u8(b'r');
+        buf.put_u64(lsn);
+        buf.put_u64(lsn);
+        buf.put_u64(lsn);
+        buf.put_i64(ts);
+        buf.put_u8(0);
+        this.stream.send(buf.freeze()).await?;
+
+        Ok(())
+    }
+
+    /// Flushes the messages captured so far to the writer's file
+    pub fn flush(self: Pin<&mut Self>) -> Result<(), CaptureError> {
+        self.project().writer.flush()
+    }
+}
+
+impl Stream for CaptureStream {
+    type Item = Result<CapturedMessage, CaptureError>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.project();
+        let payload = match ready!(this.stream.poll_next(cx)) {
+            Some(Ok(payload)) => payload,
+            Some(Err(e)) => return Poll::Ready(Some(Err(e.into()))),
+            None => {
+                return Poll::Ready(this.writer.flush().err().map(Err));
+            }
+        };
+        let message = CapturedMessage::new(payload);
+        if let Err(e) = this.writer.write(&message) {
+            return Poll::Ready(Some(Err(e)));
+        }
+        Poll::Ready(Some(Ok(message)))
+    }
+}
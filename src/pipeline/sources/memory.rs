@@ -0,0 +1,157 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    io::Read,
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use bytes::{BufMut, Bytes, BytesMut};
+use futures::Stream;
+use postgres_replication::protocol::{LogicalReplicationMessage, ReplicationMessage};
+use tokio_postgres::types::PgLsn;
+
+use crate::{
+    clients::postgres::ReplicationClientError,
+    conversions::cdc_event::{CdcEvent, CdcEventConverter, ReplicaIdentities},
+    table::{TableId, TableSchema},
+};
+
+use super::{
+    capture::{CaptureError, CaptureReader},
+    postgres::CdcStreamError,
+};
+
+/// Decodes a scripted sequence of replication messages into cdc events, the way a
+/// [`CdcStream`](super::postgres::CdcStream) decodes the messages it receives from
+/// Postgres. Meant for testing decoding, batching and sinks without a database: the
+/// events can be batched with a
+/// [`BatchTimeoutStream`](crate::pipeline::batching::stream::BatchTimeoutStream) and
+/// passed to [`BatchSink::write_cdc_events`](crate::pipeline::sinks::BatchSink::write_cdc_events).
+pub struct MemoryCdcStream {
+    messages: VecDeque<Bytes>,
+    table_schemas: HashMap<TableId, TableSchema>,
+    replica_identities: ReplicaIdentities,
+    // Whether the messages are part of a streamed transaction, which carry its xid
+    in_stream: bool,
+    emit_heartbeats: bool,
+    // The keepalive to return after the heartbeat emitted for it
+    pending_keep_alive: Option<CdcEvent>,
+}
+
+impl MemoryCdcStream {
+    pub fn new(table_schemas: HashMap<TableId, TableSchema>) -> MemoryCdcStream {
+        MemoryCdcStream {
+            messages: VecDeque::new(),
+            table_schemas,
+            replica_identities: ReplicaIdentities::default(),
+            in_stream: false,
+            emit_heartbeats: false,
+            pending_keep_alive: None,
+        }
+    }
+
+    /// Emits a [`CdcEvent::Heartbeat`] before each keepalive, like a
+    /// [`CdcStream`](super::postgres::CdcStream) of a source with
+    /// [`emit_heartbeats`](super::postgres::PostgresSource::emit_heartbeats) set
+    pub fn emit_heartbeats(&mut self) {
+        self.emit_heartbeats = true;
+    }
+
+    /// Appends a message as Postgres sends it in a CopyData message of the replication
+    /// protocol, i.e. an XLogData or primary keepalive message
+    pub fn push_raw(&mut self, message: Bytes) {
+        self.messages.push_back(message);
+    }
+
+    /// Appends the messages of a capture, see
+    /// [`PostgresSource::get_capture_stream`](super::postgres::PostgresSource::get_capture_stream),
+    /// e.g. to turn a stream which failed to decode into a test
+    pub fn push_capture<R: Read>(&mut self, capture: CaptureReader<R>) -> Result<(), CaptureError> {
+        for message in capture {
+            self.push_raw(message?.payload);
+        }
+        Ok(())
+    }
+
+    /// Appends a pgoutput message, sent in an XLogData message starting at `wal_start`
+    pub fn push_logical(&mut self, wal_start: PgLsn, message: Bytes) {
+        let wal_start: u64 = wal_start.into();
+        let mut buf = BytesMut::with_capacity(25 + message.len());
+        buf.put_u8(b'w');
+        buf.put_u64(wal_start);
+        buf.put_u64(wal_start);
+        buf.put_i64(0);
+        buf.put_slice(&message);
+        self.push_raw(buf.freeze());
+    }
+
+    /// Appends a primary keepalive message
+    pub fn push_keepalive(&mut self, wal_end: PgLsn, reply: bool) {
+        let mut buf = BytesMut::with_capacity(18);
+        buf.put_u8(b'k');
+        buf.put_u64(wal_end.into());
+        buf.put_i64(0);
+        buf.put_u8(reply as u8);
+        self.push_raw(buf.freeze());
+    }
+
+    fn decode(&mut self, message: Bytes) -> Result<CdcEvent, CdcStreamError> {
+        let invalid = |e: std::io::Error| ReplicationClientError::InvalidMessage(e.to_string());
+        let message = match ReplicationMessage::parse(&message).map_err(invalid)? {
+            ReplicationMessage::XLogData(body) => ReplicationMessage::XLogData(
+                body.map_data(|data| LogicalReplicationMessage::parse(&data, self.in_stream))
+                    .map_err(invalid)?,
+            ),
+            ReplicationMessage::PrimaryKeepAlive(body) => {
+                ReplicationMessage::PrimaryKeepAlive(body)
+            }
+            _ => {
+                return Err(ReplicationClientError::InvalidMessage(
+                    "unexpected replication message".to_string(),
+                )
+                .into())
+            }
+        };
+        let heartbeat = match &message {
+            ReplicationMessage::XLogData(body) => {
+                match body.data() {
+                    LogicalReplicationMessage::StreamStart(_) => self.in_stream = true,
+                    LogicalReplicationMessage::StreamStop(_) => self.in_stream = false,
+                    _ => {}
+                }
+                None
+            }
+            ReplicationMessage::PrimaryKeepAlive(keep_alive) if self.emit_heartbeats => {
+                Some(CdcEventConverter::heartbeat(keep_alive))
+            }
+            _ => None,
+        };
+        let event = CdcEventConverter::try_from_with_identities(
+            message,
+            &self.table_schemas,
+            &mut self.replica_identities,
+            false,
+        )?;
+        match heartbeat {
+            Some(heartbeat) => {
+                self.pending_keep_alive = Some(event);
+                Ok(heartbeat)
+            }
+            None => Ok(event),
+        }
+    }
+}
+
+impl Stream for MemoryCdcStream {
+    type Item = Result<CdcEvent, CdcStreamError>;
+
+    fn poll_next(mut self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        if let Some(keep_alive) = self.pending_keep_alive.take() {
+            return Poll::Ready(Some(Ok(keep_alive)));
+        }
+        let Some(message) = self.messages.pop_front() else {
+            return Poll::Ready(None);
+        };
+        Poll::Ready(Some(self.decode(message)))
+    }
+}
@@ -11,7 +11,13 @@ use self::postgres::{
     TableCopyStreamError,
 };
 
+pub mod backfill;
+pub mod capture;
+#[cfg(feature = "memory_source")]
+pub mod memory;
 pub mod postgres;
+pub mod slot_name;
+pub mod wal2json;
 
 pub trait SourceError: std::error::Error + Send + Sync + 'static {}
 
@@ -49,7 +55,50 @@ pub trait Source {
         column_schemas: &[ColumnSchema],
     ) -> Result<TableCopyStream, Self::Error>;
 
+    /// Returns a copy stream of at most `limit` rows of a table ordered by
+    /// `key_columns`, starting after the row whose key columns have the values `after`
+    /// in text format
+    async fn get_table_keyset_copy_stream(
+        &self,
+        table_name: &TableName,
+        column_schemas: &[ColumnSchema],
+        key_columns: &[String],
+        after: Option<&[String]>,
+        limit: u64,
+    ) -> Result<TableCopyStream, Self::Error>;
+
     async fn commit_transaction(&mut self) -> Result<(), Self::Error>;
 
+    /// Starts copying a table whose copy may fail without failing the copies of the
+    /// tables after it
+    async fn begin_table_copy(&self) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
+    /// Ends a copy started with [`Source::begin_table_copy`], undoing the effects of
+    /// a `failed` copy so that other tables can still be copied
+    async fn end_table_copy(&self, _failed: bool) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
     async fn get_cdc_stream(&self, start_lsn: PgLsn) -> Result<CdcStream, Self::Error>;
+
+    /// The name of the slot changes are streamed from, `None` for sources without
+    /// slots
+    fn slot_name(&self) -> Option<&str> {
+        None
+    }
+
+    /// Drops a slot the pipeline streamed from before its slot name changed, see
+    /// [`BatchDataPipeline::set_drop_orphaned_slot`](crate::pipeline::batching::data_pipeline::BatchDataPipeline::set_drop_orphaned_slot)
+    async fn drop_orphaned_slot(&self, _slot_name: &str) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
+    /// Drops the source's slot if the source created it when it was set up, e.g.
+    /// when it turns out it can't continue where the sink left off. Ends the
+    /// source's transaction.
+    async fn drop_created_slot(&mut self) -> Result<(), Self::Error> {
+        Ok(())
+    }
 }
@@ -1,28 +1,52 @@
 use std::{
-    collections::HashMap,
+    collections::{HashMap, HashSet},
+    future::Future,
     pin::Pin,
+    str,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
     task::{Context, Poll},
-    time::{Duration, SystemTime, SystemTimeError, UNIX_EPOCH},
+    time::{Duration, Instant, SystemTime, SystemTimeError, UNIX_EPOCH},
 };
 
 use async_trait::async_trait;
 use futures::{ready, Stream};
 use pin_project_lite::pin_project;
-use postgres_replication::LogicalReplicationStream;
+use postgres_replication::{
+    protocol::{LogicalReplicationMessage, RelationBody, ReplicationMessage, TupleData},
+    LogicalReplicationStream,
+};
 use thiserror::Error;
 use tokio_postgres::{types::PgLsn, CopyOutStream};
-use tracing::info;
+use tracing::{info, instrument, warn};
 
 use crate::{
-    clients::postgres::{ReplicationClient, ReplicationClientError},
+    clients::postgres::{
+        ReplicaIdentity, ReplicationClient, ReplicationClientError, SlotInfo, SystemIdentity,
+    },
     conversions::{
-        cdc_event::{CdcEvent, CdcEventConversionError, CdcEventConverter},
-        table_row::{TableRow, TableRowConversionError, TableRowConverter},
+        cdc_event::{
+            CdcEvent, CdcEventConversionError, CdcEventConverter, RelationIdentity,
+            ReplicaIdentities, RowChange,
+        },
+        table_row::{CopyFormat, TableRow, TableRowConversionError, TableRowConverter},
+        text::TextFormatConverter,
+        Cell,
     },
-    table::{ColumnSchema, TableId, TableName, TableSchema},
+    pipeline::stats::BackfillStats,
+    table::{ColumnSchema, LookupKey, TableId, TableName, TableSchema},
+};
+
+use super::{
+    capture::{CaptureStream, CaptureWriter},
+    wal2json::Wal2JsonStream,
+    Source, SourceError,
 };
 
-use super::{Source, SourceError};
+/// The savepoint set before each table copy which may fail
+const TABLE_COPY_SAVEPOINT: &str = "pg_replicate_table_copy";
 
 pub enum TableNamesFrom {
     Vec(Vec<TableName>),
@@ -39,6 +63,34 @@ pub enum PostgresSourceError {
 
     #[error("cdc stream can only be started with a slot_name")]
     MissingSlotName,
+
+    #[error("schema changes can only be emitted with a schema refresh client")]
+    MissingSchemaRefreshClient,
+
+    #[error("tables without replica identity full can't provide before-images: {}", display_table_names(.0))]
+    MissingBeforeImages(Vec<TableName>),
+
+    /// The server is on another timeline than expected, see
+    /// [`PostgresSource::expect_timeline`]. After a failover to a standby whose slots
+    /// aren't synchronized from the old primary, the slot is gone or doesn't match
+    /// the sink's position, and the sink has to be re-synced from a new slot with
+    /// [`BatchDataPipeline::reset`](crate::pipeline::batching::data_pipeline::BatchDataPipeline::reset).
+    /// If the slot was synchronized, expecting the new timeline is enough.
+    #[error("server is on timeline {actual} instead of {expected}, it probably failed over")]
+    TimelineChanged { expected: u32, actual: u32 },
+
+    /// The slot is missing, e.g. after a failover to a standby it wasn't synchronized
+    /// to, or was only created by [`PostgresSource::new`] although the sink has a
+    /// position from an earlier slot. Changes since that position might be lost, so
+    /// the sink has to be re-synced from a new slot with
+    /// [`BatchDataPipeline::reset`](crate::pipeline::batching::data_pipeline::BatchDataPipeline::reset).
+    #[error("replication slot {0} is missing, the sink has to be re-synced")]
+    SlotMissing(String),
+}
+
+fn display_table_names(table_names: &[TableName]) -> String {
+    let table_names: Vec<String> = table_names.iter().map(ToString::to_string).collect();
+    table_names.join(", ")
 }
 
 impl SourceError for PostgresSourceError {}
@@ -48,9 +100,22 @@ pub struct PostgresSource {
     table_schemas: HashMap<TableId, TableSchema>,
     slot_name: Option<String>,
     publication: Option<String>,
+    toast_lookup_client: Option<Arc<ReplicationClient>>,
+    schema_refresh_client: Option<Arc<ReplicationClient>>,
+    slot_active_timeout: Option<Duration>,
+    enforce_not_null: bool,
+    emit_heartbeats: bool,
+    emit_schema_changes: bool,
+    skip_dropped_tables: bool,
+    require_before_images: bool,
+    system_identity: SystemIdentity,
+    expected_timeline: Option<u32>,
+    /// Whether [`PostgresSource::new`] created the slot
+    created_slot: bool,
 }
 
 impl PostgresSource {
+    #[instrument(skip(password, table_names_from))]
     pub async fn new(
         host: &str,
         port: u16,
@@ -62,8 +127,14 @@ impl PostgresSource {
     ) -> Result<PostgresSource, PostgresSourceError> {
         let mut replication_client =
             ReplicationClient::connect_no_tls(host, port, database, username, password).await?;
+        let system_identity = replication_client.identify_system().await?;
         replication_client.begin_readonly_transaction().await?;
+        let mut created_slot = false;
         if let Some(ref slot_name) = slot_name {
+            created_slot = replication_client
+                .get_slot_activity(slot_name)
+                .await?
+                .is_none();
             replication_client.get_or_create_slot(slot_name).await?;
         }
         let (table_names, publication) =
@@ -76,15 +147,335 @@ impl PostgresSource {
             table_schemas,
             publication,
             slot_name,
+            toast_lookup_client: None,
+            schema_refresh_client: None,
+            slot_active_timeout: None,
+            enforce_not_null: false,
+            emit_heartbeats: false,
+            emit_schema_changes: false,
+            skip_dropped_tables: false,
+            require_before_images: false,
+            system_identity,
+            expected_timeline: None,
+            created_slot,
         })
     }
 
-    fn publication(&self) -> Option<&String> {
+    /// Makes the cdc stream fetch the current values of TOAST columns left unchanged by
+    /// an update, which Postgres doesn't send unless the table has `REPLICA IDENTITY FULL`.
+    /// The values are fetched with a select by lookup key using `lookup_client`, which must
+    /// be a separate connection from the one used by this source. This adds a query against
+    /// the source for every such update, and the fetched value is the row's current value,
+    /// which might be newer than the update. Tables without a lookup key are not supported
+    /// and keep getting default values for unchanged TOAST columns.
+    pub fn fetch_unchanged_toast_values(&mut self, lookup_client: ReplicationClient) {
+        self.toast_lookup_client = Some(Arc::new(lookup_client));
+    }
+
+    /// Makes the cdc stream refresh a table's schema when a row change fails to decode,
+    /// which happens if the cached schema is stale because the table was altered, and
+    /// decode the change again with the refreshed schema. The schema is fetched once
+    /// per table until the next Relation message for the table, using
+    /// `refresh_client`, which must be a separate connection from the one used by this
+    /// source and configured like it, e.g. with the same excluded columns. If the
+    /// change fails to decode again, the original error is returned. Otherwise the
+    /// refreshed schema is emitted as a [`CdcEvent::Schema`] before the change, so
+    /// that the pipeline prepares the table's changes and the sink's table for it.
+    pub fn refresh_schemas_on_decode_error(&mut self, refresh_client: ReplicationClient) {
+        self.schema_refresh_client = Some(Arc::new(refresh_client));
+    }
+
+    /// Makes the cdc stream check decoded rows for nulls in columns declared NOT NULL,
+    /// e.g. from a misconfigured replica identity, and fail such changes with an
+    /// [`UndecodableRow`](crate::conversions::cdc_event::UndecodableRow) error. With a
+    /// dead letter queue the pipeline routes them there instead of the sink rejecting
+    /// them with a less descriptive constraint violation.
+    pub fn enforce_not_null(&mut self) {
+        self.enforce_not_null = true;
+    }
+
+    /// Makes the cdc stream emit a [`CdcEvent::Heartbeat`] before each keepalive from
+    /// Postgres, which it sends even while no changes flow. Sinks can use them to
+    /// track the stream's liveness and the server's current lsn.
+    pub fn emit_heartbeats(&mut self) {
+        self.emit_heartbeats = true;
+    }
+
+    /// Makes the cdc stream emit a [`CdcEvent::Schema`] with a table's current schema
+    /// after each [`CdcEvent::Relation`] whose columns differ from the table's cached
+    /// schema, e.g. after a column was added, dropped or changed its type, so that sinks
+    /// can alter their tables before the changes with the new columns arrive. The
+    /// schema is fetched with the client given to
+    /// [`PostgresSource::refresh_schemas_on_decode_error`], without which starting the
+    /// stream fails with [`PostgresSourceError::MissingSchemaRefreshClient`]. Like a
+    /// refreshed schema, it might be newer than the Relation message if the table was
+    /// altered again since.
+    pub fn emit_schema_changes(&mut self) {
+        self.emit_schema_changes = true;
+    }
+
+    /// Makes the cdc stream emit a [`CdcEvent::TableDropped`] and carry on with the
+    /// other tables when fetching a table's schema finds the table gone, instead of
+    /// failing. Schemas are only fetched with
+    /// [`PostgresSource::refresh_schemas_on_decode_error`] or
+    /// [`PostgresSource::emit_schema_changes`], so a dropped table is noticed when a
+    /// change of it fails to decode or its Relation message announces a change.
+    pub fn skip_dropped_tables(&mut self) {
+        self.skip_dropped_tables = true;
+    }
+
+    /// Makes starting the cdc stream fail with
+    /// [`PostgresSourceError::MissingBeforeImages`] unless every table of the
+    /// publication has `REPLICA IDENTITY FULL`, so that the old row of every
+    /// [`CdcEvent::Update`] is a complete before-image, e.g. for sinks auditing the old
+    /// values of changed columns. With any other replica identity Postgres only sends
+    /// the old key columns, and only if they changed.
+    pub fn require_before_images(&mut self) {
+        self.require_before_images = true;
+    }
+
+    /// Reloads the table schemas including stored generated columns, see
+    /// [`ReplicationClient::set_include_generated_columns`]. Must be called before
+    /// the transaction started by [`PostgresSource::new`] is committed so that the
+    /// schemas come from the same snapshot as the table copies.
+    pub async fn include_generated_columns(&mut self) -> Result<(), PostgresSourceError> {
+        self.replication_client.set_include_generated_columns(true);
+        self.reload_table_schemas().await
+    }
+
+    /// Reloads the table schemas including the tables' and columns' comments, see
+    /// [`ReplicationClient::set_include_comments`]. Like
+    /// [`PostgresSource::include_generated_columns`] this must be called before the
+    /// transaction started by [`PostgresSource::new`] is committed.
+    pub async fn include_comments(&mut self) -> Result<(), PostgresSourceError> {
+        self.replication_client.set_include_comments(true);
+        self.reload_table_schemas().await
+    }
+
+    /// Leaves the columns `column_names` of a table out of table copies and cdc events,
+    /// see [`ReplicationClient::set_exclude_columns`]. Like
+    /// [`PostgresSource::include_generated_columns`] this must be called before the
+    /// transaction started by [`PostgresSource::new`] is committed.
+    pub async fn exclude_columns(
+        &mut self,
+        table_name: TableName,
+        column_names: impl IntoIterator<Item = impl Into<String>>,
+    ) -> Result<(), PostgresSourceError> {
+        self.replication_client
+            .set_exclude_columns(table_name, column_names);
+        self.reload_table_schemas().await
+    }
+
+    /// Drops the tables outside `schemas` from the source, so that they are neither
+    /// copied nor streamed, see [`ReplicationClient::set_allowed_schemas`]. Their
+    /// schemas are still loaded by [`PostgresSource::new`], and Postgres still sends
+    /// their changes if the publication includes them, which the pipeline skips.
+    pub fn allow_schemas(&mut self, schemas: impl IntoIterator<Item = impl Into<String>>) {
+        self.replication_client.set_allowed_schemas(schemas);
+        self.retain_allowed_schemas();
+    }
+
+    /// Drops the tables in `schemas` from the source like
+    /// [`PostgresSource::allow_schemas`] drops the tables outside them, see
+    /// [`ReplicationClient::set_denied_schemas`]
+    pub fn deny_schemas(&mut self, schemas: impl IntoIterator<Item = impl Into<String>>) {
+        self.replication_client.set_denied_schemas(schemas);
+        self.retain_allowed_schemas();
+    }
+
+    fn retain_allowed_schemas(&mut self) {
+        let replication_client = &self.replication_client;
+        self.table_schemas.retain(|_, table_schema| {
+            replication_client.is_schema_allowed(&table_schema.table_name.schema)
+        });
+    }
+
+    async fn reload_table_schemas(&mut self) -> Result<(), PostgresSourceError> {
+        let table_names: Vec<TableName> = self
+            .table_schemas
+            .values()
+            .map(|table_schema| table_schema.table_name.clone())
+            .collect();
+        self.table_schemas = self
+            .replication_client
+            .get_table_schemas(&table_names, self.publication.as_deref())
+            .await?;
+        Ok(())
+    }
+
+    /// The identity of the server as of [`PostgresSource::new`], whose timeline can be
+    /// stored with the sink's state to pass to [`PostgresSource::expect_timeline`]
+    /// after a restart
+    pub fn system_identity(&self) -> &SystemIdentity {
+        &self.system_identity
+    }
+
+    /// Makes starting the cdc stream fail with [`PostgresSourceError::TimelineChanged`]
+    /// if the server isn't on `timeline`, e.g. because it is a standby promoted after
+    /// the previous run. Without this a failover is only noticed if the slot is
+    /// missing on the new primary.
+    pub fn expect_timeline(&mut self, timeline: u32) {
+        self.expected_timeline = Some(timeline);
+    }
+
+    /// Makes starting the cdc stream wait for up to `timeout` and retry if the slot is
+    /// still in use by another process. This happens on a reconnect before Postgres has
+    /// noticed that the previous connection died.
+    pub fn wait_for_active_slot(&mut self, timeout: Duration) {
+        self.slot_active_timeout = Some(timeout);
+    }
+
+    /// Returns the position up to which the server's WAL is flushed to disk. Must be
+    /// called before the cdc stream is started, which takes over the connection.
+    pub async fn current_wal_flush_lsn(&self) -> Result<PgLsn, PostgresSourceError> {
+        Ok(self.replication_client.current_wal_flush_lsn().await?)
+    }
+
+    /// Drops the source's slot and creates it again, e.g. to recover from a slot whose
+    /// WAL has been removed (`wal_status = 'lost'`), which can't be streamed from
+    /// anymore. Like [`PostgresSource::new`] this starts a transaction which sees the
+    /// database as of the new slot's consistent point, and the table schemas are
+    /// reloaded in it, including the tables of the publication, which might have
+    /// changed. Returns the slot info of the new slot.
+    pub async fn recreate_slot(&mut self) -> Result<SlotInfo, PostgresSourceError> {
+        let slot_name = self
+            .slot_name
+            .clone()
+            .ok_or(PostgresSourceError::MissingSlotName)?;
+        if let Some(slot_activity) = self
+            .replication_client
+            .get_slot_activity(&slot_name)
+            .await?
+        {
+            if let (Some(pid), Some(timeout)) = (slot_activity.active_pid, self.slot_active_timeout)
+            {
+                info!("slot {slot_name} is active for pid {pid}, waiting for it to be released");
+                self.replication_client
+                    .wait_for_slot_inactive(&slot_name, timeout)
+                    .await?;
+            }
+            info!("dropping slot {slot_name}");
+            self.replication_client.drop_slot(&slot_name).await?;
+        }
+        let slot_info = self
+            .replication_client
+            .create_slot_using_snapshot(&slot_name, false)
+            .await?;
+        info!(
+            "created slot {slot_name} at lsn {}",
+            slot_info.confirmed_flush_lsn
+        );
+        // The caller re-syncs the sink from the new slot
+        self.created_slot = false;
+
+        let table_names = match &self.publication {
+            Some(publication) => {
+                self.replication_client
+                    .get_publication_table_names(publication)
+                    .await?
+            }
+            None => self
+                .table_schemas
+                .values()
+                .map(|table_schema| table_schema.table_name.clone())
+                .collect(),
+        };
+        self.table_schemas = self
+            .replication_client
+            .get_table_schemas(&table_names, self.publication.as_deref())
+            .await?;
+        Ok(slot_info)
+    }
+
+    pub fn publication(&self) -> Option<&String> {
         self.publication.as_ref()
     }
 
-    fn slot_name(&self) -> Option<&String> {
-        self.slot_name.as_ref()
+    /// Starts a stream of the source's tables from its slot, which must have been
+    /// created with the [`OutputPlugin::Wal2Json`](crate::clients::postgres::OutputPlugin::Wal2Json)
+    /// plugin. Yields the same events as [`Source::get_cdc_stream`] does for pgoutput.
+    pub async fn get_wal2json_stream(
+        &self,
+        start_lsn: PgLsn,
+    ) -> Result<Wal2JsonStream, PostgresSourceError> {
+        info!("starting wal2json stream at lsn {start_lsn}");
+        let slot_name = self
+            .slot_name()
+            .ok_or(PostgresSourceError::MissingSlotName)?;
+        let table_names: Vec<TableName> = self
+            .table_schemas
+            .values()
+            .map(|table_schema| table_schema.table_name.clone())
+            .collect();
+        self.check_stream_start(slot_name, start_lsn)?;
+        let stream = self
+            .replication_client
+            .get_wal2json_stream(slot_name, start_lsn, &table_names)
+            .await
+            .map_err(Self::slot_missing)?;
+        Ok(Wal2JsonStream::new(stream, self.table_schemas.clone()))
+    }
+
+    /// Starts a raw stream of the source's publication from its slot which captures
+    /// every message received to `writer`, e.g. to reproduce a decode failure
+    /// offline by replaying the capture through a `MemoryCdcStream` of the
+    /// `memory_source` feature. The slot can only be
+    /// streamed from once at a time, so the pipeline must be stopped while capturing,
+    /// and the capture should start from the position the sink resumes from.
+    pub async fn get_capture_stream(
+        &self,
+        start_lsn: PgLsn,
+        writer: CaptureWriter,
+    ) -> Result<CaptureStream, PostgresSourceError> {
+        info!("starting capture stream at lsn {start_lsn}");
+        let publication = self
+            .publication()
+            .ok_or(PostgresSourceError::MissingPublication)?;
+        let slot_name = self
+            .slot_name()
+            .ok_or(PostgresSourceError::MissingSlotName)?;
+        self.check_stream_start(slot_name, start_lsn)?;
+        let stream = self
+            .replication_client
+            .get_raw_logical_replication_stream(publication, slot_name, start_lsn)
+            .await
+            .map_err(Self::slot_missing)?;
+        Ok(CaptureStream::new(stream, writer))
+    }
+
+    /// Fails if the server has failed over since the timeline passed to
+    /// [`PostgresSource::expect_timeline`], or if the slot was created by
+    /// [`PostgresSource::new`] although the stream doesn't start from the beginning,
+    /// i.e. the sink has a position from an earlier slot which is gone
+    fn check_stream_start(
+        &self,
+        slot_name: &str,
+        start_lsn: PgLsn,
+    ) -> Result<(), PostgresSourceError> {
+        let timeline = self.system_identity.timeline;
+        if let Some(expected) = self.expected_timeline {
+            if expected != timeline {
+                return Err(PostgresSourceError::TimelineChanged {
+                    expected,
+                    actual: timeline,
+                });
+            }
+        }
+        // The pipeline starts streaming after the sink's last lsn, which is 0 until
+        // the sink has committed a transaction
+        if self.created_slot && u64::from(start_lsn) > 1 {
+            return Err(PostgresSourceError::SlotMissing(slot_name.to_string()));
+        }
+        Ok(())
+    }
+
+    fn slot_missing(e: ReplicationClientError) -> PostgresSourceError {
+        match e {
+            ReplicationClientError::MissingSlot(slot_name) => {
+                PostgresSourceError::SlotMissing(slot_name)
+            }
+            e => e.into(),
+        }
     }
 
     async fn get_table_names_and_publication(
@@ -131,10 +522,24 @@ impl Source for PostgresSource {
             .await
             .map_err(PostgresSourceError::ReplicationClient)?;
 
-        Ok(TableCopyStream {
-            stream,
-            column_schemas: column_schemas.to_vec(),
-        })
+        Ok(TableCopyStream::new(stream, column_schemas.to_vec()))
+    }
+
+    async fn get_table_keyset_copy_stream(
+        &self,
+        table_name: &TableName,
+        column_schemas: &[ColumnSchema],
+        key_columns: &[String],
+        after: Option<&[String]>,
+        limit: u64,
+    ) -> Result<TableCopyStream, Self::Error> {
+        let stream = self
+            .replication_client
+            .get_table_keyset_copy_stream(table_name, column_schemas, key_columns, after, limit)
+            .await
+            .map_err(PostgresSourceError::ReplicationClient)?;
+
+        Ok(TableCopyStream::new(stream, column_schemas.to_vec()))
     }
 
     async fn commit_transaction(&mut self) -> Result<(), Self::Error> {
@@ -145,6 +550,33 @@ impl Source for PostgresSource {
         Ok(())
     }
 
+    /// Sets a savepoint in the snapshot's transaction, as an error fails the
+    /// transaction and with it the copies of all other tables
+    async fn begin_table_copy(&self) -> Result<(), Self::Error> {
+        self.replication_client
+            .savepoint(TABLE_COPY_SAVEPOINT)
+            .await
+            .map_err(PostgresSourceError::ReplicationClient)
+    }
+
+    async fn end_table_copy(&self, failed: bool) -> Result<(), Self::Error> {
+        let result = if failed {
+            self.replication_client
+                .rollback_to_savepoint(TABLE_COPY_SAVEPOINT)
+                .await
+        } else {
+            self.replication_client
+                .release_savepoint(TABLE_COPY_SAVEPOINT)
+                .await
+        };
+        result.map_err(PostgresSourceError::ReplicationClient)
+    }
+
+    #[instrument(skip_all, fields(
+        slot_name = self.slot_name.as_deref(),
+        publication = self.publication.as_deref(),
+        start_lsn = %start_lsn,
+    ))]
     async fn get_cdc_stream(&self, start_lsn: PgLsn) -> Result<CdcStream, Self::Error> {
         info!("starting cdc stream at lsn {start_lsn}");
         let publication = self
@@ -153,20 +585,86 @@ impl Source for PostgresSource {
         let slot_name = self
             .slot_name()
             .ok_or(PostgresSourceError::MissingSlotName)?;
-        let stream = self
+        if self.emit_schema_changes && self.schema_refresh_client.is_none() {
+            return Err(PostgresSourceError::MissingSchemaRefreshClient);
+        }
+        if self.require_before_images {
+            let tables_without_full_identity: Vec<TableName> = self
+                .replication_client
+                .check_replica_identities(publication)
+                .await?
+                .into_iter()
+                .filter(|table| table.replica_identity != ReplicaIdentity::Full)
+                .map(|table| table.table_name)
+                .collect();
+            if !tables_without_full_identity.is_empty() {
+                return Err(PostgresSourceError::MissingBeforeImages(
+                    tables_without_full_identity,
+                ));
+            }
+        }
+        self.check_stream_start(slot_name, start_lsn)?;
+        let result = self
             .replication_client
             .get_logical_replication_stream(publication, slot_name, start_lsn)
-            .await
-            .map_err(PostgresSourceError::ReplicationClient)?;
+            .await;
+        let stream = match (result, self.slot_active_timeout) {
+            (Err(ReplicationClientError::SlotActive { pid }), Some(timeout)) => {
+                info!("slot {slot_name} is active for pid {pid}, waiting for it to be released");
+                self.replication_client
+                    .wait_for_slot_inactive(slot_name, timeout)
+                    .await?;
+                self.replication_client
+                    .get_logical_replication_stream(publication, slot_name, start_lsn)
+                    .await
+                    .map_err(Self::slot_missing)?
+            }
+            (result, _) => result.map_err(Self::slot_missing)?,
+        };
 
-        const TIME_SEC_CONVERSION: u64 = 946_684_800;
-        let postgres_epoch = UNIX_EPOCH + Duration::from_secs(TIME_SEC_CONVERSION);
+        let mut stream = CdcStream::new(stream, self.table_schemas.clone());
+        stream.toast_lookup_client = self.toast_lookup_client.clone();
+        stream.schema_refresh = self
+            .schema_refresh_client
+            .clone()
+            .map(|client| (client, self.publication.clone()));
+        stream.enforce_not_null = self.enforce_not_null;
+        stream.emit_heartbeats = self.emit_heartbeats;
+        stream.emit_schema_changes = self.emit_schema_changes;
+        stream.skip_dropped_tables = self.skip_dropped_tables;
+        Ok(stream)
+    }
 
-        Ok(CdcStream {
-            stream,
-            table_schemas: self.table_schemas.clone(),
-            postgres_epoch,
-        })
+    fn slot_name(&self) -> Option<&str> {
+        self.slot_name.as_deref()
+    }
+
+    /// Keeps the slot if another process still streams from it
+    async fn drop_orphaned_slot(&self, slot_name: &str) -> Result<(), Self::Error> {
+        let Some(slot_activity) = self.replication_client.get_slot_activity(slot_name).await?
+        else {
+            info!("orphaned slot {slot_name} was already dropped");
+            return Ok(());
+        };
+        if let Some(pid) = slot_activity.active_pid {
+            warn!("not dropping orphaned slot {slot_name} as it's active for pid {pid}");
+            return Ok(());
+        }
+        info!("dropping orphaned slot {slot_name}");
+        self.replication_client.drop_slot(slot_name).await?;
+        Ok(())
+    }
+
+    async fn drop_created_slot(&mut self) -> Result<(), Self::Error> {
+        let Some(slot_name) = self.slot_name.clone().filter(|_| self.created_slot) else {
+            return Ok(());
+        };
+        // The slot can't be dropped while its snapshot is in use
+        self.replication_client.commit_txn().await?;
+        info!("dropping created slot {slot_name}");
+        self.replication_client.drop_slot(&slot_name).await?;
+        self.created_slot = false;
+        Ok(())
     }
 }
 
@@ -185,6 +683,64 @@ pin_project! {
         #[pin]
         stream: CopyOutStream,
         column_schemas: Vec<ColumnSchema>,
+        bytes_copied: Arc<AtomicU64>,
+        rows_copied: Arc<AtomicU64>,
+        stats: Option<Arc<BackfillStats>>,
+        format: CopyFormat,
+        // Whether the header row of a csv copy is still to be skipped
+        header_pending: bool,
+    }
+}
+
+impl TableCopyStream {
+    /// Decodes the rows of `stream`, a `COPY ... TO STDOUT` of columns matching
+    /// `column_schemas`, e.g. one with a custom query. Rows are expected in text
+    /// format unless set otherwise with [`TableCopyStream::set_format`].
+    pub fn new(stream: CopyOutStream, column_schemas: Vec<ColumnSchema>) -> TableCopyStream {
+        TableCopyStream {
+            stream,
+            column_schemas,
+            bytes_copied: Arc::new(AtomicU64::new(0)),
+            rows_copied: Arc::new(AtomicU64::new(0)),
+            stats: None,
+            format: CopyFormat::Text,
+            header_pending: false,
+        }
+    }
+
+    /// Makes the stream decode rows in `format`, which must match the format of the
+    /// `COPY`. The header row of a csv copy with a header is skipped.
+    pub fn set_format(&mut self, format: CopyFormat) {
+        self.format = format;
+        self.header_pending = matches!(format, CopyFormat::Csv { header: true });
+    }
+
+    /// Returns the counter of bytes received from Postgres so far, e.g. to display the
+    /// copy's throughput while another task consumes the stream
+    pub fn byte_counter(&self) -> Arc<AtomicU64> {
+        self.bytes_copied.clone()
+    }
+
+    /// Makes the stream add the bytes it receives to `counter`, e.g. to count the
+    /// bytes of all tables of a backfill in a single counter
+    pub fn set_byte_counter(&mut self, counter: Arc<AtomicU64>) {
+        self.bytes_copied = counter;
+    }
+
+    /// Returns the counter of rows received from Postgres so far
+    pub fn row_counter(&self) -> Arc<AtomicU64> {
+        self.rows_copied.clone()
+    }
+
+    /// Makes the stream add the rows it receives to `counter`, e.g. to count the rows
+    /// of all partitions of a table in a single counter
+    pub fn set_row_counter(&mut self, counter: Arc<AtomicU64>) {
+        self.rows_copied = counter;
+    }
+
+    /// Makes the stream record the time spent decoding rows in `stats`
+    pub fn set_stats(&mut self, stats: Arc<BackfillStats>) {
+        self.stats = Some(stats);
     }
 }
 
@@ -192,15 +748,40 @@ impl Stream for TableCopyStream {
     type Item = Result<TableRow, TableCopyStreamError>;
 
     fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
-        let this = self.project();
-        match ready!(this.stream.poll_next(cx)) {
-            Some(Ok(row)) => match TableRowConverter::try_from(&row, this.column_schemas) {
-                Ok(row) => Poll::Ready(Some(Ok(row))),
-                Err(e) => {
-                    let e = TableCopyStreamError::ConversionError(e);
-                    Poll::Ready(Some(Err(e)))
+        let mut this = self.project();
+        let mut next = ready!(this.stream.as_mut().poll_next(cx));
+        if *this.header_pending {
+            if let Some(Ok(header)) = &next {
+                this.bytes_copied
+                    .fetch_add(header.len() as u64, Ordering::Relaxed);
+                *this.header_pending = false;
+                next = ready!(this.stream.as_mut().poll_next(cx));
+            }
+        }
+        match next {
+            Some(Ok(row)) => {
+                this.bytes_copied
+                    .fetch_add(row.len() as u64, Ordering::Relaxed);
+                this.rows_copied.fetch_add(1, Ordering::Relaxed);
+                let start = this.stats.as_ref().map(|_| Instant::now());
+                let row = match this.format {
+                    CopyFormat::Text => TableRowConverter::try_from(&row, this.column_schemas),
+                    CopyFormat::Csv { header: _ } => {
+                        TableRowConverter::try_from_csv(&row, this.column_schemas)
+                    }
+                };
+                if let (Some(stats), Some(start)) = (this.stats.as_ref(), start) {
+                    stats.add_decode_time(start.elapsed());
+                    stats.add_row();
                 }
-            },
+                match row {
+                    Ok(row) => Poll::Ready(Some(Ok(row))),
+                    Err(e) => {
+                        let e = TableCopyStreamError::ConversionError(e);
+                        Poll::Ready(Some(Err(e)))
+                    }
+                }
+            }
             Some(Err(e)) => Poll::Ready(Some(Err(e.into()))),
             None => Poll::Ready(None),
         }
@@ -214,15 +795,151 @@ pub enum CdcStreamError {
 
     #[error("cdc event conversion error: {0}")]
     CdcEventConversion(#[from] CdcEventConversionError),
+
+    #[error("replication client error: {0}")]
+    ReplicationClient(#[from] ReplicationClientError),
 }
 
+type ToastFetch = Pin<Box<dyn Future<Output = Result<CdcEvent, CdcStreamError>> + Send>>;
+
+type SchemaRefresh =
+    Pin<Box<dyn Future<Output = Result<(TableSchema, CdcEvent), CdcStreamError>> + Send>>;
+
+type SchemaChange = Pin<Box<dyn Future<Output = Result<TableSchema, CdcStreamError>> + Send>>;
+
 pin_project! {
     #[must_use = "streams do nothing unless polled"]
     pub struct CdcStream {
         #[pin]
         stream: LogicalReplicationStream,
         table_schemas: HashMap<TableId, TableSchema>,
+        // Replica identities announced by the Relation messages received so far
+        replica_identities: ReplicaIdentities,
         postgres_epoch: SystemTime,
+        toast_lookup_client: Option<Arc<ReplicationClient>>,
+        pending_toast_fetch: Option<ToastFetch>,
+        // Client and publication to refresh stale schemas with
+        schema_refresh: Option<(Arc<ReplicationClient>, Option<String>)>,
+        pending_schema_refresh: Option<SchemaRefresh>,
+        // Tables whose schema was refreshed since their last Relation message, whose
+        // changes failing to decode again aren't worth another refresh
+        refreshed_tables: HashSet<TableId>,
+        // Fetch of the schema to emit after a Relation message announcing a change
+        pending_schema_change: Option<SchemaChange>,
+        enforce_not_null: bool,
+        emit_heartbeats: bool,
+        emit_schema_changes: bool,
+        skip_dropped_tables: bool,
+        // The event to return before reading on, the keepalive after the heartbeat
+        // emitted for it or the change after the schema refreshed for it
+        pending_event: Option<CdcEvent>,
+        // Lsns from which on changes to tables added with `add_table` are applied
+        table_start_lsns: HashMap<TableId, PgLsn>,
+        // Commit lsn of the transaction being received, `None` between transactions
+        // and in streamed transactions
+        final_lsn: Option<PgLsn>,
+    }
+}
+
+/// The columns of an update's new tuple which hold the unchanged TOAST marker
+struct UnchangedToastColumns {
+    table_name: TableName,
+    /// Index and schema of each unchanged column
+    columns: Vec<(usize, ColumnSchema)>,
+    /// Lookup key column names and their values in text format
+    key: Vec<(String, String)>,
+}
+
+impl UnchangedToastColumns {
+    fn try_from_message(
+        message: &ReplicationMessage<LogicalReplicationMessage>,
+        table_schemas: &HashMap<TableId, TableSchema>,
+    ) -> Option<UnchangedToastColumns> {
+        let ReplicationMessage::XLogData(xlog_data) = message else {
+            return None;
+        };
+        let LogicalReplicationMessage::Update(update_body) = xlog_data.data() else {
+            return None;
+        };
+        let table_schema = table_schemas.get(&update_body.rel_id())?;
+        let tuple_data = CdcEventConverter::included_tuple_data(
+            table_schema,
+            update_body.new_tuple().tuple_data(),
+        );
+
+        // Generated columns aren't sent, so the tuple only has the other columns
+        let sent_columns: Vec<(usize, &ColumnSchema)> = table_schema
+            .column_schemas
+            .iter()
+            .enumerate()
+            .filter(|(_, column_schema)| !column_schema.generated)
+            .collect();
+
+        let columns: Vec<(usize, ColumnSchema)> = sent_columns
+            .iter()
+            .zip(&tuple_data)
+            .filter(|(_, data)| matches!(data, TupleData::UnchangedToast))
+            .map(|((i, column_schema), _)| (*i, (*column_schema).clone()))
+            .collect();
+        if columns.is_empty() {
+            return None;
+        }
+
+        let LookupKey::Key {
+            name: _,
+            columns: key_columns,
+        } = &table_schema.lookup_key
+        else {
+            return None;
+        };
+        let mut key = Vec::with_capacity(key_columns.len());
+        for key_column in key_columns {
+            let i = sent_columns
+                .iter()
+                .position(|(_, column_schema)| &column_schema.name == key_column)?;
+            let TupleData::Text(bytes) = tuple_data.get(i)? else {
+                return None;
+            };
+            key.push((key_column.clone(), str::from_utf8(bytes).ok()?.to_string()));
+        }
+
+        Some(UnchangedToastColumns {
+            table_name: table_schema.table_name.clone(),
+            columns,
+            key,
+        })
+    }
+
+    async fn fetch(
+        self,
+        lookup_client: Arc<ReplicationClient>,
+        mut event: CdcEvent,
+    ) -> Result<CdcEvent, CdcStreamError> {
+        let CdcEvent::Update((_, _, new_row, _)) = &mut event else {
+            return Ok(event);
+        };
+        let column_names: Vec<String> = self
+            .columns
+            .iter()
+            .map(|(_, column_schema)| column_schema.name.clone())
+            .collect();
+        let values = lookup_client
+            .get_column_values(&self.table_name, &column_names, &self.key)
+            .await?;
+        // The row might have been deleted since the update, leave the default values in that case
+        if let Some(values) = values {
+            for ((i, column_schema), value) in self.columns.iter().zip(values) {
+                new_row.values[*i] = match value {
+                    Some(value) => TextFormatConverter::try_from_str(&column_schema.typ, &value)
+                        .map_err(CdcEventConversionError::FromBytes)?,
+                    None => Cell::Null,
+                };
+            }
+            new_row
+                .unchanged_toast
+                .retain(|i| !self.columns.iter().any(|(column, _)| column == i));
+        }
+        Ok(event)
     }
 }
 
@@ -236,6 +953,52 @@ pub enum StatusUpdateError {
 }
 
 impl CdcStream {
+    pub(crate) fn new(
+        stream: LogicalReplicationStream,
+        table_schemas: HashMap<TableId, TableSchema>,
+    ) -> CdcStream {
+        const TIME_SEC_CONVERSION: u64 = 946_684_800;
+        let postgres_epoch = UNIX_EPOCH + Duration::from_secs(TIME_SEC_CONVERSION);
+
+        CdcStream {
+            stream,
+            table_schemas,
+            replica_identities: ReplicaIdentities::default(),
+            postgres_epoch,
+            toast_lookup_client: None,
+            pending_toast_fetch: None,
+            schema_refresh: None,
+            pending_schema_refresh: None,
+            refreshed_tables: HashSet::new(),
+            pending_schema_change: None,
+            enforce_not_null: false,
+            emit_heartbeats: false,
+            emit_schema_changes: false,
+            skip_dropped_tables: false,
+            pending_event: None,
+            table_start_lsns: HashMap::new(),
+            final_lsn: None,
+        }
+    }
+
+    /// Adds a table to a running stream, e.g. one backfilled with
+    /// [`SnapshotBackfill::backfill_added_tables`](super::backfill::SnapshotBackfill::backfill_added_tables)
+    /// after being added to the publication. Changes to the table in transactions
+    /// committed before `start_lsn` are dropped, as they are part of the backfill.
+    /// Changes in streamed transactions are sent before their commit lsn is known and
+    /// can't be dropped, so a sink might see changes of large transactions twice.
+    /// Changes the stream receives before the table is added fail to decode with
+    /// [`CdcEventConversionError::MissingSchema`], so the stream must not be read
+    /// between the creation of the backfill's slot and this call, or the changes
+    /// committed after `start_lsn` which it receives meanwhile are lost.
+    pub fn add_table(self: Pin<&mut Self>, table_schema: TableSchema, start_lsn: PgLsn) {
+        let this = self.project();
+        this.table_start_lsns
+            .insert(table_schema.table_id, start_lsn);
+        this.table_schemas
+            .insert(table_schema.table_id, table_schema);
+    }
+
     pub async fn send_status_update(
         self: Pin<&mut Self>,
         lsn: PgLsn,
@@ -248,20 +1011,250 @@ impl CdcStream {
 
         Ok(())
     }
+
+    /// Caches the schema refreshed for a change which failed to decode and returns it
+    /// as a [`CdcEvent::Schema`], leaving the decoded change to be returned next
+    fn refreshed(
+        result: Result<(TableSchema, CdcEvent), CdcStreamError>,
+        table_schemas: &mut HashMap<TableId, TableSchema>,
+        pending_event: &mut Option<CdcEvent>,
+    ) -> Result<CdcEvent, CdcStreamError> {
+        let (table_schema, event) = result?;
+        table_schemas.insert(table_schema.table_id, table_schema.clone());
+        *pending_event = Some(event);
+        Ok(CdcEvent::Schema(table_schema))
+    }
+
+    /// Turns a failed fetch of the schema of a table which was dropped into a
+    /// [`CdcEvent::TableDropped`] if enabled, forgetting the table's schema
+    fn dropped(
+        result: Result<CdcEvent, CdcStreamError>,
+        skip_dropped_tables: bool,
+        table_schemas: &mut HashMap<TableId, TableSchema>,
+    ) -> Result<CdcEvent, CdcStreamError> {
+        match result {
+            Err(CdcStreamError::ReplicationClient(ReplicationClientError::MissingTableId(
+                table_id,
+            ))) if skip_dropped_tables => {
+                warn!("table {table_id} was dropped, skipping its changes");
+                table_schemas.remove(&table_id);
+                Ok(CdcEvent::TableDropped { table_id })
+            }
+            result => result,
+        }
+    }
+}
+
+/// Fetches the current schema of the table of a change which failed to decode and
+/// decodes the change again, returning the original `error` if it fails again
+async fn refresh_schema(
+    client: Arc<ReplicationClient>,
+    publication: Option<String>,
+    row_change: RowChange,
+    identity: Option<RelationIdentity>,
+    error: CdcEventConversionError,
+    enforce_not_null: bool,
+) -> Result<(TableSchema, CdcEvent), CdcStreamError> {
+    info!(
+        "refreshing schema of table {} after a decode error: {error}",
+        row_change.table_id
+    );
+    let table_schema = client
+        .get_table_schema_by_id(row_change.table_id, publication.as_deref())
+        .await?;
+    match row_change.try_into_event(&table_schema, identity.as_ref(), enforce_not_null) {
+        Ok(event) => Ok((table_schema, event)),
+        Err(_) => Err(error.into()),
+    }
+}
+
+/// Whether the columns sent in a Relation message differ in name or type from the
+/// columns of `table_schema` which are sent in row changes
+fn relation_changed(body: &RelationBody, table_schema: &TableSchema) -> bool {
+    let mut sent_columns = vec![];
+    for (i, column) in body.columns().iter().enumerate() {
+        if table_schema.excluded_columns.contains(&i) {
+            continue;
+        }
+        let Ok(name) = column.name() else {
+            return true;
+        };
+        sent_columns.push((name, column.type_id() as u32));
+    }
+    let cached_columns = table_schema
+        .column_schemas
+        .iter()
+        .filter(|column_schema| !column_schema.generated)
+        .map(|column_schema| (column_schema.name.as_str(), column_schema.typ.oid()));
+    !sent_columns.into_iter().eq(cached_columns)
+}
+
+/// Fetches the current schema of a table whose Relation message announced a change
+async fn fetch_changed_schema(
+    client: Arc<ReplicationClient>,
+    publication: Option<String>,
+    table_id: TableId,
+) -> Result<TableSchema, CdcStreamError> {
+    info!("fetching schema of table {table_id} after a schema change");
+    let table_schema = client
+        .get_table_schema_by_id(table_id, publication.as_deref())
+        .await?;
+    Ok(table_schema)
 }
 
 impl Stream for CdcStream {
     type Item = Result<CdcEvent, CdcStreamError>;
 
     fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
-        let this = self.project();
-        match ready!(this.stream.poll_next(cx)) {
-            Some(Ok(msg)) => match CdcEventConverter::try_from(msg, this.table_schemas) {
-                Ok(row) => Poll::Ready(Some(Ok(row))),
-                Err(e) => Poll::Ready(Some(Err(e.into()))),
-            },
-            Some(Err(e)) => Poll::Ready(Some(Err(e.into()))),
-            None => Poll::Ready(None),
+        let mut this = self.project();
+        if let Some(event) = this.pending_event.take() {
+            return Poll::Ready(Some(Ok(event)));
+        }
+        if let Some(toast_fetch) = this.pending_toast_fetch {
+            let result = ready!(toast_fetch.as_mut().poll(cx));
+            *this.pending_toast_fetch = None;
+            return Poll::Ready(Some(result));
+        }
+        if let Some(schema_refresh) = this.pending_schema_refresh {
+            let result = ready!(schema_refresh.as_mut().poll(cx));
+            *this.pending_schema_refresh = None;
+            let result = Self::refreshed(result, this.table_schemas, this.pending_event);
+            return Poll::Ready(Some(Self::dropped(
+                result,
+                *this.skip_dropped_tables,
+                this.table_schemas,
+            )));
+        }
+        if let Some(schema_change) = this.pending_schema_change {
+            let result = ready!(schema_change.as_mut().poll(cx));
+            *this.pending_schema_change = None;
+            let event = result.map(|table_schema| {
+                this.table_schemas
+                    .insert(table_schema.table_id, table_schema.clone());
+                CdcEvent::Schema(table_schema)
+            });
+            return Poll::Ready(Some(Self::dropped(
+                event,
+                *this.skip_dropped_tables,
+                this.table_schemas,
+            )));
+        }
+        loop {
+            let msg = match ready!(this.stream.as_mut().poll_next(cx)) {
+                Some(Ok(msg)) => msg,
+                Some(Err(e)) => return Poll::Ready(Some(Err(e.into()))),
+                None => return Poll::Ready(None),
+            };
+            let unchanged_toast_columns = match this.toast_lookup_client {
+                Some(_) => UnchangedToastColumns::try_from_message(&msg, this.table_schemas),
+                None => None,
+            };
+            let row_change = match this.schema_refresh {
+                Some(_) => RowChange::from_message(&msg),
+                None => None,
+            };
+            let heartbeat = match &msg {
+                ReplicationMessage::PrimaryKeepAlive(keep_alive) if *this.emit_heartbeats => {
+                    Some(CdcEventConverter::heartbeat(keep_alive))
+                }
+                _ => None,
+            };
+            let row = match CdcEventConverter::try_from_with_identities(
+                msg,
+                this.table_schemas,
+                this.replica_identities,
+                *this.enforce_not_null,
+            ) {
+                Ok(row) => row,
+                Err(e) => {
+                    let (Some(row_change), Some((client, publication)), true) = (
+                        row_change,
+                        this.schema_refresh.as_ref(),
+                        matches!(e, CdcEventConversionError::UndecodableRow(_)),
+                    ) else {
+                        return Poll::Ready(Some(Err(e.into())));
+                    };
+                    if !this.refreshed_tables.insert(row_change.table_id) {
+                        return Poll::Ready(Some(Err(e.into())));
+                    }
+                    let identity = this.replica_identities.get(row_change.table_id).cloned();
+                    let mut schema_refresh: SchemaRefresh = Box::pin(refresh_schema(
+                        client.clone(),
+                        publication.clone(),
+                        row_change,
+                        identity,
+                        e,
+                        *this.enforce_not_null,
+                    ));
+                    return match schema_refresh.as_mut().poll(cx) {
+                        Poll::Ready(result) => {
+                            let result =
+                                Self::refreshed(result, this.table_schemas, this.pending_event);
+                            Poll::Ready(Some(Self::dropped(
+                                result,
+                                *this.skip_dropped_tables,
+                                this.table_schemas,
+                            )))
+                        }
+                        Poll::Pending => {
+                            *this.pending_schema_refresh = Some(schema_refresh);
+                            Poll::Pending
+                        }
+                    };
+                }
+            };
+            if let Some(heartbeat) = heartbeat {
+                *this.pending_event = Some(row);
+                return Poll::Ready(Some(Ok(heartbeat)));
+            }
+            match &row {
+                CdcEvent::Begin(begin_body) => {
+                    *this.final_lsn = Some(begin_body.final_lsn().into())
+                }
+                CdcEvent::Commit(_) => *this.final_lsn = None,
+                CdcEvent::Relation(body) => {
+                    this.refreshed_tables.remove(&body.rel_id());
+                    let table_schema = this.table_schemas.get(&body.rel_id());
+                    if let (true, Some((client, publication)), Some(table_schema)) = (
+                        *this.emit_schema_changes,
+                        this.schema_refresh.as_ref(),
+                        table_schema,
+                    ) {
+                        if relation_changed(body, table_schema) {
+                            *this.pending_schema_change = Some(Box::pin(fetch_changed_schema(
+                                client.clone(),
+                                publication.clone(),
+                                body.rel_id(),
+                            )));
+                        }
+                    }
+                }
+                CdcEvent::Insert((table_id, _, _))
+                | CdcEvent::Update((table_id, _, _, _))
+                | CdcEvent::Delete((table_id, _, _)) => {
+                    let start_lsn = this.table_start_lsns.get(table_id);
+                    if let (Some(start_lsn), Some(final_lsn)) = (start_lsn, *this.final_lsn) {
+                        if final_lsn < *start_lsn {
+                            continue;
+                        }
+                    }
+                }
+                _ => {}
+            }
+            return match (unchanged_toast_columns, this.toast_lookup_client) {
+                (Some(columns), Some(lookup_client)) => {
+                    let mut toast_fetch: ToastFetch =
+                        Box::pin(columns.fetch(lookup_client.clone(), row));
+                    match toast_fetch.as_mut().poll(cx) {
+                        Poll::Ready(result) => Poll::Ready(Some(result)),
+                        Poll::Pending => {
+                            *this.pending_toast_fetch = Some(toast_fetch);
+                            Poll::Pending
+                        }
+                    }
+                }
+                _ => Poll::Ready(Some(Ok(row))),
+            };
         }
     }
 }
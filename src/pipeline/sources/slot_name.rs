@@ -0,0 +1,105 @@
+use thiserror::Error;
+
+/// Postgres truncates names to NAMEDATALEN - 1 bytes
+pub const MAX_SLOT_NAME_LEN: usize = 63;
+
+/// Length of the hash suffix appended to derived names which had to be altered
+const HASH_SUFFIX_LEN: usize = 16;
+
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum SlotNameError {
+    #[error("slot name is empty")]
+    Empty,
+
+    #[error(
+        "slot name is {0} bytes long, at most {} are allowed",
+        MAX_SLOT_NAME_LEN
+    )]
+    TooLong(usize),
+
+    #[error("slot name contains {0:?}, only a-z, 0-9 and _ are allowed")]
+    InvalidCharacter(char),
+
+    #[error("{0} is empty")]
+    EmptyComponent(&'static str),
+}
+
+/// Checks that `slot_name` is accepted by Postgres as a replication slot name
+pub fn validate_slot_name(slot_name: &str) -> Result<(), SlotNameError> {
+    if slot_name.is_empty() {
+        return Err(SlotNameError::Empty);
+    }
+    if let Some(c) = slot_name.chars().find(|&c| !is_valid_char(c)) {
+        return Err(SlotNameError::InvalidCharacter(c));
+    }
+    if slot_name.len() > MAX_SLOT_NAME_LEN {
+        return Err(SlotNameError::TooLong(slot_name.len()));
+    }
+    Ok(())
+}
+
+/// Derives a replication slot name from a pipeline's identity, so that a pipeline
+/// finds its slot again after a restart. The name is the lower cased components joined
+/// by underscores, e.g. `acme_orders_orders_pub`. If a component contains characters
+/// not allowed in slot names, they are replaced by underscores, and if the name gets
+/// too long, it is truncated. In both cases a hash of the original components is
+/// appended, so that different identities still get different names.
+pub fn derive_slot_name(
+    tenant_id: &str,
+    pipeline_id: &str,
+    publication: &str,
+) -> Result<String, SlotNameError> {
+    let components = [
+        ("tenant_id", tenant_id),
+        ("pipeline_id", pipeline_id),
+        ("publication", publication),
+    ];
+    for (name, value) in components {
+        if value.is_empty() {
+            return Err(SlotNameError::EmptyComponent(name));
+        }
+    }
+
+    let mut altered = false;
+    let mut slot_name = String::new();
+    for (_, value) in components {
+        if !slot_name.is_empty() {
+            slot_name.push('_');
+        }
+        for c in value.chars() {
+            let sanitized = match c.to_ascii_lowercase() {
+                c if is_valid_char(c) => c,
+                _ => '_',
+            };
+            altered |= sanitized != c;
+            slot_name.push(sanitized);
+        }
+    }
+
+    if altered || slot_name.len() > MAX_SLOT_NAME_LEN {
+        let hash = fnv1a(components.iter().map(|(_, value)| *value));
+        slot_name.truncate(MAX_SLOT_NAME_LEN - HASH_SUFFIX_LEN - 1);
+        slot_name.push_str(&format!("_{hash:0width$x}", width = HASH_SUFFIX_LEN));
+    }
+
+    validate_slot_name(&slot_name)?;
+    Ok(slot_name)
+}
+
+fn is_valid_char(c: char) -> bool {
+    c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_'
+}
+
+/// 64 bit FNV-1a hash, which unlike the std hashers is the same across Rust versions.
+/// Components are separated by a nul byte, which can't occur in Postgres identifiers.
+fn fnv1a<'a>(components: impl Iterator<Item = &'a str>) -> u64 {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for (i, component) in components.enumerate() {
+        let separator: &[u8] = if i == 0 { &[] } else { &[0] };
+        for byte in separator.iter().chain(component.as_bytes()) {
+            hash ^= *byte as u64;
+            hash = hash.wrapping_mul(0x100000001b3);
+        }
+    }
+    hash
+}
@@ -0,0 +1,110 @@
+use std::{
+    collections::HashMap,
+    pin::Pin,
+    task::{Context, Poll},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use bytes::{BufMut, Bytes, BytesMut};
+use futures::{ready, SinkExt, Stream};
+use pin_project_lite::pin_project;
+use postgres_replication::protocol::ReplicationMessage;
+use tokio_postgres::{types::PgLsn, CopyBothDuplex};
+
+use crate::{
+    clients::postgres::ReplicationClientError,
+    conversions::{
+        cdc_event::{CdcEvent, CdcEventConversionError},
+        wal2json::Wal2JsonConverter,
+    },
+    table::{TableId, TableName, TableSchema},
+};
+
+use super::postgres::{CdcStreamError, StatusUpdateError};
+
+pin_project! {
+    /// A stream of cdc events from a slot using the wal2json plugin, see
+    /// [`ReplicationClient::get_wal2json_stream`](crate::clients::postgres::ReplicationClient::get_wal2json_stream).
+    /// Yields the same events as a [`CdcStream`](super::postgres::CdcStream) does for
+    /// pgoutput, except that wal2json has no relation, type or streaming messages.
+    /// [`BatchDataPipeline`](crate::pipeline::batching::data_pipeline::BatchDataPipeline)
+    /// streams from its source's [`CdcStream`](super::postgres::CdcStream) and can't
+    /// use this stream, its events have to be passed to a sink by the caller.
+    #[must_use = "streams do nothing unless polled"]
+    pub struct Wal2JsonStream {
+        #[pin]
+        stream: CopyBothDuplex<Bytes>,
+        table_ids: HashMap<TableName, TableId>,
+        table_schemas: HashMap<TableId, TableSchema>,
+        postgres_epoch: SystemTime,
+    }
+}
+
+impl Wal2JsonStream {
+    pub fn new(
+        stream: CopyBothDuplex<Bytes>,
+        table_schemas: HashMap<TableId, TableSchema>,
+    ) -> Wal2JsonStream {
+        const TIME_SEC_CONVERSION: u64 = 946_684_800;
+        let postgres_epoch = UNIX_EPOCH + Duration::from_secs(TIME_SEC_CONVERSION);
+
+        let table_ids = table_schemas
+            .values()
+            .map(|table_schema| (table_schema.table_name.clone(), table_schema.table_id))
+            .collect();
+        Wal2JsonStream {
+            stream,
+            table_ids,
+            table_schemas,
+            postgres_epoch,
+        }
+    }
+
+    pub async fn send_status_update(
+        self: Pin<&mut Self>,
+        lsn: PgLsn,
+    ) -> Result<(), StatusUpdateError> {
+        let mut this = self.project();
+        let ts = this.postgres_epoch.elapsed()?.as_micros() as i64;
+        let lsn: u64 = lsn.into();
+
+        let mut buf = BytesMut::with_capacity(34);
+        buf.put_u8(b'r');
+        buf.put_u64(lsn);
+        buf.put_u64(lsn);
+        buf.put_u64(lsn);
+        buf.put_i64(ts);
+        buf.put_u8(0);
+        this.stream.send(buf.freeze()).await?;
+
+        Ok(())
+    }
+}
+
+impl Stream for Wal2JsonStream {
+    type Item = Result<CdcEvent, CdcStreamError>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.project();
+        let buf = match ready!(this.stream.poll_next(cx)) {
+            Some(Ok(buf)) => buf,
+            Some(Err(e)) => return Poll::Ready(Some(Err(e.into()))),
+            None => return Poll::Ready(None),
+        };
+        let event = match ReplicationMessage::parse(&buf) {
+            Ok(ReplicationMessage::XLogData(xlog_data)) => {
+                Wal2JsonConverter::try_from(xlog_data.data(), this.table_ids, this.table_schemas)
+                    .map_err(CdcStreamError::from)
+            }
+            Ok(ReplicationMessage::PrimaryKeepAlive(keep_alive)) => {
+                Ok(CdcEvent::KeepAliveRequested {
+                    reply: keep_alive.reply() == 1,
+                    wal_end: keep_alive.wal_end().into(),
+                })
+            }
+            Ok(_) => Err(CdcEventConversionError::UnknownReplicationMessage.into()),
+            Err(e) => Err(ReplicationClientError::InvalidMessage(e.to_string()).into()),
+        };
+        Poll::Ready(Some(event))
+    }
+}
@@ -0,0 +1,771 @@
+use std::{
+    fs,
+    io::SeekFrom,
+    path::PathBuf,
+    time::{Duration, UNIX_EPOCH},
+};
+
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use chrono::{DateTime, Datelike, NaiveDate, NaiveTime, Timelike, Utc};
+use thiserror::Error;
+use tokio::{
+    fs::{File, OpenOptions},
+    io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt, BufReader, BufWriter},
+};
+use tokio_postgres::types::PgLsn;
+use tracing::info;
+use uuid::Uuid;
+
+use crate::conversions::{
+    bits::PgBits,
+    cdc_event::{CdcEvent, CdcEventConversionError},
+    geometry::PgGeometry,
+    interval::PgInterval,
+    range::PgRange,
+    size::estimated_size,
+    table_row::TableRow,
+    ArrayCell, Cell,
+};
+
+#[derive(Debug, Error)]
+pub enum SpillBufferError {
+    #[error("io error: {0}")]
+    Io(#[from] std::io::Error),
+
+    #[error("failed to decode spilled event: {0}")]
+    Decode(#[from] CdcEventConversionError),
+
+    #[error("corrupt spill file: {0}")]
+    Corrupt(String),
+}
+
+/// Buffers the events of a transaction held by the global lsn barrier of a
+/// [`FanOutSink`](crate::pipeline::sinks::fan_out::FanOutSink) until it is complete,
+/// in memory until they take up more than `memory_limit` bytes. Then all buffered
+/// events and all events pushed later are written to a temporary file instead, so
+/// that a huge transaction doesn't exhaust the process' memory. The memory used by
+/// events is estimated from the size of their values.
+pub struct SpillBuffer {
+    memory_limit: usize,
+    directory: PathBuf,
+    events: Vec<CdcEvent>,
+    memory_used: usize,
+    file: Option<(SpillPath, BufWriter<File>)>,
+    spilled_events: usize,
+}
+
+impl SpillBuffer {
+    /// Creates a buffer which spills to a file in `directory`, e.g.
+    /// [`std::env::temp_dir`]
+    pub fn new(memory_limit: usize, directory: impl Into<PathBuf>) -> SpillBuffer {
+        SpillBuffer {
+            memory_limit,
+            directory: directory.into(),
+            events: vec![],
+            memory_used: 0,
+            file: None,
+            spilled_events: 0,
+        }
+    }
+
+    pub async fn push(&mut self, event: CdcEvent) -> Result<(), SpillBufferError> {
+        if let Some((_, writer)) = &mut self.file {
+            write_event(writer, &event).await?;
+            self.spilled_events += 1;
+            return Ok(());
+        }
+        self.memory_used += estimated_size(&event);
+        self.events.push(event);
+        if self.memory_used > self.memory_limit {
+            self.spill().await?;
+        }
+        Ok(())
+    }
+
+    /// Number of buffered events, both in memory and spilled
+    pub fn len(&self) -> usize {
+        self.events.len() + self.spilled_events
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Whether the events have been spilled to a file
+    pub fn is_spilled(&self) -> bool {
+        self.file.is_some()
+    }
+
+    async fn spill(&mut self) -> Result<(), SpillBufferError> {
+        let path = self
+            .directory
+            .join(format!("pg_replicate_spill_{}", Uuid::new_v4()));
+        info!(
+            "spilling {} buffered events to {} after exceeding {} bytes",
+            self.events.len(),
+            path.display(),
+            self.memory_limit
+        );
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create_new(true)
+            .open(&path)
+            .await?;
+        let path = SpillPath(Some(path));
+        let mut writer = BufWriter::new(file);
+        for event in self.events.drain(..) {
+            write_event(&mut writer, &event).await?;
+            self.spilled_events += 1;
+        }
+        self.memory_used = 0;
+        self.file = Some((path, writer));
+        Ok(())
+    }
+
+    /// Returns the buffered events in the order they were pushed. Events which
+    /// weren't spilled are returned as a single chunk, spilled events are read back
+    /// in chunks of about the memory limit, and the file is removed once all chunks
+    /// are read or the chunks are dropped.
+    pub async fn into_chunks(self) -> Result<SpillChunks, SpillBufferError> {
+        let file = match self.file {
+            Some((path, mut writer)) => {
+                writer.flush().await?;
+                let mut file = writer.into_inner();
+                file.seek(SeekFrom::Start(0)).await?;
+                Some((path, BufReader::new(file)))
+            }
+            None => None,
+        };
+        Ok(SpillChunks {
+            memory_limit: self.memory_limit,
+            events: Some(self.events).filter(|events| !events.is_empty()),
+            file,
+            remaining: self.spilled_events,
+        })
+    }
+}
+
+/// The chunks of events of a [`SpillBuffer`], see [`SpillBuffer::into_chunks`]
+pub struct SpillChunks {
+    memory_limit: usize,
+    events: Option<Vec<CdcEvent>>,
+    file: Option<(SpillPath, BufReader<File>)>,
+    remaining: usize,
+}
+
+impl SpillChunks {
+    /// Returns the next chunk, `None` once all events were returned
+    pub async fn next_chunk(&mut self) -> Result<Option<Vec<CdcEvent>>, SpillBufferError> {
+        if let Some(events) = self.events.take() {
+            return Ok(Some(events));
+        }
+        let Some((_, reader)) = self.file.as_mut() else {
+            return Ok(None);
+        };
+        if self.remaining == 0 {
+            if let Some((path, _)) = self.file.take() {
+                path.remove().await?;
+            }
+            return Ok(None);
+        }
+
+        let mut chunk = vec![];
+        let mut chunk_size = 0;
+        while self.remaining > 0 && chunk_size <= self.memory_limit {
+            let event = match read_event(reader).await {
+                Ok(event) => event,
+                Err(e) => {
+                    self.remaining = 0;
+                    return Err(e);
+                }
+            };
+            self.remaining -= 1;
+            chunk_size += estimated_size(&event);
+            chunk.push(event);
+        }
+        Ok(Some(chunk))
+    }
+}
+
+/// Removes the spill file when dropped, unless it was removed already
+struct SpillPath(Option<PathBuf>);
+
+impl SpillPath {
+    async fn remove(mut self) -> Result<(), SpillBufferError> {
+        if let Some(path) = self.0.take() {
+            tokio::fs::remove_file(path).await?;
+        }
+        Ok(())
+    }
+}
+
+impl Drop for SpillPath {
+    fn drop(&mut self) {
+        // Drop can't wait for tokio's file operations, so the file of a buffer
+        // dropped before all of its chunks were read is removed synchronously
+        if let Some(path) = self.0.take() {
+            let _ = fs::remove_file(path);
+        }
+    }
+}
+
+// Tags of the encoded events
+const MESSAGE: u8 = 0;
+const INSERT: u8 = 1;
+const UPDATE: u8 = 2;
+const DELETE: u8 = 3;
+const KEEP_ALIVE: u8 = 4;
+const HEARTBEAT: u8 = 5;
+
+// Tags of the encoded cells, which also tag arrays by their element type
+const NULL: u8 = 0;
+const BOOL: u8 = 1;
+const STRING: u8 = 2;
+const I16: u8 = 3;
+const I32: u8 = 4;
+const U32: u8 = 5;
+const I64: u8 = 6;
+const F32: u8 = 7;
+const F64: u8 = 8;
+const NUMERIC: u8 = 9;
+const DATE: u8 = 10;
+const TIME: u8 = 11;
+const TIMESTAMP: u8 = 12;
+const TIMESTAMPTZ: u8 = 13;
+const INTERVAL: u8 = 14;
+const UUID: u8 = 15;
+const JSON: u8 = 16;
+const BYTES: u8 = 17;
+const ARRAY: u8 = 18;
+const NESTED: u8 = 19;
+const RANGE: u8 = 20;
+const MULTIRANGE: u8 = 21;
+const GEOMETRY: u8 = 22;
+const BITS: u8 = 23;
+
+// Flags of a range
+const RANGE_EMPTY: u8 = 1;
+const RANGE_LOWER_INC: u8 = 2;
+const RANGE_UPPER_INC: u8 = 4;
+
+/// Writes an event prefixed by its length. Row changes are encoded value by value,
+/// the other events as the pgoutput messages they were parsed from.
+async fn write_event(
+    writer: &mut BufWriter<File>,
+    event: &CdcEvent,
+) -> Result<(), SpillBufferError> {
+    let mut buf = BytesMut::new();
+    match event {
+        CdcEvent::Insert((table_id, row, xid)) => {
+            buf.put_u8(INSERT);
+            buf.put_u32(*table_id);
+            put_xid(&mut buf, *xid);
+            put_row(&mut buf, row);
+        }
+        CdcEvent::Update((table_id, old_row, new_row, xid)) => {
+            buf.put_u8(UPDATE);
+            buf.put_u32(*table_id);
+            put_xid(&mut buf, *xid);
+            buf.put_u8(old_row.is_some() as u8);
+            if let Some(old_row) = old_row {
+                put_row(&mut buf, old_row);
+            }
+            put_row(&mut buf, new_row);
+        }
+        CdcEvent::Delete((table_id, row, xid)) => {
+            buf.put_u8(DELETE);
+            buf.put_u32(*table_id);
+            put_xid(&mut buf, *xid);
+            put_row(&mut buf, row);
+        }
+        CdcEvent::KeepAliveRequested { reply, wal_end } => {
+            buf.put_u8(KEEP_ALIVE);
+            buf.put_u8(*reply as u8);
+            buf.put_u64((*wal_end).into());
+        }
+        CdcEvent::Heartbeat { lsn, server_time } => {
+            let since_epoch = server_time.duration_since(UNIX_EPOCH).unwrap_or_default();
+            buf.put_u8(HEARTBEAT);
+            buf.put_u64((*lsn).into());
+            buf.put_u64(since_epoch.as_secs());
+            buf.put_u32(since_epoch.subsec_nanos());
+        }
+        event => {
+            buf.put_u8(MESSAGE);
+            let mut message = BytesMut::new();
+            let in_stream = event.encode_message(&mut message)?;
+            buf.put_u8(in_stream as u8);
+            buf.put_slice(&message);
+        }
+    }
+    writer.write_u32(buf.len() as u32).await?;
+    writer.write_all(&buf).await?;
+    Ok(())
+}
+
+fn put_xid(buf: &mut BytesMut, xid: Option<u32>) {
+    buf.put_u8(xid.is_some() as u8);
+    buf.put_u32(xid.unwrap_or_default());
+}
+
+fn put_row(buf: &mut BytesMut, row: &TableRow) {
+    buf.put_u32(row.values.len() as u32);
+    for cell in &row.values {
+        put_cell(buf, cell);
+    }
+    buf.put_u32(row.unchanged_toast.len() as u32);
+    for &i in &row.unchanged_toast {
+        buf.put_u32(i as u32);
+    }
+}
+
+fn put_bytes(buf: &mut BytesMut, bytes: &[u8]) {
+    buf.put_u32(bytes.len() as u32);
+    buf.put_slice(bytes);
+}
+
+fn put_cell(buf: &mut BytesMut, cell: &Cell) {
+    match cell {
+        Cell::Null => buf.put_u8(NULL),
+        Cell::Bool(v) => {
+            buf.put_u8(BOOL);
+            buf.put_u8(*v as u8);
+        }
+        Cell::String(v) => {
+            buf.put_u8(STRING);
+            put_bytes(buf, v.as_bytes());
+        }
+        Cell::I16(v) => {
+            buf.put_u8(I16);
+            buf.put_i16(*v);
+        }
+        Cell::I32(v) => {
+            buf.put_u8(I32);
+            buf.put_i32(*v);
+        }
+        Cell::U32(v) => {
+            buf.put_u8(U32);
+            buf.put_u32(*v);
+        }
+        Cell::I64(v) => {
+            buf.put_u8(I64);
+            buf.put_i64(*v);
+        }
+        Cell::F32(v) => {
+            buf.put_u8(F32);
+            buf.put_f32(*v);
+        }
+        Cell::F64(v) => {
+            buf.put_u8(F64);
+            buf.put_f64(*v);
+        }
+        Cell::Numeric(v) => {
+            buf.put_u8(NUMERIC);
+            put_bytes(buf, v.to_string().as_bytes());
+        }
+        Cell::Date(v) => {
+            buf.put_u8(DATE);
+            buf.put_i32(v.num_days_from_ce());
+        }
+        Cell::Time(v) => {
+            buf.put_u8(TIME);
+            buf.put_u32(v.num_seconds_from_midnight());
+            buf.put_u32(v.nanosecond());
+        }
+        Cell::TimeStamp(v) => {
+            buf.put_u8(TIMESTAMP);
+            buf.put_i64(v.and_utc().timestamp());
+            buf.put_u32(v.and_utc().timestamp_subsec_nanos());
+        }
+        Cell::TimeStampTz(v) => {
+            buf.put_u8(TIMESTAMPTZ);
+            buf.put_i64(v.timestamp());
+            buf.put_u32(v.timestamp_subsec_nanos());
+        }
+        Cell::Interval(v) => {
+            buf.put_u8(INTERVAL);
+            buf.put_i32(v.months);
+            buf.put_i32(v.days);
+            buf.put_i64(v.microseconds);
+        }
+        Cell::Uuid(v) => {
+            buf.put_u8(UUID);
+            buf.put_slice(v.as_bytes());
+        }
+        Cell::Json(v) => {
+            buf.put_u8(JSON);
+            put_bytes(buf, v.to_string().as_bytes());
+        }
+        Cell::Bytes(v) => {
+            buf.put_u8(BYTES);
+            put_bytes(buf, v);
+        }
+        Cell::Array(array) => {
+            buf.put_u8(ARRAY);
+            put_array(buf, array);
+        }
+        Cell::Range(range) => {
+            buf.put_u8(RANGE);
+            put_range(buf, range);
+        }
+        Cell::Multirange(ranges) => {
+            buf.put_u8(MULTIRANGE);
+            buf.put_u32(ranges.len() as u32);
+            for range in ranges {
+                put_range(buf, range);
+            }
+        }
+        Cell::Geometry(v) => {
+            buf.put_u8(GEOMETRY);
+            put_bytes(buf, v.ewkb());
+        }
+        Cell::Bits(v) => {
+            buf.put_u8(BITS);
+            put_bytes(buf, v.to_string().as_bytes());
+        }
+    }
+}
+
+/// Writes a range's flags followed by its bounds as cells, with unbounded ends as
+/// [`Cell::Null`]
+fn put_range(buf: &mut BytesMut, range: &PgRange) {
+    let mut flags = 0;
+    if range.empty {
+        flags |= RANGE_EMPTY;
+    }
+    if range.lower_inc {
+        flags |= RANGE_LOWER_INC;
+    }
+    if range.upper_inc {
+        flags |= RANGE_UPPER_INC;
+    }
+    buf.put_u8(flags);
+    for bound in [&range.lower, &range.upper] {
+        put_cell(buf, bound.as_deref().unwrap_or(&Cell::Null));
+    }
+}
+
+/// Writes an array's element type followed by its elements as cells, with nulls
+/// as [`Cell::Null`]
+fn put_array(buf: &mut BytesMut, array: &ArrayCell) {
+    fn elements<T: Clone>(
+        buf: &mut BytesMut,
+        tag: u8,
+        values: &[Option<T>],
+        to_cell: impl Fn(T) -> Cell,
+    ) {
+        buf.put_u8(tag);
+        buf.put_u32(values.len() as u32);
+        for value in values {
+            put_cell(buf, &value.clone().map(&to_cell).unwrap_or(Cell::Null));
+        }
+    }
+
+    match array {
+        ArrayCell::Null => buf.put_u8(NULL),
+        ArrayCell::Bool(v) => elements(buf, BOOL, v, Cell::Bool),
+        ArrayCell::String(v) => elements(buf, STRING, v, Cell::String),
+        ArrayCell::I16(v) => elements(buf, I16, v, Cell::I16),
+        ArrayCell::I32(v) => elements(buf, I32, v, Cell::I32),
+        ArrayCell::U32(v) => elements(buf, U32, v, Cell::U32),
+        ArrayCell::I64(v) => elements(buf, I64, v, Cell::I64),
+        ArrayCell::F32(v) => elements(buf, F32, v, Cell::F32),
+        ArrayCell::F64(v) => elements(buf, F64, v, Cell::F64),
+        ArrayCell::Numeric(v) => elements(buf, NUMERIC, v, Cell::Numeric),
+        ArrayCell::Date(v) => elements(buf, DATE, v, Cell::Date),
+        ArrayCell::Time(v) => elements(buf, TIME, v, Cell::Time),
+        ArrayCell::TimeStamp(v) => elements(buf, TIMESTAMP, v, Cell::TimeStamp),
+        ArrayCell::TimeStampTz(v) => elements(buf, TIMESTAMPTZ, v, Cell::TimeStampTz),
+        ArrayCell::Interval(v) => elements(buf, INTERVAL, v, Cell::Interval),
+        ArrayCell::Uuid(v) => elements(buf, UUID, v, Cell::Uuid),
+        ArrayCell::Json(v) => elements(buf, JSON, v, Cell::Json),
+        ArrayCell::Bytes(v) => elements(buf, BYTES, v, Cell::Bytes),
+        ArrayCell::Nested(v) => {
+            buf.put_u8(NESTED);
+            buf.put_u32(v.len() as u32);
+            for sub_array in v {
+                put_array(buf, sub_array);
+            }
+        }
+    }
+}
+
+async fn read_event(reader: &mut BufReader<File>) -> Result<CdcEvent, SpillBufferError> {
+    let len = reader.read_u32().await?;
+    let mut event = vec![0; len as usize];
+    reader.read_exact(&mut event).await?;
+    let mut buf = Bytes::from(event);
+
+    let event = match get_u8(&mut buf)? {
+        INSERT => {
+            let table_id = get_u32(&mut buf)?;
+            let xid = get_xid(&mut buf)?;
+            CdcEvent::Insert((table_id, get_row(&mut buf)?, xid))
+        }
+        UPDATE => {
+            let table_id = get_u32(&mut buf)?;
+            let xid = get_xid(&mut buf)?;
+            let old_row = match get_u8(&mut buf)? {
+                0 => None,
+                _ => Some(get_row(&mut buf)?),
+            };
+            CdcEvent::Update((table_id, old_row, get_row(&mut buf)?, xid))
+        }
+        DELETE => {
+            let table_id = get_u32(&mut buf)?;
+            let xid = get_xid(&mut buf)?;
+            CdcEvent::Delete((table_id, get_row(&mut buf)?, xid))
+        }
+        KEEP_ALIVE => CdcEvent::KeepAliveRequested {
+            reply: get_u8(&mut buf)? != 0,
+            wal_end: PgLsn::from(get_u64(&mut buf)?),
+        },
+        HEARTBEAT => {
+            let lsn = PgLsn::from(get_u64(&mut buf)?);
+            let secs = get_u64(&mut buf)?;
+            let nanos = get_u32(&mut buf)?;
+            CdcEvent::Heartbeat {
+                lsn,
+                server_time: UNIX_EPOCH + Duration::new(secs, nanos),
+            }
+        }
+        MESSAGE => {
+            let in_stream = get_u8(&mut buf)? != 0;
+            CdcEvent::decode_message(&buf, in_stream)?
+        }
+        tag => return Err(corrupt(format!("unknown event tag {tag}"))),
+    };
+    Ok(event)
+}
+
+fn corrupt(reason: impl Into<String>) -> SpillBufferError {
+    SpillBufferError::Corrupt(reason.into())
+}
+
+fn ensure_remaining(buf: &Bytes, len: usize) -> Result<(), SpillBufferError> {
+    if buf.remaining() < len {
+        return Err(corrupt("unexpected end of event"));
+    }
+    Ok(())
+}
+
+fn get_u8(buf: &mut Bytes) -> Result<u8, SpillBufferError> {
+    ensure_remaining(buf, 1)?;
+    Ok(buf.get_u8())
+}
+
+fn get_u32(buf: &mut Bytes) -> Result<u32, SpillBufferError> {
+    ensure_remaining(buf, 4)?;
+    Ok(buf.get_u32())
+}
+
+fn get_u64(buf: &mut Bytes) -> Result<u64, SpillBufferError> {
+    ensure_remaining(buf, 8)?;
+    Ok(buf.get_u64())
+}
+
+fn get_bytes(buf: &mut Bytes) -> Result<Bytes, SpillBufferError> {
+    let len = get_u32(buf)? as usize;
+    ensure_remaining(buf, len)?;
+    Ok(buf.split_to(len))
+}
+
+fn get_string(buf: &mut Bytes) -> Result<String, SpillBufferError> {
+    String::from_utf8(get_bytes(buf)?.to_vec()).map_err(|e| corrupt(e.to_string()))
+}
+
+fn get_xid(buf: &mut Bytes) -> Result<Option<u32>, SpillBufferError> {
+    let has_xid = get_u8(buf)? != 0;
+    let xid = get_u32(buf)?;
+    Ok(has_xid.then_some(xid))
+}
+
+fn get_row(buf: &mut Bytes) -> Result<TableRow, SpillBufferError> {
+    let len = get_u32(buf)? as usize;
+    let mut values = Vec::with_capacity(len.min(buf.remaining()));
+    for _ in 0..len {
+        values.push(get_cell(buf)?);
+    }
+    let len = get_u32(buf)? as usize;
+    let mut unchanged_toast = Vec::with_capacity(len.min(buf.remaining()));
+    for _ in 0..len {
+        unchanged_toast.push(get_u32(buf)? as usize);
+    }
+    Ok(TableRow {
+        values,
+        unchanged_toast,
+    })
+}
+
+fn get_cell(buf: &mut Bytes) -> Result<Cell, SpillBufferError> {
+    let tag = get_u8(buf)?;
+    get_value(buf, tag)
+}
+
+/// Reads the value of a cell with type `tag`
+fn get_value(buf: &mut Bytes, tag: u8) -> Result<Cell, SpillBufferError> {
+    let cell = match tag {
+        NULL => Cell::Null,
+        BOOL => Cell::Bool(get_u8(buf)? != 0),
+        STRING => Cell::String(get_string(buf)?),
+        I16 => {
+            ensure_remaining(buf, 2)?;
+            Cell::I16(buf.get_i16())
+        }
+        I32 => Cell::I32(get_u32(buf)? as i32),
+        U32 => Cell::U32(get_u32(buf)?),
+        I64 => Cell::I64(get_u64(buf)? as i64),
+        F32 => Cell::F32(f32::from_bits(get_u32(buf)?)),
+        F64 => Cell::F64(f64::from_bits(get_u64(buf)?)),
+        NUMERIC => Cell::Numeric(
+            get_string(buf)?
+                .parse()
+                .map_err(|e| corrupt(format!("invalid numeric: {e}")))?,
+        ),
+        DATE => {
+            let days = get_u32(buf)? as i32;
+            Cell::Date(
+                NaiveDate::from_num_days_from_ce_opt(days)
+                    .ok_or_else(|| corrupt(format!("invalid date {days}")))?,
+            )
+        }
+        TIME => {
+            let secs = get_u32(buf)?;
+            let nanos = get_u32(buf)?;
+            Cell::Time(
+                NaiveTime::from_num_seconds_from_midnight_opt(secs, nanos)
+                    .ok_or_else(|| corrupt(format!("invalid time {secs}.{nanos}")))?,
+            )
+        }
+        TIMESTAMP | TIMESTAMPTZ => {
+            let secs = get_u64(buf)? as i64;
+            let nanos = get_u32(buf)?;
+            let timestamp: DateTime<Utc> = DateTime::from_timestamp(secs, nanos)
+                .ok_or_else(|| corrupt(format!("invalid timestamp {secs}.{nanos}")))?;
+            if tag == TIMESTAMP {
+                Cell::TimeStamp(timestamp.naive_utc())
+            } else {
+                Cell::TimeStampTz(timestamp)
+            }
+        }
+        INTERVAL => {
+            let months = get_u32(buf)? as i32;
+            let days = get_u32(buf)? as i32;
+            let microseconds = get_u64(buf)? as i64;
+            Cell::Interval(PgInterval {
+                months,
+                days,
+                microseconds,
+            })
+        }
+        UUID => {
+            ensure_remaining(buf, 16)?;
+            let uuid = Uuid::from_slice(&buf.split_to(16)).map_err(|e| corrupt(e.to_string()))?;
+            Cell::Uuid(uuid)
+        }
+        JSON => Cell::Json(
+            serde_json::from_str(&get_string(buf)?)
+                .map_err(|e| corrupt(format!("invalid json: {e}")))?,
+        ),
+        BYTES => Cell::Bytes(get_bytes(buf)?.to_vec()),
+        ARRAY => Cell::Array(get_array(buf)?),
+        RANGE => Cell::Range(get_range(buf)?),
+        MULTIRANGE => {
+            let len = get_u32(buf)? as usize;
+            let mut ranges = Vec::with_capacity(len.min(buf.remaining()));
+            for _ in 0..len {
+                ranges.push(get_range(buf)?);
+            }
+            Cell::Multirange(ranges)
+        }
+        GEOMETRY => Cell::Geometry(
+            PgGeometry::from_ewkb(get_bytes(buf)?.to_vec())
+                .map_err(|e| corrupt(format!("invalid geometry: {e}")))?,
+        ),
+        BITS => Cell::Bits(
+            PgBits::from_text(&get_string(buf)?)
+                .map_err(|e| corrupt(format!("invalid bit string: {e}")))?,
+        ),
+        tag => return Err(corrupt(format!("unknown cell tag {tag}"))),
+    };
+    Ok(cell)
+}
+
+fn get_range(buf: &mut Bytes) -> Result<PgRange, SpillBufferError> {
+    let flags = get_u8(buf)?;
+    let mut get_bound = || -> Result<Option<Box<Cell>>, SpillBufferError> {
+        Ok(match get_cell(buf)? {
+            Cell::Null => None,
+            cell => Some(Box::new(cell)),
+        })
+    };
+    let lower = get_bound()?;
+    let upper = get_bound()?;
+    Ok(PgRange {
+        lower,
+        upper,
+        lower_inc: flags & RANGE_LOWER_INC != 0,
+        upper_inc: flags & RANGE_UPPER_INC != 0,
+        empty: flags & RANGE_EMPTY != 0,
+    })
+}
+
+/// Reads the elements of an array of type `$array`, whose elements are `$cell`s
+macro_rules! get_elements {
+    ($buf:expr, $array:path, $cell:path) => {
+        $array(get_elements($buf, |cell| match cell {
+            $cell(v) => Some(v),
+            _ => None,
+        })?)
+    };
+}
+
+fn get_elements<T>(
+    buf: &mut Bytes,
+    from_cell: impl Fn(Cell) -> Option<T>,
+) -> Result<Vec<Option<T>>, SpillBufferError> {
+    let len = get_u32(buf)? as usize;
+    let mut values = Vec::with_capacity(len.min(buf.remaining()));
+    for _ in 0..len {
+        let value = match get_cell(buf)? {
+            Cell::Null => None,
+            cell => Some(from_cell(cell).ok_or_else(|| corrupt("array element of wrong type"))?),
+        };
+        values.push(value);
+    }
+    Ok(values)
+}
+
+fn get_array(buf: &mut Bytes) -> Result<ArrayCell, SpillBufferError> {
+    let array = match get_u8(buf)? {
+        NULL => ArrayCell::Null,
+        BOOL => get_elements!(buf, ArrayCell::Bool, Cell::Bool),
+        STRING => get_elements!(buf, ArrayCell::String, Cell::String),
+        I16 => get_elements!(buf, ArrayCell::I16, Cell::I16),
+        I32 => get_elements!(buf, ArrayCell::I32, Cell::I32),
+        U32 => get_elements!(buf, ArrayCell::U32, Cell::U32),
+        I64 => get_elements!(buf, ArrayCell::I64, Cell::I64),
+        F32 => get_elements!(buf, ArrayCell::F32, Cell::F32),
+        F64 => get_elements!(buf, ArrayCell::F64, Cell::F64),
+        NUMERIC => get_elements!(buf, ArrayCell::Numeric, Cell::Numeric),
+        DATE => get_elements!(buf, ArrayCell::Date, Cell::Date),
+        TIME => get_elements!(buf, ArrayCell::Time, Cell::Time),
+        TIMESTAMP => get_elements!(buf, ArrayCell::TimeStamp, Cell::TimeStamp),
+        TIMESTAMPTZ => get_elements!(buf, ArrayCell::TimeStampTz, Cell::TimeStampTz),
+        INTERVAL => get_elements!(buf, ArrayCell::Interval, Cell::Interval),
+        UUID => get_elements!(buf, ArrayCell::Uuid, Cell::Uuid),
+        JSON => get_elements!(buf, ArrayCell::Json, Cell::Json),
+        BYTES => get_elements!(buf, ArrayCell::Bytes, Cell::Bytes),
+        NESTED => {
+            let len = get_u32(buf)? as usize;
+            let mut sub_arrays = Vec::with_capacity(len.min(buf.remaining()));
+            for _ in 0..len {
+                sub_arrays.push(get_array(buf)?);
+            }
+            ArrayCell::Nested(sub_arrays)
+        }
+        tag => return Err(corrupt(format!("unknown array tag {tag}"))),
+    };
+    Ok(array)
+}
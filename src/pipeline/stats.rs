@@ -0,0 +1,279 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fmt::Display,
+    mem,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+    time::{Duration, SystemTime},
+};
+
+use tokio_postgres::types::PgLsn;
+
+use crate::{
+    conversions::cdc_event::{CdcEvent, CdcEventConverter},
+    table::TableId,
+};
+
+/// Time spent in each stage of table copies, to tell whether a slow backfill is
+/// bound by the network, by decoding or by the sink. Reading is the time spent
+/// waiting for rows from Postgres, decoding the time spent converting them to
+/// [`TableRow`](crate::conversions::table_row::TableRow)s including transforms
+/// and coercions, and writing the time spent in
+/// [`BatchSink::write_table_rows`](crate::pipeline::sinks::BatchSink::write_table_rows).
+#[derive(Debug, Default)]
+pub struct BackfillStats {
+    read_nanos: AtomicU64,
+    decode_nanos: AtomicU64,
+    write_nanos: AtomicU64,
+    rows: AtomicU64,
+}
+
+impl BackfillStats {
+    pub fn new() -> BackfillStats {
+        BackfillStats::default()
+    }
+
+    pub fn read_time(&self) -> Duration {
+        Duration::from_nanos(self.read_nanos.load(Ordering::Relaxed))
+    }
+
+    pub fn decode_time(&self) -> Duration {
+        Duration::from_nanos(self.decode_nanos.load(Ordering::Relaxed))
+    }
+
+    pub fn write_time(&self) -> Duration {
+        Duration::from_nanos(self.write_nanos.load(Ordering::Relaxed))
+    }
+
+    /// Number of rows decoded
+    pub fn rows(&self) -> u64 {
+        self.rows.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn add_read_time(&self, duration: Duration) {
+        Self::add(&self.read_nanos, duration);
+    }
+
+    pub(crate) fn add_decode_time(&self, duration: Duration) {
+        Self::add(&self.decode_nanos, duration);
+    }
+
+    pub(crate) fn add_write_time(&self, duration: Duration) {
+        Self::add(&self.write_nanos, duration);
+    }
+
+    pub(crate) fn add_row(&self) {
+        self.rows.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn add(nanos: &AtomicU64, duration: Duration) {
+        nanos.fetch_add(duration.as_nanos() as u64, Ordering::Relaxed);
+    }
+}
+
+impl Display for BackfillStats {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} rows, read {:?}, decode {:?}, write {:?}",
+            self.rows(),
+            self.read_time(),
+            self.decode_time(),
+            self.write_time()
+        )
+    }
+}
+
+/// How far behind the source the sink is in time: when the sink has written a
+/// transaction, its apply lag is the time since the transaction committed on the
+/// source. The commit time is taken from the source's clock and compared with the
+/// local clock, so clock skew between the two hosts adds to or subtracts from the
+/// lag, and a lag which would be negative is reported as zero. Lags are kept for
+/// the pipeline as a whole and for each table, as of the last transaction written
+/// which changed the table.
+#[derive(Debug, Default)]
+pub struct ApplyLagStats {
+    state: Mutex<ApplyLagState>,
+}
+
+#[derive(Debug, Default)]
+struct ApplyLagState {
+    lag: Option<Duration>,
+    table_lags: HashMap<TableId, Duration>,
+    /// Tables changed by each transaction which hasn't committed yet, keyed by the
+    /// xid of streamed transactions and `None` for the others
+    open_transactions: HashMap<Option<u32>, HashSet<TableId>>,
+    /// Commit times and changed tables of transactions passed to the sink but not
+    /// written yet
+    pending_commits: Vec<(SystemTime, HashSet<TableId>)>,
+}
+
+impl ApplyLagStats {
+    pub fn new() -> ApplyLagStats {
+        ApplyLagStats::default()
+    }
+
+    /// The apply lag of the last transaction written, `None` before any was written
+    pub fn lag(&self) -> Option<Duration> {
+        self.state.lock().unwrap().lag
+    }
+
+    /// The apply lag of the last transaction written which changed the table
+    pub fn table_lag(&self, table_id: TableId) -> Option<Duration> {
+        self.state
+            .lock()
+            .unwrap()
+            .table_lags
+            .get(&table_id)
+            .copied()
+    }
+
+    /// Notes an event passed to the sink, remembering the tables each transaction
+    /// changes and the time it committed
+    pub(crate) fn observe(&self, event: &CdcEvent) {
+        let mut state = self.state.lock().unwrap();
+        let state = &mut *state;
+        match event {
+            CdcEvent::Insert((table_id, _, xid))
+            | CdcEvent::Update((table_id, _, _, xid))
+            | CdcEvent::Delete((table_id, _, xid)) => {
+                state
+                    .open_transactions
+                    .entry(*xid)
+                    .or_default()
+                    .insert(*table_id);
+            }
+            CdcEvent::Commit(body) => {
+                let tables = state.open_transactions.remove(&None).unwrap_or_default();
+                let commit_time = CdcEventConverter::system_time(body.timestamp());
+                state.pending_commits.push((commit_time, tables));
+            }
+            CdcEvent::StreamCommit(body) => {
+                let tables = state
+                    .open_transactions
+                    .remove(&Some(body.xid()))
+                    .unwrap_or_default();
+                let commit_time = CdcEventConverter::system_time(body.timestamp());
+                state.pending_commits.push((commit_time, tables));
+            }
+            // Aborts of subtransactions leave the transaction open
+            CdcEvent::StreamAbort(body) if body.xid() == body.subxid() => {
+                state.open_transactions.remove(&Some(body.xid()));
+            }
+            _ => {}
+        }
+    }
+
+    /// Records the lag of the transactions observed since the last call, once the
+    /// sink has written them
+    pub(crate) fn written(&self) {
+        let now = SystemTime::now();
+        let mut state = self.state.lock().unwrap();
+        for (commit_time, tables) in mem::take(&mut state.pending_commits) {
+            let lag = now.duration_since(commit_time).unwrap_or_default();
+            state.lag = Some(lag);
+            for table_id in tables {
+                state.table_lags.insert(table_id, lag);
+            }
+        }
+    }
+}
+
+/// The changes to a table counted by [`TableChangeStats`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TableChanges {
+    pub inserts: u64,
+    pub updates: u64,
+    pub deletes: u64,
+    /// The end lsn of the last transaction counted which changed the table
+    pub last_lsn: PgLsn,
+}
+
+impl Default for TableChanges {
+    fn default() -> TableChanges {
+        TableChanges {
+            inserts: 0,
+            updates: 0,
+            deletes: 0,
+            last_lsn: PgLsn::from(0),
+        }
+    }
+}
+
+/// The number of inserts, updates and deletes of each table passed to the sink, to
+/// spot tables whose write volume changes suddenly. The counts only grow, so rates
+/// are the differences between [`TableChangeStats::snapshot`]s taken some time
+/// apart. Changes are counted once their transaction commits, so changes of
+/// aborted streamed transactions aren't counted.
+#[derive(Debug, Default)]
+pub struct TableChangeStats {
+    state: Mutex<TableChangeState>,
+}
+
+#[derive(Debug, Default)]
+struct TableChangeState {
+    tables: HashMap<TableId, TableChanges>,
+    /// Changes of each transaction which hasn't committed yet, keyed by the xid of
+    /// streamed transactions and `None` for the others
+    open_transactions: HashMap<Option<u32>, HashMap<TableId, TableChanges>>,
+}
+
+impl TableChangeStats {
+    pub fn new() -> TableChangeStats {
+        TableChangeStats::default()
+    }
+
+    /// The changes counted so far, by table
+    pub fn snapshot(&self) -> HashMap<TableId, TableChanges> {
+        self.state.lock().unwrap().tables.clone()
+    }
+
+    /// Counts an event passed to the sink
+    pub(crate) fn observe(&self, event: &CdcEvent) {
+        let mut state = self.state.lock().unwrap();
+        match event {
+            CdcEvent::Insert((table_id, _, xid)) => state.open(*table_id, *xid).inserts += 1,
+            CdcEvent::Update((table_id, _, _, xid)) => state.open(*table_id, *xid).updates += 1,
+            CdcEvent::Delete((table_id, _, xid)) => state.open(*table_id, *xid).deletes += 1,
+            CdcEvent::Commit(body) => {
+                state.commit(None, body.end_lsn().into());
+            }
+            CdcEvent::StreamCommit(body) => {
+                state.commit(Some(body.xid()), body.end_lsn().into());
+            }
+            // Aborts of subtransactions leave the transaction open. Their changes
+            // can't be told apart from the transaction's other changes, so they are
+            // still counted.
+            CdcEvent::StreamAbort(body) if body.xid() == body.subxid() => {
+                state.open_transactions.remove(&Some(body.xid()));
+            }
+            _ => {}
+        }
+    }
+}
+
+impl TableChangeState {
+    /// The changes to a table of a transaction which hasn't committed yet
+    fn open(&mut self, table_id: TableId, xid: Option<u32>) -> &mut TableChanges {
+        self.open_transactions
+            .entry(xid)
+            .or_default()
+            .entry(table_id)
+            .or_default()
+    }
+
+    fn commit(&mut self, xid: Option<u32>, end_lsn: PgLsn) {
+        let Some(changes) = self.open_transactions.remove(&xid) else {
+            return;
+        };
+        for (table_id, changes) in changes {
+            let table = self.tables.entry(table_id).or_default();
+            table.inserts += changes.inserts;
+            table.updates += changes.updates;
+            table.deletes += changes.deletes;
+            table.last_lsn = end_lsn;
+        }
+    }
+}
@@ -0,0 +1,47 @@
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+};
+
+use tokio::sync::Notify;
+
+#[derive(Debug, Default)]
+struct StopState {
+    stopped: AtomicBool,
+    notify: Notify,
+}
+
+/// Stops a running pipeline from another task, e.g. on shutdown of the process
+/// hosting it. The pipeline finishes the table or batch it's working on, confirms
+/// what the sink committed to Postgres and returns from
+/// [`BatchDataPipeline::start`](crate::pipeline::batching::data_pipeline::BatchDataPipeline::start).
+/// A stopped pipeline stays stopped. Clones control the same pipeline.
+#[derive(Debug, Clone, Default)]
+pub struct StopHandle {
+    state: Arc<StopState>,
+}
+
+impl StopHandle {
+    pub fn new() -> StopHandle {
+        StopHandle::default()
+    }
+
+    pub fn stop(&self) {
+        self.state.stopped.store(true, Ordering::Release);
+        self.state.notify.notify_waiters();
+    }
+
+    pub fn is_stopped(&self) -> bool {
+        self.state.stopped.load(Ordering::Acquire)
+    }
+
+    /// Completes once the pipeline was stopped
+    pub(crate) async fn stopped(&self) {
+        // Registered before checking the flag, so that a concurrent stop isn't missed
+        let notified = self.state.notify.notified();
+        if self.is_stopped() {
+            return;
+        }
+        notified.await;
+    }
+}
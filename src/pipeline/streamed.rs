@@ -0,0 +1,166 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::conversions::cdc_event::CdcEvent;
+
+/// The held changes of an open streamed transaction
+#[derive(Debug, Default)]
+struct StreamedTransaction {
+    changes: Vec<CdcEvent>,
+    /// Xids of the transaction's aborted subtransactions, whose changes are dropped
+    aborted_subxids: HashSet<u32>,
+}
+
+/// How to undo the last [`StreamedTransactions::hold`]
+#[derive(Debug, Default)]
+struct Undo {
+    /// Xid of the transaction whose stream segment was being received before
+    current_xid: Option<u32>,
+    /// Number of changes each transaction held before
+    held: HashMap<u32, usize>,
+    /// Subtransactions marked as aborted
+    aborted_subxids: Vec<(u32, u32)>,
+    /// Transactions which committed or aborted, dropped by the next `hold`
+    ended: Vec<u32>,
+}
+
+/// Holds back the changes of large transactions which Postgres streams before they
+/// commit, for sinks which apply changes as they receive them. The changes of a
+/// streamed transaction are released right before its [`CdcEvent::StreamCommit`]
+/// and dropped on its [`CdcEvent::StreamAbort`], and the changes of an aborted
+/// subtransaction are dropped on the subtransaction's abort. Changes outside of
+/// streamed transactions and all other events are released as they arrive.
+///
+/// The changes are held in memory until the transaction ends. A sink whose write
+/// fails calls [`StreamedTransactions::rollback`] so that the events can be passed
+/// to [`StreamedTransactions::hold`] again when the write is retried.
+#[derive(Debug, Default)]
+pub struct StreamedTransactions {
+    /// Xid of the transaction whose stream segment is being received
+    current_xid: Option<u32>,
+    /// Held changes of each open streamed transaction by its top level xid
+    transactions: HashMap<u32, StreamedTransaction>,
+    undo: Undo,
+}
+
+impl StreamedTransactions {
+    pub fn new() -> StreamedTransactions {
+        StreamedTransactions::default()
+    }
+
+    /// Returns the events of `events` which can be applied, holding back the
+    /// changes of streamed transactions which haven't committed yet
+    pub fn hold(&mut self, events: Vec<CdcEvent>) -> Vec<CdcEvent> {
+        for xid in self.undo.ended.drain(..) {
+            self.transactions.remove(&xid);
+        }
+        self.undo = Undo {
+            current_xid: self.current_xid,
+            ..Undo::default()
+        };
+
+        let mut released = Vec::with_capacity(events.len());
+        for event in events {
+            match &event {
+                CdcEvent::StreamStart(body) => self.current_xid = Some(body.xid()),
+                CdcEvent::StreamStop(_) => self.current_xid = None,
+                // Changes in a stream segment carry the xid of their subtransaction
+                CdcEvent::Insert((_, _, Some(xid)))
+                | CdcEvent::Update((_, _, _, Some(xid)))
+                | CdcEvent::Delete((_, _, Some(xid))) => {
+                    let top_level_xid = self.current_xid.unwrap_or(*xid);
+                    let transaction = self.transactions.entry(top_level_xid).or_default();
+                    self.undo
+                        .held
+                        .entry(top_level_xid)
+                        .or_insert(transaction.changes.len());
+                    transaction.changes.push(event);
+                    continue;
+                }
+                CdcEvent::StreamCommit(body) => {
+                    if let Some(transaction) = self.transactions.get(&body.xid()) {
+                        // The changes are copied so that a rollback can restore them
+                        released.extend(
+                            transaction
+                                .changes
+                                .iter()
+                                .filter(|change| {
+                                    !change_xid(change).is_some_and(|xid| {
+                                        transaction.aborted_subxids.contains(&xid)
+                                    })
+                                })
+                                .filter_map(clone_change),
+                        );
+                        self.undo.ended.push(body.xid());
+                    }
+                }
+                CdcEvent::StreamAbort(body) if body.xid() == body.subxid() => {
+                    self.undo.ended.push(body.xid());
+                }
+                CdcEvent::StreamAbort(body) => {
+                    if let Some(transaction) = self.transactions.get_mut(&body.xid()) {
+                        if transaction.aborted_subxids.insert(body.subxid()) {
+                            self.undo.aborted_subxids.push((body.xid(), body.subxid()));
+                        }
+                    }
+                }
+                _ => {}
+            }
+            released.push(event);
+        }
+        released
+    }
+
+    /// Undoes the last [`StreamedTransactions::hold`], e.g. after the sink failed to
+    /// write the released events
+    pub fn rollback(&mut self) {
+        let undo = std::mem::take(&mut self.undo);
+        self.current_xid = undo.current_xid;
+        for (xid, len) in undo.held {
+            if len == 0 {
+                self.transactions.remove(&xid);
+            } else if let Some(transaction) = self.transactions.get_mut(&xid) {
+                transaction.changes.truncate(len);
+            }
+        }
+        for (xid, subxid) in undo.aborted_subxids {
+            if let Some(transaction) = self.transactions.get_mut(&xid) {
+                transaction.aborted_subxids.remove(&subxid);
+            }
+        }
+    }
+
+    /// Number of changes held back
+    pub fn len(&self) -> usize {
+        self.transactions
+            .iter()
+            .filter(|(xid, _)| !self.undo.ended.contains(xid))
+            .map(|(_, transaction)| transaction.changes.len())
+            .sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+fn change_xid(change: &CdcEvent) -> Option<u32> {
+    match change {
+        CdcEvent::Insert((_, _, xid))
+        | CdcEvent::Update((_, _, _, xid))
+        | CdcEvent::Delete((_, _, xid)) => *xid,
+        _ => None,
+    }
+}
+
+/// Copies a row change, `None` for other events, which aren't held
+fn clone_change(change: &CdcEvent) -> Option<CdcEvent> {
+    let change = match change {
+        CdcEvent::Insert((table_id, row, xid)) => CdcEvent::Insert((*table_id, row.clone(), *xid)),
+        CdcEvent::Update((table_id, old_row, new_row, xid)) => {
+            CdcEvent::Update((*table_id, old_row.clone(), new_row.clone(), *xid))
+        }
+        CdcEvent::Delete((table_id, row, xid)) => CdcEvent::Delete((*table_id, row.clone(), *xid)),
+        _ => return None,
+    };
+    Some(change)
+}
@@ -0,0 +1,304 @@
+use std::collections::{HashMap, HashSet};
+
+use thiserror::Error;
+
+use crate::{
+    conversions::{table_row::TableRow, Cell},
+    table::{LookupKey, TableId, TableName, TableSchema},
+};
+
+#[derive(Debug, Error)]
+pub enum TransformError {
+    #[error("column {0} of table {1} is part of its lookup key and can't be dropped")]
+    KeyColumnDropped(String, TableName),
+
+    #[error("row of table {0} has {1} values, but the transform expects at least {2}")]
+    RowTooNarrow(TableId, usize, usize),
+}
+
+/// A transformation of rows applied between decoding them from the source and
+/// writing them to the sink, e.g. to mask or drop columns holding PII
+pub trait Transform: Send {
+    /// Called once for every table before any of its rows are transformed, with the
+    /// schema the rows have. Returns the schema of the transformed rows, which is also
+    /// the schema the sink sees.
+    fn transform_schema(
+        &mut self,
+        table_schema: TableSchema,
+    ) -> Result<TableSchema, TransformError>;
+
+    /// Transforms a row of the table with `table_id`. Rows narrower than the schema
+    /// the transform was given are rejected.
+    fn transform_row(&self, table_id: TableId, row: TableRow) -> Result<TableRow, TransformError>;
+
+    /// Composes this transform with `next`, which sees the output of this transform
+    fn then<T: Transform>(self, next: T) -> Chain<Self, T>
+    where
+        Self: Sized,
+    {
+        Chain { first: self, next }
+    }
+}
+
+/// Two transforms applied one after the other, see [`Transform::then`]
+pub struct Chain<A: Transform, B: Transform> {
+    first: A,
+    next: B,
+}
+
+impl<A: Transform, B: Transform> Transform for Chain<A, B> {
+    fn transform_schema(
+        &mut self,
+        table_schema: TableSchema,
+    ) -> Result<TableSchema, TransformError> {
+        let table_schema = self.first.transform_schema(table_schema)?;
+        self.next.transform_schema(table_schema)
+    }
+
+    fn transform_row(&self, table_id: TableId, row: TableRow) -> Result<TableRow, TransformError> {
+        let row = self.first.transform_row(table_id, row)?;
+        self.next.transform_row(table_id, row)
+    }
+}
+
+/// Removes the columns with the given names from every table which has them. Columns
+/// of a table's lookup key can't be dropped, as the sinks need them to apply
+/// updates and deletes.
+pub struct DropColumns {
+    column_names: HashSet<String>,
+    dropped_indexes: HashMap<TableId, Vec<usize>>,
+}
+
+impl DropColumns {
+    pub fn new(column_names: impl IntoIterator<Item = impl Into<String>>) -> DropColumns {
+        DropColumns {
+            column_names: column_names.into_iter().map(Into::into).collect(),
+            dropped_indexes: HashMap::new(),
+        }
+    }
+}
+
+impl Transform for DropColumns {
+    fn transform_schema(
+        &mut self,
+        mut table_schema: TableSchema,
+    ) -> Result<TableSchema, TransformError> {
+        if let LookupKey::Key { name: _, columns } = &table_schema.lookup_key {
+            if let Some(column) = columns.iter().find(|c| self.column_names.contains(*c)) {
+                return Err(TransformError::KeyColumnDropped(
+                    column.clone(),
+                    table_schema.table_name.clone(),
+                ));
+            }
+        }
+        let dropped_indexes = table_schema
+            .column_schemas
+            .iter()
+            .enumerate()
+            .filter(|(_, c)| self.column_names.contains(&c.name))
+            .map(|(i, _)| i)
+            .collect();
+        self.dropped_indexes
+            .insert(table_schema.table_id, dropped_indexes);
+        table_schema
+            .column_schemas
+            .retain(|c| !self.column_names.contains(&c.name));
+        Ok(table_schema)
+    }
+
+    fn transform_row(
+        &self,
+        table_id: TableId,
+        mut row: TableRow,
+    ) -> Result<TableRow, TransformError> {
+        if let Some(dropped_indexes) = self.dropped_indexes.get(&table_id) {
+            check_width(table_id, &row, dropped_indexes)?;
+            // Remove from the back so that the remaining indexes stay valid
+            for &i in dropped_indexes.iter().rev() {
+                row.values.remove(i);
+            }
+            row.unchanged_toast.retain(|i| !dropped_indexes.contains(i));
+            for i in &mut row.unchanged_toast {
+                *i -= dropped_indexes
+                    .iter()
+                    .filter(|&&dropped| dropped < *i)
+                    .count();
+            }
+        }
+        Ok(row)
+    }
+}
+
+/// Checks that a row has all the columns at `indexes`, which are ascending
+fn check_width(table_id: TableId, row: &TableRow, indexes: &[usize]) -> Result<(), TransformError> {
+    match indexes.last() {
+        Some(&last) if last >= row.values.len() => Err(TransformError::RowTooNarrow(
+            table_id,
+            row.values.len(),
+            last + 1,
+        )),
+        _ => Ok(()),
+    }
+}
+
+/// Replaces the values of the columns with the given names using a function, e.g.
+/// to hash an email column. Nulls are passed to the function as [`Cell::Null`].
+pub struct MapColumns<F: Fn(Cell) -> Cell + Send> {
+    column_names: HashSet<String>,
+    mapped_indexes: HashMap<TableId, Vec<usize>>,
+    f: F,
+}
+
+impl<F: Fn(Cell) -> Cell + Send> MapColumns<F> {
+    pub fn new(column_names: impl IntoIterator<Item = impl Into<String>>, f: F) -> MapColumns<F> {
+        MapColumns {
+            column_names: column_names.into_iter().map(Into::into).collect(),
+            mapped_indexes: HashMap::new(),
+            f,
+        }
+    }
+}
+
+impl<F: Fn(Cell) -> Cell + Send> Transform for MapColumns<F> {
+    fn transform_schema(
+        &mut self,
+        table_schema: TableSchema,
+    ) -> Result<TableSchema, TransformError> {
+        let mapped_indexes = table_schema
+            .column_schemas
+            .iter()
+            .enumerate()
+            .filter(|(_, c)| self.column_names.contains(&c.name))
+            .map(|(i, _)| i)
+            .collect();
+        self.mapped_indexes
+            .insert(table_schema.table_id, mapped_indexes);
+        Ok(table_schema)
+    }
+
+    fn transform_row(
+        &self,
+        table_id: TableId,
+        mut row: TableRow,
+    ) -> Result<TableRow, TransformError> {
+        if let Some(mapped_indexes) = self.mapped_indexes.get(&table_id) {
+            check_width(table_id, &row, mapped_indexes)?;
+            for &i in mapped_indexes {
+                let value = std::mem::replace(&mut row.values[i], Cell::Null);
+                row.values[i] = (self.f)(value);
+            }
+        }
+        Ok(row)
+    }
+}
+
+/// Writes tables to sink tables of other names, e.g. `public.orders` to
+/// `raw.raw_orders`. Tables without a mapping keep their source name. Sinks create
+/// and write tables by the name in the schema they are passed, so the mapping
+/// applies to every sink.
+pub struct TableMappings {
+    mappings: HashMap<TableName, TableName>,
+}
+
+impl TableMappings {
+    pub fn new(mappings: impl IntoIterator<Item = (TableName, TableName)>) -> TableMappings {
+        TableMappings {
+            mappings: mappings.into_iter().collect(),
+        }
+    }
+}
+
+impl Transform for TableMappings {
+    fn transform_schema(
+        &mut self,
+        mut table_schema: TableSchema,
+    ) -> Result<TableSchema, TransformError> {
+        if let Some(target) = self.mappings.get(&table_schema.table_name) {
+            table_schema.table_name = target.clone();
+        }
+        Ok(table_schema)
+    }
+
+    fn transform_row(&self, _table_id: TableId, row: TableRow) -> Result<TableRow, TransformError> {
+        Ok(row)
+    }
+}
+
+/// How source identifiers map to sink table and column names. Postgres folds unquoted
+/// identifiers to lowercase, so most names are already lowercase unless they were
+/// quoted when created. As a [`Transform`] it renames schemas, tables, columns and
+/// lookup key columns. Names which map to the same identifier, e.g. `userId` and
+/// `user_id` with [`NamingStrategy::SnakeCase`], aren't detected.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NamingStrategy {
+    Preserve,
+    Lowercase,
+    Uppercase,
+    /// Splits words at case changes and non-alphanumeric characters and joins them
+    /// lowercased with underscores, e.g. `OrderItems` becomes `order_items` and
+    /// `HTTPServer` becomes `http_server`
+    SnakeCase,
+}
+
+impl NamingStrategy {
+    pub fn apply(&self, identifier: &str) -> String {
+        match self {
+            NamingStrategy::Preserve => identifier.to_string(),
+            NamingStrategy::Lowercase => identifier.to_lowercase(),
+            NamingStrategy::Uppercase => identifier.to_uppercase(),
+            NamingStrategy::SnakeCase => Self::to_snake_case(identifier),
+        }
+    }
+
+    fn to_snake_case(identifier: &str) -> String {
+        let chars: Vec<char> = identifier.chars().collect();
+        let mut snake_case = String::with_capacity(identifier.len() + 4);
+        for (i, &c) in chars.iter().enumerate() {
+            if !c.is_alphanumeric() {
+                if !snake_case.is_empty() && !snake_case.ends_with('_') {
+                    snake_case.push('_');
+                }
+                continue;
+            }
+            if c.is_uppercase() && i > 0 {
+                let prev = chars[i - 1];
+                let next_is_lowercase = chars.get(i + 1).is_some_and(|c| c.is_lowercase());
+                let starts_word = prev.is_lowercase()
+                    || prev.is_numeric()
+                    || (prev.is_uppercase() && next_is_lowercase);
+                if starts_word && !snake_case.ends_with('_') {
+                    snake_case.push('_');
+                }
+            }
+            snake_case.extend(c.to_lowercase());
+        }
+        if snake_case.ends_with('_') {
+            snake_case.pop();
+        }
+        snake_case
+    }
+}
+
+impl Transform for NamingStrategy {
+    fn transform_schema(
+        &mut self,
+        mut table_schema: TableSchema,
+    ) -> Result<TableSchema, TransformError> {
+        let table_name = &mut table_schema.table_name;
+        table_name.schema = self.apply(&table_name.schema);
+        table_name.name = self.apply(&table_name.name);
+        for column_schema in table_schema.column_schemas.iter_mut() {
+            column_schema.name = self.apply(&column_schema.name);
+        }
+        if let LookupKey::Key { name: _, columns } = &mut table_schema.lookup_key {
+            for column in columns.iter_mut() {
+                *column = self.apply(column);
+            }
+        }
+        Ok(table_schema)
+    }
+
+    fn transform_row(&self, _table_id: TableId, row: TableRow) -> Result<TableRow, TransformError> {
+        Ok(row)
+    }
+}
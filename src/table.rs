@@ -1,9 +1,15 @@
-use std::fmt::Display;
+use std::{
+    collections::{BTreeSet, HashMap},
+    fmt::Display,
+};
 
 use pg_escape::quote_identifier;
+use thiserror::Error;
 use tokio_postgres::types::Type;
 
-#[derive(Debug, Clone)]
+use crate::conversions::{table_row::TableRow, text::TextFormatConverter, Cell};
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub struct TableName {
     pub schema: String,
     pub name: String,
@@ -31,6 +37,32 @@ pub struct ColumnSchema {
     pub typ: Type,
     pub modifier: TypeModifier,
     pub nullable: bool,
+    /// Name of the column's collation, `None` if the column's type isn't collatable
+    pub collation: Option<String>,
+    /// Whether this is a stored generated column, which isn't sent in cdc events
+    pub generated: bool,
+    /// The column's `COMMENT ON COLUMN`, only loaded with
+    /// [`ReplicationClient::set_include_comments`](crate::clients::postgres::ReplicationClient::set_include_comments)
+    pub comment: Option<String>,
+    /// The column's default expression from `pg_attrdef`, e.g. `0` or `now()`, `None`
+    /// if it has none. Rows written before a column with a default was added don't
+    /// carry it, so sinks can backfill it for existing rows or reproduce it.
+    pub default_expression: Option<String>,
+}
+
+impl ColumnSchema {
+    /// A predicate matching rows whose value in this column equals `value`, e.g. a
+    /// placeholder, for the where clause of a sink's delete. Equality with null is
+    /// never true, so a nullable column is compared with `is not distinct from`,
+    /// which matches a null value with a null column.
+    pub fn match_predicate(&self, value: &str) -> String {
+        let name = quote_identifier(&self.name);
+        if self.nullable {
+            format!("{name} is not distinct from {value}")
+        } else {
+            format!("{name} = {value}")
+        }
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -39,6 +71,53 @@ pub enum LookupKey {
     FullRow,
 }
 
+impl LookupKey {
+    /// Positions of the key columns in `column_schemas`, in key order. `None` for
+    /// [`LookupKey::FullRow`] or if a key column is missing from `column_schemas`.
+    pub fn column_indexes(&self, column_schemas: &[ColumnSchema]) -> Option<Vec<usize>> {
+        let LookupKey::Key { name: _, columns } = self else {
+            return None;
+        };
+        columns
+            .iter()
+            .map(|key_column| column_schemas.iter().position(|c| &c.name == key_column))
+            .collect()
+    }
+
+    /// Extracts the values of the key columns from a row with the columns
+    /// `column_schemas`, e.g. to build the predicate of a sink's delete. `None` for
+    /// [`LookupKey::FullRow`] or if a key column is missing from `column_schemas`.
+    pub fn extract(&self, column_schemas: &[ColumnSchema], row: &TableRow) -> Option<KeyValues> {
+        let indexes = self.column_indexes(column_schemas)?;
+        Some(KeyValues {
+            columns: indexes
+                .iter()
+                .map(|&i| column_schemas[i].name.clone())
+                .collect(),
+            values: indexes.iter().map(|&i| row.values[i].clone()).collect(),
+        })
+    }
+}
+
+/// The values of a row's lookup key columns, in the order of [`LookupKey::Key`]'s columns
+#[derive(Debug, Clone)]
+pub struct KeyValues {
+    pub columns: Vec<String>,
+    pub values: Vec<Cell>,
+}
+
+impl KeyValues {
+    /// The key's values in Postgres' text format, `None` for nulls. Equal keys give
+    /// equal texts however their values were written, e.g. uuids are always lower
+    /// case and hyphenated, so sinks can use them to match rows.
+    pub fn to_text(&self) -> Vec<Option<String>> {
+        self.values
+            .iter()
+            .map(TextFormatConverter::to_text)
+            .collect()
+    }
+}
+
 pub type TableId = u32;
 
 #[derive(Debug, Clone)]
@@ -47,6 +126,160 @@ pub struct TableSchema {
     pub table_id: TableId,
     pub column_schemas: Vec<ColumnSchema>,
     pub lookup_key: LookupKey,
+    /// Positions in cdc events' tuple data of the columns left out of `column_schemas`
+    /// by [`ReplicationClient::set_exclude_columns`](crate::clients::postgres::ReplicationClient::set_exclude_columns)
+    pub excluded_columns: Vec<usize>,
+    /// The table's `COMMENT ON TABLE`, only loaded with
+    /// [`ReplicationClient::set_include_comments`](crate::clients::postgres::ReplicationClient::set_include_comments)
+    pub comment: Option<String>,
+}
+
+impl TableSchema {
+    /// Compares the table's columns with those of its `previous` schema by name, e.g.
+    /// for a sink to alter its table after the table's schema changed
+    pub fn column_changes<'a>(&'a self, previous: &'a TableSchema) -> ColumnChanges<'a> {
+        let find = |column_schemas: &'a [ColumnSchema], name: &str| {
+            column_schemas.iter().find(|c| c.name == name)
+        };
+        let mut changes = ColumnChanges::default();
+        for column_schema in &self.column_schemas {
+            match find(&previous.column_schemas, &column_schema.name) {
+                None => changes.added.push(column_schema),
+                Some(previous) => {
+                    if previous.typ != column_schema.typ
+                        || previous.modifier != column_schema.modifier
+                        || previous.nullable != column_schema.nullable
+                    {
+                        changes.altered.push((previous, column_schema));
+                    }
+                }
+            }
+        }
+        changes.dropped = previous
+            .column_schemas
+            .iter()
+            .filter(|c| find(&self.column_schemas, &c.name).is_none())
+            .collect();
+        changes
+    }
+}
+
+/// How a table's columns changed between two of its schemas, see
+/// [`TableSchema::column_changes`]
+#[derive(Debug, Default)]
+pub struct ColumnChanges<'a> {
+    /// Columns which the previous schema doesn't have
+    pub added: Vec<&'a ColumnSchema>,
+    /// Columns of the previous schema which are gone
+    pub dropped: Vec<&'a ColumnSchema>,
+    /// Columns whose type, type modifier or nullability changed, as they were and
+    /// as they are now
+    pub altered: Vec<(&'a ColumnSchema, &'a ColumnSchema)>,
+}
+
+impl ColumnChanges<'_> {
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.dropped.is_empty() && self.altered.is_empty()
+    }
+}
+
+/// A foreign key constraint of a table
+#[derive(Debug, Clone)]
+pub struct ForeignKey {
+    pub name: String,
+    /// The referencing columns in constraint order
+    pub columns: Vec<String>,
+    pub referenced_table_id: TableId,
+    pub referenced_table_name: TableName,
+    /// The referenced columns, matching `columns` by position
+    pub referenced_columns: Vec<String>,
+}
+
+#[derive(Debug, Error)]
+#[error("foreign keys form a cycle between tables {0:?}")]
+pub struct ForeignKeyCycleError(pub Vec<TableId>);
+
+/// Orders tables so that every table comes after the tables its foreign keys reference,
+/// e.g. to copy parents before children into a sink which enforces foreign keys.
+/// `foreign_keys` holds each table's foreign keys. References to tables outside
+/// `table_ids` and from a table to itself are ignored. Returns the ids of the tables
+/// in a cycle if there is one, as no order satisfies all of their foreign keys.
+/// Tables which only reference a cycle without being part of it aren't reported.
+pub fn sort_by_foreign_keys(
+    table_ids: &[TableId],
+    foreign_keys: &HashMap<TableId, Vec<ForeignKey>>,
+) -> Result<Vec<TableId>, ForeignKeyCycleError> {
+    let table_set: BTreeSet<TableId> = table_ids.iter().copied().collect();
+
+    // For every table, the tables it references and the tables referencing it
+    let mut references: HashMap<TableId, BTreeSet<TableId>> = HashMap::new();
+    let mut referenced_by: HashMap<TableId, BTreeSet<TableId>> = HashMap::new();
+    for &table_id in &table_set {
+        for foreign_key in foreign_keys.get(&table_id).into_iter().flatten() {
+            let referenced = foreign_key.referenced_table_id;
+            if referenced == table_id || !table_set.contains(&referenced) {
+                continue;
+            }
+            references.entry(table_id).or_default().insert(referenced);
+            referenced_by
+                .entry(referenced)
+                .or_default()
+                .insert(table_id);
+        }
+    }
+
+    // Kahn's algorithm, taking the smallest ready table id first for a stable order
+    let mut ready: BTreeSet<TableId> = table_set
+        .iter()
+        .copied()
+        .filter(|table_id| !references.contains_key(table_id))
+        .collect();
+    let mut sorted = Vec::with_capacity(table_set.len());
+    while let Some(table_id) = ready.pop_first() {
+        sorted.push(table_id);
+        for referencing in referenced_by.remove(&table_id).into_iter().flatten() {
+            let remaining = references
+                .get_mut(&referencing)
+                .expect("missing references of referencing table");
+            remaining.remove(&table_id);
+            if remaining.is_empty() {
+                references.remove(&referencing);
+                ready.insert(referencing);
+            }
+        }
+    }
+
+    // The tables left each reference another table left. Those in a cycle reach
+    // themselves through their references, the others only reference a cycle.
+    if !references.is_empty() {
+        let mut cycle: Vec<TableId> = references
+            .keys()
+            .copied()
+            .filter(|&table_id| reaches(table_id, table_id, &references))
+            .collect();
+        cycle.sort();
+        return Err(ForeignKeyCycleError(cycle));
+    }
+
+    Ok(sorted)
 }
 
-impl TableSchema {}
+/// Whether `to` can be reached from `from` by following at least one reference
+fn reaches(from: TableId, to: TableId, references: &HashMap<TableId, BTreeSet<TableId>>) -> bool {
+    let mut visited = BTreeSet::new();
+    let mut pending: Vec<TableId> = references
+        .get(&from)
+        .into_iter()
+        .flatten()
+        .copied()
+        .collect();
+    while let Some(table_id) = pending.pop() {
+        if table_id == to {
+            return true;
+        }
+        if visited.insert(table_id) {
+            pending.extend(references.get(&table_id).into_iter().flatten().copied());
+        }
+    }
+    false
+}
@@ -2,8 +2,30 @@ use super::{
     assert_is_full_row, assert_is_key, create_replication_client, test_lookup_key_with_definition,
 };
 
-use crate::common::postgres_utils::TestTable;
-use pg_replicate::table::TableName;
+use std::{
+    collections::{HashMap, HashSet},
+    time::{Duration, Instant},
+};
+
+use crate::common::{
+    postgres_utils::{drop_replication_slot, TestTable},
+    POSTGRES_DBNAME, POSTGRES_HOST, POSTGRES_PASSWORD, POSTGRES_PORT, POSTGRES_USER,
+};
+use chrono::Utc;
+use futures::StreamExt;
+use pg_replicate::{
+    clients::postgres::{
+        ConnectRetryPolicy, PublicationOptions, PublicationTable, PublishedOperation,
+        ReplicaIdentity, ReplicaIdentityProblem, ReplicationClient, ReplicationClientError,
+    },
+    conversions::range::range_subtype,
+    table::{sort_by_foreign_keys, ForeignKey, ForeignKeyCycleError, TableName},
+};
+use postgres_replication::protocol::{LogicalReplicationMessage, ReplicationMessage};
+use tokio_postgres::{
+    types::{PgLsn, Type},
+    NoTls, SimpleQueryMessage,
+};
 
 #[tokio::test]
 async fn test_lookup_key_with_primary_key() -> Result<(), anyhow::Error> {
@@ -344,3 +366,1296 @@ async fn test_lookup_key_ignores_deferrable_constraints() -> Result<(), anyhow::
     )
     .await
 }
+
+#[tokio::test]
+async fn test_wait_for_slot_inactive() -> Result<(), anyhow::Error> {
+    let table_name = "test_slot_handoff";
+    let pub_name = "test_slot_handoff_pub";
+    let slot_name = "test_slot_handoff_slot";
+
+    let test_table = TestTable::new(
+        table_name,
+        &format!("CREATE TABLE {table_name} (id INT PRIMARY KEY)"),
+    )
+    .await;
+    let client = &test_table.client;
+    drop_replication_slot(client, slot_name).await;
+    client
+        .simple_query(&format!(
+            "DROP PUBLICATION IF EXISTS {pub_name};
+            CREATE PUBLICATION {pub_name} FOR TABLE {table_name};"
+        ))
+        .await?;
+
+    let mut old_consumer = create_replication_client().await;
+    old_consumer.begin_readonly_transaction().await?;
+    let slot_info = old_consumer.get_or_create_slot(slot_name).await?;
+    old_consumer.commit_txn().await?;
+    let stream = old_consumer
+        .get_logical_replication_stream(pub_name, slot_name, slot_info.confirmed_flush_lsn)
+        .await?;
+
+    // The old consumer still streams from the slot, so waiting times out
+    let new_consumer = create_replication_client().await;
+    let result = new_consumer
+        .wait_for_slot_inactive(slot_name, Duration::from_millis(300))
+        .await;
+    assert!(matches!(
+        result,
+        Err(ReplicationClientError::SlotStillActive(_, _))
+    ));
+
+    // Once the old consumer disconnects the slot can be handed over
+    drop(stream);
+    drop(old_consumer);
+    let slot_activity = new_consumer
+        .wait_for_slot_inactive(slot_name, Duration::from_secs(10))
+        .await?;
+    assert!(slot_activity.active_pid.is_none());
+    assert_eq!(
+        slot_activity.confirmed_flush_lsn,
+        slot_info.confirmed_flush_lsn
+    );
+
+    let result = new_consumer
+        .wait_for_slot_inactive("test_slot_handoff_missing_slot", Duration::from_secs(1))
+        .await;
+    assert!(matches!(
+        result,
+        Err(ReplicationClientError::MissingSlot(_))
+    ));
+
+    drop_replication_slot(client, slot_name).await;
+    client
+        .simple_query(&format!("DROP PUBLICATION IF EXISTS {pub_name}"))
+        .await?;
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_column_schemas_include_collation() -> Result<(), anyhow::Error> {
+    let table_name = "test_column_collation";
+    let _test_table = TestTable::new(
+        table_name,
+        &format!(
+            "CREATE TABLE {table_name} (
+                id INT PRIMARY KEY,
+                code TEXT COLLATE \"C\",
+                data TEXT
+            )"
+        ),
+    )
+    .await;
+
+    let replication_client = create_replication_client().await;
+    let table_id = replication_client
+        .get_table_id(&TableName {
+            schema: "public".to_string(),
+            name: table_name.to_string(),
+        })
+        .await?
+        .ok_or_else(|| anyhow::anyhow!("table ID not found!"))?;
+    let column_schemas = replication_client
+        .get_column_schemas(table_id, None)
+        .await?;
+
+    let collations: Vec<Option<&str>> = column_schemas
+        .iter()
+        .map(|c| c.collation.as_deref())
+        .collect();
+    assert_eq!(collations, vec![None, Some("C"), Some("default")]);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_column_schemas_include_defaults() -> Result<(), anyhow::Error> {
+    let table_name = "test_column_defaults";
+    let test_table = TestTable::new(
+        table_name,
+        &format!(
+            "CREATE TABLE {table_name} (
+                id INT PRIMARY KEY,
+                doubled INT GENERATED ALWAYS AS (id * 2) STORED
+            )"
+        ),
+    )
+    .await;
+    // Rows before the column was added don't carry its default
+    test_table
+        .client
+        .simple_query(&format!(
+            "INSERT INTO {table_name} (id) VALUES (1);
+            ALTER TABLE {table_name} ADD COLUMN status TEXT NOT NULL DEFAULT 'new';"
+        ))
+        .await?;
+
+    let mut replication_client = create_replication_client().await;
+    replication_client.set_include_generated_columns(true);
+    let table_id = replication_client
+        .get_table_id(&TableName {
+            schema: "public".to_string(),
+            name: table_name.to_string(),
+        })
+        .await?
+        .ok_or_else(|| anyhow::anyhow!("table ID not found!"))?;
+    let column_schemas = replication_client
+        .get_column_schemas(table_id, None)
+        .await?;
+
+    // A generated column's expression isn't a default
+    let defaults: Vec<Option<&str>> = column_schemas
+        .iter()
+        .map(|c| c.default_expression.as_deref())
+        .collect();
+    assert_eq!(defaults, vec![None, None, Some("'new'::text")]);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_table_schemas_include_comments() -> Result<(), anyhow::Error> {
+    let table_name = "test_schema_comments";
+    let _test_table = TestTable::new(
+        table_name,
+        &format!(
+            "CREATE TABLE {table_name} (
+                id INT PRIMARY KEY,
+                data TEXT
+            );
+            COMMENT ON TABLE {table_name} IS 'test table';
+            COMMENT ON COLUMN {table_name}.data IS 'the data';"
+        ),
+    )
+    .await;
+
+    let table_names = [TableName {
+        schema: "public".to_string(),
+        name: table_name.to_string(),
+    }];
+
+    let mut replication_client = create_replication_client().await;
+    let table_schemas = replication_client
+        .get_table_schemas(&table_names, None)
+        .await?;
+    let table_schema = table_schemas.values().next().unwrap();
+    assert_eq!(table_schema.comment, None);
+    assert!(table_schema
+        .column_schemas
+        .iter()
+        .all(|c| c.comment.is_none()));
+
+    replication_client.set_include_comments(true);
+    let table_schemas = replication_client
+        .get_table_schemas(&table_names, None)
+        .await?;
+    let table_schema = table_schemas.values().next().unwrap();
+    assert_eq!(table_schema.comment.as_deref(), Some("test table"));
+    let comments: Vec<Option<&str>> = table_schema
+        .column_schemas
+        .iter()
+        .map(|c| c.comment.as_deref())
+        .collect();
+    assert_eq!(comments, vec![None, Some("the data")]);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_table_schemas_resolve_custom_range_types() -> Result<(), anyhow::Error> {
+    let table_name = "test_schema_custom_range";
+    let _test_table = TestTable::new(
+        table_name,
+        &format!(
+            "DROP TYPE IF EXISTS test_floatrange CASCADE;
+            CREATE TYPE test_floatrange AS RANGE (subtype = float8);
+            CREATE TABLE {table_name} (
+                id INT PRIMARY KEY,
+                span test_floatrange
+            );"
+        ),
+    )
+    .await;
+
+    let table_names = [TableName {
+        schema: "public".to_string(),
+        name: table_name.to_string(),
+    }];
+
+    let mut replication_client = create_replication_client().await;
+    let table_schemas = replication_client
+        .get_table_schemas(&table_names, None)
+        .await?;
+    let table_schema = table_schemas.values().next().unwrap();
+    let typ = &table_schema.column_schemas[1].typ;
+    assert_eq!(typ.name(), "test_floatrange");
+    assert_eq!(typ.schema(), "public");
+    assert_eq!(range_subtype(typ), Some(&Type::FLOAT8));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_is_lsn_retained() -> Result<(), anyhow::Error> {
+    let table_name = "test_lsn_retained";
+    let slot_name = "test_lsn_retained_slot";
+
+    let test_table = TestTable::new(
+        table_name,
+        &format!("CREATE TABLE {table_name} (id INT PRIMARY KEY)"),
+    )
+    .await;
+    let client = &test_table.client;
+    drop_replication_slot(client, slot_name).await;
+
+    let mut replication_client = create_replication_client().await;
+    replication_client.begin_readonly_transaction().await?;
+    let slot_info = replication_client.get_or_create_slot(slot_name).await?;
+    replication_client.commit_txn().await?;
+
+    let confirmed_flush_lsn: u64 = slot_info.confirmed_flush_lsn.into();
+    assert!(
+        replication_client
+            .is_lsn_retained(slot_name, slot_info.confirmed_flush_lsn)
+            .await?
+    );
+    // WAL from before the slot was created was never reserved by it
+    assert!(
+        !replication_client
+            .is_lsn_retained(slot_name, PgLsn::from(confirmed_flush_lsn - 1_000_000))
+            .await?
+    );
+
+    let result = replication_client
+        .is_lsn_retained("test_lsn_retained_missing_slot", PgLsn::from(0))
+        .await;
+    assert!(matches!(
+        result,
+        Err(ReplicationClientError::MissingSlot(_))
+    ));
+
+    drop_replication_slot(client, slot_name).await;
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_get_slot_reads_flags() -> Result<(), anyhow::Error> {
+    let table_name = "test_get_slot_flags";
+    let slot_name = "test_get_slot_flags_slot";
+
+    let test_table = TestTable::new(
+        table_name,
+        &format!("CREATE TABLE {table_name} (id INT PRIMARY KEY)"),
+    )
+    .await;
+    let client = &test_table.client;
+    drop_replication_slot(client, slot_name).await;
+
+    let mut replication_client = create_replication_client().await;
+    assert!(replication_client.get_slot(slot_name).await?.is_none());
+    replication_client.begin_readonly_transaction().await?;
+    let created = replication_client.get_or_create_slot(slot_name).await?;
+    replication_client.commit_txn().await?;
+
+    let slot_info = replication_client
+        .get_slot(slot_name)
+        .await?
+        .ok_or_else(|| anyhow::anyhow!("slot not found"))?;
+    assert_eq!(slot_info.confirmed_flush_lsn, created.confirmed_flush_lsn);
+    assert!(!slot_info.two_phase);
+    assert!(!slot_info.failover);
+
+    drop_replication_slot(client, slot_name).await;
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_failover_slots_need_postgres_17() -> Result<(), anyhow::Error> {
+    let table_name = "test_failover_slot";
+    let slot_name = "test_failover_slot_slot";
+
+    let test_table = TestTable::new(
+        table_name,
+        &format!("CREATE TABLE {table_name} (id INT PRIMARY KEY)"),
+    )
+    .await;
+    let client = &test_table.client;
+    drop_replication_slot(client, slot_name).await;
+    let version: i32 = client
+        .query_one("select current_setting('server_version_num')::int", &[])
+        .await?
+        .get(0);
+
+    let mut replication_client = create_replication_client().await;
+    replication_client.set_failover_slots(true);
+    replication_client.begin_readonly_transaction().await?;
+    let result = replication_client.get_or_create_slot(slot_name).await;
+    replication_client.commit_txn().await?;
+    if version < 170000 {
+        assert!(matches!(
+            result,
+            Err(ReplicationClientError::FailoverSlotsUnsupported(v)) if v as i32 == version
+        ));
+        assert!(replication_client.get_slot(slot_name).await?.is_none());
+    } else {
+        assert!(result?.failover);
+        let slot_info = replication_client
+            .get_slot(slot_name)
+            .await?
+            .ok_or_else(|| anyhow::anyhow!("slot not found"))?;
+        assert!(slot_info.failover);
+    }
+
+    drop_replication_slot(client, slot_name).await;
+
+    // Temporary slots are created without failover, on any version
+    let temporary_slot_name = "test_failover_slot_temporary_slot";
+    let mut replication_client = create_replication_client().await;
+    replication_client.set_failover_slots(true);
+    let slot_info = replication_client
+        .create_slot_using_snapshot(temporary_slot_name, true)
+        .await?;
+    replication_client.commit_txn().await?;
+    assert!(!slot_info.failover);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_connect_from_uri() -> Result<(), anyhow::Error> {
+    let slot_name = "test_connect_from_uri_slot";
+    let uri = format!(
+        "postgres://{POSTGRES_USER}:{POSTGRES_PASSWORD}@{POSTGRES_HOST}:{POSTGRES_PORT}/{POSTGRES_DBNAME}?sslmode=disable&replication=database"
+    );
+
+    // Creating a slot only works if the connection is in replication mode
+    let mut replication_client = ReplicationClient::connect_from_uri(&uri, NoTls).await?;
+    replication_client.begin_readonly_transaction().await?;
+    replication_client.get_or_create_slot(slot_name).await?;
+    replication_client.commit_txn().await?;
+
+    let test_table = TestTable::new(
+        "test_connect_from_uri",
+        "CREATE TABLE test_connect_from_uri ()",
+    )
+    .await;
+    drop_replication_slot(&test_table.client, slot_name).await;
+
+    let physical_uri = uri.replace("replication=database", "replication=true");
+    let result = ReplicationClient::connect_from_uri(&physical_uri, NoTls).await;
+    assert!(matches!(
+        result,
+        Err(ReplicationClientError::UnsupportedReplicationParam(_))
+    ));
+
+    Ok(())
+}
+
+#[test]
+fn test_take_replication_param_from_key_value_pairs() {
+    let take = ReplicationClient::take_replication_param;
+
+    // Whitespace around `=` is allowed and other pairs are kept as written
+    let (conn_str, replication) =
+        take("host=localhost  application_name='a  b'  replication = database user=me");
+    assert_eq!(
+        conn_str,
+        "host=localhost  application_name='a  b'   user=me"
+    );
+    assert_eq!(replication.as_deref(), Some("database"));
+
+    // Quoted values and backslash escapes, including escaped quotes and spaces
+    let (conn_str, replication) =
+        take(r"password='it\'s a  secret' replication='database' options=-c\ a=b");
+    assert_eq!(conn_str, r"password='it\'s a  secret'  options=-c\ a=b");
+    assert_eq!(replication.as_deref(), Some("database"));
+
+    let (_, replication) = take(r"replication=data\base");
+    assert_eq!(replication.as_deref(), Some("database"));
+
+    // A value merely containing `replication=` isn't the parameter
+    let (conn_str, replication) = take("application_name='replication=true' dbname=db");
+    assert_eq!(conn_str, "application_name='replication=true' dbname=db");
+    assert_eq!(replication, None);
+
+    let (conn_str, replication) = take("postgres://localhost/db?replication=true&sslmode=disable");
+    assert_eq!(conn_str, "postgres://localhost/db?sslmode=disable");
+    assert_eq!(replication.as_deref(), Some("true"));
+}
+
+#[tokio::test]
+async fn test_list_publications() -> Result<(), anyhow::Error> {
+    let table_name = "test_list_publications";
+    let publication = "test_list_publications_pub";
+
+    let test_table = TestTable::new(
+        table_name,
+        &format!("CREATE TABLE {table_name} (id INT PRIMARY KEY)"),
+    )
+    .await;
+    let client = &test_table.client;
+    client
+        .simple_query(&format!(
+            "DROP PUBLICATION IF EXISTS {publication};
+            CREATE PUBLICATION {publication} FOR TABLE {table_name}
+                WITH (publish = 'insert, delete', publish_via_partition_root = true);"
+        ))
+        .await?;
+
+    let replication_client = create_replication_client().await;
+    let publications = replication_client.list_publications().await?;
+    let info = publications
+        .iter()
+        .find(|p| p.name == publication)
+        .expect("publication not listed");
+    assert_eq!(info.owner, POSTGRES_USER);
+    assert!(!info.all_tables);
+    assert!(info.via_root);
+    assert!(info.operations.insert);
+    assert!(!info.operations.update);
+    assert!(info.operations.delete);
+    assert!(!info.operations.truncate);
+
+    client
+        .simple_query(&format!("DROP PUBLICATION IF EXISTS {publication}"))
+        .await?;
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_publication_options_round_trip() -> Result<(), anyhow::Error> {
+    let table_name = "test_publication_options";
+    let publication = "test_publication_options_pub";
+
+    let test_table = TestTable::new(
+        table_name,
+        &format!("CREATE TABLE {table_name} (id INT PRIMARY KEY)"),
+    )
+    .await;
+    let client = &test_table.client;
+    client
+        .simple_query(&format!("DROP PUBLICATION IF EXISTS {publication}"))
+        .await?;
+
+    let replication_client = create_replication_client().await;
+    assert_eq!(
+        replication_client
+            .get_publication_options(publication)
+            .await?,
+        None
+    );
+
+    let options = PublicationOptions {
+        publish: HashSet::from([PublishedOperation::Insert, PublishedOperation::Delete]),
+        via_partition_root: true,
+    };
+    let table_names = [TableName {
+        schema: "public".to_string(),
+        name: table_name.to_string(),
+    }];
+    replication_client
+        .create_publication(publication, &table_names, &options)
+        .await?;
+    assert_eq!(
+        replication_client
+            .get_publication_options(publication)
+            .await?,
+        Some(options)
+    );
+    assert_eq!(
+        replication_client
+            .get_publication_table_names(publication)
+            .await?,
+        table_names
+    );
+
+    client
+        .simple_query(&format!(
+            "DROP PUBLICATION {publication}; CREATE PUBLICATION {publication}"
+        ))
+        .await?;
+    assert_eq!(
+        replication_client
+            .get_publication_options(publication)
+            .await?,
+        Some(PublicationOptions::default())
+    );
+
+    client
+        .simple_query(&format!("DROP PUBLICATION IF EXISTS {publication}"))
+        .await?;
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_check_source() -> Result<(), anyhow::Error> {
+    let table_name = "test_check_source";
+    let publication = "test_check_source_pub";
+
+    let test_table = TestTable::new(
+        table_name,
+        &format!("CREATE TABLE {table_name} (id INT PRIMARY KEY)"),
+    )
+    .await;
+    let client = &test_table.client;
+    client
+        .simple_query(&format!(
+            "DROP PUBLICATION IF EXISTS {publication};
+            CREATE PUBLICATION {publication} FOR TABLE {table_name};"
+        ))
+        .await?;
+
+    let replication_client = create_replication_client().await;
+    let check = replication_client.check_source(publication).await?;
+    assert!(check.wal_level_ok);
+    assert!(check.publication_exists);
+    assert!(check.slots_available > 0);
+    assert!(check.track_commit_timestamp);
+
+    let check = replication_client
+        .check_source("test_check_source_missing_pub")
+        .await?;
+    assert!(!check.publication_exists);
+
+    client
+        .simple_query(&format!("DROP PUBLICATION IF EXISTS {publication}"))
+        .await?;
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_get_commit_timestamp_origin() -> Result<(), anyhow::Error> {
+    let table_name = "test_commit_timestamp_origin";
+    let origin = "test_commit_timestamp_origin";
+
+    let test_table = TestTable::new(
+        table_name,
+        &format!("CREATE TABLE {table_name} (id INT PRIMARY KEY)"),
+    )
+    .await;
+    let client = &test_table.client;
+    let xid = |messages: Vec<SimpleQueryMessage>| -> u32 {
+        messages
+            .into_iter()
+            .find_map(|msg| match msg {
+                SimpleQueryMessage::Row(row) => row.get("xmin").map(|xid| xid.parse().unwrap()),
+                _ => None,
+            })
+            .expect("no xmin returned")
+    };
+
+    let replication_client = create_replication_client().await;
+    assert!(replication_client.is_commit_timestamp_tracked().await?);
+
+    let before = Utc::now() - chrono::Duration::seconds(1);
+    let local_xid = xid(client
+        .simple_query(&format!(
+            "INSERT INTO {table_name} VALUES (1) RETURNING xmin::text AS xmin"
+        ))
+        .await?);
+    let commit = replication_client
+        .get_commit_timestamp_origin(local_xid)
+        .await?
+        .expect("commit timestamp not recorded");
+    assert!(commit.timestamp >= before);
+    assert_eq!(commit.origin_id, None);
+    assert_eq!(commit.origin, None);
+
+    client
+        .simple_query(&format!(
+            "SELECT pg_replication_origin_drop(roname) FROM pg_replication_origin WHERE roname = '{origin}';
+            SELECT pg_replication_origin_create('{origin}');
+            SELECT pg_replication_origin_session_setup('{origin}');"
+        ))
+        .await?;
+    let replayed_xid = xid(client
+        .simple_query(&format!(
+            "INSERT INTO {table_name} VALUES (2) RETURNING xmin::text AS xmin"
+        ))
+        .await?);
+    client
+        .simple_query("SELECT pg_replication_origin_session_reset()")
+        .await?;
+    let commit = replication_client
+        .get_commit_timestamp_origin(replayed_xid)
+        .await?
+        .expect("commit timestamp not recorded");
+    assert!(commit.origin_id.is_some());
+    assert_eq!(commit.origin.as_deref(), Some(origin));
+
+    client
+        .simple_query(&format!("SELECT pg_replication_origin_drop('{origin}')"))
+        .await?;
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_connect_retries_transient_errors() -> Result<(), anyhow::Error> {
+    let retry_policy = ConnectRetryPolicy::new(3, Duration::from_millis(50), 0.0);
+
+    // Nothing listens on port 1, so every attempt is refused
+    let start = Instant::now();
+    let result = ReplicationClient::connect_no_tls_with_retry(
+        POSTGRES_HOST,
+        1,
+        POSTGRES_DBNAME,
+        POSTGRES_USER,
+        Some(POSTGRES_PASSWORD.to_string()),
+        &retry_policy,
+    )
+    .await;
+    assert!(matches!(
+        result,
+        Err(ReplicationClientError::TokioPostgresError(_))
+    ));
+    // Two retries with delays of 50ms and 100ms
+    assert!(start.elapsed() >= Duration::from_millis(150));
+
+    ReplicationClient::connect_no_tls_with_retry(
+        POSTGRES_HOST,
+        POSTGRES_PORT,
+        POSTGRES_DBNAME,
+        POSTGRES_USER,
+        Some(POSTGRES_PASSWORD.to_string()),
+        &retry_policy,
+    )
+    .await?;
+
+    // A wrong password won't be right on the next attempt
+    let start = Instant::now();
+    let result = ReplicationClient::connect_no_tls_with_retry(
+        POSTGRES_HOST,
+        POSTGRES_PORT,
+        POSTGRES_DBNAME,
+        POSTGRES_USER,
+        Some(format!("{POSTGRES_PASSWORD}_wrong")),
+        &retry_policy,
+    )
+    .await;
+    assert!(matches!(
+        result,
+        Err(ReplicationClientError::TokioPostgresError(_))
+    ));
+    assert!(start.elapsed() < Duration::from_millis(50));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_connect_with_connection_leaves_driving_it_to_caller() -> Result<(), anyhow::Error> {
+    let (replication_client, connection) = ReplicationClient::connect_no_tls_with_connection(
+        POSTGRES_HOST,
+        POSTGRES_PORT,
+        POSTGRES_DBNAME,
+        POSTGRES_USER,
+        Some(POSTGRES_PASSWORD.to_string()),
+    )
+    .await?;
+
+    // The client only makes progress once the caller drives the connection
+    let connection = tokio::spawn(connection);
+    let table_id = replication_client
+        .get_table_id(&TableName {
+            schema: "public".to_string(),
+            name: "test_connect_with_connection_missing".to_string(),
+        })
+        .await?;
+    assert!(table_id.is_none());
+
+    // The connection ends without error once the client is dropped
+    drop(replication_client);
+    connection.await??;
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_current_wal_lsns() -> Result<(), anyhow::Error> {
+    let test_table = TestTable::new(
+        "test_current_wal_lsns",
+        "CREATE TABLE test_current_wal_lsns (id INT PRIMARY KEY)",
+    )
+    .await;
+    let replication_client = create_replication_client().await;
+
+    let lsn_before = replication_client.current_wal_lsn().await?;
+    test_table
+        .client
+        .simple_query("INSERT INTO test_current_wal_lsns VALUES (1)")
+        .await?;
+    let lsn_after = replication_client.current_wal_lsn().await?;
+    assert!(lsn_after > lsn_before);
+
+    // The insert's commit is flushed before it returns
+    let flush_lsn = replication_client.current_wal_flush_lsn().await?;
+    assert!(flush_lsn >= lsn_after);
+
+    assert!(replication_client.is_caught_up(flush_lsn, 0).await?);
+    assert!(!replication_client.is_caught_up(lsn_before, 0).await?);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_foreign_keys_sort_tables() -> Result<(), anyhow::Error> {
+    let parent = TestTable::new(
+        "test_fk_parent",
+        "CREATE TABLE test_fk_parent (id INT, kind TEXT, PRIMARY KEY (id, kind))",
+    )
+    .await;
+    let _child = TestTable::new(
+        "test_fk_child",
+        "CREATE TABLE test_fk_child (
+            id INT PRIMARY KEY,
+            parent_kind TEXT,
+            parent_id INT,
+            CONSTRAINT child_parent_fk FOREIGN KEY (parent_id, parent_kind)
+                REFERENCES test_fk_parent (id, kind)
+        )",
+    )
+    .await;
+    let _grandchild = TestTable::new(
+        "test_fk_grandchild",
+        "CREATE TABLE test_fk_grandchild (
+            id INT PRIMARY KEY,
+            child_id INT REFERENCES test_fk_child (id),
+            sibling_id INT REFERENCES test_fk_grandchild (id)
+        )",
+    )
+    .await;
+
+    let replication_client = create_replication_client().await;
+    let mut table_ids = vec![];
+    for name in ["test_fk_grandchild", "test_fk_child", "test_fk_parent"] {
+        let table_name = TableName {
+            schema: "public".to_string(),
+            name: name.to_string(),
+        };
+        let table_id = replication_client
+            .get_table_id(&table_name)
+            .await?
+            .expect("missing table");
+        table_ids.push(table_id);
+    }
+    let (grandchild_id, child_id, parent_id) = (table_ids[0], table_ids[1], table_ids[2]);
+
+    let mut foreign_keys = HashMap::new();
+    for &table_id in &table_ids {
+        foreign_keys.insert(
+            table_id,
+            replication_client.get_foreign_keys(table_id).await?,
+        );
+    }
+
+    let child_fks = &foreign_keys[&child_id];
+    assert_eq!(child_fks.len(), 1);
+    assert_eq!(child_fks[0].name, "child_parent_fk");
+    assert_eq!(child_fks[0].columns, vec!["parent_id", "parent_kind"]);
+    assert_eq!(child_fks[0].referenced_table_id, parent_id);
+    assert_eq!(child_fks[0].referenced_table_name.name, "test_fk_parent");
+    assert_eq!(child_fks[0].referenced_columns, vec!["id", "kind"]);
+    assert_eq!(foreign_keys[&grandchild_id].len(), 2);
+
+    // The self reference of the grandchild doesn't prevent sorting
+    let sorted = sort_by_foreign_keys(&table_ids, &foreign_keys)?;
+    assert_eq!(sorted, vec![parent_id, child_id, grandchild_id]);
+
+    parent
+        .client
+        .simple_query(
+            "ALTER TABLE test_fk_parent ADD COLUMN grandchild_id INT
+                REFERENCES test_fk_grandchild (id)",
+        )
+        .await?;
+    foreign_keys.insert(
+        parent_id,
+        replication_client.get_foreign_keys(parent_id).await?,
+    );
+    let Err(ForeignKeyCycleError(mut cycle)) = sort_by_foreign_keys(&table_ids, &foreign_keys)
+    else {
+        panic!("cycle not reported");
+    };
+    cycle.sort();
+    table_ids.sort();
+    assert_eq!(cycle, table_ids);
+
+    Ok(())
+}
+
+#[test]
+fn test_foreign_key_cycle_error_lists_only_cycle_members() {
+    let foreign_key = |referenced_table_id| ForeignKey {
+        name: format!("fk_{referenced_table_id}"),
+        columns: vec!["ref_id".to_string()],
+        referenced_table_id,
+        referenced_table_name: TableName {
+            schema: "public".to_string(),
+            name: format!("table_{referenced_table_id}"),
+        },
+        referenced_columns: vec!["id".to_string()],
+    };
+    // 1 and 2 reference each other, 3 references the cycle and 4 references 3
+    let foreign_keys = HashMap::from([
+        (1, vec![foreign_key(2)]),
+        (2, vec![foreign_key(1)]),
+        (3, vec![foreign_key(1)]),
+        (4, vec![foreign_key(3)]),
+    ]);
+
+    let Err(ForeignKeyCycleError(cycle)) = sort_by_foreign_keys(&[1, 2, 3, 4], &foreign_keys)
+    else {
+        panic!("cycle not reported");
+    };
+    assert_eq!(cycle, vec![1, 2]);
+}
+
+#[tokio::test]
+async fn test_check_replica_identities() -> Result<(), anyhow::Error> {
+    let publication = "test_replica_identity_pub";
+    let mut test_tables = vec![];
+    for (table_name, create_sql) in [
+        ("test_ri_a_pk", "CREATE TABLE test_ri_a_pk (id INT PRIMARY KEY)"),
+        ("test_ri_b_no_pk", "CREATE TABLE test_ri_b_no_pk (id INT)"),
+        (
+            "test_ri_c_full",
+            "CREATE TABLE test_ri_c_full (id INT); ALTER TABLE test_ri_c_full REPLICA IDENTITY FULL",
+        ),
+        (
+            "test_ri_d_nothing",
+            "CREATE TABLE test_ri_d_nothing (id INT PRIMARY KEY);
+            ALTER TABLE test_ri_d_nothing REPLICA IDENTITY NOTHING",
+        ),
+    ] {
+        test_tables.push(TestTable::new(table_name, create_sql).await);
+    }
+    let client = &test_tables[0].client;
+    client
+        .simple_query(&format!(
+            "DROP PUBLICATION IF EXISTS {publication};
+            CREATE PUBLICATION {publication}
+                FOR TABLE test_ri_a_pk, test_ri_b_no_pk, test_ri_c_full, test_ri_d_nothing;"
+        ))
+        .await?;
+
+    let replication_client = create_replication_client().await;
+    let tables = replication_client
+        .check_replica_identities(publication)
+        .await?;
+    let tables: Vec<_> = tables
+        .iter()
+        .map(|t| (t.table_name.name.as_str(), t.replica_identity, t.problem))
+        .collect();
+    assert_eq!(
+        tables,
+        vec![
+            ("test_ri_a_pk", ReplicaIdentity::Default, None),
+            (
+                "test_ri_b_no_pk",
+                ReplicaIdentity::Default,
+                Some(ReplicaIdentityProblem::NoPrimaryKey)
+            ),
+            ("test_ri_c_full", ReplicaIdentity::Full, None),
+            (
+                "test_ri_d_nothing",
+                ReplicaIdentity::Nothing,
+                Some(ReplicaIdentityProblem::Nothing)
+            ),
+        ]
+    );
+
+    // Inserts work without a replica identity
+    client
+        .simple_query(&format!(
+            "ALTER PUBLICATION {publication} SET (publish = 'insert')"
+        ))
+        .await?;
+    let tables = replication_client
+        .check_replica_identities(publication)
+        .await?;
+    assert!(tables.iter().all(|t| t.problem.is_none()));
+
+    assert!(matches!(
+        replication_client
+            .check_replica_identities("test_replica_identity_missing")
+            .await,
+        Err(ReplicationClientError::MissingPublication(_))
+    ));
+
+    client
+        .simple_query(&format!("DROP PUBLICATION IF EXISTS {publication}"))
+        .await?;
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_find_lsn_at_time() -> Result<(), anyhow::Error> {
+    let table_name = "test_find_lsn_at_time";
+    let publication = "test_find_lsn_at_time_pub";
+    let slot_name = "test_find_lsn_at_time_slot";
+
+    let test_table = TestTable::new(
+        table_name,
+        &format!("CREATE TABLE {table_name} (id INT PRIMARY KEY)"),
+    )
+    .await;
+    let client = &test_table.client;
+    client
+        .simple_query(&format!(
+            "DROP PUBLICATION IF EXISTS {publication};
+            CREATE PUBLICATION {publication} FOR TABLE {table_name};"
+        ))
+        .await?;
+    drop_replication_slot(client, slot_name).await;
+
+    let mut replication_client = create_replication_client().await;
+    replication_client.get_or_create_slot(slot_name).await?;
+    replication_client.commit_txn().await?;
+
+    let before_first = chrono::Utc::now();
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    client
+        .simple_query(&format!("INSERT INTO {table_name} VALUES (1)"))
+        .await?;
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    let before_second = chrono::Utc::now();
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    client
+        .simple_query(&format!("INSERT INTO {table_name} VALUES (2)"))
+        .await?;
+
+    let find = |timestamp| replication_client.find_lsn_at_time(slot_name, publication, timestamp);
+    let first = find(before_first).await?.expect("first commit not found");
+    let second = find(before_second).await?.expect("second commit not found");
+    assert!(first < second);
+    assert!(find(chrono::Utc::now()).await?.is_none());
+
+    // Streaming from the lsn starts with the transaction committed after the timestamp
+    let mut stream = Box::pin(
+        replication_client
+            .get_logical_replication_stream(publication, slot_name, second)
+            .await?,
+    );
+    let begin = loop {
+        match stream.next().await {
+            Some(message) => {
+                if let ReplicationMessage::XLogData(xlog_data) = message? {
+                    if let LogicalReplicationMessage::Begin(begin) = xlog_data.into_data() {
+                        break begin;
+                    }
+                }
+            }
+            None => panic!("stream ended before the transaction"),
+        }
+    };
+    assert_eq!(PgLsn::from(begin.final_lsn()), second);
+    drop(stream);
+
+    drop_replication_slot(client, slot_name).await;
+    client
+        .simple_query(&format!("DROP PUBLICATION IF EXISTS {publication}"))
+        .await?;
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_estimated_table_size() -> Result<(), anyhow::Error> {
+    let table_name = "test_estimated_table_size";
+    let test_table = TestTable::new(
+        table_name,
+        &format!("CREATE TABLE {table_name} (id INT PRIMARY KEY, data TEXT)"),
+    )
+    .await;
+    test_table
+        .client
+        .simple_query(&format!(
+            "INSERT INTO {table_name} SELECT i, 'row ' || i FROM generate_series(1, 1000) i;
+            ANALYZE {table_name};"
+        ))
+        .await?;
+
+    let replication_client = create_replication_client().await;
+    let table_id = replication_client
+        .get_table_id(&TableName {
+            schema: "public".to_string(),
+            name: table_name.to_string(),
+        })
+        .await?
+        .expect("missing table");
+    let size = replication_client
+        .estimated_table_size(table_id)
+        .await?
+        .expect("missing table size");
+
+    assert_eq!(size.reltuples, 1000.0);
+    assert!(size.relpages > 0);
+    // The total size includes the primary key's index
+    assert!(size.total_bytes > size.relpages as u64 * 8192);
+
+    assert!(replication_client.estimated_table_size(0).await?.is_none());
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_unlogged_tables_are_rejected() -> Result<(), anyhow::Error> {
+    let table_name = "test_unlogged_table";
+    let _test_table = TestTable::new(
+        table_name,
+        &format!("CREATE UNLOGGED TABLE {table_name} (id INT PRIMARY KEY, data TEXT)"),
+    )
+    .await;
+
+    let replication_client = create_replication_client().await;
+    let result = replication_client
+        .get_table_id(&TableName {
+            schema: "public".to_string(),
+            name: table_name.to_string(),
+        })
+        .await;
+
+    assert!(matches!(
+        result,
+        Err(ReplicationClientError::UnloggedTable(table)) if table.name == table_name
+    ));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_publication_schemas() -> Result<(), anyhow::Error> {
+    let schema = "test_publication_schemas";
+    let publication = "test_publication_schemas_pub";
+
+    let test_table = TestTable::new(
+        "test_publication_schemas_table",
+        "CREATE TABLE test_publication_schemas_table (id INT PRIMARY KEY)",
+    )
+    .await;
+    let client = &test_table.client;
+    client
+        .simple_query(&format!(
+            "DROP PUBLICATION IF EXISTS {publication};
+            DROP SCHEMA IF EXISTS {schema} CASCADE;
+            CREATE SCHEMA {schema};
+            CREATE PUBLICATION {publication}
+                FOR TABLES IN SCHEMA {schema}, TABLE test_publication_schemas_table;"
+        ))
+        .await?;
+
+    let replication_client = create_replication_client().await;
+    let schemas = replication_client
+        .get_publication_schemas(publication)
+        .await?;
+    assert_eq!(schemas, vec![schema.to_string()]);
+
+    // Tables created in the schema later are published too
+    client
+        .simple_query(&format!("CREATE TABLE {schema}.added (id INT PRIMARY KEY)"))
+        .await?;
+    let mut table_names = replication_client
+        .get_publication_table_names(publication)
+        .await?;
+    table_names.sort_by(|a, b| a.name.cmp(&b.name));
+    assert_eq!(
+        table_names,
+        vec![
+            TableName {
+                schema: schema.to_string(),
+                name: "added".to_string(),
+            },
+            TableName {
+                schema: "public".to_string(),
+                name: "test_publication_schemas_table".to_string(),
+            },
+        ]
+    );
+
+    client
+        .simple_query(&format!(
+            "DROP PUBLICATION IF EXISTS {publication};
+            DROP SCHEMA IF EXISTS {schema} CASCADE;"
+        ))
+        .await?;
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_publication_table_names_of_allowed_schemas() -> Result<(), anyhow::Error> {
+    let tenants = ["test_tenant_a", "test_tenant_b", "test_tenant_c"];
+    let publication = "test_allowed_schemas_pub";
+
+    let test_table = TestTable::new(
+        "test_allowed_schemas_table",
+        "CREATE TABLE test_allowed_schemas_table (id INT PRIMARY KEY)",
+    )
+    .await;
+    let client = &test_table.client;
+    client
+        .simple_query(&format!("DROP PUBLICATION IF EXISTS {publication};"))
+        .await?;
+    for tenant in tenants {
+        client
+            .simple_query(&format!(
+                "DROP SCHEMA IF EXISTS {tenant} CASCADE;
+                CREATE SCHEMA {tenant};
+                CREATE TABLE {tenant}.orders (id INT PRIMARY KEY);"
+            ))
+            .await?;
+    }
+    client
+        .simple_query(&format!(
+            "CREATE PUBLICATION {publication} FOR TABLE test_tenant_a.orders,
+                test_tenant_b.orders, test_tenant_c.orders, test_allowed_schemas_table;"
+        ))
+        .await?;
+
+    let schemas = |table_names: Vec<TableName>| {
+        let mut schemas: Vec<String> = table_names.into_iter().map(|t| t.schema).collect();
+        schemas.sort();
+        schemas
+    };
+
+    let mut replication_client = create_replication_client().await;
+    replication_client.set_allowed_schemas(["test_tenant_a", "test_tenant_b"]);
+    let table_names = replication_client
+        .get_publication_table_names(publication)
+        .await?;
+    assert_eq!(schemas(table_names), vec!["test_tenant_a", "test_tenant_b"]);
+
+    // Denied schemas win over allowed ones
+    replication_client.set_denied_schemas(["test_tenant_b"]);
+    let table_names = replication_client
+        .get_publication_table_names(publication)
+        .await?;
+    assert_eq!(schemas(table_names), vec!["test_tenant_a"]);
+
+    let mut replication_client = create_replication_client().await;
+    replication_client.set_denied_schemas(["test_tenant_a", "test_tenant_c"]);
+    let table_names = replication_client
+        .get_publication_table_names(publication)
+        .await?;
+    assert_eq!(schemas(table_names), vec!["public", "test_tenant_b"]);
+
+    client
+        .simple_query(&format!("DROP PUBLICATION IF EXISTS {publication};"))
+        .await?;
+    for tenant in tenants {
+        client
+            .simple_query(&format!("DROP SCHEMA IF EXISTS {tenant} CASCADE;"))
+            .await?;
+    }
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_publication_tables_include_row_filters() -> Result<(), anyhow::Error> {
+    let publication = "test_row_filters_pub";
+    let filtered_table = TestTable::new(
+        "test_row_filters_filtered",
+        "CREATE TABLE test_row_filters_filtered (id INT PRIMARY KEY, status TEXT)",
+    )
+    .await;
+    let _unfiltered_table = TestTable::new(
+        "test_row_filters_unfiltered",
+        "CREATE TABLE test_row_filters_unfiltered (id INT PRIMARY KEY)",
+    )
+    .await;
+    let client = &filtered_table.client;
+    client
+        .simple_query(&format!(
+            "DROP PUBLICATION IF EXISTS {publication};
+            CREATE PUBLICATION {publication}
+                FOR TABLE test_row_filters_filtered WHERE (status = 'active'),
+                test_row_filters_unfiltered;"
+        ))
+        .await?;
+
+    let replication_client = create_replication_client().await;
+    let tables = replication_client
+        .get_publication_tables(publication)
+        .await?;
+    assert_eq!(
+        tables,
+        vec![
+            PublicationTable {
+                table_name: TableName {
+                    schema: "public".to_string(),
+                    name: "test_row_filters_filtered".to_string(),
+                },
+                row_filter: Some("(status = 'active'::text)".to_string()),
+            },
+            PublicationTable {
+                table_name: TableName {
+                    schema: "public".to_string(),
+                    name: "test_row_filters_unfiltered".to_string(),
+                },
+                row_filter: None,
+            },
+        ]
+    );
+
+    client
+        .simple_query(&format!("DROP PUBLICATION IF EXISTS {publication}"))
+        .await?;
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_leaf_partitions_reject_foreign_tables() -> Result<(), anyhow::Error> {
+    let table_name = "test_foreign_partition";
+    let wrapper = "test_foreign_partition_fdw";
+    let server = "test_foreign_partition_server";
+    // A wrapper without handler is enough to create foreign tables, though not to
+    // read them
+    let test_table = TestTable::new(
+        table_name,
+        &format!(
+            "DROP FOREIGN DATA WRAPPER IF EXISTS {wrapper} CASCADE;
+            CREATE FOREIGN DATA WRAPPER {wrapper};
+            CREATE SERVER {server} FOREIGN DATA WRAPPER {wrapper};
+            CREATE TABLE {table_name} (id INT) PARTITION BY RANGE (id);
+            CREATE TABLE {table_name}_local PARTITION OF {table_name} FOR VALUES FROM (0) TO (10);
+            CREATE FOREIGN TABLE {table_name}_remote PARTITION OF {table_name}
+                FOR VALUES FROM (10) TO (20) SERVER {server};"
+        ),
+    )
+    .await;
+
+    let replication_client = create_replication_client().await;
+    let table_id = replication_client
+        .get_table_id(&TableName {
+            schema: "public".to_string(),
+            name: table_name.to_string(),
+        })
+        .await?
+        .ok_or_else(|| anyhow::anyhow!("table ID not found!"))?;
+    let result = replication_client.get_leaf_partitions(table_id).await;
+
+    match result {
+        Err(ReplicationClientError::ForeignTablePartition(table_name)) => {
+            assert_eq!(table_name.name, "test_foreign_partition_remote")
+        }
+        result => panic!("unexpected result {result:?}"),
+    }
+
+    test_table
+        .client
+        .simple_query(&format!(
+            "DROP FOREIGN DATA WRAPPER IF EXISTS {wrapper} CASCADE"
+        ))
+        .await?;
+
+    Ok(())
+}
@@ -0,0 +1,70 @@
+use bytes::{BufMut, BytesMut};
+use pg_replicate::conversions::cdc_event::CdcEvent;
+use postgres_replication::protocol::LogicalReplicationMessage;
+
+/// Parses a pgoutput message into the event it is converted into
+fn event(message: BytesMut) -> CdcEvent {
+    match LogicalReplicationMessage::parse(&message.freeze(), false).unwrap() {
+        LogicalReplicationMessage::Begin(body) => CdcEvent::Begin(body),
+        LogicalReplicationMessage::Commit(body) => CdcEvent::Commit(body),
+        LogicalReplicationMessage::StreamStart(body) => CdcEvent::StreamStart(body),
+        LogicalReplicationMessage::StreamStop(body) => CdcEvent::StreamStop(body),
+        LogicalReplicationMessage::StreamCommit(body) => CdcEvent::StreamCommit(body),
+        LogicalReplicationMessage::StreamAbort(body) => CdcEvent::StreamAbort(body),
+        _ => unreachable!(),
+    }
+}
+
+pub fn begin(xid: u32) -> CdcEvent {
+    let mut buf = BytesMut::new();
+    buf.put_u8(b'B');
+    buf.put_u64(0);
+    buf.put_i64(0);
+    buf.put_u32(xid);
+    event(buf)
+}
+
+pub fn commit(lsn: u64) -> CdcEvent {
+    let mut buf = BytesMut::new();
+    buf.put_u8(b'C');
+    buf.put_i8(0);
+    buf.put_u64(lsn);
+    buf.put_u64(lsn);
+    buf.put_i64(0);
+    event(buf)
+}
+
+pub fn stream_start(xid: u32) -> CdcEvent {
+    let mut buf = BytesMut::new();
+    buf.put_u8(b'S');
+    buf.put_u32(xid);
+    buf.put_u8(1);
+    event(buf)
+}
+
+pub fn stream_stop() -> CdcEvent {
+    let mut buf = BytesMut::new();
+    buf.put_u8(b'E');
+    event(buf)
+}
+
+pub fn stream_commit(xid: u32, lsn: u64) -> CdcEvent {
+    let mut buf = BytesMut::new();
+    buf.put_u8(b'c');
+    buf.put_u32(xid);
+    buf.put_i8(0);
+    buf.put_u64(lsn);
+    buf.put_u64(lsn);
+    buf.put_i64(0);
+    event(buf)
+}
+
+/// The abort of the transaction `xid` if `subxid` is `xid`, else of its
+/// subtransaction `subxid`
+pub fn stream_abort(xid: u32, subxid: u32) -> CdcEvent {
+    let mut buf = BytesMut::new();
+    buf.put_u8(b'A');
+    buf.put_u32(xid);
+    buf.put_u32(subxid);
+    event(buf)
+}
@@ -1,4 +1,6 @@
+pub mod events;
 pub mod postgres_utils;
+pub mod schemas;
 
 pub const POSTGRES_HOST: &str = "127.0.0.1";
 pub const POSTGRES_PORT: u16 = 5432;
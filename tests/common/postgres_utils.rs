@@ -78,7 +78,7 @@ async fn wait_for_postgres_ready() {
     }
 }
 
-async fn create_postgres_client() -> PostgresClient {
+pub async fn create_postgres_client() -> PostgresClient {
     let conn_str = postgres_connection_string();
     let (client, connection) = connect(&conn_str, NoTls)
         .await
@@ -109,3 +109,19 @@ async fn drop_test_table(client: &PostgresClient, table_name: &str) -> Result<()
         .map_err(|e| e.to_string())?;
     Ok(())
 }
+
+/// Drops a replication slot, waiting for the walsender of a just closed
+/// replication connection to release it first
+pub async fn drop_replication_slot(client: &PostgresClient, slot_name: &str) {
+    let start = Instant::now();
+    let drop_sql = format!(
+        "SELECT pg_drop_replication_slot(slot_name) FROM pg_replication_slots WHERE slot_name = '{slot_name}'"
+    );
+
+    while let Err(e) = client.simple_query(&drop_sql).await {
+        if start.elapsed() > Duration::from_secs(10) {
+            panic!("Timed out dropping replication slot {slot_name}: {e}");
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+}
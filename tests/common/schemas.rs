@@ -0,0 +1,24 @@
+use pg_replicate::table::ColumnSchema;
+use tokio_postgres::types::Type;
+
+/// A nullable column without a type modifier
+pub fn column(name: &str, typ: Type) -> ColumnSchema {
+    ColumnSchema {
+        name: name.to_string(),
+        typ,
+        modifier: -1,
+        nullable: true,
+        collation: None,
+        generated: false,
+        comment: None,
+        default_expression: None,
+    }
+}
+
+/// A column declared NOT NULL without a type modifier
+pub fn not_null_column(name: &str, typ: Type) -> ColumnSchema {
+    ColumnSchema {
+        nullable: false,
+        ..column(name, typ)
+    }
+}
@@ -0,0 +1,183 @@
+use std::collections::HashMap;
+
+use arrow::array::{Array, AsArray};
+use arrow::datatypes::Int32Type;
+use arrow::record_batch::RecordBatch;
+use futures::StreamExt;
+use pg_replicate::{
+    conversions::{arrow::OP_COLUMN_NAME, cdc_event::CdcEvent, table_row::TableRow, Cell},
+    pipeline::{
+        batching::arrow::{ArrowBatchEvent, ArrowBatchStream},
+        sources::postgres::CdcStreamError,
+    },
+    table::{LookupKey, TableId, TableName, TableSchema},
+};
+use tokio_postgres::types::{PgLsn, Type};
+
+use crate::common::{
+    events::{begin, commit, stream_abort, stream_commit, stream_start, stream_stop},
+    schemas::column,
+};
+
+fn table_schema(table_id: TableId) -> TableSchema {
+    TableSchema {
+        table_name: TableName {
+            schema: "public".to_string(),
+            name: "test_arrow".to_string(),
+        },
+        table_id,
+        column_schemas: vec![column("id", Type::INT4), column("data", Type::TEXT)],
+        lookup_key: LookupKey::Key {
+            name: "test_arrow_pkey".to_string(),
+            columns: vec!["id".to_string()],
+        },
+        excluded_columns: vec![],
+        comment: None,
+    }
+}
+
+fn row(id: i32, data: Option<&str>) -> TableRow {
+    TableRow::new(vec![
+        Cell::I32(id),
+        data.map_or(Cell::Null, |d| Cell::String(d.to_string())),
+    ])
+}
+
+async fn collect_events(table_id: TableId, events: Vec<CdcEvent>) -> Vec<ArrowBatchEvent> {
+    let events: Vec<Result<CdcEvent, CdcStreamError>> = events.into_iter().map(Ok).collect();
+    let stream = ArrowBatchStream::new(
+        futures::stream::iter(events),
+        HashMap::from([(table_id, table_schema(table_id))]),
+        100,
+    );
+    stream.map(|event| event.unwrap()).collect().await
+}
+
+fn batches(events: Vec<ArrowBatchEvent>) -> Vec<RecordBatch> {
+    events
+        .into_iter()
+        .filter_map(|event| match event {
+            ArrowBatchEvent::Batch(_, batch) => Some(batch),
+            ArrowBatchEvent::KeepAliveRequested { .. } => None,
+        })
+        .collect()
+}
+
+fn ids_and_ops(batch: &RecordBatch) -> Vec<(i32, String)> {
+    let ids = batch.column(0).as_primitive::<Int32Type>();
+    let ops = batch
+        .column_by_name(OP_COLUMN_NAME)
+        .unwrap()
+        .as_string::<i32>();
+    (0..batch.num_rows())
+        .map(|i| (ids.value(i), ops.value(i).to_string()))
+        .collect()
+}
+
+#[tokio::test]
+async fn test_arrow_batch_stream_groups_rows_per_table() {
+    let table_id = 1;
+
+    let events = vec![
+        Ok(CdcEvent::Insert((table_id, row(1, Some("a")), None))),
+        Ok(CdcEvent::Update((table_id, None, row(1, Some("b")), None))),
+        Ok(CdcEvent::Delete((table_id, row(1, None), None))),
+    ];
+    let stream = ArrowBatchStream::new(
+        futures::stream::iter(events),
+        HashMap::from([(table_id, table_schema(table_id))]),
+        2,
+    );
+    let batches: Vec<_> = stream
+        .map(|event| match event.unwrap() {
+            ArrowBatchEvent::Batch(table_id, batch) => (table_id, batch),
+            event => panic!("unexpected event {event:?}"),
+        })
+        .collect()
+        .await;
+
+    // The first batch is emitted on reaching the batch size, the rest at the end of the stream
+    assert_eq!(batches.len(), 2);
+    let (first_table_id, first) = &batches[0];
+    assert_eq!(*first_table_id, table_id);
+    assert_eq!(first.num_rows(), 2);
+    assert_eq!(first.num_columns(), 3);
+    assert_eq!(batches[1].1.num_rows(), 1);
+
+    let ids = first.column(0).as_primitive::<Int32Type>();
+    assert_eq!(ids.values(), &[1, 1]);
+    let data = first.column(1).as_string::<i32>();
+    assert_eq!(data.value(0), "a");
+    assert_eq!(data.value(1), "b");
+
+    let deleted = &batches[1].1;
+    assert!(deleted.column(1).is_null(0));
+    let ops = deleted
+        .column_by_name(OP_COLUMN_NAME)
+        .unwrap()
+        .as_string::<i32>();
+    assert_eq!(ops.value(0), "delete");
+}
+
+#[tokio::test]
+async fn test_arrow_batch_stream_deletes_old_key_of_updates() {
+    let table_id = 1;
+    let events = vec![
+        begin(1),
+        CdcEvent::Update((table_id, Some(row(1, None)), row(2, Some("a")), None)),
+        commit(0x100),
+    ];
+
+    let batches = batches(collect_events(table_id, events).await);
+
+    assert_eq!(batches.len(), 1);
+    assert_eq!(
+        ids_and_ops(&batches[0]),
+        vec![(1, "delete".to_string()), (2, "update".to_string())]
+    );
+}
+
+#[tokio::test]
+async fn test_arrow_batch_stream_drops_aborted_streamed_rows() {
+    let table_id = 1;
+    let events = vec![
+        stream_start(10),
+        CdcEvent::Insert((table_id, row(1, Some("kept")), Some(10))),
+        CdcEvent::Insert((table_id, row(2, Some("aborted subxact")), Some(11))),
+        stream_stop(),
+        stream_abort(10, 11),
+        stream_start(20),
+        CdcEvent::Insert((table_id, row(3, Some("aborted")), Some(20))),
+        stream_stop(),
+        stream_abort(20, 20),
+        stream_commit(10, 0x100),
+    ];
+
+    let batches = batches(collect_events(table_id, events).await);
+
+    assert_eq!(batches.len(), 1);
+    assert_eq!(ids_and_ops(&batches[0]), vec![(1, "insert".to_string())]);
+}
+
+#[tokio::test]
+async fn test_arrow_batch_stream_passes_keepalives_through() {
+    let table_id = 1;
+    let events = vec![
+        begin(1),
+        CdcEvent::Insert((table_id, row(1, Some("a")), None)),
+        CdcEvent::KeepAliveRequested {
+            reply: true,
+            wal_end: PgLsn::from(0x100),
+        },
+        commit(0x100),
+    ];
+
+    let events = collect_events(table_id, events).await;
+
+    assert_eq!(events.len(), 2);
+    assert!(matches!(
+        events[0],
+        ArrowBatchEvent::KeepAliveRequested { reply: true, wal_end } if wal_end == PgLsn::from(0x100)
+    ));
+    assert!(matches!(events[1], ArrowBatchEvent::Batch(id, _) if id == table_id));
+}
@@ -0,0 +1,56 @@
+use pg_replicate::conversions::{
+    bits::{self, BitsParseError, PgBits},
+    text::{FromTextError, TextFormatConverter},
+    Cell,
+};
+use tokio_postgres::types::Type;
+
+fn parse(typ: &Type, str: &str) -> PgBits {
+    match TextFormatConverter::try_from_str(typ, str) {
+        Ok(Cell::Bits(bits)) => bits,
+        result => panic!("unexpected result {result:?} for {str}"),
+    }
+}
+
+#[test]
+fn test_parse_fixed_length_bits() {
+    // Values of a bit(10) column always have the declared length
+    let bits = parse(&Type::BIT, "1011000001");
+    assert_eq!(bits::declared_length(10), Some(bits.len()));
+    assert_eq!(bits.as_bytes(), &[0b1011_0000, 0b0100_0000]);
+    assert_eq!(bits.get(0), Some(true));
+    assert_eq!(bits.get(1), Some(false));
+    assert_eq!(bits.get(9), Some(true));
+    assert_eq!(bits.get(10), None);
+    assert_eq!(bits.to_u64(), Some(0b1011000001));
+    assert_eq!(
+        TextFormatConverter::to_text(&Cell::Bits(bits)).as_deref(),
+        Some("1011000001")
+    );
+}
+
+#[test]
+fn test_parse_varying_length_bits() {
+    // Values of a bit varying(8) column have at most the declared length
+    for value in ["", "1", "0110", "11111111"] {
+        let bits = parse(&Type::VARBIT, value);
+        assert_eq!(bits.len(), value.len());
+        assert!(bits.len() <= bits::declared_length(8).unwrap());
+        assert_eq!(bits.to_string(), value);
+    }
+    assert!(parse(&Type::VARBIT, "").is_empty());
+    assert_eq!(bits::declared_length(-1), None);
+
+    let long = "1".repeat(65);
+    let bits = parse(&Type::VARBIT, &long);
+    assert_eq!(bits.len(), 65);
+    assert_eq!(bits.to_u64(), None);
+}
+
+#[test]
+fn test_invalid_bits_are_rejected() {
+    assert!(matches!(
+        TextFormatConverter::try_from_str(&Type::BIT, "10201"),
+        Err(FromTextError::InvalidBits(BitsParseError::InvalidBit('2')))
+    ));
+}
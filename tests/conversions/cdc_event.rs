@@ -0,0 +1,406 @@
+use std::collections::HashMap;
+
+use bytes::{BufMut, Bytes, BytesMut};
+use pg_replicate::{
+    conversions::{
+        cdc_event::{
+            CdcEvent, CdcEventConversionError, CdcEventConverter, ColumnChange, RelationIdentity,
+        },
+        Cell,
+    },
+    table::{LookupKey, TableId, TableName, TableSchema},
+};
+use postgres_replication::protocol::{LogicalReplicationMessage, ReplicationMessage};
+use tokio_postgres::types::Type;
+
+use crate::common::schemas::{column, not_null_column};
+
+/// Builds a delete message with a key tuple of `values`, `None` being nulls
+fn delete_message(table_id: u32, values: &[Option<&str>]) -> Bytes {
+    row_message(b'D', table_id, b'K', values)
+}
+
+/// Builds an insert message with a new tuple of `values`, `None` being nulls
+fn insert_message(table_id: u32, values: &[Option<&str>]) -> Bytes {
+    row_message(b'I', table_id, b'N', values)
+}
+
+fn row_message(kind: u8, table_id: u32, tuple_kind: u8, values: &[Option<&str>]) -> Bytes {
+    let mut buf = BytesMut::new();
+    buf.put_u8(b'w');
+    buf.put_u64(0);
+    buf.put_u64(0);
+    buf.put_i64(0);
+    buf.put_u8(kind);
+    buf.put_u32(table_id);
+    buf.put_u8(tuple_kind);
+    buf.put_i16(values.len() as i16);
+    for value in values {
+        match value {
+            Some(value) => {
+                buf.put_u8(b't');
+                buf.put_i32(value.len() as i32);
+                buf.put_slice(value.as_bytes());
+            }
+            None => buf.put_u8(b'n'),
+        }
+    }
+    buf.freeze()
+}
+
+fn parse(message: Bytes) -> Result<ReplicationMessage<LogicalReplicationMessage>, anyhow::Error> {
+    let message = ReplicationMessage::parse(&message)?;
+    let message = match message {
+        ReplicationMessage::XLogData(body) => ReplicationMessage::XLogData(
+            body.map_data(|data| LogicalReplicationMessage::parse(&data, false))?,
+        ),
+        _ => unreachable!(),
+    };
+    Ok(message)
+}
+
+fn convert_delete(
+    message: Bytes,
+    table_schemas: &HashMap<u32, TableSchema>,
+) -> Result<Vec<Option<i32>>, anyhow::Error> {
+    let message = parse(message)?;
+    let CdcEvent::Delete((table_id, row, _)) = CdcEventConverter::try_from(message, table_schemas)?
+    else {
+        anyhow::bail!("expected a delete");
+    };
+    let table_schema = &table_schemas[&table_id];
+    let key_values = table_schema
+        .lookup_key
+        .extract(&table_schema.column_schemas, &row)
+        .expect("missing key values");
+    assert_eq!(key_values.columns, vec!["tenant", "id"]);
+    Ok(key_values
+        .values
+        .iter()
+        .map(|value| match value {
+            Cell::I32(value) => Some(*value),
+            Cell::Null => None,
+            value => panic!("unexpected value {value:?}"),
+        })
+        .collect())
+}
+
+#[test]
+fn test_delete_key_tuple_gives_key_values() -> Result<(), anyhow::Error> {
+    let table_id = 1;
+    // The key's columns are in a different order than the table's
+    let table_schema = TableSchema {
+        table_name: TableName {
+            schema: "public".to_string(),
+            name: "test_delete_key".to_string(),
+        },
+        table_id,
+        column_schemas: vec![
+            column("id", Type::INT4),
+            column("data", Type::TEXT),
+            column("tenant", Type::INT4),
+        ],
+        lookup_key: LookupKey::Key {
+            name: "test_delete_key_pkey".to_string(),
+            columns: vec!["tenant".to_string(), "id".to_string()],
+        },
+        excluded_columns: vec![],
+        comment: None,
+    };
+    let table_schemas = HashMap::from([(table_id, table_schema)]);
+
+    // A tuple with all columns in table order, as sent by Postgres
+    let message = delete_message(table_id, &[Some("42"), None, Some("7")]);
+    assert_eq!(
+        convert_delete(message, &table_schemas)?,
+        vec![Some(7), Some(42)]
+    );
+
+    // A tuple with just the key columns in index order
+    let message = delete_message(table_id, &[Some("7"), Some("42")]);
+    assert_eq!(
+        convert_delete(message, &table_schemas)?,
+        vec![Some(7), Some(42)]
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_uuid_keys_have_canonical_text() -> Result<(), anyhow::Error> {
+    let table_id = 1;
+    let table_schema = TableSchema {
+        table_name: TableName {
+            schema: "public".to_string(),
+            name: "test_uuid_key".to_string(),
+        },
+        table_id,
+        column_schemas: vec![column("id", Type::UUID), column("data", Type::TEXT)],
+        lookup_key: LookupKey::Key {
+            name: "test_uuid_key_pkey".to_string(),
+            columns: vec!["id".to_string()],
+        },
+        excluded_columns: vec![],
+        comment: None,
+    };
+    let table_schemas = HashMap::from([(table_id, table_schema)]);
+
+    // Different spellings of the same uuid give the same key
+    for id in [
+        "a0eebc99-9c0b-4ef8-bb6d-6bb9bd380a11",
+        "A0EEBC99-9C0B-4EF8-BB6D-6BB9BD380A11",
+        "{a0eebc99-9c0b-4ef8-bb6d-6bb9bd380a11}",
+        "a0eebc999c0b4ef8bb6d6bb9bd380a11",
+    ] {
+        let message = delete_message(table_id, &[Some(id)]);
+        let CdcEvent::Delete((_, row, _)) =
+            CdcEventConverter::try_from(parse(message)?, &table_schemas)?
+        else {
+            anyhow::bail!("expected a delete");
+        };
+        let table_schema = &table_schemas[&table_id];
+        let key_values = table_schema
+            .lookup_key
+            .extract(&table_schema.column_schemas, &row)
+            .expect("missing key values");
+        assert!(matches!(key_values.values[0], Cell::Uuid(_)));
+        assert_eq!(
+            key_values.to_text(),
+            vec![Some("a0eebc99-9c0b-4ef8-bb6d-6bb9bd380a11".to_string())]
+        );
+    }
+
+    Ok(())
+}
+
+fn not_null_table_schemas(table_id: TableId) -> HashMap<TableId, TableSchema> {
+    let table_schema = TableSchema {
+        table_name: TableName {
+            schema: "public".to_string(),
+            name: "test_not_null".to_string(),
+        },
+        table_id,
+        column_schemas: vec![
+            not_null_column("id", Type::INT4),
+            not_null_column("name", Type::TEXT),
+            column("note", Type::TEXT),
+        ],
+        lookup_key: LookupKey::Key {
+            name: "test_not_null_pkey".to_string(),
+            columns: vec!["id".to_string()],
+        },
+        excluded_columns: vec![],
+        comment: None,
+    };
+    HashMap::from([(table_id, table_schema)])
+}
+
+#[test]
+fn test_null_in_not_null_column_is_undecodable() -> Result<(), anyhow::Error> {
+    let table_id = 1;
+    let table_schemas = not_null_table_schemas(table_id);
+
+    let message = insert_message(table_id, &[Some("1"), None, None]);
+    let result = CdcEventConverter::try_from_checked(parse(message)?, &table_schemas, true);
+    let row = match result {
+        Err(CdcEventConversionError::UndecodableRow(row)) => row,
+        result => panic!("expected an undecodable row, got {result:?}"),
+    };
+    assert_eq!(row.table_id, table_id);
+    assert!(matches!(
+        &row.error,
+        CdcEventConversionError::NullInNotNullColumn(column) if column == "name"
+    ));
+
+    // Nulls in nullable columns are fine
+    let message = insert_message(table_id, &[Some("1"), Some("a"), None]);
+    CdcEventConverter::try_from_checked(parse(message)?, &table_schemas, true)?;
+
+    // Without enforcement the null is passed on
+    let message = insert_message(table_id, &[Some("1"), None, None]);
+    let CdcEvent::Insert((_, row, _)) =
+        CdcEventConverter::try_from(parse(message)?, &table_schemas)?
+    else {
+        anyhow::bail!("expected an insert");
+    };
+    assert!(matches!(row.values[1], Cell::Null));
+
+    Ok(())
+}
+
+#[test]
+fn test_not_null_check_ignores_non_key_columns_of_key_tuples() -> Result<(), anyhow::Error> {
+    let table_id = 1;
+    let table_schemas = not_null_table_schemas(table_id);
+
+    let message = delete_message(table_id, &[Some("1"), None, None]);
+    CdcEventConverter::try_from_checked(parse(message)?, &table_schemas, true)?;
+
+    let message = delete_message(table_id, &[None, None, None]);
+    let result = CdcEventConverter::try_from_checked(parse(message)?, &table_schemas, true);
+    assert!(matches!(
+        result,
+        Err(CdcEventConversionError::UndecodableRow(_))
+    ));
+
+    Ok(())
+}
+
+/// Appends a tuple of `values`, `None` being nulls, with the unchanged TOAST marker
+/// in the columns at `unchanged_toast`
+fn put_tuple(buf: &mut BytesMut, values: &[Option<&str>], unchanged_toast: &[usize]) {
+    buf.put_i16(values.len() as i16);
+    for (i, value) in values.iter().enumerate() {
+        match value {
+            _ if unchanged_toast.contains(&i) => buf.put_u8(b'u'),
+            Some(value) => {
+                buf.put_u8(b't');
+                buf.put_i32(value.len() as i32);
+                buf.put_slice(value.as_bytes());
+            }
+            None => buf.put_u8(b'n'),
+        }
+    }
+}
+
+/// Builds an update message with an optional old or key tuple, tagged `old_kind`
+fn update_message(
+    table_id: u32,
+    old: Option<(u8, &[Option<&str>])>,
+    new: &[Option<&str>],
+    unchanged_toast: &[usize],
+) -> Bytes {
+    let mut buf = BytesMut::new();
+    buf.put_u8(b'w');
+    buf.put_u64(0);
+    buf.put_u64(0);
+    buf.put_i64(0);
+    buf.put_u8(b'U');
+    buf.put_u32(table_id);
+    if let Some((old_kind, old)) = old {
+        buf.put_u8(old_kind);
+        put_tuple(&mut buf, old, &[]);
+    }
+    buf.put_u8(b'N');
+    put_tuple(&mut buf, new, unchanged_toast);
+    buf.freeze()
+}
+
+fn changed_columns(
+    message: Bytes,
+    table_schemas: &HashMap<TableId, TableSchema>,
+    identity: &RelationIdentity,
+) -> Result<(CdcEvent, Vec<ColumnChange>), anyhow::Error> {
+    let event = CdcEventConverter::try_from(parse(message)?, table_schemas)?;
+    let CdcEvent::Update((table_id, _, _, _)) = &event else {
+        anyhow::bail!("expected an update");
+    };
+    let changes = event
+        .changed_columns(&table_schemas[table_id], identity)
+        .expect("missing changes");
+    let changes = changes.into_iter().map(|(_, change)| change).collect();
+    Ok((event, changes))
+}
+
+#[test]
+fn test_changed_columns_of_updates() -> Result<(), anyhow::Error> {
+    let table_id = 1;
+    let table_schema = TableSchema {
+        table_name: TableName {
+            schema: "public".to_string(),
+            name: "test_changed_columns".to_string(),
+        },
+        table_id,
+        column_schemas: vec![
+            column("id", Type::INT4),
+            column("data", Type::TEXT),
+            column("payload", Type::TEXT),
+        ],
+        lookup_key: LookupKey::Key {
+            name: "test_changed_columns_pkey".to_string(),
+            columns: vec!["id".to_string()],
+        },
+        excluded_columns: vec![],
+        comment: None,
+    };
+    let table_schemas = HashMap::from([(table_id, table_schema)]);
+    let key_identity = RelationIdentity::Key(vec!["id".to_string()]);
+
+    // A full identity's old row has the unchanged TOAST value
+    let message = update_message(
+        table_id,
+        Some((b'O', &[Some("1"), Some("a"), Some("large")])),
+        &[Some("1"), Some("b"), None],
+        &[2],
+    );
+    let (event, changes) = changed_columns(message, &table_schemas, &RelationIdentity::Full)?;
+    assert_eq!(
+        changes,
+        vec![
+            ColumnChange::Unchanged,
+            ColumnChange::Changed,
+            ColumnChange::Unchanged
+        ]
+    );
+    let CdcEvent::Update((_, _, new_row, _)) = event else {
+        unreachable!()
+    };
+    assert!(matches!(&new_row.values[2], Cell::String(payload) if payload == "large"));
+
+    // Without a changed key only the key is known, to be unchanged
+    let message = update_message(table_id, None, &[Some("1"), Some("b"), None], &[2]);
+    let (_, changes) = changed_columns(message, &table_schemas, &key_identity)?;
+    assert_eq!(
+        changes,
+        vec![
+            ColumnChange::Unchanged,
+            ColumnChange::Unknown,
+            ColumnChange::Unknown
+        ]
+    );
+
+    // A changed key is sent in a key tuple
+    let message = update_message(
+        table_id,
+        Some((b'K', &[Some("1"), None, None])),
+        &[Some("2"), Some("b"), Some("c")],
+        &[],
+    );
+    let (_, changes) = changed_columns(message, &table_schemas, &key_identity)?;
+    assert_eq!(
+        changes,
+        vec![
+            ColumnChange::Changed,
+            ColumnChange::Unknown,
+            ColumnChange::Unknown
+        ]
+    );
+
+    // Columns added since the update was decoded are unknown
+    let message = update_message(
+        table_id,
+        Some((b'O', &[Some("1"), Some("a"), Some("c")])),
+        &[Some("1"), Some("b"), Some("c")],
+        &[],
+    );
+    let event = CdcEventConverter::try_from(parse(message)?, &table_schemas)?;
+    let mut altered_schema = table_schemas[&table_id].clone();
+    altered_schema
+        .column_schemas
+        .push(column("added", Type::TEXT));
+    let changes = event
+        .changed_columns(&altered_schema, &RelationIdentity::Full)
+        .expect("missing changes");
+    let changes: Vec<_> = changes.into_iter().map(|(_, change)| change).collect();
+    assert_eq!(
+        changes,
+        vec![
+            ColumnChange::Unchanged,
+            ColumnChange::Changed,
+            ColumnChange::Unchanged,
+            ColumnChange::Unknown
+        ]
+    );
+
+    Ok(())
+}
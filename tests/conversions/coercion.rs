@@ -0,0 +1,104 @@
+use chrono::{DateTime, Utc};
+use pg_replicate::conversions::{
+    coercion::{CoercionError, CoercionTable},
+    table_row::TableRow,
+    text::TextFormatConverter,
+    ArrayCell, Cell,
+};
+use serde_json::json;
+use tokio_postgres::types::Type;
+
+#[test]
+fn test_coercion_table_coerces_rows() {
+    let mut coercions = CoercionTable::new();
+    coercions.add(Type::INT2, Type::INT4);
+    coercions.add(Type::INT8, Type::INT4);
+    coercions.add(Type::TIMESTAMPTZ, Type::TIMESTAMP);
+    coercions.add(Type::NUMERIC, Type::TEXT);
+
+    let column_types = [Type::INT2, Type::INT8, Type::TIMESTAMPTZ, Type::NUMERIC];
+    let timestamp: DateTime<Utc> = "2024-01-02T03:04:05Z".parse().unwrap();
+    let row = TableRow::new(vec![
+        Cell::I16(1),
+        Cell::I64(2),
+        Cell::TimeStampTz(timestamp),
+        Cell::Numeric("1.50".parse().unwrap()),
+    ]);
+
+    let row = coercions.coerce_row(&column_types, row).unwrap();
+    match &row.values[..] {
+        [Cell::I32(1), Cell::I32(2), Cell::TimeStamp(ts), Cell::String(numeric)] => {
+            assert_eq!(*ts, timestamp.naive_utc());
+            assert_eq!(numeric, "1.50");
+        }
+        values => panic!("unexpected row {values:?}"),
+    }
+
+    // An int8 which doesn't fit into an int4 is an error instead of being truncated
+    let row = TableRow::new(vec![
+        Cell::Null,
+        Cell::I64(i64::MAX),
+        Cell::Null,
+        Cell::Null,
+    ]);
+    let result = coercions.coerce_row(&column_types, row);
+    assert!(matches!(result, Err(CoercionError::OutOfRange(_, _))));
+}
+
+#[test]
+fn test_coercion_edge_values() {
+    // i64::MIN has no positive counterpart and is too large for a float8 anyway
+    let result = CoercionTable::coerce_cell(&Type::INT8, &Type::FLOAT8, Cell::I64(i64::MIN));
+    assert!(matches!(result, Err(CoercionError::OutOfRange(_, _))));
+    match CoercionTable::coerce_cell(&Type::INT8, &Type::FLOAT8, Cell::I64(-(1 << 53))) {
+        Ok(Cell::F64(v)) => assert_eq!(v, -9007199254740992.0),
+        result => panic!("unexpected result {result:?}"),
+    }
+
+    // Values coerced to text are in Postgres' text format
+    match CoercionTable::coerce_cell(&Type::BOOL, &Type::TEXT, Cell::Bool(true)) {
+        Ok(Cell::String(v)) => assert_eq!(v, "t"),
+        result => panic!("unexpected result {result:?}"),
+    }
+}
+
+#[test]
+fn test_json_passes_through_unless_parsed() {
+    let text = r#"{"b": 1,  "a": [2]}"#;
+    let value = TextFormatConverter::try_from_str(&Type::JSONB, text).unwrap();
+    match &value {
+        Cell::String(s) => assert_eq!(s, text),
+        cell => panic!("unexpected cell {cell:?}"),
+    }
+    let array =
+        TextFormatConverter::try_from_str(&Type::JSON_ARRAY, r#"{"{\"a\": 1}",NULL}"#).unwrap();
+
+    let mut coercions = CoercionTable::new();
+    coercions.parse_json();
+    let row = TableRow::new(vec![value, array]);
+    let row = coercions
+        .coerce_row(&[Type::JSONB, Type::JSON_ARRAY], row)
+        .unwrap();
+    match &row.values[..] {
+        [Cell::Json(value), Cell::Array(ArrayCell::Json(values))] => {
+            assert_eq!(value, &json!({"a": [2], "b": 1}));
+            assert_eq!(values, &vec![Some(json!({"a": 1})), None]);
+        }
+        values => panic!("unexpected values {values:?}"),
+    }
+}
+
+#[test]
+fn test_check_type() {
+    let mut coercions = CoercionTable::new();
+    coercions.add(Type::INT8, Type::INT4);
+    coercions.add(Type::UUID, Type::INT8);
+
+    assert!(coercions.check_type(&Type::INT8).is_ok());
+    // Types without a coercion are passed through as they are
+    assert!(coercions.check_type(&Type::BYTEA).is_ok());
+    assert!(matches!(
+        coercions.check_type(&Type::UUID),
+        Err(CoercionError::Unsupported(from, to)) if from == Type::UUID && to == Type::INT8
+    ));
+}
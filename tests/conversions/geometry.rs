@@ -0,0 +1,79 @@
+use pg_replicate::conversions::{
+    geometry::{GeometryParseError, PgGeometry},
+    text::TextFormatConverter,
+    Cell,
+};
+use tokio_postgres::types::{Kind, Type};
+
+// POINT(1 2) with SRID 4326 in little endian EWKB
+const POINT_EWKB: &str = "0101000020E6100000000000000000F03F0000000000000040";
+const POINT_WKB: &str = "0101000000000000000000F03F0000000000000040";
+
+fn geometry_type() -> Type {
+    Type::new(
+        "geometry".to_string(),
+        41234,
+        Kind::Simple,
+        "public".to_string(),
+    )
+}
+
+#[test]
+fn test_parse_geometry() {
+    let geometry = match TextFormatConverter::try_from_str(&geometry_type(), POINT_EWKB) {
+        Ok(Cell::Geometry(geometry)) => geometry,
+        result => panic!("unexpected result {result:?}"),
+    };
+    assert_eq!(geometry.srid(), Some(4326));
+    assert_eq!(geometry.to_hex(), POINT_EWKB);
+    assert_eq!(
+        TextFormatConverter::to_text(&Cell::Geometry(geometry.clone())).as_deref(),
+        Some(POINT_EWKB)
+    );
+
+    let wkb = PgGeometry::from_ewkb(geometry.to_wkb()).unwrap();
+    assert_eq!(wkb.to_hex(), POINT_WKB);
+    assert_eq!(wkb.srid(), None);
+    assert_eq!(wkb.to_wkb(), wkb.ewkb());
+}
+
+#[test]
+fn test_parse_big_endian_geometry() {
+    // POINT(1 2) with SRID 4326 in big endian EWKB
+    let geometry =
+        PgGeometry::from_hex("0020000001000010E63FF00000000000004000000000000000").unwrap();
+    assert_eq!(geometry.srid(), Some(4326));
+    assert_eq!(
+        PgGeometry::from_ewkb(geometry.to_wkb()).unwrap().to_hex(),
+        "00000000013FF00000000000004000000000000000"
+    );
+}
+
+#[test]
+fn test_parse_invalid_geometry() {
+    assert!(matches!(
+        PgGeometry::from_hex("0201000000"),
+        Err(GeometryParseError::InvalidByteOrder(2))
+    ));
+    assert!(matches!(
+        PgGeometry::from_hex("010100"),
+        Err(GeometryParseError::TooShort(3))
+    ));
+    // the SRID flag is set but the SRID is missing
+    assert!(matches!(
+        PgGeometry::from_hex("0101000020"),
+        Err(GeometryParseError::TooShort(5))
+    ));
+    assert!(matches!(
+        PgGeometry::from_hex("01010"),
+        Err(GeometryParseError::InvalidHex(_))
+    ));
+}
+
+#[test]
+fn test_geometry_default_value() {
+    match TextFormatConverter::default_value(&geometry_type()) {
+        Cell::Geometry(geometry) => assert_eq!(geometry, PgGeometry::empty()),
+        cell => panic!("unexpected cell {cell:?}"),
+    }
+}
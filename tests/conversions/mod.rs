@@ -0,0 +1,10 @@
+#[cfg(feature = "arrow")]
+pub mod arrow;
+pub mod bits;
+pub mod cdc_event;
+pub mod coercion;
+pub mod geometry;
+pub mod range;
+pub mod table_row;
+pub mod text;
+pub mod wal2json;
@@ -0,0 +1,111 @@
+use chrono::NaiveDateTime;
+use pg_replicate::conversions::{range::PgRange, text::TextFormatConverter, Cell};
+use tokio_postgres::types::Type;
+
+fn parse(typ: &Type, str: &str) -> PgRange {
+    match TextFormatConverter::try_from_str(typ, str) {
+        Ok(Cell::Range(range)) => range,
+        result => panic!("unexpected result {result:?} for {str}"),
+    }
+}
+
+fn bound_i32(bound: &Option<Box<Cell>>) -> Option<i32> {
+    match bound.as_deref() {
+        Some(Cell::I32(v)) => Some(*v),
+        None => None,
+        bound => panic!("unexpected bound {bound:?}"),
+    }
+}
+
+#[test]
+fn test_parse_range() {
+    let range = parse(&Type::INT4_RANGE, "[1,5)");
+    assert!(!range.empty);
+    assert_eq!(bound_i32(&range.lower), Some(1));
+    assert_eq!(bound_i32(&range.upper), Some(5));
+    assert!(range.lower_inc);
+    assert!(!range.upper_inc);
+
+    let range = parse(
+        &Type::TSRANGE,
+        r#"["2024-01-01 00:00:00","2024-02-01 12:30:00"]"#,
+    );
+    let expected =
+        NaiveDateTime::parse_from_str("2024-02-01 12:30:00", "%Y-%m-%d %H:%M:%S").unwrap();
+    match range.upper.as_deref() {
+        Some(Cell::TimeStamp(v)) => assert_eq!(*v, expected),
+        bound => panic!("unexpected bound {bound:?}"),
+    }
+    assert!(range.upper_inc);
+}
+
+#[test]
+fn test_parse_empty_and_unbounded_ranges() {
+    let range = parse(&Type::INT4_RANGE, "empty");
+    assert!(range.empty);
+    assert!(range.lower.is_none() && range.upper.is_none());
+
+    let range = parse(&Type::INT4_RANGE, "(,5]");
+    assert!(!range.empty);
+    assert_eq!(bound_i32(&range.lower), None);
+    assert!(!range.lower_inc);
+    assert_eq!(bound_i32(&range.upper), Some(5));
+    assert!(range.upper_inc);
+
+    let range = parse(&Type::INT4_RANGE, "(,)");
+    assert!(!range.empty);
+    assert!(range.lower.is_none() && range.upper.is_none());
+}
+
+#[test]
+fn test_parse_multirange() {
+    match TextFormatConverter::try_from_str(&Type::INT8MULTI_RANGE, "{[1,3), [5,7)}") {
+        Ok(Cell::Multirange(ranges)) => {
+            let bounds: Vec<_> = ranges
+                .iter()
+                .map(|r| match (r.lower.as_deref(), r.upper.as_deref()) {
+                    (Some(Cell::I64(lower)), Some(Cell::I64(upper))) => (*lower, *upper),
+                    bounds => panic!("unexpected bounds {bounds:?}"),
+                })
+                .collect();
+            assert_eq!(bounds, vec![(1, 3), (5, 7)]);
+        }
+        result => panic!("unexpected result {result:?}"),
+    }
+
+    match TextFormatConverter::try_from_str(&Type::INT8MULTI_RANGE, "{}") {
+        Ok(Cell::Multirange(ranges)) => assert!(ranges.is_empty()),
+        result => panic!("unexpected result {result:?}"),
+    }
+}
+
+#[test]
+fn test_parse_invalid_ranges() {
+    for str in ["[1,5", "1,5)", "[1,5) x", "emptyx", "[a,5)"] {
+        assert!(
+            TextFormatConverter::try_from_str(&Type::INT4_RANGE, str).is_err(),
+            "{str} should not parse"
+        );
+    }
+}
+
+#[test]
+fn test_range_to_text_round_trips() {
+    for str in ["[1,5)", "(,5]", "[3,)", "empty"] {
+        let cell = TextFormatConverter::try_from_str(&Type::INT4_RANGE, str).unwrap();
+        let text = TextFormatConverter::to_text(&cell).unwrap();
+        let range = parse(&Type::INT4_RANGE, &text);
+        let original = parse(&Type::INT4_RANGE, str);
+        assert_eq!(bound_i32(&range.lower), bound_i32(&original.lower));
+        assert_eq!(bound_i32(&range.upper), bound_i32(&original.upper));
+        assert_eq!(range.lower_inc, original.lower_inc);
+        assert_eq!(range.upper_inc, original.upper_inc);
+        assert_eq!(range.empty, original.empty);
+    }
+
+    let cell = TextFormatConverter::try_from_str(&Type::INT4MULTI_RANGE, "{[1,3),[5,7)}").unwrap();
+    assert_eq!(
+        TextFormatConverter::to_text(&cell).as_deref(),
+        Some(r#"{["1","3"),["5","7")}"#)
+    );
+}
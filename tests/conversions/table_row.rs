@@ -0,0 +1,68 @@
+use pg_replicate::conversions::{
+    table_row::{TableRowConversionError, TableRowConverter},
+    Cell,
+};
+use tokio_postgres::types::Type;
+
+use crate::common::schemas::column;
+
+#[test]
+fn test_csv_rows_follow_quoting_rules() -> Result<(), anyhow::Error> {
+    let columns = [
+        column("id", Type::INT4),
+        column("name", Type::TEXT),
+        column("note", Type::TEXT),
+    ];
+
+    let row = TableRowConverter::try_from_csv(b"1,plain,\"a, \"\"quoted\"\"\nvalue\"\n", &columns)?;
+    assert!(matches!(
+        &row.values[..],
+        [Cell::I32(1), Cell::String(name), Cell::String(note)]
+            if name == "plain" && note == "a, \"quoted\"\nvalue"
+    ));
+
+    // An unquoted empty value is null, a quoted one an empty string
+    let row = TableRowConverter::try_from_csv(b"2,,\"\"\r\n", &columns)?;
+    assert!(matches!(
+        &row.values[..],
+        [Cell::I32(2), Cell::Null, Cell::String(note)] if note.is_empty()
+    ));
+
+    assert!(matches!(
+        TableRowConverter::try_from_csv(b"3,\"open\n", &columns),
+        Err(TableRowConversionError::UnterminatedQuote)
+    ));
+    assert!(matches!(
+        TableRowConverter::try_from_csv(b"4,too few\n", &columns),
+        Err(TableRowConversionError::NumColsMismatch)
+    ));
+    assert!(matches!(
+        TableRowConverter::try_from_csv(b"5,a,b", &columns),
+        Err(TableRowConversionError::UnterminatedRow)
+    ));
+
+    Ok(())
+}
+
+#[test]
+fn test_text_rows_distinguish_null_from_backslash_n() -> Result<(), anyhow::Error> {
+    let columns = [column("id", Type::INT4), column("note", Type::TEXT)];
+
+    let row = TableRowConverter::try_from(b"1\t\\N\n", &columns)?;
+    assert!(matches!(&row.values[..], [Cell::I32(1), Cell::Null]));
+
+    // Postgres escapes the backslash of a literal `\N` string
+    let row = TableRowConverter::try_from(b"2\t\\\\N\n", &columns)?;
+    assert!(matches!(
+        &row.values[..],
+        [Cell::I32(2), Cell::String(note)] if note == "\\N"
+    ));
+
+    let row = TableRowConverter::try_from(b"3\ta\\\\N\n", &columns)?;
+    assert!(matches!(
+        &row.values[..],
+        [Cell::I32(3), Cell::String(note)] if note == "a\\N"
+    ));
+
+    Ok(())
+}
@@ -0,0 +1,200 @@
+use pg_replicate::conversions::{
+    interval::PgInterval, numeric::PgNumeric, text::TextFormatConverter, ArrayCell, Cell,
+};
+use tokio_postgres::types::Type;
+use uuid::Uuid;
+
+fn parse(typ: &Type, str: &str) -> ArrayCell {
+    match TextFormatConverter::try_from_str(typ, str) {
+        Ok(Cell::Array(array)) => array,
+        result => panic!("unexpected result {result:?} for {str}"),
+    }
+}
+
+#[test]
+fn test_parse_arrays() {
+    match parse(&Type::INT4_ARRAY, "{1,NULL,3}") {
+        ArrayCell::I32(values) => assert_eq!(values, vec![Some(1), None, Some(3)]),
+        array => panic!("unexpected array {array:?}"),
+    }
+
+    // Only an unquoted NULL is a null
+    match parse(&Type::TEXT_ARRAY, r#"{"a,b",NULL,"NULL"," c\"d\\",e f}"#) {
+        ArrayCell::String(values) => assert_eq!(
+            values,
+            vec![
+                Some("a,b".to_string()),
+                None,
+                Some("NULL".to_string()),
+                Some(" c\"d\\".to_string()),
+                Some("e f".to_string()),
+            ]
+        ),
+        array => panic!("unexpected array {array:?}"),
+    }
+
+    let uuid = "a0eebc99-9c0b-4ef8-bb6d-6bb9bd380a11";
+    match parse(&Type::UUID_ARRAY, &format!("{{{uuid}}}")) {
+        ArrayCell::Uuid(values) => assert_eq!(values, vec![Some(Uuid::parse_str(uuid).unwrap())]),
+        array => panic!("unexpected array {array:?}"),
+    }
+
+    match parse(&Type::INT8_ARRAY, "{}") {
+        ArrayCell::I64(values) => assert!(values.is_empty()),
+        array => panic!("unexpected array {array:?}"),
+    }
+
+    // Multi-dimensional arrays, with and without explicit bounds
+    for str in ["{{1,2},{3,NULL}}", "[0:1][1:2]={{1,2},{3,NULL}}"] {
+        let ArrayCell::Nested(sub_arrays) = parse(&Type::INT2_ARRAY, str) else {
+            panic!("{str} not parsed as nested array");
+        };
+        let sub_arrays: Vec<Vec<Option<i16>>> = sub_arrays
+            .into_iter()
+            .map(|sub_array| match sub_array {
+                ArrayCell::I16(values) => values,
+                array => panic!("unexpected sub-array {array:?}"),
+            })
+            .collect();
+        assert_eq!(
+            sub_arrays,
+            vec![vec![Some(1), Some(2)], vec![Some(3), None]]
+        );
+    }
+
+    assert!(TextFormatConverter::try_from_str(&Type::INT4_ARRAY, "{1,{2}}").is_err());
+}
+
+fn interval(months: i32, days: i32, microseconds: i64) -> PgInterval {
+    PgInterval {
+        months,
+        days,
+        microseconds,
+    }
+}
+
+#[test]
+fn test_parse_money() {
+    for (str, expected) in [
+        ("$1,234.56", "1234.56"),
+        ("-$0.99", "-0.99"),
+        ("($5.00)", "-5.00"),
+        ("$0.00", "0.00"),
+    ] {
+        match TextFormatConverter::try_from_str(&Type::MONEY, str) {
+            Ok(Cell::Numeric(value)) => assert_eq!(value, expected.parse::<PgNumeric>().unwrap()),
+            result => panic!("unexpected result {result:?} for {str}"),
+        }
+    }
+
+    match parse(&Type::MONEY_ARRAY, r#"{"$1.50",NULL}"#) {
+        ArrayCell::Numeric(values) => {
+            assert_eq!(values, vec![Some("1.50".parse().unwrap()), None])
+        }
+        array => panic!("unexpected array {array:?}"),
+    }
+
+    assert!(TextFormatConverter::try_from_str(&Type::MONEY, "$").is_err());
+}
+
+#[test]
+fn test_parse_interval() {
+    let hms = |h: i64, m: i64, s: i64| ((h * 60 + m) * 60 + s) * 1_000_000;
+    for (str, expected) in [
+        ("00:00:00", interval(0, 0, 0)),
+        ("1 day", interval(0, 1, 0)),
+        (
+            "1 year 2 mons 3 days 04:05:06.789",
+            interval(14, 3, hms(4, 5, 6) + 789_000),
+        ),
+        ("-1 days +02:03:00", interval(0, -1, hms(2, 3, 0))),
+        ("1 mon -1 days", interval(1, -1, 0)),
+        ("-1 years -2 mons -00:00:01.5", interval(-14, 0, -1_500_000)),
+        ("100:00:00", interval(0, 0, hms(100, 0, 0))),
+        (
+            "P1Y2M3DT4H5M6.789S",
+            interval(14, 3, hms(4, 5, 6) + 789_000),
+        ),
+        ("P-1Y-2M3DT-4H-5M-6S", interval(-14, 3, -hms(4, 5, 6))),
+        ("PT0S", interval(0, 0, 0)),
+    ] {
+        match TextFormatConverter::try_from_str(&Type::INTERVAL, str) {
+            Ok(Cell::Interval(value)) => assert_eq!(value, expected, "{str}"),
+            result => panic!("unexpected result {result:?} for {str}"),
+        }
+    }
+
+    match parse(
+        &Type::INTERVAL_ARRAY,
+        r#"{"1 day","-1 days +02:03:00",NULL}"#,
+    ) {
+        ArrayCell::Interval(values) => assert_eq!(
+            values,
+            vec![
+                Some(interval(0, 1, 0)),
+                Some(interval(0, -1, hms(2, 3, 0))),
+                None
+            ]
+        ),
+        array => panic!("unexpected array {array:?}"),
+    }
+
+    for str in ["", "1", "1 week", "1:2:3:4", "P", "P1H"] {
+        assert!(
+            TextFormatConverter::try_from_str(&Type::INTERVAL, str).is_err(),
+            "{str}"
+        );
+    }
+}
+
+#[test]
+fn test_interval_to_text() {
+    let hms = |h: i64, m: i64, s: i64| ((h * 60 + m) * 60 + s) * 1_000_000;
+    for (interval, expected) in [
+        (interval(0, 0, 0), "00:00:00"),
+        (
+            interval(14, 3, hms(4, 5, 6) + 789_000),
+            "1 year 2 mons 3 days 04:05:06.789",
+        ),
+        (interval(0, -1, hms(2, 3, 0)), "-1 days +02:03:00"),
+        (interval(1, -1, 0), "1 mon -1 days"),
+        (interval(-14, 0, -1_500_000), "-1 years -2 mons -00:00:01.5"),
+    ] {
+        let text = TextFormatConverter::to_text(&Cell::Interval(interval)).unwrap();
+        assert_eq!(text, expected);
+        // Formatting and parsing round-trips
+        assert_eq!(text.parse::<PgInterval>().unwrap(), interval);
+    }
+}
+
+#[test]
+fn test_timestamptz_to_text() {
+    for (text, expected) in [
+        ("2024-01-01 00:00:00+00", "2024-01-01 00:00:00+00:00"),
+        ("2024-01-01 12:30:00.25+02", "2024-01-01 10:30:00.250+00:00"),
+    ] {
+        let cell = TextFormatConverter::try_from_str(&Type::TIMESTAMPTZ, text).unwrap();
+        let formatted = TextFormatConverter::to_text(&cell).unwrap();
+        assert_eq!(formatted, expected);
+        // Formatting and parsing round-trips
+        match (
+            TextFormatConverter::try_from_str(&Type::TIMESTAMPTZ, &formatted),
+            cell,
+        ) {
+            (Ok(Cell::TimeStampTz(parsed)), Cell::TimeStampTz(original)) => {
+                assert_eq!(parsed, original)
+            }
+            (result, _) => panic!("unexpected result {result:?}"),
+        }
+    }
+}
+
+#[test]
+fn test_is_supported() {
+    assert!(TextFormatConverter::is_supported(&Type::INT4));
+    assert!(TextFormatConverter::is_supported(&Type::JSONB_ARRAY));
+    assert!(TextFormatConverter::is_supported(&Type::INT4_RANGE));
+    assert!(TextFormatConverter::is_supported(&Type::TSTZ_MULTI_RANGE));
+    assert!(!TextFormatConverter::is_supported(&Type::POINT));
+    assert!(!TextFormatConverter::is_supported(&Type::TS_VECTOR));
+}
@@ -0,0 +1,141 @@
+use std::collections::HashMap;
+
+use pg_replicate::{
+    conversions::{
+        cdc_event::{CdcEvent, CdcEventConversionError},
+        wal2json::Wal2JsonConverter,
+        Cell,
+    },
+    table::{LookupKey, TableId, TableName, TableSchema},
+};
+use tokio_postgres::types::Type;
+
+use crate::common::schemas::column;
+
+const TABLE_ID: TableId = 16384;
+
+fn tables() -> (HashMap<TableName, TableId>, HashMap<TableId, TableSchema>) {
+    let table_name = TableName {
+        schema: "public".to_string(),
+        name: "wal2json".to_string(),
+    };
+    let table_schema = TableSchema {
+        table_name: table_name.clone(),
+        table_id: TABLE_ID,
+        column_schemas: vec![
+            column("id", Type::INT4),
+            column("name", Type::TEXT),
+            column("active", Type::BOOL),
+        ],
+        lookup_key: LookupKey::Key {
+            name: "wal2json_pkey".to_string(),
+            columns: vec!["id".to_string()],
+        },
+        excluded_columns: vec![],
+        comment: None,
+    };
+    (
+        HashMap::from([(table_name, TABLE_ID)]),
+        HashMap::from([(TABLE_ID, table_schema)]),
+    )
+}
+
+fn convert(change: &str) -> Result<CdcEvent, CdcEventConversionError> {
+    let (table_ids, table_schemas) = tables();
+    Wal2JsonConverter::try_from(change.as_bytes(), &table_ids, &table_schemas)
+}
+
+#[test]
+fn test_wal2json_transaction_is_converted() {
+    // Timestamps are in microseconds since 2000-01-01 00:00:00 UTC
+    match convert(
+        r#"{"action":"B","xid":731,"timestamp":"2000-01-01 02:00:01.5+02","lsn":"0/16B2F88","nextlsn":"0/16B3000"}"#,
+    ) {
+        Ok(CdcEvent::Begin(begin_body)) => {
+            assert_eq!(begin_body.xid(), 731);
+            assert_eq!(begin_body.timestamp(), 1_500_000);
+        }
+        event => panic!("unexpected event {event:?}"),
+    }
+
+    match convert(
+        r#"{"action":"C","xid":731,"timestamp":"2000-01-01 00:00:02+00","lsn":"0/16B2F88","nextlsn":"0/16B3000"}"#,
+    ) {
+        Ok(CdcEvent::Commit(commit_body)) => {
+            assert_eq!(commit_body.commit_lsn(), 0x16B2F88);
+            assert_eq!(commit_body.end_lsn(), 0x16B3000);
+            assert_eq!(commit_body.timestamp(), 2_000_000);
+        }
+        event => panic!("unexpected event {event:?}"),
+    }
+}
+
+#[test]
+fn test_wal2json_transaction_without_timestamp_fails() {
+    let result = convert(r#"{"action":"C","xid":731,"lsn":"0/16B2F88","nextlsn":"0/16B3000"}"#);
+    assert!(matches!(
+        result,
+        Err(CdcEventConversionError::InvalidWal2Json(_))
+    ));
+}
+
+#[test]
+fn test_wal2json_changes_are_converted() {
+    match convert(
+        r#"{"action":"I","schema":"public","table":"wal2json","columns":[
+            {"name":"id","type":"integer","value":1},
+            {"name":"name","type":"text","value":"a"},
+            {"name":"active","type":"boolean","value":true}]}"#,
+    ) {
+        Ok(CdcEvent::Insert((TABLE_ID, row, None))) => assert!(matches!(
+            &row.values[..],
+            [Cell::I32(1), Cell::String(name), Cell::Bool(true)] if name == "a"
+        )),
+        event => panic!("unexpected event {event:?}"),
+    }
+
+    match convert(
+        r#"{"action":"U","schema":"public","table":"wal2json","columns":[
+            {"name":"id","type":"integer","value":1},
+            {"name":"name","type":"text","value":null},
+            {"name":"active","type":"boolean","value":false}],
+            "identity":[{"name":"id","type":"integer","value":1}]}"#,
+    ) {
+        Ok(CdcEvent::Update((TABLE_ID, Some(old_row), new_row, None))) => {
+            assert!(matches!(
+                &old_row.values[..],
+                [Cell::I32(1), Cell::Null, Cell::Null]
+            ));
+            assert!(matches!(
+                &new_row.values[..],
+                [Cell::I32(1), Cell::Null, Cell::Bool(false)]
+            ));
+        }
+        event => panic!("unexpected event {event:?}"),
+    }
+
+    match convert(
+        r#"{"action":"D","schema":"public","table":"wal2json",
+            "identity":[{"name":"id","type":"integer","value":1}]}"#,
+    ) {
+        Ok(CdcEvent::Delete((TABLE_ID, row, None))) => {
+            assert!(matches!(
+                &row.values[..],
+                [Cell::I32(1), Cell::Null, Cell::Null]
+            ))
+        }
+        event => panic!("unexpected event {event:?}"),
+    }
+}
+
+#[test]
+fn test_wal2json_change_to_unknown_table_fails() {
+    let result = convert(
+        r#"{"action":"I","schema":"public","table":"other","columns":[
+            {"name":"id","type":"integer","value":1}]}"#,
+    );
+    assert!(matches!(
+        result,
+        Err(CdcEventConversionError::UnknownTable(table_name)) if table_name.name == "other"
+    ));
+}
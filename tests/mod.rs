@@ -1,2 +1,6 @@
 mod clients;
 mod common;
+mod conversions;
+mod pipeline;
+mod sinks;
+mod sources;
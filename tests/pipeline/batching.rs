@@ -0,0 +1,52 @@
+use std::time::Duration;
+
+use futures::{stream, StreamExt};
+use pg_replicate::{
+    conversions::{table_row::TableRow, Cell},
+    pipeline::batching::{stream::BatchTimeoutStream, BatchConfig},
+};
+
+fn rows(count: usize, value_len: usize) -> Vec<TableRow> {
+    (0..count)
+        .map(|_| TableRow::new(vec![Cell::String("x".repeat(value_len))]))
+        .collect()
+}
+
+async fn batch_sizes(rows: Vec<TableRow>, batch_config: BatchConfig) -> Vec<usize> {
+    BatchTimeoutStream::new(stream::iter(rows), batch_config)
+        .map(|batch| batch.len())
+        .collect()
+        .await
+}
+
+#[tokio::test]
+async fn test_batches_are_bounded_by_count() {
+    let batch_config = BatchConfig::new(4, Duration::from_secs(1));
+    assert_eq!(
+        batch_sizes(rows(10, 1000), batch_config).await,
+        vec![4, 4, 2]
+    );
+}
+
+#[tokio::test]
+async fn test_batches_are_bounded_by_bytes() {
+    let mut batch_config = BatchConfig::new(100, Duration::from_secs(1));
+    batch_config.set_max_batch_bytes(3000);
+    assert_eq!(
+        batch_sizes(rows(10, 1000), batch_config.clone()).await,
+        vec![3, 3, 3, 1]
+    );
+
+    // Small rows are still bounded by count
+    let mut batch_config = BatchConfig::new(4, Duration::from_secs(1));
+    batch_config.set_max_batch_bytes(3000);
+    assert_eq!(batch_sizes(rows(10, 1), batch_config).await, vec![4, 4, 2]);
+
+    // A row larger than the limit makes a batch of its own
+    let mut batch_config = BatchConfig::new(100, Duration::from_secs(1));
+    batch_config.set_max_batch_bytes(100);
+    assert_eq!(
+        batch_sizes(rows(3, 1000), batch_config).await,
+        vec![1, 1, 1]
+    );
+}
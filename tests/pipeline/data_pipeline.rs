@@ -0,0 +1,1820 @@
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use async_trait::async_trait;
+use pg_replicate::{
+    conversions::{
+        cdc_event::{CdcEvent, UndecodableRow},
+        table_row::TableRow,
+        Cell,
+    },
+    pipeline::{
+        batching::{
+            data_pipeline::{BatchDataPipeline, ConsumeOutcome},
+            BatchConfig,
+        },
+        circuit_breaker::{PipelineState, SinkCircuitBreaker, SinkCircuitBreakerError},
+        dead_letter::{DeadLetterQueue, DeadLetterQueueError},
+        sinks::{
+            postgres::{PostgresSink, PostgresSinkError},
+            BatchSink, SinkError,
+        },
+        sources::{
+            backfill::SnapshotBackfill,
+            postgres::{PostgresSource, TableNamesFrom},
+            Source,
+        },
+        stats::{ApplyLagStats, BackfillStats, TableChangeStats},
+        transforms::TableMappings,
+        PipelineAction, PipelineError, PipelineResumptionState,
+    },
+    table::{TableId, TableName, TableSchema},
+};
+use thiserror::Error;
+use tokio_postgres::types::{PgLsn, Type};
+
+use crate::{
+    clients::create_replication_client,
+    common::{
+        postgres_utils::{create_postgres_client, drop_replication_slot, TestTable},
+        POSTGRES_DBNAME, POSTGRES_HOST, POSTGRES_PASSWORD, POSTGRES_PORT, POSTGRES_USER,
+    },
+};
+
+#[derive(Debug, Error)]
+enum MemorySinkError {
+    #[error("simulated crash")]
+    Crashed,
+
+    #[error("all expected rows received")]
+    Done,
+}
+
+impl SinkError for MemorySinkError {}
+
+/// State which survives a simulated crash, like a sink's durable storage would
+#[derive(Default)]
+struct DurableState {
+    rows: BTreeMap<i32, String>,
+    copied_tables: HashSet<TableId>,
+    last_lsn: u64,
+    backfill_cursors: HashMap<TableId, Vec<String>>,
+    truncations: usize,
+    slot_name: Option<String>,
+}
+
+/// An in-memory sink which applies a transaction's changes only when its
+/// commit is seen and which can simulate a crash after a number of events
+struct MemorySink {
+    state: Arc<Mutex<DurableState>>,
+    pending_rows: Vec<(i32, String)>,
+    events_until_crash: Option<usize>,
+    chunks_until_crash: Option<usize>,
+    /// Number of writes of table rows to fail before they succeed
+    failing_row_writes: usize,
+    expected_rows: usize,
+}
+
+impl MemorySink {
+    fn new(
+        state: Arc<Mutex<DurableState>>,
+        events_until_crash: Option<usize>,
+        expected_rows: usize,
+    ) -> Self {
+        MemorySink {
+            state,
+            pending_rows: vec![],
+            events_until_crash,
+            chunks_until_crash: None,
+            failing_row_writes: 0,
+            expected_rows,
+        }
+    }
+
+    fn row_to_entry(row: TableRow) -> (i32, String) {
+        match &row.values[..] {
+            [Cell::I32(id), Cell::String(data)] => (*id, data.clone()),
+            values => panic!("unexpected row {values:?}"),
+        }
+    }
+}
+
+#[async_trait]
+impl BatchSink for MemorySink {
+    type Error = MemorySinkError;
+
+    async fn get_resumption_state(&mut self) -> Result<PipelineResumptionState, Self::Error> {
+        let state = self.state.lock().unwrap();
+        Ok(PipelineResumptionState {
+            copied_tables: state.copied_tables.clone(),
+            last_lsn: PgLsn::from(state.last_lsn),
+            backfill_cursors: state.backfill_cursors.clone(),
+            slot_name: state.slot_name.clone(),
+        })
+    }
+
+    async fn write_table_schemas(
+        &mut self,
+        _table_schemas: HashMap<TableId, TableSchema>,
+    ) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
+    async fn write_table_rows(
+        &mut self,
+        rows: Vec<TableRow>,
+        _table_id: TableId,
+    ) -> Result<(), Self::Error> {
+        if self.failing_row_writes > 0 {
+            self.failing_row_writes -= 1;
+            return Err(MemorySinkError::Crashed);
+        }
+        let mut state = self.state.lock().unwrap();
+        for row in rows {
+            let (id, data) = Self::row_to_entry(row);
+            state.rows.insert(id, data);
+        }
+        Ok(())
+    }
+
+    async fn write_table_rows_with_cursor(
+        &mut self,
+        rows: Vec<TableRow>,
+        table_id: TableId,
+        cursor: Vec<String>,
+    ) -> Result<(), Self::Error> {
+        if let Some(chunks_until_crash) = self.chunks_until_crash.as_mut() {
+            if *chunks_until_crash == 0 {
+                return Err(MemorySinkError::Crashed);
+            }
+            *chunks_until_crash -= 1;
+        }
+        self.write_table_rows(rows, table_id).await?;
+        let mut state = self.state.lock().unwrap();
+        state.backfill_cursors.insert(table_id, cursor);
+        Ok(())
+    }
+
+    async fn write_cdc_events(&mut self, events: Vec<CdcEvent>) -> Result<PgLsn, Self::Error> {
+        let mut state = self.state.lock().unwrap();
+        for event in events {
+            if let Some(events_until_crash) = self.events_until_crash.as_mut() {
+                if *events_until_crash == 0 {
+                    // Everything not yet committed is lost in the crash
+                    self.pending_rows.clear();
+                    return Err(MemorySinkError::Crashed);
+                }
+                *events_until_crash -= 1;
+            }
+            match event {
+                CdcEvent::Insert((_, row, _)) => self.pending_rows.push(Self::row_to_entry(row)),
+                CdcEvent::Commit(commit_body) => {
+                    for (id, data) in self.pending_rows.drain(..) {
+                        state.rows.insert(id, data);
+                    }
+                    state.last_lsn = commit_body.end_lsn();
+                }
+                _ => {}
+            }
+        }
+        if state.rows.len() >= self.expected_rows {
+            return Err(MemorySinkError::Done);
+        }
+        Ok(PgLsn::from(state.last_lsn))
+    }
+
+    async fn table_copied(&mut self, table_id: TableId) -> Result<(), Self::Error> {
+        let mut state = self.state.lock().unwrap();
+        state.copied_tables.insert(table_id);
+        state.backfill_cursors.remove(&table_id);
+        Ok(())
+    }
+
+    async fn truncate_table(&mut self, table_id: TableId) -> Result<(), Self::Error> {
+        let mut state = self.state.lock().unwrap();
+        state.rows.clear();
+        state.copied_tables.remove(&table_id);
+        state.backfill_cursors.remove(&table_id);
+        state.truncations += 1;
+        Ok(())
+    }
+
+    async fn write_slot_name(&mut self, slot_name: &str) -> Result<(), Self::Error> {
+        self.state.lock().unwrap().slot_name = Some(slot_name.to_string());
+        Ok(())
+    }
+
+    fn supports_retries(&self) -> bool {
+        true
+    }
+}
+
+/// A [`PostgresSink`] which starts without resumption state, as the state it keeps
+/// in the database is shared by all tests writing to it
+struct FreshPostgresSink(PostgresSink);
+
+impl FreshPostgresSink {
+    async fn new() -> Result<Self, PostgresSinkError> {
+        Ok(FreshPostgresSink(
+            PostgresSink::new(create_postgres_client().await).await?,
+        ))
+    }
+}
+
+#[async_trait]
+impl BatchSink for FreshPostgresSink {
+    type Error = PostgresSinkError;
+
+    async fn get_resumption_state(&mut self) -> Result<PipelineResumptionState, Self::Error> {
+        Ok(PipelineResumptionState {
+            copied_tables: HashSet::new(),
+            last_lsn: PgLsn::from(0),
+            backfill_cursors: HashMap::new(),
+            slot_name: None,
+        })
+    }
+
+    async fn write_table_schemas(
+        &mut self,
+        table_schemas: HashMap<TableId, TableSchema>,
+    ) -> Result<(), Self::Error> {
+        self.0.write_table_schemas(table_schemas).await
+    }
+
+    async fn write_table_rows(
+        &mut self,
+        rows: Vec<TableRow>,
+        table_id: TableId,
+    ) -> Result<(), Self::Error> {
+        self.0.write_table_rows(rows, table_id).await
+    }
+
+    async fn write_cdc_events(&mut self, events: Vec<CdcEvent>) -> Result<PgLsn, Self::Error> {
+        self.0.write_cdc_events(events).await
+    }
+
+    async fn table_copied(&mut self, _table_id: TableId) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
+    async fn truncate_table(&mut self, table_id: TableId) -> Result<(), Self::Error> {
+        self.0.truncate_table(table_id).await
+    }
+}
+
+/// The `(id, data)` rows of a table written by a [`PostgresSink`], ordered by id
+async fn sink_rows(
+    client: &tokio_postgres::Client,
+    table_name: &str,
+) -> Result<Vec<(i32, String)>, anyhow::Error> {
+    let rows = client
+        .query(
+            &format!("SELECT id, data FROM {table_name} ORDER BY id"),
+            &[],
+        )
+        .await?;
+    Ok(rows.iter().map(|row| (row.get(0), row.get(1))).collect())
+}
+
+async fn create_source(publication: &str, slot_name: &str) -> PostgresSource {
+    PostgresSource::new(
+        POSTGRES_HOST,
+        POSTGRES_PORT,
+        POSTGRES_DBNAME,
+        POSTGRES_USER,
+        Some(POSTGRES_PASSWORD.to_string()),
+        Some(slot_name.to_string()),
+        TableNamesFrom::Publication(publication.to_string()),
+    )
+    .await
+    .expect("failed to create postgres source")
+}
+
+#[tokio::test]
+async fn test_resume_after_crash_loses_no_committed_rows() -> Result<(), anyhow::Error> {
+    let table_name = "test_at_least_once";
+    let publication = "test_at_least_once_pub";
+    let slot_name = "test_at_least_once_slot";
+    let backfilled_rows = 10;
+    let streamed_rows = 20;
+    let expected_rows = backfilled_rows + streamed_rows;
+
+    let test_table = TestTable::new(
+        table_name,
+        &format!("CREATE TABLE {table_name} (id INT PRIMARY KEY, data TEXT NOT NULL)"),
+    )
+    .await;
+    let client = &test_table.client;
+    client
+        .simple_query(&format!(
+            "DROP PUBLICATION IF EXISTS {publication};
+            CREATE PUBLICATION {publication} FOR TABLE {table_name};
+            SELECT pg_drop_replication_slot(slot_name) FROM pg_replication_slots WHERE slot_name = '{slot_name}';
+            INSERT INTO {table_name} SELECT i, 'row ' || i FROM generate_series(1, {backfilled_rows}) i;"
+        ))
+        .await?;
+
+    let batch_config = BatchConfig::new(5, Duration::from_millis(100));
+    let state = Arc::new(Mutex::new(DurableState::default()));
+
+    // The slot is created here, so rows inserted afterwards only arrive through CDC
+    let source = create_source(publication, slot_name).await;
+    for i in (backfilled_rows + 1)..=expected_rows {
+        client
+            .simple_query(&format!("INSERT INTO {table_name} VALUES ({i}, 'row {i}')"))
+            .await?;
+    }
+
+    // Crash in the middle of the stream, before all transactions are committed to the sink
+    let sink = MemorySink::new(state.clone(), Some(15), expected_rows);
+    let mut pipeline =
+        BatchDataPipeline::new(source, sink, PipelineAction::Both, batch_config.clone());
+    let result = pipeline.start().await;
+    assert!(matches!(
+        result,
+        Err(PipelineError::Sink(MemorySinkError::Crashed))
+    ));
+    drop(pipeline);
+
+    let lsn_after_crash = state.lock().unwrap().last_lsn;
+    assert!(state.lock().unwrap().rows.len() < expected_rows);
+
+    // Restart from the persisted resumption state
+    let source = create_source(publication, slot_name).await;
+    let sink = MemorySink::new(state.clone(), None, expected_rows);
+    let mut pipeline = BatchDataPipeline::new(source, sink, PipelineAction::Both, batch_config);
+    let result = pipeline.start().await;
+    assert!(matches!(
+        result,
+        Err(PipelineError::Sink(MemorySinkError::Done))
+    ));
+    drop(pipeline);
+
+    let last_lsn = {
+        let state = state.lock().unwrap();
+        let expected: BTreeMap<i32, String> = (1..=expected_rows as i32)
+            .map(|i| (i, format!("row {i}")))
+            .collect();
+        assert_eq!(state.rows, expected);
+        state.last_lsn
+    };
+    assert!(last_lsn >= lsn_after_crash);
+
+    // The slot must never be confirmed past what the sink has durably committed
+    let confirmed_flush_lsn = client
+        .query_one(
+            "SELECT confirmed_flush_lsn::text FROM pg_replication_slots WHERE slot_name = $1",
+            &[&slot_name],
+        )
+        .await?
+        .get::<_, String>(0)
+        .parse::<PgLsn>()
+        .map_err(|_| anyhow::anyhow!("invalid confirmed_flush_lsn"))?;
+    assert!(u64::from(confirmed_flush_lsn) <= last_lsn);
+
+    drop_replication_slot(client, slot_name).await;
+    client
+        .simple_query(&format!("DROP PUBLICATION IF EXISTS {publication}"))
+        .await?;
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_reset_resyncs_from_a_new_slot() -> Result<(), anyhow::Error> {
+    let table_name = "test_reset";
+    let publication = "test_reset_pub";
+    let slot_name = "test_reset_slot";
+
+    let test_table = TestTable::new(
+        table_name,
+        &format!("CREATE TABLE {table_name} (id INT PRIMARY KEY, data TEXT NOT NULL)"),
+    )
+    .await;
+    let client = &test_table.client;
+    client
+        .simple_query(&format!(
+            "DROP PUBLICATION IF EXISTS {publication};
+            CREATE PUBLICATION {publication} FOR TABLE {table_name};
+            INSERT INTO {table_name} SELECT i, 'row ' || i FROM generate_series(1, 10) i;"
+        ))
+        .await?;
+    drop_replication_slot(client, slot_name).await;
+
+    let source = create_source(publication, slot_name).await;
+    let old_slot_lsn = confirmed_flush_lsn(client, slot_name).await?;
+    let table_id = *source
+        .get_table_schemas()
+        .keys()
+        .next()
+        .expect("missing table");
+
+    // A sink with rows which don't exist anymore and the table marked as copied
+    let state = Arc::new(Mutex::new(DurableState::default()));
+    {
+        let mut state = state.lock().unwrap();
+        state.rows.insert(100, "stale".to_string());
+        state.copied_tables.insert(table_id);
+    }
+    let sink = MemorySink::new(state.clone(), None, 11);
+    let batch_config = BatchConfig::new(5, Duration::from_millis(100));
+    let mut pipeline = BatchDataPipeline::new(source, sink, PipelineAction::Both, batch_config);
+    let pipeline = tokio::spawn(async move { pipeline.reset().await });
+
+    // Insert a row to stream once the table has been copied again
+    tokio::time::timeout(Duration::from_secs(5), async {
+        loop {
+            {
+                let state = state.lock().unwrap();
+                if !state.rows.contains_key(&100) && state.copied_tables.contains(&table_id) {
+                    break;
+                }
+            }
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+    })
+    .await?;
+    client
+        .simple_query(&format!("INSERT INTO {table_name} VALUES (11, 'row 11')"))
+        .await?;
+    let result = pipeline.await?;
+    assert!(matches!(
+        result,
+        Err(PipelineError::Sink(MemorySinkError::Done))
+    ));
+
+    let expected: BTreeMap<i32, String> = (1..=11).map(|i| (i, format!("row {i}"))).collect();
+    assert_eq!(state.lock().unwrap().rows, expected);
+    assert!(state.lock().unwrap().copied_tables.contains(&table_id));
+    assert!(confirmed_flush_lsn(client, slot_name).await? >= old_slot_lsn);
+
+    drop_replication_slot(client, slot_name).await;
+    client
+        .simple_query(&format!("DROP PUBLICATION IF EXISTS {publication}"))
+        .await?;
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_keyset_backfill_resumes_after_last_copied_key() -> Result<(), anyhow::Error> {
+    let table_name = "test_keyset_backfill";
+    let publication = "test_keyset_backfill_pub";
+    let slot_name = "test_keyset_backfill_slot";
+    let rows = 25;
+
+    let test_table = TestTable::new(
+        table_name,
+        &format!("CREATE TABLE {table_name} (id INT PRIMARY KEY, data TEXT NOT NULL)"),
+    )
+    .await;
+    let client = &test_table.client;
+    client
+        .simple_query(&format!(
+            "DROP PUBLICATION IF EXISTS {publication};
+            CREATE PUBLICATION {publication} FOR TABLE {table_name};
+            INSERT INTO {table_name} SELECT i, 'row ' || i FROM generate_series(1, {rows}) i;"
+        ))
+        .await?;
+    drop_replication_slot(client, slot_name).await;
+
+    let state = Arc::new(Mutex::new(DurableState::default()));
+    let batch_config = BatchConfig::new(5, Duration::from_millis(100));
+
+    // Crash after the first chunk of 10 rows has been written
+    let source = create_source(publication, slot_name).await;
+    let table_id = *source
+        .get_table_schemas()
+        .keys()
+        .next()
+        .expect("missing table");
+    let mut sink = MemorySink::new(state.clone(), None, rows);
+    sink.chunks_until_crash = Some(1);
+    let mut pipeline = BatchDataPipeline::new(
+        source,
+        sink,
+        PipelineAction::TableCopiesOnly,
+        batch_config.clone(),
+    );
+    pipeline.set_keyset_backfill(10);
+    let result = pipeline.start().await;
+    assert!(matches!(
+        result,
+        Err(PipelineError::Sink(MemorySinkError::Crashed))
+    ));
+    drop(pipeline);
+    {
+        let state = state.lock().unwrap();
+        assert_eq!(state.rows.len(), 10);
+        assert_eq!(state.backfill_cursors[&table_id], vec!["10".to_string()]);
+    }
+
+    // The restarted copy continues after the last copied key without truncating
+    let source = create_source(publication, slot_name).await;
+    let sink = MemorySink::new(state.clone(), None, rows);
+    let mut pipeline =
+        BatchDataPipeline::new(source, sink, PipelineAction::TableCopiesOnly, batch_config);
+    pipeline.set_keyset_backfill(10);
+    pipeline.start().await?;
+    drop(pipeline);
+
+    {
+        let state = state.lock().unwrap();
+        let expected: BTreeMap<i32, String> =
+            (1..=rows as i32).map(|i| (i, format!("row {i}"))).collect();
+        assert_eq!(state.rows, expected);
+        assert_eq!(state.truncations, 1);
+        assert!(state.copied_tables.contains(&table_id));
+        assert!(state.backfill_cursors.is_empty());
+    }
+
+    drop_replication_slot(client, slot_name).await;
+    client
+        .simple_query(&format!("DROP PUBLICATION IF EXISTS {publication}"))
+        .await?;
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_failed_table_copies_are_skipped() -> Result<(), anyhow::Error> {
+    let dropped_table = "test_skip_failed_dropped";
+    let table_name = "test_skip_failed";
+    let publication = "test_skip_failed_pub";
+    let slot_name = "test_skip_failed_slot";
+    let rows = 10;
+
+    // The dropped table is created first so that it is copied first
+    let dropped_test_table = TestTable::new(
+        dropped_table,
+        &format!("CREATE TABLE {dropped_table} (id INT PRIMARY KEY, data TEXT NOT NULL)"),
+    )
+    .await;
+    let test_table = TestTable::new(
+        table_name,
+        &format!("CREATE TABLE {table_name} (id INT PRIMARY KEY, data TEXT NOT NULL)"),
+    )
+    .await;
+    let client = &test_table.client;
+    client
+        .simple_query(&format!(
+            "DROP PUBLICATION IF EXISTS {publication};
+            CREATE PUBLICATION {publication} FOR TABLE {dropped_table}, {table_name};
+            INSERT INTO {table_name} SELECT i, 'row ' || i FROM generate_series(1, {rows}) i;"
+        ))
+        .await?;
+    drop_replication_slot(client, slot_name).await;
+
+    let source = create_source(publication, slot_name).await;
+    let dropped_table_id = *source
+        .get_table_schemas()
+        .iter()
+        .find(|(_, table_schema)| table_schema.table_name.name == dropped_table)
+        .expect("missing table")
+        .0;
+    client
+        .simple_query(&format!("DROP TABLE {dropped_table}"))
+        .await?;
+    drop(dropped_test_table);
+
+    let state = Arc::new(Mutex::new(DurableState::default()));
+    let sink = MemorySink::new(state.clone(), None, rows);
+    let batch_config = BatchConfig::new(5, Duration::from_millis(100));
+    let mut pipeline =
+        BatchDataPipeline::new(source, sink, PipelineAction::TableCopiesOnly, batch_config);
+    pipeline.set_skip_failed_tables(true);
+    pipeline.start().await?;
+
+    let failures = pipeline.table_copy_failures();
+    assert_eq!(failures.len(), 1);
+    assert_eq!(failures[0].table_id, dropped_table_id);
+    assert_eq!(failures[0].table_name.name, dropped_table);
+    drop(pipeline);
+
+    {
+        let state = state.lock().unwrap();
+        let expected: BTreeMap<i32, String> =
+            (1..=rows as i32).map(|i| (i, format!("row {i}"))).collect();
+        assert_eq!(state.rows, expected);
+        assert_eq!(state.copied_tables.len(), 1);
+        assert!(!state.copied_tables.contains(&dropped_table_id));
+    }
+
+    drop_replication_slot(client, slot_name).await;
+    client
+        .simple_query(&format!("DROP PUBLICATION IF EXISTS {publication}"))
+        .await?;
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_changes_of_skipped_tables_stay_skipped_after_restart() -> Result<(), anyhow::Error> {
+    let skipped_table = "test_skip_restart_skipped";
+    let table_name = "test_skip_restart";
+    let publication = "test_skip_restart_pub";
+    let slot_name = "test_skip_restart_slot";
+    let rows = 3;
+
+    let skipped_test_table = TestTable::new(
+        skipped_table,
+        &format!("CREATE TABLE {skipped_table} (id INT PRIMARY KEY, data TEXT NOT NULL)"),
+    )
+    .await;
+    let test_table = TestTable::new(
+        table_name,
+        &format!("CREATE TABLE {table_name} (id INT PRIMARY KEY, data TEXT NOT NULL)"),
+    )
+    .await;
+    let client = &test_table.client;
+    client
+        .simple_query(&format!(
+            "DROP PUBLICATION IF EXISTS {publication};
+            CREATE PUBLICATION {publication} FOR TABLE {skipped_table}, {table_name};"
+        ))
+        .await?;
+    drop_replication_slot(client, slot_name).await;
+
+    let source = create_source(publication, slot_name).await;
+    let table_id = *source
+        .get_table_schemas()
+        .iter()
+        .find(|(_, table_schema)| table_schema.table_name.name == table_name)
+        .expect("missing table")
+        .0;
+    // The skipped table's changes commit first, a sink applying them would see
+    // them before the other table's
+    client
+        .simple_query(&format!(
+            "INSERT INTO {skipped_table} SELECT i, 'skipped ' || i FROM generate_series(101, 103) i;
+            INSERT INTO {table_name} SELECT i, 'row ' || i FROM generate_series(1, {rows}) i;"
+        ))
+        .await?;
+
+    // As left by an earlier run which failed to copy the skipped table
+    let state = Arc::new(Mutex::new(DurableState {
+        copied_tables: HashSet::from([table_id]),
+        ..DurableState::default()
+    }));
+    let sink = MemorySink::new(state.clone(), None, rows);
+    let batch_config = BatchConfig::new(5, Duration::from_millis(100));
+    let mut pipeline = BatchDataPipeline::new(source, sink, PipelineAction::CdcOnly, batch_config);
+    pipeline.set_skip_failed_tables(true);
+    let result = pipeline.start().await;
+    assert!(matches!(
+        result,
+        Err(PipelineError::Sink(MemorySinkError::Done))
+    ));
+    drop(pipeline);
+
+    {
+        let state = state.lock().unwrap();
+        let expected: BTreeMap<i32, String> =
+            (1..=rows as i32).map(|i| (i, format!("row {i}"))).collect();
+        assert_eq!(state.rows, expected);
+    }
+
+    drop(skipped_test_table);
+    drop_replication_slot(client, slot_name).await;
+    client
+        .simple_query(&format!("DROP PUBLICATION IF EXISTS {publication}"))
+        .await?;
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_sink_circuit_breaker_retries_until_it_trips() -> Result<(), anyhow::Error> {
+    let table_name = "test_sink_circuit_breaker";
+    let publication = "test_sink_circuit_breaker_pub";
+    let slot_name = "test_sink_circuit_breaker_slot";
+
+    let test_table = TestTable::new(
+        table_name,
+        &format!("CREATE TABLE {table_name} (id INT PRIMARY KEY, data TEXT NOT NULL)"),
+    )
+    .await;
+    let client = &test_table.client;
+    client
+        .simple_query(&format!(
+            "DROP PUBLICATION IF EXISTS {publication};
+            CREATE PUBLICATION {publication} FOR TABLE {table_name};
+            INSERT INTO {table_name} SELECT i, 'row ' || i FROM generate_series(1, 10) i;"
+        ))
+        .await?;
+    drop_replication_slot(client, slot_name).await;
+
+    let batch_config = BatchConfig::new(100, Duration::from_millis(100));
+
+    // Fewer failures than the breaker allows are retried
+    let state = Arc::new(Mutex::new(DurableState::default()));
+    let breaker = Arc::new(SinkCircuitBreaker::new(
+        3,
+        Duration::from_secs(60),
+        Duration::from_millis(10),
+    )?);
+    let source = create_source(publication, slot_name).await;
+    let mut sink = MemorySink::new(state.clone(), None, 10);
+    sink.failing_row_writes = 2;
+    let mut pipeline = BatchDataPipeline::new(
+        source,
+        sink,
+        PipelineAction::TableCopiesOnly,
+        batch_config.clone(),
+    );
+    pipeline.set_sink_circuit_breaker(breaker.clone());
+    pipeline.start().await?;
+    drop(pipeline);
+    assert_eq!(state.lock().unwrap().rows.len(), 10);
+    assert_eq!(breaker.state(), PipelineState::Running);
+    assert_eq!(breaker.last_error().as_deref(), Some("simulated crash"));
+
+    // A sink which keeps failing trips it
+    let state = Arc::new(Mutex::new(DurableState::default()));
+    let source = create_source(publication, slot_name).await;
+    let mut sink = MemorySink::new(state.clone(), None, 10);
+    sink.failing_row_writes = usize::MAX;
+    let mut pipeline =
+        BatchDataPipeline::new(source, sink, PipelineAction::TableCopiesOnly, batch_config);
+    pipeline.set_sink_circuit_breaker(breaker.clone());
+    let result = pipeline.start().await;
+    assert!(matches!(
+        result,
+        Err(PipelineError::SinkCircuitOpen(
+            3,
+            _,
+            MemorySinkError::Crashed
+        ))
+    ));
+    drop(pipeline);
+    assert!(state.lock().unwrap().rows.is_empty());
+    assert_eq!(breaker.state(), PipelineState::Errored);
+
+    drop_replication_slot(client, slot_name).await;
+    client
+        .simple_query(&format!("DROP PUBLICATION IF EXISTS {publication}"))
+        .await?;
+
+    Ok(())
+}
+
+#[test]
+fn test_sink_circuit_breaker_rejects_retries_outlasting_its_window() {
+    // With the time the writes take, the first failure would leave the window
+    // before the third one is recorded
+    let result = SinkCircuitBreaker::new(3, Duration::from_secs(1), Duration::from_millis(500));
+    assert!(matches!(
+        result,
+        Err(SinkCircuitBreakerError::WindowTooShort(3, _, _))
+    ));
+}
+
+struct MemoryDeadLetterQueue {
+    lsns: Arc<Mutex<Vec<PgLsn>>>,
+}
+
+#[async_trait]
+impl DeadLetterQueue for MemoryDeadLetterQueue {
+    async fn write_dead_letter(
+        &mut self,
+        row: &UndecodableRow,
+    ) -> Result<(), DeadLetterQueueError> {
+        self.lsns.lock().unwrap().push(row.lsn);
+        Ok(())
+    }
+}
+
+#[tokio::test]
+async fn test_undecodable_events_go_to_dead_letter_queue() -> Result<(), anyhow::Error> {
+    let table_name = "test_dead_letter";
+    let publication = "test_dead_letter_pub";
+    let slot_name = "test_dead_letter_slot";
+
+    let test_table = TestTable::new(
+        table_name,
+        &format!("CREATE TABLE {table_name} (id INT PRIMARY KEY, data TEXT NOT NULL)"),
+    )
+    .await;
+    let client = &test_table.client;
+    client
+        .simple_query(&format!(
+            "DROP PUBLICATION IF EXISTS {publication};
+            CREATE PUBLICATION {publication} FOR TABLE {table_name};"
+        ))
+        .await?;
+    drop_replication_slot(client, slot_name).await;
+
+    // The source keeps the schema from before the column type change, so
+    // ids which aren't integers can't be decoded
+    let source = create_source(publication, slot_name).await;
+    client
+        .simple_query(&format!(
+            "ALTER TABLE {table_name} ALTER COLUMN id TYPE TEXT;
+            INSERT INTO {table_name} VALUES ('not an int', 'bad');
+            INSERT INTO {table_name} VALUES ('1', 'good');"
+        ))
+        .await?;
+
+    let state = Arc::new(Mutex::new(DurableState::default()));
+    let sink = MemorySink::new(state.clone(), None, 1);
+    let batch_config = BatchConfig::new(5, Duration::from_millis(100));
+    let mut pipeline = BatchDataPipeline::new(source, sink, PipelineAction::CdcOnly, batch_config);
+    let dead_letter_lsns = Arc::new(Mutex::new(vec![]));
+    pipeline.set_dead_letter_queue(
+        MemoryDeadLetterQueue {
+            lsns: dead_letter_lsns.clone(),
+        },
+        10,
+        Duration::from_secs(60),
+    );
+    let result = pipeline.start().await;
+    assert!(matches!(
+        result,
+        Err(PipelineError::Sink(MemorySinkError::Done))
+    ));
+    drop(pipeline);
+
+    assert_eq!(dead_letter_lsns.lock().unwrap().len(), 1);
+    let rows = state.lock().unwrap().rows.clone();
+    assert_eq!(rows, BTreeMap::from([(1, "good".to_string())]));
+
+    drop_replication_slot(client, slot_name).await;
+    client
+        .simple_query(&format!("DROP PUBLICATION IF EXISTS {publication}"))
+        .await?;
+
+    Ok(())
+}
+
+async fn confirmed_flush_lsn(
+    client: &tokio_postgres::Client,
+    slot_name: &str,
+) -> Result<u64, anyhow::Error> {
+    let lsn = client
+        .query_one(
+            "SELECT confirmed_flush_lsn::text FROM pg_replication_slots WHERE slot_name = $1",
+            &[&slot_name],
+        )
+        .await?
+        .get::<_, String>(0)
+        .parse::<PgLsn>()
+        .map_err(|_| anyhow::anyhow!("invalid confirmed_flush_lsn"))?;
+    Ok(lsn.into())
+}
+
+#[tokio::test]
+async fn test_status_update_sent_when_sink_commits() -> Result<(), anyhow::Error> {
+    let table_name = "test_status_update";
+    let publication = "test_status_update_pub";
+    let slot_name = "test_status_update_slot";
+
+    let test_table = TestTable::new(
+        table_name,
+        &format!("CREATE TABLE {table_name} (id INT PRIMARY KEY, data TEXT NOT NULL)"),
+    )
+    .await;
+    let client = &test_table.client;
+    client
+        .simple_query(&format!(
+            "DROP PUBLICATION IF EXISTS {publication};
+            CREATE PUBLICATION {publication} FOR TABLE {table_name};"
+        ))
+        .await?;
+    drop_replication_slot(client, slot_name).await;
+
+    let source = create_source(publication, slot_name).await;
+    let state = Arc::new(Mutex::new(DurableState::default()));
+    let sink = MemorySink::new(state.clone(), None, 2);
+    let batch_config = BatchConfig::new(5, Duration::from_millis(100));
+    let mut pipeline = BatchDataPipeline::new(source, sink, PipelineAction::CdcOnly, batch_config);
+    pipeline.set_status_update_interval(Duration::from_secs(60));
+    let pipeline = tokio::spawn(async move { pipeline.start().await });
+
+    client
+        .simple_query(&format!("INSERT INTO {table_name} VALUES (1, 'row 1')"))
+        .await?;
+
+    // Postgres only requests a reply after half of wal_sender_timeout, so the slot
+    // advancing right away means the pipeline acknowledged the commit on its own
+    tokio::time::timeout(Duration::from_secs(5), async {
+        loop {
+            let committed_lsn = state.lock().unwrap().last_lsn;
+            if committed_lsn > 0 && confirmed_flush_lsn(client, slot_name).await? >= committed_lsn {
+                return Ok::<_, anyhow::Error>(());
+            }
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+    })
+    .await??;
+
+    client
+        .simple_query(&format!("INSERT INTO {table_name} VALUES (2, 'row 2')"))
+        .await?;
+    let result = pipeline.await?;
+    assert!(matches!(
+        result,
+        Err(PipelineError::Sink(MemorySinkError::Done))
+    ));
+
+    drop_replication_slot(client, slot_name).await;
+    client
+        .simple_query(&format!("DROP PUBLICATION IF EXISTS {publication}"))
+        .await?;
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_backfill_stats_record_each_stage() -> Result<(), anyhow::Error> {
+    let table_name = "test_backfill_stats";
+    let publication = "test_backfill_stats_pub";
+    let slot_name = "test_backfill_stats_slot";
+    let rows = 100;
+
+    let test_table = TestTable::new(
+        table_name,
+        &format!("CREATE TABLE {table_name} (id INT PRIMARY KEY, data TEXT NOT NULL)"),
+    )
+    .await;
+    let client = &test_table.client;
+    client
+        .simple_query(&format!(
+            "DROP PUBLICATION IF EXISTS {publication};
+            CREATE PUBLICATION {publication} FOR TABLE {table_name};
+            SELECT pg_drop_replication_slot(slot_name) FROM pg_replication_slots WHERE slot_name = '{slot_name}';
+            INSERT INTO {table_name} SELECT i, 'row ' || i FROM generate_series(1, {rows}) i;"
+        ))
+        .await?;
+
+    let source = create_source(publication, slot_name).await;
+    let state = Arc::new(Mutex::new(DurableState::default()));
+    let sink = MemorySink::new(state.clone(), None, rows);
+    let batch_config = BatchConfig::new(10, Duration::from_millis(100));
+    let mut pipeline =
+        BatchDataPipeline::new(source, sink, PipelineAction::TableCopiesOnly, batch_config);
+    let stats = Arc::new(BackfillStats::new());
+    pipeline.set_backfill_stats(stats.clone());
+    pipeline.start().await?;
+    drop(pipeline);
+
+    assert_eq!(state.lock().unwrap().rows.len(), rows);
+    assert_eq!(stats.rows(), rows as u64);
+    assert!(stats.read_time() > Duration::ZERO);
+    assert!(stats.decode_time() > Duration::ZERO);
+    assert!(stats.write_time() > Duration::ZERO);
+
+    drop_replication_slot(client, slot_name).await;
+    client
+        .simple_query(&format!("DROP PUBLICATION IF EXISTS {publication}"))
+        .await?;
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_apply_lag_stats_record_lag_per_table() -> Result<(), anyhow::Error> {
+    let table_name = "test_apply_lag_stats";
+    let publication = "test_apply_lag_stats_pub";
+    let slot_name = "test_apply_lag_stats_slot";
+    let rows = 10;
+
+    let test_table = TestTable::new(
+        table_name,
+        &format!("CREATE TABLE {table_name} (id INT PRIMARY KEY, data TEXT NOT NULL)"),
+    )
+    .await;
+    let client = &test_table.client;
+    client
+        .simple_query(&format!(
+            "DROP PUBLICATION IF EXISTS {publication};
+            CREATE PUBLICATION {publication} FOR TABLE {table_name};
+            SELECT pg_drop_replication_slot(slot_name) FROM pg_replication_slots WHERE slot_name = '{slot_name}';"
+        ))
+        .await?;
+
+    let source = create_source(publication, slot_name).await;
+    let table_id = *source
+        .get_table_schemas()
+        .keys()
+        .next()
+        .expect("missing table");
+    for i in 1..=rows {
+        client
+            .simple_query(&format!("INSERT INTO {table_name} VALUES ({i}, 'row {i}')"))
+            .await?;
+    }
+    let committed = std::time::Instant::now();
+
+    let state = Arc::new(Mutex::new(DurableState::default()));
+    let sink = MemorySink::new(state.clone(), None, rows);
+    let batch_config = BatchConfig::new(5, Duration::from_millis(100));
+    let mut pipeline = BatchDataPipeline::new(source, sink, PipelineAction::CdcOnly, batch_config);
+    let stats = Arc::new(ApplyLagStats::new());
+    pipeline.set_apply_lag_stats(stats.clone());
+    let result = pipeline.start().await;
+    assert!(matches!(
+        result,
+        Err(PipelineError::Sink(MemorySinkError::Done))
+    ));
+    drop(pipeline);
+
+    // The transactions were written after they committed, but not after the test
+    // got here, give or take the clock skew between the test and Postgres
+    let lag = stats.lag().expect("no lag recorded");
+    assert!(lag <= committed.elapsed() + Duration::from_secs(5));
+    assert_eq!(stats.table_lag(table_id), Some(lag));
+    assert_eq!(stats.table_lag(table_id + 1), None);
+
+    drop_replication_slot(client, slot_name).await;
+    client
+        .simple_query(&format!("DROP PUBLICATION IF EXISTS {publication}"))
+        .await?;
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_table_change_stats_count_changes_per_table() -> Result<(), anyhow::Error> {
+    let table_name = "test_table_change_stats";
+    let publication = "test_table_change_stats_pub";
+    let slot_name = "test_table_change_stats_slot";
+
+    let test_table = TestTable::new(
+        table_name,
+        &format!("CREATE TABLE {table_name} (id INT PRIMARY KEY, data TEXT NOT NULL)"),
+    )
+    .await;
+    let client = &test_table.client;
+    client
+        .simple_query(&format!(
+            "DROP PUBLICATION IF EXISTS {publication};
+            CREATE PUBLICATION {publication} FOR TABLE {table_name};"
+        ))
+        .await?;
+    drop_replication_slot(client, slot_name).await;
+
+    let source = create_source(publication, slot_name).await;
+    let table_id = *source
+        .get_table_schemas()
+        .keys()
+        .next()
+        .expect("missing table");
+    client
+        .simple_query(&format!(
+            "INSERT INTO {table_name} SELECT i, 'row ' || i FROM generate_series(1, 3) i;
+            UPDATE {table_name} SET data = 'updated' WHERE id <= 2;
+            DELETE FROM {table_name} WHERE id = 3;"
+        ))
+        .await?;
+
+    let state = Arc::new(Mutex::new(DurableState::default()));
+    let sink = MemorySink::new(state.clone(), None, usize::MAX);
+    let batch_config = BatchConfig::new(5, Duration::from_millis(100));
+    let mut pipeline = BatchDataPipeline::new(source, sink, PipelineAction::CdcOnly, batch_config);
+    let stats = Arc::new(TableChangeStats::new());
+    pipeline.set_table_change_stats(stats.clone());
+    let outcome = pipeline
+        .consume_until(Instant::now() + Duration::from_secs(10))
+        .await?;
+    assert_eq!(outcome, ConsumeOutcome::CaughtUp);
+    drop(pipeline);
+
+    let snapshot = stats.snapshot();
+    assert_eq!(snapshot.len(), 1);
+    let changes = snapshot[&table_id];
+    assert_eq!(
+        (changes.inserts, changes.updates, changes.deletes),
+        (3, 2, 1)
+    );
+    // The statements ran in one transaction, the last the sink committed
+    assert_eq!(
+        changes.last_lsn,
+        PgLsn::from(state.lock().unwrap().last_lsn)
+    );
+
+    drop_replication_slot(client, slot_name).await;
+    client
+        .simple_query(&format!("DROP PUBLICATION IF EXISTS {publication}"))
+        .await?;
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_idle_flush_confirms_changes_outside_publication() -> Result<(), anyhow::Error> {
+    let table_name = "test_idle_flush";
+    let other_table_name = "test_idle_flush_other";
+    let publication = "test_idle_flush_pub";
+    let slot_name = "test_idle_flush_slot";
+
+    let test_table = TestTable::new(
+        table_name,
+        &format!("CREATE TABLE {table_name} (id INT PRIMARY KEY, data TEXT NOT NULL)"),
+    )
+    .await;
+    let _other_table = TestTable::new(
+        other_table_name,
+        &format!("CREATE TABLE {other_table_name} (id INT PRIMARY KEY)"),
+    )
+    .await;
+    let client = &test_table.client;
+    client
+        .simple_query(&format!(
+            "DROP PUBLICATION IF EXISTS {publication};
+            CREATE PUBLICATION {publication} FOR TABLE {table_name};"
+        ))
+        .await?;
+    drop_replication_slot(client, slot_name).await;
+
+    let source = create_source(publication, slot_name).await;
+    let state = Arc::new(Mutex::new(DurableState::default()));
+    let sink = MemorySink::new(state.clone(), None, 1);
+    let batch_config = BatchConfig::new(5, Duration::from_millis(100));
+    let mut pipeline = BatchDataPipeline::new(source, sink, PipelineAction::CdcOnly, batch_config);
+    pipeline.set_idle_flush_timeout(Duration::ZERO);
+    let pipeline = tokio::spawn(async move { pipeline.start().await });
+
+    client
+        .simple_query(&format!(
+            "INSERT INTO {other_table_name} SELECT generate_series(1, 10)"
+        ))
+        .await?;
+    let wal_lsn: u64 = client
+        .query_one("SELECT pg_current_wal_lsn()::text", &[])
+        .await?
+        .get::<_, String>(0)
+        .parse::<PgLsn>()
+        .map_err(|_| anyhow::anyhow!("invalid wal lsn"))?
+        .into();
+
+    // The sink never commits anything, but the slot still advances past the insert
+    tokio::time::timeout(Duration::from_secs(5), async {
+        while confirmed_flush_lsn(client, slot_name).await? < wal_lsn {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+        Ok::<_, anyhow::Error>(())
+    })
+    .await??;
+    assert_eq!(state.lock().unwrap().last_lsn, 0);
+
+    pipeline.abort();
+    let _ = pipeline.await;
+    drop_replication_slot(client, slot_name).await;
+    client
+        .simple_query(&format!("DROP PUBLICATION IF EXISTS {publication}"))
+        .await?;
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_unsupported_columns_are_listed() -> Result<(), anyhow::Error> {
+    let table_name = "test_unsupported_columns";
+    let publication = "test_unsupported_columns_pub";
+    let slot_name = "test_unsupported_columns_slot";
+
+    let test_table = TestTable::new(
+        table_name,
+        &format!(
+            "CREATE TABLE {table_name} (
+                id INT PRIMARY KEY, location POINT, data TEXT, search TSVECTOR
+            )"
+        ),
+    )
+    .await;
+    let client = &test_table.client;
+    client
+        .simple_query(&format!(
+            "DROP PUBLICATION IF EXISTS {publication};
+            CREATE PUBLICATION {publication} FOR TABLE {table_name};"
+        ))
+        .await?;
+    drop_replication_slot(client, slot_name).await;
+
+    let source = create_source(publication, slot_name).await;
+    let state = Arc::new(Mutex::new(DurableState::default()));
+    let sink = MemorySink::new(state, None, usize::MAX);
+    let batch_config = BatchConfig::new(5, Duration::from_millis(100));
+    let pipeline = BatchDataPipeline::new(source, sink, PipelineAction::Both, batch_config);
+
+    let unsupported = pipeline.unsupported_columns();
+    let columns: Vec<(&str, &str, u32)> = unsupported
+        .iter()
+        .map(|column| {
+            (
+                column.table_name.name.as_str(),
+                column.column.as_str(),
+                column.oid,
+            )
+        })
+        .collect();
+    assert_eq!(
+        columns,
+        vec![
+            (table_name, "location", Type::POINT.oid()),
+            (table_name, "search", Type::TS_VECTOR.oid()),
+        ]
+    );
+    assert!(unsupported[0].reason.contains("point"));
+    drop(pipeline);
+
+    drop_replication_slot(client, slot_name).await;
+    client
+        .simple_query(&format!("DROP PUBLICATION IF EXISTS {publication}"))
+        .await?;
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_orphaned_slot_is_dropped() -> Result<(), anyhow::Error> {
+    let table_name = "test_orphaned_slot";
+    let publication = "test_orphaned_slot_pub";
+    let old_slot_name = "test_orphaned_slot_old";
+    let new_slot_name = "test_orphaned_slot_new";
+
+    let test_table = TestTable::new(
+        table_name,
+        &format!("CREATE TABLE {table_name} (id INT PRIMARY KEY, data TEXT NOT NULL)"),
+    )
+    .await;
+    let client = &test_table.client;
+    client
+        .simple_query(&format!(
+            "DROP PUBLICATION IF EXISTS {publication};
+            CREATE PUBLICATION {publication} FOR TABLE {table_name};"
+        ))
+        .await?;
+    drop_replication_slot(client, old_slot_name).await;
+    drop_replication_slot(client, new_slot_name).await;
+
+    let state = Arc::new(Mutex::new(DurableState::default()));
+    let batch_config = BatchConfig::new(5, Duration::from_millis(100));
+    let slot_exists = |slot_name: &'static str| async move {
+        let row = client
+            .query_opt(
+                "SELECT 1 FROM pg_replication_slots WHERE slot_name = $1",
+                &[&slot_name],
+            )
+            .await?;
+        Ok::<_, anyhow::Error>(row.is_some())
+    };
+
+    let source = create_source(publication, old_slot_name).await;
+    let sink = MemorySink::new(state.clone(), None, usize::MAX);
+    let mut pipeline =
+        BatchDataPipeline::new(source, sink, PipelineAction::CdcOnly, batch_config.clone());
+    pipeline
+        .consume_until(Instant::now() + Duration::from_secs(10))
+        .await?;
+    drop(pipeline);
+    assert_eq!(
+        state.lock().unwrap().slot_name.as_deref(),
+        Some(old_slot_name)
+    );
+
+    // The pipeline's slot name changed, e.g. by a config edit
+    let source = create_source(publication, new_slot_name).await;
+    let sink = MemorySink::new(state.clone(), None, usize::MAX);
+    let mut pipeline = BatchDataPipeline::new(source, sink, PipelineAction::CdcOnly, batch_config);
+    pipeline.set_drop_orphaned_slot(true);
+    pipeline
+        .consume_until(Instant::now() + Duration::from_secs(10))
+        .await?;
+    drop(pipeline);
+    assert_eq!(
+        state.lock().unwrap().slot_name.as_deref(),
+        Some(new_slot_name)
+    );
+    assert!(!slot_exists(old_slot_name).await?);
+    assert!(slot_exists(new_slot_name).await?);
+
+    drop_replication_slot(client, new_slot_name).await;
+    client
+        .simple_query(&format!("DROP PUBLICATION IF EXISTS {publication}"))
+        .await?;
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_slot_name_change_after_committed_changes_fails() -> Result<(), anyhow::Error> {
+    let table_name = "test_slot_name_change";
+    let publication = "test_slot_name_change_pub";
+    let old_slot_name = "test_slot_name_change_old";
+    let new_slot_name = "test_slot_name_change_new";
+
+    let test_table = TestTable::new(
+        table_name,
+        &format!("CREATE TABLE {table_name} (id INT PRIMARY KEY, data TEXT NOT NULL)"),
+    )
+    .await;
+    let client = &test_table.client;
+    client
+        .simple_query(&format!(
+            "DROP PUBLICATION IF EXISTS {publication};
+            CREATE PUBLICATION {publication} FOR TABLE {table_name};"
+        ))
+        .await?;
+    drop_replication_slot(client, old_slot_name).await;
+    drop_replication_slot(client, new_slot_name).await;
+
+    let state = Arc::new(Mutex::new(DurableState::default()));
+    let batch_config = BatchConfig::new(5, Duration::from_millis(100));
+    let slot_exists = |slot_name: &'static str| async move {
+        let row = client
+            .query_opt(
+                "SELECT 1 FROM pg_replication_slots WHERE slot_name = $1",
+                &[&slot_name],
+            )
+            .await?;
+        Ok::<_, anyhow::Error>(row.is_some())
+    };
+
+    let source = create_source(publication, old_slot_name).await;
+    client
+        .simple_query(&format!(
+            "INSERT INTO {table_name} (id, data) VALUES (1, 'streamed')"
+        ))
+        .await?;
+    let sink = MemorySink::new(state.clone(), None, usize::MAX);
+    let mut pipeline =
+        BatchDataPipeline::new(source, sink, PipelineAction::CdcOnly, batch_config.clone());
+    pipeline
+        .consume_until(Instant::now() + Duration::from_secs(10))
+        .await?;
+    drop(pipeline);
+    assert!(state.lock().unwrap().last_lsn > 0);
+
+    // The new slot starts after the sink's position, so the pipeline must not
+    // switch to it or drop the old slot
+    let source = create_source(publication, new_slot_name).await;
+    let sink = MemorySink::new(state.clone(), None, usize::MAX);
+    let mut pipeline =
+        BatchDataPipeline::new(source, sink, PipelineAction::CdcOnly, batch_config.clone());
+    pipeline.set_drop_orphaned_slot(true);
+    let result = pipeline
+        .consume_until(Instant::now() + Duration::from_secs(10))
+        .await;
+    drop(pipeline);
+    assert!(matches!(result, Err(PipelineError::SlotNameChanged(..))));
+    assert_eq!(
+        state.lock().unwrap().slot_name.as_deref(),
+        Some(old_slot_name)
+    );
+    assert!(slot_exists(old_slot_name).await?);
+    assert!(!slot_exists(new_slot_name).await?);
+
+    // A reset re-syncs the sink from the new slot
+    let source = create_source(publication, new_slot_name).await;
+    let sink = MemorySink::new(state.clone(), None, usize::MAX);
+    let mut pipeline =
+        BatchDataPipeline::new(source, sink, PipelineAction::TableCopiesOnly, batch_config);
+    pipeline.set_drop_orphaned_slot(true);
+    pipeline.reset().await?;
+    drop(pipeline);
+    {
+        let state = state.lock().unwrap();
+        assert_eq!(state.slot_name.as_deref(), Some(new_slot_name));
+        assert_eq!(state.rows, BTreeMap::from([(1, "streamed".to_string())]));
+    }
+    assert!(!slot_exists(old_slot_name).await?);
+    assert!(slot_exists(new_slot_name).await?);
+
+    drop_replication_slot(client, new_slot_name).await;
+    client
+        .simple_query(&format!("DROP PUBLICATION IF EXISTS {publication}"))
+        .await?;
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_table_added_to_publication_is_backfilled() -> Result<(), anyhow::Error> {
+    let table_name = "test_pipeline_add_table";
+    let added_table_name = "test_pipeline_add_table_added";
+    let publication = "test_pipeline_add_table_pub";
+    let slot_name = "test_pipeline_add_table_slot";
+
+    let test_table = TestTable::new(
+        table_name,
+        &format!("CREATE TABLE {table_name} (id INT PRIMARY KEY, data TEXT NOT NULL)"),
+    )
+    .await;
+    let _added_table = TestTable::new(
+        added_table_name,
+        &format!("CREATE TABLE {added_table_name} (id INT PRIMARY KEY, data TEXT NOT NULL)"),
+    )
+    .await;
+    let client = &test_table.client;
+    client
+        .simple_query(&format!(
+            "DROP PUBLICATION IF EXISTS {publication};
+            CREATE PUBLICATION {publication} FOR TABLE {table_name};"
+        ))
+        .await?;
+    drop_replication_slot(client, slot_name).await;
+
+    let state = Arc::new(Mutex::new(DurableState::default()));
+    let batch_config = BatchConfig::new(100, Duration::from_millis(100));
+    let source = create_source(publication, slot_name).await;
+
+    // The first row is only in the backfill, the second one both in the backfill and
+    // in the stream
+    client
+        .simple_query(&format!(
+            "INSERT INTO {added_table_name} VALUES (101, 'before');
+            ALTER PUBLICATION {publication} ADD TABLE {added_table_name};
+            INSERT INTO {added_table_name} VALUES (102, 'added');"
+        ))
+        .await?;
+
+    let sink = MemorySink::new(state.clone(), None, usize::MAX);
+    let mut pipeline = BatchDataPipeline::new(source, sink, PipelineAction::CdcOnly, batch_config);
+    pipeline.set_added_table_backfill(SnapshotBackfill::new(create_replication_client().await));
+    pipeline
+        .consume_until(Instant::now() + Duration::from_secs(10))
+        .await?;
+    drop(pipeline);
+
+    let state = state.lock().unwrap();
+    assert_eq!(
+        state.rows,
+        BTreeMap::from([(101, "before".to_string()), (102, "added".to_string())])
+    );
+    assert_eq!(state.copied_tables.len(), 1);
+    drop(state);
+
+    drop_replication_slot(client, slot_name).await;
+    client
+        .simple_query(&format!("DROP PUBLICATION IF EXISTS {publication}"))
+        .await?;
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_existing_tables_keep_streaming_after_a_table_is_added() -> Result<(), anyhow::Error> {
+    let existing_table = "test_add_table_existing";
+    let added_table = "test_add_table_new";
+    let publication = "test_add_table_existing_pub";
+    let slot_name = "test_add_table_existing_slot";
+
+    let existing = TestTable::new(
+        existing_table,
+        &format!("CREATE TABLE {existing_table} (id INT PRIMARY KEY, data TEXT NOT NULL)"),
+    )
+    .await;
+    let _added = TestTable::new(
+        added_table,
+        &format!("CREATE TABLE {added_table} (id INT PRIMARY KEY, data TEXT NOT NULL)"),
+    )
+    .await;
+    // The sink writes to copies of the tables in the same database
+    let _existing_copy = TestTable::new(&format!("{existing_table}_copy"), "SELECT 1").await;
+    let _added_copy = TestTable::new(&format!("{added_table}_copy"), "SELECT 1").await;
+    let client = &existing.client;
+    client
+        .simple_query(&format!(
+            "DROP PUBLICATION IF EXISTS {publication};
+            CREATE PUBLICATION {publication} FOR TABLE {existing_table};"
+        ))
+        .await?;
+    drop_replication_slot(client, slot_name).await;
+
+    let source = create_source(publication, slot_name).await;
+    client
+        .simple_query(&format!(
+            "INSERT INTO {existing_table} VALUES (1, 'before');
+            INSERT INTO {added_table} VALUES (101, 'before');
+            ALTER PUBLICATION {publication} ADD TABLE {added_table};
+            INSERT INTO {added_table} VALUES (102, 'added');
+            INSERT INTO {existing_table} VALUES (2, 'after');"
+        ))
+        .await?;
+
+    let mappings = [existing_table, added_table].map(|name| {
+        let table_name = |name: String| TableName {
+            schema: "public".to_string(),
+            name,
+        };
+        (
+            table_name(name.to_string()),
+            table_name(format!("{name}_copy")),
+        )
+    });
+    // Every event is a batch of its own, so the change after the added table's is
+    // written after the table was backfilled
+    let batch_config = BatchConfig::new(1, Duration::from_millis(100));
+    let sink = FreshPostgresSink::new().await?;
+    let mut pipeline = BatchDataPipeline::new(source, sink, PipelineAction::CdcOnly, batch_config);
+    pipeline.set_transform(TableMappings::new(mappings));
+    pipeline.set_added_table_backfill(SnapshotBackfill::new(create_replication_client().await));
+    let outcome = pipeline
+        .consume_until(Instant::now() + Duration::from_secs(10))
+        .await?;
+    assert_eq!(outcome, ConsumeOutcome::CaughtUp);
+    drop(pipeline);
+
+    assert_eq!(
+        sink_rows(client, &format!("{existing_table}_copy")).await?,
+        vec![(1, "before".to_string()), (2, "after".to_string())]
+    );
+    assert_eq!(
+        sink_rows(client, &format!("{added_table}_copy")).await?,
+        vec![(101, "before".to_string()), (102, "added".to_string())]
+    );
+
+    drop_replication_slot(client, slot_name).await;
+    client
+        .simple_query(&format!("DROP PUBLICATION IF EXISTS {publication}"))
+        .await?;
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_stopped_pipeline_confirms_committed_changes() -> Result<(), anyhow::Error> {
+    let table_name = "test_stop_pipeline";
+    let publication = "test_stop_pipeline_pub";
+    let slot_name = "test_stop_pipeline_slot";
+
+    let test_table = TestTable::new(
+        table_name,
+        &format!("CREATE TABLE {table_name} (id INT PRIMARY KEY, data TEXT NOT NULL)"),
+    )
+    .await;
+    let client = &test_table.client;
+    client
+        .simple_query(&format!(
+            "DROP PUBLICATION IF EXISTS {publication};
+            CREATE PUBLICATION {publication} FOR TABLE {table_name};"
+        ))
+        .await?;
+    drop_replication_slot(client, slot_name).await;
+
+    let state = Arc::new(Mutex::new(DurableState::default()));
+    let batch_config = BatchConfig::new(5, Duration::from_millis(100));
+
+    let source = create_source(publication, slot_name).await;
+    client
+        .simple_query(&format!(
+            "INSERT INTO {table_name} VALUES (1, 'row 1');
+            INSERT INTO {table_name} VALUES (2, 'row 2');"
+        ))
+        .await?;
+    let sink = MemorySink::new(state.clone(), None, usize::MAX);
+    let mut pipeline = BatchDataPipeline::new(source, sink, PipelineAction::CdcOnly, batch_config);
+    let stop_handle = pipeline.stop_handle();
+    let stopper_state = state.clone();
+    let stopper = tokio::spawn(async move {
+        let deadline = Instant::now() + Duration::from_secs(10);
+        while stopper_state.lock().unwrap().rows.len() < 2 && Instant::now() < deadline {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+        stop_handle.stop();
+    });
+
+    pipeline.start().await?;
+    stopper.await?;
+    let committed_lsn = {
+        let state = state.lock().unwrap();
+        assert_eq!(state.rows.len(), 2);
+        state.last_lsn
+    };
+    assert!(confirmed_flush_lsn(client, slot_name).await? >= committed_lsn);
+    drop(pipeline);
+
+    drop_replication_slot(client, slot_name).await;
+    client
+        .simple_query(&format!("DROP PUBLICATION IF EXISTS {publication}"))
+        .await?;
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_paused_pipeline_writes_nothing_until_resumed() -> Result<(), anyhow::Error> {
+    let table_name = "test_pause_pipeline";
+    let publication = "test_pause_pipeline_pub";
+    let slot_name = "test_pause_pipeline_slot";
+
+    let test_table = TestTable::new(
+        table_name,
+        &format!("CREATE TABLE {table_name} (id INT PRIMARY KEY, data TEXT NOT NULL)"),
+    )
+    .await;
+    let client = &test_table.client;
+    client
+        .simple_query(&format!(
+            "DROP PUBLICATION IF EXISTS {publication};
+            CREATE PUBLICATION {publication} FOR TABLE {table_name};"
+        ))
+        .await?;
+    drop_replication_slot(client, slot_name).await;
+
+    let state = Arc::new(Mutex::new(DurableState::default()));
+    let batch_config = BatchConfig::new(5, Duration::from_millis(100));
+
+    let source = create_source(publication, slot_name).await;
+    client
+        .simple_query(&format!(
+            "INSERT INTO {table_name} VALUES (1, 'row 1');
+            INSERT INTO {table_name} VALUES (2, 'row 2');"
+        ))
+        .await?;
+    let sink = MemorySink::new(state.clone(), None, 2);
+    let mut pipeline = BatchDataPipeline::new(source, sink, PipelineAction::CdcOnly, batch_config);
+    let pause_handle = pipeline.pause_handle();
+    pause_handle.pause();
+    let resumer_state = state.clone();
+    let resumer = tokio::spawn(async move {
+        // Long enough for the batch timeout to have passed several times
+        tokio::time::sleep(Duration::from_secs(1)).await;
+        let rows_while_paused = resumer_state.lock().unwrap().rows.len();
+        pause_handle.resume();
+        rows_while_paused
+    });
+
+    let result = pipeline.start().await;
+    assert!(matches!(
+        result,
+        Err(PipelineError::Sink(MemorySinkError::Done))
+    ));
+    assert_eq!(resumer.await?, 0);
+    assert_eq!(state.lock().unwrap().rows.len(), 2);
+    drop(pipeline);
+
+    drop_replication_slot(client, slot_name).await;
+    client
+        .simple_query(&format!("DROP PUBLICATION IF EXISTS {publication}"))
+        .await?;
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_consume_until_caught_up_or_deadline() -> Result<(), anyhow::Error> {
+    let table_name = "test_consume_until";
+    let publication = "test_consume_until_pub";
+    let slot_name = "test_consume_until_slot";
+
+    let test_table = TestTable::new(
+        table_name,
+        &format!("CREATE TABLE {table_name} (id INT PRIMARY KEY, data TEXT NOT NULL)"),
+    )
+    .await;
+    let client = &test_table.client;
+    client
+        .simple_query(&format!(
+            "DROP PUBLICATION IF EXISTS {publication};
+            CREATE PUBLICATION {publication} FOR TABLE {table_name};"
+        ))
+        .await?;
+    drop_replication_slot(client, slot_name).await;
+
+    let state = Arc::new(Mutex::new(DurableState::default()));
+    let batch_config = BatchConfig::new(5, Duration::from_millis(100));
+
+    let source = create_source(publication, slot_name).await;
+    client
+        .simple_query(&format!(
+            "INSERT INTO {table_name} VALUES (1, 'row 1');
+            INSERT INTO {table_name} VALUES (2, 'row 2');"
+        ))
+        .await?;
+    let sink = MemorySink::new(state.clone(), None, usize::MAX);
+    let mut pipeline =
+        BatchDataPipeline::new(source, sink, PipelineAction::CdcOnly, batch_config.clone());
+    let outcome = pipeline
+        .consume_until(Instant::now() + Duration::from_secs(10))
+        .await?;
+    assert_eq!(outcome, ConsumeOutcome::CaughtUp);
+    let committed_lsn = {
+        let state = state.lock().unwrap();
+        assert_eq!(state.rows.len(), 2);
+        state.last_lsn
+    };
+    assert!(confirmed_flush_lsn(client, slot_name).await? >= committed_lsn);
+    drop(pipeline);
+
+    // A paused pipeline can't catch up before the deadline
+    client
+        .simple_query(&format!("INSERT INTO {table_name} VALUES (3, 'row 3')"))
+        .await?;
+    let mut source = create_source(publication, slot_name).await;
+    // the first pipeline's connection might not have released the slot yet
+    source.wait_for_active_slot(Duration::from_secs(5));
+    let sink = MemorySink::new(state.clone(), None, usize::MAX);
+    let mut pipeline = BatchDataPipeline::new(source, sink, PipelineAction::CdcOnly, batch_config);
+    pipeline.pause_handle().pause();
+    let outcome = pipeline
+        .consume_until(Instant::now() + Duration::from_millis(500))
+        .await?;
+    assert_eq!(outcome, ConsumeOutcome::DeadlineReached);
+    assert_eq!(state.lock().unwrap().rows.len(), 2);
+    drop(pipeline);
+
+    drop_replication_slot(client, slot_name).await;
+    client
+        .simple_query(&format!("DROP PUBLICATION IF EXISTS {publication}"))
+        .await?;
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_schema_change_keeps_other_tables_streaming() -> Result<(), anyhow::Error> {
+    let altered_table = "test_schema_change_altered";
+    let kept_table = "test_schema_change_kept";
+    let publication = "test_schema_change_pub";
+    let slot_name = "test_schema_change_slot";
+
+    let altered = TestTable::new(
+        altered_table,
+        &format!("CREATE TABLE {altered_table} (id INT PRIMARY KEY, data TEXT NOT NULL)"),
+    )
+    .await;
+    let _kept = TestTable::new(
+        kept_table,
+        &format!("CREATE TABLE {kept_table} (id INT PRIMARY KEY, data TEXT NOT NULL)"),
+    )
+    .await;
+    // The sink writes to copies of the tables in the same database
+    let _altered_copy = TestTable::new(&format!("{altered_table}_copy"), "SELECT 1").await;
+    let _kept_copy = TestTable::new(&format!("{kept_table}_copy"), "SELECT 1").await;
+    let client = &altered.client;
+    client
+        .simple_query(&format!(
+            "DROP PUBLICATION IF EXISTS {publication};
+            CREATE PUBLICATION {publication} FOR TABLE {altered_table}, {kept_table};"
+        ))
+        .await?;
+    drop_replication_slot(client, slot_name).await;
+
+    let mut source = create_source(publication, slot_name).await;
+    source.refresh_schemas_on_decode_error(create_replication_client().await);
+    source.emit_schema_changes();
+    client
+        .simple_query(&format!(
+            "INSERT INTO {altered_table} VALUES (1, 'a');
+            INSERT INTO {kept_table} VALUES (1, 'a');
+            ALTER TABLE {altered_table} ADD COLUMN extra TEXT;
+            INSERT INTO {altered_table} VALUES (2, 'b', 'extra');
+            INSERT INTO {kept_table} VALUES (2, 'b');"
+        ))
+        .await?;
+
+    let copy_name = |name: &str| TableName {
+        schema: "public".to_string(),
+        name: format!("{name}_copy"),
+    };
+    let source_name = |name: &str| TableName {
+        schema: "public".to_string(),
+        name: name.to_string(),
+    };
+    let batch_config = BatchConfig::new(100, Duration::from_millis(100));
+    let sink = FreshPostgresSink::new().await?;
+    let mut pipeline = BatchDataPipeline::new(source, sink, PipelineAction::CdcOnly, batch_config);
+    pipeline.set_transform(TableMappings::new([
+        (source_name(altered_table), copy_name(altered_table)),
+        (source_name(kept_table), copy_name(kept_table)),
+    ]));
+    let outcome = pipeline
+        .consume_until(Instant::now() + Duration::from_secs(10))
+        .await?;
+    assert_eq!(outcome, ConsumeOutcome::CaughtUp);
+    drop(pipeline);
+
+    let rows = client
+        .query(
+            &format!("SELECT id, data, extra FROM {altered_table}_copy ORDER BY id"),
+            &[],
+        )
+        .await?;
+    let rows: Vec<(i32, String, Option<String>)> = rows
+        .iter()
+        .map(|row| (row.get(0), row.get(1), row.get(2)))
+        .collect();
+    assert_eq!(
+        rows,
+        vec![
+            (1, "a".to_string(), None),
+            (2, "b".to_string(), Some("extra".to_string()))
+        ]
+    );
+    assert_eq!(
+        sink_rows(client, &format!("{kept_table}_copy")).await?,
+        vec![(1, "a".to_string()), (2, "b".to_string())]
+    );
+
+    drop_replication_slot(client, slot_name).await;
+    client
+        .simple_query(&format!("DROP PUBLICATION IF EXISTS {publication}"))
+        .await?;
+
+    Ok(())
+}
@@ -0,0 +1,6 @@
+pub mod batching;
+pub mod data_pipeline;
+pub mod rate_limit;
+pub mod spill;
+pub mod streamed;
+pub mod transforms;
@@ -0,0 +1,31 @@
+use std::time::{Duration, Instant};
+
+use pg_replicate::pipeline::rate_limit::{RateLimit, RateLimiter, RateLimiterError};
+
+#[tokio::test]
+async fn test_rate_limiter_waits_for_debt_to_be_paid_off() {
+    let limiter = RateLimiter::new(RateLimit::RowsPerSecond(100)).unwrap();
+
+    // the bucket starts full and a batch larger than it is let through right away
+    let start = Instant::now();
+    limiter.acquire(100).await;
+    limiter.acquire(20).await;
+    assert!(start.elapsed() < Duration::from_millis(100));
+    assert_eq!(limiter.throttles(), 0);
+
+    // the next batch waits until the 20 rows taken on credit are paid off
+    limiter.acquire(1).await;
+    assert!(start.elapsed() >= Duration::from_millis(150));
+    assert_eq!(limiter.throttles(), 1);
+    let throttled_time = limiter.throttled_time();
+    assert!(throttled_time > Duration::from_millis(150));
+    assert!(throttled_time <= Duration::from_millis(200));
+}
+
+#[test]
+fn test_rate_limiter_rejects_zero_rate() {
+    assert!(matches!(
+        RateLimiter::new(RateLimit::BytesPerSecond(0)),
+        Err(RateLimiterError::ZeroRate)
+    ));
+}
@@ -0,0 +1,146 @@
+use std::{
+    fs,
+    path::PathBuf,
+    str::FromStr,
+    time::{Duration, UNIX_EPOCH},
+};
+
+use chrono::{DateTime, NaiveDate, NaiveDateTime, NaiveTime};
+use pg_replicate::{
+    conversions::{
+        cdc_event::CdcEvent, interval::PgInterval, numeric::PgNumeric, table_row::TableRow,
+        ArrayCell, Cell,
+    },
+    pipeline::spill::SpillBuffer,
+};
+use tokio_postgres::types::PgLsn;
+use uuid::Uuid;
+
+use crate::common::events::begin;
+
+/// A directory of its own for each test, so that tests can check it's left empty
+fn spill_directory() -> PathBuf {
+    let directory =
+        std::env::temp_dir().join(format!("pg_replicate_spill_test_{}", Uuid::new_v4()));
+    fs::create_dir(&directory).unwrap();
+    directory
+}
+
+fn files(directory: &PathBuf) -> usize {
+    fs::read_dir(directory).unwrap().count()
+}
+
+fn cells() -> Vec<Cell> {
+    vec![
+        Cell::Null,
+        Cell::Bool(true),
+        Cell::String("text".to_string()),
+        Cell::I16(-16),
+        Cell::I32(-32),
+        Cell::U32(32),
+        Cell::I64(-64),
+        Cell::F32(1.5),
+        Cell::F64(-2.25),
+        Cell::Numeric(PgNumeric::from_str("123.456").unwrap()),
+        Cell::Date(NaiveDate::from_ymd_opt(2024, 2, 29).unwrap()),
+        Cell::Time(NaiveTime::from_hms_micro_opt(12, 34, 56, 789).unwrap()),
+        Cell::TimeStamp(
+            NaiveDateTime::parse_from_str("1999-12-31 23:59:59.123456", "%Y-%m-%d %H:%M:%S%.f")
+                .unwrap(),
+        ),
+        Cell::TimeStampTz(DateTime::from_timestamp(-1, 500).unwrap()),
+        Cell::Interval(PgInterval {
+            months: 14,
+            days: -3,
+            microseconds: 4_000_001,
+        }),
+        Cell::Uuid(Uuid::from_u128(0x1234_5678_9abc_def0)),
+        Cell::Json(serde_json::json!({"a": [1, null, "b"]})),
+        Cell::Bytes(vec![0, 1, 255]),
+        Cell::Array(ArrayCell::Null),
+        Cell::Array(ArrayCell::String(vec![Some("a".to_string()), None])),
+        Cell::Array(ArrayCell::Interval(vec![None, Some(PgInterval::default())])),
+        Cell::Array(ArrayCell::Nested(vec![
+            ArrayCell::I32(vec![Some(1), None]),
+            ArrayCell::I32(vec![Some(3), Some(4)]),
+        ])),
+    ]
+}
+
+fn events() -> Vec<CdcEvent> {
+    let row = TableRow::new(cells());
+    vec![
+        begin(7),
+        CdcEvent::Insert((1, row.clone(), None)),
+        CdcEvent::Update((2, Some(row.clone()), row.clone(), Some(7))),
+        CdcEvent::Update((2, None, row.clone(), None)),
+        CdcEvent::Delete((3, row, Some(8))),
+        CdcEvent::KeepAliveRequested {
+            reply: true,
+            wal_end: PgLsn::from(42),
+        },
+        CdcEvent::Heartbeat {
+            lsn: PgLsn::from(43),
+            server_time: UNIX_EPOCH + Duration::new(1_700_000_000, 123),
+        },
+    ]
+}
+
+#[tokio::test]
+async fn test_spill_buffer_keeps_small_transactions_in_memory() {
+    let directory = spill_directory();
+    let mut buffer = SpillBuffer::new(usize::MAX, &directory);
+    for event in events() {
+        buffer.push(event).await.unwrap();
+    }
+
+    assert!(!buffer.is_spilled());
+    assert_eq!(buffer.len(), events().len());
+    assert_eq!(files(&directory), 0);
+    let mut chunks = buffer.into_chunks().await.unwrap();
+    let chunk = chunks.next_chunk().await.unwrap();
+    assert_eq!(format!("{chunk:?}"), format!("{:?}", Some(events())));
+    assert!(chunks.next_chunk().await.unwrap().is_none());
+
+    fs::remove_dir(directory).unwrap();
+}
+
+#[tokio::test]
+async fn test_spill_buffer_round_trips_spilled_events() {
+    let directory = spill_directory();
+    // Small enough to spill right away and to read back every event as a chunk of
+    // its own
+    let mut buffer = SpillBuffer::new(100, &directory);
+    for event in events() {
+        buffer.push(event).await.unwrap();
+    }
+
+    assert!(buffer.is_spilled());
+    assert_eq!(buffer.len(), events().len());
+    assert_eq!(files(&directory), 1);
+    let mut chunks = buffer.into_chunks().await.unwrap();
+    let mut events_read: Vec<CdcEvent> = vec![];
+    while let Some(chunk) = chunks.next_chunk().await.unwrap() {
+        events_read.extend(chunk);
+    }
+    assert_eq!(format!("{events_read:?}"), format!("{:?}", events()));
+
+    // The file is removed once all chunks are read
+    assert_eq!(files(&directory), 0);
+    fs::remove_dir(directory).unwrap();
+}
+
+#[tokio::test]
+async fn test_spill_buffer_removes_file_when_dropped() {
+    let directory = spill_directory();
+    let mut buffer = SpillBuffer::new(0, &directory);
+    for event in events() {
+        buffer.push(event).await.unwrap();
+    }
+    assert_eq!(files(&directory), 1);
+
+    drop(buffer);
+
+    assert_eq!(files(&directory), 0);
+    fs::remove_dir(directory).unwrap();
+}
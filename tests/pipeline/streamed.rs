@@ -0,0 +1,80 @@
+use pg_replicate::{
+    conversions::{cdc_event::CdcEvent, table_row::TableRow, Cell},
+    pipeline::streamed::StreamedTransactions,
+};
+
+use crate::common::events::{
+    begin, commit, stream_abort, stream_commit, stream_start, stream_stop,
+};
+
+fn insert(id: i32, xid: Option<u32>) -> CdcEvent {
+    CdcEvent::Insert((1, TableRow::new(vec![Cell::I32(id)]), xid))
+}
+
+fn inserted_ids(events: &[CdcEvent]) -> Vec<i32> {
+    events
+        .iter()
+        .filter_map(|event| match event {
+            CdcEvent::Insert((_, row, _)) => match row.values[0] {
+                Cell::I32(id) => Some(id),
+                _ => None,
+            },
+            _ => None,
+        })
+        .collect()
+}
+
+#[test]
+fn test_streamed_transactions_release_changes_on_commit() {
+    let mut streamed = StreamedTransactions::new();
+
+    let released = streamed.hold(vec![
+        stream_start(10),
+        insert(1, Some(10)),
+        insert(2, Some(11)),
+        insert(3, Some(12)),
+        stream_stop(),
+        begin(5),
+        insert(4, None),
+        commit(50),
+    ]);
+    assert_eq!(inserted_ids(&released), vec![4]);
+    assert_eq!(streamed.len(), 3);
+
+    let released = streamed.hold(vec![
+        stream_start(20),
+        insert(5, Some(20)),
+        stream_stop(),
+        stream_abort(10, 11),
+        stream_abort(20, 20),
+    ]);
+    assert!(inserted_ids(&released).is_empty());
+
+    let released = streamed.hold(vec![stream_commit(10, 100)]);
+    assert_eq!(inserted_ids(&released), vec![1, 3]);
+    assert!(matches!(released.last(), Some(CdcEvent::StreamCommit(_))));
+    assert!(streamed.is_empty());
+}
+
+#[test]
+fn test_streamed_transactions_roll_back_failed_writes() {
+    let mut streamed = StreamedTransactions::new();
+    streamed.hold(vec![stream_start(10), insert(1, Some(10)), stream_stop()]);
+
+    // The sink fails to write the commit, which is retried with the same events
+    let batch = || {
+        vec![
+            stream_start(10),
+            insert(2, Some(10)),
+            insert(3, Some(11)),
+            stream_stop(),
+            stream_abort(10, 11),
+            stream_commit(10, 100),
+        ]
+    };
+    assert_eq!(inserted_ids(&streamed.hold(batch())), vec![1, 2]);
+    streamed.rollback();
+    assert_eq!(streamed.len(), 1);
+    assert_eq!(inserted_ids(&streamed.hold(batch())), vec![1, 2]);
+    assert!(streamed.is_empty());
+}
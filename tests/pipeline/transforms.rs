@@ -0,0 +1,212 @@
+use pg_replicate::{
+    conversions::{table_row::TableRow, Cell},
+    pipeline::transforms::{
+        DropColumns, MapColumns, NamingStrategy, TableMappings, Transform, TransformError,
+    },
+    table::{LookupKey, TableName, TableSchema},
+};
+use tokio_postgres::types::Type;
+
+use crate::common::schemas::column;
+
+#[test]
+fn test_chained_transforms_mask_and_drop_columns() {
+    let table_id = 1;
+    let table_schema = TableSchema {
+        table_name: TableName {
+            schema: "public".to_string(),
+            name: "users".to_string(),
+        },
+        table_id,
+        column_schemas: vec![
+            column("id", Type::INT4),
+            column("email", Type::TEXT),
+            column("ssn", Type::TEXT),
+        ],
+        lookup_key: LookupKey::FullRow,
+        excluded_columns: vec![],
+        comment: None,
+    };
+
+    let mask = |cell| match cell {
+        Cell::String(s) => Cell::String("*".repeat(s.len())),
+        cell => cell,
+    };
+    let mut transform = DropColumns::new(["ssn"]).then(MapColumns::new(["email"], mask));
+
+    let table_schema = transform.transform_schema(table_schema).unwrap();
+    let column_names: Vec<&str> = table_schema
+        .column_schemas
+        .iter()
+        .map(|c| c.name.as_str())
+        .collect();
+    assert_eq!(column_names, ["id", "email"]);
+
+    let row = TableRow::new(vec![
+        Cell::I32(1),
+        Cell::String("a@b.c".to_string()),
+        Cell::String("123-45-6789".to_string()),
+    ]);
+    match &transform.transform_row(table_id, row).unwrap().values[..] {
+        [Cell::I32(1), Cell::String(email)] => assert_eq!(email, "*****"),
+        values => panic!("unexpected row {values:?}"),
+    }
+
+    // Rows of tables without the named columns are left unchanged
+    let row = TableRow::new(vec![Cell::String("a@b.c".to_string())]);
+    match &transform.transform_row(table_id + 1, row).unwrap().values[..] {
+        [Cell::String(value)] => assert_eq!(value, "a@b.c"),
+        values => panic!("unexpected row {values:?}"),
+    }
+}
+
+#[test]
+fn test_transforms_reject_key_columns_and_narrow_rows() {
+    let table_id = 1;
+    let table_schema = |lookup_key| TableSchema {
+        table_name: TableName {
+            schema: "public".to_string(),
+            name: "users".to_string(),
+        },
+        table_id,
+        column_schemas: vec![column("id", Type::INT4), column("email", Type::TEXT)],
+        lookup_key,
+        excluded_columns: vec![],
+        comment: None,
+    };
+
+    let key = LookupKey::Key {
+        name: "users_pkey".to_string(),
+        columns: vec!["id".to_string()],
+    };
+    match DropColumns::new(["id"]).transform_schema(table_schema(key)) {
+        Err(TransformError::KeyColumnDropped(column, _)) => assert_eq!(column, "id"),
+        result => panic!("unexpected result {result:?}"),
+    }
+
+    // Rows missing columns the transforms were built for, e.g. of an older schema,
+    // are errors instead of panics
+    let mut drop = DropColumns::new(["email"]);
+    drop.transform_schema(table_schema(LookupKey::FullRow))
+        .unwrap();
+    let mut map = MapColumns::new(["email"], |cell| cell);
+    map.transform_schema(table_schema(LookupKey::FullRow))
+        .unwrap();
+    let row = || TableRow::new(vec![Cell::I32(1)]);
+    assert!(matches!(
+        drop.transform_row(table_id, row()),
+        Err(TransformError::RowTooNarrow(1, 1, 2))
+    ));
+    assert!(matches!(
+        map.transform_row(table_id, row()),
+        Err(TransformError::RowTooNarrow(1, 1, 2))
+    ));
+}
+
+#[test]
+fn test_naming_strategies() {
+    let names = [
+        "OrderItems",
+        "orderID",
+        "HTTPServer",
+        "user-name 2",
+        "already_snake",
+    ];
+    let apply = |strategy: NamingStrategy| -> Vec<String> {
+        names.iter().map(|name| strategy.apply(name)).collect()
+    };
+    assert_eq!(apply(NamingStrategy::Preserve), names);
+    assert_eq!(
+        apply(NamingStrategy::Lowercase),
+        [
+            "orderitems",
+            "orderid",
+            "httpserver",
+            "user-name 2",
+            "already_snake"
+        ]
+    );
+    assert_eq!(
+        apply(NamingStrategy::Uppercase),
+        [
+            "ORDERITEMS",
+            "ORDERID",
+            "HTTPSERVER",
+            "USER-NAME 2",
+            "ALREADY_SNAKE"
+        ]
+    );
+    assert_eq!(
+        apply(NamingStrategy::SnakeCase),
+        [
+            "order_items",
+            "order_id",
+            "http_server",
+            "user_name_2",
+            "already_snake"
+        ]
+    );
+
+    let mut strategy = NamingStrategy::SnakeCase;
+    let table_schema = strategy
+        .transform_schema(TableSchema {
+            table_name: TableName {
+                schema: "Sales".to_string(),
+                name: "OrderItems".to_string(),
+            },
+            table_id: 1,
+            column_schemas: vec![
+                column("OrderId", Type::INT4),
+                column("ItemName", Type::TEXT),
+            ],
+            lookup_key: LookupKey::Key {
+                name: "OrderItems_pkey".to_string(),
+                columns: vec!["OrderId".to_string()],
+            },
+            excluded_columns: vec![],
+            comment: None,
+        })
+        .unwrap();
+    assert_eq!(table_schema.table_name.to_string(), "sales.order_items");
+    let column_names: Vec<&str> = table_schema
+        .column_schemas
+        .iter()
+        .map(|c| c.name.as_str())
+        .collect();
+    assert_eq!(column_names, ["order_id", "item_name"]);
+    match table_schema.lookup_key {
+        LookupKey::Key { columns, .. } => assert_eq!(columns, ["order_id"]),
+        LookupKey::FullRow => panic!("lookup key changed to full row"),
+    }
+}
+
+#[test]
+fn test_table_mappings_rename_mapped_tables() {
+    let table_name = |schema: &str, name: &str| TableName {
+        schema: schema.to_string(),
+        name: name.to_string(),
+    };
+    let table_schema = |table_id, table_name| TableSchema {
+        table_name,
+        table_id,
+        column_schemas: vec![column("id", Type::INT4)],
+        lookup_key: LookupKey::FullRow,
+        excluded_columns: vec![],
+        comment: None,
+    };
+    let mut mappings = TableMappings::new([(
+        table_name("public", "orders"),
+        table_name("raw", "raw_orders"),
+    )]);
+
+    let orders = mappings
+        .transform_schema(table_schema(1, table_name("public", "orders")))
+        .unwrap();
+    assert_eq!(orders.table_name.to_string(), "raw.raw_orders");
+
+    // Unmapped tables keep their source name
+    let users = mappings
+        .transform_schema(table_schema(2, table_name("public", "users")))
+        .unwrap();
+    assert_eq!(users.table_name.to_string(), "public.users");
+}
@@ -0,0 +1,159 @@
+use std::collections::HashMap;
+
+use pg_replicate::{
+    conversions::{cdc_event::CdcEvent, table_row::TableRow, Cell},
+    pipeline::sinks::clickhouse::{ClickHouseSink, RowVersions, TableJsonRow},
+    table::{LookupKey, TableId, TableName, TableSchema},
+};
+use tokio_postgres::types::{PgLsn, Type};
+
+use crate::common::{
+    events::{begin, commit, stream_abort, stream_commit, stream_start, stream_stop},
+    schemas::column,
+};
+
+#[test]
+fn test_postgres_to_clickhouse_type_mapping() {
+    assert_eq!(
+        ClickHouseSink::postgres_to_clickhouse_type(&Type::INT2, -1),
+        "Int16"
+    );
+    assert_eq!(
+        ClickHouseSink::postgres_to_clickhouse_type(&Type::INT8, -1),
+        "Int64"
+    );
+    assert_eq!(
+        ClickHouseSink::postgres_to_clickhouse_type(&Type::TEXT, -1),
+        "String"
+    );
+    assert_eq!(
+        ClickHouseSink::postgres_to_clickhouse_type(&Type::TIMESTAMPTZ, -1),
+        "DateTime64(6, 'UTC')"
+    );
+    assert_eq!(
+        ClickHouseSink::postgres_to_clickhouse_type(&Type::INT4_ARRAY, -1),
+        "Array(Nullable(Int32))"
+    );
+}
+
+#[test]
+fn test_numeric_to_clickhouse_type_mapping() {
+    // numeric(10, 2) is stored in atttypmod as ((10 << 16) | 2) + 4
+    let modifier = ((10 << 16) | 2) + 4;
+    assert_eq!(
+        ClickHouseSink::postgres_to_clickhouse_type(&Type::NUMERIC, modifier),
+        "Decimal(10, 2)"
+    );
+
+    // unconstrained numerics can't be represented by a decimal
+    assert_eq!(
+        ClickHouseSink::postgres_to_clickhouse_type(&Type::NUMERIC, -1),
+        "String"
+    );
+}
+
+fn table_schemas() -> HashMap<TableId, TableSchema> {
+    let table_schema = TableSchema {
+        table_name: TableName {
+            schema: "public".to_string(),
+            name: "test_clickhouse".to_string(),
+        },
+        table_id: 1,
+        column_schemas: vec![column("id", Type::INT4), column("data", Type::TEXT)],
+        lookup_key: LookupKey::Key {
+            name: "test_clickhouse_pkey".to_string(),
+            columns: vec!["id".to_string()],
+        },
+        excluded_columns: vec![],
+        comment: None,
+    };
+    HashMap::from([(1, table_schema)])
+}
+
+fn row(id: i32, data: Option<&str>) -> TableRow {
+    TableRow::new(vec![
+        Cell::I32(id),
+        data.map_or(Cell::Null, |d| Cell::String(d.to_string())),
+    ])
+}
+
+/// The id, version and is-deleted flag of each row version
+fn versions(rows: &[TableJsonRow]) -> Vec<(i64, u64, u64)> {
+    rows.iter()
+        .map(|(_, row)| {
+            (
+                row["id"].as_i64().unwrap(),
+                row["_pg_replicate_version"].as_u64().unwrap(),
+                row["_pg_replicate_is_deleted"].as_u64().unwrap(),
+            )
+        })
+        .collect()
+}
+
+#[test]
+fn test_clickhouse_updated_key_is_deleted() {
+    let mut row_versions = RowVersions::new();
+    let events = vec![
+        begin(1),
+        CdcEvent::Insert((1, row(1, Some("a")), None)),
+        CdcEvent::Update((1, Some(row(1, None)), row(2, Some("b")), None)),
+        CdcEvent::Update((1, None, row(2, Some("c")), None)),
+        CdcEvent::Delete((1, row(2, None), None)),
+        commit(0x100),
+    ];
+
+    let (rows, last_lsn) = row_versions.push_events(&table_schemas(), events).unwrap();
+
+    assert_eq!(
+        versions(&rows),
+        vec![(1, 0, 0), (1, 0, 1), (2, 0, 0), (2, 0, 0), (2, 0, 1)]
+    );
+    assert_eq!(last_lsn, Some(PgLsn::from(0x100)));
+}
+
+#[test]
+fn test_clickhouse_streamed_transactions_are_versioned_on_commit() {
+    let mut row_versions = RowVersions::new();
+    let events = vec![
+        stream_start(10),
+        CdcEvent::Insert((1, row(1, Some("kept")), Some(10))),
+        CdcEvent::Insert((1, row(2, Some("aborted subxact")), Some(11))),
+        stream_stop(),
+        stream_abort(10, 11),
+        stream_start(20),
+        CdcEvent::Insert((1, row(3, Some("aborted")), Some(20))),
+        stream_stop(),
+    ];
+    let (rows, last_lsn) = row_versions.push_events(&table_schemas(), events).unwrap();
+    assert!(rows.is_empty());
+    assert_eq!(last_lsn, None);
+
+    let events = vec![stream_abort(20, 20), stream_commit(10, 0x200)];
+    let (rows, last_lsn) = row_versions.push_events(&table_schemas(), events).unwrap();
+    assert_eq!(versions(&rows), vec![(1, 0x200, 0)]);
+    assert_eq!(last_lsn, Some(PgLsn::from(0x200)));
+}
+
+#[test]
+fn test_clickhouse_row_versions_rollback() {
+    let mut row_versions = RowVersions::new();
+    let events = || {
+        vec![
+            stream_start(10),
+            CdcEvent::Insert((1, row(1, Some("a")), Some(10))),
+            stream_stop(),
+            stream_commit(10, 0x200),
+        ]
+    };
+    let (rows, _) = row_versions
+        .push_events(&table_schemas(), events())
+        .unwrap();
+    assert_eq!(versions(&rows), vec![(1, 0x200, 0)]);
+
+    // The insert of the rows failed and the events are written again
+    row_versions.rollback();
+    let (rows, _) = row_versions
+        .push_events(&table_schemas(), events())
+        .unwrap();
+    assert_eq!(versions(&rows), vec![(1, 0x200, 0)]);
+}
@@ -0,0 +1,102 @@
+use std::collections::HashMap;
+
+use pg_replicate::{
+    clients::duckdb::DuckDbClient,
+    conversions::{table_row::TableRow, Cell},
+    pipeline::sinks::{duckdb::DuckDbSink, BatchSink},
+    table::{LookupKey, TableName, TableSchema},
+};
+use tokio_postgres::types::{PgLsn, Type};
+
+use crate::common::schemas::{column, not_null_column};
+
+#[test]
+fn test_postgres_to_duckdb_type_mapping() {
+    assert_eq!(
+        DuckDbClient::postgres_to_duckdb_type(&Type::INT2, -1),
+        "smallint"
+    );
+    assert_eq!(
+        DuckDbClient::postgres_to_duckdb_type(&Type::INT8, -1),
+        "bigint"
+    );
+    assert_eq!(
+        DuckDbClient::postgres_to_duckdb_type(&Type::TEXT, -1),
+        "varchar"
+    );
+    assert_eq!(
+        DuckDbClient::postgres_to_duckdb_type(&Type::TIMESTAMP, -1),
+        "timestamp"
+    );
+    assert_eq!(
+        DuckDbClient::postgres_to_duckdb_type(&Type::TIMESTAMPTZ, -1),
+        "timestamptz"
+    );
+    assert_eq!(
+        DuckDbClient::postgres_to_duckdb_type(&Type::INT4_ARRAY, -1),
+        "integer[]"
+    );
+}
+
+#[test]
+fn test_numeric_to_duckdb_type_mapping() {
+    // numeric(10, 2) is stored in atttypmod as ((10 << 16) | 2) + 4
+    let modifier = ((10 << 16) | 2) + 4;
+    assert_eq!(
+        DuckDbClient::postgres_to_duckdb_type(&Type::NUMERIC, modifier),
+        "decimal(10, 2)"
+    );
+
+    // unconstrained numerics can't be represented by a decimal
+    assert_eq!(
+        DuckDbClient::postgres_to_duckdb_type(&Type::NUMERIC, -1),
+        "varchar"
+    );
+
+    // numerics wider than 38 digits don't fit into a duckdb decimal
+    let modifier = ((50 << 16) | 2) + 4;
+    assert_eq!(
+        DuckDbClient::postgres_to_duckdb_type(&Type::NUMERIC, modifier),
+        "varchar"
+    );
+}
+
+#[tokio::test]
+async fn test_duckdb_sink_resumption_state() -> Result<(), anyhow::Error> {
+    let mut sink = DuckDbSink::in_memory()?;
+
+    let table_schema = TableSchema {
+        table_name: TableName {
+            schema: "public".to_string(),
+            name: "test_duckdb_sink".to_string(),
+        },
+        table_id: 1,
+        column_schemas: vec![
+            not_null_column("id", Type::INT4),
+            column("data", Type::TEXT),
+        ],
+        lookup_key: LookupKey::Key {
+            name: "test_duckdb_sink_pkey".to_string(),
+            columns: vec!["id".to_string()],
+        },
+        excluded_columns: vec![],
+        comment: None,
+    };
+
+    let mut table_schemas = HashMap::new();
+    table_schemas.insert(table_schema.table_id, table_schema);
+    sink.write_table_schemas(table_schemas).await?;
+
+    let rows = vec![
+        TableRow::new(vec![Cell::I32(1), Cell::String("one".to_string())]),
+        TableRow::new(vec![Cell::I32(2), Cell::Null]),
+    ];
+    sink.write_table_rows(rows, 1).await?;
+    sink.table_copied(1).await?;
+
+    let resumption_state = sink.get_resumption_state().await?;
+    assert!(resumption_state.copied_tables.contains(&1));
+    assert_eq!(resumption_state.last_lsn, PgLsn::from(0));
+
+    Ok(())
+}
@@ -0,0 +1,320 @@
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{Arc, Mutex},
+};
+
+use async_trait::async_trait;
+use pg_replicate::{
+    conversions::{cdc_event::CdcEvent, table_row::TableRow, Cell},
+    pipeline::{
+        sinks::{
+            fan_out::{FanOutSink, FanOutSinkError, LsnPolicy},
+            BatchSink, SinkError,
+        },
+        PipelineResumptionState,
+    },
+    table::{TableId, TableSchema},
+};
+use thiserror::Error;
+use tokio_postgres::types::PgLsn;
+
+use crate::common::events::{begin, commit, stream_commit, stream_start, stream_stop};
+
+#[derive(Debug, Error)]
+#[error("sink is down")]
+struct SinkDownError;
+
+impl SinkError for SinkDownError {}
+
+type Batches = Arc<Mutex<Vec<Vec<CdcEvent>>>>;
+
+/// Records the batches of events written to it and confirms `lsn`, or fails if it
+/// is down
+struct RecordingSink {
+    events: Batches,
+    copied_tables: HashSet<TableId>,
+    lsn: u64,
+    down: bool,
+}
+
+impl RecordingSink {
+    fn new(lsn: u64, down: bool) -> (RecordingSink, Batches) {
+        let events = Arc::new(Mutex::new(vec![]));
+        let sink = RecordingSink {
+            events: events.clone(),
+            copied_tables: HashSet::new(),
+            lsn,
+            down,
+        };
+        (sink, events)
+    }
+
+    fn check(&self) -> Result<(), SinkDownError> {
+        if self.down {
+            return Err(SinkDownError);
+        }
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl BatchSink for RecordingSink {
+    type Error = SinkDownError;
+
+    async fn get_resumption_state(&mut self) -> Result<PipelineResumptionState, Self::Error> {
+        self.check()?;
+        Ok(PipelineResumptionState {
+            copied_tables: self.copied_tables.clone(),
+            last_lsn: PgLsn::from(self.lsn),
+            backfill_cursors: HashMap::new(),
+            slot_name: None,
+        })
+    }
+
+    async fn write_table_schemas(
+        &mut self,
+        _table_schemas: HashMap<TableId, TableSchema>,
+    ) -> Result<(), Self::Error> {
+        self.check()
+    }
+
+    async fn write_table_rows(
+        &mut self,
+        _rows: Vec<TableRow>,
+        _table_id: TableId,
+    ) -> Result<(), Self::Error> {
+        self.check()
+    }
+
+    async fn write_cdc_events(&mut self, events: Vec<CdcEvent>) -> Result<PgLsn, Self::Error> {
+        self.check()?;
+        self.events.lock().unwrap().push(events);
+        Ok(PgLsn::from(self.lsn))
+    }
+
+    async fn table_copied(&mut self, table_id: TableId) -> Result<(), Self::Error> {
+        self.check()?;
+        self.copied_tables.insert(table_id);
+        Ok(())
+    }
+
+    async fn truncate_table(&mut self, _table_id: TableId) -> Result<(), Self::Error> {
+        self.check()
+    }
+}
+
+fn insert(id: i32) -> CdcEvent {
+    let row = TableRow::new(vec![Cell::I32(id)]);
+    CdcEvent::Insert((1, row, None))
+}
+
+fn inserted_ids(batches: &Mutex<Vec<Vec<CdcEvent>>>) -> Vec<i32> {
+    describe(batches)
+        .into_iter()
+        .flatten()
+        .map(|event| event.parse().expect("expected an insert"))
+        .collect()
+}
+
+/// Describes the batches of events, inserts by their id and other events by name
+fn describe(batches: &Mutex<Vec<Vec<CdcEvent>>>) -> Vec<Vec<String>> {
+    batches
+        .lock()
+        .unwrap()
+        .iter()
+        .map(|events| {
+            events
+                .iter()
+                .map(|event| match event {
+                    CdcEvent::Insert((_, row, _)) => match row.values[0] {
+                        Cell::I32(id) => id.to_string(),
+                        _ => panic!("unexpected cell"),
+                    },
+                    CdcEvent::Begin(_) => "begin".to_string(),
+                    CdcEvent::Commit(_) => "commit".to_string(),
+                    CdcEvent::StreamStart(_) => "stream start".to_string(),
+                    CdcEvent::StreamStop(_) => "stream stop".to_string(),
+                    CdcEvent::StreamCommit(_) => "stream commit".to_string(),
+                    _ => panic!("unexpected event {event:?}"),
+                })
+                .collect()
+        })
+        .collect()
+}
+
+#[tokio::test]
+async fn test_fan_out_writes_to_all_sinks() {
+    let mut sink = FanOutSink::new(LsnPolicy::AllSinks);
+    let (first, first_events) = RecordingSink::new(200, false);
+    let (second, second_events) = RecordingSink::new(100, false);
+    sink.add_sink(first);
+    sink.add_sink(second);
+
+    let lsn = sink
+        .write_cdc_events(vec![insert(1), insert(2)])
+        .await
+        .unwrap();
+
+    assert_eq!(lsn, PgLsn::from(100));
+    assert_eq!(inserted_ids(&first_events), vec![1, 2]);
+    assert_eq!(inserted_ids(&second_events), vec![1, 2]);
+}
+
+#[tokio::test]
+async fn test_fan_out_fails_if_any_sink_fails() {
+    let mut sink = FanOutSink::new(LsnPolicy::AllSinks);
+    let (first, first_events) = RecordingSink::new(200, false);
+    let (second, _) = RecordingSink::new(100, true);
+    sink.add_sink(first);
+    sink.add_sink(second);
+
+    let result = sink.write_cdc_events(vec![insert(1)]).await;
+
+    match result {
+        Err(FanOutSinkError::Sinks(failures)) => {
+            assert_eq!(failures.len(), 1);
+            assert_eq!(failures[0].0, 1);
+        }
+        result => panic!("unexpected result {result:?}"),
+    }
+    // The healthy sink was still written to
+    assert_eq!(inserted_ids(&first_events), vec![1]);
+}
+
+#[tokio::test]
+async fn test_fan_out_ignores_failing_secondary_sinks() {
+    let mut sink = FanOutSink::new(LsnPolicy::Primary(0));
+    let (primary, primary_events) = RecordingSink::new(200, false);
+    let (secondary, _) = RecordingSink::new(100, true);
+    sink.add_sink(primary);
+    sink.add_sink(secondary);
+
+    let lsn = sink.write_cdc_events(vec![insert(1)]).await.unwrap();
+
+    assert_eq!(lsn, PgLsn::from(200));
+    assert_eq!(inserted_ids(&primary_events), vec![1]);
+}
+
+#[tokio::test]
+async fn test_fan_out_fails_if_primary_sink_fails() {
+    let mut sink = FanOutSink::new(LsnPolicy::Primary(1));
+    let (secondary, _) = RecordingSink::new(200, false);
+    let (primary, _) = RecordingSink::new(100, true);
+    sink.add_sink(secondary);
+    sink.add_sink(primary);
+
+    let result = sink.write_cdc_events(vec![insert(1)]).await;
+
+    assert!(matches!(result, Err(FanOutSinkError::Sinks(_))));
+}
+
+#[tokio::test]
+async fn test_fan_out_resumes_from_slowest_sink() {
+    let mut sink = FanOutSink::new(LsnPolicy::AllSinks);
+    let (mut first, _) = RecordingSink::new(200, false);
+    first.copied_tables.extend([1, 2]);
+    let (mut second, _) = RecordingSink::new(100, false);
+    second.copied_tables.extend([2, 3]);
+    sink.add_sink(first);
+    sink.add_sink(second);
+
+    let resumption_state = sink.get_resumption_state().await.unwrap();
+
+    assert_eq!(resumption_state.last_lsn, PgLsn::from(100));
+    assert_eq!(resumption_state.copied_tables, HashSet::from([2]));
+}
+
+#[tokio::test]
+async fn test_fan_out_without_sinks_fails() {
+    let mut sink = FanOutSink::new(LsnPolicy::AllSinks);
+
+    let result = sink.write_cdc_events(vec![insert(1)]).await;
+
+    assert!(matches!(result, Err(FanOutSinkError::NoSinks)));
+}
+
+#[tokio::test]
+async fn test_fan_out_barrier_writes_whole_transactions() {
+    let mut sink = FanOutSink::new(LsnPolicy::AllSinks);
+    sink.set_global_lsn_barrier(true);
+    let (first, first_events) = RecordingSink::new(100, false);
+    let (second, second_events) = RecordingSink::new(100, false);
+    sink.add_sink(first);
+    sink.add_sink(second);
+
+    // Two transactions in one batch, then a segment of a streamed transaction and a
+    // transaction which commits while the streamed one is still open
+    sink.write_cdc_events(vec![
+        begin(1),
+        insert(1),
+        commit(10),
+        begin(2),
+        insert(2),
+        commit(20),
+    ])
+    .await
+    .unwrap();
+    sink.write_cdc_events(vec![
+        stream_start(4),
+        insert(4),
+        stream_stop(),
+        begin(3),
+        insert(3),
+    ])
+    .await
+    .unwrap();
+    sink.write_cdc_events(vec![commit(30)]).await.unwrap();
+
+    let expected = vec![vec!["begin", "1", "commit"], vec!["begin", "2", "commit"]];
+    assert_eq!(describe(&first_events), expected);
+    assert_eq!(describe(&second_events), expected);
+
+    sink.write_cdc_events(vec![stream_commit(4, 40)])
+        .await
+        .unwrap();
+
+    let expected = vec![
+        vec!["begin", "1", "commit"],
+        vec!["begin", "2", "commit"],
+        vec![
+            "stream start",
+            "4",
+            "stream stop",
+            "begin",
+            "3",
+            "commit",
+            "stream commit",
+        ],
+    ];
+    assert_eq!(describe(&first_events), expected);
+    assert_eq!(describe(&second_events), expected);
+}
+
+#[tokio::test]
+async fn test_fan_out_barrier_spills_large_transactions() {
+    let mut sink = FanOutSink::new(LsnPolicy::AllSinks);
+    sink.set_global_lsn_barrier(true);
+    sink.spill_transactions(0, std::env::temp_dir());
+    let (first, first_events) = RecordingSink::new(100, false);
+    let (second, second_events) = RecordingSink::new(100, false);
+    sink.add_sink(first);
+    sink.add_sink(second);
+
+    sink.write_cdc_events(vec![begin(1), insert(1), insert(2)])
+        .await
+        .unwrap();
+    sink.write_cdc_events(vec![insert(3), commit(10), begin(2), insert(4)])
+        .await
+        .unwrap();
+
+    // The spilled transaction is read back one event at a time
+    let expected = vec![
+        vec!["begin"],
+        vec!["1"],
+        vec!["2"],
+        vec!["3"],
+        vec!["commit"],
+    ];
+    assert_eq!(describe(&first_events), expected);
+    assert_eq!(describe(&second_events), expected);
+}
@@ -0,0 +1,75 @@
+use chrono::{NaiveDate, NaiveTime};
+use pg_replicate::{
+    conversions::{table_row::TableRow, ArrayCell, Cell},
+    pipeline::sinks::kafka::KafkaSink,
+    table::{LookupKey, TableName, TableSchema},
+};
+use serde_json::{json, Value};
+use tokio_postgres::types::Type;
+
+use crate::common::schemas::column;
+
+fn table_schema(lookup_key: LookupKey) -> TableSchema {
+    TableSchema {
+        table_name: TableName {
+            schema: "public".to_string(),
+            name: "orders".to_string(),
+        },
+        table_id: 1,
+        column_schemas: vec![
+            column("tenant", Type::INT4),
+            column("id", Type::INT8),
+            column("day", Type::DATE),
+            column("at", Type::TIME),
+            column("data", Type::BYTEA),
+            column("tags", Type::TEXT_ARRAY),
+        ],
+        lookup_key,
+        excluded_columns: vec![],
+        comment: None,
+    }
+}
+
+fn row() -> TableRow {
+    TableRow::new(vec![
+        Cell::I32(7),
+        Cell::I64(1),
+        Cell::Date(NaiveDate::from_ymd_opt(1970, 1, 11).unwrap()),
+        Cell::Time(NaiveTime::from_hms_micro_opt(0, 0, 1, 5).unwrap()),
+        Cell::Bytes(vec![0xde, 0xad]),
+        Cell::Array(ArrayCell::String(vec![Some("a".to_string()), None])),
+    ])
+}
+
+#[test]
+fn test_kafka_row_to_json() {
+    let row = KafkaSink::row_to_json(&table_schema(LookupKey::FullRow), row());
+    assert_eq!(
+        Value::Object(row),
+        json!({
+            "tenant": 7,
+            "id": 1,
+            "day": 10,
+            "at": 1_000_005,
+            "data": "dead",
+            "tags": ["a", null],
+        })
+    );
+}
+
+#[test]
+fn test_kafka_message_key() {
+    let keyed_schema = table_schema(LookupKey::Key {
+        name: "orders_pkey".to_string(),
+        columns: vec!["tenant".to_string(), "id".to_string()],
+    });
+    let row = KafkaSink::row_to_json(&keyed_schema, row());
+    let key = KafkaSink::key(&keyed_schema, &row).unwrap();
+    assert_eq!(
+        serde_json::from_str::<Value>(&key).unwrap(),
+        json!({"tenant": 7, "id": 1})
+    );
+
+    let full_row_schema = table_schema(LookupKey::FullRow);
+    assert_eq!(KafkaSink::key(&full_row_schema, &row), None);
+}
@@ -0,0 +1,9 @@
+#[cfg(feature = "clickhouse")]
+pub mod clickhouse;
+#[cfg(feature = "duckdb")]
+pub mod duckdb;
+pub mod fan_out;
+#[cfg(feature = "kafka")]
+pub mod kafka;
+pub mod postgres;
+pub mod table_parallel;
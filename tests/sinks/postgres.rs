@@ -0,0 +1,419 @@
+use std::{collections::HashMap, time::Duration};
+
+use pg_replicate::{
+    conversions::{cdc_event::CdcEvent, table_row::TableRow, ArrayCell, Cell},
+    pipeline::sinks::{
+        postgres::{PostgresSink, PostgresSinkError},
+        BatchSink,
+    },
+    table::{LookupKey, TableName, TableSchema},
+};
+use tokio_postgres::{error::SqlState, types::Type};
+
+use crate::common::{
+    events::{stream_abort, stream_commit, stream_start, stream_stop},
+    postgres_utils::{create_postgres_client, TestTable},
+    schemas::{column, not_null_column},
+};
+
+fn table_name(name: &str) -> TableName {
+    TableName {
+        schema: "public".to_string(),
+        name: name.to_string(),
+    }
+}
+
+#[tokio::test]
+async fn test_postgres_sink_applies_batched_changes() -> Result<(), anyhow::Error> {
+    let keyed = TestTable::new("test_pg_sink_keyed", "select 1").await;
+    let full_row = TestTable::new("test_pg_sink_full_row", "select 1").await;
+    let mut sink = PostgresSink::new(create_postgres_client().await).await?;
+
+    let keyed_schema = TableSchema {
+        table_name: table_name(&keyed.table),
+        table_id: 1,
+        column_schemas: vec![
+            not_null_column("tenant", Type::INT4),
+            not_null_column("id", Type::INT8),
+            column("data", Type::TEXT),
+            column("tags", Type::TEXT_ARRAY),
+        ],
+        lookup_key: LookupKey::Key {
+            name: "test_pg_sink_keyed_pkey".to_string(),
+            columns: vec!["tenant".to_string(), "id".to_string()],
+        },
+        excluded_columns: vec![],
+        comment: None,
+    };
+    let full_row_schema = TableSchema {
+        table_name: table_name(&full_row.table),
+        table_id: 2,
+        column_schemas: vec![column("a", Type::INT4), column("b", Type::TEXT)],
+        lookup_key: LookupKey::FullRow,
+        excluded_columns: vec![],
+        comment: None,
+    };
+    sink.write_table_schemas(HashMap::from([(1, keyed_schema), (2, full_row_schema)]))
+        .await?;
+
+    let keyed_row = |tenant: i32, id: i64, data: Option<&str>| {
+        TableRow::new(vec![
+            Cell::I32(tenant),
+            Cell::I64(id),
+            data.map_or(Cell::Null, |d| Cell::String(d.to_string())),
+            Cell::Array(ArrayCell::String(vec![Some("a,\"b\"".to_string()), None])),
+        ])
+    };
+    let full_row_row = |a: i32, b: Option<&str>| {
+        TableRow::new(vec![
+            Cell::I32(a),
+            b.map_or(Cell::Null, |b| Cell::String(b.to_string())),
+        ])
+    };
+
+    // The table without a key can hold duplicate rows
+    sink.write_table_rows(
+        vec![
+            full_row_row(1, Some("x")),
+            full_row_row(1, Some("x")),
+            full_row_row(2, Some("y")),
+        ],
+        2,
+    )
+    .await?;
+
+    let injection = "'); drop table test_pg_sink_keyed; --";
+    let events = vec![
+        CdcEvent::Insert((1, keyed_row(1, 1, Some("a")), None)),
+        CdcEvent::Insert((1, keyed_row(1, 2, Some("b")), None)),
+        CdcEvent::Update((1, None, keyed_row(1, 1, Some(injection)), None)),
+        CdcEvent::Insert((1, keyed_row(2, 1, Some("c")), None)),
+        CdcEvent::Delete((1, keyed_row(1, 2, None), None)),
+        // The key of (2, 1) changes to (2, 5)
+        CdcEvent::Update((
+            1,
+            Some(keyed_row(2, 1, None)),
+            keyed_row(2, 5, Some("c")),
+            None,
+        )),
+        CdcEvent::Delete((2, full_row_row(1, Some("x")), None)),
+        CdcEvent::Update((
+            2,
+            Some(full_row_row(2, Some("y"))),
+            full_row_row(2, Some("z")),
+            None,
+        )),
+        CdcEvent::Insert((2, full_row_row(3, None), None)),
+    ];
+    sink.write_cdc_events(events).await?;
+
+    let rows = keyed
+        .client
+        .query(
+            "select tenant, id, data, tags from test_pg_sink_keyed order by tenant, id",
+            &[],
+        )
+        .await?;
+    let rows: Vec<(i32, i64, String, Vec<Option<String>>)> = rows
+        .iter()
+        .map(|row| (row.get(0), row.get(1), row.get(2), row.get(3)))
+        .collect();
+    let tags = vec![Some("a,\"b\"".to_string()), None];
+    assert_eq!(
+        rows,
+        vec![
+            (1, 1, injection.to_string(), tags.clone()),
+            (2, 5, "c".to_string(), tags),
+        ]
+    );
+
+    let rows = full_row
+        .client
+        .query("select a, b from test_pg_sink_full_row order by a", &[])
+        .await?;
+    let rows: Vec<(i32, Option<String>)> =
+        rows.iter().map(|row| (row.get(0), row.get(1))).collect();
+    assert_eq!(
+        rows,
+        vec![
+            (1, Some("x".to_string())),
+            (2, Some("z".to_string())),
+            (3, None),
+        ]
+    );
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_postgres_sink_matches_keys_with_nulls() -> Result<(), anyhow::Error> {
+    let table = TestTable::new("test_pg_sink_nullable_key", "select 1").await;
+    let mut sink = PostgresSink::new(create_postgres_client().await).await?;
+
+    let schema = TableSchema {
+        table_name: table_name(&table.table),
+        table_id: 1,
+        column_schemas: vec![
+            column("tenant", Type::INT4),
+            not_null_column("id", Type::INT8),
+            column("data", Type::TEXT),
+        ],
+        lookup_key: LookupKey::Key {
+            name: "test_pg_sink_nullable_key_key".to_string(),
+            columns: vec!["tenant".to_string(), "id".to_string()],
+        },
+        excluded_columns: vec![],
+        comment: None,
+    };
+    assert_eq!(
+        schema.column_schemas[0].match_predicate("$1"),
+        "tenant is not distinct from $1"
+    );
+    assert_eq!(schema.column_schemas[1].match_predicate("$2"), "id = $2");
+    sink.write_table_schemas(HashMap::from([(1, schema)]))
+        .await?;
+
+    let row = |tenant: Option<i32>, id: i64, data: &str| {
+        TableRow::new(vec![
+            tenant.map_or(Cell::Null, Cell::I32),
+            Cell::I64(id),
+            Cell::String(data.to_string()),
+        ])
+    };
+    let events = vec![
+        CdcEvent::Insert((1, row(None, 1, "a"), None)),
+        CdcEvent::Insert((1, row(Some(1), 1, "b"), None)),
+        CdcEvent::Insert((1, row(None, 2, "c"), None)),
+    ];
+    sink.write_cdc_events(events).await?;
+    let events = vec![
+        // The null key is matched by both the upsert and the delete
+        CdcEvent::Update((1, None, row(None, 1, "d"), None)),
+        CdcEvent::Delete((1, row(None, 2, ""), None)),
+        CdcEvent::Delete((1, row(Some(1), 1, ""), None)),
+    ];
+    sink.write_cdc_events(events).await?;
+
+    let rows = table
+        .client
+        .query(
+            "select tenant, id, data from test_pg_sink_nullable_key order by id",
+            &[],
+        )
+        .await?;
+    let rows: Vec<(Option<i32>, i64, String)> = rows
+        .iter()
+        .map(|row| (row.get(0), row.get(1), row.get(2)))
+        .collect();
+    assert_eq!(rows, vec![(None, 1, "d".to_string())]);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_postgres_sink_drops_aborted_streamed_transactions() -> Result<(), anyhow::Error> {
+    let table = TestTable::new("test_pg_sink_streamed", "select 1").await;
+    let mut sink = PostgresSink::new(create_postgres_client().await).await?;
+
+    let schema = TableSchema {
+        table_name: table_name(&table.table),
+        table_id: 1,
+        column_schemas: vec![not_null_column("id", Type::INT8)],
+        lookup_key: LookupKey::Key {
+            name: "test_pg_sink_streamed_pkey".to_string(),
+            columns: vec!["id".to_string()],
+        },
+        excluded_columns: vec![],
+        comment: None,
+    };
+    sink.write_table_schemas(HashMap::from([(1, schema)]))
+        .await?;
+
+    let insert =
+        |id: i64, xid: u32| CdcEvent::Insert((1, TableRow::new(vec![Cell::I64(id)]), Some(xid)));
+    let ids = || async {
+        let rows = table
+            .client
+            .query("select id from test_pg_sink_streamed order by id", &[])
+            .await
+            .unwrap();
+        rows.iter().map(|row| row.get(0)).collect::<Vec<i64>>()
+    };
+
+    // Transaction 10 is streamed in two segments, its subtransaction 11 is aborted
+    let mut events = vec![stream_start(10)];
+    events.extend((1..=100).map(|id| insert(id, 10)));
+    events.extend([insert(101, 11), stream_stop()]);
+    sink.write_cdc_events(events).await?;
+    sink.write_cdc_events(vec![
+        stream_start(10),
+        insert(102, 11),
+        stream_stop(),
+        stream_abort(10, 11),
+    ])
+    .await?;
+    // Transaction 20 is streamed and aborted as a whole
+    let mut events = vec![stream_start(20)];
+    events.extend((1000..2000).map(|id| insert(id, 20)));
+    events.extend([stream_stop(), stream_abort(20, 20)]);
+    sink.write_cdc_events(events).await?;
+    assert!(ids().await.is_empty());
+
+    sink.write_cdc_events(vec![stream_commit(10, 100)]).await?;
+    assert_eq!(ids().await, (1..=100).collect::<Vec<_>>());
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_postgres_sink_keeps_unchanged_toast_values() -> Result<(), anyhow::Error> {
+    let table = TestTable::new("test_pg_sink_toast", "select 1").await;
+    let mut sink = PostgresSink::new(create_postgres_client().await).await?;
+
+    let schema = TableSchema {
+        table_name: table_name(&table.table),
+        table_id: 1,
+        column_schemas: vec![
+            not_null_column("id", Type::INT8),
+            column("data", Type::TEXT),
+            column("blob", Type::TEXT),
+        ],
+        lookup_key: LookupKey::Key {
+            name: "test_pg_sink_toast_pkey".to_string(),
+            columns: vec!["id".to_string()],
+        },
+        excluded_columns: vec![],
+        comment: None,
+    };
+    sink.write_table_schemas(HashMap::from([(1, schema)]))
+        .await?;
+
+    let row = |id: i64, data: &str, blob: &str| {
+        TableRow::new(vec![
+            Cell::I64(id),
+            Cell::String(data.to_string()),
+            Cell::String(blob.to_string()),
+        ])
+    };
+    // The placeholder of an unchanged TOAST value
+    let unchanged_blob = |id: i64, data: &str| TableRow {
+        unchanged_toast: vec![2],
+        ..row(id, data, "")
+    };
+    sink.write_table_rows(vec![row(1, "a", "large 1"), row(2, "b", "large 2")], 1)
+        .await?;
+    sink.write_cdc_events(vec![
+        CdcEvent::Update((1, None, unchanged_blob(1, "c"), None)),
+        // The blob of row 2 changes before an update leaving it unchanged
+        CdcEvent::Update((1, None, row(2, "d", "large 3"), None)),
+        CdcEvent::Update((1, None, unchanged_blob(2, "e"), None)),
+    ])
+    .await?;
+
+    let rows = table
+        .client
+        .query(
+            "select id, data, blob from test_pg_sink_toast order by id",
+            &[],
+        )
+        .await?;
+    let rows: Vec<(i64, String, String)> = rows
+        .iter()
+        .map(|row| (row.get(0), row.get(1), row.get(2)))
+        .collect();
+    assert_eq!(
+        rows,
+        vec![
+            (1, "c".to_string(), "large 1".to_string()),
+            (2, "e".to_string(), "large 3".to_string()),
+        ]
+    );
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_postgres_sink_retries_deadlocks() -> Result<(), anyhow::Error> {
+    let table = TestTable::new("test_pg_sink_deadlock", "select 1").await;
+    let mut sink = PostgresSink::new(create_postgres_client().await).await?;
+    sink.set_deadlock_retries(3, Duration::from_millis(10));
+
+    let schema = TableSchema {
+        table_name: table_name(&table.table),
+        table_id: 1,
+        column_schemas: vec![not_null_column("id", Type::INT4)],
+        lookup_key: LookupKey::Key {
+            name: "test_pg_sink_deadlock_pkey".to_string(),
+            columns: vec!["id".to_string()],
+        },
+        excluded_columns: vec![],
+        comment: None,
+    };
+    sink.write_table_schemas(HashMap::from([(1, schema)]))
+        .await?;
+
+    // Sequences aren't rolled back with the failed transactions, so the trigger
+    // fails the first `deadlocks` writes
+    table
+        .client
+        .batch_execute(
+            "drop sequence if exists test_pg_sink_deadlock_attempts;
+            create sequence test_pg_sink_deadlock_attempts;
+            drop table if exists test_pg_sink_deadlock_config;
+            create table test_pg_sink_deadlock_config (deadlocks int not null);
+            create or replace function test_pg_sink_deadlock() returns trigger language plpgsql as $$
+            begin
+                if nextval('test_pg_sink_deadlock_attempts') <= (select deadlocks from test_pg_sink_deadlock_config) then
+                    raise exception 'simulated deadlock' using errcode = 'deadlock_detected';
+                end if;
+                return new;
+            end $$;
+            create trigger test_pg_sink_deadlock before insert on test_pg_sink_deadlock
+                for each row execute function test_pg_sink_deadlock();
+            insert into test_pg_sink_deadlock_config values (2);",
+        )
+        .await?;
+    let insert = |id: i32| CdcEvent::Insert((1, TableRow::new(vec![Cell::I32(id)]), None));
+
+    // The third attempt succeeds
+    sink.write_cdc_events(vec![insert(1)]).await?;
+    let attempts: i64 = table
+        .client
+        .query_one("select last_value from test_pg_sink_deadlock_attempts", &[])
+        .await?
+        .get(0);
+    assert_eq!(attempts, 3);
+
+    // The deadlock is returned once all attempts failed
+    table
+        .client
+        .batch_execute(
+            "alter sequence test_pg_sink_deadlock_attempts restart;
+            update test_pg_sink_deadlock_config set deadlocks = 3;",
+        )
+        .await?;
+    let result = sink.write_cdc_events(vec![insert(2)]).await;
+    assert!(matches!(
+        result,
+        Err(PostgresSinkError::TokioPostgres(e))
+            if e.code() == Some(&SqlState::T_R_DEADLOCK_DETECTED)
+    ));
+
+    let rows = table
+        .client
+        .query("select id from test_pg_sink_deadlock order by id", &[])
+        .await?;
+    let ids: Vec<i32> = rows.iter().map(|row| row.get(0)).collect();
+    assert_eq!(ids, vec![1]);
+
+    table
+        .client
+        .batch_execute(
+            "drop table test_pg_sink_deadlock_config;
+            drop function test_pg_sink_deadlock cascade;
+            drop sequence test_pg_sink_deadlock_attempts;",
+        )
+        .await?;
+
+    Ok(())
+}
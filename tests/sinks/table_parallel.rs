@@ -0,0 +1,186 @@
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{Arc, Mutex},
+};
+
+use async_trait::async_trait;
+use pg_replicate::{
+    conversions::{cdc_event::CdcEvent, table_row::TableRow, Cell},
+    pipeline::{
+        sinks::{
+            table_parallel::{TableParallelSink, TableParallelSinkError},
+            BatchSink, InfallibleSinkError,
+        },
+        PipelineResumptionState,
+    },
+    table::{TableId, TableSchema},
+};
+use tokio_postgres::types::PgLsn;
+
+use crate::common::events::{begin, commit};
+
+type Batches = Arc<Mutex<Vec<Vec<String>>>>;
+
+/// Records the batches of events written to it, inserts as `table:id`, and
+/// confirms `lsn`
+struct Worker {
+    batches: Batches,
+    copied_tables: HashSet<TableId>,
+    lsn: u64,
+}
+
+impl Worker {
+    fn new(lsn: u64) -> (Worker, Batches) {
+        let batches = Arc::new(Mutex::new(vec![]));
+        let worker = Worker {
+            batches: batches.clone(),
+            copied_tables: HashSet::new(),
+            lsn,
+        };
+        (worker, batches)
+    }
+}
+
+#[async_trait]
+impl BatchSink for Worker {
+    type Error = InfallibleSinkError;
+
+    async fn get_resumption_state(&mut self) -> Result<PipelineResumptionState, Self::Error> {
+        Ok(PipelineResumptionState {
+            copied_tables: self.copied_tables.clone(),
+            last_lsn: PgLsn::from(self.lsn),
+            backfill_cursors: HashMap::new(),
+            slot_name: None,
+        })
+    }
+
+    async fn write_table_schemas(
+        &mut self,
+        _table_schemas: HashMap<TableId, TableSchema>,
+    ) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
+    async fn write_table_rows(
+        &mut self,
+        _rows: Vec<TableRow>,
+        _table_id: TableId,
+    ) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
+    async fn write_cdc_events(&mut self, events: Vec<CdcEvent>) -> Result<PgLsn, Self::Error> {
+        let events = events
+            .iter()
+            .map(|event| match event {
+                CdcEvent::Insert((table_id, row, _)) => match row.values[0] {
+                    Cell::I32(id) => format!("{table_id}:{id}"),
+                    _ => panic!("unexpected cell"),
+                },
+                CdcEvent::Begin(_) => "begin".to_string(),
+                CdcEvent::Commit(_) => "commit".to_string(),
+                _ => panic!("unexpected event {event:?}"),
+            })
+            .collect();
+        self.batches.lock().unwrap().push(events);
+        Ok(PgLsn::from(self.lsn))
+    }
+
+    async fn table_copied(&mut self, table_id: TableId) -> Result<(), Self::Error> {
+        self.copied_tables.insert(table_id);
+        Ok(())
+    }
+
+    async fn truncate_table(&mut self, _table_id: TableId) -> Result<(), Self::Error> {
+        Ok(())
+    }
+}
+
+fn insert(table_id: TableId, id: i32) -> CdcEvent {
+    let row = TableRow::new(vec![Cell::I32(id)]);
+    CdcEvent::Insert((table_id, row, None))
+}
+
+fn batch() -> Vec<CdcEvent> {
+    vec![
+        begin(1),
+        insert(2, 1),
+        insert(3, 1),
+        insert(2, 2),
+        commit(10),
+        begin(2),
+        insert(3, 2),
+        commit(20),
+    ]
+}
+
+#[tokio::test]
+async fn test_table_parallel_sink_splits_tables_across_workers() {
+    let (even, even_batches) = Worker::new(200);
+    let (odd, odd_batches) = Worker::new(100);
+    let mut sink = TableParallelSink::new(vec![even, odd]);
+    assert_eq!(sink.parallelism(), 2);
+
+    let lsn = sink.write_cdc_events(batch()).await.unwrap();
+
+    // The lowest lsn of the workers is confirmed
+    assert_eq!(lsn, PgLsn::from(100));
+    // Each worker gets its tables' changes in order, and every transaction boundary
+    assert_eq!(
+        *even_batches.lock().unwrap(),
+        vec![vec!["begin", "2:1", "2:2", "commit", "begin", "commit"]]
+    );
+    assert_eq!(
+        *odd_batches.lock().unwrap(),
+        vec![vec!["begin", "3:1", "commit", "begin", "3:2", "commit"]]
+    );
+}
+
+#[tokio::test]
+async fn test_consistent_table_parallel_sink_writes_one_transaction_at_a_time() {
+    let (even, even_batches) = Worker::new(100);
+    let (odd, odd_batches) = Worker::new(100);
+    let mut sink = TableParallelSink::new(vec![even, odd]);
+    sink.set_consistent(true);
+
+    sink.write_cdc_events(batch()).await.unwrap();
+
+    assert_eq!(
+        *even_batches.lock().unwrap(),
+        vec![
+            vec!["begin", "2:1", "2:2", "commit"],
+            vec!["begin", "commit"]
+        ]
+    );
+    assert_eq!(
+        *odd_batches.lock().unwrap(),
+        vec![
+            vec!["begin", "3:1", "commit"],
+            vec!["begin", "3:2", "commit"]
+        ]
+    );
+}
+
+#[tokio::test]
+async fn test_table_parallel_sink_resumes_from_slowest_worker() {
+    let (mut even, _) = Worker::new(200);
+    let (mut odd, _) = Worker::new(100);
+    even.copied_tables.insert(2);
+    odd.copied_tables.insert(3);
+    let mut sink = TableParallelSink::new(vec![even, odd]);
+
+    sink.table_copied(4).await.unwrap();
+    let resumption_state = sink.get_resumption_state().await.unwrap();
+
+    assert_eq!(resumption_state.last_lsn, PgLsn::from(100));
+    assert_eq!(resumption_state.copied_tables, HashSet::from([2, 3, 4]));
+}
+
+#[tokio::test]
+async fn test_table_parallel_sink_without_workers_fails() {
+    let mut sink = TableParallelSink::<Worker>::new(vec![]);
+
+    let result = sink.write_cdc_events(vec![insert(1, 1)]).await;
+
+    assert!(matches!(result, Err(TableParallelSinkError::NoWorkers)));
+}
@@ -0,0 +1,291 @@
+use std::{
+    collections::HashMap,
+    time::{Duration, UNIX_EPOCH},
+};
+
+use bytes::{BufMut, Bytes, BytesMut};
+use futures::StreamExt;
+use pg_replicate::{
+    conversions::{cdc_event::CdcEvent, table_row::TableRow, Cell},
+    pipeline::sources::{
+        capture::{CaptureReader, CaptureWriter, CapturedMessage},
+        memory::MemoryCdcStream,
+    },
+    table::{LookupKey, TableName, TableSchema},
+};
+use tokio_postgres::types::{PgLsn, Type};
+
+use crate::common::schemas::not_null_column;
+
+const TABLE_ID: u32 = 16384;
+
+fn table_schemas() -> HashMap<u32, TableSchema> {
+    let table_schema = TableSchema {
+        table_name: TableName {
+            schema: "public".to_string(),
+            name: "memory_source".to_string(),
+        },
+        table_id: TABLE_ID,
+        column_schemas: vec![not_null_column("id", Type::INT4)],
+        lookup_key: LookupKey::Key {
+            name: "memory_source_pkey".to_string(),
+            columns: vec!["id".to_string()],
+        },
+        excluded_columns: vec![],
+        comment: None,
+    };
+    HashMap::from([(TABLE_ID, table_schema)])
+}
+
+#[tokio::test]
+async fn test_memory_cdc_stream_decodes_scripted_messages() {
+    let mut stream = MemoryCdcStream::new(table_schemas());
+
+    let mut begin = BytesMut::new();
+    begin.put_u8(b'B');
+    begin.put_u64(0x200);
+    begin.put_i64(0);
+    begin.put_u32(1);
+    stream.push_logical(PgLsn::from(0x100), begin.freeze());
+
+    let mut insert = BytesMut::new();
+    insert.put_u8(b'I');
+    insert.put_u32(TABLE_ID);
+    insert.put_u8(b'N');
+    insert.put_i16(1);
+    insert.put_u8(b't');
+    insert.put_i32(2);
+    insert.put_slice(b"42");
+    stream.push_logical(PgLsn::from(0x100), insert.freeze());
+
+    let mut commit = BytesMut::new();
+    commit.put_u8(b'C');
+    commit.put_u8(0);
+    commit.put_u64(0x200);
+    commit.put_u64(0x210);
+    commit.put_i64(0);
+    stream.push_logical(PgLsn::from(0x200), commit.freeze());
+
+    stream.push_keepalive(PgLsn::from(0x210), true);
+
+    let events: Vec<_> = stream.collect().await;
+    let events: Vec<_> = events.into_iter().map(|event| event.unwrap()).collect();
+    assert_eq!(events.len(), 4);
+    assert!(matches!(events[0], CdcEvent::Begin(_)));
+    match &events[1] {
+        CdcEvent::Insert((table_id, row, _)) => {
+            assert_eq!(*table_id, TABLE_ID);
+            assert!(matches!(row.values[..], [Cell::I32(42)]));
+        }
+        event => panic!("unexpected event {event:?}"),
+    }
+    match &events[2] {
+        CdcEvent::Commit(commit_body) => assert_eq!(commit_body.end_lsn(), 0x210),
+        event => panic!("unexpected event {event:?}"),
+    }
+    assert!(matches!(
+        events[3],
+        CdcEvent::KeepAliveRequested {
+            reply: true,
+            wal_end,
+        } if wal_end == PgLsn::from(0x210)
+    ));
+}
+
+#[tokio::test]
+async fn test_memory_cdc_stream_rejects_invalid_messages() {
+    let mut stream = MemoryCdcStream::new(table_schemas());
+    stream.push_raw(bytes::Bytes::from_static(b"x"));
+
+    let events: Vec<_> = stream.collect().await;
+    assert_eq!(events.len(), 1);
+    assert!(events[0].is_err());
+}
+
+#[tokio::test]
+async fn test_memory_cdc_stream_replays_captures() -> Result<(), anyhow::Error> {
+    let path = std::env::temp_dir().join("pg_replicate_test_memory_replays_captures");
+    let mut begin = BytesMut::new();
+    begin.put_u8(b'B');
+    begin.put_u64(0x200);
+    begin.put_i64(0);
+    begin.put_u32(1);
+    let mut xlog_data = BytesMut::new();
+    xlog_data.put_u8(b'w');
+    xlog_data.put_u64(0x100);
+    xlog_data.put_u64(0x100);
+    xlog_data.put_i64(0);
+    xlog_data.put_slice(&begin);
+    let mut keepalive = BytesMut::new();
+    keepalive.put_u8(b'k');
+    keepalive.put_u64(0x210);
+    keepalive.put_i64(0);
+    keepalive.put_u8(1);
+
+    let mut writer = CaptureWriter::create(&path)?;
+    writer.write(&CapturedMessage::new(xlog_data.freeze()))?;
+    writer.write(&CapturedMessage::new(keepalive.freeze()))?;
+    writer.flush()?;
+    drop(writer);
+
+    let mut stream = MemoryCdcStream::new(table_schemas());
+    stream.push_capture(CaptureReader::open(&path)?)?;
+    let events: Vec<_> = stream.collect().await;
+    let events: Vec<_> = events.into_iter().map(|event| event.unwrap()).collect();
+    assert_eq!(events.len(), 2);
+    assert!(matches!(events[0], CdcEvent::Begin(_)));
+    assert!(matches!(
+        events[1],
+        CdcEvent::KeepAliveRequested { reply: true, .. }
+    ));
+
+    std::fs::remove_file(&path)?;
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_memory_cdc_stream_emits_heartbeats() {
+    let mut stream = MemoryCdcStream::new(table_schemas());
+    stream.emit_heartbeats();
+    stream.push_keepalive(PgLsn::from(0x300), false);
+
+    let events: Vec<_> = stream.collect().await;
+    let events: Vec<_> = events.into_iter().map(|event| event.unwrap()).collect();
+    assert_eq!(events.len(), 2);
+    match &events[0] {
+        CdcEvent::Heartbeat { lsn, server_time } => {
+            assert_eq!(*lsn, PgLsn::from(0x300));
+            // The keepalive was sent at Postgres' epoch
+            assert_eq!(*server_time, UNIX_EPOCH + Duration::from_secs(946_684_800));
+        }
+        event => panic!("unexpected event {event:?}"),
+    }
+    assert!(matches!(
+        events[1],
+        CdcEvent::KeepAliveRequested { reply: false, .. }
+    ));
+}
+
+/// Builds a Relation message of a table with an int4 `id` and a text `name` column,
+/// with `id` as the replica identity's key unless the identity is `FULL`
+fn relation_message(replica_identity: u8) -> Bytes {
+    let mut buf = BytesMut::new();
+    buf.put_u8(b'R');
+    buf.put_u32(TABLE_ID);
+    buf.put_slice(b"public\0identity_flip\0");
+    buf.put_u8(replica_identity);
+    buf.put_i16(2);
+    for (flags, name, typ) in [(1, "id", Type::INT4), (0, "name", Type::TEXT)] {
+        // Postgres flags all columns as key columns of a full identity
+        let flags = if replica_identity == b'f' { 1 } else { flags };
+        buf.put_i8(flags);
+        buf.put_slice(name.as_bytes());
+        buf.put_u8(0);
+        buf.put_u32(typ.oid());
+        buf.put_i32(-1);
+    }
+    buf.freeze()
+}
+
+fn put_tuple(buf: &mut BytesMut, kind: u8, values: &[Option<&str>]) {
+    buf.put_u8(kind);
+    buf.put_i16(values.len() as i16);
+    for value in values {
+        match value {
+            Some(value) => {
+                buf.put_u8(b't');
+                buf.put_i32(value.len() as i32);
+                buf.put_slice(value.as_bytes());
+            }
+            None => buf.put_u8(b'n'),
+        }
+    }
+}
+
+fn update_message(old: (u8, &[Option<&str>]), new: &[Option<&str>]) -> Bytes {
+    let mut buf = BytesMut::new();
+    buf.put_u8(b'U');
+    buf.put_u32(TABLE_ID);
+    put_tuple(&mut buf, old.0, old.1);
+    put_tuple(&mut buf, b'N', new);
+    buf.freeze()
+}
+
+fn delete_message(old: (u8, &[Option<&str>])) -> Bytes {
+    let mut buf = BytesMut::new();
+    buf.put_u8(b'D');
+    buf.put_u32(TABLE_ID);
+    put_tuple(&mut buf, old.0, old.1);
+    buf.freeze()
+}
+
+/// Describes the cells of a row, nulls as `None`
+fn describe(row: &TableRow) -> Vec<Option<String>> {
+    row.values
+        .iter()
+        .map(|cell| match cell {
+            Cell::Null => None,
+            Cell::I32(id) => Some(id.to_string()),
+            Cell::String(name) => Some(name.clone()),
+            cell => panic!("unexpected cell {cell:?}"),
+        })
+        .collect()
+}
+
+#[tokio::test]
+async fn test_memory_cdc_stream_follows_replica_identity_changes() {
+    let mut table_schemas = table_schemas();
+    let table_schema = table_schemas.get_mut(&TABLE_ID).unwrap();
+    table_schema
+        .column_schemas
+        .push(not_null_column("name", Type::TEXT));
+    let mut stream = MemoryCdcStream::new(table_schemas);
+
+    // With a full identity old tuples hold the whole row
+    stream.push_logical(PgLsn::from(0x100), relation_message(b'f'));
+    stream.push_logical(
+        PgLsn::from(0x100),
+        update_message((b'O', &[Some("1"), Some("a")]), &[Some("1"), Some("b")]),
+    );
+    stream.push_logical(
+        PgLsn::from(0x100),
+        delete_message((b'O', &[Some("1"), Some("b")])),
+    );
+    // After `ALTER TABLE ... REPLICA IDENTITY DEFAULT` they hold just the key
+    stream.push_logical(PgLsn::from(0x200), relation_message(b'd'));
+    stream.push_logical(
+        PgLsn::from(0x200),
+        update_message((b'K', &[Some("2"), None]), &[Some("3"), Some("c")]),
+    );
+    stream.push_logical(
+        PgLsn::from(0x200),
+        delete_message((b'K', &[Some("3"), None])),
+    );
+
+    let events: Vec<_> = stream.collect().await;
+    let events: Vec<_> = events.into_iter().map(|event| event.unwrap()).collect();
+    assert_eq!(events.len(), 6);
+    let s = |value: &str| Some(value.to_string());
+    match &events[1] {
+        CdcEvent::Update((_, Some(old_row), new_row, _)) => {
+            assert_eq!(describe(old_row), vec![s("1"), s("a")]);
+            assert_eq!(describe(new_row), vec![s("1"), s("b")]);
+        }
+        event => panic!("unexpected event {event:?}"),
+    }
+    match &events[2] {
+        CdcEvent::Delete((_, row, _)) => assert_eq!(describe(row), vec![s("1"), s("b")]),
+        event => panic!("unexpected event {event:?}"),
+    }
+    match &events[4] {
+        CdcEvent::Update((_, Some(old_row), new_row, _)) => {
+            assert_eq!(describe(old_row), vec![s("2"), None]);
+            assert_eq!(describe(new_row), vec![s("3"), s("c")]);
+        }
+        event => panic!("unexpected event {event:?}"),
+    }
+    match &events[5] {
+        CdcEvent::Delete((_, row, _)) => assert_eq!(describe(row), vec![s("3"), None]),
+        event => panic!("unexpected event {event:?}"),
+    }
+}
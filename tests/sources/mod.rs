@@ -0,0 +1,5 @@
+pub mod capture;
+#[cfg(feature = "memory_source")]
+pub mod memory;
+pub mod postgres;
+pub mod slot_name;
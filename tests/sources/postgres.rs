@@ -0,0 +1,1522 @@
+use std::{
+    sync::{atomic::Ordering, Mutex},
+    time::Duration,
+};
+
+use futures::{Stream, StreamExt};
+use pg_replicate::{
+    clients::postgres::ReplicationClientError,
+    conversions::{
+        cdc_event::{CdcEvent, CdcEventConversionError},
+        table_row::CopyFormat,
+        Cell,
+    },
+    pipeline::sources::{
+        backfill::{RowCountMismatch, SnapshotBackfill, SnapshotBackfillError},
+        postgres::{
+            CdcStreamError, PostgresSource, PostgresSourceError, TableCopyStream,
+            TableCopyStreamError, TableNamesFrom,
+        },
+        Source,
+    },
+    table::TableName,
+};
+use tokio_postgres::types::{PgLsn, Type};
+
+use crate::{
+    clients::create_replication_client,
+    common::{
+        postgres_utils::{drop_replication_slot, TestTable},
+        POSTGRES_DBNAME, POSTGRES_HOST, POSTGRES_PASSWORD, POSTGRES_PORT, POSTGRES_USER,
+    },
+};
+
+#[tokio::test]
+async fn test_unchanged_toast_values_are_fetched() -> Result<(), anyhow::Error> {
+    let table_name = "test_unchanged_toast";
+    let publication = "test_unchanged_toast_pub";
+    let slot_name = "test_unchanged_toast_slot";
+
+    let test_table = TestTable::new(
+        table_name,
+        &format!("CREATE TABLE {table_name} (id INT PRIMARY KEY, payload TEXT, counter INT)"),
+    )
+    .await;
+    let client = &test_table.client;
+    // External storage moves large values out of line without compressing them
+    client
+        .simple_query(&format!(
+            "ALTER TABLE {table_name} ALTER COLUMN payload SET STORAGE EXTERNAL;
+            INSERT INTO {table_name} VALUES (1, repeat('x', 10000), 0);
+            DROP PUBLICATION IF EXISTS {publication};
+            CREATE PUBLICATION {publication} FOR TABLE {table_name};
+            SELECT pg_drop_replication_slot(slot_name) FROM pg_replication_slots WHERE slot_name = '{slot_name}';"
+        ))
+        .await?;
+
+    let mut source = PostgresSource::new(
+        POSTGRES_HOST,
+        POSTGRES_PORT,
+        POSTGRES_DBNAME,
+        POSTGRES_USER,
+        Some(POSTGRES_PASSWORD.to_string()),
+        Some(slot_name.to_string()),
+        TableNamesFrom::Publication(publication.to_string()),
+    )
+    .await?;
+    source.commit_transaction().await?;
+    source.fetch_unchanged_toast_values(create_replication_client().await);
+
+    client
+        .simple_query(&format!(
+            "UPDATE {table_name} SET counter = counter + 1 WHERE id = 1"
+        ))
+        .await?;
+
+    let mut cdc_stream = Box::pin(source.get_cdc_stream(PgLsn::from(0)).await?);
+    let new_row = loop {
+        match cdc_stream.next().await {
+            Some(event) => {
+                if let CdcEvent::Update((_, _, new_row, _)) = event? {
+                    break new_row;
+                }
+            }
+            None => panic!("cdc stream ended before the update"),
+        }
+    };
+
+    match &new_row.values[..] {
+        [Cell::I32(1), Cell::String(payload), Cell::I32(1)] => {
+            assert_eq!(payload, &"x".repeat(10000))
+        }
+        values => panic!("unexpected row {values:?}"),
+    }
+
+    drop(cdc_stream);
+    drop(source);
+    drop_replication_slot(client, slot_name).await;
+    client
+        .simple_query(&format!("DROP PUBLICATION IF EXISTS {publication}"))
+        .await?;
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_stale_schema_is_refreshed_on_decode_error() -> Result<(), anyhow::Error> {
+    let table_name = "test_schema_refresh";
+    let publication = "test_schema_refresh_pub";
+    let slot_name = "test_schema_refresh_slot";
+
+    let test_table = TestTable::new(
+        table_name,
+        &format!("CREATE TABLE {table_name} (id INT PRIMARY KEY, data INT)"),
+    )
+    .await;
+    let client = &test_table.client;
+    client
+        .simple_query(&format!(
+            "DROP PUBLICATION IF EXISTS {publication};
+            CREATE PUBLICATION {publication} FOR TABLE {table_name};"
+        ))
+        .await?;
+    drop_replication_slot(client, slot_name).await;
+
+    let mut source = PostgresSource::new(
+        POSTGRES_HOST,
+        POSTGRES_PORT,
+        POSTGRES_DBNAME,
+        POSTGRES_USER,
+        Some(POSTGRES_PASSWORD.to_string()),
+        Some(slot_name.to_string()),
+        TableNamesFrom::Publication(publication.to_string()),
+    )
+    .await?;
+    source.commit_transaction().await?;
+    source.refresh_schemas_on_decode_error(create_replication_client().await);
+
+    // The cached schema still has data as an integer
+    client
+        .simple_query(&format!(
+            "ALTER TABLE {table_name} ALTER COLUMN data TYPE TEXT;
+            INSERT INTO {table_name} VALUES (1, 'not a number');"
+        ))
+        .await?;
+
+    let mut cdc_stream = Box::pin(source.get_cdc_stream(PgLsn::from(0)).await?);
+    let mut refreshed_schema = None;
+    let row = loop {
+        match cdc_stream.next().await {
+            Some(event) => match event? {
+                CdcEvent::Schema(table_schema) => refreshed_schema = Some(table_schema),
+                CdcEvent::Insert((_, row, _)) => break row,
+                _ => {}
+            },
+            None => panic!("cdc stream ended before the insert"),
+        }
+    };
+
+    // The refreshed schema comes right before the change decoded with it
+    let refreshed_schema = refreshed_schema.expect("missing refreshed schema");
+    assert_eq!(refreshed_schema.column_schemas[1].typ, Type::TEXT);
+    match &row.values[..] {
+        [Cell::I32(1), Cell::String(data)] => assert_eq!(data, "not a number"),
+        values => panic!("unexpected row {values:?}"),
+    }
+
+    drop(cdc_stream);
+    drop(source);
+    drop_replication_slot(client, slot_name).await;
+    client
+        .simple_query(&format!("DROP PUBLICATION IF EXISTS {publication}"))
+        .await?;
+
+    Ok(())
+}
+
+/// Skips events up to the next error, failing on inserts
+async fn next_error(
+    cdc_stream: &mut (impl Stream<Item = Result<CdcEvent, CdcStreamError>> + Unpin),
+) -> CdcStreamError {
+    loop {
+        match cdc_stream.next().await {
+            Some(Ok(CdcEvent::Insert((_, row, _)))) => panic!("unexpected insert {row:?}"),
+            Some(Ok(_)) => {}
+            Some(Err(e)) => return e,
+            None => panic!("cdc stream ended before the error"),
+        }
+    }
+}
+
+#[tokio::test]
+async fn test_schema_is_refreshed_once_per_relation() -> Result<(), anyhow::Error> {
+    let table_name = "test_schema_refresh_once";
+    let publication = "test_schema_refresh_once_pub";
+    let slot_name = "test_schema_refresh_once_slot";
+
+    let test_table = TestTable::new(
+        table_name,
+        &format!("CREATE TABLE {table_name} (id INT PRIMARY KEY, data INT)"),
+    )
+    .await;
+    let client = &test_table.client;
+    client
+        .simple_query(&format!(
+            "DROP PUBLICATION IF EXISTS {publication};
+            CREATE PUBLICATION {publication} FOR TABLE {table_name};"
+        ))
+        .await?;
+    drop_replication_slot(client, slot_name).await;
+
+    let mut source = PostgresSource::new(
+        POSTGRES_HOST,
+        POSTGRES_PORT,
+        POSTGRES_DBNAME,
+        POSTGRES_USER,
+        Some(POSTGRES_PASSWORD.to_string()),
+        Some(slot_name.to_string()),
+        TableNamesFrom::Publication(publication.to_string()),
+    )
+    .await?;
+    source.commit_transaction().await?;
+    source.refresh_schemas_on_decode_error(create_replication_client().await);
+
+    // Both inserts follow the same Relation message, and by the time the stream
+    // refreshes the schema data is an integer again
+    client
+        .simple_query(&format!(
+            "ALTER TABLE {table_name} ALTER COLUMN data TYPE TEXT;
+            INSERT INTO {table_name} VALUES (1, 'one');
+            INSERT INTO {table_name} VALUES (2, 'two');
+            ALTER TABLE {table_name} ALTER COLUMN data TYPE INT USING 0;"
+        ))
+        .await?;
+
+    let mut cdc_stream = Box::pin(source.get_cdc_stream(PgLsn::from(0)).await?);
+    assert!(matches!(
+        next_error(&mut cdc_stream).await,
+        CdcStreamError::CdcEventConversion(CdcEventConversionError::UndecodableRow(_))
+    ));
+
+    // A refresh would now decode the second insert, but the table was refreshed since
+    // its last Relation message already
+    client
+        .simple_query(&format!(
+            "ALTER TABLE {table_name} ALTER COLUMN data TYPE TEXT"
+        ))
+        .await?;
+    assert!(matches!(
+        next_error(&mut cdc_stream).await,
+        CdcStreamError::CdcEventConversion(CdcEventConversionError::UndecodableRow(_))
+    ));
+
+    drop(cdc_stream);
+    drop(source);
+    drop_replication_slot(client, slot_name).await;
+    client
+        .simple_query(&format!("DROP PUBLICATION IF EXISTS {publication}"))
+        .await?;
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_schema_changes_are_emitted() -> Result<(), anyhow::Error> {
+    let table_name = "test_schema_changes";
+    let publication = "test_schema_changes_pub";
+    let slot_name = "test_schema_changes_slot";
+
+    let test_table = TestTable::new(
+        table_name,
+        &format!("CREATE TABLE {table_name} (id INT PRIMARY KEY, data INT)"),
+    )
+    .await;
+    let client = &test_table.client;
+    client
+        .simple_query(&format!(
+            "DROP PUBLICATION IF EXISTS {publication};
+            CREATE PUBLICATION {publication} FOR TABLE {table_name};"
+        ))
+        .await?;
+    drop_replication_slot(client, slot_name).await;
+
+    let mut source = PostgresSource::new(
+        POSTGRES_HOST,
+        POSTGRES_PORT,
+        POSTGRES_DBNAME,
+        POSTGRES_USER,
+        Some(POSTGRES_PASSWORD.to_string()),
+        Some(slot_name.to_string()),
+        TableNamesFrom::Publication(publication.to_string()),
+    )
+    .await?;
+    source.commit_transaction().await?;
+    source.emit_schema_changes();
+    assert!(matches!(
+        source.get_cdc_stream(PgLsn::from(0)).await,
+        Err(PostgresSourceError::MissingSchemaRefreshClient)
+    ));
+    source.refresh_schemas_on_decode_error(create_replication_client().await);
+
+    // Only the Relation message sent after the change differs from the cached schema
+    client
+        .simple_query(&format!(
+            "INSERT INTO {table_name} VALUES (1, 1);
+            ALTER TABLE {table_name} ADD COLUMN extra TEXT;
+            INSERT INTO {table_name} VALUES (2, 2, 'extra');"
+        ))
+        .await?;
+
+    let mut cdc_stream = Box::pin(source.get_cdc_stream(PgLsn::from(0)).await?);
+    let mut events = vec![];
+    let row = loop {
+        match cdc_stream.next().await {
+            Some(event) => match event? {
+                CdcEvent::Insert((_, row, _)) if row.values.len() == 3 => break row,
+                CdcEvent::Insert(_) => events.push("insert".to_string()),
+                CdcEvent::Relation(_) => events.push("relation".to_string()),
+                CdcEvent::Schema(table_schema) => {
+                    let columns: Vec<&str> = table_schema
+                        .column_schemas
+                        .iter()
+                        .map(|c| c.name.as_str())
+                        .collect();
+                    events.push(format!("schema {}", columns.join(",")));
+                }
+                _ => {}
+            },
+            None => panic!("cdc stream ended before the insert"),
+        }
+    };
+
+    assert_eq!(
+        events,
+        vec!["relation", "insert", "relation", "schema id,data,extra"]
+    );
+    match &row.values[..] {
+        [Cell::I32(2), Cell::I32(2), Cell::String(extra)] => assert_eq!(extra, "extra"),
+        values => panic!("unexpected row {values:?}"),
+    }
+
+    drop(cdc_stream);
+    drop(source);
+    drop_replication_slot(client, slot_name).await;
+    client
+        .simple_query(&format!("DROP PUBLICATION IF EXISTS {publication}"))
+        .await?;
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_dropped_tables_are_skipped() -> Result<(), anyhow::Error> {
+    let dropped_table = "test_dropped_table";
+    let kept_table = "test_dropped_table_kept";
+    let publication = "test_dropped_table_pub";
+    let slot_name = "test_dropped_table_slot";
+
+    let _dropped = TestTable::new(
+        dropped_table,
+        &format!("CREATE TABLE {dropped_table} (id INT PRIMARY KEY)"),
+    )
+    .await;
+    let kept = TestTable::new(
+        kept_table,
+        &format!("CREATE TABLE {kept_table} (id INT PRIMARY KEY)"),
+    )
+    .await;
+    let client = &kept.client;
+    client
+        .simple_query(&format!(
+            "DROP PUBLICATION IF EXISTS {publication};
+            CREATE PUBLICATION {publication} FOR TABLE {dropped_table}, {kept_table};"
+        ))
+        .await?;
+    drop_replication_slot(client, slot_name).await;
+
+    let mut source = PostgresSource::new(
+        POSTGRES_HOST,
+        POSTGRES_PORT,
+        POSTGRES_DBNAME,
+        POSTGRES_USER,
+        Some(POSTGRES_PASSWORD.to_string()),
+        Some(slot_name.to_string()),
+        TableNamesFrom::Publication(publication.to_string()),
+    )
+    .await?;
+    source.commit_transaction().await?;
+    let dropped_table_id = source
+        .get_table_schemas()
+        .values()
+        .find(|table_schema| table_schema.table_name.name == dropped_table)
+        .map(|table_schema| table_schema.table_id)
+        .expect("missing schema of the dropped table");
+    source.refresh_schemas_on_decode_error(create_replication_client().await);
+    source.emit_schema_changes();
+    source.skip_dropped_tables();
+
+    // The table is gone by the time the stream fetches its changed schema
+    client
+        .simple_query(&format!(
+            "ALTER TABLE {dropped_table} ADD COLUMN extra TEXT;
+            INSERT INTO {dropped_table} VALUES (1, 'extra');
+            DROP TABLE {dropped_table};
+            INSERT INTO {kept_table} VALUES (2);"
+        ))
+        .await?;
+
+    let mut cdc_stream = Box::pin(source.get_cdc_stream(PgLsn::from(0)).await?);
+    let mut dropped = vec![];
+    let row = loop {
+        match cdc_stream.next().await {
+            Some(Ok(CdcEvent::TableDropped { table_id })) => dropped.push(table_id),
+            Some(Ok(CdcEvent::Insert((_, row, _)))) => break row,
+            Some(Ok(_)) => {}
+            // The dropped table's remaining changes can't be decoded, the pipeline
+            // skips these
+            Some(Err(CdcStreamError::CdcEventConversion(
+                CdcEventConversionError::MissingSchema(table_id),
+            ))) => assert_eq!(table_id, dropped_table_id),
+            Some(Err(e)) => return Err(e.into()),
+            None => panic!("cdc stream ended before the insert"),
+        }
+    };
+
+    assert_eq!(dropped, vec![dropped_table_id]);
+    assert!(matches!(row.values[..], [Cell::I32(2)]));
+
+    drop(cdc_stream);
+    drop(source);
+    drop_replication_slot(client, slot_name).await;
+    client
+        .simple_query(&format!("DROP PUBLICATION IF EXISTS {publication}"))
+        .await?;
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_cdc_stream_waits_for_active_slot() -> Result<(), anyhow::Error> {
+    let table_name = "test_active_slot";
+    let publication = "test_active_slot_pub";
+    let slot_name = "test_active_slot_slot";
+
+    let test_table = TestTable::new(
+        table_name,
+        &format!("CREATE TABLE {table_name} (id INT PRIMARY KEY)"),
+    )
+    .await;
+    let client = &test_table.client;
+    client
+        .simple_query(&format!(
+            "DROP PUBLICATION IF EXISTS {publication};
+            CREATE PUBLICATION {publication} FOR TABLE {table_name};"
+        ))
+        .await?;
+    drop_replication_slot(client, slot_name).await;
+
+    let create_source = || {
+        PostgresSource::new(
+            POSTGRES_HOST,
+            POSTGRES_PORT,
+            POSTGRES_DBNAME,
+            POSTGRES_USER,
+            Some(POSTGRES_PASSWORD.to_string()),
+            Some(slot_name.to_string()),
+            TableNamesFrom::Publication(publication.to_string()),
+        )
+    };
+
+    let mut first_source = create_source().await?;
+    first_source.commit_transaction().await?;
+    let first_stream = Box::pin(first_source.get_cdc_stream(PgLsn::from(0)).await?);
+
+    let mut second_source = create_source().await?;
+    second_source.commit_transaction().await?;
+    let result = second_source.get_cdc_stream(PgLsn::from(0)).await;
+    assert!(matches!(
+        result,
+        Err(PostgresSourceError::ReplicationClient(
+            ReplicationClientError::SlotActive { .. }
+        ))
+    ));
+
+    // Release the slot while the second source is waiting for it
+    second_source.wait_for_active_slot(Duration::from_secs(10));
+    let release = tokio::spawn(async move {
+        tokio::time::sleep(Duration::from_millis(500)).await;
+        drop(first_stream);
+        drop(first_source);
+    });
+    let second_stream = second_source.get_cdc_stream(PgLsn::from(0)).await?;
+    release.await?;
+
+    drop(second_stream);
+    drop(second_source);
+    drop_replication_slot(client, slot_name).await;
+    client
+        .simple_query(&format!("DROP PUBLICATION IF EXISTS {publication}"))
+        .await?;
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_replication_lag_of_active_slot() -> Result<(), anyhow::Error> {
+    let table_name = "test_replication_lag";
+    let publication = "test_replication_lag_pub";
+    let slot_name = "test_replication_lag_slot";
+
+    let test_table = TestTable::new(
+        table_name,
+        &format!("CREATE TABLE {table_name} (id INT PRIMARY KEY)"),
+    )
+    .await;
+    let client = &test_table.client;
+    client
+        .simple_query(&format!(
+            "DROP PUBLICATION IF EXISTS {publication};
+            CREATE PUBLICATION {publication} FOR TABLE {table_name};"
+        ))
+        .await?;
+    drop_replication_slot(client, slot_name).await;
+
+    let mut source = PostgresSource::new(
+        POSTGRES_HOST,
+        POSTGRES_PORT,
+        POSTGRES_DBNAME,
+        POSTGRES_USER,
+        Some(POSTGRES_PASSWORD.to_string()),
+        Some(slot_name.to_string()),
+        TableNamesFrom::Publication(publication.to_string()),
+    )
+    .await?;
+    source.commit_transaction().await?;
+
+    let monitoring_client = create_replication_client().await;
+    assert!(monitoring_client
+        .get_replication_lag(slot_name)
+        .await?
+        .is_none());
+
+    let mut cdc_stream = Box::pin(source.get_cdc_stream(PgLsn::from(0)).await?);
+    client
+        .simple_query(&format!("INSERT INTO {table_name} VALUES (1)"))
+        .await?;
+    let commit_lsn = loop {
+        match cdc_stream.next().await {
+            Some(event) => {
+                if let CdcEvent::Commit(commit_body) = event? {
+                    break commit_body.end_lsn();
+                }
+            }
+            None => panic!("cdc stream ended before the commit"),
+        }
+    };
+    cdc_stream
+        .as_mut()
+        .send_status_update(PgLsn::from(commit_lsn))
+        .await?;
+
+    // The walsender reports lag for the positions acknowledged by the status update
+    let lag = tokio::time::timeout(Duration::from_secs(10), async {
+        loop {
+            let lag = monitoring_client.get_replication_lag(slot_name).await?;
+            if let Some(lag) = lag.filter(|lag| lag.flush_lag.is_some()) {
+                return Ok::<_, anyhow::Error>(lag);
+            }
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+    })
+    .await??;
+    assert!(lag.write_lag.is_some());
+
+    drop(cdc_stream);
+    drop(source);
+    drop_replication_slot(client, slot_name).await;
+    client
+        .simple_query(&format!("DROP PUBLICATION IF EXISTS {publication}"))
+        .await?;
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_generated_columns_are_copied_but_not_streamed() -> Result<(), anyhow::Error> {
+    let table_name = "test_generated_columns";
+    let publication = "test_generated_columns_pub";
+    let slot_name = "test_generated_columns_slot";
+
+    let test_table = TestTable::new(
+        table_name,
+        &format!(
+            "CREATE TABLE {table_name} (
+                id INT PRIMARY KEY,
+                doubled INT GENERATED ALWAYS AS (id * 2) STORED,
+                data TEXT
+            )"
+        ),
+    )
+    .await;
+    let client = &test_table.client;
+    client
+        .simple_query(&format!(
+            "INSERT INTO {table_name} (id, data) VALUES (1, 'copied');
+            DROP PUBLICATION IF EXISTS {publication};
+            CREATE PUBLICATION {publication} FOR TABLE {table_name};"
+        ))
+        .await?;
+    drop_replication_slot(client, slot_name).await;
+
+    let mut source = PostgresSource::new(
+        POSTGRES_HOST,
+        POSTGRES_PORT,
+        POSTGRES_DBNAME,
+        POSTGRES_USER,
+        Some(POSTGRES_PASSWORD.to_string()),
+        Some(slot_name.to_string()),
+        TableNamesFrom::Publication(publication.to_string()),
+    )
+    .await?;
+    source.include_generated_columns().await?;
+
+    let table_schema = source
+        .get_table_schemas()
+        .values()
+        .next()
+        .expect("missing table schema")
+        .clone();
+    let generated: Vec<bool> = table_schema
+        .column_schemas
+        .iter()
+        .map(|c| c.generated)
+        .collect();
+    assert_eq!(generated, [false, true, false]);
+
+    let copied_rows: Vec<_> = source
+        .get_table_copy_stream(&table_schema.table_name, &table_schema.column_schemas)
+        .await?
+        .collect()
+        .await;
+    match &copied_rows[..] {
+        [Ok(row)] => assert!(matches!(
+            &row.values[..],
+            [Cell::I32(1), Cell::I32(2), Cell::String(data)] if data == "copied"
+        )),
+        rows => panic!("unexpected rows {rows:?}"),
+    }
+    source.commit_transaction().await?;
+
+    client
+        .simple_query(&format!(
+            "INSERT INTO {table_name} (id, data) VALUES (2, 'streamed')"
+        ))
+        .await?;
+    let mut cdc_stream = Box::pin(source.get_cdc_stream(PgLsn::from(0)).await?);
+    let row = loop {
+        match cdc_stream.next().await {
+            Some(event) => {
+                if let CdcEvent::Insert((_, row, _)) = event? {
+                    break row;
+                }
+            }
+            None => panic!("cdc stream ended before the insert"),
+        }
+    };
+    assert!(matches!(
+        &row.values[..],
+        [Cell::I32(2), Cell::Null, Cell::String(data)] if data == "streamed"
+    ));
+
+    drop(cdc_stream);
+    drop(source);
+    drop_replication_slot(client, slot_name).await;
+    client
+        .simple_query(&format!("DROP PUBLICATION IF EXISTS {publication}"))
+        .await?;
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_snapshot_backfill_copies_as_of_slot_creation() -> Result<(), anyhow::Error> {
+    let slot_name = "test_snapshot_backfill_slot";
+    let table_names = ["test_snapshot_backfill_a", "test_snapshot_backfill_b"];
+
+    let mut test_tables = vec![];
+    for table_name in table_names {
+        let test_table = TestTable::new(
+            table_name,
+            &format!("CREATE TABLE {table_name} (id INT PRIMARY KEY)"),
+        )
+        .await;
+        test_table
+            .client
+            .simple_query(&format!("INSERT INTO {table_name} VALUES (1), (2)"))
+            .await?;
+        test_tables.push(test_table);
+    }
+    let client = &test_tables[0].client;
+
+    let replication_client = create_replication_client().await;
+    drop_replication_slot(client, slot_name).await;
+    let table_names: Vec<TableName> = table_names
+        .iter()
+        .map(|name| TableName {
+            schema: "public".to_string(),
+            name: name.to_string(),
+        })
+        .collect();
+    let table_schemas: Vec<_> = replication_client
+        .get_table_schemas(&table_names, None)
+        .await?
+        .into_values()
+        .collect();
+
+    let mut backfill = SnapshotBackfill::new(replication_client);
+    backfill.add_copy_client(create_replication_client().await);
+    backfill.add_copy_client(create_replication_client().await);
+
+    // Rows inserted after the slot was created must not be copied
+    let copied = Mutex::new(vec![]);
+    let consistent_point = backfill
+        .run(slot_name, &table_schemas, |table_schema, stream| {
+            let copied = &copied;
+            async move {
+                client
+                    .simple_query(&format!(
+                        "INSERT INTO {} VALUES (3)",
+                        table_schema.table_name.as_quoted_identifier()
+                    ))
+                    .await?;
+                let rows: Vec<_> = stream.collect().await;
+                let rows_len = rows.len();
+                for row in rows {
+                    row?;
+                }
+                copied
+                    .lock()
+                    .unwrap()
+                    .push((table_schema.table_name.name, rows_len));
+                Ok::<(), TableCopyStreamError>(())
+            }
+        })
+        .await?;
+
+    let mut copied = copied.into_inner().unwrap();
+    copied.sort();
+    assert_eq!(
+        copied,
+        vec![
+            ("test_snapshot_backfill_a".to_string(), 2),
+            ("test_snapshot_backfill_b".to_string(), 2),
+        ]
+    );
+
+    let replication_client = backfill.into_slot_client();
+    let slot_activity = replication_client
+        .get_slot_activity(slot_name)
+        .await?
+        .expect("slot not created");
+    assert_eq!(slot_activity.confirmed_flush_lsn, consistent_point);
+
+    drop_replication_slot(client, slot_name).await;
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_snapshot_backfill_verifies_row_counts() -> Result<(), anyhow::Error> {
+    let slot_name = "test_verify_row_counts_slot";
+    let table_name = "test_verify_row_counts";
+
+    let test_table = TestTable::new(
+        table_name,
+        &format!("CREATE TABLE {table_name} (id INT PRIMARY KEY)"),
+    )
+    .await;
+    let client = &test_table.client;
+    client
+        .simple_query(&format!("INSERT INTO {table_name} VALUES (1), (2)"))
+        .await?;
+
+    let replication_client = create_replication_client().await;
+    drop_replication_slot(client, slot_name).await;
+    let table_schemas: Vec<_> = replication_client
+        .get_table_schemas(
+            &[TableName {
+                schema: "public".to_string(),
+                name: table_name.to_string(),
+            }],
+            None,
+        )
+        .await?
+        .into_values()
+        .collect();
+
+    let mut backfill = SnapshotBackfill::new(replication_client);
+    backfill.add_copy_client(create_replication_client().await);
+
+    // The row inserted during the backfill isn't copied
+    backfill
+        .run(slot_name, &table_schemas, |_, stream| async move {
+            client
+                .simple_query(&format!("INSERT INTO {table_name} VALUES (3)"))
+                .await?;
+            let rows: Vec<_> = stream.collect().await;
+            for row in rows {
+                row?;
+            }
+            Ok::<(), TableCopyStreamError>(())
+        })
+        .await?;
+
+    let mismatches = backfill.verify_row_counts(&table_schemas, 0).await?;
+    assert_eq!(
+        mismatches,
+        vec![RowCountMismatch {
+            table_name: table_schemas[0].table_name.clone(),
+            source_rows: 3,
+            copied_rows: 2,
+        }]
+    );
+    assert!(backfill
+        .verify_row_counts(&table_schemas, 1)
+        .await?
+        .is_empty());
+
+    drop(backfill);
+    drop_replication_slot(client, slot_name).await;
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_snapshot_backfill_detects_recreated_tables() -> Result<(), anyhow::Error> {
+    let slot_name = "test_recreated_backfill_slot";
+    let table_name = "test_recreated_backfill";
+
+    let test_table = TestTable::new(
+        table_name,
+        &format!("CREATE TABLE {table_name} (id INT PRIMARY KEY)"),
+    )
+    .await;
+    let client = &test_table.client;
+
+    let replication_client = create_replication_client().await;
+    drop_replication_slot(client, slot_name).await;
+    let table_schemas: Vec<_> = replication_client
+        .get_table_schemas(
+            &[TableName {
+                schema: "public".to_string(),
+                name: table_name.to_string(),
+            }],
+            None,
+        )
+        .await?
+        .into_values()
+        .collect();
+
+    // The table gets a new id, so the loaded schema no longer matches it
+    client
+        .simple_query(&format!(
+            "DROP TABLE {table_name}; CREATE TABLE {table_name} (id INT PRIMARY KEY)"
+        ))
+        .await?;
+
+    let mut backfill = SnapshotBackfill::new(replication_client);
+    let result = backfill
+        .run(slot_name, &table_schemas, |_, _| async move {
+            Ok::<(), TableCopyStreamError>(())
+        })
+        .await;
+    assert!(matches!(
+        result,
+        Err(SnapshotBackfillError::ReplicationClient(
+            ReplicationClientError::TableChanged(table_id, table)
+        )) if table_id == table_schemas[0].table_id && table.name == table_name
+    ));
+
+    drop(backfill);
+    drop_replication_slot(client, slot_name).await;
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_snapshot_backfill_copies_leaf_partitions() -> Result<(), anyhow::Error> {
+    let slot_name = "test_partition_backfill_slot";
+    let table_name = "test_partition_backfill";
+
+    let test_table = TestTable::new(
+        table_name,
+        &format!(
+            "CREATE TABLE {table_name} (id INT PRIMARY KEY) PARTITION BY RANGE (id);
+            CREATE TABLE {table_name}_low PARTITION OF {table_name} FOR VALUES FROM (0) TO (10);
+            CREATE TABLE {table_name}_high PARTITION OF {table_name} FOR VALUES FROM (10) TO (20);"
+        ),
+    )
+    .await;
+    let client = &test_table.client;
+    client
+        .simple_query(&format!("INSERT INTO {table_name} VALUES (1), (2), (11)"))
+        .await?;
+
+    let replication_client = create_replication_client().await;
+    drop_replication_slot(client, slot_name).await;
+    let table_schemas: Vec<_> = replication_client
+        .get_table_schemas(
+            &[TableName {
+                schema: "public".to_string(),
+                name: table_name.to_string(),
+            }],
+            None,
+        )
+        .await?
+        .into_values()
+        .collect();
+    let table_id = table_schemas[0].table_id;
+
+    let mut backfill = SnapshotBackfill::new(replication_client);
+    backfill.set_copy_leaf_partitions(true);
+    backfill.add_copy_client(create_replication_client().await);
+    backfill.add_copy_client(create_replication_client().await);
+
+    // Every partition is copied on its own, presented as the partitioned table
+    let copied = Mutex::new(vec![]);
+    backfill
+        .run(slot_name, &table_schemas, |table_schema, stream| {
+            let copied = &copied;
+            async move {
+                assert_eq!(table_schema.table_id, table_id);
+                let rows: Vec<_> = stream.collect().await;
+                let rows_len = rows.len();
+                for row in rows {
+                    row?;
+                }
+                copied.lock().unwrap().push(rows_len);
+                Ok::<(), TableCopyStreamError>(())
+            }
+        })
+        .await?;
+
+    let mut copied = copied.into_inner().unwrap();
+    copied.sort();
+    assert_eq!(copied, vec![1, 2]);
+
+    drop(backfill);
+    drop_replication_slot(client, slot_name).await;
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_table_copy_stream_counts_bytes() -> Result<(), anyhow::Error> {
+    let table_name = "test_copy_byte_counter";
+    let test_table = TestTable::new(
+        table_name,
+        &format!("CREATE TABLE {table_name} (id INT PRIMARY KEY, data TEXT)"),
+    )
+    .await;
+    test_table
+        .client
+        .simple_query(&format!(
+            "INSERT INTO {table_name} VALUES (1, 'ab'), (22, 'cd')"
+        ))
+        .await?;
+
+    let table_name = TableName {
+        schema: "public".to_string(),
+        name: table_name.to_string(),
+    };
+    let source = PostgresSource::new(
+        POSTGRES_HOST,
+        POSTGRES_PORT,
+        POSTGRES_DBNAME,
+        POSTGRES_USER,
+        Some(POSTGRES_PASSWORD.to_string()),
+        None,
+        TableNamesFrom::Vec(vec![table_name.clone()]),
+    )
+    .await?;
+    let table_schema = source
+        .get_table_schemas()
+        .values()
+        .next()
+        .expect("missing table schema");
+    let stream = source
+        .get_table_copy_stream(&table_name, &table_schema.column_schemas)
+        .await?;
+    let byte_counter = stream.byte_counter();
+
+    let rows: Vec<_> = stream.collect().await;
+    assert_eq!(rows.len(), 2);
+    // The rows are copied as "1\tab\n" and "22\tcd\n"
+    assert_eq!(byte_counter.load(Ordering::Relaxed), 11);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_table_copy_stream_decodes_csv_with_header() -> Result<(), anyhow::Error> {
+    let table_name = "test_copy_csv";
+    let test_table = TestTable::new(
+        table_name,
+        &format!("CREATE TABLE {table_name} (id INT PRIMARY KEY, data TEXT)"),
+    )
+    .await;
+    let client = &test_table.client;
+    client
+        .simple_query(&format!(
+            "INSERT INTO {table_name} VALUES (1, 'a,b'), (2, NULL), (3, '')"
+        ))
+        .await?;
+
+    let source = PostgresSource::new(
+        POSTGRES_HOST,
+        POSTGRES_PORT,
+        POSTGRES_DBNAME,
+        POSTGRES_USER,
+        Some(POSTGRES_PASSWORD.to_string()),
+        None,
+        TableNamesFrom::Vec(vec![TableName {
+            schema: "public".to_string(),
+            name: table_name.to_string(),
+        }]),
+    )
+    .await?;
+    let table_schema = source
+        .get_table_schemas()
+        .values()
+        .next()
+        .expect("missing table schema");
+
+    let copy_out = client
+        .copy_out(&format!(
+            "COPY (SELECT * FROM {table_name} ORDER BY id) TO STDOUT WITH (FORMAT csv, HEADER)"
+        ))
+        .await?;
+    let mut stream = TableCopyStream::new(copy_out, table_schema.column_schemas.clone());
+    stream.set_format(CopyFormat::Csv { header: true });
+    let row_counter = stream.row_counter();
+
+    let rows: Vec<_> = stream.collect().await;
+    let rows: Vec<_> = rows
+        .into_iter()
+        .map(|row| row.map(|row| row.values))
+        .collect::<Result<_, _>>()?;
+    match &rows[..] {
+        [first, second, third] => {
+            assert!(matches!(&first[..], [Cell::I32(1), Cell::String(s)] if s == "a,b"));
+            assert!(matches!(&second[..], [Cell::I32(2), Cell::Null]));
+            assert!(matches!(&third[..], [Cell::I32(3), Cell::String(s)] if s.is_empty()));
+        }
+        rows => panic!("unexpected rows {rows:?}"),
+    }
+    assert_eq!(row_counter.load(Ordering::Relaxed), 3);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_snapshot_and_stream_has_no_gap_or_overlap() -> Result<(), anyhow::Error> {
+    let table_name = "test_snapshot_and_stream";
+    let publication = "test_snapshot_and_stream_pub";
+    let slot_name = "test_snapshot_and_stream_slot";
+
+    let test_table = TestTable::new(
+        table_name,
+        &format!("CREATE TABLE {table_name} (id INT PRIMARY KEY)"),
+    )
+    .await;
+    let client = &test_table.client;
+    client
+        .simple_query(&format!(
+            "INSERT INTO {table_name} VALUES (1), (2);
+            DROP PUBLICATION IF EXISTS {publication};
+            CREATE PUBLICATION {publication} FOR TABLE {table_name};"
+        ))
+        .await?;
+    drop_replication_slot(client, slot_name).await;
+
+    let replication_client = create_replication_client().await;
+    let table_schemas: Vec<_> = replication_client
+        .get_table_schemas(
+            &[TableName {
+                schema: "public".to_string(),
+                name: table_name.to_string(),
+            }],
+            Some(publication),
+        )
+        .await?
+        .into_values()
+        .collect();
+
+    // A row inserted during the copy is streamed instead of copied
+    let mut backfill = SnapshotBackfill::new(replication_client);
+    let copied = Mutex::new(vec![]);
+    let id = |values: Vec<Cell>| match values[..] {
+        [Cell::I32(id)] => id,
+        ref values => panic!("unexpected row {values:?}"),
+    };
+    let (_, cdc_stream) = backfill
+        .snapshot_and_stream(slot_name, publication, &table_schemas, |_, stream| {
+            let copied = &copied;
+            async move {
+                client
+                    .simple_query(&format!("INSERT INTO {table_name} VALUES (3)"))
+                    .await?;
+                let rows: Vec<_> = stream.collect().await;
+                for row in rows {
+                    copied.lock().unwrap().push(id(row?.values));
+                }
+                Ok::<(), TableCopyStreamError>(())
+            }
+        })
+        .await?;
+    client
+        .simple_query(&format!("INSERT INTO {table_name} VALUES (4)"))
+        .await?;
+
+    assert_eq!(copied.into_inner().unwrap(), vec![1, 2]);
+
+    let mut cdc_stream = Box::pin(cdc_stream);
+    let mut streamed = vec![];
+    while streamed.len() < 2 {
+        match cdc_stream.next().await {
+            Some(event) => {
+                if let CdcEvent::Insert((_, row, _)) = event? {
+                    streamed.push(id(row.values));
+                }
+            }
+            None => panic!("cdc stream ended before the inserts"),
+        }
+    }
+    assert_eq!(streamed, vec![3, 4]);
+
+    drop(cdc_stream);
+    drop(backfill);
+    drop_replication_slot(client, slot_name).await;
+    client
+        .simple_query(&format!("DROP PUBLICATION IF EXISTS {publication}"))
+        .await?;
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_added_table_is_backfilled_without_gap_or_overlap() -> Result<(), anyhow::Error> {
+    let table_name = "test_add_table";
+    let added_table_name = "test_add_table_added";
+    let publication = "test_add_table_pub";
+    let slot_name = "test_add_table_slot";
+    let added_slot_name = "test_add_table_added_slot";
+
+    let test_table = TestTable::new(
+        table_name,
+        &format!("CREATE TABLE {table_name} (id INT PRIMARY KEY)"),
+    )
+    .await;
+    let _added_table = TestTable::new(
+        added_table_name,
+        &format!("CREATE TABLE {added_table_name} (id INT PRIMARY KEY)"),
+    )
+    .await;
+    let client = &test_table.client;
+    client
+        .simple_query(&format!(
+            "DROP PUBLICATION IF EXISTS {publication};
+            CREATE PUBLICATION {publication} FOR TABLE {table_name};"
+        ))
+        .await?;
+    drop_replication_slot(client, slot_name).await;
+
+    let table_names = |name: &str| {
+        [TableName {
+            schema: "public".to_string(),
+            name: name.to_string(),
+        }]
+    };
+    let id = |values: Vec<Cell>| match values[..] {
+        [Cell::I32(id)] => id,
+        ref values => panic!("unexpected row {values:?}"),
+    };
+
+    let replication_client = create_replication_client().await;
+    let table_schemas: Vec<_> = replication_client
+        .get_table_schemas(&table_names(table_name), Some(publication))
+        .await?
+        .into_values()
+        .collect();
+    let mut backfill = SnapshotBackfill::new(replication_client);
+    let (_, cdc_stream) = backfill
+        .snapshot_and_stream(slot_name, publication, &table_schemas, |_, _| async {
+            Ok::<(), TableCopyStreamError>(())
+        })
+        .await?;
+    let mut cdc_stream = Box::pin(cdc_stream);
+
+    // The second row is both in the backfill and in the running stream
+    client
+        .simple_query(&format!(
+            "INSERT INTO {added_table_name} VALUES (1);
+            ALTER PUBLICATION {publication} ADD TABLE {added_table_name};
+            INSERT INTO {added_table_name} VALUES (2);"
+        ))
+        .await?;
+
+    let replication_client = create_replication_client().await;
+    let added_table_schema = replication_client
+        .get_table_schemas(&table_names(added_table_name), Some(publication))
+        .await?
+        .into_values()
+        .next()
+        .expect("missing added table schema");
+    let mut added_backfill = SnapshotBackfill::new(replication_client);
+    let copied = Mutex::new(vec![]);
+    let start_lsn = added_backfill
+        .backfill_added_tables(
+            added_slot_name,
+            std::slice::from_ref(&added_table_schema),
+            |_, stream| {
+                let copied = &copied;
+                async move {
+                    let rows: Vec<_> = stream.collect().await;
+                    for row in rows {
+                        copied.lock().unwrap().push(id(row?.values));
+                    }
+                    Ok::<(), TableCopyStreamError>(())
+                }
+            },
+        )
+        .await?;
+    assert_eq!(copied.into_inner().unwrap(), vec![1, 2]);
+
+    client
+        .simple_query(&format!(
+            "INSERT INTO {added_table_name} VALUES (3);
+            INSERT INTO {table_name} VALUES (1);"
+        ))
+        .await?;
+    let added_table_id = added_table_schema.table_id;
+    cdc_stream.as_mut().add_table(added_table_schema, start_lsn);
+
+    // Only the row inserted after the backfill is streamed for the added table
+    let mut streamed = vec![];
+    loop {
+        match cdc_stream.next().await {
+            Some(event) => match event? {
+                CdcEvent::Insert((table_id, row, _)) => {
+                    if table_id != added_table_id {
+                        break;
+                    }
+                    streamed.push(id(row.values));
+                }
+                _ => continue,
+            },
+            None => panic!("cdc stream ended before the inserts"),
+        }
+    }
+    assert_eq!(streamed, vec![3]);
+
+    drop(cdc_stream);
+    drop(backfill);
+    drop_replication_slot(client, slot_name).await;
+    client
+        .simple_query(&format!("DROP PUBLICATION IF EXISTS {publication}"))
+        .await?;
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_excluded_columns_are_not_replicated() -> Result<(), anyhow::Error> {
+    let table_name = "test_exclude_columns";
+    let publication = "test_exclude_columns_pub";
+    let slot_name = "test_exclude_columns_slot";
+
+    let test_table = TestTable::new(
+        table_name,
+        &format!("CREATE TABLE {table_name} (id INT PRIMARY KEY, blob TEXT, data TEXT)"),
+    )
+    .await;
+    let client = &test_table.client;
+    client
+        .simple_query(&format!(
+            "INSERT INTO {table_name} VALUES (1, 'large', 'a');
+            DROP PUBLICATION IF EXISTS {publication};
+            CREATE PUBLICATION {publication} FOR TABLE {table_name};"
+        ))
+        .await?;
+    drop_replication_slot(client, slot_name).await;
+
+    let table = TableName {
+        schema: "public".to_string(),
+        name: table_name.to_string(),
+    };
+    let mut source = PostgresSource::new(
+        POSTGRES_HOST,
+        POSTGRES_PORT,
+        POSTGRES_DBNAME,
+        POSTGRES_USER,
+        Some(POSTGRES_PASSWORD.to_string()),
+        Some(slot_name.to_string()),
+        TableNamesFrom::Publication(publication.to_string()),
+    )
+    .await?;
+
+    let result = source.exclude_columns(table.clone(), ["id"]).await;
+    assert!(matches!(
+        result,
+        Err(PostgresSourceError::ReplicationClient(
+            ReplicationClientError::ExcludedKeyColumn(..)
+        ))
+    ));
+
+    source.exclude_columns(table.clone(), ["blob"]).await?;
+    let table_schema = source.get_table_schemas().values().next().unwrap().clone();
+    let column_names: Vec<&str> = table_schema
+        .column_schemas
+        .iter()
+        .map(|c| c.name.as_str())
+        .collect();
+    assert_eq!(column_names, vec!["id", "data"]);
+
+    let rows: Vec<_> = source
+        .get_table_copy_stream(&table, &table_schema.column_schemas)
+        .await?
+        .collect()
+        .await;
+    match &rows[..] {
+        [Ok(row)] => match &row.values[..] {
+            [Cell::I32(1), Cell::String(data)] => assert_eq!(data, "a"),
+            values => panic!("unexpected row {values:?}"),
+        },
+        rows => panic!("unexpected rows {rows:?}"),
+    }
+    source.commit_transaction().await?;
+
+    client
+        .simple_query(&format!(
+            "INSERT INTO {table_name} VALUES (2, 'large', 'b')"
+        ))
+        .await?;
+
+    let mut cdc_stream = Box::pin(source.get_cdc_stream(PgLsn::from(0)).await?);
+    let row = loop {
+        match cdc_stream.next().await {
+            Some(event) => {
+                if let CdcEvent::Insert((_, row, _)) = event? {
+                    break row;
+                }
+            }
+            None => panic!("cdc stream ended before the insert"),
+        }
+    };
+    match &row.values[..] {
+        [Cell::I32(2), Cell::String(data)] => assert_eq!(data, "b"),
+        values => panic!("unexpected row {values:?}"),
+    }
+
+    drop(cdc_stream);
+    drop(source);
+    drop_replication_slot(client, slot_name).await;
+    client
+        .simple_query(&format!("DROP PUBLICATION IF EXISTS {publication}"))
+        .await?;
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_before_images_require_replica_identity_full() -> Result<(), anyhow::Error> {
+    let table_name = "test_before_images";
+    let publication = "test_before_images_pub";
+    let slot_name = "test_before_images_slot";
+
+    let test_table = TestTable::new(
+        table_name,
+        &format!("CREATE TABLE {table_name} (id INT PRIMARY KEY, data TEXT)"),
+    )
+    .await;
+    let client = &test_table.client;
+    client
+        .simple_query(&format!(
+            "INSERT INTO {table_name} VALUES (1, 'before');
+            DROP PUBLICATION IF EXISTS {publication};
+            CREATE PUBLICATION {publication} FOR TABLE {table_name};"
+        ))
+        .await?;
+    drop_replication_slot(client, slot_name).await;
+
+    let mut source = PostgresSource::new(
+        POSTGRES_HOST,
+        POSTGRES_PORT,
+        POSTGRES_DBNAME,
+        POSTGRES_USER,
+        Some(POSTGRES_PASSWORD.to_string()),
+        Some(slot_name.to_string()),
+        TableNamesFrom::Publication(publication.to_string()),
+    )
+    .await?;
+    source.commit_transaction().await?;
+    source.require_before_images();
+
+    match source.get_cdc_stream(PgLsn::from(0)).await {
+        Err(PostgresSourceError::MissingBeforeImages(table_names)) => {
+            assert_eq!(
+                table_names,
+                vec![TableName {
+                    schema: "public".to_string(),
+                    name: table_name.to_string(),
+                }]
+            );
+        }
+        Err(e) => panic!("unexpected error {e}"),
+        Ok(_) => panic!("cdc stream started without replica identity full"),
+    }
+
+    client
+        .simple_query(&format!(
+            "ALTER TABLE {table_name} REPLICA IDENTITY FULL;
+            UPDATE {table_name} SET data = 'after' WHERE id = 1;"
+        ))
+        .await?;
+
+    let mut cdc_stream = Box::pin(source.get_cdc_stream(PgLsn::from(0)).await?);
+    let old_row = loop {
+        match cdc_stream.next().await {
+            Some(event) => {
+                if let CdcEvent::Update((_, old_row, _, _)) = event? {
+                    break old_row;
+                }
+            }
+            None => panic!("cdc stream ended before the update"),
+        }
+    };
+
+    match old_row.as_ref().map(|row| &row.values[..]) {
+        Some([Cell::I32(1), Cell::String(data)]) => assert_eq!(data, "before"),
+        values => panic!("unexpected old row {values:?}"),
+    }
+
+    drop(cdc_stream);
+    drop(source);
+    drop_replication_slot(client, slot_name).await;
+    client
+        .simple_query(&format!("DROP PUBLICATION IF EXISTS {publication}"))
+        .await?;
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_failover_is_detected() -> Result<(), anyhow::Error> {
+    let table_name = "test_failover";
+    let publication = "test_failover_pub";
+    let slot_name = "test_failover_slot";
+
+    let test_table = TestTable::new(
+        table_name,
+        &format!("CREATE TABLE {table_name} (id INT PRIMARY KEY, data TEXT)"),
+    )
+    .await;
+    let client = &test_table.client;
+    client
+        .simple_query(&format!(
+            "DROP PUBLICATION IF EXISTS {publication};
+            CREATE PUBLICATION {publication} FOR TABLE {table_name};"
+        ))
+        .await?;
+    drop_replication_slot(client, slot_name).await;
+
+    let create_source = || {
+        PostgresSource::new(
+            POSTGRES_HOST,
+            POSTGRES_PORT,
+            POSTGRES_DBNAME,
+            POSTGRES_USER,
+            Some(POSTGRES_PASSWORD.to_string()),
+            Some(slot_name.to_string()),
+            TableNamesFrom::Publication(publication.to_string()),
+        )
+    };
+
+    // A slot created now can't continue from the sink's position in an earlier one
+    let mut source = create_source().await?;
+    source.commit_transaction().await?;
+    match source.get_cdc_stream(PgLsn::from(100)).await {
+        Err(PostgresSourceError::SlotMissing(name)) => assert_eq!(name, slot_name),
+        Err(e) => panic!("unexpected error {e}"),
+        Ok(_) => panic!("cdc stream started from a new slot"),
+    }
+    drop(source);
+
+    // The server is on another timeline than the one the sink was synced from
+    let mut source = create_source().await?;
+    source.commit_transaction().await?;
+    let timeline = source.system_identity().timeline;
+    source.expect_timeline(timeline + 1);
+    match source.get_cdc_stream(PgLsn::from(100)).await {
+        Err(PostgresSourceError::TimelineChanged { expected, actual }) => {
+            assert_eq!((expected, actual), (timeline + 1, timeline));
+        }
+        Err(e) => panic!("unexpected error {e}"),
+        Ok(_) => panic!("cdc stream started on another timeline"),
+    }
+
+    // The slot is dropped while the source is connected
+    source.expect_timeline(timeline);
+    drop_replication_slot(client, slot_name).await;
+    match source.get_cdc_stream(PgLsn::from(100)).await {
+        Err(PostgresSourceError::SlotMissing(name)) => assert_eq!(name, slot_name),
+        Err(e) => panic!("unexpected error {e}"),
+        Ok(_) => panic!("cdc stream started without a slot"),
+    }
+
+    client
+        .simple_query(&format!("DROP PUBLICATION IF EXISTS {publication}"))
+        .await?;
+
+    Ok(())
+}
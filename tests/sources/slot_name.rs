@@ -0,0 +1,60 @@
+use pg_replicate::pipeline::sources::slot_name::{
+    derive_slot_name, validate_slot_name, SlotNameError, MAX_SLOT_NAME_LEN,
+};
+
+#[test]
+fn test_derived_slot_name_joins_valid_components() {
+    assert_eq!(
+        derive_slot_name("acme", "orders", "orders_pub"),
+        Ok("acme_orders_orders_pub".to_string())
+    );
+}
+
+#[test]
+fn test_derived_slot_name_is_sanitized_and_hashed() {
+    let slot_name = derive_slot_name("Acme-Corp", "orders", "orders_pub").unwrap();
+    assert!(slot_name.starts_with("acme_corp_orders_orders_pub_"));
+    assert_eq!(validate_slot_name(&slot_name), Ok(()));
+
+    // Components which sanitize to the same characters get different names
+    let other = derive_slot_name("acme.corp", "orders", "orders_pub").unwrap();
+    assert_ne!(slot_name, other);
+
+    // Deriving is deterministic
+    assert_eq!(
+        derive_slot_name("Acme-Corp", "orders", "orders_pub").unwrap(),
+        slot_name
+    );
+}
+
+#[test]
+fn test_long_derived_slot_name_is_truncated() {
+    let pipeline_id = "p".repeat(100);
+    let slot_name = derive_slot_name("acme", &pipeline_id, "pub_a").unwrap();
+    assert_eq!(slot_name.len(), MAX_SLOT_NAME_LEN);
+    assert_eq!(validate_slot_name(&slot_name), Ok(()));
+
+    let other = derive_slot_name("acme", &pipeline_id, "pub_b").unwrap();
+    assert_ne!(slot_name, other);
+}
+
+#[test]
+fn test_empty_component_is_rejected() {
+    assert_eq!(
+        derive_slot_name("acme", "", "orders_pub"),
+        Err(SlotNameError::EmptyComponent("pipeline_id"))
+    );
+}
+
+#[test]
+fn test_invalid_slot_names_are_rejected() {
+    assert_eq!(validate_slot_name(""), Err(SlotNameError::Empty));
+    assert_eq!(
+        validate_slot_name("Slot"),
+        Err(SlotNameError::InvalidCharacter('S'))
+    );
+    assert_eq!(
+        validate_slot_name(&"s".repeat(64)),
+        Err(SlotNameError::TooLong(64))
+    );
+}